@@ -1,14 +1,20 @@
 //! Easy file downloading
 #![deny(rust_2018_idioms)]
 
+use std::fmt;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
 pub use anyhow::Result;
+use lazy_static::lazy_static;
 use url::Url;
 
 mod errors;
 pub use crate::errors::*;
+mod s3;
 
 #[derive(Debug, Copy, Clone)]
 pub enum Backend {
@@ -22,6 +28,167 @@ pub enum TlsBackend {
     Default,
 }
 
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Curl => write!(f, "curl"),
+            Self::Reqwest(TlsBackend::Rustls) => write!(f, "reqwest (rustls)"),
+            Self::Reqwest(TlsBackend::Default) => write!(f, "reqwest (default TLS)"),
+        }
+    }
+}
+
+/// Which IP address family downloads may connect over.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IpVersion {
+    /// Whichever family the backend connects over, as the resolver lists
+    /// the addresses.
+    Auto,
+    V4,
+    V6,
+}
+
+impl IpVersion {
+    pub fn names() -> &'static [&'static str] {
+        &["auto", "v4", "v6"]
+    }
+
+    pub fn default_name() -> &'static str {
+        "auto"
+    }
+}
+
+impl FromStr for IpVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "auto" => Ok(Self::Auto),
+            "v4" => Ok(Self::V4),
+            "v6" => Ok(Self::V6),
+            _ => Err(anyhow::anyhow!(
+                "unknown IP version: '{}'; valid versions are {}",
+                name,
+                Self::names().join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for IpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::V4 => write!(f, "v4"),
+            Self::V6 => write!(f, "v6"),
+        }
+    }
+}
+
+/// How long to wait for a server to connect, or to send more data, when
+/// no timeout is set.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How every download connects, whichever backend makes it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkOptions {
+    pub ip_version: IpVersion,
+    /// The proxy to use for every URL, in place of those the environment
+    /// gives, such as `https_proxy`.
+    pub proxy: Option<Url>,
+    /// How long to wait for a server to connect, or to send more data.
+    pub timeout: Duration,
+    /// The most bytes a second to download at, if limited.
+    pub rate_limit: Option<u64>,
+}
+
+impl Default for NetworkOptions {
+    fn default() -> Self {
+        Self {
+            ip_version: IpVersion::Auto,
+            proxy: None,
+            timeout: DEFAULT_TIMEOUT,
+            rate_limit: None,
+        }
+    }
+}
+
+lazy_static! {
+    static ref NETWORK_OPTIONS: RwLock<NetworkOptions> = RwLock::new(NetworkOptions::default());
+}
+
+/// Sets how all subsequent downloads connect.
+///
+/// This must be called before the first download is made: the reqwest
+/// clients are built once per process and keep their connection settings,
+/// although the proxy and the rate limit are looked at for every download.
+pub fn set_network_options(options: NetworkOptions) {
+    *NETWORK_OPTIONS.write().unwrap() = options;
+}
+
+fn network_options() -> NetworkOptions {
+    NETWORK_OPTIONS.read().unwrap().clone()
+}
+
+/// Keeps downloading at no more than `rate` bytes a second on average, by
+/// waiting once ahead of the rate.
+struct Throttle {
+    rate: u64,
+    started: Instant,
+    received: u64,
+}
+
+impl Throttle {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate: rate.max(1),
+            started: Instant::now(),
+            received: 0,
+        }
+    }
+
+    fn received(&mut self, bytes: usize) {
+        self.received += bytes as u64;
+        let due = Duration::from_secs_f64(self.received as f64 / self.rate as f64);
+        if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+/// Whether a failed download might succeed if retried with another backend.
+///
+/// TLS handshake and certificate failures, and proxy authentication
+/// failures, frequently depend on the TLS stack and proxy support a backend
+/// was built with rather than on the server itself.
+pub fn is_backend_specific_error(err: &anyhow::Error) -> bool {
+    if let Some(DownloadError::HttpStatus(407)) = err.downcast_ref::<DownloadError>() {
+        return true;
+    }
+    #[cfg(feature = "reqwest-backend")]
+    {
+        if let Some(DownloadError::Reqwest(e)) = err.downcast_ref::<DownloadError>() {
+            if e.is_connect() && reqwest_be::is_tls_or_proxy_error(e) {
+                return true;
+            }
+        }
+    }
+    #[cfg(feature = "curl-backend")]
+    {
+        if let Some(e) = err.downcast_ref::<::curl::Error>() {
+            if e.is_ssl_connect_error()
+                || e.is_peer_failed_verification()
+                || e.is_ssl_certproblem()
+                || e.is_ssl_cacert()
+                || e.is_couldnt_resolve_proxy()
+            {
+                return true;
+            }
+        }
+    }
+    false
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum Event<'a> {
     ResumingPartialDownload,
@@ -37,12 +204,93 @@ fn download_with_backend(
     resume_from: u64,
     callback: &dyn Fn(Event<'_>) -> Result<()>,
 ) -> Result<()> {
+    // Local mirrors and S3 buckets are handled here rather than by each
+    // backend, so that they behave identically whichever one is in use.
+    match url.scheme() {
+        "file" => return download_from_file_url(url, resume_from, callback),
+        "s3" => {
+            let url = s3::to_https_url(url)?;
+            return download_with_backend(backend, &url, resume_from, callback);
+        }
+        _ => {}
+    }
+
+    let throttle = network_options()
+        .rate_limit
+        .map(|rate| std::cell::RefCell::new(Throttle::new(rate)));
+    let throttled = |event: Event<'_>| {
+        if let (Some(throttle), Event::DownloadDataReceived(data)) = (&throttle, event) {
+            throttle.borrow_mut().received(data.len());
+        }
+        callback(event)
+    };
+    let callback: &dyn Fn(Event<'_>) -> Result<()> = &throttled;
+
     match backend {
         Backend::Curl => curl::download(url, resume_from, callback),
         Backend::Reqwest(tls) => reqwest_be::download(url, resume_from, callback, tls),
     }
 }
 
+/// Whether `url` can be downloaded, asking only for its headers rather
+/// than the whole of it, as a quick check on how well its server responds.
+pub fn probe_with_backend(backend: Backend, url: &Url) -> Result<()> {
+    match url.scheme() {
+        "file" => {
+            let src = url
+                .to_file_path()
+                .map_err(|_| DownloadError::Message(format!("bogus file url: '{}'", url)))?;
+            return if src.is_file() {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!(DownloadError::FileNotFound))
+            };
+        }
+        "s3" => return probe_with_backend(backend, &s3::to_https_url(url)?),
+        _ => {}
+    }
+
+    match backend {
+        Backend::Curl => curl::probe(url),
+        Backend::Reqwest(tls) => reqwest_be::probe(url, tls),
+    }
+}
+
+fn download_from_file_url(
+    url: &Url,
+    resume_from: u64,
+    callback: &dyn Fn(Event<'_>) -> Result<()>,
+) -> Result<()> {
+    use std::fs;
+    use std::io;
+
+    let src = url
+        .to_file_path()
+        .map_err(|_| DownloadError::Message(format!("bogus file url: '{}'", url)))?;
+    if !src.is_file() {
+        // Because some of rustup's logic depends on checking
+        // the error when a downloaded file doesn't exist, make
+        // the file case return the same error value as the
+        // network case.
+        return Err(anyhow::anyhow!(DownloadError::FileNotFound));
+    }
+
+    let mut f = fs::File::open(src).context("unable to open downloaded file")?;
+    callback(Event::DownloadContentLengthReceived(f.metadata()?.len()))?;
+    io::Seek::seek(&mut f, io::SeekFrom::Start(resume_from))?;
+
+    let mut buffer = vec![0u8; 0x10000];
+    loop {
+        let bytes_read = io::Read::read(&mut f, &mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        callback(Event::DownloadDataReceived(&buffer[0..bytes_read]))?;
+    }
+
+    Ok(())
+}
+
 pub fn download_to_path_with_backend(
     backend: Backend,
     url: &Url,
@@ -138,13 +386,12 @@ pub fn download_to_path_with_backend(
 pub mod curl {
     use std::cell::RefCell;
     use std::str;
-    use std::time::Duration;
 
     use anyhow::{Context, Result};
-    use curl::easy::Easy;
+    use curl::easy::{Easy, HttpVersion, IpResolve};
     use url::Url;
 
-    use super::Event;
+    use super::{Event, IpVersion};
     use crate::errors::*;
 
     pub fn download(
@@ -171,9 +418,7 @@ pub mod curl {
                 // so there's nothing to "clear" - safe to ignore this error.
                 let _ = handle.resume_from(0);
             }
-
-            // Take at most 30s to connect
-            handle.connect_timeout(Duration::new(30, 0))?;
+            configure(&mut handle)?;
 
             {
                 let cberr = RefCell::new(None);
@@ -244,11 +489,58 @@ pub mod curl {
             Ok(())
         })
     }
+
+    /// Sets the options every request is made with, from the network
+    /// settings.
+    fn configure(handle: &mut Easy) -> Result<()> {
+        let options = super::network_options();
+        // Give up on a server which takes too long to connect, or
+        // stops sending anything.
+        handle.connect_timeout(options.timeout)?;
+        handle.low_speed_limit(1)?;
+        handle.low_speed_time(options.timeout)?;
+
+        // Without a proxy set, libcurl goes by the environment.
+        if let Some(proxy) = &options.proxy {
+            handle.proxy(proxy.as_str())?;
+        }
+
+        // libcurl already falls back from one address family to the
+        // other, so only an explicit restriction needs passing on.
+        handle.ip_resolve(match options.ip_version {
+            IpVersion::Auto => IpResolve::Any,
+            IpVersion::V4 => IpResolve::V4,
+            IpVersion::V6 => IpResolve::V6,
+        })?;
+
+        // Prefer HTTP/2 over TLS so that consecutive component downloads
+        // from the same server share a single multiplexed connection. An
+        // error here means libcurl was built without HTTP/2 support, in
+        // which case it keeps using (pooled) HTTP/1.1 connections.
+        let _ = handle.http_version(HttpVersion::V2TLS);
+        handle.tcp_keepalive(true)?;
+        Ok(())
+    }
+
+    /// Asks for the headers of `url` alone, with a HEAD request.
+    pub fn probe(url: &Url) -> Result<()> {
+        let mut handle = Easy::new();
+        handle.url(&url.to_string())?;
+        handle.follow_location(true)?;
+        handle.nobody(true)?;
+        configure(&mut handle)?;
+        handle.perform().context("error during download")?;
+        match handle.response_code()? {
+            0 | 200..=299 => Ok(()),
+            code => Err(DownloadError::HttpStatus(code).into()),
+        }
+    }
 }
 
 #[cfg(feature = "reqwest-backend")]
 pub mod reqwest_be {
     use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
     use std::time::Duration;
 
     use anyhow::{anyhow, Context, Result};
@@ -258,6 +550,7 @@ pub mod reqwest_be {
     use url::Url;
 
     use super::Event;
+    use super::IpVersion;
     use super::TlsBackend;
     use crate::errors::*;
 
@@ -267,11 +560,6 @@ pub mod reqwest_be {
         callback: &dyn Fn(Event<'_>) -> Result<()>,
         tls: TlsBackend,
     ) -> Result<()> {
-        // Short-circuit reqwest for the "file:" URL scheme
-        if download_from_file_url(url, resume_from, callback)? {
-            return Ok(());
-        }
-
         let mut res = request(url, resume_from, tls).context("failed to make network request")?;
 
         if !res.status().is_success() {
@@ -299,11 +587,32 @@ pub mod reqwest_be {
         }
     }
 
+    // The clients below are built once per process, so every download made
+    // during an operation shares their connection pool. HTTP/2 is negotiated
+    // via ALPN where the server supports it, letting successive component
+    // downloads reuse a single connection.
+    //
+    // rustup doesn't race the address families itself. hyper's connector,
+    // which reqwest uses with its defaults, tries the other family once the
+    // one the resolver lists first hasn't connected within 300ms, for the
+    // host it connects to, which is the proxy where there is one. The
+    // connect timeout keeps a black-holed route from eating the whole
+    // request timeout. Binding to an unspecified local address of one
+    // family restricts connections to that family.
     fn client_generic() -> ClientBuilder {
-        Client::builder()
+        let options = super::network_options();
+        let builder = Client::builder()
             .gzip(false)
-            .proxy(Proxy::custom(env_proxy))
-            .timeout(Duration::from_secs(30))
+            .proxy(Proxy::custom(proxy_for))
+            .timeout(options.timeout)
+            .connect_timeout(options.timeout.min(Duration::from_secs(10)))
+            .pool_idle_timeout(Duration::from_secs(90))
+            .tcp_keepalive(Some(Duration::from_secs(60)));
+        match options.ip_version {
+            IpVersion::Auto => builder,
+            IpVersion::V4 => builder.local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            IpVersion::V6 => builder.local_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        }
     }
     #[cfg(feature = "reqwest-rustls-tls")]
     lazy_static! {
@@ -340,74 +649,94 @@ pub mod reqwest_be {
         };
     }
 
-    fn env_proxy(url: &Url) -> Option<Url> {
-        env_proxy::for_url(url).to_url()
+    /// Whether a failure to connect was in the TLS handshake or at the
+    /// proxy, rather than, say, a refused connection, which any backend
+    /// would run into.
+    pub(crate) fn is_tls_or_proxy_error(err: &reqwest::Error) -> bool {
+        let mut failed_io = false;
+        let mut source = std::error::Error::source(err);
+        while let Some(err) = source {
+            if is_tls_error(err) {
+                return true;
+            }
+            failed_io |= err.is::<io::Error>();
+            source = err.source();
+        }
+        // reqwest has no error of its own for a proxy which refuses to open
+        // the tunnel, as when it wants other authentication, only a message.
+        // Going through a proxy, a failure which wasn't in reading or
+        // writing the connection is taken to be that.
+        let through_proxy = err.url().map_or(false, |url| proxy_for(url).is_some());
+        through_proxy && !failed_io
     }
 
-    fn request(
-        url: &Url,
-        resume_from: u64,
-        backend: TlsBackend,
-    ) -> Result<Response, DownloadError> {
-        let client: &Client = match backend {
-            #[cfg(feature = "reqwest-rustls-tls")]
-            TlsBackend::Rustls => &CLIENT_RUSTLS_TLS,
-            #[cfg(not(feature = "reqwest-rustls-tls"))]
-            TlsBackend::Rustls => {
-                return Err(DownloadError::BackendUnavailable("reqwest rustls"));
+    /// Whether `err` is one of the TLS libraries' own errors, as they are
+    /// or as the I/O error rustls gives them in.
+    fn is_tls_error(err: &(dyn std::error::Error + 'static)) -> bool {
+        #[cfg(feature = "reqwest-default-tls")]
+        {
+            if err.is::<native_tls::Error>() {
+                return true;
             }
-            #[cfg(feature = "reqwest-default-tls")]
-            TlsBackend::Default => &CLIENT_DEFAULT_TLS,
-            #[cfg(not(feature = "reqwest-default-tls"))]
-            TlsBackend::Default => {
-                return Err(DownloadError::BackendUnavailable("reqwest default TLS"));
+        }
+        #[cfg(feature = "reqwest-rustls-tls")]
+        {
+            if err.is::<rustls::TLSError>() {
+                return true;
             }
-        };
-        let mut req = client.get(url.as_str());
+        }
+        match err.downcast_ref::<io::Error>().and_then(io::Error::get_ref) {
+            Some(inner) => is_tls_error(inner),
+            None => false,
+        }
+    }
 
-        if resume_from != 0 {
-            req = req.header(header::RANGE, format!("bytes={}-", resume_from));
+    /// The proxy set for every URL, or else the one the environment gives
+    /// for `url`.
+    fn proxy_for(url: &Url) -> Option<Url> {
+        super::network_options()
+            .proxy
+            .or_else(|| env_proxy::for_url(url).to_url())
+    }
+
+    /// Asks for the headers of `url` alone, with a HEAD request.
+    pub fn probe(url: &Url, tls: TlsBackend) -> Result<()> {
+        let res = client(tls)?
+            .head(url.as_str())
+            .send()
+            .context("failed to make network request")?;
+        if !res.status().is_success() {
+            let code: u16 = res.status().into();
+            return Err(anyhow!(DownloadError::HttpStatus(u32::from(code))));
         }
+        Ok(())
+    }
 
-        Ok(req.send()?)
+    fn client(backend: TlsBackend) -> Result<&'static Client, DownloadError> {
+        match backend {
+            #[cfg(feature = "reqwest-rustls-tls")]
+            TlsBackend::Rustls => Ok(&*CLIENT_RUSTLS_TLS),
+            #[cfg(not(feature = "reqwest-rustls-tls"))]
+            TlsBackend::Rustls => Err(DownloadError::BackendUnavailable("reqwest rustls")),
+            #[cfg(feature = "reqwest-default-tls")]
+            TlsBackend::Default => Ok(&*CLIENT_DEFAULT_TLS),
+            #[cfg(not(feature = "reqwest-default-tls"))]
+            TlsBackend::Default => Err(DownloadError::BackendUnavailable("reqwest default TLS")),
+        }
     }
 
-    fn download_from_file_url(
+    fn request(
         url: &Url,
         resume_from: u64,
-        callback: &dyn Fn(Event<'_>) -> Result<()>,
-    ) -> Result<bool> {
-        use std::fs;
-
-        // The file scheme is mostly for use by tests to mock the dist server
-        if url.scheme() == "file" {
-            let src = url
-                .to_file_path()
-                .map_err(|_| DownloadError::Message(format!("bogus file url: '{}'", url)))?;
-            if !src.is_file() {
-                // Because some of rustup's logic depends on checking
-                // the error when a downloaded file doesn't exist, make
-                // the file case return the same error value as the
-                // network case.
-                return Err(anyhow!(DownloadError::FileNotFound));
-            }
-
-            let mut f = fs::File::open(src).context("unable to open downloaded file")?;
-            io::Seek::seek(&mut f, io::SeekFrom::Start(resume_from))?;
-
-            let mut buffer = vec![0u8; 0x10000];
-            loop {
-                let bytes_read = io::Read::read(&mut f, &mut buffer)?;
-                if bytes_read == 0 {
-                    break;
-                }
-                callback(Event::DownloadDataReceived(&buffer[0..bytes_read]))?;
-            }
+        backend: TlsBackend,
+    ) -> Result<Response, DownloadError> {
+        let mut req = client(backend)?.get(url.as_str());
 
-            Ok(true)
-        } else {
-            Ok(false)
+        if resume_from != 0 {
+            req = req.header(header::RANGE, format!("bytes={}-", resume_from));
         }
+
+        Ok(req.send()?)
     }
 }
 
@@ -427,6 +756,10 @@ pub mod curl {
     ) -> Result<()> {
         Err(anyhow!(DownloadError::BackendUnavailable("curl")))
     }
+
+    pub fn probe(_url: &Url) -> Result<()> {
+        Err(anyhow!(DownloadError::BackendUnavailable("curl")))
+    }
 }
 
 #[cfg(not(feature = "reqwest-backend"))]
@@ -447,4 +780,8 @@ pub mod reqwest_be {
     ) -> Result<()> {
         Err(anyhow!(DownloadError::BackendUnavailable("reqwest")))
     }
+
+    pub fn probe(_url: &Url, _tls: TlsBackend) -> Result<()> {
+        Err(anyhow!(DownloadError::BackendUnavailable("reqwest")))
+    }
 }
@@ -0,0 +1,67 @@
+#![cfg(feature = "reqwest-backend")]
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use url::Url;
+
+use download::*;
+
+fn download_error(url: &str) -> anyhow::Error {
+    let tmpdir = tempfile::Builder::new()
+        .prefix("rustup-download-test-")
+        .tempdir()
+        .unwrap();
+    let url = Url::parse(url).unwrap();
+    download_to_path_with_backend(
+        Backend::Reqwest(TlsBackend::Default),
+        &url,
+        &tmpdir.path().join("downloaded"),
+        false,
+        None,
+    )
+    .unwrap_err()
+}
+
+#[test]
+fn failed_tls_handshake_is_backend_specific() {
+    // A server which answers the handshake with plain HTTP.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let _ = stream.read(&mut [0; 1024]);
+            let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+        }
+    });
+
+    let err = download_error(&format!("https://{}/", addr));
+    assert!(is_backend_specific_error(&err), "{:?}", err);
+}
+
+#[test]
+fn proxy_authentication_is_backend_specific() {
+    let err = anyhow::Error::from(DownloadError::HttpStatus(407));
+    assert!(is_backend_specific_error(&err));
+}
+
+#[test]
+fn refused_connection_is_not_backend_specific() {
+    // Nothing listens on the port once the listener is gone.
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let err = download_error(&format!("http://{}/", addr));
+    assert!(!is_backend_specific_error(&err), "{:?}", err);
+}
+
+#[test]
+fn server_errors_are_not_backend_specific() {
+    for status in &[403, 404, 500] {
+        let err = anyhow::Error::from(DownloadError::HttpStatus(*status));
+        assert!(!is_backend_specific_error(&err));
+    }
+}
@@ -35,6 +35,7 @@ FLAGS:
     -v, --verbose           Enable verbose output
     -q, --quiet             Disable progress output
     -y                      Disable confirmation prompt.
+        --non-interactive   Never ask anything: use the default answer, or fail where there is none
         --no-modify-path    Don't configure the PATH environment variable
     -h, --help              Prints help information
     -V, --version           Prints version information
@@ -88,13 +89,16 @@ main() {
 
     # check if we have to use /dev/tty to prompt the user
     local need_tty=yes
+    if [ -n "${RUSTUP_NON_INTERACTIVE:-}" ] && [ "$RUSTUP_NON_INTERACTIVE" != 0 ]; then
+        need_tty=no
+    fi
     for arg in "$@"; do
         case "$arg" in
             -h|--help)
                 usage
                 exit 0
                 ;;
-            -y)
+            -y|--non-interactive)
                 # user wants to skip the prompt -- we don't need /dev/tty
                 need_tty=no
                 ;;
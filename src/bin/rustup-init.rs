@@ -15,11 +15,12 @@
 
 use std::path::PathBuf;
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use cfg_if::cfg_if;
 use rs_tracing::*;
 
 use rustup::cli::common;
+use rustup::cli::errors;
 use rustup::cli::proxy_mode;
 use rustup::cli::rustup_mode;
 #[cfg(windows)]
@@ -28,14 +29,13 @@ use rustup::cli::setup_mode;
 use rustup::currentprocess::{process, with, OSProcess};
 use rustup::env_var::RUST_RECURSION_COUNT_MAX;
 use rustup::utils::utils;
-use rustup::{DUP_TOOLS, TOOLS};
 
 fn main() {
     let process = OSProcess::default();
     with(Box::new(process), || match run_rustup() {
         Err(e) => {
             common::report_error(&e);
-            std::process::exit(1);
+            std::process::exit(errors::exit_code(&e));
         }
         Ok(utils::ExitCode(c)) => std::process::exit(c),
     });
@@ -93,22 +93,9 @@ fn run_rustup_inner() -> Result<utils::ExitCode> {
                 }
             }
         }
-        Some(n) => {
-            if TOOLS.iter().chain(DUP_TOOLS.iter()).any(|&name| name == n) {
-                proxy_mode::main(n)
-            } else {
-                Err(anyhow!(format!(
-                    "unknown proxy name: '{}'; valid proxy names are {}",
-                    n,
-                    TOOLS
-                        .iter()
-                        .chain(DUP_TOOLS.iter())
-                        .map(|s| format!("'{}'", s))
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                )))
-            }
-        }
+        // Whether it is a proxy rustup knows is only known once the
+        // settings, which list the tools added with `rustup proxy add`, are.
+        Some(n) => proxy_mode::main(n),
         None => {
             // Weird case. No arg0, or it's unparsable.
             Err(rustup::cli::errors::CLIError::NoExeName.into())
@@ -123,7 +110,7 @@ fn do_recursion_guard() -> Result<()> {
         .and_then(|s| s.parse().ok())
         .unwrap_or(0);
     if recursion_count > RUST_RECURSION_COUNT_MAX {
-        return Err(anyhow!("infinite recursion detected"));
+        return Err(proxy_mode::recursion_error());
     }
 
     Ok(())
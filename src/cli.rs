@@ -1,15 +1,30 @@
 /// The CLI specific code lives in the cli module and sub-modules.
 #[macro_use]
 pub mod log;
+mod auto_update;
+mod bisect;
+mod command_aliases;
 pub mod common;
+mod completions;
+mod config;
+mod doctor;
 mod download_tracker;
 pub mod errors;
 pub mod help;
+mod history;
 mod job;
 mod markdown;
+mod news;
+mod prompt;
 pub mod proxy_mode;
 pub mod rustup_mode;
 pub mod self_update;
 pub mod setup_mode;
+mod snapshot;
+mod stats;
+mod status;
 mod term2;
 mod topical_doc;
+mod ui;
+mod wsl;
+mod xdg;
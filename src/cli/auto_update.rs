@@ -0,0 +1,210 @@
+//! Checking for updates every so often without being asked, as the
+//! `[auto_update]` table of the settings says. When a check is due, an
+//! ordinary `rustup` command starts `rustup self auto-update` in the
+//! background and carries on. That looks for updates to rustup and to the
+//! installed channels, installs those it is allowed to, and leaves a notice
+//! of what it found for the next command to show.
+//!
+//! Nothing is checked in CI, where a build has no use for updates turning
+//! up halfway through and nobody would see the notices.
+
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+
+use super::common::{self, SelfUpdatePermission};
+use super::history;
+use super::self_update::{self, SelfUpdateMode};
+use crate::process;
+use crate::toolchain::DistributableToolchain;
+use crate::utils::utils::{self, ExitCode};
+use crate::{Cfg, UpdateStatus};
+
+/// Environment variables which CI services set, any of which means rustup
+/// is running in CI.
+static CI_VARS: &[&str] = &[
+    "CI",
+    "TF_BUILD",
+    "GITHUB_ACTIONS",
+    "GITLAB_CI",
+    "BUILDKITE",
+    "CIRCLECI",
+    "TRAVIS",
+    "APPVEYOR",
+    "JENKINS_URL",
+    "TEAMCITY_VERSION",
+];
+
+fn last_check_file(cfg: &Cfg) -> PathBuf {
+    cfg.cache_dir().join("update-check")
+}
+
+fn notices_file(cfg: &Cfg) -> PathBuf {
+    cfg.cache_dir().join("update-notices")
+}
+
+fn in_ci() -> bool {
+    CI_VARS.iter().any(|var| process().var_os(var).is_some())
+}
+
+fn record_check(cfg: &Cfg) -> Result<()> {
+    utils::write_file(
+        "update check",
+        &last_check_file(cfg),
+        &Utc::now().to_rfc3339(),
+    )
+}
+
+/// Whether the last check was longer ago than `interval`, or there hasn't
+/// been one.
+fn due(cfg: &Cfg, interval: std::time::Duration) -> bool {
+    let file = last_check_file(cfg);
+    let last = utils::read_file("update check", &file)
+        .ok()
+        .and_then(|last| DateTime::parse_from_rfc3339(last.trim()).ok());
+    match (last, chrono::Duration::from_std(interval)) {
+        (Some(last), Ok(interval)) => Utc::now() - last.with_timezone(&Utc) >= interval,
+        // Longer than any time that can have gone by since.
+        (Some(_), Err(_)) => false,
+        (None, _) => true,
+    }
+}
+
+/// Starts checking for updates in the background if a check is due, with
+/// the same rustup home as this command, from its `--profile`.
+pub(crate) fn schedule(cfg: &Cfg, home_profile: Option<&str>) -> Result<()> {
+    let interval = match cfg.settings_file.with(|s| Ok(s.auto_update.interval()))? {
+        Some(interval) => interval,
+        None => return Ok(()),
+    };
+    if in_ci() {
+        debug!("not checking for updates in CI");
+        return Ok(());
+    }
+    if !due(cfg, interval) {
+        return Ok(());
+    }
+    // Before starting the check, so that commands run meanwhile don't
+    // start another.
+    record_check(cfg)?;
+
+    let mut cmd = Command::new(utils::current_exe()?);
+    if let Some(profile) = home_profile {
+        cmd.arg("--profile").arg(profile);
+    }
+    cmd.arg("self")
+        .arg("auto-update")
+        .env("RUSTUP_FORCE_ARG0", "rustup")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // DETACHED_PROCESS, so that it has no console to keep open.
+        cmd.creation_flags(0x0000_0008);
+    }
+    cmd.spawn()
+        .context("could not start checking for updates")?;
+    debug!("checking for updates in the background");
+    Ok(())
+}
+
+/// Shows, and then forgets, what the last check in the background found.
+pub(crate) fn show_notices(cfg: &Cfg) -> Result<()> {
+    let file = notices_file(cfg);
+    if !utils::is_file(&file) {
+        return Ok(());
+    }
+    let notices = utils::read_file("update notices", &file)?;
+    utils::remove_file("update notices", &file)?;
+    for notice in notices.lines().filter(|l| !l.is_empty()) {
+        info!("{}", notice);
+    }
+    Ok(())
+}
+
+/// `rustup self auto-update`: the check itself, run by `schedule`. What it
+/// finds is added to the notices rather than shown, since nobody is
+/// watching.
+pub(crate) fn run(cfg: &Cfg) -> Result<ExitCode> {
+    record_check(cfg)?;
+    let channels_mode = cfg.settings_file.with(|s| Ok(s.auto_update.channels()))?;
+    let mut notices = Vec::new();
+
+    if channels_mode != SelfUpdateMode::Disable {
+        for (name, toolchain) in cfg.list_channels()? {
+            let toolchain = match toolchain {
+                Ok(toolchain) => toolchain,
+                Err(_) => continue,
+            };
+            let distributable = DistributableToolchain::new(&toolchain)?;
+            let version = match distributable.show_dist_version() {
+                Ok(Some(version)) => version,
+                Ok(None) => continue,
+                Err(e) => {
+                    notices.push(format!("could not check for updates to {}: {:#}", name, e));
+                    continue;
+                }
+            };
+            if channels_mode == SelfUpdateMode::CheckOnly {
+                notices.push(format!(
+                    "{} can be updated to {}; run `rustup update` to update it",
+                    name, version
+                ));
+                continue;
+            }
+            match distributable.install_from_dist(false, false, &[], &[], None) {
+                Ok(status) => {
+                    history::record_update(cfg, &name, &status);
+                    if !matches!(status, UpdateStatus::Unchanged) {
+                        notices.push(format!("{} was updated to {}", name, version));
+                    }
+                }
+                Err(e) => notices.push(format!("could not update {}: {:#}", name, e)),
+            }
+        }
+    }
+
+    match update_rustup(cfg) {
+        Ok(Some(notice)) => notices.push(notice),
+        Ok(None) => {}
+        Err(e) => notices.push(format!("could not check for updates to rustup: {:#}", e)),
+    }
+
+    for notice in notices {
+        utils::append_file("update notices", &notices_file(cfg), &notice)?;
+    }
+    Ok(ExitCode(0))
+}
+
+/// Looks for an update to rustup, and installs it if `auto_self_update`
+/// allows, returning the notice to leave about it.
+fn update_rustup(cfg: &Cfg) -> Result<Option<String>> {
+    let mode = cfg.get_self_update_mode()?;
+    if self_update::NEVER_SELF_UPDATE || mode == SelfUpdateMode::Disable {
+        return Ok(None);
+    }
+    let current = env!("CARGO_PKG_VERSION");
+    let available = self_update::get_available_rustup_version()?;
+    if available == current {
+        return Ok(None);
+    }
+    let permitted = matches!(
+        common::self_update_permitted(false)?,
+        SelfUpdatePermission::Permit
+    );
+    if mode == SelfUpdateMode::Enable && permitted {
+        if let Some(setup_path) = self_update::prepare_update()? {
+            self_update::run_update(&setup_path)?;
+            return Ok(Some(format!("rustup was updated to {}", available)));
+        }
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "rustup can be updated from {} to {}; run `rustup self update` to update it",
+        current, available
+    )))
+}
@@ -0,0 +1,120 @@
+//! `rustup bisect`: finding the nightly in which a command's behaviour
+//! changed, by running it against nightlies chosen by binary search.
+
+use std::ffi::OsStr;
+use std::io::Write;
+use std::process::Stdio;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{Duration, NaiveDate};
+
+use crate::errors::RustupError;
+use crate::process;
+use crate::toolchain::DistributableToolchain;
+use crate::utils::utils::ExitCode;
+use crate::Cfg;
+
+pub(crate) fn parse_date(date: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| anyhow!("invalid date '{}': expected YYYY-MM-DD", date))
+}
+
+/// Searches the nightlies from `start` to `end` for the first one in which
+/// `args` stops succeeding, or starts, and prints its name. Toolchains that
+/// weren't already installed are removed again once they have been tried.
+pub(crate) fn bisect(
+    cfg: &Cfg,
+    start: NaiveDate,
+    end: NaiveDate,
+    args: &[&str],
+) -> Result<ExitCode> {
+    if start >= end {
+        return Err(anyhow!("the start date must be before the end date"));
+    }
+    let mut dates: Vec<NaiveDate> = (0..=(end - start).num_days())
+        .map(|days| start + Duration::days(days))
+        .collect();
+
+    let first = try_nightly(cfg, start, args)?
+        .ok_or_else(|| anyhow!("could not install nightly-{}", start))?;
+    let last =
+        try_nightly(cfg, end, args)?.ok_or_else(|| anyhow!("could not install nightly-{}", end))?;
+    if first == last {
+        return Err(anyhow!(
+            "the command {} with both nightly-{} and nightly-{}; there is nothing to bisect",
+            describe(first),
+            start,
+            end
+        ));
+    }
+
+    // `dates[lo]` behaves like the start and `dates[hi]` like the end.
+    let (mut lo, mut hi) = (0, dates.len() - 1);
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        match try_nightly(cfg, dates[mid], args)? {
+            Some(success) if success == first => lo = mid,
+            Some(_) => hi = mid,
+            // Not every day has a nightly with every component; skip it.
+            None => {
+                dates.remove(mid);
+                hi -= 1;
+            }
+        }
+    }
+
+    if dates[hi] - dates[lo] > Duration::days(1) {
+        warn!(
+            "nightlies after {} and before {} could not be installed, so the change may be in one of them",
+            dates[lo], dates[hi]
+        );
+    }
+    info!(
+        "the command first {} with nightly-{}",
+        describe(last),
+        dates[hi]
+    );
+    writeln!(process().stdout(), "nightly-{}", dates[hi])?;
+    Ok(ExitCode(0))
+}
+
+fn describe(success: bool) -> &'static str {
+    if success {
+        "succeeds"
+    } else {
+        "fails"
+    }
+}
+
+/// Runs `args` with the nightly for `date`, installing it first if needed.
+/// Returns whether the command succeeded, or `None` if there is no such
+/// nightly to try.
+fn try_nightly(cfg: &Cfg, date: NaiveDate, args: &[&str]) -> Result<Option<bool>> {
+    let toolchain = cfg.get_toolchain(&format!("nightly-{}", date), true)?;
+    let installed_here = !toolchain.exists();
+    if installed_here {
+        let distributable = DistributableToolchain::new(&toolchain)?;
+        if let Err(e) = distributable.install_from_dist(false, false, &[], &[], None) {
+            warn!("skipping {}: {:#}", toolchain.name(), e);
+            return Ok(None);
+        }
+    }
+
+    info!("trying {}", toolchain.name());
+    let mut cmd = cfg.create_command_for_toolchain(toolchain.name(), false, args[0])?;
+    cmd.args(&args[1..]).stdin(Stdio::inherit());
+    let status = cmd.status().with_context(|| RustupError::RunningCommand {
+        name: OsStr::new(args[0]).to_owned(),
+    });
+
+    if installed_here {
+        toolchain.remove()?;
+    }
+    let success = status?.success();
+    info!(
+        "the command {} with {}",
+        describe(success),
+        toolchain.name()
+    );
+    Ok(Some(success))
+}
@@ -0,0 +1,87 @@
+//! Command aliases: shorter names for rustup's own commands, kept in the
+//! `aliases` table of settings.toml, such as `up = "update"`. The
+//! command is replaced by what its alias stands for before the command line
+//! is parsed. An alias never takes the place of one of rustup's commands.
+
+use std::ffi::OsString;
+
+use anyhow::Result;
+use clap::App;
+
+use super::rustup_mode::cli;
+use crate::utils::utils;
+use crate::Cfg;
+
+/// `args` with the command, if it is an alias, replaced by the words of the
+/// command it stands for. The settings are only read when the command isn't
+/// one of rustup's own.
+pub(crate) fn expand(args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let app = cli();
+    let mut home_profile = None;
+    let mut command = None;
+    let mut i = 1;
+    while let Some(arg) = args.get(i).and_then(|arg| arg.to_str()) {
+        if let Some(name) = option_with_value(&app, arg) {
+            if name == "home-profile" {
+                home_profile = args.get(i + 1).and_then(|value| value.to_str());
+            }
+            i += 2;
+            continue;
+        }
+        if let Some(value) = arg.strip_prefix("--profile=") {
+            home_profile = Some(value);
+        }
+        if !arg.starts_with('-') && !arg.starts_with('+') {
+            command = Some((i, arg));
+            break;
+        }
+        i += 1;
+    }
+    let (i, name) = match command {
+        Some((i, name)) if !is_builtin(&app, name) => (i, name),
+        _ => return Ok(args),
+    };
+
+    // Don't create the settings file just to look in it.
+    let settings_file = Cfg::settings_file(home_profile)?;
+    if !utils::is_file(settings_file.path()) {
+        return Ok(args);
+    }
+    let alias = settings_file.with(|s| Ok(s.command_aliases.get(name).cloned()))?;
+    let alias = match alias {
+        Some(alias) => alias,
+        None => return Ok(args),
+    };
+    debug!("expanding command alias '{}' to '{}'", name, alias);
+    let mut expanded = args[..i].to_vec();
+    expanded.extend(alias.split_whitespace().map(OsString::from));
+    expanded.extend(args[i + 1..].iter().cloned());
+    Ok(expanded)
+}
+
+/// The name of the option `arg` is, if it is one of `app`'s which takes its
+/// value from the next argument.
+fn option_with_value<'a>(app: &App<'a, '_>, arg: &str) -> Option<&'a str> {
+    app.p
+        .opts()
+        .find(|opt| match (opt.s.long, opt.s.short) {
+            (Some(long), _) if arg.strip_prefix("--") == Some(long) => true,
+            (_, Some(short)) => arg.strip_prefix('-') == Some(short.encode_utf8(&mut [0; 4])),
+            _ => false,
+        })
+        .map(|opt| opt.b.name)
+}
+
+/// Whether `name` is one of rustup's commands, or one of their aliases.
+fn is_builtin(app: &App<'_, '_>, name: &str) -> bool {
+    name == "help"
+        || app.p.subcommands().any(|command| {
+            let meta = &command.p.meta;
+            meta.name == name
+                || meta
+                    .aliases
+                    .iter()
+                    .flatten()
+                    .any(|(alias, _)| *alias == name)
+        })
+}
@@ -1,29 +1,81 @@
 //! Just a dumping ground for cli stuff
 
+use std::cell::Cell;
 use std::fs;
 use std::io::{BufRead, ErrorKind, Write};
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
 use std::{cmp, env, iter};
 
 use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
 use git_testament::{git_testament, render_testament};
 use lazy_static::lazy_static;
+use serde::{Serialize, Serializer};
 use term2::Terminal;
 
+use super::download_tracker::{self, DownloadTracker};
+use super::errors::{self, CLIError};
+use super::history;
+use super::log;
+use super::news;
 use super::self_update;
 use super::term2;
+use crate::currentprocess::{self, home_process};
 use crate::dist::notifications as dist_notifications;
 use crate::process;
-use crate::toolchain::DistributableToolchain;
+use crate::toolchain::{ComponentStatus, DistributableToolchain};
 use crate::utils::notifications as util_notifications;
 use crate::utils::notify::NotificationLevel;
+use crate::utils::tty;
+use crate::utils::units::{Size, Unit, UnitMode};
 use crate::utils::utils;
 use crate::{Cfg, Notification, Toolchain, UpdateStatus};
 
 pub const WARN_COMPLETE_PROFILE: &str = "downloading with complete profile isn't recommended unless you are a developer of the rust language";
 
+thread_local! {
+    static NON_INTERACTIVE: Cell<bool> = Cell::default();
+}
+
+/// Makes rustup never ask anything, as `--non-interactive` does.
+pub(crate) fn set_non_interactive(non_interactive: bool) {
+    NON_INTERACTIVE.with(|n| n.set(non_interactive));
+}
+
+/// Whether rustup mustn't ask anything: with `--non-interactive`, or with
+/// RUSTUP_NON_INTERACTIVE set to anything but `0`. Each question is then
+/// either answered with its default or, where there is no safe default,
+/// an error.
+pub(crate) fn non_interactive() -> bool {
+    NON_INTERACTIVE.with(Cell::get)
+        || matches!(process().var("RUSTUP_NON_INTERACTIVE"), Ok(v) if !v.is_empty() && v != "0")
+}
+
+/// Whether there is someone to ask: rustup may ask, and stdin and stderr
+/// are terminals.
+pub(crate) fn can_prompt() -> bool {
+    !non_interactive() && tty::stdin_isatty() && tty::stderr_isatty()
+}
+
+/// The error for `question` going unasked, with `reason` for scripts to
+/// match on.
+pub(crate) fn non_interactive_error(question: &str, reason: &'static str) -> anyhow::Error {
+    let question = question.trim().trim_end_matches("(y/N)").trim_end();
+    CLIError::NonInteractive {
+        question: question.to_owned(),
+        reason,
+    }
+    .into()
+}
+
+/// Asks a yes or no question. Non-interactively, that is an error, since
+/// each question asked this way is whether to go on with something which
+/// needs a person's say-so.
 pub fn confirm(question: &str, default: bool) -> Result<bool> {
+    if non_interactive() {
+        return Err(non_interactive_error(question, "confirmation-required"));
+    }
     write!(process().stdout(), "{} ", question)?;
     let _ = std::io::stdout().flush();
     let input = read_line()?;
@@ -40,6 +92,43 @@ pub fn confirm(question: &str, default: bool) -> Result<bool> {
     Ok(r)
 }
 
+/// Whether to go ahead with removing something for good, as `question`
+/// asks, such as "Remove these overrides?". `yes`, from `-y` or `--yes`,
+/// answers it. Otherwise it is asked at a terminal, after listing `items`.
+/// Where there is no one to ask, `default` is the answer if it is yes, and
+/// else `command` is left undone, saying how to go ahead, or with
+/// `--non-interactive` fails.
+pub(crate) fn confirm_removal(
+    question: &str,
+    items: &[String],
+    command: &str,
+    default: bool,
+    yes: bool,
+) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if can_prompt() {
+        let mut out = process().stdout();
+        for item in items {
+            writeln!(out, "  {}", item)?;
+        }
+        let choices = if default { "(Y/n)" } else { "(y/N)" };
+        return confirm(&format!("\n{} {}", question, choices), default);
+    }
+    if default {
+        return Ok(true);
+    }
+    if non_interactive() {
+        return Err(non_interactive_error(
+            &format!("{} (pass `--yes` to go ahead)", question),
+            "confirmation-required",
+        ));
+    }
+    info!("run `{} --yes` to go ahead", command);
+    Ok(false)
+}
+
 pub enum Confirm {
     Yes,
     No,
@@ -47,6 +136,9 @@ pub enum Confirm {
 }
 
 pub fn confirm_advanced() -> Result<Confirm> {
+    if non_interactive() {
+        return Ok(Confirm::Yes);
+    }
     writeln!(process().stdout())?;
     writeln!(process().stdout(), "1) Proceed with installation (default)")?;
     writeln!(process().stdout(), "2) Customize installation")?;
@@ -68,6 +160,9 @@ pub fn confirm_advanced() -> Result<Confirm> {
 }
 
 pub fn question_str(question: &str, default: &str) -> Result<String> {
+    if non_interactive() {
+        return Ok(default.to_string());
+    }
     writeln!(process().stdout(), "{} [{}]", question, default)?;
     let _ = std::io::stdout().flush();
     let input = read_line()?;
@@ -82,6 +177,9 @@ pub fn question_str(question: &str, default: &str) -> Result<String> {
 }
 
 pub fn question_bool(question: &str, default: bool) -> Result<bool> {
+    if non_interactive() {
+        return Ok(default);
+    }
     let default_text = if default { "(Y/n)" } else { "(y/N)" };
     writeln!(process().stdout(), "{} {}", question, default_text)?;
 
@@ -132,47 +230,51 @@ impl NotifyOnConsole {
             }
         };
         let level = n.level();
+        let target = n.target();
         for n in format!("{}", n).lines() {
             match level {
                 NotificationLevel::Verbose => {
-                    if self.verbose {
-                        verbose!("{}", n);
+                    if self.verbose || log::filtered() {
+                        verbose!(target: target, "{}", n);
                     }
                 }
                 NotificationLevel::Info => {
-                    info!("{}", n);
+                    info!(target: target, "{}", n);
                 }
                 NotificationLevel::Warn => {
-                    warn!("{}", n);
+                    warn!(target: target, "{}", n);
                 }
                 NotificationLevel::Error => {
-                    err!("{}", n);
+                    err!(target: target, "{}", n);
                 }
                 NotificationLevel::Debug => {
-                    debug!("{}", n);
+                    debug!(target: target, "{}", n);
                 }
             }
         }
     }
 }
 
-pub fn set_globals(verbose: bool, quiet: bool) -> Result<Cfg> {
-    use std::cell::RefCell;
-
-    use super::download_tracker::DownloadTracker;
-
-    let download_tracker = RefCell::new(DownloadTracker::new().with_display_progress(!quiet));
-    let console_notifier = RefCell::new(NotifyOnConsole {
+pub fn set_globals(verbose: bool, quiet: bool, home_profile: Option<&str>) -> Result<Cfg> {
+    download_tracker::display_progress(!quiet);
+    let download_tracker = Mutex::new(DownloadTracker::new());
+    let console_notifier = Mutex::new(NotifyOnConsole {
         verbose,
         ..Default::default()
     });
 
-    Cfg::from_env(Arc::new(move |n: Notification<'_>| {
-        if download_tracker.borrow_mut().handle_notification(&n) {
-            return;
-        }
-        console_notifier.borrow_mut().handle(n);
-    }))
+    let mut cfg = Cfg::from_env(
+        home_profile,
+        Arc::new(move |n: Notification<'_>| {
+            if download_tracker.lock().unwrap().handle_notification(&n) {
+                return;
+            }
+            console_notifier.lock().unwrap().handle(n);
+        }),
+    )?;
+    term2::set_color_theme(cfg.get_color_theme()?);
+    cfg.use_project_settings()?;
+    Ok(cfg)
 }
 
 pub fn show_channel_update(cfg: &Cfg, name: &str, updated: Result<UpdateStatus>) -> Result<()> {
@@ -185,12 +287,12 @@ fn show_channel_updates(cfg: &Cfg, toolchains: Vec<(String, Result<UpdateStatus>
         let mut version: String = toolchain.rustc_version();
 
         let banner;
-        let color;
+        let style;
         let mut previous_version: Option<String> = None;
         match result {
             Ok(UpdateStatus::Installed) => {
                 banner = "installed";
-                color = Some(term2::color::GREEN);
+                style = Some(term2::Style::Good);
             }
             Ok(UpdateStatus::Updated(v)) => {
                 if name == "rustup" {
@@ -200,24 +302,24 @@ fn show_channel_updates(cfg: &Cfg, toolchains: Vec<(String, Result<UpdateStatus>
                     previous_version = Some(v);
                 }
                 banner = "updated";
-                color = Some(term2::color::GREEN);
+                style = Some(term2::Style::Good);
             }
             Ok(UpdateStatus::Unchanged) => {
                 if name == "rustup" {
                     version = env!("CARGO_PKG_VERSION").into();
                 }
                 banner = "unchanged";
-                color = None;
+                style = None;
             }
             Err(_) => {
                 banner = "update failed";
-                color = Some(term2::color::RED);
+                style = Some(term2::Style::Error);
             }
         }
 
         let width = name.len() + 1 + banner.len();
 
-        Ok((name, banner, width, color, version, previous_version))
+        Ok((name, banner, width, style, version, previous_version))
     });
 
     let mut t = term2::stdout();
@@ -227,13 +329,13 @@ fn show_channel_updates(cfg: &Cfg, toolchains: Vec<(String, Result<UpdateStatus>
         .iter()
         .fold(0, |a, &(_, _, width, _, _, _)| cmp::max(a, width));
 
-    for (name, banner, width, color, version, previous_version) in data {
+    for (name, banner, width, style, version, previous_version) in data {
         let padding = max_width - width;
         let padding: String = iter::repeat(' ').take(padding).collect();
         let _ = write!(t, "  {}", padding);
         let _ = t.attr(term2::Attr::Bold);
-        if let Some(color) = color {
-            let _ = t.fg(color);
+        if let Some(style) = style {
+            let _ = t.style(style);
         }
         let _ = write!(t, "{} ", name);
         let _ = write!(t, "{}", banner);
@@ -253,8 +355,22 @@ pub(crate) fn update_all_channels(
     cfg: &Cfg,
     do_self_update: bool,
     force_update: bool,
+    jobs: usize,
 ) -> Result<utils::ExitCode> {
-    let toolchains = cfg.update_all_channels(force_update)?;
+    let toolchains = if jobs > 1 {
+        update_channels_in_parallel(cfg, force_update, jobs)?
+    } else {
+        cfg.update_all_channels(force_update)?
+    };
+    let mut changed = Vec::new();
+    for (name, status) in &toolchains {
+        if let Ok(status) = status {
+            history::record_update(cfg, name, status);
+            if !matches!(status, UpdateStatus::Unchanged) {
+                changed.push(name.clone());
+            }
+        }
+    }
 
     if toolchains.is_empty() {
         info!("no updatable toolchains installed");
@@ -265,6 +381,8 @@ pub(crate) fn update_all_channels(
             writeln!(process().stdout())?;
 
             show_channel_updates(cfg, toolchains)?;
+            let changed: Vec<&str> = changed.iter().map(String::as_str).collect();
+            news::show_after_update(cfg, &changed);
         }
         Ok(utils::ExitCode(0))
     };
@@ -276,6 +394,79 @@ pub(crate) fn update_all_channels(
     }
 }
 
+/// Updates the installed channels as `Cfg::update_all_channels` does, but
+/// `jobs` at a time, each on its own thread with its own copy of `cfg`.
+/// They share the download cache, and the toolchain locks keep them out of
+/// each other's way.
+fn update_channels_in_parallel(
+    cfg: &Cfg,
+    force_update: bool,
+    jobs: usize,
+) -> Result<Vec<(String, Result<UpdateStatus>)>> {
+    let names: Vec<_> = cfg
+        .list_channels()?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    if names.len() < 2 {
+        return cfg.update_all_channels(force_update);
+    }
+
+    let jobs = cmp::min(jobs, names.len());
+    info!("updating {} toolchains, {} at a time", names.len(), jobs);
+    let pool = threadpool::ThreadPool::new(jobs);
+    let (tx, rx) = mpsc::channel();
+    for (index, name) in names.iter().enumerate() {
+        let tx = tx.clone();
+        let process = home_process();
+        let name = name.clone();
+        let cfg = cfg.clone();
+        let color_choice = term2::color_choice_override();
+        pool.execute(move || {
+            term2::set_color_choice(color_choice);
+            let status = currentprocess::with(process, || update_channel(&name, cfg, force_update));
+            let _ = tx.send((index, status));
+        });
+    }
+    drop(tx);
+
+    let mut statuses: Vec<_> = rx.iter().collect();
+    if statuses.len() != names.len() {
+        return Err(anyhow!("a toolchain update stopped unexpectedly"));
+    }
+    statuses.sort_by_key(|(index, _)| *index);
+    Ok(names
+        .into_iter()
+        .zip(statuses)
+        .map(|(name, (_, status))| {
+            if let Err(ref e) = status {
+                (cfg.notify_handler)(Notification::NonFatalError(e));
+            }
+            (name, status)
+        })
+        .collect())
+}
+
+fn update_channel(name: &str, mut cfg: Cfg, force_update: bool) -> Result<UpdateStatus> {
+    // Only progress and problems are worth reporting while several updates
+    // are going at once; everything else is summarized at the end.
+    let prefix = name.to_owned();
+    let download_tracker = Mutex::new(DownloadTracker::new().with_prefix(name));
+    cfg.set_notify_handler(Arc::new(move |n: Notification<'_>| {
+        if download_tracker.lock().unwrap().handle_notification(&n) {
+            return;
+        }
+        match n.level() {
+            NotificationLevel::Warn => warn!("{}: {}", prefix, n),
+            NotificationLevel::Error => err!("{}: {}", prefix, n),
+            _ => {}
+        }
+    }));
+    let toolchain = cfg.get_toolchain(name, false)?;
+    let distributable = DistributableToolchain::new(&toolchain)?;
+    distributable.install_from_dist(force_update, false, &[], &[], None)
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum SelfUpdatePermission {
     HardFail,
@@ -354,6 +545,111 @@ where
     Ok(utils::ExitCode(0))
 }
 
+/// The version of the JSON printed for `--format json`, raised whenever a
+/// change to it could break a script reading it.
+pub(crate) const JSON_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Versioned<'a, T> {
+    version: u32,
+    #[serde(flatten)]
+    output: &'a T,
+}
+
+/// Prints the JSON output of a command, with its version first.
+pub(crate) fn print_json<T: Serialize>(output: &T) -> Result<()> {
+    let output = Versioned {
+        version: JSON_VERSION,
+        output,
+    };
+    writeln!(
+        process().stdout(),
+        "{}",
+        serde_json::to_string_pretty(&output)?
+    )?;
+    Ok(())
+}
+
+/// A component or target in the JSON output of `component list` and
+/// `target list`.
+#[derive(Serialize)]
+struct ComponentDetails {
+    name: String,
+    target: Option<String>,
+    installed: bool,
+    available: bool,
+}
+
+impl ComponentDetails {
+    fn new(name: String, status: &ComponentStatus) -> Self {
+        Self {
+            name,
+            target: status.component.target.as_ref().map(ToString::to_string),
+            installed: status.installed,
+            available: status.available,
+        }
+    }
+}
+
+/// The components of the toolchain that `component list` and `target list`
+/// show: those installed or available, or only those installed if
+/// `installed_only`.
+fn listed_components(
+    toolchain: &Toolchain<'_>,
+    installed_only: bool,
+) -> Result<Vec<ComponentStatus>> {
+    let distributable = DistributableToolchain::new_for_components(&toolchain)?;
+    let components = distributable.list_components()?;
+    Ok(components
+        .into_iter()
+        .filter(|c| c.installed || (!installed_only && c.available))
+        .collect())
+}
+
+pub(crate) fn list_targets_json(
+    toolchain: &Toolchain<'_>,
+    installed_only: bool,
+) -> Result<utils::ExitCode> {
+    let targets: Vec<_> = listed_components(toolchain, installed_only)?
+        .iter()
+        .filter(|c| c.component.short_name_in_manifest() == "rust-std")
+        .filter_map(|c| {
+            let target = c.component.target.as_ref()?;
+            Some(ComponentDetails::new(target.to_string(), c))
+        })
+        .collect();
+    #[derive(Serialize)]
+    struct TargetList<'a> {
+        toolchain: &'a str,
+        targets: Vec<ComponentDetails>,
+    }
+    print_json(&TargetList {
+        toolchain: toolchain.name(),
+        targets,
+    })?;
+    Ok(utils::ExitCode(0))
+}
+
+pub(crate) fn list_components_json(
+    toolchain: &Toolchain<'_>,
+    installed_only: bool,
+) -> Result<utils::ExitCode> {
+    let components: Vec<_> = listed_components(toolchain, installed_only)?
+        .iter()
+        .map(|c| ComponentDetails::new(c.name.clone(), c))
+        .collect();
+    #[derive(Serialize)]
+    struct ComponentList<'a> {
+        toolchain: &'a str,
+        components: Vec<ComponentDetails>,
+    }
+    print_json(&ComponentList {
+        toolchain: toolchain.name(),
+        components,
+    })?;
+    Ok(utils::ExitCode(0))
+}
+
 pub(crate) fn list_targets(toolchain: &Toolchain<'_>) -> Result<utils::ExitCode> {
     let mut t = term2::stdout();
     let distributable = DistributableToolchain::new_for_components(&toolchain)?;
@@ -427,105 +723,286 @@ pub(crate) fn list_installed_components(toolchain: &Toolchain<'_>) -> Result<uti
     Ok(utils::ExitCode(0))
 }
 
-fn print_toolchain_path(
-    cfg: &Cfg,
-    toolchain: &str,
-    if_default: &str,
-    if_override: &str,
-    verbose: bool,
-) -> Result<()> {
-    let toolchain_path = {
-        let mut t_path = cfg.toolchains_dir.clone();
-        t_path.push(&toolchain);
-        t_path
-    };
-    let toolchain_meta = fs::symlink_metadata(&toolchain_path)?;
-    let toolchain_path = if verbose {
-        if toolchain_meta.is_dir() {
-            format!("\t{}", toolchain_path.display())
+/// What `rustup toolchain list --verbose` shows about a toolchain. Details
+/// which can't be worked out are left empty rather than failing the listing.
+#[derive(Serialize)]
+struct ToolchainDetails {
+    name: String,
+    default: bool,
+    #[serde(rename = "override")]
+    is_override: bool,
+    /// Provided by the read-only system store rather than the user's home.
+    system: bool,
+    path: PathBuf,
+    /// In bytes.
+    size: Option<u64>,
+    #[serde(serialize_with = "serialize_time")]
+    installed: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "serialize_time")]
+    last_used: Option<DateTime<Utc>>,
+    targets: Vec<String>,
+    components: Vec<String>,
+}
+
+impl ToolchainDetails {
+    /// Only the name, path and status are filled in unless `detailed`, as
+    /// the rest can take a while to work out.
+    fn new(
+        cfg: &Cfg,
+        name: &str,
+        default: bool,
+        is_override: bool,
+        system: bool,
+        detailed: bool,
+    ) -> Result<Self> {
+        let path = cfg.toolchain_path(name);
+        let path = if fs::symlink_metadata(&path)?.is_dir() {
+            path
         } else {
-            format!("\t{}", fs::read_link(toolchain_path)?.display())
+            fs::read_link(&path)?
+        };
+        let mut details = Self {
+            name: name.to_owned(),
+            default,
+            is_override,
+            system,
+            path,
+            size: None,
+            installed: None,
+            last_used: None,
+            targets: Vec::new(),
+            components: Vec::new(),
+        };
+        if !detailed {
+            return Ok(details);
         }
-    } else {
-        String::new()
-    };
-    writeln!(
-        process().stdout(),
-        "{}{}{}{}",
-        &toolchain,
-        if_default,
-        if_override,
-        toolchain_path
-    )?;
-    Ok(())
+
+        let toolchain = cfg.get_toolchain(name, false)?;
+        details.size = utils::dir_size(&details.path).ok();
+        details.installed = toolchain.installed_at().ok().map(DateTime::from);
+        details.last_used = toolchain.last_used().ok().map(DateTime::from);
+        if let Ok(distributable) = DistributableToolchain::new_for_components(&toolchain) {
+            for status in distributable.list_components().unwrap_or_default() {
+                if !status.installed {
+                    continue;
+                }
+                let component = status.component;
+                let short_name = component.short_name_in_manifest();
+                if short_name == "rust-std" {
+                    let target = component.target.as_ref().map(ToString::to_string);
+                    details.targets.extend(target);
+                } else if !details.components.contains(short_name) {
+                    details.components.push(short_name.clone());
+                }
+            }
+        }
+        Ok(details)
+    }
+
+    fn print(&self, verbose: bool) -> Result<()> {
+        let mut out = process().stdout();
+        let if_default = if self.default { " (default)" } else { "" };
+        let if_override = if self.is_override { " (override)" } else { "" };
+        let if_system = if self.system { " (system)" } else { "" };
+        if !verbose {
+            writeln!(
+                out,
+                "{}{}{}{}",
+                self.name, if_default, if_override, if_system
+            )?;
+            return Ok(());
+        }
+
+        writeln!(
+            out,
+            "{}{}{}{}\t{}",
+            self.name,
+            if_default,
+            if_override,
+            if_system,
+            self.path.display()
+        )?;
+        if let Some(size) = self.size {
+            let size = Size::new(size as usize, Unit::B, UnitMode::Norm);
+            writeln!(out, "  size:       {}", size.to_string().trim_start())?;
+        }
+        if let Some(installed) = self.installed {
+            writeln!(out, "  installed:  {}", installed.format("%Y-%m-%d"))?;
+        }
+        if let Some(last_used) = self.last_used {
+            writeln!(out, "  last used:  {}", last_used.format("%Y-%m-%d"))?;
+        }
+        if !self.targets.is_empty() {
+            writeln!(out, "  targets:    {}", self.targets.join(", "))?;
+        }
+        if !self.components.is_empty() {
+            writeln!(out, "  components: {}", self.components.join(", "))?;
+        }
+        Ok(())
+    }
 }
 
-pub(crate) fn list_toolchains(cfg: &Cfg, verbose: bool) -> Result<utils::ExitCode> {
-    let toolchains = cfg.list_toolchains()?;
-    if toolchains.is_empty() {
+fn serialize_time<S: Serializer>(
+    time: &Option<DateTime<Utc>>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    time.map(|t| t.to_rfc3339()).serialize(serializer)
+}
+
+pub(crate) fn list_toolchains(cfg: &Cfg, verbose: bool, json: bool) -> Result<utils::ExitCode> {
+    let system_toolchains = cfg.list_system_toolchains()?;
+    let mut toolchains = cfg.list_toolchains()?;
+    toolchains.extend(system_toolchains.iter().cloned());
+    utils::toolchain_sort(&mut toolchains);
+    if toolchains.is_empty() && !json {
         writeln!(process().stdout(), "no installed toolchains")?;
+        return Ok(utils::ExitCode(0));
+    }
+
+    let def_toolchain_name = if let Ok(Some(def_toolchain)) = cfg.find_default() {
+        def_toolchain.name().to_string()
     } else {
-        let def_toolchain_name = if let Ok(Some(def_toolchain)) = cfg.find_default() {
-            def_toolchain.name().to_string()
-        } else {
-            String::new()
-        };
-        let cwd = utils::current_dir()?;
-        let ovr_toolchain_name = if let Ok(Some((toolchain, _reason))) = cfg.find_override(&cwd) {
-            toolchain.name().to_string()
+        String::new()
+    };
+    let cwd = utils::current_dir()?;
+    let ovr_toolchain_name = if let Ok(Some((toolchain, _reason))) = cfg.find_override(&cwd) {
+        toolchain.name().to_string()
+    } else {
+        String::new()
+    };
+    let mut all_details = Vec::new();
+    for toolchain in toolchains {
+        let details = ToolchainDetails::new(
+            cfg,
+            &toolchain,
+            def_toolchain_name == toolchain,
+            ovr_toolchain_name == toolchain,
+            system_toolchains.contains(&toolchain),
+            verbose || json,
+        )
+        .context("Failed to list toolchains' directories")?;
+        if json {
+            all_details.push(details);
         } else {
-            String::new()
-        };
-        for toolchain in toolchains {
-            let if_default = if def_toolchain_name == *toolchain {
-                " (default)"
-            } else {
-                ""
-            };
-            let if_override = if ovr_toolchain_name == *toolchain {
-                " (override)"
-            } else {
-                ""
-            };
-
-            print_toolchain_path(cfg, &toolchain, if_default, if_override, verbose)
-                .context("Failed to list toolchains' directories")?;
+            details.print(verbose)?;
         }
     }
+    if json {
+        #[derive(Serialize)]
+        struct ToolchainList {
+            toolchains: Vec<ToolchainDetails>,
+        }
+        print_json(&ToolchainList {
+            toolchains: all_details,
+        })?;
+    }
     Ok(utils::ExitCode(0))
 }
 
-pub(crate) fn list_overrides(cfg: &Cfg) -> Result<utils::ExitCode> {
-    let overrides = cfg.settings_file.with(|s| Ok(s.overrides.clone()))?;
+/// An override in the JSON output of `override list`.
+#[derive(Serialize)]
+struct OverrideDetails {
+    path: String,
+    /// Unset for a host override.
+    toolchain: Option<String>,
+    /// Set only for a host override.
+    host: Option<String>,
+    directory_exists: bool,
+    toolchain_installed: bool,
+}
 
-    if overrides.is_empty() {
+pub(crate) fn list_overrides(cfg: &Cfg, json: bool) -> Result<utils::ExitCode> {
+    let (overrides, host_overrides) = cfg
+        .settings_file
+        .with(|s| Ok((s.overrides.clone(), s.host_overrides.clone())))?;
+
+    if json {
+        let overrides = overrides
+            .into_iter()
+            .map(|(path, toolchain)| OverrideDetails {
+                directory_exists: Path::new(&path).is_dir(),
+                toolchain_installed: toolchain_installed(cfg, &toolchain),
+                path,
+                toolchain: Some(toolchain),
+                host: None,
+            });
+        let host_overrides = host_overrides
+            .into_iter()
+            .map(|(path, host)| OverrideDetails {
+                directory_exists: Path::new(&path).is_dir(),
+                toolchain_installed: true,
+                path,
+                toolchain: None,
+                host: Some(host),
+            });
+        #[derive(Serialize)]
+        struct OverrideList {
+            overrides: Vec<OverrideDetails>,
+        }
+        print_json(&OverrideList {
+            overrides: overrides.chain(host_overrides).collect(),
+        })?;
+    } else if overrides.is_empty() && host_overrides.is_empty() {
         writeln!(process().stdout(), "no overrides")?;
     } else {
-        let mut any_not_exist = false;
-        for (k, v) in overrides {
+        let mut any_stale = false;
+        let overrides = overrides.into_iter().map(|(k, v)| {
+            let installed = toolchain_installed(cfg, &v);
+            (k, v, installed)
+        });
+        let host_overrides = host_overrides
+            .into_iter()
+            .map(|(k, v)| (k, format!("host {}", v), true));
+        for (k, v, installed) in overrides.chain(host_overrides) {
             let dir_exists = Path::new(&k).is_dir();
-            if !dir_exists {
-                any_not_exist = true;
+            if !dir_exists || !installed {
+                any_stale = true;
             }
             writeln!(
                 process().stdout(),
                 "{:<40}\t{:<20}",
                 utils::format_path_for_display(&k)
                     + if dir_exists { "" } else { " (not a directory)" },
-                v
+                v + if installed { "" } else { " (not installed)" }
             )?
         }
-        if any_not_exist {
+        if any_stale {
             writeln!(process().stdout())?;
-            info!(
-                "you may remove overrides for non-existent directories with
-`rustup override unset --nonexistent`"
-            );
+            info!("you may remove stale overrides with `rustup override prune`");
         }
     }
     Ok(utils::ExitCode(0))
 }
 
+fn toolchain_installed(cfg: &Cfg, name: &str) -> bool {
+    cfg.get_toolchain(name, false)
+        .map(|toolchain| toolchain.exists())
+        .unwrap_or(false)
+}
+
+/// The overrides whose directory no longer exists or whose toolchain isn't
+/// installed, each with its directory, what it sets, and what is wrong
+/// with it. Host overrides are included, and start with `host `.
+pub(crate) fn stale_overrides(cfg: &Cfg) -> Result<Vec<(String, String, &'static str)>> {
+    let (overrides, host_overrides) = cfg
+        .settings_file
+        .with(|s| Ok((s.overrides.clone(), s.host_overrides.clone())))?;
+    let mut stale = Vec::new();
+    for (dir, toolchain) in overrides {
+        if !Path::new(&dir).is_dir() {
+            stale.push((dir, toolchain, "not a directory"));
+        } else if !toolchain_installed(cfg, &toolchain) {
+            stale.push((dir, toolchain, "not installed"));
+        }
+    }
+    for (dir, host) in host_overrides {
+        if !Path::new(&dir).is_dir() {
+            stale.push((dir, format!("host {}", host), "not a directory"));
+        }
+    }
+    Ok(stale)
+}
+
 git_testament!(TESTAMENT);
 
 pub fn version() -> &'static str {
@@ -611,10 +1088,14 @@ pub fn report_error(e: &anyhow::Error) {
     // reasonable tradeoff, but if we want to do differently, this is the code
     // hunk to revisit, that and a similar build.rs auto-detect glue as anyhow
     // has to detect when backtrace is available.
+    let mut hints = String::new();
+    for hint in errors::hints(e) {
+        hints.push_str(&format!("\nhelp: {}", hint));
+    }
     if show_backtrace() {
-        err!("{:?}", e);
+        err!("{:?}{}", e, hints);
     } else {
-        err!("{:#}", e);
+        err!("{:#}{}", e, hints);
     }
 }
 
@@ -0,0 +1,261 @@
+//! Completing the names of toolchains, components and targets.
+//!
+//! The completion scripts clap writes only know the commands and options.
+//! To each, bar elvish's, a hook is added which, where a toolchain,
+//! component or target goes, asks `rustup list-completions` for the ones
+//! there are at the time, and otherwise leaves it to clap's script.
+
+use std::io::Write;
+
+use anyhow::Result;
+use clap::Shell;
+
+use crate::process;
+use crate::toolchain::DistributableToolchain;
+use crate::utils::utils::{self, ExitCode};
+use crate::Cfg;
+
+/// What `rustup list-completions` can list.
+pub(crate) static KINDS: &[&str] = &[
+    "toolchains",
+    "components",
+    "installed-components",
+    "targets",
+    "installed-targets",
+];
+
+/// Prints the values of `kind` for the completion scripts, one to a line.
+pub(crate) fn list(cfg: &Cfg, kind: &str) -> Result<ExitCode> {
+    let values = match kind {
+        "toolchains" => toolchains(cfg)?,
+        _ => components(cfg, kind)?,
+    };
+    let mut out = process().stdout();
+    for value in values {
+        writeln!(out, "{}", value)?;
+    }
+    Ok(ExitCode(0))
+}
+
+/// The installed toolchains, with those for the host under their short
+/// names too.
+fn toolchains(cfg: &Cfg) -> Result<Vec<String>> {
+    let mut names = cfg.list_toolchains()?;
+    names.extend(cfg.list_system_toolchains()?);
+    let host = format!("-{}", cfg.get_host_triple()?);
+    let short: Vec<_> = names
+        .iter()
+        .filter(|name| name.ends_with(&host))
+        .map(|name| name[..name.len() - host.len()].to_owned())
+        .collect();
+    names.extend(short);
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// The components or targets of the active toolchain: those which can be
+/// added, or those installed. Unlike running a tool, completing one never
+/// installs the toolchain, and a custom toolchain has none to complete.
+fn components(cfg: &Cfg, kind: &str) -> Result<Vec<String>> {
+    let toolchain = match cfg.find_override(&utils::current_dir()?)? {
+        Some((toolchain, _)) => Some(toolchain),
+        None => cfg.find_default()?,
+    };
+    let toolchain = match toolchain {
+        Some(toolchain) if toolchain.exists() && !toolchain.is_custom() => toolchain,
+        _ => return Ok(Vec::new()),
+    };
+    let distributable = DistributableToolchain::new_for_components(&toolchain)?;
+    let suffix = format!("-{}", distributable.component_target()?);
+    let installed = kind.starts_with("installed-");
+    let targets = kind.ends_with("targets");
+    let mut values = Vec::new();
+    for status in distributable.list_components()? {
+        let is_target = status.component.short_name_in_manifest() == "rust-std";
+        if is_target != targets || status.installed != installed {
+            continue;
+        }
+        if !installed && !status.available {
+            continue;
+        }
+        let value = match (&status.component.target, is_target) {
+            (Some(target), true) => target.to_string(),
+            // `component add` takes the components for the toolchain's own
+            // target without it.
+            _ if status.name.ends_with(&suffix) => {
+                status.name[..status.name.len() - suffix.len()].to_owned()
+            }
+            _ => status.name,
+        };
+        values.push(value);
+    }
+    Ok(values)
+}
+
+/// Adds the hook for `shell` to clap's completion script for rustup.
+pub(crate) fn add_hook(shell: Shell, script: &str) -> String {
+    match shell {
+        Shell::Bash => {
+            // The hook takes over from clap's function, and falls back to it.
+            let complete = "complete -F _rustup -o bashdefault -o default rustup";
+            let script = script.replace(complete, "");
+            format!("{}{}", script.trim_end(), BASH_HOOK)
+        }
+        Shell::Zsh => {
+            let script = script.trim_end();
+            let script = script.strip_suffix("_rustup \"$@\"").unwrap_or(script);
+            format!("{}{}", script.trim_end(), ZSH_HOOK)
+        }
+        Shell::Fish => format!("{}{}", script.trim_end(), FISH_HOOK),
+        Shell::PowerShell => {
+            let results = "    $completions.Where{";
+            script.replacen(results, &format!("{}{}", POWERSHELL_HOOK, results), 1)
+        }
+        Shell::Elvish => script.to_owned(),
+    }
+}
+
+static BASH_HOOK: &str = r#"
+
+_rustup_values() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local i word skip= plus= kind= words=()
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        word="${COMP_WORDS[i]}"
+        if [[ -n "$skip" ]]; then
+            skip=
+            continue
+        fi
+        case "$word" in
+            --toolchain-file|--profile|--format|--color) skip=1 ;;
+            +*) plus="$word" ;;
+            -*) ;;
+            *) words+=("$word") ;;
+        esac
+    done
+
+    if [[ "$prev" == --toolchain ]]; then
+        kind=toolchains
+    elif [[ "$cur" == +* && ${#words[@]} -eq 0 ]]; then
+        COMPREPLY=( $(compgen -P + -W "$(rustup list-completions toolchains 2>/dev/null)" -- "${cur#+}") )
+        return 0
+    else
+        case "${words[*]}" in
+            default|run|"override set"|"override add"|update*|uninstall*|"toolchain uninstall"*|"toolchain remove"*)
+                kind=toolchains ;;
+            "component add"*) kind=components ;;
+            "component remove"*) kind=installed-components ;;
+            "target add"*|"target install"*) kind=targets ;;
+            "target remove"*|"target uninstall"*) kind=installed-targets ;;
+        esac
+    fi
+
+    if [[ -z "$kind" ]]; then
+        _rustup "$@"
+        return
+    fi
+    COMPREPLY=( $(compgen -W "$(rustup $plus list-completions $kind 2>/dev/null)" -- "$cur") )
+}
+
+complete -F _rustup_values -o bashdefault -o default rustup
+"#;
+
+static ZSH_HOOK: &str = r#"
+
+_rustup_values() {
+    local word skip= plus= kind=
+    local -a args values
+    for word in "${(@)words[2,CURRENT-1]}"; do
+        if [[ -n $skip ]]; then
+            skip=
+            continue
+        fi
+        case $word in
+            --toolchain-file|--profile|--format|--color) skip=1 ;;
+            +*) plus=$word ;;
+            -*) ;;
+            *) args+=($word) ;;
+        esac
+    done
+
+    if [[ ${words[CURRENT-1]} == --toolchain ]]; then
+        kind=toolchains
+    elif [[ ${words[CURRENT]} == +* && ${#args} -eq 0 ]]; then
+        values=(${(f)"$(rustup list-completions toolchains 2>/dev/null)"})
+        compadd -P + -a values
+        return
+    else
+        case "${args[*]}" in
+            default|run|"override set"|"override add"|update*|uninstall*|"toolchain uninstall"*|"toolchain remove"*)
+                kind=toolchains ;;
+            "component add"*) kind=components ;;
+            "component remove"*) kind=installed-components ;;
+            "target add"*|"target install"*) kind=targets ;;
+            "target remove"*|"target uninstall"*) kind=installed-targets ;;
+        esac
+    fi
+
+    if [[ -z $kind ]]; then
+        _rustup "$@"
+        return
+    fi
+    values=(${(f)"$(rustup $plus list-completions $kind 2>/dev/null)"})
+    compadd -a values
+}
+
+_rustup_values "$@"
+"#;
+
+static FISH_HOOK: &str = r#"
+
+function __rustup_values
+    set -l plus (commandline -opc | string match -r '^\+.+')[1]
+    rustup $plus list-completions $argv 2>/dev/null
+end
+
+function __rustup_after_toolchain_option
+    test (commandline -opc)[-1] = --toolchain
+end
+
+complete -c rustup -n "__rustup_after_toolchain_option" -x -a "(__rustup_values toolchains)"
+complete -c rustup -n "__fish_use_subcommand" -f -a "(__rustup_values toolchains | string replace -r '^' '+')"
+complete -c rustup -n "__fish_seen_subcommand_from default run update; and not __fish_seen_subcommand_from toolchain" -f -a "(__rustup_values toolchains)"
+complete -c rustup -n "__fish_seen_subcommand_from uninstall remove; and not __fish_seen_subcommand_from component target" -f -a "(__rustup_values toolchains)"
+complete -c rustup -n "__fish_seen_subcommand_from override; and __fish_seen_subcommand_from set add" -f -a "(__rustup_values toolchains)"
+complete -c rustup -n "__fish_seen_subcommand_from component; and __fish_seen_subcommand_from add" -f -a "(__rustup_values components)"
+complete -c rustup -n "__fish_seen_subcommand_from component; and __fish_seen_subcommand_from remove" -f -a "(__rustup_values installed-components)"
+complete -c rustup -n "__fish_seen_subcommand_from target; and __fish_seen_subcommand_from add install" -f -a "(__rustup_values targets)"
+complete -c rustup -n "__fish_seen_subcommand_from target; and __fish_seen_subcommand_from remove uninstall" -f -a "(__rustup_values installed-targets)"
+"#;
+
+static POWERSHELL_HOOK: &str = r#"    $words = @($commandElements | Select-Object -Skip 1 | ForEach-Object { "$_" })
+    if ($wordToComplete) {
+        $words = @($words | Select-Object -First ($words.Count - 1))
+    }
+    $plus = @($words | Where-Object { $_.StartsWith('+') } | Select-Object -First 1)
+    $positional = @($words | Where-Object { -not $_.StartsWith('+') -and -not $_.StartsWith('-') })
+    $kind = $null
+    if ($words.Count -gt 0 -and $words[-1] -eq '--toolchain') {
+        $kind = 'toolchains'
+    } elseif ($wordToComplete.StartsWith('+') -and $positional.Count -eq 0) {
+        return & rustup list-completions toolchains 2>$null |
+            Where-Object { "+$_" -like "$wordToComplete*" } |
+            ForEach-Object { [CompletionResult]::new("+$_", "+$_", [CompletionResultType]::ParameterValue, "+$_") }
+    } else {
+        $kind = switch -Regex ($positional -join ' ') {
+            '^(default|run|override (set|add))$' { 'toolchains' }
+            '^(update|uninstall|toolchain (uninstall|remove))( |$)' { 'toolchains' }
+            '^component add( |$)' { 'components' }
+            '^component remove( |$)' { 'installed-components' }
+            '^target (add|install)( |$)' { 'targets' }
+            '^target (remove|uninstall)( |$)' { 'installed-targets' }
+        }
+    }
+    if ($kind) {
+        return & rustup @plus list-completions $kind 2>$null |
+            Where-Object { $_ -like "$wordToComplete*" } |
+            ForEach-Object { [CompletionResult]::new($_, $_, [CompletionResultType]::ParameterValue, $_) }
+    }
+
+"#;
@@ -0,0 +1,604 @@
+//! `rustup config`: reading and changing the settings in settings.toml by
+//! name, each checked as `rustup set` checks it, rather than by editing
+//! the file, and exporting them to set up another machine the same way.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use super::common;
+use crate::hooks;
+use crate::policy::Policy;
+use crate::process;
+use crate::settings::{parse_name_list, set_override, setting_value, settings_in, Settings};
+use crate::toml_utils::get_table;
+use crate::toolchain::DistributableToolchain;
+use crate::utils::utils::{self, ExitCode};
+use crate::utils::Notification;
+use crate::Cfg;
+
+/// A setting, named as it is in settings.toml, with a dot for those in a
+/// table.
+struct Key {
+    name: &'static str,
+    get: fn(&Settings) -> Option<String>,
+    set: fn(&mut Cfg, &str) -> Result<()>,
+    unset: fn(&mut Settings),
+}
+
+static KEYS: &[Key] = &[
+    Key {
+        name: "default_toolchain",
+        get: |s| s.default_toolchain.clone(),
+        set: |cfg, value| cfg.get_toolchain_to_save(value)?.make_default(),
+        unset: |s| s.default_toolchain = None,
+    },
+    Key {
+        name: "default_host_triple",
+        get: |s| s.default_host_triple.clone(),
+        set: |cfg, value| {
+            cfg.set_default_host_triple(value)?;
+            info!("default host triple set to '{}'", value);
+            Ok(())
+        },
+        unset: |s| s.default_host_triple = None,
+    },
+    Key {
+        name: "profile",
+        get: |s| s.profile.map(|v| v.to_string()),
+        set: |cfg, value| cfg.set_profile(value),
+        unset: |s| s.profile = None,
+    },
+    Key {
+        name: "auto_self_update",
+        get: |s| s.auto_self_update.as_ref().map(|v| v.to_string()),
+        set: |cfg, value| cfg.set_auto_self_update(value),
+        unset: |s| s.auto_self_update = None,
+    },
+    Key {
+        name: "auto_install_targets",
+        get: |s| s.auto_install_targets.map(|v| v.to_string()),
+        set: |cfg, value| cfg.set_auto_install_targets(value),
+        unset: |s| s.auto_install_targets = None,
+    },
+    Key {
+        name: "auto_install_components",
+        get: |s| s.auto_install_components.map(|v| v.to_string()),
+        set: |cfg, value| cfg.set_auto_install_components(value),
+        unset: |s| s.auto_install_components = None,
+    },
+    Key {
+        name: "toolchain_file_search",
+        get: |s| s.toolchain_file_search.map(|v| v.to_string()),
+        set: |cfg, value| cfg.set_toolchain_file_search(value),
+        unset: |s| s.toolchain_file_search = None,
+    },
+    Key {
+        name: "shims",
+        get: |s| s.shims.map(|v| v.to_string()),
+        set: |cfg, value| cfg.set_shims(value),
+        unset: |s| s.shims = None,
+    },
+    Key {
+        name: "color_theme",
+        get: |s| s.color_theme.map(|v| v.to_string()),
+        set: |cfg, value| cfg.set_color_theme(value),
+        unset: |s| s.color_theme = None,
+    },
+    Key {
+        name: "usage_stats",
+        get: |s| s.usage_stats.map(|v| v.to_string()),
+        set: |cfg, value| cfg.set_usage_stats(value),
+        unset: |s| s.usage_stats = None,
+    },
+    Key {
+        name: "rustc_wrapper",
+        get: |s| s.rustc_wrapper.clone(),
+        set: |cfg, value| {
+            cfg.set_rustc_wrapper(None, Some(value))?;
+            info!("rustc wrapper set to '{}'", value);
+            Ok(())
+        },
+        unset: |s| s.rustc_wrapper = None,
+    },
+    Key {
+        name: "default_components",
+        get: |s| {
+            if s.default_components.is_empty() {
+                None
+            } else {
+                Some(s.default_components.join(","))
+            }
+        },
+        set: |cfg, value| cfg.set_default_components(&parse_name_list(value, "component")?),
+        unset: |s| s.default_components.clear(),
+    },
+    Key {
+        name: "default_targets",
+        get: |s| {
+            if s.default_targets.is_empty() {
+                None
+            } else {
+                Some(s.default_targets.join(","))
+            }
+        },
+        set: |cfg, value| cfg.set_default_targets(&parse_name_list(value, "target")?),
+        unset: |s| s.default_targets.clear(),
+    },
+    Key {
+        name: "pgp_keys",
+        get: |s| s.pgp_keys.clone(),
+        set: |cfg, value| {
+            if !utils::is_file(value) {
+                return Err(anyhow!("PGP key file '{}' does not exist", value));
+            }
+            cfg.settings_file.with_mut(|s| {
+                s.pgp_keys = Some(value.to_owned());
+                Ok(())
+            })?;
+            info!("PGP keys set to '{}'", value);
+            Ok(())
+        },
+        unset: |s| s.pgp_keys = None,
+    },
+    Key {
+        name: "network.ip-version",
+        get: |s| s.network.ip_version.map(|v| v.to_string()),
+        set: |cfg, value| cfg.set_ip_version(value),
+        unset: |s| s.network.ip_version = None,
+    },
+    Key {
+        name: "network.proxy",
+        get: |s| s.network.proxy.clone(),
+        set: |cfg, value| set_network(cfg, "network.proxy", value),
+        unset: |s| s.network.proxy = None,
+    },
+    Key {
+        name: "network.retries",
+        get: |s| s.network.retries.map(|v| v.to_string()),
+        set: |cfg, value| set_network(cfg, "network.retries", value),
+        unset: |s| s.network.retries = None,
+    },
+    Key {
+        name: "network.timeout",
+        get: |s| s.network.timeout.map(|v| v.to_string()),
+        set: |cfg, value| set_network(cfg, "network.timeout", value),
+        unset: |s| s.network.timeout = None,
+    },
+    Key {
+        name: "network.rate-limit",
+        get: |s| s.network.rate_limit.map(|v| v.to_string()),
+        set: |cfg, value| set_network(cfg, "network.rate-limit", value),
+        unset: |s| s.network.rate_limit = None,
+    },
+    Key {
+        name: "network.mirrors",
+        get: |s| {
+            if s.network.mirrors.is_empty() {
+                None
+            } else {
+                Some(s.network.mirrors.join(","))
+            }
+        },
+        set: |cfg, value| set_network(cfg, "network.mirrors", value),
+        unset: |s| s.network.mirrors.clear(),
+    },
+    Key {
+        name: "network.offline",
+        get: |s| s.network.offline.map(|v| v.to_string()),
+        set: |cfg, value| set_network(cfg, "network.offline", value),
+        unset: |s| s.network.offline = None,
+    },
+    Key {
+        name: "temp.dir",
+        get: |s| s.temp.dir.as_ref().map(|v| v.display().to_string()),
+        set: |cfg, value| set_by_name(cfg, "temp.dir", value),
+        unset: |s| s.temp.dir = None,
+    },
+    Key {
+        name: "temp.max-age",
+        get: |s| s.temp.max_age.map(|v| v.to_string()),
+        set: |cfg, value| set_by_name(cfg, "temp.max-age", value),
+        unset: |s| s.temp.max_age = None,
+    },
+    Key {
+        name: "auto_update.interval",
+        get: |s| s.auto_update.interval.map(|v| v.to_string()),
+        set: |cfg, value| set_by_name(cfg, "auto_update.interval", value),
+        unset: |s| s.auto_update.interval = None,
+    },
+    Key {
+        name: "auto_update.channels",
+        get: |s| s.auto_update.channels.as_ref().map(|v| v.to_string()),
+        set: |cfg, value| set_by_name(cfg, "auto_update.channels", value),
+        unset: |s| s.auto_update.channels = None,
+    },
+    Key {
+        name: "override_hook.ttl",
+        get: |s| s.override_hook.ttl.map(|v| v.to_string()),
+        set: |cfg, value| {
+            let ttl = u64::from_str(value).map_err(|_| {
+                anyhow!(
+                    "invalid value '{}' for 'override_hook.ttl': expected a number of seconds",
+                    value
+                )
+            })?;
+            cfg.settings_file.with_mut(|s| {
+                s.override_hook.ttl = Some(ttl);
+                Ok(())
+            })?;
+            info!("override hook answers kept for {} seconds", ttl);
+            Ok(())
+        },
+        unset: |s| s.override_hook.ttl = None,
+    },
+];
+
+/// Sets one of the `[network]` settings, checked as its
+/// `RUSTUP_SETTINGS_*` variable is.
+fn set_network(cfg: &mut Cfg, name: &str, value: &str) -> Result<()> {
+    set_by_name(cfg, name, value)?;
+    cfg.apply_network_settings()
+}
+
+/// Sets `name` as a `RUSTUP_SETTINGS_*` variable would, for the settings
+/// with no `rustup set` command of their own.
+fn set_by_name(cfg: &mut Cfg, name: &str, value: &str) -> Result<()> {
+    cfg.settings_file.with_mut(|s| {
+        set_override(s, name, value)
+            .map_err(|e| anyhow!("invalid value '{}' for '{}': {}", value, name, e))
+    })
+}
+
+/// The names `rustup config` takes.
+pub(crate) fn names() -> Vec<&'static str> {
+    KEYS.iter().map(|key| key.name).collect()
+}
+
+fn key(name: &str) -> Result<&'static Key> {
+    KEYS.iter().find(|key| key.name == name).ok_or_else(|| {
+        anyhow!(
+            "unknown setting '{}'; valid settings are {}",
+            name,
+            names().join(", ")
+        )
+    })
+}
+
+/// Prints the value of `name`, or fails with exit code 1, printing nothing,
+/// if it isn't set, so that scripts can tell the two apart.
+pub(crate) fn get(cfg: &Cfg, name: &str) -> Result<ExitCode> {
+    let key = key(name)?;
+    match cfg.settings_file.with(|s| Ok((key.get)(s)))? {
+        Some(value) => {
+            writeln!(process().stdout(), "{}", value)?;
+            Ok(ExitCode(0))
+        }
+        None => Ok(ExitCode(1)),
+    }
+}
+
+pub(crate) fn set(cfg: &mut Cfg, name: &str, value: &str) -> Result<ExitCode> {
+    let key = key(name)?;
+    (key.set)(cfg, value)?;
+    Ok(ExitCode(0))
+}
+
+pub(crate) fn unset(cfg: &Cfg, name: &str) -> Result<ExitCode> {
+    let key = key(name)?;
+    cfg.settings_file.with_mut(|s| {
+        (key.unset)(s);
+        Ok(())
+    })?;
+    info!("'{}' unset", name);
+    Ok(ExitCode(0))
+}
+
+/// Lists the settings which are set, or with `all` every one, unset ones
+/// too.
+pub(crate) fn list(cfg: &Cfg, all: bool, json: bool) -> Result<ExitCode> {
+    let values: BTreeMap<&str, Option<String>> = cfg.settings_file.with(|s| {
+        Ok(KEYS
+            .iter()
+            .map(|key| (key.name, (key.get)(s)))
+            .filter(|(_, value)| all || value.is_some())
+            .collect())
+    })?;
+    if json {
+        #[derive(Serialize)]
+        struct Listed<'a> {
+            settings: &'a BTreeMap<&'a str, Option<String>>,
+        }
+        common::print_json(&Listed { settings: &values })?;
+        return Ok(ExitCode(0));
+    }
+
+    let mut out = process().stdout();
+    for (name, value) in &values {
+        match value {
+            Some(value) => writeln!(out, "{} = {}", name, value)?,
+            None => writeln!(out, "{} (not set)", name)?,
+        }
+    }
+    Ok(ExitCode(0))
+}
+
+/// The settings `export` leaves out, which belong to the machine rather
+/// than to the user: its host, and a file of keys the other machine may not
+/// have.
+static MACHINE_KEYS: &[&str] = &["default_host_triple", "pgp_keys"];
+
+/// The settings whose values are lists, exported as arrays.
+static LIST_KEYS: &[&str] = &["default_components", "default_targets", "network.mirrors"];
+
+/// Prints the user's settings, with the toolchain and command aliases, as
+/// TOML for `import` to read on another machine. Only what is in
+/// settings.toml is exported, not what the environment or a project
+/// overrides.
+pub(crate) fn export(cfg: &Cfg) -> Result<ExitCode> {
+    let path = cfg.settings_file.path();
+    let settings = Settings::parse(&utils::read_file("settings", path)?)?;
+    let mut exported = toml::value::Table::new();
+    for key in KEYS.iter().filter(|key| !MACHINE_KEYS.contains(&key.name)) {
+        let value = match (key.get)(&settings) {
+            Some(value) => value,
+            None => continue,
+        };
+        let value = if LIST_KEYS.contains(&key.name) {
+            toml::Value::Array(
+                value
+                    .split(',')
+                    .map(|v| toml::Value::String(v.to_owned()))
+                    .collect(),
+            )
+        } else if let Ok(number) = i64::from_str(&value) {
+            toml::Value::Integer(number)
+        } else {
+            toml::Value::String(value)
+        };
+        // Those with a dot go in a table, as they are in settings.toml.
+        let mut table = &mut exported;
+        let mut parts: Vec<&str> = key.name.split('.').collect();
+        let name = parts.pop().unwrap();
+        for part in parts {
+            table = table
+                .entry(part)
+                .or_insert_with(|| toml::Value::Table(toml::value::Table::new()))
+                .as_table_mut()
+                .unwrap();
+        }
+        table.insert(name.to_owned(), value);
+    }
+    let strings = |map: &BTreeMap<String, String>| {
+        map.iter()
+            .map(|(k, v)| (k.clone(), toml::Value::String(v.clone())))
+            .collect::<toml::value::Table>()
+    };
+    if !settings.toolchain_aliases.is_empty() {
+        let aliases = strings(&settings.toolchain_aliases);
+        exported.insert("toolchain_aliases".to_owned(), toml::Value::Table(aliases));
+    }
+    if !settings.command_aliases.is_empty() {
+        let aliases = strings(&settings.command_aliases);
+        exported.insert("aliases".to_owned(), toml::Value::Table(aliases));
+    }
+
+    let mut out = process().stdout();
+    writeln!(out, "# Exported by `rustup config export`.")?;
+    writeln!(
+        out,
+        "# Set up another machine with `rustup config import <file>`."
+    )?;
+    write!(out, "{}", toml::Value::Table(exported))?;
+    Ok(ExitCode(0))
+}
+
+/// Sets the settings and aliases exported to `path`, then installs the
+/// default toolchain they name if it isn't installed yet. Every setting is
+/// checked before any is changed.
+pub(crate) fn import(cfg: &mut Cfg, path: &Path) -> Result<ExitCode> {
+    let what = || format!("invalid settings in '{}'", path.display());
+    let data = utils::read_file("exported settings", path)?;
+    let mut table: toml::value::Table = toml::from_str(&data).with_context(what)?;
+    let aliases = strings_in(&mut table, "toolchain_aliases").with_context(what)?;
+    let command_aliases = strings_in(&mut table, "aliases").with_context(what)?;
+    let values = settings_in(table).with_context(what)?;
+
+    for (name, value) in &values {
+        (key(name)?.set)(cfg, value)?;
+    }
+    for (alias, toolchain) in &aliases {
+        cfg.set_alias(alias, toolchain)?;
+    }
+    if !command_aliases.is_empty() {
+        cfg.settings_file.with_mut(|s| {
+            s.command_aliases.extend(command_aliases.clone());
+            Ok(())
+        })?;
+    }
+    info!("imported the settings from '{}'", path.display());
+
+    if let Some(name) = cfg.get_default()? {
+        let toolchain = cfg.get_toolchain(&name, false)?;
+        if !toolchain.is_custom() && !toolchain.exists() {
+            let distributable = DistributableToolchain::new(&toolchain)?;
+            let status = distributable.install_from_dist_if_not_installed()?;
+            writeln!(process().stdout())?;
+            common::show_channel_update(cfg, toolchain.name(), Ok(status))?;
+        }
+    }
+    Ok(ExitCode(0))
+}
+
+/// What `rustup config check` found wrong.
+#[derive(Default)]
+struct Problems {
+    errors: usize,
+    warnings: usize,
+}
+
+impl Problems {
+    fn error(&mut self, message: impl std::fmt::Display) {
+        err!("{}", message);
+        self.errors += 1;
+    }
+
+    fn warn(&mut self, message: impl std::fmt::Display) {
+        warn!("{}", message);
+        self.warnings += 1;
+    }
+}
+
+/// Checks the settings, the policy, the overrides and the mirrors, going on
+/// past each problem to report the rest, and exits with status 1 if any is
+/// an error. This runs before the rest of the configuration is read, since
+/// a settings file with a mistake in it would stop that.
+pub(crate) fn check(verbose: bool, quiet: bool, home_profile: Option<&str>) -> Result<ExitCode> {
+    let mut problems = Problems::default();
+    let path = Cfg::settings_path(home_profile)?;
+    if utils::is_file(&path) {
+        check_settings(&path, &mut problems)?;
+    }
+    if let Err(e) = Policy::load() {
+        problems.error(format!("{:#}", e));
+    }
+
+    match common::set_globals(verbose, quiet, home_profile) {
+        Ok(cfg) => {
+            check_overrides(&cfg, &mut problems)?;
+            check_mirrors(&cfg, &mut problems)?;
+        }
+        Err(_) if problems.errors > 0 => {
+            warn!("the overrides and mirrors can't be checked until that is fixed")
+        }
+        Err(e) => problems.error(format!("{:#}", e)),
+    }
+
+    if problems.errors > 0 {
+        return Ok(ExitCode(1));
+    }
+    if problems.warnings == 0 {
+        info!("the configuration is valid");
+    }
+    Ok(ExitCode(0))
+}
+
+fn check_settings(path: &Path, problems: &mut Problems) -> Result<()> {
+    let content = utils::read_file("settings", path)?;
+    let table: toml::value::Table = match toml::from_str(&content) {
+        Ok(table) => table,
+        Err(e) => {
+            problems.error(format!("'{}' isn't valid TOML: {}", path.display(), e));
+            return Ok(());
+        }
+    };
+    let mut invalid = false;
+    for key in KEYS {
+        if let Some(value) = value_in(&table, key.name) {
+            if let Err(e) = setting_value(key.name, value.clone()) {
+                problems.error(format!("in '{}': {:#}", path.display(), e));
+                invalid = true;
+            }
+        }
+    }
+
+    match Settings::parse(&content) {
+        // What's wrong with a setting is clearer from the check above.
+        Err(_) if invalid => {}
+        Err(e) => problems.error(format!("in '{}': {:#}", path.display(), e)),
+        Ok(settings) => {
+            let tables = [
+                ("", &settings.unknown),
+                ("network.", &settings.network.unknown),
+                ("temp.", &settings.temp.unknown),
+                ("override_hook.", &settings.override_hook.unknown),
+                ("auto_update.", &settings.auto_update.unknown),
+            ];
+            for (prefix, unknown) in &tables {
+                for key in unknown.keys() {
+                    problems.warn(format!(
+                        "unknown setting '{}{}' in '{}', which this rustup ignores",
+                        prefix,
+                        key,
+                        path.display()
+                    ));
+                }
+            }
+            for hook in settings.hooks.keys() {
+                if !hooks::HOOKS.contains(&hook.as_str()) {
+                    problems.warn(format!(
+                        "unknown hook '{}' in '{}', which is never run",
+                        hook,
+                        path.display()
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The value of the setting `name`, with a dot for one in a table.
+fn value_in<'a>(table: &'a toml::value::Table, name: &str) -> Option<&'a toml::Value> {
+    let mut parts = name.split('.');
+    let mut value = table.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_table()?.get(part)?;
+    }
+    Some(value)
+}
+
+fn check_overrides(cfg: &Cfg, problems: &mut Problems) -> Result<()> {
+    let overrides = cfg.settings_file.with(|s| Ok(s.overrides.clone()))?;
+    for (dir, name) in overrides {
+        let resolved = cfg
+            .resolve_toolchain(&name)
+            .unwrap_or_else(|_| name.clone());
+        let installed = matches!(cfg.get_toolchain(&resolved, false), Ok(t) if t.exists());
+        if !installed {
+            problems.error(format!(
+                "the override for '{}' is '{}', which isn't installed",
+                dir, name
+            ));
+        }
+        if !Path::new(&dir).exists() {
+            problems.warn(format!(
+                "the override for '{}' is for a directory which no longer exists; \
+                 `rustup override unset --nonexistent` removes it",
+                dir
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Fetches the checksum of the stable manifest from each mirror, as the
+/// smallest thing every dist server has.
+fn check_mirrors(cfg: &Cfg, problems: &mut Problems) -> Result<()> {
+    let mirrors = cfg.settings_file.with(|s| Ok(s.network.mirrors.clone()))?;
+    for mirror in mirrors {
+        let file = cfg.temp_cfg.new_file()?;
+        let url = format!("{}/dist/channel-rust-stable.toml.sha256", mirror);
+        let reached = utils::parse_url(&url)
+            .and_then(|url| utils::download_file(&url, &file, None, &|_: Notification<'_>| {}));
+        if let Err(e) = reached {
+            problems.error(format!("the mirror '{}' can't be reached: {:#}", mirror, e));
+        }
+    }
+    Ok(())
+}
+
+/// The table of strings `name` in `table`, such as the aliases.
+fn strings_in(table: &mut toml::value::Table, name: &str) -> Result<Vec<(String, String)>> {
+    get_table(table, name, "")?
+        .into_iter()
+        .map(|(key, value)| match value {
+            toml::Value::String(value) => Ok((key, value)),
+            _ => Err(anyhow!("expected a string for '{}.{}'", name, key)),
+        })
+        .collect()
+}
@@ -0,0 +1,300 @@
+//! `rustup doctor`: looking for the problems that most often leave rustup
+//! or the toolchains it manages unusable, and suggesting how to fix them.
+
+use std::env;
+use std::env::consts::EXE_SUFFIX;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use url::Url;
+
+use super::term2::{self, Terminal};
+use crate::dist::dist::TargetTriple;
+use crate::process;
+use crate::utils::utils::{self, ExitCode};
+use crate::{Cfg, TOOLS};
+
+struct Problem {
+    description: String,
+    fix: String,
+}
+
+impl Problem {
+    fn new(description: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            description: description.into(),
+            fix: fix.into(),
+        }
+    }
+}
+
+type Check = fn(&Cfg) -> Result<Vec<Problem>>;
+
+static CHECKS: &[(&str, Check)] = &[
+    ("proxies", check_proxies),
+    ("PATH", check_path),
+    ("overrides", check_overrides),
+    ("rustup home", check_rustup_home),
+    ("linker", check_linker),
+    ("network", check_network),
+];
+
+pub(crate) fn doctor(cfg: &Cfg) -> Result<ExitCode> {
+    let mut t = term2::stdout();
+    let mut total = 0;
+    for (name, check) in CHECKS {
+        let problems = check(cfg)?;
+        let _ = t.attr(term2::Attr::Bold);
+        write!(t, "{}: ", name)?;
+        let _ = t.reset();
+        if problems.is_empty() {
+            let _ = t.style(term2::Style::Good);
+            writeln!(t, "ok")?;
+            let _ = t.reset();
+            continue;
+        }
+        let _ = t.style(term2::Style::Warning);
+        writeln!(t, "{}", plural(problems.len(), "problem"))?;
+        let _ = t.reset();
+        for problem in &problems {
+            writeln!(t, "  {}", problem.description)?;
+            writeln!(t, "    fix: {}", problem.fix)?;
+        }
+        total += problems.len();
+    }
+
+    writeln!(t)?;
+    if total == 0 {
+        writeln!(t, "no problems found")?;
+        Ok(ExitCode(0))
+    } else {
+        writeln!(t, "{} found", plural(total, "problem"))?;
+        Ok(ExitCode(1))
+    }
+}
+
+/// What the checks find, bar the network one, for `rustup status`, which
+/// finds out about the network from its own downloads.
+pub(crate) fn find_problems(cfg: &Cfg) -> Result<Vec<String>> {
+    let mut found = Vec::new();
+    for (name, check) in CHECKS {
+        if *name != "network" {
+            found.extend(check(cfg)?.into_iter().map(|p| p.description));
+        }
+    }
+    Ok(found)
+}
+
+pub(crate) fn plural(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("1 {}", noun)
+    } else {
+        format!("{} {}s", n, noun)
+    }
+}
+
+fn cargo_bin() -> Result<PathBuf> {
+    Ok(utils::cargo_home()?.join("bin"))
+}
+
+fn exe(name: &str) -> String {
+    format!("{}{}", name, EXE_SUFFIX)
+}
+
+/// Every proxy should be rustup itself under another name.
+fn check_proxies(_: &Cfg) -> Result<Vec<Problem>> {
+    let bin = cargo_bin()?;
+    let rustup = bin.join(exe("rustup"));
+    if !rustup.is_file() {
+        return Ok(vec![Problem::new(
+            format!("rustup is not installed in '{}'", bin.display()),
+            "install rustup again with rustup-init",
+        )]);
+    }
+
+    let mut problems = Vec::new();
+    for tool in TOOLS {
+        let path = bin.join(exe(tool));
+        if !path.exists() {
+            problems.push(Problem::new(
+                format!("the {} proxy is missing", tool),
+                "run `rustup self update` to restore it",
+            ));
+        } else if !same_file::is_same_file(&path, &rustup).unwrap_or(false) {
+            problems.push(Problem::new(
+                format!("'{}' is not a rustup proxy", path.display()),
+                "remove it and run `rustup self update` to restore the proxy",
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+/// rustup's proxies must be found in PATH before any other Rust install.
+fn check_path(_: &Cfg) -> Result<Vec<Problem>> {
+    let bin = cargo_bin()?;
+    let path = process().var_os("PATH").unwrap_or_default();
+    let dirs: Vec<_> = env::split_paths(&path).collect();
+    if !dirs.iter().any(|dir| same_dir(dir, &bin)) {
+        return Ok(vec![Problem::new(
+            format!("'{}' is not in PATH", bin.display()),
+            format!("add '{}' to PATH", bin.display()),
+        )]);
+    }
+
+    let mut problems = Vec::new();
+    for tool in &["rustc", "cargo"] {
+        let first = dirs.iter().find(|dir| dir.join(exe(tool)).is_file());
+        if let Some(dir) = first {
+            if !same_dir(dir, &bin) {
+                problems.push(Problem::new(
+                    format!(
+                        "'{}' comes before rustup's {} in PATH",
+                        dir.join(exe(tool)).display(),
+                        tool
+                    ),
+                    format!(
+                        "uninstall that Rust, or put '{}' before '{}' in PATH",
+                        bin.display(),
+                        dir.display()
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    a == b || same_file::is_same_file(a, b).unwrap_or(false)
+}
+
+/// The default toolchain and overrides should name installed toolchains.
+fn check_overrides(cfg: &Cfg) -> Result<Vec<Problem>> {
+    let (default, overrides) = cfg
+        .settings_file
+        .with(|s| Ok((s.default_toolchain.clone(), s.overrides.clone())))?;
+
+    let mut problems = Vec::new();
+    let installable = |name: &str| {
+        cfg.get_toolchain(name, false)
+            .map(|t| !t.is_custom())
+            .unwrap_or(false)
+    };
+    let installed = |name: &str| {
+        cfg.get_toolchain(name, false)
+            .map(|t| t.exists())
+            .unwrap_or(false)
+    };
+    if let Some(name) = default {
+        if !installed(&name) {
+            problems.push(Problem::new(
+                format!("the default toolchain '{}' is not installed", name),
+                if installable(&name) {
+                    format!("run `rustup toolchain install {}`", name)
+                } else {
+                    "choose another with `rustup default`".to_owned()
+                },
+            ));
+        }
+    }
+    for (dir, name) in overrides {
+        if !Path::new(&dir).is_dir() {
+            problems.push(Problem::new(
+                format!(
+                    "the override for '{}' is for a directory that no longer exists",
+                    dir
+                ),
+                "run `rustup override unset --nonexistent`",
+            ));
+        } else if !installed(&name) {
+            let unset = format!("`rustup override unset --path {}`", dir);
+            problems.push(Problem::new(
+                format!(
+                    "the override for '{}' is '{}', which is not installed",
+                    dir, name
+                ),
+                if installable(&name) {
+                    format!("run `rustup toolchain install {}` or {}", name, unset)
+                } else {
+                    format!("run {}", unset)
+                },
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+fn check_rustup_home(cfg: &Cfg) -> Result<Vec<Problem>> {
+    let probe = tempfile::Builder::new()
+        .prefix(".rustup-doctor")
+        .tempfile_in(&cfg.rustup_dir);
+    Ok(match probe {
+        Ok(_) => Vec::new(),
+        Err(e) => vec![Problem::new(
+            format!("'{}' is not writable: {}", cfg.rustup_dir.display(), e),
+            "fix its permissions, or set RUSTUP_HOME to a directory you can write to",
+        )],
+    })
+}
+
+/// rustc needs a linker from the platform's C toolchain.
+fn check_linker(cfg: &Cfg) -> Result<Vec<Problem>> {
+    let host = cfg.get_host_triple()?;
+    Ok(if has_linker(&host) {
+        Vec::new()
+    } else if host.to_string().contains("msvc") {
+        vec![Problem::new(
+            "the Visual Studio Build Tools were not found",
+            "install them with the \"Desktop development with C++\" workload",
+        )]
+    } else {
+        vec![Problem::new(
+            "no C compiler was found in PATH to use as a linker",
+            "install the platform's C toolchain, such as `build-essential` or the Xcode command line tools",
+        )]
+    })
+}
+
+#[cfg(windows)]
+fn has_linker(host: &TargetTriple) -> bool {
+    let host = host.to_string();
+    if host.contains("msvc") {
+        cc::windows_registry::find_tool(&host, "link.exe").is_some()
+    } else {
+        utils::find_cmd(&["gcc", "cc"]).is_some()
+    }
+}
+
+#[cfg(not(windows))]
+fn has_linker(_: &TargetTriple) -> bool {
+    utils::find_cmd(&["cc", "gcc", "clang"]).is_some()
+}
+
+/// The dist server should be reachable, through any configured proxy.
+fn check_network(cfg: &Cfg) -> Result<Vec<Problem>> {
+    let url = format!("{}/channel-rust-stable.toml.sha256", cfg.dist_root_url);
+    let url = Url::parse(&url)?;
+    let file = cfg.temp_cfg.new_file()?;
+    let result = utils::download_file(&url, &file, None, &|_| {});
+    Ok(match result {
+        Ok(()) => Vec::new(),
+        Err(e) => {
+            let proxy = ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+                .iter()
+                .find_map(|var| process().var(var).ok());
+            let through = match proxy {
+                Some(proxy) => format!(" through the proxy '{}'", proxy),
+                None => String::new(),
+            };
+            vec![Problem::new(
+                format!(
+                    "could not reach {}{}: {:#}",
+                    cfg.dist_root_server, through, e
+                ),
+                "check your network connection and proxy settings; `rustup report network` lists recent download failures",
+            )]
+        }
+    })
+}
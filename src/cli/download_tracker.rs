@@ -1,8 +1,10 @@
 use std::collections::VecDeque;
 use std::fmt;
 use std::io::Write;
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
+use lazy_static::lazy_static;
 use term::Terminal;
 
 use super::term2;
@@ -15,75 +17,351 @@ use crate::Notification;
 /// Keep track of this many past download amounts
 const DOWNLOAD_TRACK_COUNT: usize = 5;
 
-/// Tracks download progress and displays information about it to a terminal.
-pub struct DownloadTracker {
-    /// Content-Length of the to-be downloaded object.
-    content_len: Option<usize>,
-    /// Total data downloaded in bytes.
-    total_downloaded: usize,
-    /// Data downloaded this second.
-    downloaded_this_sec: usize,
-    /// Keeps track of amount of data downloaded every last few secs.
-    /// Used for averaging the download speed. NB: This does not necessarily
+/// A bar is only drawn once its phase has gone on this long, so that quick
+/// ones don't flicker past.
+const SHOW_AFTER: Duration = Duration::from_secs(1);
+
+/// How often the bars are redrawn on a terminal.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How often a phase that is still going is logged when stdout isn't a
+/// terminal, such as in CI, where the bars can't be drawn over.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    /// The bars of every tracker in the process: updates of several
+    /// toolchains at once each have their own tracker, but share the screen.
+    static ref BARS: Mutex<Bars> = Mutex::new(Bars::default());
+}
+
+/// Turns progress reporting on or off for the whole process, as `--quiet`
+/// does.
+pub(crate) fn display_progress(display: bool) {
+    lock().hidden = !display;
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Bars> {
+    // A panic while drawing leaves nothing that matters inconsistent.
+    BARS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// What is being done with the bytes (or files) a bar counts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Downloading,
+    Verifying,
+    Unpacking,
+    Writing,
+}
+
+impl Phase {
+    fn done(self) -> &'static str {
+        match self {
+            Phase::Downloading => "downloaded",
+            Phase::Verifying => "verified",
+            Phase::Unpacking => "unpacked",
+            Phase::Writing => "written",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Downloading => "downloading",
+            Phase::Verifying => "verifying",
+            Phase::Unpacking => "unpacking",
+            Phase::Writing => "writing",
+        })
+    }
+}
+
+/// The progress of one phase of one file.
+struct Bar {
+    id: usize,
+    label: String,
+    phase: Phase,
+    unit: Unit,
+    /// The size of the whole, once known.
+    total: Option<usize>,
+    done: usize,
+    /// Done this second.
+    done_this_sec: usize,
+    /// Keeps track of amount of data done every last few secs.
+    /// Used for averaging the speed. NB: This does not necessarily
     /// represent adjacent seconds; thus it may not show the average at all.
-    downloaded_last_few_secs: VecDeque<usize>,
+    done_last_few_secs: VecDeque<usize>,
     /// Time stamp of the last second
-    last_sec: Option<Instant>,
-    /// Time stamp of the start of the download
-    start_sec: Option<Instant>,
-    /// The terminal we write the information to.
-    /// XXX: Could be a term trait, but with #1818 on the horizon that
-    ///      is a pointless change to make - better to let that transition
-    ///      happen and take stock after that.
-    term: term2::StdoutTerminal,
-    /// Whether we displayed progress for the download or not.
-    ///
-    /// If the download is quick enough, we don't have time to
-    /// display the progress info.
-    /// In that case, we do not want to do some cleanup stuff we normally do.
-    ///
-    /// If we have displayed progress, this is the number of characters we
-    /// rendered, so we can erase it cleanly.
-    displayed_charcount: Option<usize>,
+    last_sec: Instant,
+    started: Instant,
+    /// When a line about this bar was last logged, when not on a terminal.
+    logged: Option<Instant>,
+}
+
+impl Bar {
+    fn speed(&self) -> usize {
+        let sum: usize = self.done_last_few_secs.iter().sum();
+        let len = self.done_last_few_secs.len();
+        if len > 0 {
+            sum / len
+        } else {
+            0
+        }
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.done += len;
+        self.done_this_sec += len;
+        if self.last_sec.elapsed() >= Duration::from_secs(1) {
+            self.last_sec = Instant::now();
+            if self.done_last_few_secs.len() == DOWNLOAD_TRACK_COUNT {
+                self.done_last_few_secs.pop_back();
+            }
+            self.done_last_few_secs.push_front(self.done_this_sec);
+            self.done_this_sec = 0;
+        }
+    }
+
+    fn line(&self) -> String {
+        let done = Size::new(self.done, self.unit, UnitMode::Norm);
+        let speed = self.speed();
+        let speed_h = Size::new(speed, self.unit, UnitMode::Rate);
+        let elapsed = self.started.elapsed().display();
+        match self.total {
+            Some(total) if total > 0 => {
+                let total_h = Size::new(total, self.unit, UnitMode::Norm);
+                let percent = (self.done as f64 / total as f64) * 100.;
+                let remaining = total.saturating_sub(self.done);
+                let eta = Duration::from_secs(if speed == 0 {
+                    std::u64::MAX
+                } else {
+                    (remaining / speed) as u64
+                });
+                format!(
+                    "{} {}: {} / {} ({:3.0} %) {} in {} ETA: {}",
+                    self.label,
+                    self.phase,
+                    done,
+                    total_h,
+                    percent.min(100.),
+                    speed_h,
+                    elapsed,
+                    eta.display(),
+                )
+            }
+            _ => format!(
+                "{} {}: {} {} in {}",
+                self.label, self.phase, done, speed_h, elapsed
+            ),
+        }
+    }
+
+    fn summary(&self) -> String {
+        let elapsed = self.started.elapsed();
+        let done = Size::new(self.done, self.unit, UnitMode::Norm);
+        let millis = elapsed.as_millis().max(1) as usize;
+        let speed = Size::new(self.done * 1000 / millis, self.unit, UnitMode::Rate);
+        format!(
+            "{} {}: {} in {} ({})",
+            self.label,
+            self.phase.done(),
+            done.to_string().trim_start(),
+            elapsed.display().to_string().trim_start(),
+            speed.to_string().trim_start(),
+        )
+    }
+}
+
+/// Draws the bars, one line each, on stdout when it is a terminal, and
+/// otherwise logs an occasional line for those that take a while.
+#[derive(Default)]
+struct Bars {
+    bars: Vec<Bar>,
+    next_id: usize,
+    /// The width of each line drawn last time, to draw over them.
+    drawn: Vec<usize>,
+    last_drawn: Option<Instant>,
+    hidden: bool,
+}
+
+impl Bars {
+    fn start(&mut self, label: String, phase: Phase, unit: Unit) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        let now = Instant::now();
+        self.bars.push(Bar {
+            id,
+            label,
+            phase,
+            unit,
+            total: None,
+            done: 0,
+            done_this_sec: 0,
+            done_last_few_secs: VecDeque::with_capacity(DOWNLOAD_TRACK_COUNT),
+            last_sec: now,
+            started: now,
+            logged: None,
+        });
+        id
+    }
+
+    fn get(&mut self, id: usize) -> Option<&mut Bar> {
+        self.bars.iter_mut().find(|bar| bar.id == id)
+    }
+
+    fn advance(&mut self, id: usize, len: usize) {
+        let bar = match self.get(id) {
+            Some(bar) => bar,
+            None => return,
+        };
+        bar.advance(len);
+        if !tty::stdout_isatty() {
+            let recently_logged = matches!(bar.logged, Some(last) if last.elapsed() < LOG_INTERVAL);
+            if bar.started.elapsed() >= LOG_INTERVAL && !recently_logged {
+                bar.logged = Some(Instant::now());
+                info!("{}", bar.line());
+            }
+            return;
+        }
+        let recently_drawn =
+            matches!(self.last_drawn, Some(last) if last.elapsed() < REDRAW_INTERVAL);
+        if !recently_drawn {
+            self.draw(None);
+        }
+    }
+
+    fn finish(&mut self, id: usize) {
+        let index = match self.bars.iter().position(|bar| bar.id == id) {
+            Some(index) => index,
+            None => return,
+        };
+        let bar = self.bars.remove(index);
+        if tty::stdout_isatty() {
+            // Only a bar which has been drawn leaves its summary behind.
+            let summary = Some(bar.summary()).filter(|_| bar.started.elapsed() >= SHOW_AFTER);
+            if summary.is_some() || !self.drawn.is_empty() {
+                self.draw(summary);
+            }
+        } else if bar.logged.is_some() {
+            info!("{}", bar.summary());
+        }
+    }
+
+    /// Draws over the lines drawn last time: first `finished`, which stays
+    /// where it is, then a line for each bar.
+    fn draw(&mut self, finished: Option<String>) {
+        let bars: Vec<_> = self
+            .bars
+            .iter()
+            .filter(|bar| bar.started.elapsed() >= SHOW_AFTER)
+            .map(Bar::line)
+            .collect();
+        let lines: Vec<_> = finished.iter().chain(bars.iter()).collect();
+        self.last_drawn = Some(Instant::now());
+        if lines.is_empty() && self.drawn.is_empty() {
+            return;
+        }
+
+        let mut t = term2::stdout();
+        let _ = t.carriage_return();
+        for _ in 0..self.drawn.len() {
+            let _ = t.cursor_up();
+        }
+        // We'd prefer to use delete_line() but on Windows it seems to
+        // sometimes do unusual things, so lines are blanked with spaces.
+        for (i, line) in lines.iter().enumerate() {
+            let width = line.chars().count();
+            let blank = self.drawn.get(i).map_or(0, |&w| w.saturating_sub(width));
+            let _ = writeln!(t, "{}{}", line, " ".repeat(blank));
+        }
+        let left_over = self.drawn.len().saturating_sub(lines.len());
+        for &width in &self.drawn[lines.len().min(self.drawn.len())..] {
+            let _ = writeln!(t, "{}", " ".repeat(width));
+        }
+        for _ in 0..left_over {
+            let _ = t.cursor_up();
+        }
+        let _ = t.flush();
+
+        self.drawn = bars.iter().map(|line| line.chars().count()).collect();
+    }
+}
+
+/// Follows the download and install notifications of one `Cfg`, turning
+/// them into bars: a phase of a component (or of some other file) starts
+/// a bar, and finishing it takes the bar away.
+pub struct DownloadTracker {
+    /// Put before the label of each bar, to tell apart the toolchains
+    /// being updated at once.
+    prefix: Option<String>,
+    /// What the bars are about, from the last notification naming a
+    /// component or a file.
+    label: String,
+    /// Whether `label` was just set from a component, which the
+    /// download of its file that follows shouldn't replace.
+    component_announced: bool,
+    phase: Phase,
+    /// The phase a download interrupted, to go back to after it: the
+    /// download of a cached file which turned out to be bad.
+    resume_phase: Option<Phase>,
+    /// The bar in `BARS` of what is going on now.
+    bar: Option<usize>,
     /// What units to show progress in
     units: Vec<Unit>,
-    /// Whether we display progress
-    display_progress: bool,
 }
 
 impl DownloadTracker {
     /// Creates a new DownloadTracker.
     pub fn new() -> Self {
         Self {
-            content_len: None,
-            total_downloaded: 0,
-            downloaded_this_sec: 0,
-            downloaded_last_few_secs: VecDeque::with_capacity(DOWNLOAD_TRACK_COUNT),
-            start_sec: None,
-            last_sec: None,
-            term: term2::stdout(),
-            displayed_charcount: None,
+            prefix: None,
+            label: String::new(),
+            component_announced: false,
+            phase: Phase::Downloading,
+            resume_phase: None,
+            bar: None,
             units: vec![Unit::B],
-            display_progress: true,
         }
     }
 
-    pub fn with_display_progress(mut self, display_progress: bool) -> Self {
-        self.display_progress = display_progress;
+    pub(crate) fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_owned());
         self
     }
 
     pub(crate) fn handle_notification(&mut self, n: &Notification<'_>) -> bool {
         match *n {
+            Notification::Install(In::DownloadingComponent(c, h, t)) => {
+                self.component(c, h, t, Phase::Downloading);
+                false
+            }
+            Notification::Install(In::VerifyingComponent(c, h, t)) => {
+                self.component(c, h, t, Phase::Verifying);
+                false
+            }
+            Notification::Install(In::InstallingComponent(c, h, t)) => {
+                self.component(c, h, t, Phase::Unpacking);
+                false
+            }
+            Notification::Install(In::Utils(Un::DownloadingFile(url, _))) => {
+                if !self.component_announced {
+                    if let Some(name) = url.path_segments().and_then(Iterator::last) {
+                        self.label = name.to_owned();
+                    }
+                }
+                self.component_announced = false;
+                if self.phase != Phase::Downloading {
+                    self.resume_phase = Some(self.phase);
+                    self.phase = Phase::Downloading;
+                }
+                false
+            }
             Notification::Install(In::Utils(Un::DownloadContentLengthReceived(content_len))) => {
                 self.content_length_received(content_len);
-
                 true
             }
             Notification::Install(In::Utils(Un::DownloadDataReceived(data))) => {
-                if tty::stdout_isatty() {
-                    self.data_received(data.len());
-                }
+                self.data_received(data.len());
                 true
             }
             Notification::Install(In::Utils(Un::DownloadFinished)) => {
@@ -103,128 +381,92 @@ impl DownloadTracker {
         }
     }
 
+    fn component(
+        &mut self,
+        name: &str,
+        host: &crate::dist::dist::TargetTriple,
+        target: Option<&crate::dist::dist::TargetTriple>,
+        phase: Phase,
+    ) {
+        self.label = match target {
+            Some(target) if target != host => format!("{} ({})", name, target),
+            _ => name.to_owned(),
+        };
+        self.component_announced = true;
+        self.phase = phase;
+        self.resume_phase = None;
+    }
+
+    /// The bar for what is going on now, started if need be.
+    fn bar(&mut self, bars: &mut Bars) -> usize {
+        if let Some(id) = self.bar {
+            return id;
+        }
+        let label = match &self.prefix {
+            Some(prefix) => format!("{}: {}", prefix, self.label),
+            None => self.label.clone(),
+        };
+        // Panic if someone pops the default bytes unit...
+        let unit = *self.units.last().unwrap();
+        let id = bars.start(label, self.phase, unit);
+        self.bar = Some(id);
+        id
+    }
+
     /// Notifies self that Content-Length information has been received.
     pub fn content_length_received(&mut self, content_len: u64) {
-        self.content_len = Some(content_len as usize);
+        let mut bars = lock();
+        if bars.hidden {
+            return;
+        }
+        let id = self.bar(&mut bars);
+        if let Some(bar) = bars.get(id) {
+            bar.total = Some(content_len as usize);
+        }
     }
 
     /// Notifies self that data of size `len` has been received.
     pub fn data_received(&mut self, len: usize) {
-        self.total_downloaded += len;
-        self.downloaded_this_sec += len;
-
-        let current_time = Instant::now();
-
-        match self.last_sec {
-            None => self.last_sec = Some(current_time),
-            Some(prev) => {
-                let elapsed = current_time.saturating_duration_since(prev);
-                if elapsed >= Duration::from_secs(1) {
-                    if self.display_progress {
-                        self.display();
-                    }
-                    self.last_sec = Some(current_time);
-                    if self.downloaded_last_few_secs.len() == DOWNLOAD_TRACK_COUNT {
-                        self.downloaded_last_few_secs.pop_back();
-                    }
-                    self.downloaded_last_few_secs
-                        .push_front(self.downloaded_this_sec);
-                    self.downloaded_this_sec = 0;
-                }
-            }
+        let mut bars = lock();
+        if bars.hidden {
+            return;
         }
+        let id = self.bar(&mut bars);
+        bars.advance(id, len);
     }
+
     /// Notifies self that the download has finished.
     pub fn download_finished(&mut self) {
-        if self.displayed_charcount.is_some() {
-            // Display the finished state
-            self.display();
-            let _ = writeln!(self.term);
+        if let Some(id) = self.bar.take() {
+            lock().finish(id);
         }
-        self.prepare_for_new_download();
-    }
-    /// Resets the state to be ready for a new download.
-    fn prepare_for_new_download(&mut self) {
-        self.content_len = None;
-        self.total_downloaded = 0;
-        self.downloaded_this_sec = 0;
-        self.downloaded_last_few_secs.clear();
-        self.start_sec = Some(Instant::now());
-        self.last_sec = None;
-        self.displayed_charcount = None;
-    }
-    /// Display the tracked download information to the terminal.
-    fn display(&mut self) {
-        match self.start_sec {
-            // Maybe forgot to call `prepare_for_new_download` first
-            None => {}
-            Some(start_sec) => {
-                // Panic if someone pops the default bytes unit...
-                let unit = *self.units.last().unwrap();
-                let total_h = Size::new(self.total_downloaded, unit, UnitMode::Norm);
-                let sum: usize = self.downloaded_last_few_secs.iter().sum();
-                let len = self.downloaded_last_few_secs.len();
-                let speed = if len > 0 { sum / len } else { 0 };
-                let speed_h = Size::new(speed, unit, UnitMode::Rate);
-                let elapsed_h = Instant::now().saturating_duration_since(start_sec);
-
-                // First, move to the start of the current line and clear it.
-                let _ = self.term.carriage_return();
-                // We'd prefer to use delete_line() but on Windows it seems to
-                // sometimes do unusual things
-                // let _ = self.term.as_mut().unwrap().delete_line();
-                // So instead we do:
-                if let Some(n) = self.displayed_charcount {
-                    // This is not ideal as very narrow terminals might mess up,
-                    // but it is more likely to succeed until term's windows console
-                    // fixes whatever's up with delete_line().
-                    let _ = write!(self.term, "{}", " ".repeat(n));
-                    let _ = self.term.flush();
-                    let _ = self.term.carriage_return();
-                }
-
-                let output = match self.content_len {
-                    Some(content_len) => {
-                        let content_len_h = Size::new(content_len, unit, UnitMode::Norm);
-                        let percent = (self.total_downloaded as f64 / content_len as f64) * 100.;
-                        let remaining = content_len - self.total_downloaded;
-                        let eta_h = Duration::from_secs(if speed == 0 {
-                            std::u64::MAX
-                        } else {
-                            (remaining / speed) as u64
-                        });
-                        format!(
-                            "{} / {} ({:3.0} %) {} in {} ETA: {}",
-                            total_h,
-                            content_len_h,
-                            percent,
-                            speed_h,
-                            elapsed_h.display(),
-                            eta_h.display(),
-                        )
-                    }
-                    None => format!(
-                        "Total: {} Speed: {} Elapsed: {}",
-                        total_h,
-                        speed_h,
-                        elapsed_h.display()
-                    ),
-                };
-
-                let _ = write!(self.term, "{}", output);
-                // Since stdout is typically line-buffered and we don't print a newline, we manually flush.
-                let _ = self.term.flush();
-                self.displayed_charcount = Some(output.chars().count());
+        self.component_announced = false;
+        if self.phase == Phase::Downloading {
+            if let Some(phase) = self.resume_phase.take() {
+                self.phase = phase;
             }
         }
     }
 
     pub(crate) fn push_unit(&mut self, new_unit: Unit) {
         self.units.push(new_unit);
+        if let Unit::IO = new_unit {
+            self.phase = Phase::Writing;
+        }
     }
 
     pub fn pop_unit(&mut self) {
-        self.units.pop();
+        if let Some(Unit::IO) = self.units.pop() {
+            self.phase = Phase::Downloading;
+        }
+    }
+}
+
+impl Drop for DownloadTracker {
+    fn drop(&mut self) {
+        // A tracker whose install failed part way mustn't leave its bar
+        // on the screen.
+        self.download_finished();
     }
 }
 
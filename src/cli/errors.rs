@@ -9,16 +9,189 @@ use regex::Regex;
 use strsim::damerau_levenshtein;
 use thiserror::Error as ThisError;
 
+use crate::dist::dist::DistError;
+use crate::errors::{Hint, OperationError, RustupError};
+use download::DownloadError;
+
 #[derive(ThisError, Debug)]
 pub enum CLIError {
     #[error("couldn't determine self executable name")]
     NoExeName,
     #[error("rustup is not installed at '{}'", .p.display())]
     NotSelfInstalled { p: PathBuf },
+    #[error("the rustup downloaded from '{url}' isn't signed with a key this rustup trusts, so it wasn't installed")]
+    SelfUpdateNotSigned { url: String },
     #[error("failure reading directory {}", .p.display())]
     ReadDirError { p: PathBuf, source: io::Error },
     #[error("failure during windows uninstall")]
     WindowsUninstallMadness,
+    #[error("rustup is non-interactive, so it can't ask: {question} [reason: {reason}]")]
+    NonInteractive {
+        question: String,
+        reason: &'static str,
+    },
+}
+
+/// The kinds of failure rustup exits with a code of their own for, so that
+/// scripts can tell them apart, say to retry only network failures. Any
+/// other failure exits with 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailureClass {
+    Network,
+    ChecksumMismatch,
+    ComponentUnavailable,
+    DiskFull,
+    PermissionDenied,
+    FileConflict,
+}
+
+impl FailureClass {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Network => 10,
+            Self::ChecksumMismatch => 11,
+            Self::ComponentUnavailable => 12,
+            Self::DiskFull => 13,
+            Self::PermissionDenied => 14,
+            Self::FileConflict => 15,
+        }
+    }
+
+    /// The class of `error`, going by the errors it was caused by. The
+    /// innermost cause that has a class decides, since a failure to write a
+    /// download to a full disk is reported as a failed download. Errors
+    /// added as context can't be found in the chain, so failing that the
+    /// `RustupError` context nearest the top decides.
+    pub fn of(error: &anyhow::Error) -> Option<Self> {
+        error
+            .chain()
+            .filter_map(Self::of_cause)
+            .last()
+            .or_else(|| error.downcast_ref().and_then(Self::of_rustup_error))
+    }
+
+    fn of_cause(cause: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            return Self::of_io_error(e);
+        }
+        if let Some(e) = cause.downcast_ref::<RustupError>() {
+            return Self::of_rustup_error(e);
+        }
+        // A download that failed however often it was tried; what it failed
+        // with isn't its source, so isn't in the chain.
+        if let Some(retry::Error::Operation {
+            error: OperationError(e),
+            ..
+        }) = cause.downcast_ref()
+        {
+            return Self::of(e);
+        }
+        if let Some(DistError::ToolchainComponentsMissing(..)) = cause.downcast_ref::<DistError>() {
+            return Some(Self::ComponentUnavailable);
+        }
+        match cause.downcast_ref::<DownloadError>() {
+            Some(DownloadError::HttpStatus(_)) | Some(DownloadError::Message(_)) => {
+                Some(Self::Network)
+            }
+            #[cfg(feature = "reqwest-backend")]
+            Some(DownloadError::Reqwest(_)) => Some(Self::Network),
+            #[cfg(feature = "curl-backend")]
+            Some(DownloadError::CurlError(_)) => Some(Self::Network),
+            _ => None,
+        }
+    }
+
+    fn of_rustup_error(e: &RustupError) -> Option<Self> {
+        match e {
+            RustupError::ChecksumFailed { .. } | RustupError::PinnedManifestMismatch { .. } => {
+                Some(Self::ChecksumMismatch)
+            }
+            RustupError::RequestedComponentsUnavailable { .. } => Some(Self::ComponentUnavailable),
+            RustupError::ComponentConflict { .. } => Some(Self::FileConflict),
+            RustupError::DownloadingFile { .. } | RustupError::ComponentDownloadFailed(_) => {
+                Some(Self::Network)
+            }
+            _ => None,
+        }
+    }
+
+    fn of_io_error(e: &io::Error) -> Option<Self> {
+        // ENOSPC, and ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL on Windows.
+        let disk_full: &[i32] = if cfg!(windows) { &[39, 112] } else { &[28] };
+        if matches!(e.raw_os_error(), Some(code) if disk_full.contains(&code)) {
+            return Some(Self::DiskFull);
+        }
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => Some(Self::PermissionDenied),
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::TimedOut => Some(Self::Network),
+            _ => None,
+        }
+    }
+}
+
+/// The code to exit with for `error`.
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    FailureClass::of(error).map_or(1, FailureClass::exit_code)
+}
+
+/// What to do about `error`: the hints of the errors it was caused by, the
+/// outermost first, then any for its class.
+pub fn hints(error: &anyhow::Error) -> Vec<Hint> {
+    let mut hints = Vec::new();
+    add_hints(error, &mut hints);
+    let hint = match FailureClass::of(error) {
+        Some(FailureClass::Network) => Some(Hint::new(
+            "check the network connection; if it goes through a proxy, see \
+             https://rust-lang.github.io/rustup/network-proxies.html",
+        )),
+        Some(FailureClass::DiskFull) => Some(Hint::run(
+            "to free space by uninstalling the toolchains which haven't been used lately",
+            "rustup toolchain gc --unused-for 90d",
+        )),
+        Some(FailureClass::PermissionDenied) => Some(Hint::new(
+            "check that RUSTUP_HOME and CARGO_HOME belong to the user running rustup",
+        )),
+        _ => None,
+    };
+    hints.extend(hint);
+    let mut unique = Vec::new();
+    for hint in hints {
+        if !unique.contains(&hint) {
+            unique.push(hint);
+        }
+    }
+    unique
+}
+
+fn add_hints(error: &anyhow::Error, hints: &mut Vec<Hint>) {
+    // As in `FailureClass::of`, context can't be found in the chain.
+    if let Some(hint) = error
+        .downcast_ref::<RustupError>()
+        .and_then(RustupError::hint)
+    {
+        hints.push(hint);
+    }
+    for cause in error.chain() {
+        let hint = if let Some(e) = cause.downcast_ref::<RustupError>() {
+            e.hint()
+        } else if let Some(e) = cause.downcast_ref::<DistError>() {
+            e.hint()
+        } else if let Some(retry::Error::Operation {
+            error: OperationError(e),
+            ..
+        }) = cause.downcast_ref()
+        {
+            add_hints(e, hints);
+            None
+        } else {
+            None
+        };
+        hints.extend(hint);
+    }
 }
 
 fn maybe_suggest_toolchain(bad_name: &str) -> String {
@@ -5,6 +5,11 @@ pub static RUSTUP_HELP: &str = r"DISCUSSION:
     cross-compiling simpler with binary builds of the standard library
     for common platforms.
 
+    `--format json`, or RUSTUP_FORMAT=json, makes `show`, `check`,
+    `status`, `history`, `config list`, `toolchain list`,
+    `component list`, `target list`, `override list` and
+    `which --chain` print versioned JSON for scripts.
+
     If you are new to Rust consider running `rustup doc --book` to
     learn Rust.";
 
@@ -15,7 +20,12 @@ pub static SHOW_HELP: &str = r"DISCUSSION:
     compilation targets, then they are listed as well.
 
     If there are multiple toolchains installed then all installed
-    toolchains are listed as well.";
+    toolchains are listed as well.
+
+    With `rustup --format json show`, the active toolchain also comes
+    with its sysroot, its installed components, and the path of each
+    of its tools that is installed, so that editors can find them
+    without running `rustc`.";
 
 pub static SHOW_ACTIVE_TOOLCHAIN_HELP: &str = r"DISCUSSION:
     Shows the name of the active toolchain.
@@ -29,7 +39,15 @@ pub static SHOW_ACTIVE_TOOLCHAIN_HELP: &str = r"DISCUSSION:
 pub static UPDATE_HELP: &str = r"DISCUSSION:
     With no toolchain specified, the `update` command updates each of
     the installed toolchains from the official release channels, then
-    updates rustup itself.
+    updates rustup itself. Up to four toolchains are updated at once,
+    each showing the progress of its downloads, and the results are
+    summarized when they have all finished; `--jobs 1` updates them one
+    at a time, as they used to be.
+
+    Before updating an installed toolchain, `update` lists the version
+    each of its components goes from and to, any components added or
+    removed, and the total size of the downloads. `--dry-run` lists the
+    changes without making them.
 
     If given a toolchain argument then `update` updates that
     toolchain, the same as `rustup toolchain install`.";
@@ -39,6 +57,32 @@ pub static INSTALL_HELP: &str = r"DISCUSSION:
 
     The 'install' command is an alias for 'rustup update <toolchain>'.";
 
+pub static PREFETCH_HELP: &str = r"DISCUSSION:
+    Downloads and verifies everything needed to install the given
+    toolchains, along with any requested components and targets,
+    without installing or updating anything.
+
+    A later `rustup toolchain install --offline` with the same options
+    then works without network access, using the prefetched channel
+    manifest rather than asking the dist server for a newer one. For
+    example:
+
+        $ rustup prefetch nightly --target wasm32-unknown-unknown
+        $ rustup toolchain install nightly --target wasm32-unknown-unknown --offline
+
+    Note that `rustup update` without arguments clears the download
+    cache once it finishes.";
+
+pub static REPORT_NETWORK_HELP: &str = r"DISCUSSION:
+    Every package and channel manifest rustup downloads is recorded,
+    along with the mirror that served it, whether its checksum matched,
+    how many retries it needed and how long it took. This summarizes
+    the recent part of that record, listing the latest failures.
+
+    The full record is kept in `logs/downloads.log` in the rustup home
+    directory, and is worth attaching to reports of unreliable
+    downloads.";
+
 pub static DEFAULT_HELP: &str = r"DISCUSSION:
     Sets the default toolchain to the one specified. If the toolchain
     is not already installed then it is installed first.";
@@ -85,6 +129,16 @@ pub static TOOLCHAIN_HELP: &str = r"DISCUSSION:
     often used for developing Rust itself. For more information see
     `rustup toolchain help link`.";
 
+pub static TOOLCHAIN_UNINSTALL_HELP: &str = r"DISCUSSION:
+    Removes the given toolchains. With `--purge`, directory overrides
+    for them are removed as well, the download cache and temporary
+    files are emptied, and the disk space that reclaimed is reported:
+
+        $ rustup toolchain uninstall --purge nightly-2021-01-01
+
+    At a terminal, the toolchains are listed and uninstalled once
+    confirmed; `--yes` uninstalls them without asking.";
+
 pub static TOOLCHAIN_LINK_HELP: &str = r"DISCUSSION:
     'toolchain' is the custom name to be assigned to the new toolchain.
     Any name is permitted as long as it does not fully match an initial
@@ -102,7 +156,383 @@ pub static TOOLCHAIN_LINK_HELP: &str = r"DISCUSSION:
         $ rustup override set latest-stage1
 
     If you now compile a crate in the current directory, the custom
-    toolchain 'latest-stage1' will be used.";
+    toolchain 'latest-stage1' will be used.
+
+    Components such as 'rust-src' or 'clippy' can only be added to a
+    custom toolchain once it knows where to get them from. Use
+    '--components-from' to name a channel on the dist server whose
+    components match the toolchain, or a channel manifest file:
+
+        $ rustup toolchain link latest-stage1 build/x86_64-unknown-linux-gnu/stage1 --components-from nightly
+        $ rustup component add rust-src --toolchain latest-stage1";
+
+pub static TOOLCHAIN_GC_HELP: &str = r"DISCUSSION:
+    Uninstalls every toolchain that has not been used for at least the
+    given length of time, which is a number followed by one of 's',
+    'm', 'h', 'd' or 'w'. For example, to see what would be removed
+    after three months:
+
+        $ rustup toolchain gc --unused-for 90d --dry-run
+
+    The default toolchain and any toolchain named by a directory
+    override, an alias or a virtual toolchain are always kept, as are
+    custom (linked) toolchains.
+    Toolchains named only in `rust-toolchain` files are not known to
+    rustup, so removed ones are reinstalled when next needed.
+
+    At a terminal, the toolchains are listed and uninstalled once
+    confirmed; `--yes` uninstalls them without asking.";
+
+pub static TOOLCHAIN_ALIAS_HELP: &str = r#"DISCUSSION:
+    An alias is another name for a toolchain, which can be used
+    anywhere a toolchain name can: `cargo +bisect build`, `rustup run
+    bisect`, or `channel = "bisect"` in a `rust-toolchain.toml` file.
+    Pointing the alias at a different toolchain moves every project
+    that uses it along with it:
+
+        $ rustup toolchain alias bisect nightly-2024-03-01
+        $ rustup toolchain alias bisect nightly-2024-03-15
+
+    With just a name, prints the toolchain it stands for; with no
+    arguments, lists all aliases. Remove one with `--remove`."#;
+
+pub static TOOLCHAIN_VIRTUAL_HELP: &str = r#"DISCUSSION:
+    A virtual toolchain is another toolchain, its base, except that
+    some of its tools are run from other toolchains. For instance, to
+    build with stable but format with nightly's rustfmt:
+
+        $ rustup toolchain virtual fmt-nightly stable rustfmt=nightly
+        $ rustup override set fmt-nightly
+
+    Tools are named either by their binary, such as 'cargo-fmt', or by
+    the component that provides them, such as 'rustfmt' for both
+    `rustfmt` and `cargo fmt`, or 'clippy' for `cargo clippy`. Like an
+    alias, a virtual toolchain can be used anywhere a toolchain name
+    can; anything other than running its tools, such as adding
+    components, applies to its base.
+
+    With just a name, prints what it is made of; with no arguments,
+    lists all virtual toolchains. Remove one with `--remove`."#;
+
+pub static UI_HELP: &str = r"DISCUSSION:
+    Opens a full screen view of the installed toolchains, with the size
+    of each on disk. Opening a toolchain shows its components and
+    targets, which can be added or removed there, with the size of each
+    one installed. Toolchains can also be updated, made the default, or
+    uninstalled.
+
+    The arrow keys, or j and k, move through the list and enter opens
+    the toolchain or adds or removes the component. The keys for the
+    rest are shown at the bottom of the screen. Changes are made just as
+    the other commands make them, with their progress shown.";
+
+pub static WHICH_HELP: &str = r"DISCUSSION:
+    Prints the path of the binary that running a tool would run in
+    the current directory, or with `--toolchain`. `--all` prints one
+    for every tool the toolchain has.
+
+    When the wrong rustc seems to be running, `--trace` shows how it
+    was chosen: the toolchain and what selected it, such as an
+    override or `rust-toolchain` file, where the toolchain lives, and
+    whether cargo comes from another toolchain because a linked one
+    has none of its own.
+
+        $ rustup which --trace cargo
+
+    `--chain` goes further, starting from the file the shell finds for
+    the command in PATH and whether that is rustup's proxy at all, and
+    with `--format json`, or RUSTUP_FORMAT=json, prints the same as
+    versioned JSON, like rustup's other commands.
+
+        $ rustup which --chain --format json rustc";
+
+pub static AUTO_INSTALL_TARGETS_HELP: &str = r"DISCUSSION:
+    When cargo or rustc is run through a proxy with `--target` for a
+    target whose standard library isn't installed, rustup can install
+    it before the build starts:
+
+    - `prompt` asks first, when run from a terminal.
+    - `enable` installs it without asking.
+    - `disable` leaves the build to fail.";
+
+pub static USAGE_STATS_HELP: &str = r"DISCUSSION:
+    Records which toolchain and tool each run of a proxy or `rustup
+    run` uses, for `rustup stats` to show. It is off unless turned on,
+    and what is recorded stays in RUSTUP_HOME/logs; turning it off
+    again keeps what was recorded until `rustup stats --clear`.";
+
+pub static AUTO_INSTALL_COMPONENTS_HELP: &str = r"DISCUSSION:
+    When a tool such as rustfmt or clippy is run through its proxy,
+    for instance by `cargo fmt` or `cargo clippy`, and its component
+    isn't installed in the toolchain, rustup can install it first:
+
+    - `ask` asks first when run from a terminal, and otherwise fails
+      with the `rustup component add` command to run.
+    - `always` installs it without asking.
+    - `never` fails with the `rustup component add` command to run.";
+
+pub static TOOLCHAIN_FILE_SEARCH_HELP: &str = r"DISCUSSION:
+    rustup looks for a 'rust-toolchain' or 'rust-toolchain.toml' file
+    in the current directory and then in each directory above it:
+
+    - `filesystem` looks all the way up to the root of the filesystem.
+    - `vcs-root` stops at the root of the repository, the directory
+      with a '.git' or '.hg' in it, so that a toolchain file in a
+      directory above, such as the home directory, isn't used.
+
+    Directory overrides set with `rustup override set` apply either
+    way.";
+
+pub static RUSTC_WRAPPER_HELP: &str = r"DISCUSSION:
+    Runs rustc through a command such as `sccache`, which is given the
+    path to the toolchain's rustc and then rustc's own arguments, the
+    way cargo runs RUSTC_WRAPPER. Unlike RUSTC_WRAPPER it is set once,
+    rather than in every shell and CI job, and can differ by toolchain:
+
+        $ rustup set rustc-wrapper sccache
+        $ rustup set rustc-wrapper --toolchain nightly none
+
+    `none` runs that toolchain's rustc without a wrapper. With no
+    command, the wrapper is removed: for the toolchain given, which
+    then uses the wrapper for every toolchain, or otherwise for every
+    toolchain. rustc isn't wrapped twice when cargo is already given
+    RUSTC_WRAPPER.";
+
+pub static SHIMS_HELP: &str = r"DISCUSSION:
+    The tools in CARGO_HOME/bin, such as `cargo` and `rustc`, are
+    normally proxies: links to rustup, which works out the toolchain
+    to use each time one of them runs.
+
+    - `proxy` keeps them that way.
+    - `symlink`, on Unix, makes each of them a symlink straight to the
+      binary in the default toolchain, so that nothing stands between
+      running `cargo` and cargo itself. The symlinks follow the default
+      toolchain when it is changed or updated. Tools the default
+      toolchain lacks remain proxies.
+
+    With symlinks, directory overrides, toolchain files, `+toolchain`
+    and RUSTUP_TOOLCHAIN are not followed by the symlinked tools; use
+    `rustup run` for another toolchain.";
+
+pub static COLOR_THEME_HELP: &str = r"DISCUSSION:
+    Picks the colors rustup's output is in, where it is colored.
+
+    - `default` is red for errors, yellow for warnings and green for
+      things being as they should.
+    - `colorblind` uses magenta, yellow and blue instead, which are
+      told apart without seeing red and green.
+    - `monochrome` leaves out colors, keeping bold text.
+
+    Whether output is colored at all is up to `--color`, which is
+    `auto` when not given: colored when written to a terminal, unless
+    NO_COLOR is set, or CLICOLOR_FORCE asks for colors regardless.";
+
+pub static CONFIG_HELP: &str = r"DISCUSSION:
+    Reads and changes the settings kept in RUSTUP_HOME/settings.toml,
+    by the names they have there, so that the file needn't be edited
+    by hand. Each value is checked as the matching `rustup set` command
+    checks it, and a value that isn't valid leaves the file as it was.
+
+    `get` prints nothing and exits with status 1 for a setting which
+    isn't set. `unset` removes a setting, so that its default is used.
+    `list` shows the settings which are set, and with `--all` the rest
+    too; with `--format json` it prints a JSON object.
+
+        $ rustup config set profile minimal
+        $ rustup config get auto_self_update
+        $ rustup config unset network.ip-version
+
+    `export` prints the settings, with the toolchain and command
+    aliases, for `import` to set on another machine, which then
+    installs the default toolchain. The host triple and PGP keys
+    belong to the machine and are left out.
+
+        $ rustup config export > rustup-config.toml
+        $ rustup config import rustup-config.toml
+
+    `check` reports each mistake in the settings and the machine's
+    policy, settings this rustup doesn't know, overrides naming
+    toolchains which aren't installed, and mirrors which can't be
+    reached, and exits with status 1 if there is an error, so that CI
+    can check the configuration before it starts.";
+
+pub static OVERRIDE_HOOK_HELP: &str = r"DISCUSSION:
+    Runs a command to find out which toolchain to use in a directory,
+    for monorepos whose tooling knows better than a toolchain file.
+    The command is run in the directory, with the directory added as
+    its last argument, and prints the name of a toolchain. If it
+    prints nothing, toolchain files and directory overrides are used
+    as usual.
+
+        $ rustup set override-hook --ttl 300 -- monorepo-tool rust-toolchain
+
+    Its answer for each directory is kept for `--ttl` seconds, one
+    minute by default, so that it isn't run for every `cargo` and
+    `rustc`. `+toolchain` and RUSTUP_TOOLCHAIN still come first. With
+    no command, the hook is removed.";
+
+pub static DEFAULT_COMPONENTS_HELP: &str = r"DISCUSSION:
+    Installs the components given with every toolchain installed from
+    now on, on top of those of the profile, replacing any given
+    before. With none, new toolchains get only the components of the
+    profile again. Toolchains already installed are left as they are.
+
+        $ rustup set default-components clippy rust-src
+
+    As with the profile's components, a nightly missing one of them is
+    skipped for the latest nightly that has them all.";
+
+pub static DEFAULT_TARGETS_HELP: &str = r"DISCUSSION:
+    Installs the standard library for the targets given with every
+    toolchain installed from now on, as with `rustup target add`,
+    replacing any given before. With none, new toolchains get only
+    the host's target again. Toolchains already installed are left as
+    they are.
+
+        $ rustup set default-targets wasm32-unknown-unknown aarch64-unknown-linux-musl";
+
+pub static TOOLCHAIN_FILE_ENV_HELP: &str = r"DISCUSSION:
+    Values in a 'rust-toolchain.toml' file can use environment
+    variables as `${VAR}`, such as a `path` to a toolchain built on
+    each machine:
+
+        [toolchain]
+        path = '${RUST_DEV}/build/host/stage1'
+
+    A toolchain file comes with the code it is in, so only HOME,
+    USERPROFILE, CARGO_HOME and RUSTUP_HOME can be used by default.
+    This allows the variables given as well, replacing any allowed
+    before; with none, it allows only the defaults again:
+
+        $ rustup set toolchain-file-env RUST_DEV";
+
+pub static TOOLCHAIN_ENV_HELP: &str = r"DISCUSSION:
+    Sets environment variables for the tools of one toolchain, both
+    when they are run through a proxy and with `rustup run`. This
+    keeps settings that only make sense for one toolchain, such as
+    sanitizer flags for a nightly, out of every other build:
+
+        $ rustup toolchain env nightly RUSTFLAGS=-Zsanitizer=address
+
+    A variable that is already set in the environment is left as it
+    is. With just a toolchain, lists its variables; remove one with
+    `--unset KEY`.";
+
+pub static TOOLCHAIN_EXPORT_HELP: &str = r"DISCUSSION:
+    Writes an installed toolchain, with everything rustup knows about
+    it, to a single archive. The archive can be copied to a machine
+    without network access, or kept as a CI artifact, and installed
+    there with `rustup toolchain import`.
+
+        $ rustup toolchain export nightly -o nightly.tar.zst
+
+    The archive is compressed according to its extension, which must
+    be one of '.tar.zst', '.tar.xz' or '.tar.gz'. Toolchains only run
+    on the host they were built for, so the archive is only useful on
+    machines of the same kind.";
+
+pub static TOOLCHAIN_CLONE_HELP: &str = r"DISCUSSION:
+    Copies an installed toolchain to a new custom toolchain, which can
+    then be patched without touching the original:
+
+        $ rustup toolchain clone stable patched
+        $ cargo +patched build
+
+    As with `rustup toolchain link`, the new name must not look like
+    the name of a release toolchain.
+
+    To save space and time, files are hard linked to the original's
+    rather than copied wherever the filesystem allows. A hard linked
+    file must be replaced, not edited in place, or the original
+    changes too; pass `--copy` to copy every file instead.";
+
+pub static SNAPSHOT_HELP: &str = r"DISCUSSION:
+    A snapshot records the exact release of every installed toolchain,
+    with its components, targets and the hashes of their packages, as
+    a lockfile for the toolchains themselves. Save one on a machine
+    that works and restore it on another to get the same toolchains:
+
+        $ rustup snapshot save env.lock
+        $ rustup snapshot restore env.lock
+
+    Toolchains keep their names, so a restored 'nightly' is the nightly
+    from the snapshot until it is next updated. Restoring reinstalls
+    any toolchain which doesn't match the snapshot, and fails if a
+    release has changed on the server since. Linked toolchains are not
+    included.";
+
+pub static TRUST_HELP: &str = r"DISCUSSION:
+    A toolchain file can name a toolchain by its path rather than its
+    channel, and rustup then runs whatever is there as rustc or cargo.
+    To keep a repository from running anything it likes that way, a
+    toolchain file which names a path is only used in a trusted
+    directory, or one inside it.
+
+    When a toolchain file in a directory which isn't trusted names a
+    path, rustup asks whether to trust the directory if it can, and
+    fails otherwise. Directories can be trusted ahead of time, which
+    is what CI and other non-interactive uses need:
+
+        $ rustup trust add ~/src/rust
+
+    `rustup trust add '*'` trusts every directory.
+
+    A toolchain file which names a dist server, and the settings of a
+    project's '.rustup.toml', are also only used in a trusted
+    directory, and are ignored with a warning elsewhere.";
+
+pub static WSL_SHIMS_HELP: &str = r"DISCUSSION:
+    Inside WSL, the toolchains installed by rustup on Windows can be
+    used rather than installing them again. This writes a shell script
+    for each tool in the Windows CARGO_HOME/bin, which runs the Windows
+    tool with any arguments that are absolute Linux paths translated
+    for Windows, and with RUSTUP_TOOLCHAIN, RUSTFLAGS, CARGO_TARGET_DIR
+    and the like passed on:
+
+        $ rustup wsl-shims install /mnt/c/Users/me/.cargo
+        $ export PATH=$HOME/.cargo/wsl-bin:$PATH
+
+    The tools run as Windows programs, so they build for Windows.
+    rustup itself gets no shim; run 'rustup.exe' to manage the
+    Windows toolchains.
+    Run `rustup wsl-shims install` again after installing more tools
+    into the Windows CARGO_HOME, and `rustup wsl-shims remove` to
+    remove the shims.";
+
+pub static PROXY_HELP: &str = r"DISCUSSION:
+    rustup gives the tools that come with Rust, such as `cargo` and
+    `rustc`, proxies in CARGO_HOME/bin, which run the tool from the
+    toolchain chosen by `+toolchain`, overrides, or the default. Other
+    tools installed into a toolchain's 'bin' directory can be given
+    proxies the same way:
+
+        $ rustup proxy add mdbook
+        $ mdbook +nightly build
+
+    Running a tool through its proxy fails if the chosen toolchain
+    doesn't have it.";
+
+pub static TOOLCHAIN_FILE_CHECK_HELP: &str = r"DISCUSSION:
+    Checks a 'rust-toolchain' or 'rust-toolchain.toml' file for keys
+    rustup doesn't know, which are otherwise ignored with a warning,
+    for invalid settings, and for components and targets which aren't
+    available in its channel's release for this host. Given a
+    directory, it checks the toolchain file in that directory.
+
+    It prints each problem it finds, and exits with status 1 if there
+    are any, so it can be run by a pre-commit hook or in CI:
+
+        $ rustup toolchain-file check";
+
+pub static TOOLCHAIN_REPAIR_HELP: &str = r"DISCUSSION:
+    Uninstalls a toolchain and installs it again from its channel,
+    with the same components and targets. Use it when a toolchain has
+    been left broken, for instance because rustup was interrupted
+    while updating it, which rustup warns about when it next runs.
+
+    Toolchains that track a channel, such as 'nightly', are reinstalled
+    at the channel's latest release. Linked toolchains can't be
+    repaired.";
 
 pub static OVERRIDE_HELP: &str = r"DISCUSSION:
     Overrides configure Rustup to use a specific toolchain when
@@ -129,8 +559,49 @@ pub static OVERRIDE_UNSET_HELP: &str = r"DISCUSSION:
     If `--path` argument is present, removes the override toolchain
     for the specified directory. If `--nonexistent` argument is
     present, removes the override toolchain for all nonexistent
-    directories. Otherwise, removes the override toolchain for the
-    current directory.";
+    directories, once confirmed at a terminal or straight away with
+    `--yes`. Otherwise, removes the override toolchain for the current
+    directory.";
+
+pub static OVERRIDE_PRUNE_HELP: &str = r"DISCUSSION:
+    Removes the overrides which no longer do anything useful: those
+    for directories which don't exist any more, and those naming a
+    toolchain which isn't installed. The overrides are listed first,
+    and removed once confirmed, or straight away with `--yes`.";
+
+pub static OVERRIDE_EXPLAIN_HELP: &str = r"DISCUSSION:
+    Lists everything that could choose the toolchain for a directory,
+    in order of precedence: `+toolchain` on the command line, the
+    RUSTUP_TOOLCHAIN environment variable, then directory overrides
+    and toolchain files from the directory up to the root, and
+    finally the default toolchain.
+
+    The first of them is the one used, and is marked as active. The
+    others are marked as ignored, except for those an active toolchain
+    file inherits from. Toolchain files are shown with their settings.";
+
+pub static OVERRIDE_HOST_HELP: &str = r"DISCUSSION:
+    Sets the host triple to use in place of the default host triple
+    within a directory and its child directories. Toolchain names
+    that leave out the host, such as `stable`, name toolchains for
+    this host there, and those are the toolchains that are installed
+    with their standard library for it.
+
+    To use x86_64 toolchains under Rosetta on an Apple silicon Mac:
+
+        $ rustup override host x86_64-apple-darwin
+
+    `--path` sets it for another directory than the current one, and
+    `--unset` removes it again.
+
+    `--toolchain` sets it instead for a toolchain name which leaves out
+    the host, in every directory without a host override of its own:
+
+        $ rustup override host --toolchain nightly x86_64-apple-darwin
+
+    The default toolchain is always saved with the default host triple,
+    so `rustup default` in a directory with a host override doesn't
+    change the default toolchain's host elsewhere.";
 
 pub static RUN_HELP: &str = r"DISCUSSION:
     Configures an environment to use the given toolchain and then runs
@@ -145,7 +616,172 @@ pub static RUN_HELP: &str = r"DISCUSSION:
 
         $ cargo +nightly build
 
-        $ rustup run nightly cargo build";
+        $ rustup run nightly cargo build
+
+    `--env KEY=VALUE` sets a variable for the command and may be given
+    more than once, and `--cd` runs it in another directory. With
+    `--clean-env` the command doesn't inherit rustup's environment: it
+    gets only the variables rustup sets for the toolchain, those given
+    with `--env`, and a few that most programs need, such as PATH and
+    HOME.
+
+        $ rustup run --clean-env --env RUSTFLAGS=-Dwarnings --cd foo stable cargo build";
+
+pub static BISECT_HELP: &str = r"DISCUSSION:
+    Runs the given command with nightlies between the start and end
+    dates, choosing which to try by binary search, and prints the
+    first nightly with which the command's exit status differs from
+    its status with the start date's nightly:
+
+        $ rustup bisect --start 2021-01-01 --end 2021-03-01 -- cargo build
+
+    Nightlies which are not already installed are installed as they
+    are needed and removed again once they have been tried. Days for
+    which no nightly can be installed are skipped.";
+
+pub static CHECK_HELP: &str = r"DISCUSSION:
+    Checks whether updates are available for the installed release
+    channels and for rustup itself, without installing them.
+
+    With `--format json` it prints a JSON object for editors and
+    prompts, with the version and release date of each toolchain and of
+    its update, and whether `rustup self update` can update rustup:
+
+        $ rustup check --format json";
+
+pub static DOCTOR_HELP: &str = r"DISCUSSION:
+    Checks for the problems that most often break a rustup
+    installation, and suggests a fix for each one it finds:
+
+    - proxies in CARGO_HOME/bin which are missing or aren't rustup
+    - another Rust installation coming before rustup's in PATH
+    - a default toolchain or overrides naming toolchains that aren't
+      installed, and overrides for directories that no longer exist
+    - a RUSTUP_HOME which can't be written to
+    - no linker, or on Windows no Visual Studio Build Tools
+    - the dist server being unreachable
+
+    Exits with status 1 if any problem was found.";
+
+pub static STATUS_HELP: &str = r"DISCUSSION:
+    Shows on one screen what is otherwise spread across `rustup show`,
+    `rustup check` and `rustup doctor`:
+
+    - the active toolchain and why it is active, and whether it is
+      installed; unlike `rustup show`, a missing one isn't installed
+    - whether updates are available for the installed channels, and
+      for rustup itself
+    - how much space the toolchains, the download cache and temporary
+      files take up in RUSTUP_HOME
+    - the problems `rustup doctor` finds, bar its network check
+
+    Checking for updates needs the network; where that fails the
+    reason is shown in place of the update, and the rest still is.
+    With `--format json` the same is printed as JSON, with the sizes in
+    bytes.";
+
+pub static PROMPT_HELP: &str = r#"DISCUSSION:
+    Prints the name of the toolchain active in the current directory,
+    for embedding in a shell prompt, such as starship's:
+
+        [custom.rust]
+        command = "rustup prompt"
+        when = true
+
+    It answers from the cache the proxies keep of the toolchain chosen
+    for each directory, which holds until the settings or a toolchain
+    file, Cargo.toml or repository root in the directory or above it
+    changes, and so takes a few milliseconds. Otherwise it finds the
+    toolchain as `rustup show active-toolchain` does, but never
+    installs it, and remembers it for next time.
+
+    Nothing is printed, and the exit status is 1, if no toolchain is
+    active or it can't be found out."#;
+
+pub static MIGRATE_HELP: &str = r"DISCUSSION:
+    `rustup migrate xdg` moves rustup's files out of ~/.rustup and into
+    the XDG base directories: the settings to $XDG_CONFIG_HOME/rustup,
+    downloads and other caches to $XDG_CACHE_HOME/rustup, and the
+    toolchains and everything else to $XDG_DATA_HOME/rustup. Lines of
+    the shell profiles which set RUSTUP_HOME to ~/.rustup are commented
+    out.
+
+    From then on rustup uses that layout, as long as RUSTUP_HOME isn't
+    set and there is no ~/.rustup. It is only used on Unix.";
+
+pub static NEWS_HELP: &str = r"DISCUSSION:
+    Shows the release notes for a version of Rust, such as 1.52.0, or
+    with no version for the installed stable toolchain: the section
+    about it in the compiler's RELEASES.md.
+
+    The notes for a new stable or beta are also shown when `rustup
+    update` or `rustup toolchain install` installs it, if the output is
+    a terminal. A copy is kept in RUSTUP_HOME/release-notes.md, so that
+    notes once fetched can be shown again offline.";
+
+pub static SELF_ROLLBACK_HELP: &str = r"DISCUSSION:
+    Each `rustup self update` keeps the rustup it replaces, in
+    RUSTUP_HOME/previous, so that a new release which breaks something
+    can be undone until a fix ships. This reinstalls that rustup, and
+    links the proxies to it again.
+
+    The rustup rolled back from is kept in its place, so running this
+    again goes back to it. `rustup update` installs the new release
+    again unless `auto-self-update` is set to `check-only` or
+    `disable`.";
+
+pub static STATS_HELP: &str = r"DISCUSSION:
+    Shows how many times each installed toolchain, and each tool such
+    as cargo or rustfmt, has been run through the proxies or `rustup
+    run` in the last 30 days, or `--days N`. Toolchains not run at
+    all are listed too, to find those which can be uninstalled with
+    `rustup toolchain gc`.
+
+    Nothing is recorded until it is turned on with `rustup set
+    usage-stats enable`. The record is kept in RUSTUP_HOME/logs and is
+    never sent anywhere; `--clear` removes it.";
+
+pub static HISTORY_HELP: &str = r"DISCUSSION:
+    Lists the changes rustup has made, oldest first, with the number
+    `rustup undo` takes to undo each: toolchains installed, updated and
+    uninstalled, the default toolchain and overrides set and removed,
+    and components and targets added and removed. Those undone already
+    are marked as such.
+
+    The history is kept in RUSTUP_HOME/history.jsonl, one JSON object
+    to a line. With `--format json` it is printed as a JSON object.";
+
+pub static UNDO_HELP: &str = r"DISCUSSION:
+    Undoes the last change in `rustup history` which hasn't been undone
+    yet, or the one whose number is given:
+
+    - setting the default toolchain puts back the one before it
+    - setting an override puts back the one before it, or removes it
+    - removing an override puts it back
+    - installing a toolchain uninstalls it, once confirmed, unless it
+      is the default toolchain or a directory's override
+    - adding components or targets removes them, and removing them
+      adds them back
+
+    Updating and uninstalling a toolchain can't be undone, as the
+    toolchain as it was is no longer available. Undoing is recorded in
+    the history too, but can't itself be undone; make the change again
+    instead.";
+
+pub static PROFILE_HELP: &str = r"DISCUSSION:
+    Profiles keep separate sets of toolchains, overrides and settings,
+    each in its own rustup home, so that work for one client doesn't
+    affect another's. They are unrelated to installation profiles such
+    as `minimal`, which are chosen with `rustup set profile`.
+
+    The `default` profile uses RUSTUP_HOME itself; the home of any other
+    profile is in RUSTUP_HOME/profiles. `rustup profile switch` changes
+    which profile rustup and the proxies use, and `--profile` uses a
+    profile for a single command:
+
+        $ rustup profile switch client-a
+
+        $ rustup --profile client-b toolchain install stable";
 
 pub static DOC_HELP: &str = r"DISCUSSION:
     Opens the documentation for the currently active toolchain with
@@ -162,6 +798,11 @@ pub static COMPLETIONS_HELP: &str = r"DISCUSSION:
     using. Your particular configuration may also determine where
     these scripts need to be placed.
 
+    Besides commands and options, the scripts complete the names of
+    installed toolchains, and of the components and targets which can be
+    added to or removed from the active toolchain, looking them up each
+    time.
+
     Here are some common set ups for the three supported shells under
     Unix and similar operating systems (such as GNU/Linux).
 
@@ -278,6 +919,9 @@ pub static TOOLCHAIN_ARG_HELP: &str = "Toolchain name, such as 'stable', 'nightl
                                        or '1.8.0'. For more information see `rustup \
                                        help toolchain`";
 
+pub static KEEP_GOING_ARG_HELP: &str = "Change each one separately, carrying on past any \
+                                        that fail, instead of all of them or none";
+
 pub static TOPIC_ARG_HELP: &str = "Topic such as 'core', 'fn', 'usize', 'eprintln!', \
                                    'core::arch', 'alloc::format!', 'std::fs', \
                                    'std::fs::read_dir', 'std::io::Bytes', \
@@ -0,0 +1,451 @@
+//! `rustup history` and `rustup undo`: the changes rustup has made, kept
+//! in `history.jsonl` in RUSTUP_HOME, and the undoing of those that can be
+//! undone: changes to the default toolchain and overrides, components and
+//! targets added or removed, and toolchains installed.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::common;
+use crate::dist::dist::TargetTriple;
+use crate::dist::manifest::Component;
+use crate::process;
+use crate::toolchain::{DistributableToolchain, Toolchain};
+use crate::utils::utils::{self, ExitCode};
+use crate::{Cfg, UpdateStatus};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub(crate) enum Operation {
+    SetDefault {
+        toolchain: String,
+        previous: Option<String>,
+    },
+    SetOverride {
+        path: PathBuf,
+        toolchain: String,
+        previous: Option<String>,
+    },
+    RemoveOverride {
+        path: PathBuf,
+        toolchain: String,
+    },
+    InstallToolchain {
+        toolchain: String,
+    },
+    UpdateToolchain {
+        toolchain: String,
+    },
+    UninstallToolchain {
+        toolchain: String,
+    },
+    AddComponents {
+        toolchain: String,
+        components: Vec<ComponentName>,
+    },
+    RemoveComponents {
+        toolchain: String,
+        components: Vec<ComponentName>,
+    },
+    /// `undid` is the id of the operation undone.
+    Undo {
+        undid: u64,
+    },
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_none = |name: &Option<String>| match name {
+            Some(name) => format!("'{}'", name),
+            None => "none".to_owned(),
+        };
+        let names = |components: &[ComponentName]| {
+            components
+                .iter()
+                .map(|c| format!("'{}'", c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            Self::SetDefault {
+                toolchain,
+                previous,
+            } => write!(
+                f,
+                "set the default toolchain to '{}' (was {})",
+                toolchain,
+                or_none(previous)
+            ),
+            Self::SetOverride {
+                path,
+                toolchain,
+                previous,
+            } => write!(
+                f,
+                "set the override for '{}' to '{}' (was {})",
+                path.display(),
+                toolchain,
+                or_none(previous)
+            ),
+            Self::RemoveOverride { path, toolchain } => write!(
+                f,
+                "removed the override for '{}' (was '{}')",
+                path.display(),
+                toolchain
+            ),
+            Self::InstallToolchain { toolchain } => write!(f, "installed '{}'", toolchain),
+            Self::UpdateToolchain { toolchain } => write!(f, "updated '{}'", toolchain),
+            Self::UninstallToolchain { toolchain } => write!(f, "uninstalled '{}'", toolchain),
+            Self::AddComponents {
+                toolchain,
+                components,
+            } => write!(f, "added {} to '{}'", names(components), toolchain),
+            Self::RemoveComponents {
+                toolchain,
+                components,
+            } => write!(f, "removed {} from '{}'", names(components), toolchain),
+            Self::Undo { undid } => write!(f, "undid #{}", undid),
+        }
+    }
+}
+
+/// A component as it is named in the manifest, with its target.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct ComponentName {
+    pkg: String,
+    target: Option<String>,
+}
+
+impl From<&Component> for ComponentName {
+    fn from(component: &Component) -> Self {
+        Self {
+            pkg: component.short_name_in_manifest().to_owned(),
+            target: component.target.as_ref().map(ToString::to_string),
+        }
+    }
+}
+
+impl ComponentName {
+    fn component(&self) -> Component {
+        let target = self.target.as_deref().map(TargetTriple::new);
+        Component::new(self.pkg.clone(), target, true)
+    }
+}
+
+impl fmt::Display for ComponentName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.target {
+            Some(target) => write!(f, "{}-{}", self.pkg, target),
+            None => write!(f, "{}", self.pkg),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    id: u64,
+    /// RFC 3339, in UTC.
+    time: String,
+    #[serde(flatten)]
+    operation: Operation,
+}
+
+fn history_file(cfg: &Cfg) -> PathBuf {
+    cfg.rustup_dir.join("history.jsonl")
+}
+
+/// The entries in the history, oldest first. Lines that can't be read, such
+/// as those a later rustup writes for changes this one doesn't know of, are
+/// left out.
+fn entries(cfg: &Cfg) -> Result<Vec<Entry>> {
+    let path = history_file(cfg);
+    if !utils::is_file(&path) {
+        return Ok(Vec::new());
+    }
+    let contents = utils::read_file("history", &path)?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Adds `operation` to the history. Failing to is only warned about, as the
+/// change has been made by then.
+pub(crate) fn record(cfg: &Cfg, operation: Operation) {
+    if let Err(e) = append(cfg, operation) {
+        warn!("could not record the change in the history: {:#}", e);
+    }
+}
+
+fn append(cfg: &Cfg, operation: Operation) -> Result<u64> {
+    // Other rustup processes may be recording changes of their own.
+    let _lock = cfg.lock_metadata()?;
+    let id = entries(cfg)?.last().map_or(1, |entry| entry.id + 1);
+    let entry = Entry {
+        id,
+        time: Utc::now().to_rfc3339(),
+        operation,
+    };
+    let line = serde_json::to_string(&entry)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_file(cfg))?;
+    writeln!(file, "{}", line)?;
+    Ok(id)
+}
+
+/// Records installing or updating `toolchain`.
+pub(crate) fn record_update(cfg: &Cfg, toolchain: &str, status: &UpdateStatus) {
+    let toolchain = toolchain.to_owned();
+    match status {
+        UpdateStatus::Installed => record(cfg, Operation::InstallToolchain { toolchain }),
+        UpdateStatus::Updated(_) => record(cfg, Operation::UpdateToolchain { toolchain }),
+        UpdateStatus::Unchanged => {}
+    }
+}
+
+/// The components of `toolchain` which are installed, to compare with those
+/// installed after a change, so that what is recorded is what the change
+/// did, once renames and components installed already are allowed for.
+pub(crate) fn installed_components(toolchain: &Toolchain<'_>) -> Option<Vec<Component>> {
+    let distributable = DistributableToolchain::new_for_components(toolchain).ok()?;
+    let components = distributable.list_components().ok()?;
+    Some(
+        components
+            .into_iter()
+            .filter(|status| status.installed)
+            .map(|status| status.component)
+            .collect(),
+    )
+}
+
+/// Records the components added to and removed from `toolchain` since
+/// `before` was taken.
+pub(crate) fn record_components(
+    cfg: &Cfg,
+    toolchain: &Toolchain<'_>,
+    before: Option<Vec<Component>>,
+) {
+    let (before, after) = match (before, installed_components(toolchain)) {
+        (Some(before), Some(after)) => (before, after),
+        _ => return,
+    };
+    let difference = |a: &[Component], b: &[Component]| -> Vec<ComponentName> {
+        a.iter()
+            .filter(|c| !b.contains(c))
+            .map(ComponentName::from)
+            .collect()
+    };
+    let added = difference(&after, &before);
+    if !added.is_empty() {
+        let toolchain = toolchain.name().to_owned();
+        record(
+            cfg,
+            Operation::AddComponents {
+                toolchain,
+                components: added,
+            },
+        );
+    }
+    let removed = difference(&before, &after);
+    if !removed.is_empty() {
+        let toolchain = toolchain.name().to_owned();
+        record(
+            cfg,
+            Operation::RemoveComponents {
+                toolchain,
+                components: removed,
+            },
+        );
+    }
+}
+
+/// The ids of the operations that have been undone.
+fn undone(entries: &[Entry]) -> HashSet<u64> {
+    entries
+        .iter()
+        .filter_map(|entry| match entry.operation {
+            Operation::Undo { undid } => Some(undid),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct History<'a> {
+    operations: Vec<HistoryEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct HistoryEntry<'a> {
+    #[serde(flatten)]
+    entry: &'a Entry,
+    undone: bool,
+}
+
+pub(crate) fn history(cfg: &Cfg, json: bool) -> Result<ExitCode> {
+    let entries = entries(cfg)?;
+    let undone = undone(&entries);
+    if json {
+        let operations = entries
+            .iter()
+            .map(|entry| HistoryEntry {
+                entry,
+                undone: undone.contains(&entry.id),
+            })
+            .collect();
+        common::print_json(&History { operations })?;
+        return Ok(ExitCode(0));
+    }
+
+    if entries.is_empty() {
+        info!("no changes recorded");
+        return Ok(ExitCode(0));
+    }
+    let mut out = process().stdout();
+    for entry in &entries {
+        let time = match DateTime::parse_from_rfc3339(&entry.time) {
+            Ok(time) => time
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            Err(_) => entry.time.clone(),
+        };
+        write!(out, "{:>4}  {}  {}", entry.id, time, entry.operation)?;
+        if undone.contains(&entry.id) {
+            write!(out, " (undone)")?;
+        }
+        writeln!(out)?;
+    }
+    Ok(ExitCode(0))
+}
+
+/// Undoes the operation `id`, or else the last one not undone already.
+/// Undoing an install asks first, unless `yes`.
+pub(crate) fn undo(cfg: &Cfg, id: Option<u64>, yes: bool) -> Result<ExitCode> {
+    let entries = entries(cfg)?;
+    let undone = undone(&entries);
+    let entry = match id {
+        Some(id) => entries
+            .iter()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| anyhow!("there is no change #{} in the history", id))?,
+        None => entries
+            .iter()
+            .rev()
+            .find(|entry| {
+                !matches!(entry.operation, Operation::Undo { .. }) && !undone.contains(&entry.id)
+            })
+            .ok_or_else(|| anyhow!("there are no changes to undo"))?,
+    };
+    if undone.contains(&entry.id) {
+        bail!(
+            "#{} ({}) has been undone already",
+            entry.id,
+            entry.operation
+        );
+    }
+
+    let notify = cfg.notify_handler.as_ref();
+    match &entry.operation {
+        Operation::SetDefault { previous, .. } => match previous {
+            Some(previous) => cfg.set_default(previous)?,
+            None => cfg.settings_file.with_mut(|s| {
+                s.default_toolchain = None;
+                Ok(())
+            })?,
+        },
+        Operation::SetOverride { path, previous, .. } => {
+            cfg.settings_file.with_mut(|s| {
+                match previous {
+                    Some(previous) => s.add_override(path, previous.clone(), notify),
+                    None => {
+                        s.remove_override(path, notify);
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        Operation::RemoveOverride { path, toolchain } => cfg.settings_file.with_mut(|s| {
+            s.add_override(path, toolchain.clone(), notify);
+            Ok(())
+        })?,
+        Operation::InstallToolchain { toolchain } => {
+            let toolchain = cfg.get_toolchain(toolchain, false)?;
+            if let Some(reason) = in_use(cfg, toolchain.name())? {
+                bail!(
+                    "#{} ({}) can't be undone: '{}' is {}",
+                    entry.id,
+                    entry.operation,
+                    toolchain.name(),
+                    reason
+                );
+            }
+            if !common::confirm_removal(
+                "Uninstall this toolchain?",
+                &[toolchain.name().to_owned()],
+                "rustup undo",
+                true,
+                yes,
+            )? {
+                return Ok(ExitCode(0));
+            }
+            toolchain.remove()?;
+        }
+        Operation::AddComponents {
+            toolchain,
+            components,
+        } => components_of(cfg, toolchain, |distributable| {
+            distributable
+                .remove_components(components.iter().map(ComponentName::component).collect())
+        })?,
+        Operation::RemoveComponents {
+            toolchain,
+            components,
+        } => components_of(cfg, toolchain, |distributable| {
+            distributable.add_components(components.iter().map(ComponentName::component).collect())
+        })?,
+        Operation::UpdateToolchain { .. }
+        | Operation::UninstallToolchain { .. }
+        | Operation::Undo { .. } => {
+            bail!("#{} ({}) can't be undone", entry.id, entry.operation)
+        }
+    }
+    append(cfg, Operation::Undo { undid: entry.id })?;
+    info!("undid #{}: {}", entry.id, entry.operation);
+    Ok(ExitCode(0))
+}
+
+/// Why the toolchain `name` is still wanted, if it is: it is the default
+/// toolchain or a directory's override.
+fn in_use(cfg: &Cfg, name: &str) -> Result<Option<String>> {
+    let resolves_to_it = |other: &str| cfg.resolve_toolchain(other).ok().as_deref() == Some(name);
+    if let Some(default) = cfg.get_default()? {
+        if resolves_to_it(&default) {
+            return Ok(Some("the default toolchain".to_owned()));
+        }
+    }
+    let overrides = cfg.settings_file.with(|s| Ok(s.overrides.clone()))?;
+    Ok(overrides
+        .into_iter()
+        .find(|(_, toolchain)| resolves_to_it(toolchain))
+        .map(|(dir, _)| format!("the override for '{}'", dir)))
+}
+
+fn components_of(
+    cfg: &Cfg,
+    toolchain: &str,
+    change: impl FnOnce(&DistributableToolchain<'_>) -> Result<()>,
+) -> Result<()> {
+    let toolchain = cfg.get_toolchain(toolchain, false)?;
+    let distributable = DistributableToolchain::new_for_components(&toolchain)?;
+    change(&distributable)
+}
@@ -105,6 +105,13 @@ mod imp {
 
     impl Drop for Setup {
         fn drop(&mut self) {
+            // rustup lets the tool it runs deal with ctrl-c and outlives it,
+            // so anything the tool left running that ignored the ctrl-c, or
+            // never got it, is ended here rather than left behind.
+            if crate::command::interrupted() {
+                unsafe { kill_remaining(self.job.inner) }
+            }
+
             // On normal exits (not ctrl-c), we don't want to kill any child
             // processes. The destructor here configures our job object to
             // *not* kill everything on close, then closes the job object.
@@ -124,6 +131,43 @@ mod imp {
         }
     }
 
+    unsafe fn kill_remaining(job: HANDLE) {
+        #[repr(C)]
+        struct Jobs {
+            header: JOBOBJECT_BASIC_PROCESS_ID_LIST,
+            list: [usize; 1024],
+        }
+
+        let mut jobs: Jobs = mem::zeroed();
+        let r = QueryInformationJobObject(
+            job,
+            JobObjectBasicProcessIdList,
+            &mut jobs as *mut _ as LPVOID,
+            mem::size_of_val(&jobs) as DWORD,
+            ptr::null_mut(),
+        );
+        if r == 0 {
+            info!("failed to query job object: {}", last_err());
+            return;
+        }
+
+        let list = jobs.header.ProcessIdList.as_ptr();
+        let me = GetCurrentProcessId();
+        for i in 0..jobs.header.NumberOfProcessIdsInList as usize {
+            let pid = *list.add(i) as DWORD;
+            if pid == me {
+                continue;
+            }
+            let process = OpenProcess(PROCESS_TERMINATE, FALSE, pid);
+            if process.is_null() {
+                continue;
+            }
+            // The exit status of a process ended by ctrl-c.
+            TerminateProcess(process, 0xC000_013A);
+            CloseHandle(process);
+        }
+    }
+
     impl Drop for Handle {
         fn drop(&mut self) {
             unsafe {
@@ -1,75 +1,217 @@
+//! Logging, as `tracing` events.
+//!
+//! What the macros below print, and the notifications `NotifyOnConsole`
+//! prints, are emitted as events whose target is the part of rustup they
+//! come from: `rustup`, `download`, `dist`, `transaction` or `proxy`.
+//! `Logger` shows them on stderr the way rustup always has, keeping to the
+//! directives in `RUSTUP_LOG` when it is set, and can also append them as
+//! JSON lines to the file that `RUSTUP_LOG_FILE` names.
+
+use std::ffi::OsStr;
 use std::fmt;
-use std::io::Write;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+use lazy_static::lazy_static;
+use serde::Serialize;
 use term2::Terminal;
+use tracing::field::{Field, Visit};
+use tracing::subscriber::{Interest, Subscriber};
+use tracing::{span, Dispatch, Event, Level, Metadata};
 
 use super::term2;
 use crate::process;
 
 macro_rules! warn {
-    ( $ ( $ arg : tt ) * ) => ( $crate::cli::log::warn_fmt ( format_args ! ( $ ( $ arg ) * ) ) )
+    ( target: $ target : expr , $ ( $ arg : tt ) * ) => ( $crate::cli::log::log ( $ target , ::tracing::Level::WARN , format_args ! ( $ ( $ arg ) * ) ) );
+    ( $ ( $ arg : tt ) * ) => ( $crate::cli::log::log ( "rustup" , ::tracing::Level::WARN , format_args ! ( $ ( $ arg ) * ) ) )
 }
 macro_rules! err {
-    ( $ ( $ arg : tt ) * ) => ( $crate::cli::log::err_fmt ( format_args ! ( $ ( $ arg ) * ) ) )
+    ( target: $ target : expr , $ ( $ arg : tt ) * ) => ( $crate::cli::log::log ( $ target , ::tracing::Level::ERROR , format_args ! ( $ ( $ arg ) * ) ) );
+    ( $ ( $ arg : tt ) * ) => ( $crate::cli::log::log ( "rustup" , ::tracing::Level::ERROR , format_args ! ( $ ( $ arg ) * ) ) )
 }
 macro_rules! info {
-    ( $ ( $ arg : tt ) * ) => ( $crate::cli::log::info_fmt ( format_args ! ( $ ( $ arg ) * ) ) )
+    ( target: $ target : expr , $ ( $ arg : tt ) * ) => ( $crate::cli::log::log ( $ target , ::tracing::Level::INFO , format_args ! ( $ ( $ arg ) * ) ) );
+    ( $ ( $ arg : tt ) * ) => ( $crate::cli::log::log ( "rustup" , ::tracing::Level::INFO , format_args ! ( $ ( $ arg ) * ) ) )
 }
 
+/// Only shown with `--verbose`, or as `RUSTUP_LOG` allows.
 macro_rules! verbose {
-    ( $ ( $ arg : tt ) * ) => ( $crate::cli::log::verbose_fmt ( format_args ! ( $ ( $ arg ) * ) ) )
+    ( target: $ target : expr , $ ( $ arg : tt ) * ) => ( $crate::cli::log::log ( $ target , ::tracing::Level::DEBUG , format_args ! ( $ ( $ arg ) * ) ) );
+    ( $ ( $ arg : tt ) * ) => ( $crate::cli::log::log ( "rustup" , ::tracing::Level::DEBUG , format_args ! ( $ ( $ arg ) * ) ) )
 }
 
+/// Only shown with `RUSTUP_DEBUG` set, or as `RUSTUP_LOG` allows.
 macro_rules! debug {
-    ( $ ( $ arg : tt ) * ) => ( $crate::cli::log::debug_fmt ( format_args ! ( $ ( $ arg ) * ) ) )
+    ( target: $ target : expr , $ ( $ arg : tt ) * ) => ( $crate::cli::log::log ( $ target , ::tracing::Level::TRACE , format_args ! ( $ ( $ arg ) * ) ) );
+    ( $ ( $ arg : tt ) * ) => ( $crate::cli::log::log ( "rustup" , ::tracing::Level::TRACE , format_args ! ( $ ( $ arg ) * ) ) )
 }
 
-pub fn warn_fmt(args: fmt::Arguments<'_>) {
-    let mut t = term2::stderr();
-    let _ = t.fg(term2::color::YELLOW);
-    let _ = t.attr(term2::Attr::Bold);
-    let _ = write!(t, "warning: ");
-    let _ = t.reset();
-    let _ = t.write_fmt(args);
-    let _ = writeln!(t);
+// An event's target and level are part of its static metadata, so each has
+// to be spelled out.
+macro_rules! event_at {
+    ( $ target : literal , $ level : expr , $ args : expr ) => {
+        match $level {
+            Level::ERROR => tracing::event!(target: $target, Level::ERROR, "{}", $args),
+            Level::WARN => tracing::event!(target: $target, Level::WARN, "{}", $args),
+            Level::INFO => tracing::event!(target: $target, Level::INFO, "{}", $args),
+            Level::DEBUG => tracing::event!(target: $target, Level::DEBUG, "{}", $args),
+            _ => tracing::event!(target: $target, Level::TRACE, "{}", $args),
+        }
+    };
 }
 
-pub fn err_fmt(args: fmt::Arguments<'_>) {
-    let mut t = term2::stderr();
-    let _ = t.fg(term2::color::RED);
-    let _ = t.attr(term2::Attr::Bold);
-    let _ = write!(t, "error: ");
-    let _ = t.reset();
-    let _ = t.write_fmt(args);
-    let _ = writeln!(t);
+lazy_static! {
+    static ref LOGGER: Dispatch = Dispatch::new(Logger);
 }
 
-pub fn info_fmt(args: fmt::Arguments<'_>) {
-    let mut t = term2::stderr();
-    let _ = t.attr(term2::Attr::Bold);
-    let _ = write!(t, "info: ");
-    let _ = t.reset();
-    let _ = t.write_fmt(args);
-    let _ = writeln!(t);
+pub fn log(target: &str, level: Level, args: fmt::Arguments<'_>) {
+    // The logger is made the default only for the event, so that it is what
+    // each rustup process run in the same test binary logs to, and so that
+    // it doesn't take over the events of the crates rustup uses.
+    tracing::dispatcher::with_default(&LOGGER, || match target {
+        "download" => event_at!("download", level, args),
+        "dist" => event_at!("dist", level, args),
+        "transaction" => event_at!("transaction", level, args),
+        "proxy" => event_at!("proxy", level, args),
+        _ => event_at!("rustup", level, args),
+    })
+}
+
+/// Whether `RUSTUP_LOG` decides what is logged, in which case events that
+/// would otherwise wait on `--verbose` should be emitted anyway.
+pub(crate) fn filtered() -> bool {
+    process().var_os("RUSTUP_LOG").is_some()
+}
+
+struct Logger;
+
+impl Logger {
+    fn enabled(metadata: &Metadata<'_>) -> bool {
+        let process = process();
+        let directives = match process.var("RUSTUP_LOG") {
+            Ok(directives) => directives,
+            Err(_) => {
+                return *metadata.level() <= Level::DEBUG || process.var("RUSTUP_DEBUG").is_ok()
+            }
+        };
+        // The directive for the longest matching target wins, and a bare
+        // level goes for every target.
+        let mut best: Option<(usize, Option<Level>)> = None;
+        for directive in directives.split(',').map(str::trim) {
+            let (target, level) = match directive.rfind('=') {
+                Some(i) => (&directive[..i], &directive[i + 1..]),
+                None => ("", directive),
+            };
+            let level = match parse_level(level) {
+                Some(level) => level,
+                None => continue,
+            };
+            let target_matches = target.is_empty()
+                || metadata.target() == target
+                || metadata.target().starts_with(&format!("{}::", target));
+            if target_matches && !matches!(best, Some((len, _)) if target.len() < len) {
+                best = Some((target.len(), level));
+            }
+        }
+        matches!(best, Some((_, Some(max))) if *metadata.level() <= max)
+    }
+}
+
+/// `off` is `Some(None)`: nothing is logged.
+fn parse_level(s: &str) -> Option<Option<Level>> {
+    let level = match &*s.to_lowercase() {
+        "off" => return Some(None),
+        "error" => Level::ERROR,
+        "warn" => Level::WARN,
+        "info" => Level::INFO,
+        "debug" => Level::DEBUG,
+        "trace" => Level::TRACE,
+        _ => return None,
+    };
+    Some(Some(level))
+}
+
+impl Subscriber for Logger {
+    fn register_callsite(&self, _: &'static Metadata<'static>) -> Interest {
+        // Whether an event is logged depends on the environment of the
+        // process emitting it.
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        Self::enabled(metadata)
+    }
+
+    fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(1)
+    }
+
+    fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut message = Message::default();
+        event.record(&mut message);
+        let metadata = event.metadata();
+        show(*metadata.level(), &message.0);
+        if let Some(path) = process().var_os("RUSTUP_LOG_FILE") {
+            // Logging mustn't be what makes rustup fail.
+            let _ = append(&path, metadata, &message.0);
+        }
+    }
+
+    fn enter(&self, _: &span::Id) {}
+
+    fn exit(&self, _: &span::Id) {}
 }
 
-pub fn verbose_fmt(args: fmt::Arguments<'_>) {
+#[derive(Default)]
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
+
+fn show(level: Level, message: &str) {
+    let (label, style) = match level {
+        Level::ERROR => ("error", Some(term2::Style::Error)),
+        Level::WARN => ("warning", Some(term2::Style::Warning)),
+        Level::INFO => ("info", None),
+        Level::DEBUG => ("verbose", Some(term2::Style::Verbose)),
+        _ => ("debug", Some(term2::Style::Debug)),
+    };
     let mut t = term2::stderr();
-    let _ = t.fg(term2::color::MAGENTA);
+    if let Some(style) = style {
+        let _ = t.style(style);
+    }
     let _ = t.attr(term2::Attr::Bold);
-    let _ = write!(t, "verbose: ");
+    let _ = write!(t, "{}: ", label);
     let _ = t.reset();
-    let _ = t.write_fmt(args);
-    let _ = writeln!(t);
-}
-
-pub fn debug_fmt(args: fmt::Arguments<'_>) {
-    if process().var("RUSTUP_DEBUG").is_ok() {
-        let mut t = term2::stderr();
-        let _ = t.fg(term2::color::BLUE);
-        let _ = t.attr(term2::Attr::Bold);
-        let _ = write!(t, "debug: ");
-        let _ = t.reset();
-        let _ = t.write_fmt(args);
-        let _ = writeln!(t);
-    }
+    let _ = writeln!(t, "{}", message);
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    level: String,
+    target: &'a str,
+    message: &'a str,
+}
+
+fn append(path: &OsStr, metadata: &Metadata<'_>, message: &str) -> io::Result<()> {
+    let record = Record {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        level: metadata.level().to_string().to_lowercase(),
+        target: metadata.target(),
+        message,
+    };
+    let line = serde_json::to_string(&record)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
 }
@@ -4,7 +4,7 @@ use std::io;
 
 use pulldown_cmark::{Event, Tag};
 
-use super::term2::{color, Attr, Terminal};
+use super::term2::{Attr, Style, Terminal};
 
 // Handles the wrapping of text written to the console
 struct LineWrapper<'a, T: Terminal> {
@@ -145,7 +145,10 @@ impl<'a, T: Terminal + io::Write + 'a> LineFormatter<'a, T> {
                 self.wrapper.write_line();
             }
             Tag::Emphasis => {
-                self.push_attr(Attr::ForegroundColor(color::RED));
+                self.push_attr(match Style::Emphasis.color() {
+                    Some(color) => Attr::ForegroundColor(color),
+                    None => Attr::Underline(true),
+                });
             }
             Tag::Strong => {}
             Tag::Strikethrough => {}
@@ -0,0 +1,148 @@
+//! `rustup news`: the release notes for a version of Rust, the section of
+//! the compiler's RELEASES.md about it. A copy is kept in RUSTUP_HOME, so
+//! that notes once fetched can be shown offline, and those for a new stable
+//! or beta are shown when one is installed.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use super::markdown::md;
+use super::term2;
+use crate::dist::dist::ToolchainDesc;
+use crate::process;
+use crate::utils::tty;
+use crate::utils::utils::{self, ExitCode};
+use crate::Cfg;
+
+static RELEASE_NOTES_URL: &str =
+    "https://raw.githubusercontent.com/rust-lang/rust/master/RELEASES.md";
+
+fn cache_file(cfg: &Cfg) -> PathBuf {
+    cfg.cache_dir().join("release-notes.md")
+}
+
+/// Downloads the release notes afresh, replacing the cached copy.
+fn fetch(cfg: &Cfg) -> Result<String> {
+    let url = process()
+        .var("RUSTUP_RELEASE_NOTES_URL")
+        .unwrap_or_else(|_| String::from(RELEASE_NOTES_URL));
+    let url = utils::parse_url(&url)?;
+    let file = cfg.temp_cfg.new_file()?;
+    utils::download_file(&url, &file, None, &|_| ())?;
+    let notes = utils::read_file("release notes", &file)?;
+    utils::write_file("release notes", &cache_file(cfg), &notes)?;
+    Ok(notes)
+}
+
+/// The cached release notes, if there are any.
+fn cached(cfg: &Cfg) -> Option<String> {
+    let file = cache_file(cfg);
+    if !utils::is_file(&file) {
+        return None;
+    }
+    utils::read_file("release notes", &file).ok()
+}
+
+/// The section of `notes` for `version`: its `Version x.y.z (date)` heading
+/// and everything up to the next version's.
+fn section(notes: &str, version: &str) -> Option<String> {
+    let is_heading =
+        |lines: &[&str]| lines[0].starts_with("Version ") && lines[1].starts_with("===");
+    let lines: Vec<&str> = notes.lines().collect();
+    let heading = format!("Version {}", version);
+    let start = lines.windows(2).position(|lines| {
+        is_heading(lines) && (lines[0] == heading || lines[0].starts_with(&format!("{} ", heading)))
+    })?;
+    let end = lines[start + 1..]
+        .windows(2)
+        .position(is_heading)
+        .map(|i| start + 1 + i)
+        .unwrap_or_else(|| lines.len());
+    Some(lines[start..end].join("\n").trim_end().to_owned())
+}
+
+/// The version of Rust a toolchain's `rustc --version` is for, without any
+/// `-beta.N`, as the release notes name it.
+fn release_version(rustc_version: &str) -> Option<&str> {
+    let version = rustc_version.split_whitespace().next()?;
+    version.split('-').next()
+}
+
+/// Shows the release notes for `version`, fetching them if the cached copy
+/// doesn't have them yet.
+pub(crate) fn news(cfg: &Cfg, version: Option<&str>) -> Result<ExitCode> {
+    let version = match version {
+        Some(version) => version.to_owned(),
+        None => {
+            let toolchain = cfg.get_toolchain("stable", false)?;
+            if !toolchain.exists() {
+                return Err(anyhow!(
+                    "no stable toolchain installed; give the version to show the release notes for"
+                ));
+            }
+            let rustc_version = toolchain.rustc_version();
+            release_version(&rustc_version)
+                .ok_or_else(|| anyhow!("could not work out the version of the stable toolchain"))?
+                .to_owned()
+        }
+    };
+
+    let notes = match cached(cfg).and_then(|notes| section(&notes, &version)) {
+        Some(notes) => notes,
+        None => section(&fetch(cfg)?, &version)
+            .ok_or_else(|| anyhow!("no release notes for Rust {}", version))?,
+    };
+    md(&mut term2::stdout(), notes);
+    Ok(ExitCode(0))
+}
+
+/// After `toolchains` have been installed or updated, shows the release
+/// notes for the new stable or beta among them. Only on a terminal: CI logs
+/// have no use for them, and nothing here is worth failing the update for.
+pub(crate) fn show_after_update(cfg: &Cfg, toolchains: &[&str]) {
+    if !tty::stdout_isatty() {
+        return;
+    }
+    let mut versions = Vec::new();
+    for name in toolchains {
+        let is_release = ToolchainDesc::from_str(name)
+            .map(|desc| desc.channel == "stable" || desc.channel == "beta")
+            .unwrap_or(false);
+        if !is_release {
+            continue;
+        }
+        let rustc_version = match cfg.get_toolchain(name, false) {
+            Ok(toolchain) => toolchain.rustc_version(),
+            Err(_) => continue,
+        };
+        if let Some(version) = release_version(&rustc_version) {
+            if !versions.iter().any(|v: &String| v == version) {
+                versions.push(version.to_owned());
+            }
+        }
+    }
+    if versions.is_empty() {
+        return;
+    }
+
+    let notes = match fetch(cfg) {
+        Ok(notes) => notes,
+        Err(e) => {
+            verbose!("could not fetch the release notes: {:#}", e);
+            match cached(cfg) {
+                Some(notes) => notes,
+                None => return,
+            }
+        }
+    };
+    for version in &versions {
+        if let Some(section) = section(&notes, version) {
+            let _ = writeln!(process().stdout());
+            md(&mut term2::stdout(), section);
+            info!("run `rustup news {}` to see these again", version);
+        }
+    }
+}
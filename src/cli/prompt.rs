@@ -0,0 +1,33 @@
+//! `rustup prompt`: the active toolchain for the current directory, for
+//! shell prompts, which run it each time they are drawn. While the proxy
+//! cache's answer for the directory holds, that is printed, so that only
+//! the settings and the files which could change the answer are looked at.
+
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::process;
+use crate::utils::utils::{self, ExitCode};
+use crate::Cfg;
+
+/// Prints the name of the active toolchain, or nothing, exiting with 1, if
+/// there isn't one or it can't be found out. Nothing is logged: a prompt is
+/// no place for warnings.
+pub(crate) fn prompt(cfg: &Cfg) -> Result<ExitCode> {
+    let name = match cfg.active_toolchain_name_cached(&utils::current_dir()?) {
+        Ok(Some(name)) => name,
+        Ok(None) | Err(_) => return Ok(ExitCode(1)),
+    };
+    writeln!(process().stdout(), "{}", name)?;
+    Ok(ExitCode(0))
+}
+
+/// `rustup prompt` on its own, taken before the command line is parsed and
+/// the housekeeping every other command does, which together take longer
+/// than the rest.
+pub(crate) fn fast_prompt() -> Result<ExitCode> {
+    let cfg = Cfg::from_env(None, Arc::new(|_| {}))?;
+    prompt(&cfg)
+}
@@ -1,14 +1,21 @@
+use std::env;
+use std::env::consts::EXE_SUFFIX;
 use std::ffi::OsString;
+use std::io::Write;
+use std::path::Path;
 use std::process;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
-use super::common::set_globals;
+use super::common::{can_prompt, read_line, set_globals};
 use super::job;
 use super::self_update;
 use crate::command::run_command_for_dir;
+use crate::env_var;
+use crate::settings::{AutoInstallComponents, AutoInstallTargets};
+use crate::toolchain::DistributableToolchain;
 use crate::utils::utils::{self, ExitCode};
-use crate::Cfg;
+use crate::{Cfg, Toolchain, DUP_TOOLS, TOOLS};
 
 pub fn main(arg0: &str) -> Result<ExitCode> {
     self_update::cleanup_self_updater()?;
@@ -33,23 +40,315 @@ pub fn main(arg0: &str) -> Result<ExitCode> {
             crate::process().args_os().skip(2).collect()
         };
 
-        let cfg = set_globals(false, true)?;
+        let cfg = set_globals(false, true, None)?;
         cfg.check_metadata_version()?;
+        let custom = cfg.list_proxies()?;
+        if !TOOLS.iter().chain(DUP_TOOLS).any(|&name| name == arg0)
+            && !custom.iter().any(|name| name == arg0)
+        {
+            return Err(anyhow!(
+                "unknown proxy name: '{}'; valid proxy names are {}",
+                arg0,
+                TOOLS
+                    .iter()
+                    .chain(DUP_TOOLS)
+                    .copied()
+                    .chain(custom.iter().map(String::as_str))
+                    .map(|s| format!("'{}'", s))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
         direct_proxy(&cfg, &arg0, toolchain, &cmd_args)?
     };
 
     process::exit(c)
 }
 
+/// The error for a proxy that has ended up running itself, with what each
+/// proxy in the chain ran and what could be done about it.
+pub fn recursion_error() -> anyhow::Error {
+    let trace = crate::process()
+        .var(env_var::RUSTUP_PROXY_TRACE)
+        .unwrap_or_default();
+    let current_exe = utils::current_exe().ok();
+    let is_rustup = |path: &Path| match &current_exe {
+        Some(exe) => same_file::is_same_file(path, exe).unwrap_or(false),
+        None => false,
+    };
+
+    let entries: Vec<Vec<_>> = trace
+        .lines()
+        .map(|line| line.splitn(5, '\t').collect())
+        .filter(|fields: &Vec<_>| fields.len() == 5)
+        .collect();
+
+    let mut msg = String::from("infinite recursion detected");
+    let mut hints = Vec::new();
+    if let Ok(mut cfg) = set_globals(false, true, None) {
+        // Every proxy after the first was given RUSTUP_TOOLCHAIN by the
+        // one before, so what matters is what the first one was given.
+        if let Some(first) = entries.first() {
+            cfg.env_override = Some(first[2].to_owned()).filter(|name| !name.is_empty());
+        }
+        if let Ok(cwd) = utils::current_dir() {
+            match cfg.find_override(&cwd) {
+                Ok(Some((toolchain, reason))) => msg.push_str(&format!(
+                    "\nthe toolchain here is '{}' ({})",
+                    toolchain.name(),
+                    reason
+                )),
+                Ok(None) => {
+                    if let Ok(Some(toolchain)) = cfg.find_default() {
+                        msg.push_str(&format!(
+                            "\nthe toolchain here is '{}' (default toolchain)",
+                            toolchain.name()
+                        ));
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    for (i, fields) in entries.iter().enumerate() {
+        let (tool, toolchain, toolchain_path) = (fields[0], fields[1], fields[3]);
+        let candidate = Path::new(fields[4]);
+        msg.push_str(&format!(
+            "\n  {}: '{}' from '{}' ran '{}'",
+            i + 1,
+            tool,
+            toolchain,
+            candidate.display()
+        ));
+        if candidate.is_absolute() {
+            if is_rustup(candidate) {
+                msg.push_str(" which is rustup itself");
+                hints.push(format!(
+                    "'{}' is a link to rustup; link the toolchain to a directory holding a real Rust install",
+                    candidate.display()
+                ));
+            }
+        } else {
+            msg.push_str(&format!(
+                ", as it isn't in '{}' it was looked up in PATH",
+                toolchain_path
+            ));
+            let found = crate::process().var_os("PATH").and_then(|path| {
+                env::split_paths(&path)
+                    .map(|dir| dir.join(candidate))
+                    .find(|path| path.is_file())
+            });
+            if let Some(found) = found.filter(|found| is_rustup(found)) {
+                hints.push(format!(
+                    "'{}' found in PATH is a rustup proxy; install '{}' into the toolchain '{}'",
+                    found.display(),
+                    tool,
+                    toolchain
+                ));
+            }
+        }
+    }
+
+    hints.dedup();
+    hints.push("run `rustup override explain` to see why this toolchain was chosen".to_owned());
+    for hint in hints {
+        msg.push_str(&format!("\nhelp: {}", hint));
+    }
+    anyhow!(msg)
+}
+
 fn direct_proxy(
     cfg: &Cfg,
     arg0: &str,
     toolchain: Option<&str>,
     args: &[OsString],
 ) -> Result<ExitCode> {
+    let targets = requested_targets(arg0, args);
+    // Only the tools from components that may be left out are checked for,
+    // to keep rustc and cargo from looking for the toolchain twice.
+    let component = crate::component_for_bin(arg0).filter(|c| !matches!(*c, "rustc" | "cargo"));
+    if !targets.is_empty() || component.is_some() {
+        let toolchain = match toolchain {
+            None => cfg.toolchain_for_dir(&utils::current_dir()?)?.0,
+            Some(tc) => cfg.get_toolchain(tc, false)?,
+        };
+        // The build will report the problem itself if this goes wrong.
+        if let Err(e) = install_missing_targets(cfg, &toolchain, &targets) {
+            warn!("could not install the requested targets: {:#}", e);
+        }
+        if let Some(component) = component {
+            let toolchain = toolchain.for_tool(arg0)?.unwrap_or(toolchain);
+            install_missing_component(cfg, &toolchain, arg0, component)?;
+        }
+    }
+
     let cmd = match toolchain {
-        None => cfg.create_command_for_dir(&utils::current_dir()?, arg0)?,
-        Some(tc) => cfg.create_command_for_toolchain(tc, false, arg0)?,
+        None => cfg.create_command_for_dir_cached(&utils::current_dir()?, arg0)?,
+        Some(tc) => {
+            // The tool would only be looked for in PATH, where its proxy
+            // is, so a toolchain that should have it says so right away.
+            let toolchain = cfg.get_toolchain(tc, false)?;
+            let toolchain = toolchain.for_tool(arg0)?.unwrap_or(toolchain);
+            if toolchain.exists()
+                && !toolchain.is_custom()
+                && !utils::is_file(toolchain.binary_file(arg0))
+            {
+                return Err(missing_binary_error(&toolchain, arg0)?);
+            }
+            cfg.create_command_for_toolchain(tc, false, arg0)?
+        }
     };
+    debug!(target: "proxy", "running {:?}", cmd);
     run_command_for_dir(cmd, arg0, args)
 }
+
+fn missing_binary_error(toolchain: &Toolchain<'_>, arg0: &str) -> Result<anyhow::Error> {
+    let binary = format!("{}{}", arg0, EXE_SUFFIX);
+    Ok(toolchain
+        .as_installed_common()?
+        .missing_binary_error(binary.as_ref()))
+}
+
+/// The targets that cargo or rustc has been asked to build for.
+fn requested_targets(arg0: &str, args: &[OsString]) -> Vec<String> {
+    if arg0 != "cargo" && arg0 != "rustc" {
+        return Vec::new();
+    }
+    let mut targets = Vec::new();
+    let mut args = args.iter().map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--target" {
+            targets.extend(args.next().map(String::from));
+        } else if let Some(target) = arg.strip_prefix("--target=") {
+            targets.push(target.to_owned());
+        }
+    }
+    if arg0 == "cargo" && targets.is_empty() {
+        targets.extend(crate::process().var("CARGO_BUILD_TARGET").ok());
+    }
+    targets
+}
+
+/// Installs `component`, which provides `arg0`, if `toolchain` is missing
+/// it, as the `auto-install-components` setting allows, and otherwise
+/// fails saying how to install it.
+fn install_missing_component(
+    cfg: &Cfg,
+    toolchain: &Toolchain<'_>,
+    arg0: &str,
+    component: &str,
+) -> Result<()> {
+    if !toolchain.exists() || utils::is_file(toolchain.binary_file(arg0)) {
+        return Ok(());
+    }
+    let distributable = match DistributableToolchain::new(toolchain) {
+        Ok(distributable) => distributable,
+        Err(_) => return Ok(()),
+    };
+    let manifest = match distributable.get_manifest()? {
+        Some(manifest) => manifest,
+        None => return Ok(()),
+    };
+    // A component that isn't available is left for the proxy to explain.
+    let status = match distributable.list_components()?.into_iter().find(|status| {
+        status.component.short_name(&manifest) == component && status.available && !status.installed
+    }) {
+        Some(status) => status,
+        None => return Ok(()),
+    };
+
+    let install = match cfg.get_auto_install_components()? {
+        AutoInstallComponents::Always => true,
+        AutoInstallComponents::Never => false,
+        AutoInstallComponents::Ask if can_prompt() => {
+            write!(
+                crate::process().stderr(),
+                "component '{}' is not installed for '{}'. Install it? (Y/n) ",
+                component,
+                toolchain.name()
+            )?;
+            let answer = read_line()?.trim().to_lowercase();
+            matches!(answer.as_str(), "" | "y" | "yes")
+        }
+        AutoInstallComponents::Ask => false,
+    };
+    if !install {
+        return Err(missing_binary_error(toolchain, arg0)?);
+    }
+    distributable.add_component(status.component)?;
+    Ok(())
+}
+
+/// Installs the standard library for any of `targets` that `toolchain` is
+/// missing, as the `auto-install-targets` setting allows.
+fn install_missing_targets(cfg: &Cfg, toolchain: &Toolchain<'_>, targets: &[String]) -> Result<()> {
+    if !toolchain.exists() {
+        return Ok(());
+    }
+    // An installed target has its own directory, which is far cheaper to
+    // look for than the manifest is to load on every build.
+    let rustlib = toolchain.path().join("lib").join("rustlib");
+    let targets: Vec<_> = targets
+        .iter()
+        .filter(|target| !rustlib.join(target).exists())
+        .collect();
+    if targets.is_empty() {
+        return Ok(());
+    }
+    let distributable = match DistributableToolchain::new(toolchain) {
+        Ok(distributable) => distributable,
+        Err(_) => return Ok(()),
+    };
+
+    // Targets that aren't in the manifest, such as custom target specs,
+    // are left to the build to deal with.
+    let missing = distributable
+        .list_components()?
+        .into_iter()
+        .filter(|status| {
+            status.component.short_name_in_manifest() == "rust-std"
+                && status.available
+                && !status.installed
+                && status
+                    .component
+                    .target
+                    .iter()
+                    .any(|target| targets.contains(&&target.to_string()))
+        });
+
+    let mode = cfg.get_auto_install_targets()?;
+    for status in missing {
+        let target = status.component.target.as_ref().unwrap().to_string();
+        let install = match mode {
+            AutoInstallTargets::Enable => true,
+            AutoInstallTargets::Disable => false,
+            AutoInstallTargets::Prompt if can_prompt() => {
+                write!(
+                    crate::process().stderr(),
+                    "target '{}' is not installed for '{}'. Install it? (Y/n) ",
+                    target,
+                    toolchain.name()
+                )?;
+                let answer = read_line()?.trim().to_lowercase();
+                matches!(answer.as_str(), "" | "y" | "yes")
+            }
+            AutoInstallTargets::Prompt => {
+                warn!(
+                    "target '{}' is not installed for '{}'; install it with `rustup target add --toolchain {} {}`",
+                    target,
+                    toolchain.name(),
+                    toolchain.name(),
+                    target
+                );
+                false
+            }
+        };
+        if install {
+            distributable.add_component(status.component)?;
+        }
+    }
+    Ok(())
+}
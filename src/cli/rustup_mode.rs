@@ -1,33 +1,63 @@
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::env::consts::EXE_SUFFIX;
 use std::fmt;
 use std::io::Write;
 use std::iter;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, bail, Error, Result};
 use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, Shell, SubCommand};
+use download::IpVersion;
+use serde::Serialize;
 
+use super::auto_update;
+use super::bisect;
+use super::command_aliases;
+use super::completions;
+use super::config;
+use super::doctor;
 use super::help::*;
+use super::history::{self, Operation};
+use super::news;
+use super::prompt;
 use super::self_update;
+use super::snapshot;
+use super::stats;
+use super::status;
 use super::term2;
-use super::term2::Terminal;
+use super::term2::{ColorChoice, Terminal};
 use super::topical_doc;
+use super::ui;
+use super::wsl;
+use super::xdg;
 use super::{
     common,
     self_update::{check_rustup_update, SelfUpdateMode},
 };
 use crate::cli::errors::CLIError;
 use crate::dist::dist::{
-    PartialTargetTriple, PartialToolchainDesc, Profile, TargetTriple, ToolchainDesc,
+    self, PartialTargetTriple, PartialToolchainDesc, Profile, TargetTriple, ToolchainDesc,
 };
 use crate::dist::manifest::Component;
+use crate::dist::toolchain_archive;
 use crate::errors::RustupError;
 use crate::process;
+use crate::settings::{
+    parse_name_list, AutoInstallComponents, AutoInstallTargets, ColorTheme, ShimMode,
+    ToolchainFileSearch, UsageStats, VirtualToolchain,
+};
 use crate::toolchain::{CustomToolchain, DistributableToolchain};
+use crate::utils::units::{Size, Unit, UnitMode};
 use crate::utils::utils;
 use crate::Notification;
-use crate::{command, Cfg, ComponentStatus, Toolchain};
+use crate::{command, Cfg, ComponentStatus, Toolchain, DEFAULT_HOME_PROFILE, DUP_TOOLS, TOOLS};
+
+/// How many toolchains `rustup update` updates at once.
+const DEFAULT_UPDATE_JOBS: usize = 4;
 
 fn handle_epipe(res: Result<utils::ExitCode>) -> Result<utils::ExitCode> {
     match res {
@@ -44,6 +74,23 @@ fn handle_epipe(res: Result<utils::ExitCode>) -> Result<utils::ExitCode> {
     }
 }
 
+/// Whether the commands that show information print JSON, as asked for with
+/// `--format` or else RUSTUP_FORMAT.
+fn json_format(matches: &ArgMatches<'_>) -> Result<bool> {
+    if let Some(format) = matches.value_of("format") {
+        return Ok(format == "json");
+    }
+    match process().var("RUSTUP_FORMAT") {
+        Ok(format) if format == "json" => Ok(true),
+        Ok(format) if format == "text" || format.is_empty() => Ok(false),
+        Ok(format) => Err(anyhow!(
+            "invalid RUSTUP_FORMAT '{}': expected 'text' or 'json'",
+            format
+        )),
+        Err(_) => Ok(false),
+    }
+}
+
 fn deprecated<F, B, R>(instead: &str, cfg: &mut Cfg, matches: B, callee: F) -> R
 where
     F: FnOnce(&mut Cfg, B) -> R,
@@ -65,10 +112,21 @@ where
 }
 
 pub fn main() -> Result<utils::ExitCode> {
+    // Shell prompts run this every time they are drawn.
+    if process()
+        .args()
+        .skip(1)
+        .eq(std::iter::once("prompt".to_owned()))
+    {
+        return prompt::fast_prompt();
+    }
+
     self_update::cleanup_self_updater()?;
 
+    let args = command_aliases::expand(process().args_os().collect())?;
+
     use clap::ErrorKind::*;
-    let matches = match cli().get_matches_from_safe(process().args_os()) {
+    let matches = match cli().get_matches_from_safe(args) {
         Ok(matches) => Ok(matches),
         Err(clap::Error {
             kind: HelpDisplayed,
@@ -87,7 +145,7 @@ pub fn main() -> Result<utils::ExitCode> {
             info!("This is the version for the rustup toolchain manager, not the rustc compiler.");
 
             fn rustc_version() -> std::result::Result<String, Box<dyn std::error::Error>> {
-                let cfg = &mut common::set_globals(false, true)?;
+                let cfg = &mut common::set_globals(false, true, None)?;
                 let cwd = std::env::current_dir()?;
 
                 if let Some(t) = process().args().find(|x| x.starts_with('+')) {
@@ -126,70 +184,211 @@ pub fn main() -> Result<utils::ExitCode> {
     }?;
     let verbose = matches.is_present("verbose");
     let quiet = matches.is_present("quiet");
-    let cfg = &mut common::set_globals(verbose, quiet)?;
+    let json = json_format(&matches)?;
+    term2::set_color_choice(matches.value_of("color").and_then(ColorChoice::from_name));
+    common::set_non_interactive(matches.is_present("non-interactive"));
+    // Reports a broken settings file, which setting up the rest would stop
+    // at.
+    if let ("config", Some(c)) = matches.subcommand() {
+        if c.subcommand_name() == Some("check") {
+            return config::check(verbose, quiet, matches.value_of("home-profile"));
+        }
+    }
+    let cfg = &mut common::set_globals(verbose, quiet, matches.value_of("home-profile"))?;
 
     if let Some(t) = matches.value_of("+toolchain") {
         cfg.set_toolchain_override(&t[1..]);
     }
 
+    if let Some(file) = matches.value_of("toolchain-file") {
+        cfg.set_toolchain_file_override(Path::new(file))?;
+    }
+
     if maybe_upgrade_data(cfg, &matches)? {
         return Ok(utils::ExitCode(0));
     }
 
     cfg.check_metadata_version()?;
+    // Not a reason to stop, least of all `toolchain repair`, which exists
+    // to put right what this couldn't.
+    if let Err(e) = cfg.clean_up_incomplete_installs() {
+        warn!("could not clean up after an interrupted install: {:#}", e);
+    }
+    self_update::recover_replaced_bins()?;
+
+    // Anything which may change the default toolchain or what is in it.
+    let refresh_shims = matches!(
+        matches.subcommand_name(),
+        Some("install")
+            | Some("update")
+            | Some("uninstall")
+            | Some("default")
+            | Some("toolchain")
+            | Some("component")
+            | Some("snapshot")
+            | Some("self")
+            | Some("set")
+            | Some("ui")
+    );
 
-    Ok(match matches.subcommand() {
+    let exit_code = match matches.subcommand() {
         ("dump-testament", _) => common::dump_testament()?,
+        ("list-completions", Some(m)) => completions::list(cfg, m.value_of("kind").expect(""))?,
         ("show", Some(c)) => match c.subcommand() {
             ("active-toolchain", Some(m)) => handle_epipe(show_active_toolchain(cfg, m))?,
             ("home", Some(_)) => handle_epipe(show_rustup_home(cfg))?,
             ("profile", Some(_)) => handle_epipe(show_profile(cfg))?,
             ("keys", Some(_)) => handle_epipe(show_keys(cfg))?,
-            (_, _) => handle_epipe(show(cfg))?,
+            (_, _) => handle_epipe(show(cfg, json))?,
         },
         ("install", Some(m)) => deprecated("toolchain install", cfg, m, update)?,
         ("update", Some(m)) => update(cfg, m)?,
-        ("check", Some(_)) => check_updates(cfg)?,
+        ("check", Some(_)) => check_updates(cfg, json)?,
+        ("doctor", Some(_)) => doctor::doctor(cfg)?,
+        ("status", Some(_)) => handle_epipe(status::status(cfg, json))?,
+        ("prompt", Some(_)) => handle_epipe(prompt::prompt(cfg))?,
+        ("news", Some(m)) => handle_epipe(news::news(cfg, m.value_of("version")))?,
+        ("history", Some(_)) => handle_epipe(history::history(cfg, json))?,
+        ("stats", Some(m)) => {
+            if m.is_present("clear") {
+                stats::clear(cfg)?
+            } else {
+                handle_epipe(stats::stats(cfg, m.value_of("days").unwrap(), json))?
+            }
+        }
+        ("undo", Some(m)) => {
+            let id = match m.value_of("id") {
+                Some(id) => Some(
+                    id.parse()
+                        .map_err(|_| anyhow!("invalid change number: '{}'", id))?,
+                ),
+                None => None,
+            };
+            history::undo(cfg, id, m.is_present("yes"))?
+        }
+        ("prefetch", Some(m)) => prefetch(cfg, m)?,
+        ("report", Some(c)) => match c.subcommand() {
+            ("network", Some(m)) => handle_epipe(report_network(cfg, m))?,
+            (_, _) => unreachable!(),
+        },
         ("uninstall", Some(m)) => deprecated("toolchain uninstall", cfg, m, toolchain_remove)?,
         ("default", Some(m)) => default_(cfg, m)?,
         ("toolchain", Some(c)) => match c.subcommand() {
             ("install", Some(m)) => update(cfg, m)?,
-            ("list", Some(m)) => handle_epipe(toolchain_list(cfg, m))?,
+            ("list", Some(m)) => handle_epipe(toolchain_list(cfg, m, json))?,
             ("link", Some(m)) => toolchain_link(cfg, m)?,
             ("uninstall", Some(m)) => toolchain_remove(cfg, m)?,
+            ("gc", Some(m)) => toolchain_gc(cfg, m)?,
+            ("alias", Some(m)) => toolchain_alias(cfg, m)?,
+            ("virtual", Some(m)) => toolchain_virtual(cfg, m)?,
+            ("env", Some(m)) => toolchain_env(cfg, m)?,
+            ("export", Some(m)) => toolchain_export(cfg, m)?,
+            ("import", Some(m)) => toolchain_import(cfg, m)?,
+            ("clone", Some(m)) => toolchain_clone(cfg, m)?,
+            ("repair", Some(m)) => toolchain_repair(cfg, m)?,
+            (_, _) => unreachable!(),
+        },
+        ("migrate", Some(c)) => match c.subcommand() {
+            ("xdg", Some(_)) => xdg::migrate(cfg)?,
+            (_, _) => unreachable!(),
+        },
+        ("profile", Some(c)) => match c.subcommand() {
+            ("list", Some(_)) => handle_epipe(home_profile_list(cfg))?,
+            ("switch", Some(m)) => home_profile_switch(cfg, m)?,
             (_, _) => unreachable!(),
         },
         ("target", Some(c)) => match c.subcommand() {
-            ("list", Some(m)) => handle_epipe(target_list(cfg, m))?,
+            ("list", Some(m)) => handle_epipe(target_list(cfg, m, json))?,
             ("add", Some(m)) => target_add(cfg, m)?,
             ("remove", Some(m)) => target_remove(cfg, m)?,
             (_, _) => unreachable!(),
         },
         ("component", Some(c)) => match c.subcommand() {
-            ("list", Some(m)) => handle_epipe(component_list(cfg, m))?,
+            ("list", Some(m)) => handle_epipe(component_list(cfg, m, json))?,
             ("add", Some(m)) => component_add(cfg, m)?,
             ("remove", Some(m)) => component_remove(cfg, m)?,
             (_, _) => unreachable!(),
         },
         ("override", Some(c)) => match c.subcommand() {
-            ("list", Some(_)) => handle_epipe(common::list_overrides(cfg))?,
+            ("list", Some(_)) => handle_epipe(common::list_overrides(cfg, json))?,
             ("set", Some(m)) => override_add(cfg, m)?,
             ("unset", Some(m)) => override_remove(cfg, m)?,
+            ("prune", Some(m)) => override_prune(cfg, m)?,
+            ("host", Some(m)) => override_host(cfg, m)?,
+            ("explain", Some(m)) => handle_epipe(override_explain(cfg, m))?,
             (_, _) => unreachable!(),
         },
         ("run", Some(m)) => run(cfg, m)?,
-        ("which", Some(m)) => which(cfg, m)?,
+        ("bisect", Some(m)) => bisect(cfg, m)?,
+        ("snapshot", Some(c)) => match c.subcommand() {
+            ("save", Some(m)) => snapshot::save(cfg, Path::new(m.value_of("file").unwrap()))?,
+            ("restore", Some(m)) => snapshot::restore(cfg, Path::new(m.value_of("file").unwrap()))?,
+            (_, _) => unreachable!(),
+        },
+        ("trust", Some(c)) => match c.subcommand() {
+            ("list", Some(_)) => handle_epipe(trust_list(cfg))?,
+            ("add", Some(m)) => trust_add(cfg, m)?,
+            ("remove", Some(m)) => trust_remove(cfg, m)?,
+            (_, _) => unreachable!(),
+        },
+        ("proxy", Some(c)) => match c.subcommand() {
+            ("list", Some(_)) => handle_epipe(proxy_list(cfg))?,
+            ("add", Some(m)) => proxy_add(cfg, m)?,
+            ("remove", Some(m)) => proxy_remove(cfg, m)?,
+            (_, _) => unreachable!(),
+        },
+        ("toolchain-file", Some(c)) => match c.subcommand() {
+            ("check", Some(m)) => toolchain_file_check(cfg, m)?,
+            (_, _) => unreachable!(),
+        },
+        ("wsl-shims", Some(c)) => match c.subcommand() {
+            ("install", Some(m)) => wsl::install(
+                Path::new(m.value_of("windows-cargo-home").unwrap()),
+                &wsl_shims_dir(m)?,
+            )?,
+            ("remove", Some(m)) => wsl::remove(&wsl_shims_dir(m)?)?,
+            (_, _) => unreachable!(),
+        },
+        ("ui", Some(_)) => ui::ui(cfg)?,
+        ("which", Some(m)) => which(cfg, m, json)?,
         ("doc", Some(m)) => doc(cfg, m)?,
         ("man", Some(m)) => man(cfg, m)?,
         ("self", Some(c)) => match c.subcommand() {
             ("update", Some(_)) => self_update::update(cfg)?,
+            ("rollback", Some(_)) => self_update::rollback()?,
             ("uninstall", Some(m)) => self_uninstall(m)?,
+            ("auto-update", Some(_)) => auto_update::run(cfg)?,
             (_, _) => unreachable!(),
         },
         ("set", Some(c)) => match c.subcommand() {
             ("default-host", Some(m)) => set_default_host_triple(cfg, m)?,
             ("profile", Some(m)) => set_profile(cfg, m)?,
+            ("default-components", Some(m)) => set_default_components(cfg, m)?,
+            ("default-targets", Some(m)) => set_default_targets(cfg, m)?,
             ("auto-self-update", Some(m)) => set_auto_self_update(cfg, m)?,
+            ("ip-version", Some(m)) => set_ip_version(cfg, m)?,
+            ("auto-install-targets", Some(m)) => set_auto_install_targets(cfg, m)?,
+            ("usage-stats", Some(m)) => set_usage_stats(cfg, m)?,
+            ("auto-install-components", Some(m)) => set_auto_install_components(cfg, m)?,
+            ("toolchain-file-env", Some(m)) => set_toolchain_file_env(cfg, m)?,
+            ("toolchain-file-search", Some(m)) => set_toolchain_file_search(cfg, m)?,
+            ("override-hook", Some(m)) => set_override_hook(cfg, m)?,
+            ("shims", Some(m)) => set_shims(cfg, m)?,
+            ("color-theme", Some(m)) => set_color_theme(cfg, m)?,
+            ("rustc-wrapper", Some(m)) => set_rustc_wrapper(cfg, m)?,
+            (_, _) => unreachable!(),
+        },
+        ("config", Some(c)) => match c.subcommand() {
+            ("get", Some(m)) => handle_epipe(config::get(cfg, m.value_of("key").unwrap()))?,
+            ("set", Some(m)) => config::set(
+                cfg,
+                m.value_of("key").unwrap(),
+                m.value_of("value").unwrap(),
+            )?,
+            ("unset", Some(m)) => config::unset(cfg, m.value_of("key").unwrap())?,
+            ("list", Some(m)) => handle_epipe(config::list(cfg, m.is_present("all"), json))?,
+            ("export", Some(_)) => handle_epipe(config::export(cfg))?,
+            ("import", Some(m)) => config::import(cfg, Path::new(m.value_of("file").unwrap()))?,
             (_, _) => unreachable!(),
         },
         ("completions", Some(c)) => {
@@ -205,7 +404,22 @@ pub fn main() -> Result<utils::ExitCode> {
             }
         }
         (_, _) => unreachable!(),
-    })
+    };
+
+    if refresh_shims {
+        self_update::refresh_shims(cfg)?;
+    }
+
+    // Neither is worth failing a command which has done what it was asked.
+    if matches.subcommand_name() != Some("self") {
+        if let Err(e) = auto_update::show_notices(cfg) {
+            debug!("could not show what the last update check found: {:#}", e);
+        }
+        if let Err(e) = auto_update::schedule(cfg, matches.value_of("home-profile")) {
+            debug!("could not check for updates: {:#}", e);
+        }
+    }
+    Ok(exit_code)
 }
 
 pub fn cli() -> App<'static, 'static> {
@@ -229,6 +443,40 @@ pub fn cli() -> App<'static, 'static> {
                 .short("q")
                 .long("quiet"),
         )
+        .arg(
+            Arg::with_name("toolchain-file")
+                .help("Use the given toolchain file instead of looking for one")
+                .long("toolchain-file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("home-profile")
+                .help("Use the given profile's rustup home instead of the active profile's")
+                .long("profile")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .help("Output format of the commands that show information")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("color")
+                .help("Whether to color output")
+                .long("color")
+                .takes_value(true)
+                .possible_values(ColorChoice::choices())
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("non-interactive")
+                .help("Never ask anything: use the default answer, or fail where there is none")
+                .long("non-interactive")
+                .global(true),
+        )
         .arg(
             Arg::with_name("+toolchain")
                 .help("release channel (e.g. +stable) or custom toolchain to set override")
@@ -245,6 +493,16 @@ pub fn cli() -> App<'static, 'static> {
                 .about("Dump information about the build")
                 .setting(AppSettings::Hidden), // Not for users, only CI
         )
+        .subcommand(
+            SubCommand::with_name("list-completions")
+                .about("List toolchains, components or targets for shell completion")
+                .setting(AppSettings::Hidden) // Not for users, only the completion scripts
+                .arg(
+                    Arg::with_name("kind")
+                        .required(true)
+                        .possible_values(completions::KINDS),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("show")
                 .about("Show the active and installed toolchains or profiles")
@@ -294,6 +552,12 @@ pub fn cli() -> App<'static, 'static> {
                         .long("no-self-update")
                         .takes_value(false),
                 )
+                .arg(
+                    Arg::with_name("offline")
+                        .help("Install from what `rustup prefetch` downloaded, without checking for newer")
+                        .long("offline")
+                        .takes_value(false),
+                )
                 .arg(
                     Arg::with_name("force")
                         .help("Force an update, even if some components are missing")
@@ -310,6 +574,12 @@ pub fn cli() -> App<'static, 'static> {
                         .help(TOOLCHAIN_ARG_HELP)
                         .required(true)
                         .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .help("Uninstall them without asking")
+                        .short("y")
+                        .long("yes"),
                 ),
         )
         .subcommand(
@@ -329,6 +599,12 @@ pub fn cli() -> App<'static, 'static> {
                         .long("no-self-update")
                         .takes_value(false),
                 )
+                .arg(
+                    Arg::with_name("offline")
+                        .help("Install from what `rustup prefetch` downloaded, without checking for newer")
+                        .long("offline")
+                        .takes_value(false),
+                )
                 .arg(
                     Arg::with_name("force")
                         .help("Force an update, even if some components are missing")
@@ -339,9 +615,141 @@ pub fn cli() -> App<'static, 'static> {
                     Arg::with_name("force-non-host")
                         .help("Install toolchains that require an emulator. See https://github.com/rust-lang/rustup/wiki/Non-host-toolchains")
                         .long("force-non-host")
-                        .takes_value(false)),
+                        .takes_value(false))
+                .arg(
+                    Arg::with_name("jobs")
+                        .help("Update this many toolchains at once when updating them all [default: 4]")
+                        .long("jobs")
+                        .short("j")
+                        .takes_value(true)
+                        .value_name("N"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .help("Show what would change, without updating anything")
+                        .long("dry-run")
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check for updates to Rust toolchains and rustup")
+                .after_help(CHECK_HELP),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Look for problems with the rustup installation")
+                .after_help(DOCTOR_HELP),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Summarize the active toolchain, updates, disk usage and problems")
+                .after_help(STATUS_HELP),
+        )
+        .subcommand(
+            SubCommand::with_name("prompt")
+                .about("Print the active toolchain quickly, for shell prompts")
+                .after_help(PROMPT_HELP),
+        )
+        .subcommand(
+            SubCommand::with_name("news")
+                .about("Show the release notes for a version of Rust")
+                .after_help(NEWS_HELP)
+                .arg(Arg::with_name("version").help("Version of Rust, such as 1.52.0")),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Show which toolchains and tools have been run lately")
+                .after_help(STATS_HELP)
+                .arg(
+                    Arg::with_name("days")
+                        .help("How many days of runs to count")
+                        .long("days")
+                        .takes_value(true)
+                        .default_value("30"),
+                )
+                .arg(
+                    Arg::with_name("clear")
+                        .help("Remove everything recorded")
+                        .long("clear"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("List the changes rustup has made to toolchains and overrides")
+                .after_help(HISTORY_HELP),
+        )
+        .subcommand(
+            SubCommand::with_name("undo")
+                .about("Undo the last change, or the one given")
+                .after_help(UNDO_HELP)
+                .arg(
+                    Arg::with_name("id")
+                        .help("The number of the change in `rustup history`")
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .help("Uninstall a toolchain without asking")
+                        .short("y")
+                        .long("yes"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("prefetch")
+                .about("Download toolchains to install later, without installing them")
+                .after_help(PREFETCH_HELP)
+                .arg(
+                    Arg::with_name("toolchain")
+                        .help(TOOLCHAIN_ARG_HELP)
+                        .required(true)
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("profile")
+                        .long("profile")
+                        .takes_value(true)
+                        .possible_values(Profile::names())
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("components")
+                        .help("Also download specific components")
+                        .long("component")
+                        .short("c")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true),
+                )
+                .arg(
+                    Arg::with_name("targets")
+                        .help("Also download specific targets")
+                        .long("target")
+                        .short("t")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("Summarize information for diagnosing problems")
+                .setting(AppSettings::VersionlessSubcommands)
+                .setting(AppSettings::DeriveDisplayOrder)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("network")
+                        .about("Summarize recent downloads and how they failed")
+                        .after_help(REPORT_NETWORK_HELP)
+                        .arg(
+                            Arg::with_name("days")
+                                .help("How many days of downloads to summarize")
+                                .long("days")
+                                .takes_value(true)
+                                .default_value("7"),
+                        ),
+                ),
         )
-        .subcommand(SubCommand::with_name("check").about("Check for updates to Rust toolchains and rustup"))
         .subcommand(
             SubCommand::with_name("default")
                 .about("Set the default toolchain")
@@ -396,6 +804,12 @@ pub fn cli() -> App<'static, 'static> {
                                 .long("no-self-update")
                                 .takes_value(false),
                         )
+                        .arg(
+                            Arg::with_name("offline")
+                                .help("Install from what `rustup prefetch` downloaded, without checking for newer")
+                                .long("offline")
+                                .takes_value(false),
+                        )
                         .arg(
                             Arg::with_name("components")
                                 .help("Add specific components on installation")
@@ -430,12 +844,24 @@ pub fn cli() -> App<'static, 'static> {
                 .subcommand(
                     SubCommand::with_name("uninstall")
                         .about("Uninstall a toolchain")
+                        .after_help(TOOLCHAIN_UNINSTALL_HELP)
                         .alias("remove")
                         .arg(
                             Arg::with_name("toolchain")
                                 .help(TOOLCHAIN_ARG_HELP)
                                 .required(true)
                                 .multiple(true),
+                        )
+                        .arg(
+                            Arg::with_name("purge")
+                                .help("Also remove overrides for the toolchains, and empty the download cache")
+                                .long("purge"),
+                        )
+                        .arg(
+                            Arg::with_name("yes")
+                                .help("Uninstall them without asking")
+                                .short("y")
+                                .long("yes"),
                         ),
                 )
                 .subcommand(
@@ -451,6 +877,169 @@ pub fn cli() -> App<'static, 'static> {
                             Arg::with_name("path")
                                 .help("Path to the directory")
                                 .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("components-from")
+                                .help("Channel or manifest file to add components from")
+                                .long("components-from")
+                                .takes_value(true)
+                                .value_name("source"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("gc")
+                        .about("Uninstall toolchains that have not been used for a while")
+                        .after_help(TOOLCHAIN_GC_HELP)
+                        .arg(
+                            Arg::with_name("unused-for")
+                                .help("How long a toolchain must have gone unused, e.g. '90d'")
+                                .long("unused-for")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("dry-run")
+                                .help("List the toolchains that would be uninstalled")
+                                .long("dry-run")
+                                .takes_value(false),
+                        )
+                        .arg(
+                            Arg::with_name("yes")
+                                .help("Uninstall them without asking")
+                                .short("y")
+                                .long("yes")
+                                .conflicts_with("dry-run"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("alias")
+                        .about("Give a toolchain another name, or list the names given")
+                        .after_help(TOOLCHAIN_ALIAS_HELP)
+                        .arg(Arg::with_name("alias").help("The other name, e.g. 'bisect'"))
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .requires("alias"),
+                        )
+                        .arg(
+                            Arg::with_name("remove")
+                                .help("Remove the alias instead")
+                                .long("remove")
+                                .takes_value(false)
+                                .requires("alias")
+                                .conflicts_with("toolchain"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("virtual")
+                        .about("Make a toolchain that takes some tools from other toolchains")
+                        .after_help(TOOLCHAIN_VIRTUAL_HELP)
+                        .arg(Arg::with_name("name").help("The virtual toolchain's name"))
+                        .arg(
+                            Arg::with_name("base")
+                                .help("The toolchain to take every other tool from")
+                                .requires("name"),
+                        )
+                        .arg(
+                            Arg::with_name("tools")
+                                .help("Tools to take from elsewhere, as TOOL=TOOLCHAIN")
+                                .multiple(true)
+                                .requires("base"),
+                        )
+                        .arg(
+                            Arg::with_name("remove")
+                                .help("Remove the virtual toolchain instead")
+                                .long("remove")
+                                .takes_value(false)
+                                .requires("name")
+                                .conflicts_with("base"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("env")
+                        .about("Set environment variables for a toolchain's tools, or list them")
+                        .after_help(TOOLCHAIN_ENV_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("vars")
+                                .help("Variables to set, as KEY=VALUE")
+                                .multiple(true),
+                        )
+                        .arg(
+                            Arg::with_name("unset")
+                                .help("Variables to unset")
+                                .long("unset")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Package an installed toolchain into an archive")
+                        .after_help(TOOLCHAIN_EXPORT_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .help("Where to write the archive [default: <toolchain>.tar.zst]")
+                                .short("o")
+                                .long("output")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("Install a toolchain from an archive made by `export`")
+                        .arg(
+                            Arg::with_name("archive")
+                                .help("The archive to install from")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .help("Install under this name instead of the exported one")
+                                .long("name")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("clone")
+                        .about("Make a copy of an installed toolchain under a custom name")
+                        .after_help(TOOLCHAIN_CLONE_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .help("Custom toolchain name for the copy")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("copy")
+                                .help("Copy every file instead of hard linking")
+                                .long("copy")
+                                .takes_value(false),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("repair")
+                        .about("Reinstall a broken toolchain")
+                        .after_help(TOOLCHAIN_REPAIR_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(true)
+                                .multiple(true),
                         ),
                 ),
         )
@@ -488,6 +1077,11 @@ pub fn cli() -> App<'static, 'static> {
                                 .help(TOOLCHAIN_ARG_HELP)
                                 .long("toolchain")
                                 .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("keep-going")
+                                .help(KEEP_GOING_ARG_HELP)
+                                .long("keep-going"),
                         ),
                 )
                 .subcommand(
@@ -500,6 +1094,11 @@ pub fn cli() -> App<'static, 'static> {
                                 .help(TOOLCHAIN_ARG_HELP)
                                 .long("toolchain")
                                 .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("keep-going")
+                                .help(KEEP_GOING_ARG_HELP)
+                                .long("keep-going"),
                         ),
                 ),
         )
@@ -534,7 +1133,12 @@ pub fn cli() -> App<'static, 'static> {
                                 .long("toolchain")
                                 .takes_value(true),
                         )
-                        .arg(Arg::with_name("target").long("target").takes_value(true)),
+                        .arg(Arg::with_name("target").long("target").takes_value(true))
+                        .arg(
+                            Arg::with_name("keep-going")
+                                .help(KEEP_GOING_ARG_HELP)
+                                .long("keep-going"),
+                        ),
                 )
                 .subcommand(
                     SubCommand::with_name("remove")
@@ -546,7 +1150,12 @@ pub fn cli() -> App<'static, 'static> {
                                 .long("toolchain")
                                 .takes_value(true),
                         )
-                        .arg(Arg::with_name("target").long("target").takes_value(true)),
+                        .arg(Arg::with_name("target").long("target").takes_value(true))
+                        .arg(
+                            Arg::with_name("keep-going")
+                                .help(KEEP_GOING_ARG_HELP)
+                                .long("keep-going"),
+                        ),
                 ),
         )
         .subcommand(
@@ -591,23 +1200,123 @@ pub fn cli() -> App<'static, 'static> {
                                 .long("nonexistent")
                                 .takes_value(false)
                                 .help("Remove override toolchain for all nonexistent directories"),
+                        )
+                        .arg(
+                            Arg::with_name("yes")
+                                .help("Remove the overrides for nonexistent directories without asking")
+                                .short("y")
+                                .long("yes")
+                                .requires("nonexistent"),
                         ),
-                ),
-        )
-        .subcommand(
-            SubCommand::with_name("run")
-                .about("Run a command with an environment configured for a given toolchain")
-                .after_help(RUN_HELP)
-                .setting(AppSettings::TrailingVarArg)
-                .arg(
-                    Arg::with_name("install")
-                        .help("Install the requested toolchain if needed")
-                        .long("install"),
                 )
-                .arg(
-                    Arg::with_name("toolchain")
-                        .help(TOOLCHAIN_ARG_HELP)
-                        .required(true),
+                .subcommand(
+                    SubCommand::with_name("prune")
+                        .about("Remove overrides for missing directories or toolchains")
+                        .after_help(OVERRIDE_PRUNE_HELP)
+                        .arg(
+                            Arg::with_name("yes")
+                                .help("Remove them without asking")
+                                .short("y")
+                                .long("yes"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("explain")
+                        .about("Explain which toolchain is used in a directory, and why")
+                        .after_help(OVERRIDE_EXPLAIN_HELP)
+                        .arg(
+                            Arg::with_name("path")
+                                .help("Path to the directory [default: the current directory]"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("host")
+                        .about("Set the host triple to use for a directory or toolchain")
+                        .after_help(OVERRIDE_HOST_HELP)
+                        .arg(
+                            Arg::with_name("host_triple")
+                                .required_unless("unset")
+                                .conflicts_with("unset"),
+                        )
+                        .arg(
+                            Arg::with_name("path")
+                                .long("path")
+                                .takes_value(true)
+                                .help("Path to the directory"),
+                        )
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .long("toolchain")
+                                .takes_value(true)
+                                .conflicts_with("path")
+                                .help("Toolchain name without a host, such as `nightly`"),
+                        )
+                        .arg(
+                            Arg::with_name("unset")
+                                .long("unset")
+                                .help("Go back to the default host triple for the directory or toolchain"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Run a command with an environment configured for a given toolchain")
+                .after_help(RUN_HELP)
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("install")
+                        .help("Install the requested toolchain if needed")
+                        .long("install"),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .help("Set a variable for the command, as KEY=VALUE")
+                        .long("env")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("cd")
+                        .help("Run the command in this directory")
+                        .long("cd")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("clean-env")
+                        .help("Run the command without inheriting rustup's environment")
+                        .long("clean-env"),
+                )
+                .arg(
+                    Arg::with_name("toolchain")
+                        .help(TOOLCHAIN_ARG_HELP)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .required(true)
+                        .multiple(true)
+                        .use_delimiter(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bisect")
+                .about("Find the nightly in which a command's behaviour changed")
+                .after_help(BISECT_HELP)
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("start")
+                        .help("The first nightly to try, as YYYY-MM-DD")
+                        .long("start")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("end")
+                        .help("The last nightly to try, as YYYY-MM-DD")
+                        .long("end")
+                        .takes_value(true)
+                        .required(true),
                 )
                 .arg(
                     Arg::with_name("command")
@@ -616,15 +1325,160 @@ pub fn cli() -> App<'static, 'static> {
                         .use_delimiter(false),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("snapshot")
+                .about("Save or restore the exact toolchains that are installed")
+                .after_help(SNAPSHOT_HELP)
+                .setting(AppSettings::VersionlessSubcommands)
+                .setting(AppSettings::DeriveDisplayOrder)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("save")
+                        .about("Write the installed toolchains to a snapshot file")
+                        .arg(Arg::with_name("file").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("restore")
+                        .about("Install the toolchains in a snapshot file")
+                        .arg(Arg::with_name("file").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("trust")
+                .about("Modify the directories whose toolchain files can name a toolchain by path")
+                .after_help(TRUST_HELP)
+                .setting(AppSettings::VersionlessSubcommands)
+                .setting(AppSettings::DeriveDisplayOrder)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list").about("List the trusted directories"),
+                )
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Trust a directory and everything in it")
+                        .arg(
+                            Arg::with_name("path")
+                                .help("The directory to trust, or `*` for every directory")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Stop trusting a directory")
+                        .arg(Arg::with_name("path").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("proxy")
+                .about("Modify the tools besides the built-in ones which have proxies")
+                .after_help(PROXY_HELP)
+                .setting(AppSettings::VersionlessSubcommands)
+                .setting(AppSettings::DeriveDisplayOrder)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list").about("List the tools given proxies"),
+                )
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Give tools proxies in CARGO_HOME/bin")
+                        .arg(
+                            Arg::with_name("tool")
+                                .help("The name of the tool's binary, without any extension")
+                                .required(true)
+                                .multiple(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Remove the proxies for tools")
+                        .arg(Arg::with_name("tool").required(true).multiple(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("wsl-shims")
+                .about("Run the tools of rustup on Windows from inside WSL")
+                .after_help(WSL_SHIMS_HELP)
+                .setting(AppSettings::VersionlessSubcommands)
+                .setting(AppSettings::DeriveDisplayOrder)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("install")
+                        .about("Write shims for the tools in a Windows CARGO_HOME")
+                        .arg(
+                            Arg::with_name("windows-cargo-home")
+                                .help("The Windows CARGO_HOME, as seen from WSL, such as /mnt/c/Users/me/.cargo")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("dir")
+                                .help("Where to write the shims [default: CARGO_HOME/wsl-bin]")
+                                .long("dir")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Remove the shims")
+                        .arg(
+                            Arg::with_name("dir")
+                                .help("Where the shims are [default: CARGO_HOME/wsl-bin]")
+                                .long("dir")
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("toolchain-file")
+                .about("Work with rust-toolchain files")
+                .setting(AppSettings::VersionlessSubcommands)
+                .setting(AppSettings::DeriveDisplayOrder)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about("Check a toolchain file for mistakes")
+                        .after_help(TOOLCHAIN_FILE_CHECK_HELP)
+                        .arg(
+                            Arg::with_name("path")
+                                .help("The toolchain file, or the directory it is in")
+                                .default_value("."),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("ui")
+                .about("Manage toolchains, components and targets in the terminal")
+                .after_help(UI_HELP),
+        )
         .subcommand(
             SubCommand::with_name("which")
                 .about("Display which binary will be run for a given command")
-                .arg(Arg::with_name("command").required(true))
+                .after_help(WHICH_HELP)
+                .arg(Arg::with_name("command").required_unless("all"))
                 .arg(
                     Arg::with_name("toolchain")
                         .help(TOOLCHAIN_ARG_HELP)
                         .long("toolchain")
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .help("Display the binary for every tool of the toolchain")
+                        .long("all")
+                        .takes_value(false)
+                        .conflicts_with("command"),
+                )
+                .arg(
+                    Arg::with_name("trace")
+                        .help("Show how the toolchain and binary were chosen")
+                        .long("trace")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("chain")
+                        .help("Show every step from the command in PATH to the binary")
+                        .long("chain")
+                        .takes_value(false)
+                        .conflicts_with_all(&["all", "trace"]),
                 ),
         )
         .subcommand(
@@ -684,14 +1538,60 @@ pub fn cli() -> App<'static, 'static> {
                 .subcommand(
                     SubCommand::with_name("update").about("Download and install updates to rustup"),
                 )
+                .subcommand(
+                    SubCommand::with_name("rollback")
+                        .about("Go back to the rustup which the last update replaced")
+                        .after_help(SELF_ROLLBACK_HELP),
+                )
                 .subcommand(
                     SubCommand::with_name("uninstall")
                         .about("Uninstall rustup.")
-                        .arg(Arg::with_name("no-prompt").short("y")),
+                        .arg(
+                            Arg::with_name("yes")
+                                .help("Uninstall without asking")
+                                .short("y")
+                                .long("yes"),
+                        )
+                        .arg(
+                            Arg::with_name("purge")
+                                .help("Report how much disk space was reclaimed")
+                                .long("purge"),
+                        ),
                 )
                 .subcommand(
                     SubCommand::with_name("upgrade-data")
                         .about("Upgrade the internal data format."),
+                )
+                .subcommand(
+                    SubCommand::with_name("auto-update")
+                        .about("Check for updates as the auto_update settings say")
+                        .setting(AppSettings::Hidden), // Run in the background by rustup itself
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("profile")
+                .about("Switch between separate rustup homes")
+                .after_help(PROFILE_HELP)
+                .setting(AppSettings::VersionlessSubcommands)
+                .setting(AppSettings::DeriveDisplayOrder)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list").about("List the profiles"))
+                .subcommand(
+                    SubCommand::with_name("switch")
+                        .about("Make a profile the active one, creating it if needed")
+                        .arg(Arg::with_name("name").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Move rustup's files to another layout")
+                .after_help(MIGRATE_HELP)
+                .setting(AppSettings::VersionlessSubcommands)
+                .setting(AppSettings::DeriveDisplayOrder)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("xdg")
+                        .about("Move rustup's files from ~/.rustup into the XDG base directories"),
                 ),
         )
         .subcommand(
@@ -713,6 +1613,18 @@ pub fn cli() -> App<'static, 'static> {
                                 .default_value(Profile::default_name()),
                         ),
                 )
+                .subcommand(
+                    SubCommand::with_name("default-components")
+                        .about("Components installed with every new toolchain")
+                        .after_help(DEFAULT_COMPONENTS_HELP)
+                        .arg(Arg::with_name("component").multiple(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("default-targets")
+                        .about("Targets installed with every new toolchain")
+                        .after_help(DEFAULT_TARGETS_HELP)
+                        .arg(Arg::with_name("target").multiple(true)),
+                )
                 .subcommand(
                     SubCommand::with_name("auto-self-update")
                         .about("The rustup auto self update mode")
@@ -722,6 +1634,178 @@ pub fn cli() -> App<'static, 'static> {
                                 .possible_values(SelfUpdateMode::modes())
                                 .default_value(SelfUpdateMode::default_mode()),
                         ),
+                )
+                .subcommand(
+                    SubCommand::with_name("ip-version")
+                        .about("The IP address family used for downloads")
+                        .arg(
+                            Arg::with_name("ip-version")
+                                .required(true)
+                                .possible_values(IpVersion::names())
+                                .default_value(IpVersion::default_name()),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("auto-install-targets")
+                        .about(
+                            "Whether the proxies install a missing target before building for it",
+                        )
+                        .after_help(AUTO_INSTALL_TARGETS_HELP)
+                        .arg(
+                            Arg::with_name("auto-install-targets-mode")
+                                .required(true)
+                                .possible_values(AutoInstallTargets::modes())
+                                .default_value(AutoInstallTargets::default_mode()),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("usage-stats")
+                        .about("Whether to record the tools run, for `rustup stats`")
+                        .after_help(USAGE_STATS_HELP)
+                        .arg(
+                            Arg::with_name("usage-stats-mode")
+                                .required(true)
+                                .possible_values(UsageStats::modes())
+                                .default_value(UsageStats::default_mode()),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("auto-install-components")
+                        .about("Whether the proxies install a missing component before running its tool")
+                        .after_help(AUTO_INSTALL_COMPONENTS_HELP)
+                        .arg(
+                            Arg::with_name("auto-install-components-mode")
+                                .required(true)
+                                .possible_values(AutoInstallComponents::modes())
+                                .default_value(AutoInstallComponents::default_mode()),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("toolchain-file-env")
+                        .about("The environment variables toolchain files may use")
+                        .after_help(TOOLCHAIN_FILE_ENV_HELP)
+                        .arg(Arg::with_name("var").multiple(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("toolchain-file-search")
+                        .about("How far up from the current directory to look for a toolchain file")
+                        .after_help(TOOLCHAIN_FILE_SEARCH_HELP)
+                        .arg(
+                            Arg::with_name("toolchain-file-search-mode")
+                                .required(true)
+                                .possible_values(ToolchainFileSearch::modes())
+                                .default_value(ToolchainFileSearch::default_mode()),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("rustc-wrapper")
+                        .about("A command, such as sccache, to run rustc through")
+                        .after_help(RUSTC_WRAPPER_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .long("toolchain")
+                                .takes_value(true),
+                        )
+                        .arg(Arg::with_name("wrapper")),
+                )
+                .subcommand(
+                    SubCommand::with_name("shims")
+                        .about("Whether the tools in CARGO_HOME/bin are proxies or symlinks")
+                        .after_help(SHIMS_HELP)
+                        .arg(
+                            Arg::with_name("shims-mode")
+                                .required(true)
+                                .possible_values(ShimMode::modes())
+                                .default_value(ShimMode::default_mode()),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("color-theme")
+                        .about("The colors rustup's output is in")
+                        .after_help(COLOR_THEME_HELP)
+                        .arg(
+                            Arg::with_name("color-theme")
+                                .required(true)
+                                .possible_values(ColorTheme::themes())
+                                .default_value(ColorTheme::default_theme()),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("override-hook")
+                        .about("A command which says which toolchain to use in a directory")
+                        .after_help(OVERRIDE_HOOK_HELP)
+                        .setting(AppSettings::TrailingVarArg)
+                        .arg(
+                            Arg::with_name("ttl")
+                                .help("How many seconds to keep using its answer for a directory")
+                                .long("ttl")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("command")
+                                .multiple(true)
+                                .use_delimiter(false),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Read and change the settings in settings.toml")
+                .after_help(CONFIG_HELP)
+                .setting(AppSettings::VersionlessSubcommands)
+                .setting(AppSettings::DeriveDisplayOrder)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Print the value of a setting")
+                        .arg(
+                            Arg::with_name("key")
+                                .required(true)
+                                .possible_values(&config::names()),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Change a setting")
+                        .arg(
+                            Arg::with_name("key")
+                                .required(true)
+                                .possible_values(&config::names()),
+                        )
+                        .arg(Arg::with_name("value").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("unset")
+                        .about("Remove a setting, so that its default is used")
+                        .arg(
+                            Arg::with_name("key")
+                                .required(true)
+                                .possible_values(&config::names()),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the settings which are set")
+                        .arg(
+                            Arg::with_name("all")
+                                .help("List every setting, including those not set")
+                                .long("all"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Print the settings and aliases to set up another machine with"),
+                )
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("Set the settings and aliases printed by `rustup config export`")
+                        .arg(Arg::with_name("file").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("check").about(
+                        "Check the settings, policy, overrides and mirrors, failing if any is wrong",
+                    ),
                 ),
         );
 
@@ -849,7 +1933,7 @@ fn default_(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
     if m.is_present("toolchain") {
         let toolchain = m.value_of("toolchain").unwrap();
         default_bare_triple_check(cfg, toolchain)?;
-        let toolchain = cfg.get_toolchain(toolchain, false)?;
+        let toolchain = cfg.get_toolchain_to_save(toolchain)?;
 
         let status = if !toolchain.is_custom() {
             let distributable = DistributableToolchain::new(&toolchain)?;
@@ -860,8 +1944,22 @@ fn default_(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
             None
         };
 
+        let previous = cfg.get_default()?;
         toolchain.make_default()?;
 
+        if let Some(status) = &status {
+            history::record_update(cfg, toolchain.name(), status);
+        }
+        if previous.as_deref() != Some(toolchain.name()) {
+            history::record(
+                cfg,
+                Operation::SetDefault {
+                    toolchain: toolchain.name().to_owned(),
+                    previous,
+                },
+            );
+        }
+
         if let Some(status) = status {
             writeln!(process().stdout())?;
             common::show_channel_update(cfg, toolchain.name(), Ok(status))?;
@@ -882,40 +1980,218 @@ fn default_(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
         writeln!(process().stdout(), "{} (default)", default_toolchain?)?;
     }
 
-    Ok(utils::ExitCode(0))
+    Ok(utils::ExitCode(0))
+}
+
+fn prefetch(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let profile = m.value_of("profile").map(Profile::from_str).transpose()?;
+    let components: Vec<_> = m
+        .values_of("components")
+        .map(|v| v.collect())
+        .unwrap_or_else(Vec::new);
+    let targets: Vec<_> = m
+        .values_of("targets")
+        .map(|v| v.collect())
+        .unwrap_or_else(Vec::new);
+    for name in m.values_of("toolchain").unwrap() {
+        let toolchain = cfg.get_toolchain(name, false)?;
+        let distributable = DistributableToolchain::new(&toolchain)?;
+        let date = distributable.prefetch(&components, &targets, profile)?;
+        info!(
+            "'{}' ({}) is downloaded and can now be installed with --offline",
+            toolchain.name(),
+            date
+        );
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn report_network(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    use crate::dist::download_log::Outcome;
+    use std::collections::BTreeMap;
+
+    const MAX_LISTED_FAILURES: usize = 10;
+
+    let days = m.value_of("days").unwrap();
+    let days: u32 = match days.parse() {
+        Ok(days) if days > 0 => days,
+        _ => return Err(anyhow!("'{}' is not a number of days", days)),
+    };
+    // So many days back that they go past what a date can be are all of them.
+    let since = chrono::Utc::now().checked_sub_signed(chrono::Duration::days(i64::from(days)));
+    let entries: Vec<_> = cfg
+        .download_log
+        .entries()?
+        .into_iter()
+        .filter(|e| since.map_or(true, |since| e.time >= since))
+        .collect();
+
+    let mut t = term2::stdout();
+    if entries.is_empty() {
+        writeln!(t, "no downloads recorded in the last {} days", days)?;
+        return Ok(utils::ExitCode(0));
+    }
+
+    let failures: Vec<_> = entries
+        .iter()
+        .filter(|e| e.outcome != Outcome::Success)
+        .collect();
+    t.attr(term2::Attr::Bold)?;
+    write!(t, "Downloads in the last {} days: ", days)?;
+    t.reset()?;
+    writeln!(t, "{}", entries.len())?;
+    writeln!(t, "  failed:             {}", failures.len())?;
+    writeln!(
+        t,
+        "  needed retries:     {}",
+        entries.iter().filter(|e| e.retries > 0).count()
+    )?;
+    writeln!(
+        t,
+        "  served by a mirror: {}",
+        entries.iter().filter(|e| e.source != e.url).count()
+    )?;
+
+    if !failures.is_empty() {
+        let mut by_host = BTreeMap::new();
+        for failure in &failures {
+            let host = url::Url::parse(&failure.url)
+                .ok()
+                .and_then(|u| u.host_str().map(str::to_owned))
+                .unwrap_or_else(|| failure.url.clone());
+            *by_host.entry(host).or_insert(0) += 1;
+        }
+        writeln!(t)?;
+        t.attr(term2::Attr::Bold)?;
+        writeln!(t, "Failures by host")?;
+        t.reset()?;
+        for (host, count) in by_host {
+            writeln!(t, "  {}: {}", host, count)?;
+        }
+
+        writeln!(t)?;
+        t.attr(term2::Attr::Bold)?;
+        writeln!(t, "Most recent failures")?;
+        t.reset()?;
+        for failure in failures.iter().rev().take(MAX_LISTED_FAILURES) {
+            let reason = match &failure.outcome {
+                Outcome::ChecksumFailed => "checksum did not match",
+                Outcome::Failed(message) => message,
+                Outcome::Success => unreachable!(),
+            };
+            writeln!(
+                t,
+                "  {} {}",
+                failure.time.format("%Y-%m-%d %H:%M:%S UTC"),
+                failure.url
+            )?;
+            writeln!(
+                t,
+                "    {} ({} retries, {:.1}s)",
+                reason,
+                failure.retries,
+                failure.duration.as_secs_f64()
+            )?;
+        }
+    }
+
+    writeln!(t)?;
+    writeln!(t, "full log: {}", cfg.download_log.path().display())?;
+    Ok(utils::ExitCode(0))
+}
+
+/// What `rustup check` found for a toolchain, as its JSON output has it.
+#[derive(Serialize)]
+struct ToolchainCheck {
+    name: String,
+    current_version: Option<String>,
+    current_date: Option<String>,
+    update_available: bool,
+    update_version: Option<String>,
+    update_date: Option<String>,
+}
+
+impl ToolchainCheck {
+    /// From the version and release date installed and of the update.
+    fn new(
+        name: &str,
+        current: Option<(String, String)>,
+        update: Option<(String, String)>,
+    ) -> Self {
+        let (current_version, current_date) = match current {
+            Some((version, date)) => (Some(version), Some(date)),
+            None => (None, None),
+        };
+        let (update_version, update_date) = match update {
+            Some((version, date)) => (Some(version), Some(date)),
+            None => (None, None),
+        };
+        Self {
+            name: name.to_owned(),
+            current_version,
+            current_date,
+            update_available: update_version.is_some(),
+            update_version,
+            update_date,
+        }
+    }
+}
+
+/// What `rustup check` found for rustup itself, as its JSON output has it.
+#[derive(Serialize)]
+struct RustupCheck {
+    name: &'static str,
+    current_version: &'static str,
+    update_available: bool,
+    update_version: Option<String>,
+    /// Whether `rustup self update` can install the update, which it
+    /// can't where rustup was built without self-update.
+    self_update_available: bool,
+    /// The `auto-self-update` setting, which says whether `rustup update`
+    /// installs it.
+    auto_self_update: String,
 }
 
-fn check_updates(cfg: &Cfg) -> Result<utils::ExitCode> {
+fn check_updates(cfg: &Cfg, json: bool) -> Result<utils::ExitCode> {
     let mut t = term2::stdout();
     let channels = cfg.list_channels()?;
+    let mut checks = Vec::new();
 
     for channel in channels {
         match channel {
             (ref name, Ok(ref toolchain)) => {
                 let distributable = DistributableToolchain::new(&toolchain)?;
+                if json {
+                    checks.push(ToolchainCheck::new(
+                        name,
+                        distributable.show_release()?,
+                        distributable.show_dist_release()?,
+                    ));
+                    continue;
+                }
                 let current_version = distributable.show_version()?;
                 let dist_version = distributable.show_dist_version()?;
                 let _ = t.attr(term2::Attr::Bold);
                 write!(t, "{} - ", name)?;
                 match (current_version, dist_version) {
                     (None, None) => {
-                        let _ = t.fg(term2::color::RED);
+                        let _ = t.style(term2::Style::Error);
                         writeln!(t, "Cannot identify installed or update versions")?;
                     }
                     (Some(cv), None) => {
-                        let _ = t.fg(term2::color::GREEN);
+                        let _ = t.style(term2::Style::Good);
                         write!(t, "Up to date")?;
                         let _ = t.reset();
                         writeln!(t, " : {}", cv)?;
                     }
                     (Some(cv), Some(dv)) => {
-                        let _ = t.fg(term2::color::YELLOW);
+                        let _ = t.style(term2::Style::Warning);
                         write!(t, "Update available")?;
                         let _ = t.reset();
                         writeln!(t, " : {} -> {}", cv, dv)?;
                     }
                     (None, Some(dv)) => {
-                        let _ = t.fg(term2::color::YELLOW);
+                        let _ = t.style(term2::Style::Warning);
                         write!(t, "Update available")?;
                         let _ = t.reset();
                         writeln!(t, " : (Unknown version) -> {}", dv)?;
@@ -926,7 +2202,30 @@ fn check_updates(cfg: &Cfg) -> Result<utils::ExitCode> {
         }
     }
 
-    check_rustup_update()?;
+    if json {
+        let current_version = env!("CARGO_PKG_VERSION");
+        let available_version = self_update::get_available_rustup_version()?;
+        let update_available = available_version != current_version;
+        let rustup = RustupCheck {
+            name: "rustup",
+            current_version,
+            update_available,
+            update_version: Some(available_version).filter(|_| update_available),
+            self_update_available: update_available && !self_update::NEVER_SELF_UPDATE,
+            auto_self_update: cfg.get_self_update_mode()?.to_string(),
+        };
+        #[derive(Serialize)]
+        struct Checks {
+            toolchains: Vec<ToolchainCheck>,
+            rustup: RustupCheck,
+        }
+        common::print_json(&Checks {
+            toolchains: checks,
+            rustup,
+        })?;
+    } else {
+        check_rustup_update()?;
+    }
 
     Ok(utils::ExitCode(0))
 }
@@ -941,6 +2240,9 @@ fn update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
         && self_update_mode == SelfUpdateMode::Enable
         && !m.is_present("no-self-update");
     let forced = m.is_present("force-non-host");
+    if m.is_present("offline") {
+        cfg.set_offline();
+    }
     if let Some(p) = m.value_of("profile") {
         let p = Profile::from_str(p)?;
         cfg.set_profile_override(p);
@@ -949,6 +2251,27 @@ fn update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
     if cfg.get_profile()? == Profile::Complete {
         warn!("{}", common::WARN_COMPLETE_PROFILE);
     }
+    let components: Vec<_> = m
+        .values_of("components")
+        .map(|v| v.collect())
+        .unwrap_or_else(Vec::new);
+    let targets: Vec<_> = m
+        .values_of("targets")
+        .map(|v| v.collect())
+        .unwrap_or_else(Vec::new);
+    let dry_run = m.is_present("dry-run");
+    let names: Vec<String> = match m.values_of("toolchain") {
+        Some(names) => names.map(String::from).collect(),
+        None => cfg
+            .list_channels()?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect(),
+    };
+    show_update_plans(cfg, &names, &components, &targets, dry_run)?;
+    if dry_run {
+        return Ok(utils::ExitCode(0));
+    }
     if let Some(names) = m.values_of("toolchain") {
         for name in names {
             update_bare_triple_check(cfg, name)?;
@@ -979,14 +2302,6 @@ fn update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
             let toolchain = cfg.get_toolchain(name, false)?;
 
             let status = if !toolchain.is_custom() {
-                let components: Vec<_> = m
-                    .values_of("components")
-                    .map(|v| v.collect())
-                    .unwrap_or_else(Vec::new);
-                let targets: Vec<_> = m
-                    .values_of("targets")
-                    .map(|v| v.collect())
-                    .unwrap_or_else(Vec::new);
                 let distributable = DistributableToolchain::new(&toolchain)?;
                 Some(distributable.install_from_dist(
                     m.is_present("force"),
@@ -1004,14 +2319,26 @@ fn update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
             };
 
             if let Some(status) = status.clone() {
+                history::record_update(cfg, toolchain.name(), &status);
                 writeln!(process().stdout())?;
+                let changed = !matches!(status, crate::UpdateStatus::Unchanged);
                 common::show_channel_update(cfg, toolchain.name(), Ok(status))?;
+                if changed {
+                    news::show_after_update(cfg, &[toolchain.name()]);
+                }
             }
 
             if cfg.get_default()?.is_none() {
                 use crate::UpdateStatus;
                 if let Some(UpdateStatus::Installed) = status {
                     toolchain.make_default()?;
+                    history::record(
+                        cfg,
+                        Operation::SetDefault {
+                            toolchain: toolchain.name().to_owned(),
+                            previous: None,
+                        },
+                    );
                 }
             }
         }
@@ -1019,9 +2346,19 @@ fn update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
             common::self_update(|| Ok(utils::ExitCode(0)))?;
         }
     } else {
-        common::update_all_channels(cfg, self_update, m.is_present("force"))?;
+        let jobs = match m.value_of("jobs") {
+            Some(jobs) => jobs
+                .parse()
+                .ok()
+                .filter(|&jobs| jobs > 0)
+                .ok_or_else(|| anyhow!("invalid number of jobs: '{}'", jobs))?,
+            None => DEFAULT_UPDATE_JOBS,
+        };
+        common::update_all_channels(cfg, self_update, m.is_present("force"), jobs)?;
         info!("cleaning up downloads & tmp directories");
-        utils::delete_dir_contents(&cfg.download_dir);
+        // Wait for other processes to finish with them first.
+        let _lock = cfg.lock_metadata()?;
+        dist::clean_download_dir(&cfg.download_dir)?;
         cfg.temp_cfg.clean();
     }
 
@@ -1032,40 +2369,452 @@ fn update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
     Ok(utils::ExitCode(0))
 }
 
+/// Shows what updating each of the installed toolchains among `names`
+/// would change, before they are updated or, with `--dry-run`, instead.
+fn show_update_plans(
+    cfg: &Cfg,
+    names: &[String],
+    components: &[&str],
+    targets: &[&str],
+    dry_run: bool,
+) -> Result<()> {
+    let mut out = process().stdout();
+    for name in names {
+        let toolchain = cfg.get_toolchain(name, false)?;
+        if !toolchain.exists() || toolchain.is_custom() {
+            continue;
+        }
+        let distributable = DistributableToolchain::new(&toolchain)?;
+        let plan = match distributable.plan_update(components, targets) {
+            Ok(Some(plan)) => plan,
+            Ok(None) => {
+                if dry_run {
+                    writeln!(out, "{}: up to date", toolchain.name())?;
+                }
+                continue;
+            }
+            // The update itself says why, backtracking to an older nightly
+            // where the latest lacks a component.
+            Err(e) if dry_run => {
+                warn!(
+                    "could not work out what updating '{}' would change: {:#}",
+                    toolchain.name(),
+                    e
+                );
+                continue;
+            }
+            Err(_) => continue,
+        };
+        writeln!(out, "{}:", toolchain.name())?;
+        for change in &plan.components {
+            match (&change.from, &change.to) {
+                (Some(from), Some(to)) => writeln!(out, "  {}: {} -> {}", change.name, from, to)?,
+                (None, Some(to)) => writeln!(out, "  {}: added, {}", change.name, to)?,
+                (Some(from), None) => writeln!(out, "  {}: removed, was {}", change.name, from)?,
+                (None, None) => writeln!(out, "  {}", change.name)?,
+            }
+        }
+        match plan.download_size {
+            Some(bytes) => {
+                let size = Size::new(bytes as usize, Unit::B, UnitMode::Norm);
+                writeln!(out, "  download size: {}", size.to_string().trim_start())?;
+            }
+            None => writeln!(out, "  download size: unknown")?,
+        }
+    }
+    Ok(())
+}
+
 fn run(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
     let toolchain = m.value_of("toolchain").unwrap();
     let args = m.values_of("command").unwrap();
     let args: Vec<_> = args.collect();
-    let cmd = cfg.create_command_for_toolchain(toolchain, m.is_present("install"), args[0])?;
+    let mut vars = Vec::new();
+    for var in m.values_of("env").into_iter().flatten() {
+        match var.find('=') {
+            Some(i) if i > 0 => vars.push((&var[..i], &var[i + 1..])),
+            _ => return Err(anyhow!("expected KEY=VALUE, found '{}'", var)),
+        }
+    }
+    let dir = m.value_of("cd").map(Path::new);
+    if let Some(dir) = dir {
+        if !utils::is_directory(dir) {
+            return Err(anyhow!("'{}' is not a directory", dir.display()));
+        }
+    }
+
+    let mut cmd = cfg.create_command_for_toolchain(toolchain, m.is_present("install"), args[0])?;
+    if m.is_present("clean-env") {
+        cmd.env_clear();
+        for var in CLEAN_ENV_VARS {
+            if let Some(value) = process().var_os(var) {
+                cmd.env(var, value);
+            }
+        }
+        cfg.set_clean_env_for_toolchain(toolchain, args[0], &mut cmd)?;
+    }
+    cmd.envs(vars);
+    if let Some(dir) = dir {
+        cmd.current_dir(dir);
+    }
 
     let code = command::run_command_for_dir(cmd, args[0], &args[1..])?;
     Ok(code)
 }
 
-fn which(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
-    let binary = m.value_of("command").unwrap();
-    let binary_path = if m.is_present("toolchain") {
-        let toolchain = m.value_of("toolchain").unwrap();
-        cfg.which_binary_by_toolchain(toolchain, binary)?
-    } else {
-        cfg.which_binary(&utils::current_dir()?, binary)?
+/// The variables a command run with `rustup run --clean-env` keeps, which
+/// most programs need to work at all.
+const CLEAN_ENV_VARS: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "TERM",
+    "TMPDIR",
+    "LANG",
+    "SYSTEMROOT",
+    "USERPROFILE",
+    "TEMP",
+    "TMP",
+    "PATHEXT",
+    "COMSPEC",
+];
+
+fn bisect(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let start = bisect::parse_date(m.value_of("start").unwrap())?;
+    let end = bisect::parse_date(m.value_of("end").unwrap())?;
+    let args: Vec<_> = m.values_of("command").unwrap().collect();
+    bisect::bisect(cfg, start, end, &args)
+}
+
+fn which(cfg: &Cfg, m: &ArgMatches<'_>, json: bool) -> Result<utils::ExitCode> {
+    let trace = m.is_present("trace");
+    let (toolchain, reason) = match m.value_of("toolchain") {
+        Some(name) => {
+            let toolchain = cfg.get_toolchain(name, false)?;
+            if !toolchain.exists() {
+                return Err(RustupError::ToolchainNotInstalled(name.to_owned()).into());
+            }
+            (toolchain, None)
+        }
+        None => cfg.find_or_install_override_toolchain_or_default(&utils::current_dir()?)?,
+    };
+    let reason = match reason {
+        Some(reason) => reason.to_string(),
+        None if m.is_present("toolchain") => "given by --toolchain".to_owned(),
+        None => "default toolchain".to_owned(),
+    };
+
+    if m.is_present("chain") {
+        let binary = m.value_of("command").unwrap();
+        let chain = WhichChain::new(cfg, &toolchain, reason, binary)?;
+        if json {
+            common::print_json(&chain)?;
+        } else {
+            chain.print()?;
+        }
+        return Ok(utils::ExitCode(0));
+    }
+
+    if trace {
+        writeln!(
+            process().stdout(),
+            "toolchain: {} ({})",
+            toolchain.name(),
+            reason
+        )?;
+        if let Some(name) = toolchain.virtual_name() {
+            writeln!(process().stdout(), "virtual toolchain: {}", name)?;
+        }
+        let kind = if toolchain.is_system() {
+            " (provided by the system)"
+        } else if toolchain.is_custom() {
+            " (linked)"
+        } else {
+            ""
+        };
+        writeln!(
+            process().stdout(),
+            "toolchain path: {}{}",
+            toolchain.path().display(),
+            kind
+        )?;
     }
-    .expect("binary not found");
 
-    utils::assert_is_file(&binary_path)?;
+    if !m.is_present("all") {
+        let binary = m.value_of("command").unwrap();
+        let binary_path = which_binary(cfg, &toolchain, binary, trace)?;
+        utils::assert_is_file(&binary_path)?;
+        writeln!(process().stdout(), "{}", binary_path.display())?;
+        return Ok(utils::ExitCode(0));
+    }
 
-    writeln!(process().stdout(), "{}", binary_path.display())?;
+    let mut found = Vec::new();
+    for tool in TOOLS.iter().chain(DUP_TOOLS) {
+        let binary_path = which_binary(cfg, &toolchain, tool, trace)?;
+        if utils::is_file(&binary_path) {
+            found.push((tool, binary_path));
+        }
+    }
+    let width = found.iter().map(|(tool, _)| tool.len()).max().unwrap_or(0);
+    for (tool, binary_path) in found {
+        writeln!(
+            process().stdout(),
+            "{:width$}  {}",
+            tool,
+            binary_path.display(),
+            width = width
+        )?;
+    }
     Ok(utils::ExitCode(0))
 }
 
-fn show(cfg: &Cfg) -> Result<utils::ExitCode> {
+/// Every step from running a tool in the shell to the binary that runs,
+/// for `rustup which --chain`.
+#[derive(Serialize)]
+struct WhichChain {
+    tool: String,
+    /// The first file in PATH that the shell runs for the tool.
+    command: Option<PathBuf>,
+    /// Whether that is rustup's proxy, rather than another install.
+    command_is_proxy: bool,
+    toolchain: String,
+    reason: String,
+    virtual_toolchain: Option<String>,
+    toolchain_path: PathBuf,
+    /// The toolchain the binary comes from, where that is another one, as
+    /// with virtual toolchains and linked toolchains without cargo.
+    binary_toolchain: Option<String>,
+    binary: PathBuf,
+    binary_exists: bool,
+    rustc_wrapper: Option<String>,
+}
+
+impl WhichChain {
+    fn new(cfg: &Cfg, toolchain: &Toolchain<'_>, reason: String, tool: &str) -> Result<Self> {
+        let exe = format!("{}{}", tool, EXE_SUFFIX);
+        let path = process().var_os("PATH").unwrap_or_default();
+        let command = env::split_paths(&path)
+            .map(|dir| dir.join(&exe))
+            .find(|path| utils::is_file(path));
+        let proxy = utils::cargo_home()?.join("bin").join(&exe);
+        let command_is_proxy = match &command {
+            Some(command) => {
+                command == &proxy || same_file::is_same_file(command, &proxy).unwrap_or(false)
+            }
+            None => false,
+        };
+
+        let source = match toolchain.for_tool(tool)? {
+            Some(source) => Some(source),
+            None if tool == "cargo" => cfg.cargo_fallback_toolchain(toolchain)?,
+            None => None,
+        };
+        let binary = match &source {
+            Some(source) => source.binary_file(tool),
+            None => toolchain.binary_file(tool),
+        };
+        let rustc_wrapper = if tool == "rustc" {
+            cfg.get_rustc_wrapper(toolchain.name())?
+        } else {
+            None
+        };
+
+        Ok(Self {
+            tool: tool.to_owned(),
+            command,
+            command_is_proxy,
+            toolchain: toolchain.name().to_owned(),
+            reason,
+            virtual_toolchain: toolchain.virtual_name().map(str::to_owned),
+            toolchain_path: toolchain.path().to_owned(),
+            binary_toolchain: source.map(|source| source.name().to_owned()),
+            binary_exists: utils::is_file(&binary),
+            binary,
+            rustc_wrapper,
+        })
+    }
+
+    fn print(&self) -> Result<()> {
+        let mut t = process().stdout();
+        match &self.command {
+            Some(command) if self.command_is_proxy => {
+                writeln!(t, "command: {} (rustup proxy)", command.display())?
+            }
+            Some(command) => writeln!(
+                t,
+                "command: {} (not rustup's proxy, so rustup isn't involved)",
+                command.display()
+            )?,
+            None => writeln!(t, "command: '{}' isn't in PATH", self.tool)?,
+        }
+        writeln!(t, "toolchain: {} ({})", self.toolchain, self.reason)?;
+        if let Some(name) = &self.virtual_toolchain {
+            writeln!(t, "virtual toolchain: {}", name)?;
+        }
+        writeln!(t, "toolchain path: {}", self.toolchain_path.display())?;
+        if let Some(name) = &self.binary_toolchain {
+            writeln!(t, "{} comes from: {}", self.tool, name)?;
+        }
+        if let Some(wrapper) = &self.rustc_wrapper {
+            writeln!(t, "rustc wrapper: {}", wrapper)?;
+        }
+        if self.binary_exists {
+            writeln!(t, "binary: {}", self.binary.display())?;
+        } else {
+            writeln!(t, "binary: {} (missing)", self.binary.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// The binary the proxy for `binary` runs with `toolchain`.
+fn which_binary(
+    cfg: &Cfg,
+    toolchain: &Toolchain<'_>,
+    binary: &str,
+    trace: bool,
+) -> Result<PathBuf> {
+    if let Some(source) = toolchain.for_tool(binary)? {
+        if trace {
+            writeln!(
+                process().stdout(),
+                "{}: '{}' takes it from '{}'",
+                binary,
+                toolchain.virtual_name().unwrap_or_default(),
+                source.name()
+            )?;
+        }
+        return Ok(source.binary_file(binary));
+    }
+    if binary == "cargo" {
+        if let Some(fallback) = cfg.cargo_fallback_toolchain(toolchain)? {
+            if trace {
+                writeln!(
+                    process().stdout(),
+                    "cargo: '{}' has no cargo, so the one from '{}' is used",
+                    toolchain.name(),
+                    fallback.name()
+                )?;
+            }
+            return Ok(fallback.binary_file(binary));
+        }
+    }
+    Ok(toolchain.binary_file(binary))
+}
+
+/// What `rustup show` shows, for its JSON output.
+#[derive(Serialize)]
+struct ShowDetails {
+    default_host: String,
+    /// The directory of the host override that sets `default_host`, if any.
+    host_override: Option<PathBuf>,
+    rustup_home: PathBuf,
+    home_profile: String,
+    default_toolchain: Option<String>,
+    installed_toolchains: Vec<String>,
+    active_toolchain: Option<ActiveToolchain>,
+    /// Why there is no active toolchain, where that is down to an error.
+    active_toolchain_error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ActiveToolchain {
+    name: String,
+    reason: String,
+    rustc_version: String,
+    /// Where the standard library and tools are, as `rustc --print sysroot`
+    /// gives it.
+    sysroot: PathBuf,
+    targets: Vec<String>,
+    components: Vec<String>,
+    /// The tools the proxies run, with the path of each that is installed.
+    tools: BTreeMap<&'static str, PathBuf>,
+}
+
+impl ShowDetails {
+    fn new(cfg: &Cfg) -> Result<Self> {
+        let cwd = utils::current_dir()?;
+        let (default_host, host_override) = match cfg.find_host_override(&cwd)? {
+            Some((host_triple, dir)) => (host_triple.to_string(), Some(dir)),
+            None => (cfg.get_default_host_triple()?.to_string(), None),
+        };
+        let mut details = Self {
+            default_host,
+            host_override,
+            rustup_home: cfg.rustup_dir.clone(),
+            home_profile: cfg.home_profile.clone(),
+            default_toolchain: cfg.get_default()?,
+            installed_toolchains: cfg.list_toolchains()?,
+            active_toolchain: None,
+            active_toolchain_error: None,
+        };
+        match cfg.find_or_install_override_toolchain_or_default(&cwd) {
+            Ok((toolchain, reason)) => {
+                let installed: Vec<_> = DistributableToolchain::new(&toolchain)
+                    .and_then(|distributable| distributable.list_components())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|c| c.installed)
+                    .collect();
+                let targets = installed
+                    .iter()
+                    .filter(|c| c.component.short_name_in_manifest() == "rust-std")
+                    .filter_map(|c| c.component.target.as_ref().map(ToString::to_string))
+                    .collect();
+                let components = installed.into_iter().map(|c| c.name).collect();
+                let sysroot = match toolchain.for_tool("rustc")? {
+                    Some(source) => source.path().to_owned(),
+                    None => toolchain.path().to_owned(),
+                };
+                let mut tools = BTreeMap::new();
+                for tool in TOOLS.iter().chain(DUP_TOOLS) {
+                    let path = which_binary(cfg, &toolchain, tool, false)?;
+                    if utils::is_file(&path) {
+                        tools.insert(*tool, path);
+                    }
+                }
+                details.active_toolchain = Some(ActiveToolchain {
+                    name: toolchain.name().to_owned(),
+                    reason: reason.map_or_else(|| "default".to_owned(), |r| r.to_string()),
+                    rustc_version: toolchain.rustc_version(),
+                    sysroot,
+                    targets,
+                    components,
+                    tools,
+                });
+            }
+            Err(err) => {
+                if let Some(RustupError::ToolchainNotSelected) =
+                    err.root_cause().downcast_ref::<RustupError>()
+                {
+                } else {
+                    details.active_toolchain_error = Some(format!("{:#}", err));
+                }
+            }
+        }
+        Ok(details)
+    }
+}
+
+fn show(cfg: &Cfg, json: bool) -> Result<utils::ExitCode> {
+    if json {
+        common::print_json(&ShowDetails::new(cfg)?)?;
+        return Ok(utils::ExitCode(0));
+    }
+
     // Print host triple
     {
         let mut t = term2::stdout();
         t.attr(term2::Attr::Bold)?;
         write!(t, "Default host: ")?;
         t.reset()?;
-        writeln!(t, "{}", cfg.get_default_host_triple()?)?;
+        match cfg.find_host_override(&utils::current_dir()?)? {
+            Some((host_triple, dir)) => {
+                writeln!(t, "{} (host override for '{}')", host_triple, dir.display())?
+            }
+            None => writeln!(t, "{}", cfg.get_default_host_triple()?)?,
+        }
     }
 
     // Print rustup home directory
@@ -1075,6 +2824,12 @@ fn show(cfg: &Cfg) -> Result<utils::ExitCode> {
         write!(t, "rustup home:  ")?;
         t.reset()?;
         writeln!(t, "{}", cfg.rustup_dir.display())?;
+        if cfg.home_profile != DEFAULT_HOME_PROFILE {
+            t.attr(term2::Attr::Bold)?;
+            write!(t, "home profile: ")?;
+            t.reset()?;
+            writeln!(t, "{}", cfg.home_profile)?;
+        }
         writeln!(t)?;
     }
 
@@ -1236,15 +2991,36 @@ fn show_active_toolchain(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCod
     Ok(utils::ExitCode(0))
 }
 
+fn home_profile_list(cfg: &Cfg) -> Result<utils::ExitCode> {
+    let active = cfg.active_home_profile()?;
+    for name in cfg.list_home_profiles()? {
+        if name == active {
+            writeln!(process().stdout(), "{} (active)", name)?;
+        } else {
+            writeln!(process().stdout(), "{}", name)?;
+        }
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn home_profile_switch(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let name = m.value_of("name").unwrap();
+    cfg.switch_home_profile(name)?;
+    info!("switched to profile '{}'", name);
+    Ok(utils::ExitCode(0))
+}
+
 fn show_rustup_home(cfg: &Cfg) -> Result<utils::ExitCode> {
     writeln!(process().stdout(), "{}", cfg.rustup_dir.display())?;
     Ok(utils::ExitCode(0))
 }
 
-fn target_list(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+fn target_list(cfg: &Cfg, m: &ArgMatches<'_>, json: bool) -> Result<utils::ExitCode> {
     let toolchain = explicit_or_dir_toolchain(cfg, m)?;
 
-    if m.is_present("installed") {
+    if json {
+        common::list_targets_json(&toolchain, m.is_present("installed"))
+    } else if m.is_present("installed") {
         common::list_installed_targets(&toolchain)
     } else {
         common::list_targets(&toolchain)
@@ -1290,129 +3066,516 @@ fn target_add(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
         }
     }
 
-    for target in &targets {
-        let new_component = Component::new(
-            "rust-std".to_string(),
-            Some(TargetTriple::new(target)),
-            false,
-        );
-        distributable.add_component(new_component)?;
+    let components = targets
+        .iter()
+        .map(|target| {
+            Component::new(
+                "rust-std".to_string(),
+                Some(TargetTriple::new(target)),
+                false,
+            )
+        })
+        .collect();
+    change_components(cfg, m, &toolchain, components, "added", |c| {
+        distributable.add_components(c)
+    })
+}
+
+fn target_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let toolchain = explicit_or_dir_toolchain(cfg, m)?;
+    let distributable = DistributableToolchain::new_for_components(&toolchain)?;
+
+    let components = m
+        .values_of("target")
+        .unwrap()
+        .map(|target| {
+            Component::new(
+                "rust-std".to_string(),
+                Some(TargetTriple::new(target)),
+                false,
+            )
+        })
+        .collect();
+    change_components(cfg, m, &toolchain, components, "removed", |c| {
+        distributable.remove_components(c)
+    })
+}
+
+/// Makes a change to all of `components` at once, so that it either
+/// happens to all of them or to none. With `--keep-going` it is made to
+/// each of them in turn instead, reporting any that fail and carrying on.
+fn change_components(
+    cfg: &Cfg,
+    m: &ArgMatches<'_>,
+    toolchain: &Toolchain<'_>,
+    components: Vec<Component>,
+    changed: &str,
+    change: impl Fn(Vec<Component>) -> Result<()>,
+) -> Result<utils::ExitCode> {
+    let before = history::installed_components(toolchain);
+    if !m.is_present("keep-going") {
+        change(components)?;
+        history::record_components(cfg, toolchain, before);
+        return Ok(utils::ExitCode(0));
     }
 
+    let total = components.len();
+    let mut failed = 0;
+    for component in components {
+        if let Err(e) = change(vec![component]) {
+            (cfg.notify_handler)(Notification::NonFatalError(&e));
+            failed += 1;
+        }
+    }
+    history::record_components(cfg, toolchain, before);
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of the {} requested could not be {}",
+            failed,
+            total,
+            changed
+        ));
+    }
     Ok(utils::ExitCode(0))
 }
 
-fn target_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+fn component_list(cfg: &Cfg, m: &ArgMatches<'_>, json: bool) -> Result<utils::ExitCode> {
     let toolchain = explicit_or_dir_toolchain(cfg, m)?;
 
-    for target in m.values_of("target").unwrap() {
-        let new_component = Component::new(
-            "rust-std".to_string(),
-            Some(TargetTriple::new(target)),
-            false,
-        );
-        let distributable = DistributableToolchain::new_for_components(&toolchain)?;
-        distributable.remove_component(new_component)?;
+    if json {
+        common::list_components_json(&toolchain, m.is_present("installed"))
+    } else if m.is_present("installed") {
+        common::list_installed_components(&toolchain)
+    } else {
+        common::list_components(&toolchain)?;
+        Ok(utils::ExitCode(0))
+    }
+}
+
+fn component_add(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let toolchain = explicit_or_dir_toolchain(cfg, m)?;
+    let distributable = DistributableToolchain::new_for_components(&toolchain)?;
+    let target = m
+        .value_of("target")
+        .map(TargetTriple::new)
+        .or_else(|| distributable.component_target().ok());
+
+    let components = m
+        .values_of("component")
+        .unwrap()
+        .map(|component| {
+            Component::new_with_target(component, false)
+                .unwrap_or_else(|| Component::new(component.to_string(), target.clone(), true))
+        })
+        .collect();
+    change_components(cfg, m, &toolchain, components, "added", |c| {
+        distributable.add_components(c)
+    })
+}
+
+fn component_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let toolchain = explicit_or_dir_toolchain(cfg, m)?;
+    let distributable = DistributableToolchain::new_for_components(&toolchain)?;
+    let target = m
+        .value_of("target")
+        .map(TargetTriple::new)
+        .or_else(|| distributable.component_target().ok());
+
+    let components = m
+        .values_of("component")
+        .unwrap()
+        .map(|component| {
+            Component::new_with_target(component, false)
+                .unwrap_or_else(|| Component::new(component.to_string(), target.clone(), true))
+        })
+        .collect();
+    change_components(cfg, m, &toolchain, components, "removed", |c| {
+        distributable.remove_components(c)
+    })
+}
+
+fn explicit_or_dir_toolchain<'a>(cfg: &'a Cfg, m: &ArgMatches<'_>) -> Result<Toolchain<'a>> {
+    let toolchain = m.value_of("toolchain");
+    if let Some(toolchain) = toolchain {
+        let toolchain = cfg.get_toolchain(toolchain, false)?;
+        return Ok(toolchain);
+    }
+
+    let cwd = utils::current_dir()?;
+    let (toolchain, _) = cfg.toolchain_for_dir(&cwd)?;
+
+    Ok(toolchain)
+}
+
+fn toolchain_list(cfg: &Cfg, m: &ArgMatches<'_>, json: bool) -> Result<utils::ExitCode> {
+    common::list_toolchains(cfg, m.is_present("verbose"), json)
+}
+
+fn toolchain_link(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let toolchain = m.value_of("toolchain").unwrap();
+    let path = m.value_of("path").unwrap();
+    let toolchain = cfg.get_toolchain_to_link(toolchain)?;
+
+    if let Ok(custom) = CustomToolchain::new(&toolchain) {
+        custom.install_from_dir(Path::new(path), true)?;
+        if let Some(source) = m.value_of("components-from") {
+            custom.set_component_source(source)?;
+        }
+        Ok(utils::ExitCode(0))
+    } else {
+        Err(anyhow!(
+            "invalid custom toolchain name: '{}'",
+            toolchain.name().to_string()
+        ))
+    }
+}
+
+fn toolchain_remove(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let names: Vec<String> = m
+        .values_of("toolchain")
+        .unwrap()
+        .map(String::from)
+        .collect();
+    if !common::confirm_removal(
+        "Uninstall these toolchains?",
+        &names,
+        "rustup toolchain uninstall",
+        true,
+        m.is_present("yes"),
+    )? {
+        return Ok(utils::ExitCode(0));
+    }
+    let purge = m.is_present("purge");
+    let mut freed = 0;
+    let mut removed = Vec::new();
+    for name in &names {
+        // Only the alias or virtual toolchain goes, not what it stands for.
+        if cfg.remove_toolchain_name(name)? {
+            info!(
+                "removed '{}', leaving the toolchain it names installed",
+                name
+            );
+            continue;
+        }
+        let toolchain = cfg.get_toolchain(name, false)?;
+        // A linked toolchain's files aren't rustup's, and stay where they are.
+        if purge && toolchain.exists() && !toolchain.is_custom() {
+            freed += utils::dir_size(toolchain.path())?;
+        }
+        let existed = toolchain.exists();
+        toolchain.remove()?;
+        if existed {
+            history::record(
+                cfg,
+                Operation::UninstallToolchain {
+                    toolchain: toolchain.name().to_owned(),
+                },
+            );
+        }
+        removed.push(toolchain.name().to_owned());
     }
 
+    if purge {
+        for dir in cfg.remove_overrides_for(&removed)? {
+            info!("override toolchain for '{}' removed", dir);
+        }
+        freed += cfg.purge_caches()?;
+        let freed = Size::new(freed as usize, Unit::B, UnitMode::Norm);
+        info!("reclaimed {}", freed.to_string().trim_start());
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn toolchain_gc(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let unused_for = parse_age(m.value_of("unused-for").unwrap())?;
+    let dry_run = m.is_present("dry-run");
+
+    // Toolchains that something still refers to are kept however long ago
+    // they were last used.
+    let mut in_use: Vec<String> = cfg.get_default()?.into_iter().collect();
+    in_use.extend(cfg.settings_file.with(|s| {
+        // And so are those virtual toolchains run their tools from.
+        let virtual_sources = s
+            .virtual_toolchains
+            .values()
+            .flat_map(|t| std::iter::once(&t.base).chain(t.tools.values()));
+        Ok(s.overrides
+            .values()
+            .chain(s.toolchain_aliases.values())
+            .chain(virtual_sources)
+            .cloned()
+            .collect::<Vec<_>>())
+    })?);
+    let in_use: HashSet<String> = in_use
+        .into_iter()
+        .map(|name| cfg.resolve_toolchain(&name).unwrap_or(name))
+        .collect();
+
+    let now = SystemTime::now();
+    let mut names = Vec::new();
+    let mut listed = Vec::new();
+    for name in cfg.list_toolchains()? {
+        let toolchain = cfg.get_toolchain(&name, false)?;
+        // Linked toolchains are not rustup's to remove.
+        if toolchain.is_custom() || in_use.contains(&name) {
+            continue;
+        }
+        let unused = now
+            .duration_since(toolchain.last_used()?)
+            .unwrap_or_default();
+        if unused >= unused_for {
+            listed.push(format!(
+                "{} (unused for {} days)",
+                name,
+                unused.as_secs() / 86400
+            ));
+            names.push(name);
+        }
+    }
+    if names.is_empty() {
+        info!("no toolchains have gone unused for that long");
+        return Ok(utils::ExitCode(0));
+    }
+    if dry_run {
+        let mut out = process().stdout();
+        for line in &listed {
+            writeln!(out, "{}", line)?;
+        }
+        return Ok(utils::ExitCode(0));
+    }
+    let command = format!(
+        "rustup toolchain gc --unused-for {}",
+        m.value_of("unused-for").unwrap()
+    );
+    if !common::confirm_removal(
+        "Uninstall these toolchains?",
+        &listed,
+        &command,
+        true,
+        m.is_present("yes"),
+    )? {
+        return Ok(utils::ExitCode(0));
+    }
+    for name in &names {
+        cfg.get_toolchain(name, false)?.remove()?;
+    }
     Ok(utils::ExitCode(0))
 }
 
-fn component_list(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
-    let toolchain = explicit_or_dir_toolchain(cfg, m)?;
+fn toolchain_alias(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let alias = match m.value_of("alias") {
+        Some(alias) => alias,
+        None => {
+            let aliases = cfg
+                .settings_file
+                .with(|s| Ok(s.toolchain_aliases.clone()))?;
+            if aliases.is_empty() {
+                writeln!(process().stdout(), "no toolchain aliases")?;
+            }
+            for (alias, toolchain) in aliases {
+                writeln!(process().stdout(), "{} -> {}", alias, toolchain)?;
+            }
+            return Ok(utils::ExitCode(0));
+        }
+    };
 
-    if m.is_present("installed") {
-        common::list_installed_components(&toolchain)
+    if m.is_present("remove") {
+        if !cfg.remove_alias(alias)? {
+            return Err(anyhow!("no toolchain alias named '{}'", alias));
+        }
+        info!("removed toolchain alias '{}'", alias);
+    } else if let Some(toolchain) = m.value_of("toolchain") {
+        cfg.set_alias(alias, toolchain)?;
+        info!("'{}' is now an alias for '{}'", alias, toolchain);
     } else {
-        common::list_components(&toolchain)?;
-        Ok(utils::ExitCode(0))
+        match cfg.get_alias(alias)? {
+            Some(toolchain) => writeln!(process().stdout(), "{}", toolchain)?,
+            None => return Err(anyhow!("no toolchain alias named '{}'", alias)),
+        }
     }
+    Ok(utils::ExitCode(0))
 }
 
-fn component_add(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
-    let toolchain = explicit_or_dir_toolchain(cfg, m)?;
-    let distributable = DistributableToolchain::new(&toolchain)?;
-    let target = m.value_of("target").map(TargetTriple::new).or_else(|| {
-        distributable
-            .desc()
-            .as_ref()
-            .ok()
-            .map(|desc| desc.target.clone())
-    });
+fn toolchain_virtual(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let describe = |toolchain: &VirtualToolchain| {
+        let mut description = toolchain.base.clone();
+        for (tool, source) in &toolchain.tools {
+            description.push_str(&format!(", {} from {}", tool, source));
+        }
+        description
+    };
+    let name = match m.value_of("name") {
+        Some(name) => name,
+        None => {
+            let toolchains = cfg
+                .settings_file
+                .with(|s| Ok(s.virtual_toolchains.clone()))?;
+            if toolchains.is_empty() {
+                writeln!(process().stdout(), "no virtual toolchains")?;
+            }
+            for (name, toolchain) in toolchains {
+                writeln!(process().stdout(), "{} -> {}", name, describe(&toolchain))?;
+            }
+            return Ok(utils::ExitCode(0));
+        }
+    };
 
-    for component in m.values_of("component").unwrap() {
-        let new_component = Component::new_with_target(component, false)
-            .unwrap_or_else(|| Component::new(component.to_string(), target.clone(), true));
-        distributable.add_component(new_component)?;
+    if m.is_present("remove") {
+        if !cfg.remove_virtual_toolchain(name)? {
+            return Err(anyhow!("no virtual toolchain named '{}'", name));
+        }
+        info!("removed virtual toolchain '{}'", name);
+    } else if let Some(base) = m.value_of("base") {
+        let mut tools = BTreeMap::new();
+        for tool in m.values_of("tools").into_iter().flatten() {
+            let (tool, source) = match tool.find('=') {
+                Some(i) if i > 0 && i + 1 < tool.len() => (&tool[..i], &tool[i + 1..]),
+                _ => return Err(anyhow!("expected TOOL=TOOLCHAIN, found '{}'", tool)),
+            };
+            tools.insert(tool.to_owned(), source.to_owned());
+        }
+        let toolchain = VirtualToolchain {
+            base: base.to_owned(),
+            tools,
+        };
+        info!("'{}' is now {}", name, describe(&toolchain));
+        cfg.set_virtual_toolchain(name, toolchain)?;
+    } else {
+        match cfg.get_virtual_toolchain(name)? {
+            Some(toolchain) => writeln!(process().stdout(), "{}", describe(&toolchain))?,
+            None => return Err(anyhow!("no virtual toolchain named '{}'", name)),
+        }
     }
-
     Ok(utils::ExitCode(0))
 }
 
-fn component_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
-    let toolchain = explicit_or_dir_toolchain(cfg, m)?;
-    let distributable = DistributableToolchain::new_for_components(&toolchain)?;
-    let target = m.value_of("target").map(TargetTriple::new).or_else(|| {
-        distributable
-            .desc()
-            .as_ref()
-            .ok()
-            .map(|desc| desc.target.clone())
-    });
+fn toolchain_env(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").unwrap(), false)?;
+    let name = toolchain.name();
 
-    for component in m.values_of("component").unwrap() {
-        let new_component = Component::new_with_target(component, false)
-            .unwrap_or_else(|| Component::new(component.to_string(), target.clone(), true));
-        distributable.remove_component(new_component)?;
+    let vars = m.values_of("vars").into_iter().flatten();
+    let unset = m.values_of("unset").into_iter().flatten();
+    if vars.clone().next().is_none() && unset.clone().next().is_none() {
+        for (key, value) in cfg.get_toolchain_env(name)? {
+            writeln!(process().stdout(), "{}={}", key, value)?;
+        }
+        return Ok(utils::ExitCode(0));
     }
 
+    for var in vars {
+        let (key, value) = match var.find('=') {
+            Some(i) => (&var[..i], &var[i + 1..]),
+            None => return Err(anyhow!("expected KEY=VALUE, found '{}'", var)),
+        };
+        cfg.set_toolchain_env(name, key, value)?;
+        info!("'{}' will be set for '{}'", key, name);
+    }
+    for key in unset {
+        if cfg.unset_toolchain_env(name, key)? {
+            info!("'{}' will no longer be set for '{}'", key, name);
+        } else {
+            warn!("'{}' was not set for '{}'", key, name);
+        }
+    }
     Ok(utils::ExitCode(0))
 }
 
-fn explicit_or_dir_toolchain<'a>(cfg: &'a Cfg, m: &ArgMatches<'_>) -> Result<Toolchain<'a>> {
-    let toolchain = m.value_of("toolchain");
-    if let Some(toolchain) = toolchain {
-        let toolchain = cfg.get_toolchain(toolchain, false)?;
-        return Ok(toolchain);
+fn toolchain_export(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").unwrap(), false)?;
+    if toolchain.is_custom() {
+        return Err(anyhow!(
+            "'{}' is a custom toolchain; only toolchains installed by rustup can be exported",
+            toolchain.name()
+        ));
     }
-
-    let cwd = utils::current_dir()?;
-    let (toolchain, _) = cfg.toolchain_for_dir(&cwd)?;
-
-    Ok(toolchain)
+    let distributable = DistributableToolchain::new(&toolchain)?;
+    let output = match m.value_of("output") {
+        Some(output) => PathBuf::from(output),
+        None => PathBuf::from(format!("{}.tar.zst", toolchain.name())),
+    };
+    let output = utils::current_dir()?.join(output);
+    distributable.export(&output)?;
+    info!("exported '{}' to '{}'", toolchain.name(), output.display());
+    Ok(utils::ExitCode(0))
 }
 
-fn toolchain_list(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
-    common::list_toolchains(cfg, m.is_present("verbose"))
+fn toolchain_import(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let archive = utils::current_dir()?.join(m.value_of("archive").unwrap());
+    // A name of one's own is taken as `toolchain link` takes it.
+    let toolchain = match m.value_of("name") {
+        Some(name) => cfg.get_toolchain_to_link(name)?,
+        None => cfg.get_toolchain(&toolchain_archive::read_metadata(&archive)?.name, true)?,
+    };
+    match CustomToolchain::new(&toolchain) {
+        Ok(custom) => custom.install_from_archive(&archive)?,
+        Err(_) => DistributableToolchain::new(&toolchain)?.install_from_archive(&archive)?,
+    }
+    info!(
+        "installed '{}' from '{}'",
+        toolchain.name(),
+        archive.display()
+    );
+    Ok(utils::ExitCode(0))
 }
 
-fn toolchain_link(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
-    let toolchain = m.value_of("toolchain").unwrap();
-    let path = m.value_of("path").unwrap();
-    let toolchain = cfg.get_toolchain(toolchain, true)?;
-
-    if let Ok(custom) = CustomToolchain::new(&toolchain) {
-        custom.install_from_dir(Path::new(path), true)?;
-        Ok(utils::ExitCode(0))
-    } else {
-        Err(anyhow!(
-            "invalid custom toolchain name: '{}'",
-            toolchain.name().to_string()
-        ))
+fn toolchain_clone(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let src = cfg.get_toolchain(m.value_of("toolchain").unwrap(), false)?;
+    let name = m.value_of("name").unwrap();
+    let toolchain = cfg.get_toolchain(name, true)?;
+    let custom = CustomToolchain::new(&toolchain)
+        .map_err(|_| anyhow!("invalid custom toolchain name: '{}'", name))?;
+    if toolchain.exists() {
+        return Err(anyhow!("toolchain '{}' is already installed", name));
     }
+    custom.install_from_toolchain(&src, !m.is_present("copy"))?;
+    Ok(utils::ExitCode(0))
 }
 
-fn toolchain_remove(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
-    for toolchain in m.values_of("toolchain").unwrap() {
-        let toolchain = cfg.get_toolchain(toolchain, false)?;
-        toolchain.remove()?;
+fn toolchain_repair(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    for name in m.values_of("toolchain").unwrap() {
+        let toolchain = cfg.get_toolchain(name, false)?;
+        if !toolchain.exists() {
+            return Err(RustupError::ToolchainNotInstalled(name.to_string()).into());
+        }
+        let distributable = DistributableToolchain::new(&toolchain)
+            .map_err(|_| anyhow!("toolchain '{}' is linked and can't be repaired", name))?;
+        distributable.repair()?;
     }
     Ok(utils::ExitCode(0))
 }
 
+/// Parses a length of time such as `90d`, in seconds (`s`), minutes (`m`),
+/// hours (`h`), days (`d`) or weeks (`w`).
+fn parse_age(age: &str) -> Result<Duration> {
+    let (number, unit) = match age.char_indices().last() {
+        Some((i, unit)) => (&age[..i], unit),
+        None => ("", ' '),
+    };
+    let unit = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => 0,
+    };
+    match number.parse::<u64>().ok().and_then(|n| n.checked_mul(unit)) {
+        Some(secs) if unit != 0 => Ok(Duration::from_secs(secs)),
+        _ => Err(anyhow!(
+            "invalid length of time '{}': expected a number followed by 's', 'm', 'h', 'd' or 'w', such as '90d'",
+            age
+        )),
+    }
+}
+
 fn override_add(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let path = if let Some(path) = m.value_of("path") {
+        PathBuf::from(path)
+    } else {
+        utils::current_dir()?
+    };
     let toolchain = m.value_of("toolchain").unwrap();
-    let toolchain = cfg.get_toolchain(toolchain, false)?;
+    let toolchain = cfg.get_toolchain_in(toolchain, &utils::current_dir()?.join(&path))?;
 
     let status = if !toolchain.is_custom() {
         let distributable = DistributableToolchain::new(&toolchain)?;
@@ -1423,18 +3586,32 @@ fn override_add(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
         None
     };
 
-    let path = if let Some(path) = m.value_of("path") {
-        PathBuf::from(path)
-    } else {
-        utils::current_dir()?
-    };
+    let previous = cfg
+        .settings_file
+        .with(|s| Ok(s.dir_override(&path, cfg.notify_handler.as_ref())))?;
     toolchain.make_override(&path)?;
 
+    if let Some(status) = &status {
+        history::record_update(cfg, toolchain.name(), status);
+    }
+    history::record(
+        cfg,
+        Operation::SetOverride {
+            path: utils::current_dir()?.join(&path),
+            toolchain: toolchain.requested_name().to_owned(),
+            previous,
+        },
+    );
+
     if let Some(status) = status {
         writeln!(process().stdout(),)?;
         common::show_channel_update(cfg, toolchain.name(), Ok(status))?;
     }
 
+    if cfg.get_shims()? == ShimMode::Symlink {
+        warn!("the tools in CARGO_HOME/bin are symlinks to the default toolchain, and don't follow overrides; see `rustup set shims --help`");
+    }
+
     Ok(utils::ExitCode(0))
 }
 
@@ -1452,8 +3629,40 @@ fn override_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
                 })
                 .collect())
         })?;
-        if list.is_empty() {
+        let hosts: Vec<_> = cfg.settings_file.with(|s| {
+            Ok(s.host_overrides
+                .keys()
+                .filter(|k| !Path::new(k).is_dir())
+                .cloned()
+                .collect())
+        })?;
+        if list.is_empty() && hosts.is_empty() {
             info!("no nonexistent paths detected");
+            return Ok(utils::ExitCode(0));
+        }
+        let mut dirs: Vec<_> = list.iter().chain(&hosts).cloned().collect();
+        dirs.sort();
+        dirs.dedup();
+        if !common::confirm_removal(
+            "Remove the overrides for these directories?",
+            &dirs,
+            "rustup override unset --nonexistent",
+            true,
+            m.is_present("yes"),
+        )? {
+            return Ok(utils::ExitCode(0));
+        }
+        if !hosts.is_empty() {
+            cfg.settings_file.with_mut(|s| {
+                for dir in &hosts {
+                    s.host_overrides.remove(dir);
+                }
+                Ok(())
+            })?;
+            info!(
+                "removed {} host overrides for nonexistent paths",
+                hosts.len()
+            );
         }
         list
     } else if m.is_present("path") {
@@ -1463,10 +3672,22 @@ fn override_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
     };
 
     for path in paths {
+        let toolchain = cfg
+            .settings_file
+            .with(|s| Ok(s.dir_override(Path::new(&path), cfg.notify_handler.as_ref())))?;
         if cfg
             .settings_file
             .with_mut(|s| Ok(s.remove_override(&Path::new(&path), cfg.notify_handler.as_ref())))?
         {
+            if let Some(toolchain) = toolchain {
+                history::record(
+                    cfg,
+                    Operation::RemoveOverride {
+                        path: utils::current_dir()?.join(&path),
+                        toolchain,
+                    },
+                );
+            }
             info!("override toolchain for '{}' removed", path);
         } else {
             info!("no override toolchain for '{}'", path);
@@ -1481,6 +3702,226 @@ fn override_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
     Ok(utils::ExitCode(0))
 }
 
+fn override_prune(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let stale = common::stale_overrides(cfg)?;
+    if stale.is_empty() {
+        info!("no stale overrides");
+        return Ok(utils::ExitCode(0));
+    }
+    for (dir, value, reason) in &stale {
+        writeln!(
+            process().stdout(),
+            "{:<40}\t{} ({})",
+            utils::format_path_for_display(dir),
+            value,
+            reason
+        )?;
+    }
+    if !common::confirm_removal(
+        "Remove these overrides?",
+        &[],
+        "rustup override prune",
+        false,
+        m.is_present("yes"),
+    )? {
+        return Ok(utils::ExitCode(0));
+    }
+    cfg.settings_file.with_mut(|s| {
+        for (dir, value, _) in &stale {
+            if value.starts_with("host ") {
+                s.host_overrides.remove(dir);
+            } else {
+                s.overrides.remove(dir);
+            }
+        }
+        Ok(())
+    })?;
+    info!("removed {} stale overrides", stale.len());
+    Ok(utils::ExitCode(0))
+}
+
+fn override_explain(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let cwd = utils::current_dir()?;
+    let path = match m.value_of("path") {
+        Some(path) => cwd.join(path),
+        None => cwd,
+    };
+
+    let candidates = cfg.override_candidates(&path)?;
+    if candidates.is_empty() {
+        writeln!(
+            process().stdout(),
+            "no toolchain is chosen for '{}'",
+            path.display()
+        )?;
+        return Ok(utils::ExitCode(0));
+    }
+
+    // The first candidate wins, along with those it inherits from.
+    let mut used = true;
+    for (i, candidate) in candidates.iter().enumerate() {
+        let status = match (used, i) {
+            (true, 0) => "active",
+            (true, _) => "inherited",
+            (false, _) => "ignored",
+        };
+        writeln!(
+            process().stdout(),
+            "{}. {} ({})",
+            i + 1,
+            candidate.source,
+            status
+        )?;
+        for setting in &candidate.settings {
+            writeln!(process().stdout(), "     {}", setting)?;
+        }
+        used = used && candidate.inherit;
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn trust_list(cfg: &Cfg) -> Result<utils::ExitCode> {
+    let dirs = cfg.list_trusted_dirs()?;
+    if dirs.is_empty() {
+        info!("no directories are trusted");
+    }
+    for dir in dirs {
+        writeln!(process().stdout(), "{}", dir)?;
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn trust_path(m: &ArgMatches<'_>) -> Result<PathBuf> {
+    let path = m.value_of("path").unwrap();
+    Ok(if path == "*" {
+        PathBuf::from(path)
+    } else {
+        utils::current_dir()?.join(path)
+    })
+}
+
+fn trust_add(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let path = trust_path(m)?;
+    if cfg.trust_dir(&path)? {
+        info!("trusting '{}'", path.display());
+    } else {
+        info!("'{}' is already trusted", path.display());
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn trust_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let path = trust_path(m)?;
+    if !cfg.untrust_dir(&path)? {
+        info!("'{}' was not trusted", path.display());
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn proxy_list(cfg: &Cfg) -> Result<utils::ExitCode> {
+    let tools = cfg.list_proxies()?;
+    if tools.is_empty() {
+        info!("no tools besides the built-in ones have proxies");
+    }
+    for tool in tools {
+        writeln!(process().stdout(), "{}", tool)?;
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn proxy_add(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    for tool in m.values_of("tool").unwrap() {
+        let added = cfg.add_proxy(tool)?;
+        if let Err(e) = self_update::install_proxy(tool) {
+            if added {
+                cfg.remove_proxy(tool)?;
+            }
+            return Err(e);
+        }
+        if added {
+            info!("added a proxy for '{}'", tool);
+        } else {
+            info!("'{}' already has a proxy", tool);
+        }
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn proxy_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    for tool in m.values_of("tool").unwrap() {
+        if cfg.remove_proxy(tool)? {
+            self_update::remove_proxy(tool)?;
+            info!("removed the proxy for '{}'", tool);
+        } else {
+            info!("'{}' has no proxy", tool);
+        }
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn wsl_shims_dir(m: &ArgMatches<'_>) -> Result<PathBuf> {
+    match m.value_of("dir") {
+        Some(dir) => Ok(utils::current_dir()?.join(dir)),
+        None => Ok(utils::cargo_home()?.join("wsl-bin")),
+    }
+}
+
+fn toolchain_file_check(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let path = utils::current_dir()?.join(m.value_of("path").unwrap());
+    let (file, problems) = cfg.check_toolchain_file(&path)?;
+    if problems.is_empty() {
+        writeln!(process().stdout(), "'{}': ok", file.display())?;
+        return Ok(utils::ExitCode(0));
+    }
+    for problem in &problems {
+        err!("'{}': {}", file.display(), problem);
+    }
+    Ok(utils::ExitCode(1))
+}
+
+fn override_host(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    if let Some(toolchain) = m.value_of("toolchain") {
+        if m.is_present("unset") {
+            if cfg.remove_toolchain_host_override(toolchain)? {
+                info!("host override for toolchain '{}' removed", toolchain);
+            } else {
+                info!("no host override for toolchain '{}'", toolchain);
+            }
+        } else {
+            let host_triple = m.value_of("host_triple").unwrap();
+            cfg.set_toolchain_host_override(toolchain, host_triple)?;
+            info!(
+                "host triple for toolchain '{}' set to '{}'",
+                toolchain, host_triple
+            );
+        }
+        return Ok(utils::ExitCode(0));
+    }
+
+    let path = if let Some(path) = m.value_of("path") {
+        PathBuf::from(path)
+    } else {
+        utils::current_dir()?
+    };
+
+    if m.is_present("unset") {
+        if cfg.remove_host_override(&path)? {
+            info!("host override for '{}' removed", path.display());
+        } else {
+            info!("no host override for '{}'", path.display());
+        }
+    } else {
+        let host_triple = m.value_of("host_triple").unwrap();
+        cfg.set_host_override(&path, host_triple)?;
+        info!(
+            "host triple for '{}' set to '{}'",
+            path.display(),
+            host_triple
+        );
+    }
+    Ok(utils::ExitCode(0))
+}
+
 const DOCS_DATA: &[(&str, &str, &str,)] = &[
     // flags can be used to open specific documents, e.g. `rustup doc --nomicon`
     // tuple elements: document name used as flag, help message, document index path
@@ -1571,9 +4012,9 @@ fn man(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
 }
 
 fn self_uninstall(m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
-    let no_prompt = m.is_present("no-prompt");
+    let no_prompt = m.is_present("yes");
 
-    self_update::uninstall(no_prompt)
+    self_update::uninstall(no_prompt, m.is_present("purge"))
 }
 
 fn set_default_host_triple(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
@@ -1600,6 +4041,123 @@ fn set_auto_self_update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::Exit
     Ok(utils::ExitCode(0))
 }
 
+fn set_ip_version(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    cfg.set_ip_version(m.value_of("ip-version").unwrap())?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_auto_install_targets(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    cfg.set_auto_install_targets(m.value_of("auto-install-targets-mode").unwrap())?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_usage_stats(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    cfg.set_usage_stats(m.value_of("usage-stats-mode").unwrap())?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_auto_install_components(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    cfg.set_auto_install_components(m.value_of("auto-install-components-mode").unwrap())?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_toolchain_file_search(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    cfg.set_toolchain_file_search(m.value_of("toolchain-file-search-mode").unwrap())?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_rustc_wrapper(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let wrapper = m.value_of("wrapper");
+    match m.value_of("toolchain") {
+        Some(toolchain) => {
+            let toolchain = cfg.get_toolchain(toolchain, false)?;
+            let name = toolchain.name();
+            // `none` keeps the wrapper for every toolchain away from this one.
+            let wrapper = wrapper.map(|w| if w == "none" { "" } else { w });
+            cfg.set_rustc_wrapper(Some(name), wrapper)?;
+            match wrapper {
+                Some("") => info!("rustc from '{}' will not be run through a wrapper", name),
+                Some(w) => info!("rustc from '{}' will be run through '{}'", name, w),
+                None => info!(
+                    "rustc from '{}' will be run through the wrapper for every toolchain",
+                    name
+                ),
+            }
+        }
+        None => {
+            cfg.set_rustc_wrapper(None, wrapper)?;
+            match wrapper {
+                Some(w) => info!("rustc will be run through '{}'", w),
+                None => info!("rustc will no longer be run through a wrapper"),
+            }
+        }
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn set_shims(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    cfg.set_shims(m.value_of("shims-mode").unwrap())?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_color_theme(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    cfg.set_color_theme(m.value_of("color-theme").unwrap())?;
+    Ok(utils::ExitCode(0))
+}
+
+fn set_default_components(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let list: Vec<_> = m.values_of("component").into_iter().flatten().collect();
+    let components = parse_name_list(&list.join(" "), "component")?;
+    cfg.set_default_components(&components)?;
+    if components.is_empty() {
+        info!("new toolchains get only the components of the profile");
+    } else {
+        info!("new toolchains also get {}", components.join(", "));
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn set_default_targets(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let list: Vec<_> = m.values_of("target").into_iter().flatten().collect();
+    let targets = parse_name_list(&list.join(" "), "target")?;
+    cfg.set_default_targets(&targets)?;
+    if targets.is_empty() {
+        info!("new toolchains get only the host's target");
+    } else {
+        info!("new toolchains also get {}", targets.join(", "));
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn set_toolchain_file_env(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let vars: Vec<_> = m.values_of("var").into_iter().flatten().collect();
+    cfg.set_toolchain_file_env(&vars)?;
+    if vars.is_empty() {
+        info!("toolchain files may only use the default environment variables");
+    } else {
+        info!("toolchain files may also use {}", vars.join(", "));
+    }
+    Ok(utils::ExitCode(0))
+}
+
+fn set_override_hook(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<utils::ExitCode> {
+    let command: Vec<_> = m.values_of("command").into_iter().flatten().collect();
+    let ttl = match m.value_of("ttl") {
+        Some(ttl) => Some(
+            ttl.parse()
+                .map_err(|_| anyhow!("invalid number of seconds '{}'", ttl))?,
+        ),
+        None => None,
+    };
+    cfg.set_override_hook(&command, ttl)?;
+    if command.is_empty() {
+        info!("override hook removed");
+    } else {
+        info!("override hook set to '{}'", command.join(" "));
+    }
+    Ok(utils::ExitCode(0))
+}
+
 fn show_profile(cfg: &Cfg) -> Result<utils::ExitCode> {
     writeln!(process().stdout(), "{}", cfg.get_profile()?)?;
     Ok(utils::ExitCode(0))
@@ -1666,7 +4224,14 @@ impl fmt::Display for CompletionCommand {
 fn output_completion_script(shell: Shell, command: CompletionCommand) -> Result<utils::ExitCode> {
     match command {
         CompletionCommand::Rustup => {
-            cli().gen_completions_to("rustup", shell, &mut term2::stdout());
+            let mut script = Vec::new();
+            cli().gen_completions_to("rustup", shell, &mut script);
+            let script = String::from_utf8_lossy(&script);
+            write!(
+                &mut term2::stdout(),
+                "{}",
+                completions::add_hook(shell, &script)
+            )?;
         }
         CompletionCommand::Cargo => {
             if let Shell::Zsh = shell {
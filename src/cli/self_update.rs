@@ -20,6 +20,9 @@
 //!   this is a self-upgrade
 //! * rustup-init copies bins and hardlinks into place. On windows
 //!   this happens *after* the upgrade command exits successfully.
+//!   Each file is replaced by moving it aside and its replacement in,
+//!   with a journal so that an interrupted update is finished or
+//!   undone rather than leaving proxies missing (see `journal`).
 //!
 //! During uninstall (`rustup self uninstall`):
 //!
@@ -30,6 +33,7 @@
 //! Deleting the running binary during uninstall is tricky
 //! and racy on Windows.
 
+mod journal;
 #[cfg(unix)]
 mod shell;
 pub mod test;
@@ -55,22 +59,32 @@ use std::str::FromStr;
 
 use anyhow::{anyhow, Context, Result};
 use cfg_if::cfg_if;
+use pgp::{Deserializable, SignedPublicKey};
 use same_file::Handle;
+use sha2::{Digest, Sha256};
 
 use super::common::{self, ignorable_error, Confirm};
 use super::errors::*;
 use super::markdown::md;
 use super::term2;
 use crate::cli::term2::Terminal;
+use crate::config::PgpPublicKey;
 use crate::dist::dist::{self, Profile, TargetTriple};
+use crate::dist::signatures;
+use crate::errors::RustupError;
 use crate::process;
+use crate::settings::ShimMode;
 use crate::toolchain::{DistributableToolchain, Toolchain};
+use crate::utils::binary_patch;
+use crate::utils::units::{Size, Unit, UnitMode};
 use crate::utils::utils;
 use crate::utils::Notification;
 use crate::{Cfg, UpdateStatus};
 use crate::{DUP_TOOLS, TOOLS};
 use os::*;
-pub(crate) use os::{delete_rustup_and_cargo_home, run_update, self_replace};
+pub(crate) use os::{
+    delete_rustup_and_cargo_home, do_remove_rustup_home_exports, run_update, self_replace,
+};
 #[cfg(windows)]
 pub use windows::complete_windows_uninstall;
 
@@ -674,17 +688,94 @@ fn install_bins() -> Result<()> {
     let rustup_path = bin_path.join(&format!("rustup{}", EXE_SUFFIX));
 
     utils::ensure_dir_exists("bin", &bin_path, &|_: Notification<'_>| {})?;
+    // Tools given proxies with `rustup proxy add` are links to the old
+    // rustup, to be moved over to the new one with the rest.
+    let custom = custom_proxies(&bin_path, &rustup_path);
     // NB: Even on Linux we can't just copy the new binary over the (running)
-    // old binary; we must unlink it first.
-    if rustup_path.exists() {
-        utils::remove_file("rustup-bin", &rustup_path)?;
+    // old binary; it is moved aside first.
+    journal::replace(
+        &bin_path,
+        &[(rustup_path, journal::Source::Copy(&this_exe_path))],
+    )?;
+    link_proxies(&custom)
+}
+
+/// The tools in `bin_path` besides the built-in ones which are links to
+/// `rustup_path`.
+fn custom_proxies(bin_path: &Path, rustup_path: &Path) -> Vec<String> {
+    let rustup = match Handle::from_path(rustup_path) {
+        Ok(rustup) => rustup,
+        Err(_) => return Vec::new(),
+    };
+    let entries = match fs::read_dir(bin_path) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let tool = name.strip_suffix(EXE_SUFFIX).unwrap_or(&name).to_owned();
+            if tool.starts_with("rustup")
+                || tool.contains('.')
+                || TOOLS.iter().chain(DUP_TOOLS).any(|t| *t == tool)
+            {
+                return None;
+            }
+            match Handle::from_path(entry.path()) {
+                Ok(handle) if handle == rustup => Some(tool),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Gives `tool` a proxy in CARGO_HOME/bin, like the built-in ones.
+pub(crate) fn install_proxy(tool: &str) -> Result<()> {
+    let bin_path = utils::cargo_home()?.join("bin");
+    let rustup_path = bin_path.join(&format!("rustup{}", EXE_SUFFIX));
+    let tool_path = bin_path.join(&format!("{}{}", tool, EXE_SUFFIX));
+    let rustup = Handle::from_path(&rustup_path).map_err(|_| CLIError::NotSelfInstalled {
+        p: utils::cargo_home().unwrap_or_default(),
+    })?;
+    match Handle::from_path(&tool_path) {
+        Ok(handle) if handle == rustup => Ok(()),
+        Ok(_) => Err(anyhow!(
+            "'{}' is already installed, and isn't a rustup proxy; remove it to give '{}' a proxy",
+            tool_path.display(),
+            tool
+        )),
+        Err(_) => utils::hard_or_symlink_file(&rustup_path, &tool_path),
     }
-    utils::copy_file(&this_exe_path, &rustup_path)?;
-    utils::make_executable(&rustup_path)?;
-    install_proxies()
+}
+
+/// Removes the proxy for `tool` from CARGO_HOME/bin, leaving anything
+/// else of that name alone.
+pub(crate) fn remove_proxy(tool: &str) -> Result<()> {
+    let bin_path = utils::cargo_home()?.join("bin");
+    let rustup_path = bin_path.join(&format!("rustup{}", EXE_SUFFIX));
+    let tool_path = bin_path.join(&format!("{}{}", tool, EXE_SUFFIX));
+    if custom_proxies(&bin_path, &rustup_path)
+        .iter()
+        .any(|t| t == tool)
+    {
+        utils::remove_file("proxy", &tool_path)?;
+    }
+    Ok(())
+}
+
+/// Finishes or undoes replacing rustup and its proxies if an earlier run
+/// was stopped part way through.
+pub(crate) fn recover_replaced_bins() -> Result<()> {
+    journal::recover(&utils::cargo_home()?.join("bin"))
 }
 
 pub fn install_proxies() -> Result<()> {
+    link_proxies(&[])
+}
+
+/// Links the built-in tools and `custom` ones in CARGO_HOME/bin to rustup.
+fn link_proxies(custom: &[String]) -> Result<()> {
     let bin_path = utils::cargo_home()?.join("bin");
     let rustup_path = bin_path.join(&format!("rustup{}", EXE_SUFFIX));
 
@@ -714,8 +805,16 @@ pub fn install_proxies() -> Result<()> {
     // actually be on Windows). As a result we manually drop all the
     // `tool_handles` later on. This'll allow us, afterwards, to actually
     // overwrite all the previous hard links with new ones.
-    for tool in TOOLS {
+    for tool in TOOLS
+        .iter()
+        .copied()
+        .chain(custom.iter().map(String::as_str))
+    {
         let tool_path = bin_path.join(&format!("{}{}", tool, EXE_SUFFIX));
+        // Symlink shims are kept up to date by `refresh_shims` instead.
+        if is_symlink_shim(&tool_path) {
+            continue;
+        }
         if let Ok(handle) = Handle::from_path(&tool_path) {
             tool_handles.push(handle);
             if rustup == *tool_handles.last().unwrap() {
@@ -727,6 +826,9 @@ pub fn install_proxies() -> Result<()> {
 
     for tool in DUP_TOOLS {
         let tool_path = bin_path.join(&format!("{}{}", tool, EXE_SUFFIX));
+        if is_symlink_shim(&tool_path) {
+            continue;
+        }
         if let Ok(handle) = Handle::from_path(&tool_path) {
             // Like above, don't clobber anything that's already hardlinked to
             // avoid extraneous errors from being returned.
@@ -752,12 +854,83 @@ pub fn install_proxies() -> Result<()> {
                 continue;
             }
         }
-        utils::hard_or_symlink_file(&rustup_path, &tool_path)?;
+        link_afterwards.push(tool_path);
     }
 
     drop(tool_handles);
-    for path in link_afterwards {
-        utils::hard_or_symlink_file(&rustup_path, &path)?;
+    // Every proxy is replaced, or none are.
+    let files: Vec<_> = link_afterwards
+        .into_iter()
+        .map(|path| (path, journal::Source::Link(&rustup_path)))
+        .collect();
+    journal::replace(&bin_path, &files)
+}
+
+/// Whether `path` is a symlink made by `refresh_shims`, to the binary of
+/// the same name in some toolchain's `bin` directory.
+fn is_symlink_shim(path: &Path) -> bool {
+    let target = match fs::read_link(path) {
+        Ok(target) => target,
+        Err(_) => return false,
+    };
+    let toolchains = target
+        .parent()
+        .filter(|bin| bin.file_name() == Some("bin".as_ref()))
+        .and_then(Path::parent)
+        .and_then(Path::parent);
+    target.file_name() == path.file_name()
+        && matches!(toolchains, Some(dir) if dir.file_name() == Some("toolchains".as_ref()))
+}
+
+/// Brings the tools in `CARGO_HOME/bin` in line with the shims setting:
+/// symlinks straight to the default toolchain's binaries, where it has
+/// them, or links to rustup. Anything rustup didn't put there is left
+/// alone.
+pub(crate) fn refresh_shims(cfg: &Cfg) -> Result<()> {
+    let bin_path = utils::cargo_home()?.join("bin");
+    let rustup_path = bin_path.join(&format!("rustup{}", EXE_SUFFIX));
+    let rustup = match Handle::from_path(&rustup_path) {
+        Ok(rustup) => rustup,
+        // Not installed by rustup-init, so there are no proxies to replace.
+        Err(_) => return Ok(()),
+    };
+    let default = match cfg.get_shims()? {
+        ShimMode::Symlink => cfg.find_default()?.filter(Toolchain::exists),
+        ShimMode::Proxy => None,
+    };
+
+    for tool in TOOLS.iter().chain(DUP_TOOLS) {
+        let tool_path = bin_path.join(&format!("{}{}", tool, EXE_SUFFIX));
+        let is_shim = is_symlink_shim(&tool_path);
+        let ours = is_shim
+            || fs::symlink_metadata(&tool_path).is_err()
+            || matches!(Handle::from_path(&tool_path), Ok(handle) if handle == rustup);
+        if !ours {
+            continue;
+        }
+
+        let target = match &default {
+            Some(toolchain) => {
+                let for_tool = toolchain.for_tool(tool)?;
+                let toolchain = for_tool.as_ref().unwrap_or(toolchain);
+                Some(toolchain.binary_file(tool)).filter(|path| utils::is_file(path))
+            }
+            None => None,
+        };
+        match target {
+            Some(target) => {
+                if fs::read_link(&tool_path).ok().as_ref() == Some(&target) {
+                    continue;
+                }
+                utils::ensure_file_removed("shim", &tool_path)?;
+                utils::symlink_file(&target, &tool_path)?;
+            }
+            None if is_shim => {
+                utils::ensure_file_removed("shim", &tool_path)?;
+                utils::hard_or_symlink_file(&rustup_path, &tool_path)?;
+            }
+            None => {}
+        }
     }
 
     Ok(())
@@ -773,7 +946,7 @@ fn maybe_install_rust(
     verbose: bool,
     quiet: bool,
 ) -> Result<()> {
-    let mut cfg = common::set_globals(verbose, quiet)?;
+    let mut cfg = common::set_globals(verbose, quiet, None)?;
 
     let toolchain = _install_selection(
         &mut cfg,
@@ -862,7 +1035,7 @@ fn _install_selection<'a>(
     })
 }
 
-pub(crate) fn uninstall(no_prompt: bool) -> Result<utils::ExitCode> {
+pub(crate) fn uninstall(no_prompt: bool, purge: bool) -> Result<utils::ExitCode> {
     if NEVER_SELF_UPDATE {
         err!("self-uninstall is disabled for this build of rustup");
         err!("you should probably use your system package manager to uninstall rustup");
@@ -878,22 +1051,36 @@ pub(crate) fn uninstall(no_prompt: bool) -> Result<utils::ExitCode> {
         return Err(CLIError::NotSelfInstalled { p: cargo_home }.into());
     }
 
-    if !no_prompt {
+    if !no_prompt && common::can_prompt() {
         writeln!(process().stdout())?;
         let msg = format!(pre_uninstall_msg!(), cargo_home = canonical_cargo_home()?);
         md(&mut term2::stdout(), msg);
-        if !common::confirm("\nContinue? (y/N)", false)? {
-            info!("aborting uninstallation");
-            return Ok(utils::ExitCode(0));
-        }
+    }
+    if !common::confirm_removal("Continue?", &[], "rustup self uninstall", false, no_prompt)? {
+        info!("aborting uninstallation");
+        return Ok(utils::ExitCode(0));
     }
 
     info!("removing rustup home");
 
-    // Delete RUSTUP_HOME
-    let rustup_dir = home::rustup_home()?;
-    if rustup_dir.exists() {
-        utils::remove_dir("rustup_home", &rustup_dir, &|_: Notification<'_>| {})?;
+    // Delete RUSTUP_HOME, or the directories of the XDG layout
+    let rustup_dirs = match utils::xdg_layout() {
+        Some(dirs) => vec![dirs.data, dirs.config, dirs.cache],
+        None => vec![home::rustup_home()?],
+    };
+    // The download cache and temporary files are in RUSTUP_HOME, so they
+    // go whether purging or not; purging only reports what that freed.
+    let freed = if purge {
+        let mut rustup_size = 0;
+        for dir in rustup_dirs.iter().filter(|dir| dir.exists()) {
+            rustup_size += utils::dir_size(dir)?;
+        }
+        Some(rustup_size + utils::dir_size(&cargo_home)?)
+    } else {
+        None
+    };
+    for dir in rustup_dirs.iter().filter(|dir| dir.exists()) {
+        utils::remove_dir("rustup_home", dir, &|_: Notification<'_>| {})?;
     }
 
     info!("removing cargo home");
@@ -959,6 +1146,10 @@ pub(crate) fn uninstall(no_prompt: bool) -> Result<utils::ExitCode> {
     delete_rustup_and_cargo_home()?;
 
     info!("rustup is uninstalled");
+    if let Some(freed) = freed {
+        let freed = Size::new(freed as usize, Unit::B, UnitMode::Norm);
+        info!("reclaimed {}", freed.to_string().trim_start());
+    }
 
     Ok(utils::ExitCode(0))
 }
@@ -1099,16 +1290,187 @@ pub fn prepare_update() -> Result<Option<PathBuf>> {
     // Get download path
     let download_url = utils::parse_url(&url)?;
 
-    // Download new version
-    info!("downloading self-update");
-    utils::download_file(&download_url, &setup_path, None, &|_| ())?;
+    // Download new version, as a patch to this one if there is one
+    if !patch_self_update(&url, &rustup_path, &setup_path)? {
+        info!("downloading self-update");
+        utils::download_file(&download_url, &setup_path, None, &|_| ())?;
+    }
+
+    // Check its signature before anything runs it
+    if let Err(e) = verify_self_update(&url, &setup_path) {
+        utils::remove_file("setup", &setup_path)?;
+        return Err(e);
+    }
 
     // Mark as executable
     utils::make_executable(&setup_path)?;
 
+    // Keep the version being replaced for `rustup self rollback`
+    let previous_path = previous_rustup_path()?;
+    utils::ensure_dir_exists(
+        "rustup home",
+        previous_path.parent().unwrap(),
+        &|_: Notification<'_>| {},
+    )?;
+    utils::copy_file(&rustup_path, &previous_path)?;
+    utils::make_executable(&previous_path)?;
+
     Ok(Some(setup_path))
 }
 
+/// Tries to make the new rustup at `url` out of the installed one at
+/// `rustup_path`, with the patch from this version next to it, `url` with
+/// `.from-<version>.patch` added. The result is checked against the hash
+/// next to it, `url` with `.sha256` added, before it is written to
+/// `setup_path`. Whether that worked; if not, the new rustup should be
+/// downloaded whole.
+fn patch_self_update(url: &str, rustup_path: &Path, setup_path: &Path) -> Result<bool> {
+    let tempdir = tempfile::Builder::new()
+        .prefix("rustup-update")
+        .tempdir()
+        .context("error creating temp directory")?;
+    let patch_path = tempdir.path().join("rustup-init.patch");
+    let patch_url = utils::parse_url(&format!("{}.from-{}.patch", url, env!("CARGO_PKG_VERSION")))?;
+    if let Err(e) = utils::download_file(&patch_url, &patch_path, None, &|_| ()) {
+        debug!("no self-update patch from this version: {:#}", e);
+        return Ok(false);
+    }
+
+    info!("applying self-update patch");
+    let patched = || -> Result<Vec<u8>> {
+        let hash_path = tempdir.path().join("rustup-init.sha256");
+        let hash_url = utils::parse_url(&format!("{}.sha256", url))?;
+        utils::download_file(&hash_url, &hash_path, None, &|_| ())
+            .context("could not download the checksum of the self-update")?;
+        let hash = utils::read_file("checksum", &hash_path)?;
+        let expected = hash.split_whitespace().next().unwrap_or_default();
+
+        let old = fs::read(rustup_path).with_context(|| RustupError::ReadingFile {
+            name: "rustup",
+            path: rustup_path.to_owned(),
+        })?;
+        let new = binary_patch::apply(&old, utils::open_file("patch", &patch_path)?)?;
+        let actual = format!("{:x}", Sha256::digest(&new));
+        if actual != expected {
+            return Err(anyhow!(
+                "the patched rustup's checksum is {}, not {}",
+                actual,
+                expected
+            ));
+        }
+        Ok(new)
+    };
+    let new = match patched() {
+        Ok(new) => new,
+        Err(e) => {
+            warn!("{:#}", e);
+            warn!("could not update rustup from a patch; downloading it whole");
+            return Ok(false);
+        }
+    };
+    fs::write(setup_path, new).with_context(|| RustupError::WritingFile {
+        name: "setup",
+        path: setup_path.to_owned(),
+    })?;
+    Ok(true)
+}
+
+/// The keys which a new rustup must be signed with one of, pinned in this
+/// one rather than taken from the update root, so that whoever controls the
+/// update root can't have anything else installed.
+///
+/// Rotating the key takes two releases: one which adds the new key here
+/// while updates are still signed with the old one, and, once that is what
+/// people update from, one signed with the new key, which can drop the old.
+fn self_update_keys() -> Result<Vec<PgpPublicKey>> {
+    let mut keys = vec![PgpPublicKey::Builtin];
+    // For the tests, whose updates are signed with a key of their own. A
+    // release build trusts only the pinned keys.
+    if cfg!(debug_assertions) {
+        if let Some(path) = process().var_os("RUSTUP_OVERRIDE_SELF_UPDATE_KEY") {
+            let path = PathBuf::from(path);
+            let file = utils::open_file("self-update key", &path)?;
+            let (key, _) = SignedPublicKey::from_armor_single(file).map_err(|error| {
+                anyhow!(RustupError::InvalidPgpKey {
+                    path: path.clone(),
+                    source: error,
+                })
+            })?;
+            keys.push(PgpPublicKey::FromEnvironment(path, key));
+        }
+    }
+    Ok(keys)
+}
+
+/// Checks the new rustup at `setup_path`, downloaded from `url`, against
+/// the detached signature next to it, `url` with `.asc` added.
+fn verify_self_update(url: &str, setup_path: &Path) -> Result<()> {
+    let tempdir = tempfile::Builder::new()
+        .prefix("rustup-update")
+        .tempdir()
+        .context("error creating temp directory")?;
+    let signature_path = tempdir.path().join("rustup-init.asc");
+    let signature_url = utils::parse_url(&format!("{}.asc", url))?;
+    utils::download_file(&signature_url, &signature_path, None, &|_| ())
+        .context("could not download the signature of the self-update")?;
+    let signature = utils::read_file("signature", &signature_path)?;
+    let setup = utils::open_file("setup", setup_path)?;
+    match signatures::verify_signature(setup, &signature, &self_update_keys()?)? {
+        Some(_) => Ok(()),
+        None => Err(CLIError::SelfUpdateNotSigned {
+            url: url.to_owned(),
+        }
+        .into()),
+    }
+}
+
+/// Where the rustup which the last self-update replaced is kept, in the
+/// rustup home, so that uninstalling removes it with the rest.
+fn previous_rustup_path() -> Result<PathBuf> {
+    let home = match utils::xdg_layout() {
+        Some(dirs) => dirs.data,
+        None => utils::rustup_home()?,
+    };
+    Ok(home.join("previous").join(format!("rustup{}", EXE_SUFFIX)))
+}
+
+/// `rustup self rollback`: reinstalls the rustup which the last self-update
+/// replaced, as a self-update installs a new one, proxies and all. The one
+/// rolled back from is kept in its place, so that rolling back again goes
+/// back to it.
+pub(crate) fn rollback() -> Result<utils::ExitCode> {
+    if NEVER_SELF_UPDATE {
+        err!("self-update is disabled for this build of rustup");
+        err!("you should probably use your system package manager to update rustup");
+        return Ok(utils::ExitCode(1));
+    }
+    let cargo_home = utils::cargo_home()?;
+    let rustup_path = cargo_home.join(&format!("bin{}rustup{}", MAIN_SEPARATOR, EXE_SUFFIX));
+    let setup_path = cargo_home.join(&format!("bin{}rustup-init{}", MAIN_SEPARATOR, EXE_SUFFIX));
+    if !rustup_path.exists() {
+        return Err(CLIError::NotSelfInstalled { p: cargo_home }.into());
+    }
+    let previous_path = previous_rustup_path()?;
+    if !previous_path.exists() {
+        return Err(anyhow!(
+            "there is no previous version of rustup to roll back to; one is kept by `rustup self update`"
+        ));
+    }
+    let version = get_new_rustup_version(&previous_path)
+        .map(parse_new_rustup_version)
+        .ok_or_else(|| anyhow!("failed to get the version of the previous rustup"))?;
+
+    if setup_path.exists() {
+        utils::remove_file("setup", &setup_path)?;
+    }
+    utils::copy_file(&previous_path, &setup_path)?;
+    utils::make_executable(&setup_path)?;
+    utils::copy_file(&rustup_path, &previous_path)?;
+
+    info!("rolling back to rustup {}", version);
+    run_update(&setup_path)
+}
+
 pub fn get_available_rustup_version() -> Result<String> {
     let update_root = process()
         .var("RUSTUP_UPDATE_ROOT")
@@ -1162,12 +1524,12 @@ pub fn check_rustup_update() -> Result<()> {
     write!(t, "rustup - ")?;
 
     if current_version != available_version {
-        let _ = t.fg(term2::color::YELLOW);
+        let _ = t.style(term2::Style::Warning);
         write!(t, "Update available")?;
         let _ = t.reset();
         writeln!(t, " : {} -> {}", current_version, available_version)?;
     } else {
-        let _ = t.fg(term2::color::GREEN);
+        let _ = t.style(term2::Style::Good);
         write!(t, "Up to date")?;
         let _ = t.reset();
         writeln!(t, " : {}", current_version)?;
@@ -1198,9 +1560,11 @@ pub fn valid_self_update_modes() -> String {
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::fs;
 
     use anyhow::Result;
 
+    use super::journal;
     use crate::cli::common;
     use crate::dist::dist::ToolchainDesc;
     use crate::test::{test_dir, with_rustup_home, Env};
@@ -1218,7 +1582,7 @@ mod tests {
             currentprocess::with(tp.clone(), || -> Result<()> {
                 // TODO: we could pass in a custom cfg to get notification
                 // callbacks rather than output to the tp sink.
-                let mut cfg = common::set_globals(false, false).unwrap();
+                let mut cfg = common::set_globals(false, false, None).unwrap();
                 assert_eq!(
                     "stable",
                     super::_install_selection(
@@ -1269,4 +1633,59 @@ info: default host triple is {0}
         .unwrap();
         assert!(cargo_home.exists());
     }
+
+    #[test]
+    fn replace_leaves_files_alone_if_staging_fails() {
+        let bin = test_dir().unwrap();
+        let rustc = bin.path().join("rustc");
+        let cargo = bin.path().join("cargo");
+        fs::write(&rustc, "old rustc").unwrap();
+        fs::write(&cargo, "old cargo").unwrap();
+        let new = bin.path().join("new");
+        fs::write(&new, "new").unwrap();
+        let missing = bin.path().join("missing");
+
+        let files = [
+            (rustc.clone(), journal::Source::Copy(&new)),
+            (cargo.clone(), journal::Source::Copy(&missing)),
+        ];
+        assert!(journal::replace(bin.path(), &files).is_err());
+        assert_eq!(fs::read_to_string(&rustc).unwrap(), "old rustc");
+        assert_eq!(fs::read_to_string(&cargo).unwrap(), "old cargo");
+        assert!(!bin.path().join("rustc.new").exists());
+
+        let files = [
+            (rustc.clone(), journal::Source::Copy(&new)),
+            (cargo.clone(), journal::Source::Link(&new)),
+        ];
+        journal::replace(bin.path(), &files).unwrap();
+        assert_eq!(fs::read_to_string(&rustc).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&cargo).unwrap(), "new");
+        assert!(!bin.path().join("rustc.old").exists());
+    }
+
+    #[test]
+    fn recover_finishes_interrupted_replace() {
+        let bin = test_dir().unwrap();
+        // Stopped with rustc swapped, cargo moved aside and rls untouched.
+        fs::write(bin.path().join("rustc"), "new").unwrap();
+        fs::write(bin.path().join("rustc.old"), "old").unwrap();
+        fs::write(bin.path().join("cargo.old"), "old").unwrap();
+        fs::write(bin.path().join("cargo.new"), "new").unwrap();
+        fs::write(bin.path().join("rls"), "old").unwrap();
+        fs::write(bin.path().join("rls.new"), "new").unwrap();
+        fs::write(
+            bin.path().join(".rustup-replace-journal"),
+            "rustc\ncargo\nrls",
+        )
+        .unwrap();
+
+        journal::recover(bin.path()).unwrap();
+        for tool in &["rustc", "cargo", "rls"] {
+            assert!(bin.path().join(tool).exists(), "{} is missing", tool);
+            assert!(!bin.path().join(format!("{}.new", tool)).exists());
+        }
+        assert_eq!(fs::read_to_string(bin.path().join("cargo")).unwrap(), "new");
+        assert!(!bin.path().join(".rustup-replace-journal").exists());
+    }
 }
@@ -0,0 +1,179 @@
+//! Replacing rustup and its proxies in CARGO_HOME/bin
+//!
+//! On Windows a running exe can't be deleted or written over, but it
+//! can be renamed, and the proxies are running whenever cargo is. So
+//! rather than removing each file and linking its replacement in its
+//! place, which leaves nothing there if the second step fails, the
+//! replacements are first made beside the files as `<name>.new`.
+//! Then, with a journal naming them written down, each file is moved
+//! aside to `<name>.old` and its replacement moved in. If that fails
+//! part way the moves are undone, and if rustup doesn't live to undo
+//! them the journal lets the next run of rustup finish the job. The
+//! `.old` files are removed once nothing is running them.
+
+use std::env::consts::EXE_SUFFIX;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::utils::utils;
+use crate::utils::Notification;
+use crate::{DUP_TOOLS, TOOLS};
+
+const JOURNAL: &str = ".rustup-replace-journal";
+
+/// Where the replacement for a file comes from.
+pub(crate) enum Source<'a> {
+    /// A copy of this file.
+    Copy(&'a Path),
+    /// A link to this file.
+    Link(&'a Path),
+}
+
+/// Replaces each file in `bin_path` named by `files` with its source, or
+/// leaves all of them as they were.
+pub(crate) fn replace(bin_path: &Path, files: &[(PathBuf, Source<'_>)]) -> Result<()> {
+    recover(bin_path)?;
+    remove_old_files(bin_path);
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    if let Err(e) = stage(files) {
+        for (path, _) in files {
+            let _ = fs::remove_file(with_suffix(path, "new"));
+        }
+        return Err(e);
+    }
+
+    let journal = bin_path.join(JOURNAL);
+    let names: Vec<_> = files
+        .iter()
+        .map(|(path, _)| path.file_name().unwrap().to_string_lossy())
+        .collect();
+    utils::write_file("replace journal", &journal, &names.join("\n"))?;
+
+    let mut done: Vec<&Path> = Vec::new();
+    for (path, _) in files {
+        if let Err(e) = swap(path) {
+            let mut undone = done.iter().all(|path| undo(path).is_ok());
+            // This one is only put back if it got as far as being moved aside.
+            if fs::symlink_metadata(path).is_err() {
+                undone &= rename(&with_suffix(path, "old"), path).is_ok();
+            }
+            for (path, _) in files {
+                let _ = fs::remove_file(with_suffix(path, "new"));
+            }
+            // If anything couldn't be put back, the next run tries again.
+            if undone {
+                let _ = fs::remove_file(&journal);
+            }
+            return Err(e);
+        }
+        done.push(path);
+    }
+
+    utils::remove_file("replace journal", &journal)?;
+    for (path, _) in files {
+        // Anything still running keeps its `.old` file until next time.
+        let _ = fs::remove_file(with_suffix(path, "old"));
+    }
+    Ok(())
+}
+
+/// Finishes a replacement that rustup didn't live to complete or undo.
+pub(crate) fn recover(bin_path: &Path) -> Result<()> {
+    let journal = bin_path.join(JOURNAL);
+    if let Ok(names) = fs::read_to_string(&journal) {
+        for name in names.lines().filter(|name| !name.is_empty()) {
+            let path = bin_path.join(name);
+            let new = with_suffix(&path, "new");
+            if fs::symlink_metadata(&path).is_err() {
+                // Whatever was moved aside is replaced by its replacement
+                // if that was made, or put back if not.
+                let old = with_suffix(&path, "old");
+                if fs::symlink_metadata(&new).is_ok() {
+                    rename(&new, &path)?;
+                } else if fs::symlink_metadata(&old).is_ok() {
+                    rename(&old, &path)?;
+                }
+            }
+            let _ = fs::remove_file(&new);
+        }
+        utils::remove_file("replace journal", &journal)?;
+    }
+    Ok(())
+}
+
+/// Removes the `.old` files left behind by earlier replacements, other
+/// than those still running.
+fn remove_old_files(bin_path: &Path) {
+    let prefixes: Vec<_> = TOOLS
+        .iter()
+        .chain(DUP_TOOLS)
+        .chain(&["rustup"])
+        .map(|tool| format!("{}{}.", tool, EXE_SUFFIX))
+        .collect();
+    if let Ok(entries) = fs::read_dir(bin_path) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".old") && prefixes.iter().any(|p| name.starts_with(p)) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+fn stage(files: &[(PathBuf, Source<'_>)]) -> Result<()> {
+    for (path, source) in files {
+        let new = with_suffix(path, "new");
+        let _ = fs::remove_file(&new);
+        match source {
+            Source::Copy(src) => {
+                utils::copy_file(src, &new)?;
+                utils::make_executable(&new)?;
+            }
+            Source::Link(src) => utils::hard_or_symlink_file(src, &new)?,
+        }
+    }
+    Ok(())
+}
+
+/// Moves `path` aside to `<path>.old`, if it is there, and the
+/// replacement staged at `<path>.new` into its place.
+fn swap(path: &Path) -> Result<()> {
+    let old = with_suffix(path, "old");
+    if fs::symlink_metadata(path).is_ok() {
+        // An `.old` file from an earlier replacement may still be running,
+        // in which case it is left behind under another name.
+        if fs::symlink_metadata(&old).is_ok() && fs::remove_file(&old).is_err() {
+            let stale = with_suffix(path, &format!("{:x}.old", rand::random::<u32>()));
+            rename(&old, &stale)?;
+        }
+        rename(path, &old)?;
+    }
+    rename(&with_suffix(path, "new"), path)
+}
+
+/// Puts back the file `swap` moved aside from `path`.
+fn undo(path: &Path) -> Result<()> {
+    let old = with_suffix(path, "old");
+    if fs::symlink_metadata(&old).is_ok() {
+        utils::remove_file("replaced file", path)?;
+        rename(&old, path)?;
+    }
+    Ok(())
+}
+
+fn rename(src: &Path, dest: &Path) -> Result<()> {
+    utils::rename_file("replaced file", src, dest, &|_: Notification<'_>| ())
+        .with_context(|| format!("could not move '{}' into place", dest.display()))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
@@ -79,6 +79,54 @@ pub fn do_remove_from_path() -> Result<()> {
     Ok(())
 }
 
+/// Comments out the lines of the shell profiles which set RUSTUP_HOME to
+/// `home`, which would keep the XDG layout from being used once the home has
+/// moved there. Returns the profiles changed.
+pub(crate) fn do_remove_rustup_home_exports(home: &Path) -> Result<Vec<PathBuf>> {
+    let mut values = vec![home.display().to_string()];
+    if let Some(rel) = utils::home_dir().and_then(|dir| home.strip_prefix(dir).ok()) {
+        for prefix in &["$HOME", "${HOME}", "~"] {
+            values.push(format!("{}/{}", prefix, rel.display()));
+        }
+    }
+    let sets_home = |line: &str| {
+        let line = line.trim();
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        match line.strip_prefix("RUSTUP_HOME=") {
+            Some(value) => {
+                let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+                values.iter().any(|v| v == value)
+            }
+            None => false,
+        }
+    };
+
+    let mut changed = vec![];
+    for sh in shell::get_available_shells() {
+        for rc in sh.rcfiles().into_iter().filter(|rc| rc.is_file()) {
+            if changed.contains(&rc) {
+                continue;
+            }
+            let file = utils::read_file("rcfile", &rc)?;
+            if !file.lines().any(&sets_home) {
+                continue;
+            }
+            let mut new_file = String::new();
+            for line in file.lines() {
+                if sets_home(line) {
+                    new_file.push_str(&format!("# {} # moved by `rustup migrate xdg`\n", line));
+                } else {
+                    new_file.push_str(line);
+                    new_file.push('\n');
+                }
+            }
+            utils::write_file("rcfile", &rc, &new_file)?;
+            changed.push(rc);
+        }
+    }
+    Ok(changed)
+}
+
 pub fn do_add_to_path() -> Result<()> {
     for sh in shell::get_available_shells() {
         let source_cmd = sh.source_string()?;
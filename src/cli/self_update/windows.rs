@@ -18,6 +18,9 @@ use winreg::enums::{RegType, HKEY_CURRENT_USER, KEY_READ, KEY_WRITE};
 use winreg::{RegKey, RegValue};
 
 pub fn ensure_prompt() -> Result<()> {
+    if common::non_interactive() {
+        return Ok(());
+    }
     writeln!(process().stdout(),)?;
     writeln!(process().stdout(), "Press the Enter key to continue.")?;
     common::read_line()?;
@@ -281,6 +284,12 @@ pub fn do_remove_from_path() -> Result<()> {
 
 const RUSTUP_UNINSTALL_ENTRY: &str = r"Software\Microsoft\Windows\CurrentVersion\Uninstall\Rustup";
 
+/// The XDG layout isn't used on Windows, so there are no shell profiles to
+/// change for it.
+pub(crate) fn do_remove_rustup_home_exports(_home: &Path) -> Result<Vec<std::path::PathBuf>> {
+    Ok(vec![])
+}
+
 pub fn do_add_to_programs() -> Result<()> {
     use std::path::PathBuf;
 
@@ -45,6 +45,11 @@ pub fn main() -> Result<utils::ExitCode> {
                 .short("y")
                 .help("Disable confirmation prompt."),
         )
+        .arg(
+            Arg::with_name("non-interactive")
+                .long("non-interactive")
+                .help("Never ask anything: use the default answer, or fail where there is none"),
+        )
         .arg(
             Arg::with_name("default-host")
                 .long("default-host")
@@ -104,6 +109,7 @@ pub fn main() -> Result<utils::ExitCode> {
         Err(e) => return Err(e.into()),
     };
     let no_prompt = matches.is_present("no-prompt");
+    common::set_non_interactive(matches.is_present("non-interactive"));
     let verbose = matches.is_present("verbose");
     let quiet = matches.is_present("quiet");
     let default_host = matches.value_of("default-host").map(ToOwned::to_owned);
@@ -0,0 +1,213 @@
+//! `rustup snapshot`: recording exactly which releases, components and
+//! targets are installed, so that the same toolchains can be installed
+//! elsewhere, like a lockfile for the toolchains themselves.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::dist::dist::TargetTriple;
+use crate::dist::manifest::{Component, CompressionKind, Manifest};
+use crate::dist::manifestation::Manifestation;
+use crate::dist::prefix::InstallPrefix;
+use crate::toolchain::{DistributableToolchain, Toolchain};
+use crate::utils::utils::{self, ExitCode};
+use crate::Cfg;
+
+const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+    #[serde(default, rename = "toolchain")]
+    toolchains: Vec<ToolchainSnapshot>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ToolchainSnapshot {
+    name: String,
+    /// The date of the release, as in its channel manifest.
+    date: String,
+    #[serde(default, rename = "component")]
+    components: Vec<ComponentSnapshot>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ComponentSnapshot {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    /// The SHA-256 of the component's package, as in the manifest.
+    hash: String,
+}
+
+impl ComponentSnapshot {
+    fn component(&self) -> Component {
+        Component::new(
+            self.name.clone(),
+            self.target.as_deref().map(TargetTriple::new),
+            false,
+        )
+    }
+}
+
+/// Writes a snapshot of every installed release toolchain to `path`.
+pub(crate) fn save(cfg: &Cfg, path: &Path) -> Result<ExitCode> {
+    let mut toolchains = Vec::new();
+    for name in cfg.list_toolchains()? {
+        let toolchain = cfg.get_toolchain(&name, false)?;
+        match take(&toolchain)? {
+            Some(snapshot) => toolchains.push(snapshot),
+            None => warn!("skipping '{}', which wasn't installed from a release", name),
+        }
+    }
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        default: cfg.get_default()?,
+        toolchains,
+    };
+    utils::write_file("snapshot", path, &toml::to_string(&snapshot)?)?;
+    info!(
+        "saved {} toolchains to '{}'",
+        snapshot.toolchains.len(),
+        path.display()
+    );
+    Ok(ExitCode(0))
+}
+
+/// Installs the toolchains recorded in the snapshot at `path`, at the same
+/// releases and with the same components as when it was taken.
+pub(crate) fn restore(cfg: &Cfg, path: &Path) -> Result<ExitCode> {
+    let contents = utils::read_file("snapshot", path)?;
+    let snapshot: Snapshot = toml::from_str(&contents)
+        .with_context(|| format!("invalid snapshot '{}'", path.display()))?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(anyhow!(
+            "snapshot '{}' has version {}, but this rustup only understands version {}",
+            path.display(),
+            snapshot.version,
+            SNAPSHOT_VERSION
+        ));
+    }
+
+    // Check every release against the server before changing anything, so
+    // that a snapshot which can no longer be restored leaves things as they
+    // were.
+    let mut outdated = Vec::new();
+    for wanted in &snapshot.toolchains {
+        let toolchain = cfg.get_toolchain(&wanted.name, false)?;
+        let distributable = DistributableToolchain::new(&toolchain)
+            .map_err(|_| anyhow!("'{}' is not the name of a release toolchain", wanted.name))?;
+        if toolchain.exists() && take(&toolchain)?.as_ref() == Some(wanted) {
+            info!("'{}' already matches the snapshot", wanted.name);
+            continue;
+        }
+        let manifest = distributable.release_manifest(&wanted.date)?;
+        for component in &wanted.components {
+            if package_hash(&manifest, &component.component())? != component.hash {
+                return Err(anyhow!(
+                    "'{}' can't be restored: the release from {} has changed on the server",
+                    wanted.name,
+                    wanted.date
+                ));
+            }
+        }
+        outdated.push(wanted);
+    }
+
+    for wanted in outdated {
+        let toolchain = cfg.get_toolchain(&wanted.name, true)?;
+        let distributable = DistributableToolchain::new(&toolchain)?;
+        let components: Vec<_> = wanted
+            .components
+            .iter()
+            .map(ComponentSnapshot::component)
+            .collect();
+        // Installed over what is there, rather than afresh, so that the
+        // toolchain is never missing; then whatever the snapshot doesn't
+        // have goes.
+        distributable.install_release(&wanted.date, &components)?;
+        if let Some(installed) = take(&toolchain)? {
+            let extra: Vec<_> = installed
+                .components
+                .iter()
+                .filter(|c| {
+                    !wanted
+                        .components
+                        .iter()
+                        .any(|w| w.name == c.name && w.target == c.target)
+                })
+                .map(ComponentSnapshot::component)
+                .collect();
+            if !extra.is_empty() {
+                distributable.remove_components(extra)?;
+            }
+        }
+
+        if take(&toolchain)?.as_ref() != Some(wanted) {
+            return Err(anyhow!(
+                "'{}' as installed doesn't match the snapshot: the release from {} has changed on the server",
+                wanted.name,
+                wanted.date
+            ));
+        }
+    }
+
+    if let Some(default) = &snapshot.default {
+        cfg.set_default(default)?;
+    }
+    Ok(ExitCode(0))
+}
+
+/// Records the release and components of an installed toolchain, or
+/// `None` if it has no manifest to say what they are.
+fn take(toolchain: &Toolchain<'_>) -> Result<Option<ToolchainSnapshot>> {
+    let distributable = match DistributableToolchain::new(toolchain) {
+        Ok(distributable) => distributable,
+        Err(_) => return Ok(None),
+    };
+    let manifest = match distributable.get_manifest()? {
+        Some(manifest) => manifest,
+        None => return Ok(None),
+    };
+    let prefix = InstallPrefix::from(toolchain.path().to_owned());
+    let manifestation = Manifestation::open(prefix, distributable.desc()?.target)?;
+    let config = match manifestation.read_config()? {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+
+    let mut components = Vec::new();
+    for component in &config.components {
+        components.push(ComponentSnapshot {
+            name: component.short_name_in_manifest().clone(),
+            target: component.target.as_ref().map(ToString::to_string),
+            hash: package_hash(&manifest, component)?,
+        });
+    }
+    components.sort_by(|a, b| (&a.name, &a.target).cmp(&(&b.name, &b.target)));
+
+    Ok(Some(ToolchainSnapshot {
+        name: toolchain.name().to_owned(),
+        date: manifest.date,
+        components,
+    }))
+}
+
+/// The SHA-256 of `component`'s package in `manifest`.
+fn package_hash(manifest: &Manifest, component: &Component) -> Result<String> {
+    let package = manifest
+        .get_package(component.short_name_in_manifest())?
+        .get_target(component.target.as_ref())?;
+    // Every manifest has gzip packages; the others come and go.
+    Ok(package
+        .bins
+        .iter()
+        .find(|(kind, _)| *kind == CompressionKind::GZip)
+        .or_else(|| package.bins.first())
+        .map(|(_, bin)| bin.hash.clone())
+        .unwrap_or_default())
+}
@@ -0,0 +1,145 @@
+//! `rustup stats`: which toolchains and tools have been run lately, from the
+//! usage log kept once `rustup set usage-stats enable` turns it on. Every
+//! installed toolchain is listed, so that those not run at all stand out.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::common;
+use super::term2::{self, Terminal};
+use crate::settings::UsageStats;
+use crate::utils::utils::ExitCode;
+use crate::Cfg;
+
+#[derive(Serialize)]
+struct ToolchainStats {
+    name: String,
+    runs: usize,
+    /// RFC 3339, as the history's times are.
+    last_run: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    enabled: bool,
+    days: u32,
+    runs: usize,
+    toolchains: Vec<ToolchainStats>,
+    tools: BTreeMap<String, usize>,
+}
+
+pub(crate) fn clear(cfg: &Cfg) -> Result<ExitCode> {
+    cfg.usage_log.clear()?;
+    info!("usage statistics cleared");
+    Ok(ExitCode(0))
+}
+
+pub(crate) fn stats(cfg: &Cfg, days: &str, json: bool) -> Result<ExitCode> {
+    let days: u32 = match days.parse() {
+        Ok(days) if days > 0 => days,
+        _ => return Err(anyhow!("'{}' is not a number of days", days)),
+    };
+    // So many days back that they go past what a date can be are all of them.
+    let since = Utc::now().checked_sub_signed(chrono::Duration::days(i64::from(days)));
+    let enabled = cfg.get_usage_stats()? == UsageStats::Enable;
+    if !enabled {
+        warn!("usage statistics are off; turn them on with `rustup set usage-stats enable`");
+    }
+
+    let mut toolchains: BTreeMap<String, ToolchainStats> = cfg
+        .list_toolchains()?
+        .into_iter()
+        .map(|name| {
+            let stats = ToolchainStats {
+                name: name.clone(),
+                runs: 0,
+                last_run: None,
+            };
+            (name, stats)
+        })
+        .collect();
+    let mut tools = BTreeMap::new();
+    let mut runs = 0;
+    for entry in cfg.usage_log.entries()? {
+        if since.map_or(false, |since| entry.time < since) {
+            continue;
+        }
+        runs += 1;
+        *tools.entry(entry.tool).or_insert(0) += 1;
+        // Toolchains uninstalled since are left out.
+        if let Some(toolchain) = toolchains.get_mut(&entry.toolchain) {
+            toolchain.runs += 1;
+            toolchain.last_run = Some(entry.time.to_rfc3339());
+        }
+    }
+    let mut toolchains: Vec<_> = toolchains.into_values().collect();
+    toolchains.sort_by(|a, b| b.runs.cmp(&a.runs).then_with(|| a.name.cmp(&b.name)));
+
+    if json {
+        common::print_json(&Stats {
+            enabled,
+            days,
+            runs,
+            toolchains,
+            tools,
+        })?;
+        return Ok(ExitCode(0));
+    }
+
+    let mut t = term2::stdout();
+    t.attr(term2::Attr::Bold)?;
+    write!(t, "Runs in the last {} days: ", days)?;
+    t.reset()?;
+    writeln!(t, "{}", runs)?;
+    let width = toolchains.iter().map(|t| t.name.len()).max().unwrap_or(0);
+    for toolchain in &toolchains {
+        let last_run = toolchain
+            .last_run
+            .as_deref()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+        match last_run {
+            Some(last) => writeln!(
+                t,
+                "  {:width$}  {:>6}  last {}",
+                toolchain.name,
+                toolchain.runs,
+                last.format("%Y-%m-%d"),
+                width = width
+            )?,
+            None => writeln!(t, "  {:width$}  {:>6}", toolchain.name, 0, width = width)?,
+        }
+    }
+    if !tools.is_empty() {
+        writeln!(t)?;
+        t.attr(term2::Attr::Bold)?;
+        writeln!(t, "Tools")?;
+        t.reset()?;
+        let width = tools.keys().map(|t| t.len()).max().unwrap_or(0);
+        for (tool, count) in &tools {
+            writeln!(t, "  {:width$}  {:>6}", tool, count, width = width)?;
+        }
+    }
+
+    // Only worth pointing out once there has been time to run them.
+    let forgotten: Vec<_> = toolchains
+        .iter()
+        .filter(|t| t.runs == 0)
+        .map(|t| &*t.name)
+        .collect();
+    if enabled && runs > 0 && !forgotten.is_empty() {
+        info!(
+            "not run in the last {} days: {}",
+            days,
+            forgotten.join(", ")
+        );
+        info!(
+            "run `rustup toolchain gc --unused-for {}d --dry-run` to see which can be uninstalled",
+            days
+        );
+    }
+    Ok(ExitCode(0))
+}
@@ -0,0 +1,253 @@
+//! `rustup status`: one screen on the state of the installation, gathering
+//! what `rustup show`, `rustup check` and `rustup doctor` report: the active
+//! toolchain and why, the updates waiting for the installed channels and for
+//! rustup itself, the disk space rustup uses, and any problems found.
+
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::common;
+use super::doctor;
+use super::self_update;
+use super::term2::{self, Terminal};
+use crate::toolchain::DistributableToolchain;
+use crate::utils::units::{Size, Unit, UnitMode};
+use crate::utils::utils::{self, ExitCode};
+use crate::Cfg;
+
+#[derive(Serialize)]
+struct Status {
+    active_toolchain: Option<ActiveToolchain>,
+    toolchains: Vec<ToolchainStatus>,
+    rustup: RustupStatus,
+    disk_usage: DiskUsage,
+    problems: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ActiveToolchain {
+    name: String,
+    reason: String,
+    installed: bool,
+}
+
+/// Whether a channel has an update; `error` is why that couldn't be found
+/// out, such as the network being down.
+#[derive(Serialize)]
+struct ToolchainStatus {
+    name: String,
+    current_version: Option<String>,
+    update_available: bool,
+    update_version: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RustupStatus {
+    current_version: &'static str,
+    update_available: bool,
+    update_version: Option<String>,
+    self_update_available: bool,
+    error: Option<String>,
+}
+
+/// The bytes taken up by each of the parts of rustup home that grow.
+#[derive(Serialize)]
+struct DiskUsage {
+    toolchains: u64,
+    downloads: u64,
+    temporary_files: u64,
+    total: u64,
+}
+
+impl Status {
+    fn new(cfg: &Cfg) -> Result<Self> {
+        Ok(Self {
+            active_toolchain: active_toolchain(cfg)?,
+            toolchains: toolchains(cfg)?,
+            rustup: rustup(),
+            disk_usage: disk_usage(cfg)?,
+            problems: doctor::find_problems(cfg)?,
+        })
+    }
+}
+
+/// Unlike `rustup show`, this doesn't install the active toolchain if it's
+/// missing, but says so.
+fn active_toolchain(cfg: &Cfg) -> Result<Option<ActiveToolchain>> {
+    let (toolchain, reason) = match cfg.find_override(&utils::current_dir()?)? {
+        Some((toolchain, reason)) => (toolchain, reason.to_string()),
+        None => match cfg.find_default()? {
+            Some(toolchain) => (toolchain, "default".to_owned()),
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(ActiveToolchain {
+        name: toolchain.name().to_owned(),
+        reason,
+        installed: toolchain.exists(),
+    }))
+}
+
+fn toolchains(cfg: &Cfg) -> Result<Vec<ToolchainStatus>> {
+    let mut statuses = Vec::new();
+    for (name, toolchain) in cfg.list_channels()? {
+        let toolchain = toolchain?;
+        let distributable = DistributableToolchain::new(&toolchain)?;
+        let current_version = distributable.show_version()?;
+        let (update_version, error) = match distributable.show_dist_version() {
+            Ok(version) => (version, None),
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+        statuses.push(ToolchainStatus {
+            name,
+            current_version,
+            update_available: update_version.is_some(),
+            update_version,
+            error,
+        });
+    }
+    Ok(statuses)
+}
+
+fn rustup() -> RustupStatus {
+    let current_version = env!("CARGO_PKG_VERSION");
+    let (update_version, error) = match self_update::get_available_rustup_version() {
+        Ok(version) if version != current_version => (Some(version), None),
+        Ok(_) => (None, None),
+        Err(e) => (None, Some(format!("{:#}", e))),
+    };
+    let update_available = update_version.is_some();
+    RustupStatus {
+        current_version,
+        update_available,
+        update_version,
+        self_update_available: update_available && !self_update::NEVER_SELF_UPDATE,
+        error,
+    }
+}
+
+fn disk_usage(cfg: &Cfg) -> Result<DiskUsage> {
+    let size = |dir: &Path| -> Result<u64> {
+        if utils::is_directory(dir) {
+            utils::dir_size(dir)
+        } else {
+            Ok(0)
+        }
+    };
+    let toolchains = size(&cfg.toolchains_dir)?;
+    let downloads = size(&cfg.download_dir)?;
+    let temporary_files = size(cfg.temp_cfg.root_directory())?;
+    Ok(DiskUsage {
+        toolchains,
+        downloads,
+        temporary_files,
+        total: toolchains + downloads + temporary_files,
+    })
+}
+
+fn size(bytes: u64) -> String {
+    let size = Size::new(bytes as usize, Unit::B, UnitMode::Norm);
+    size.to_string().trim_start().to_owned()
+}
+
+pub(crate) fn status(cfg: &Cfg, json: bool) -> Result<ExitCode> {
+    let status = Status::new(cfg)?;
+    if json {
+        common::print_json(&status)?;
+        return Ok(ExitCode(0));
+    }
+
+    let mut t = term2::stdout();
+    let _ = t.attr(term2::Attr::Bold);
+    write!(t, "active toolchain: ")?;
+    let _ = t.reset();
+    match &status.active_toolchain {
+        Some(active) if active.installed => writeln!(t, "{} ({})", active.name, active.reason)?,
+        Some(active) => {
+            write!(t, "{} ({}), ", active.name, active.reason)?;
+            let _ = t.style(term2::Style::Warning);
+            writeln!(t, "not installed")?;
+            let _ = t.reset();
+        }
+        None => writeln!(t, "none")?,
+    }
+
+    let _ = t.attr(term2::Attr::Bold);
+    writeln!(t, "updates:")?;
+    let _ = t.reset();
+    for toolchain in &status.toolchains {
+        write!(t, "  {}: ", toolchain.name)?;
+        let current = toolchain
+            .current_version
+            .as_deref()
+            .unwrap_or("unknown version");
+        match (&toolchain.update_version, &toolchain.error) {
+            (_, Some(error)) => {
+                let _ = t.style(term2::Style::Error);
+                write!(t, "could not check")?;
+                let _ = t.reset();
+                writeln!(t, " ({})", error)?;
+            }
+            (Some(update), None) => {
+                let _ = t.style(term2::Style::Warning);
+                write!(t, "update available")?;
+                let _ = t.reset();
+                writeln!(t, " ({} -> {})", current, update)?;
+            }
+            (None, None) => writeln!(t, "up to date ({})", current)?,
+        }
+    }
+    let rustup = &status.rustup;
+    write!(t, "  rustup: ")?;
+    match (&rustup.update_version, &rustup.error) {
+        (_, Some(error)) => {
+            let _ = t.style(term2::Style::Error);
+            write!(t, "could not check")?;
+            let _ = t.reset();
+            writeln!(t, " ({})", error)?;
+        }
+        (Some(update), None) => {
+            let _ = t.style(term2::Style::Warning);
+            write!(t, "update available")?;
+            let _ = t.reset();
+            write!(t, " ({} -> {})", rustup.current_version, update)?;
+            if rustup.self_update_available {
+                writeln!(t, ", run `rustup self update`")?;
+            } else {
+                writeln!(t, ", through the package manager rustup came from")?;
+            }
+        }
+        (None, None) => writeln!(t, "up to date ({})", rustup.current_version)?,
+    }
+
+    let usage = &status.disk_usage;
+    let _ = t.attr(term2::Attr::Bold);
+    write!(t, "disk usage: ")?;
+    let _ = t.reset();
+    writeln!(t, "{}", size(usage.total))?;
+    writeln!(t, "  toolchains: {}", size(usage.toolchains))?;
+    writeln!(t, "  downloads: {}", size(usage.downloads))?;
+    writeln!(t, "  temporary files: {}", size(usage.temporary_files))?;
+
+    let _ = t.attr(term2::Attr::Bold);
+    write!(t, "problems: ")?;
+    let _ = t.reset();
+    if status.problems.is_empty() {
+        let _ = t.style(term2::Style::Good);
+        writeln!(t, "none")?;
+        let _ = t.reset();
+    } else {
+        let _ = t.style(term2::Style::Warning);
+        writeln!(t, "{}", doctor::plural(status.problems.len(), "problem"))?;
+        let _ = t.reset();
+        for problem in &status.problems {
+            writeln!(t, "  {}", problem)?;
+        }
+        writeln!(t, "  run `rustup doctor` to see how to fix them")?;
+    }
+    Ok(ExitCode(0))
+}
@@ -2,7 +2,8 @@
 //! that does not fail if `StdoutTerminal` etc can't be constructed, which happens
 //! if TERM isn't defined.
 
-use std::io;
+use std::cell::Cell;
+use std::io::{self, Write};
 use std::ops::Deref;
 use std::sync::Mutex;
 
@@ -13,6 +14,7 @@ pub use term::Terminal;
 
 use crate::currentprocess::filesource::{Isatty, Writer};
 use crate::process;
+use crate::settings::ColorTheme;
 
 mod termhack {
     // Things we should submit to term as improvements: here temporarily.
@@ -99,6 +101,120 @@ mod termhack {
     }
 }
 
+/// Whether to color output, as asked for with `--color`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ColorChoice {
+    /// When writing to a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub(crate) fn choices() -> &'static [&'static str] {
+        &["auto", "always", "never"]
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(Self::Auto),
+            "always" => Some(Self::Always),
+            "never" => Some(Self::Never),
+            _ => None,
+        }
+    }
+}
+
+/// What a piece of output is, which the theme picks the color of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Style {
+    Error,
+    Warning,
+    Verbose,
+    Debug,
+    /// Things being as they should, such as a toolchain being up to date.
+    Good,
+    /// Emphasized text in the documentation rustup prints.
+    Emphasis,
+}
+
+impl Style {
+    /// The color of the style in the current theme, if it has one.
+    pub(crate) fn color(self) -> Option<color::Color> {
+        let theme = COLORS.with(Cell::get).theme;
+        match theme.unwrap_or(ColorTheme::Default) {
+            ColorTheme::Default => Some(match self {
+                Self::Error | Self::Emphasis => color::RED,
+                Self::Warning => color::YELLOW,
+                Self::Verbose => color::MAGENTA,
+                Self::Debug => color::BLUE,
+                Self::Good => color::GREEN,
+            }),
+            // Blue and yellow are told apart with either of the common
+            // kinds of color blindness, where red and green aren't.
+            ColorTheme::Colorblind => Some(match self {
+                Self::Error | Self::Emphasis => color::BRIGHT_MAGENTA,
+                Self::Warning => color::YELLOW,
+                Self::Verbose => color::CYAN,
+                Self::Debug => color::BRIGHT_BLACK,
+                Self::Good => color::BLUE,
+            }),
+            ColorTheme::Monochrome => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Colors {
+    choice: Option<ColorChoice>,
+    /// Until it's read from the settings.
+    theme: Option<ColorTheme>,
+}
+
+// Set for the thread running a command, as each rustup run in the same test
+// binary has its own.
+thread_local! {
+    static COLORS: Cell<Colors> = Cell::new(Colors::default());
+}
+
+/// Sets whether to color output regardless of the environment, or with
+/// `None` leaves it to the environment again.
+pub(crate) fn set_color_choice(choice: Option<ColorChoice>) {
+    COLORS.with(|c| c.set(Colors { choice, ..c.get() }));
+}
+
+pub(crate) fn color_choice_override() -> Option<ColorChoice> {
+    COLORS.with(Cell::get).choice
+}
+
+pub(crate) fn set_color_theme(theme: ColorTheme) {
+    COLORS.with(|c| {
+        c.set(Colors {
+            theme: Some(theme),
+            ..c.get()
+        })
+    });
+}
+
+/// `--color`, or else NO_COLOR and CLICOLOR_FORCE as https://no-color.org
+/// and https://bixense.com/clicolors have them, and CLICOLOR=0.
+fn color_choice() -> ColorChoice {
+    if let Some(choice) = color_choice_override() {
+        return choice;
+    }
+    let process = process();
+    let set = |var: &str| matches!(process.var(var), Ok(value) if !value.is_empty());
+    if set("NO_COLOR") {
+        ColorChoice::Never
+    } else if set("CLICOLOR_FORCE") && process.var("CLICOLOR_FORCE").as_deref() != Ok("0") {
+        ColorChoice::Always
+    } else if process.var("CLICOLOR").as_deref() == Ok("0") {
+        ColorChoice::Never
+    } else {
+        ColorChoice::Auto
+    }
+}
+
 // Decorator to:
 // - Disable all terminal controls on non-tty's
 // - Swallow errors when we try to use features a terminal doesn't have
@@ -125,6 +241,42 @@ impl Isatty for Box<dyn Writer> {
     }
 }
 
+impl<T> AutomationFriendlyTerminal<T>
+where
+    T: io::Write + Isatty,
+{
+    /// Colors what is written next as `style` has it in the theme.
+    pub(crate) fn style(&mut self, style: Style) -> term::Result<()> {
+        match style.color() {
+            Some(color) => term::Terminal::fg(self, color),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether colors and attributes are written, and if so whether they
+    /// were asked for regardless of there being a terminal to show them.
+    fn styling(&self) -> Option<bool> {
+        match color_choice() {
+            ColorChoice::Always => Some(!self.get_ref().isatty()),
+            ColorChoice::Never => None,
+            ColorChoice::Auto if self.get_ref().isatty() => Some(false),
+            ColorChoice::Auto => None,
+        }
+    }
+
+    /// Writes the escape code itself where the terminal, lacking a terminfo
+    /// entry, says it can't style text that is to be styled regardless.
+    fn styled(&mut self, result: term::Result<()>, forced: bool, code: &str) -> term::Result<()> {
+        match result {
+            Err(term::Error::NotSupported) | Err(term::Error::ColorOutOfRange) if forced => {
+                write!(self.0, "\x1b[{}m", code)?;
+                Ok(())
+            }
+            result => swallow_unsupported!(result),
+        }
+    }
+}
+
 impl<T> term::Terminal for AutomationFriendlyTerminal<T>
 where
     T: io::Write + Isatty,
@@ -132,24 +284,34 @@ where
     type Output = T;
 
     fn fg(&mut self, color: color::Color) -> term::Result<()> {
-        if !self.get_ref().isatty() {
-            return Ok(());
-        }
-        swallow_unsupported!(self.0.fg(color))
+        let forced = match self.styling() {
+            Some(forced) => forced,
+            None => return Ok(()),
+        };
+        let result = self.0.fg(color);
+        self.styled(result, forced, &ansi_color(30, color))
     }
 
     fn bg(&mut self, color: color::Color) -> term::Result<()> {
-        if !self.get_ref().isatty() {
-            return Ok(());
-        }
-        swallow_unsupported!(self.0.bg(color))
+        let forced = match self.styling() {
+            Some(forced) => forced,
+            None => return Ok(()),
+        };
+        let result = self.0.bg(color);
+        self.styled(result, forced, &ansi_color(40, color))
     }
 
     fn attr(&mut self, attr: Attr) -> term::Result<()> {
-        if !self.get_ref().isatty() {
-            return Ok(());
+        let forced = match self.styling() {
+            Some(forced) => forced,
+            None => return Ok(()),
+        };
+        let result = self.0.attr(attr);
+        match attr {
+            Attr::Bold => self.styled(result, forced, "1"),
+            Attr::ForegroundColor(color) => self.styled(result, forced, &ansi_color(30, color)),
+            _ => swallow_unsupported!(result),
         }
-        swallow_unsupported!(self.0.attr(attr))
     }
 
     fn supports_attr(&self, attr: Attr) -> bool {
@@ -157,10 +319,12 @@ where
     }
 
     fn reset(&mut self) -> term::Result<()> {
-        if !self.get_ref().isatty() {
-            return Ok(());
-        }
-        swallow_unsupported!(self.0.reset())
+        let forced = match self.styling() {
+            Some(forced) => forced,
+            None => return Ok(()),
+        };
+        let result = self.0.reset();
+        self.styled(result, forced, "0")
     }
 
     /// Returns true if reset is supported.
@@ -206,6 +370,16 @@ where
     }
 }
 
+/// The SGR code for `color` in the range starting at `base`, 30 for the
+/// foreground and 40 for the background.
+fn ansi_color(base: u32, color: color::Color) -> String {
+    if color < 8 {
+        (base + color).to_string()
+    } else {
+        (base + 60 + color % 8).to_string()
+    }
+}
+
 impl<T: Isatty + io::Write> io::Write for AutomationFriendlyTerminal<T> {
     fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
         self.0.write(buf)
@@ -0,0 +1,784 @@
+//! `rustup ui`: a terminal interface for looking through the installed
+//! toolchains and their components and targets, and for installing,
+//! removing and updating them.
+//!
+//! The screen is drawn with ANSI escape sequences, with the terminal in raw
+//! mode so that keys arrive as they are pressed. What a key does is worked
+//! out by `Ui`, which knows nothing of the terminal, and carried out with
+//! the same toolchain APIs as the other commands. While it is, the normal
+//! screen is back, so that their progress is shown just as it is there.
+
+use std::io::{self, Read, Write};
+
+use anyhow::{anyhow, Context, Result};
+
+use super::common;
+use super::history::{self, Operation};
+use crate::dist::manifest::Component;
+use crate::process;
+use crate::toolchain::DistributableToolchain;
+use crate::utils::tty;
+use crate::utils::units::{Size, Unit, UnitMode};
+use crate::utils::utils::{self, ExitCode};
+use crate::Cfg;
+
+/// A toolchain in the list of toolchains.
+#[derive(Debug)]
+struct ToolchainRow {
+    name: String,
+    default: bool,
+    system: bool,
+    size: Option<u64>,
+}
+
+/// A component or target of the open toolchain.
+#[derive(Debug)]
+struct ItemRow {
+    /// The target of a target, and the name of a component.
+    name: String,
+    target: bool,
+    installed: bool,
+    size: Option<u64>,
+    component: Component,
+}
+
+/// The toolchain whose components and targets are shown.
+#[derive(Debug)]
+struct OpenToolchain {
+    name: String,
+    items: Vec<ItemRow>,
+    /// Why there are no components to show, where they can't be managed.
+    note: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Back,
+    Interrupt,
+    Char(char),
+}
+
+/// What `Ui` can't do by itself, for `ui` to carry out.
+#[derive(Clone, Debug, PartialEq)]
+enum Action {
+    Quit,
+    Open(String),
+    Update(String),
+    MakeDefault(String),
+    Uninstall(String),
+    /// Adds the item of the open toolchain at this index.
+    Add(usize),
+    /// Removes the item of the open toolchain at this index.
+    Remove(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Style {
+    Title,
+    Plain,
+    Selected,
+    Help,
+}
+
+struct Ui {
+    toolchains: Vec<ToolchainRow>,
+    open: Option<OpenToolchain>,
+    selected: usize,
+    /// Where the selection was in the list of toolchains while one is open.
+    toolchain_selected: usize,
+    /// The first row on the screen.
+    top: usize,
+    /// A question to answer with 'y' before the action is taken.
+    confirm: Option<(String, Action)>,
+    message: Option<String>,
+}
+
+impl Ui {
+    fn new(toolchains: Vec<ToolchainRow>) -> Self {
+        Self {
+            toolchains,
+            open: None,
+            selected: 0,
+            toolchain_selected: 0,
+            top: 0,
+            confirm: None,
+            message: None,
+        }
+    }
+
+    fn rows(&self) -> usize {
+        match &self.open {
+            Some(open) => open.items.len(),
+            None => self.toolchains.len(),
+        }
+    }
+
+    fn set_toolchains(&mut self, toolchains: Vec<ToolchainRow>) {
+        self.toolchains = toolchains;
+        self.clamp_selection();
+    }
+
+    fn set_open(&mut self, open: OpenToolchain) {
+        if self.open.is_none() {
+            self.toolchain_selected = self.selected;
+            self.selected = 0;
+            self.top = 0;
+        }
+        self.open = Some(open);
+        self.clamp_selection();
+    }
+
+    fn close(&mut self) {
+        self.open = None;
+        self.selected = self.toolchain_selected;
+        self.top = 0;
+        self.clamp_selection();
+    }
+
+    fn clamp_selection(&mut self) {
+        self.selected = self.selected.min(self.rows().saturating_sub(1));
+    }
+
+    fn key(&mut self, key: Key) -> Option<Action> {
+        self.message = None;
+        if key == Key::Interrupt {
+            return Some(Action::Quit);
+        }
+        if let Some((_, action)) = self.confirm.take() {
+            // Anything else is a no.
+            return Some(action).filter(|_| key == Key::Char('y'));
+        }
+
+        match key {
+            Key::Up | Key::Char('k') => self.selected = self.selected.saturating_sub(1),
+            Key::Down | Key::Char('j') => {
+                if self.selected + 1 < self.rows() {
+                    self.selected += 1;
+                }
+            }
+            _ if self.open.is_some() => return self.toolchain_key(key),
+            _ => return self.toolchains_key(key),
+        }
+        None
+    }
+
+    fn toolchains_key(&mut self, key: Key) -> Option<Action> {
+        if let Key::Back | Key::Char('q') = key {
+            return Some(Action::Quit);
+        }
+        let name = self.toolchains.get(self.selected)?.name.clone();
+        match key {
+            Key::Enter | Key::Right | Key::Char('l') => Some(Action::Open(name)),
+            Key::Char('u') => Some(Action::Update(name)),
+            Key::Char('d') => Some(Action::MakeDefault(name)),
+            Key::Char('x') => {
+                let question = format!("uninstall toolchain '{}'? (y/n)", name);
+                self.confirm = Some((question, Action::Uninstall(name)));
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn toolchain_key(&mut self, key: Key) -> Option<Action> {
+        let open = self.open.as_ref()?;
+        match key {
+            Key::Back | Key::Left | Key::Char('h') | Key::Char('q') => {
+                self.close();
+                None
+            }
+            Key::Char('u') => Some(Action::Update(open.name.clone())),
+            Key::Enter | Key::Char(' ') => {
+                let item = open.items.get(self.selected)?;
+                if !item.installed {
+                    return Some(Action::Add(self.selected));
+                }
+                let kind = if item.target { "target" } else { "component" };
+                let question = format!("remove {} '{}'? (y/n)", kind, item.name);
+                self.confirm = Some((question, Action::Remove(self.selected)));
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// The lines that fill a screen of this size.
+    fn lines(&mut self, width: usize, height: usize) -> Vec<(Style, String)> {
+        let mut lines = Vec::new();
+        let (title, help) = match &self.open {
+            Some(open) => (
+                format!("rustup ui: toolchain '{}'", open.name),
+                "enter: add or remove  u: update  esc: back",
+            ),
+            None => (
+                "rustup ui: installed toolchains".to_owned(),
+                "enter: open  u: update  d: make default  x: uninstall  q: quit",
+            ),
+        };
+        lines.push((Style::Title, title));
+        lines.push((Style::Plain, String::new()));
+
+        // The title and the help, each with a blank line.
+        let space = height.saturating_sub(4).max(1);
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + space {
+            self.top = self.selected + 1 - space;
+        }
+        let rows = match &self.open {
+            Some(open) => {
+                let width = open.items.iter().map(|i| i.name.len()).max();
+                let rows = open.items.iter().map(|item| {
+                    format!(
+                        "[{}] {:<width$}  {:<9}  {}",
+                        if item.installed { 'x' } else { ' ' },
+                        item.name,
+                        if item.target { "target" } else { "component" },
+                        format_size(item.size),
+                        width = width.unwrap_or(0),
+                    )
+                });
+                let mut rows: Vec<_> = rows.collect();
+                if let Some(note) = &open.note {
+                    rows.push(note.clone());
+                }
+                rows
+            }
+            None if self.toolchains.is_empty() => vec!["no installed toolchains".to_owned()],
+            None => {
+                let width = self.toolchains.iter().map(|t| t.name.len()).max();
+                self.toolchains
+                    .iter()
+                    .map(|toolchain| {
+                        format!(
+                            "{:<width$}  {:>10}{}{}",
+                            toolchain.name,
+                            format_size(toolchain.size),
+                            if toolchain.default { "  (default)" } else { "" },
+                            if toolchain.system { "  (system)" } else { "" },
+                            width = width.unwrap_or(0),
+                        )
+                    })
+                    .collect()
+            }
+        };
+        let selectable = self.rows();
+        for (i, row) in rows.into_iter().enumerate().skip(self.top).take(space) {
+            let style = if i == self.selected && i < selectable {
+                Style::Selected
+            } else {
+                Style::Plain
+            };
+            lines.push((style, row));
+        }
+
+        while lines.len() < height.saturating_sub(1) {
+            lines.push((Style::Plain, String::new()));
+        }
+        let bottom = match (&self.confirm, &self.message) {
+            (Some((question, _)), _) => (Style::Title, question.clone()),
+            (None, Some(message)) => (Style::Title, message.clone()),
+            (None, None) => (Style::Help, help.to_owned()),
+        };
+        lines.push(bottom);
+        for (_, line) in &mut lines {
+            if let Some((end, _)) = line.char_indices().nth(width) {
+                line.truncate(end);
+            }
+        }
+        lines
+    }
+}
+
+fn format_size(size: Option<u64>) -> String {
+    match size {
+        Some(size) => Size::new(size as usize, Unit::B, UnitMode::Norm)
+            .to_string()
+            .trim_start()
+            .to_owned(),
+        None => String::new(),
+    }
+}
+
+pub(crate) fn ui(cfg: &Cfg) -> Result<ExitCode> {
+    if common::non_interactive() {
+        return Err(common::non_interactive_error(
+            "which changes to make in `rustup ui`",
+            "terminal-required",
+        ));
+    }
+    if !tty::stdin_isatty() || !tty::stdout_isatty() {
+        return Err(anyhow!(
+            "rustup ui needs a terminal; use `rustup toolchain list --verbose`, `rustup component list` and `rustup target list` in scripts"
+        ));
+    }
+
+    let mut ui = Ui::new(toolchains(cfg)?);
+    let mut screen = Screen::enter()?;
+    loop {
+        let (width, height) = terminal_size();
+        screen.draw(&ui.lines(width, height))?;
+        let action = match screen.read_key()? {
+            Some(key) => ui.key(key),
+            None => None,
+        };
+        let action = match action {
+            Some(Action::Quit) => break,
+            Some(Action::Open(name)) => {
+                ui.set_open(open_toolchain(cfg, &name)?);
+                continue;
+            }
+            Some(action) => action,
+            None => continue,
+        };
+
+        // Anything which changes the toolchains is done on the normal
+        // screen, where its progress shows.
+        let done = done_message(&ui, &action);
+        drop(screen);
+        let result = act(cfg, &ui, &action);
+        if let Err(e) = &result {
+            err!("{:?}", e);
+        }
+        write!(
+            process().stdout(),
+            "\npress any key to go back to rustup ui"
+        )?;
+        process().stdout().flush()?;
+        screen = Screen::enter()?;
+        screen.read_key()?;
+
+        ui.message = result.ok().map(|()| done);
+        ui.set_toolchains(toolchains(cfg)?);
+        let open = ui.open.as_ref().map(|open| open.name.clone());
+        if let Some(name) = open {
+            ui.set_open(open_toolchain(cfg, &name)?);
+        }
+    }
+    Ok(ExitCode(0))
+}
+
+fn done_message(ui: &Ui, action: &Action) -> String {
+    let item = |index: &usize| {
+        let open = ui
+            .open
+            .as_ref()
+            .expect("components are only changed when open");
+        &open.items[*index].name
+    };
+    match action {
+        Action::Update(name) => format!("updated '{}'", name),
+        Action::MakeDefault(name) => format!("'{}' is now the default toolchain", name),
+        Action::Uninstall(name) => format!("uninstalled '{}'", name),
+        Action::Add(index) => format!("added '{}'", item(index)),
+        Action::Remove(index) => format!("removed '{}'", item(index)),
+        Action::Quit | Action::Open(_) => unreachable!(),
+    }
+}
+
+fn act(cfg: &Cfg, ui: &Ui, action: &Action) -> Result<()> {
+    match action {
+        Action::Update(name) => {
+            let toolchain = cfg.get_toolchain(name, false)?;
+            let distributable = DistributableToolchain::new(&toolchain)?;
+            let status = distributable.install_from_dist(false, false, &[], &[], None);
+            if let Ok(status) = &status {
+                history::record_update(cfg, name, status);
+            }
+            writeln!(process().stdout())?;
+            common::show_channel_update(cfg, name, status)
+        }
+        Action::MakeDefault(name) => {
+            let previous = cfg.get_default()?;
+            cfg.get_toolchain_to_save(name)?.make_default()?;
+            if previous.as_ref() != Some(name) {
+                let toolchain = name.clone();
+                history::record(
+                    cfg,
+                    Operation::SetDefault {
+                        toolchain,
+                        previous,
+                    },
+                );
+            }
+            Ok(())
+        }
+        Action::Uninstall(name) => {
+            if cfg.remove_toolchain_name(name)? {
+                return Ok(());
+            }
+            cfg.get_toolchain(name, false)?.remove()?;
+            let toolchain = name.clone();
+            history::record(cfg, Operation::UninstallToolchain { toolchain });
+            Ok(())
+        }
+        Action::Add(index) | Action::Remove(index) => {
+            let open = ui
+                .open
+                .as_ref()
+                .expect("components are only changed when open");
+            let item = &open.items[*index];
+            let toolchain = cfg.get_toolchain(&open.name, false)?;
+            let distributable = DistributableToolchain::new_for_components(&toolchain)?;
+            let before = history::installed_components(&toolchain);
+            if let Action::Add(_) = action {
+                distributable.add_component(item.component.clone())?;
+            } else {
+                distributable.remove_component(item.component.clone())?;
+            }
+            history::record_components(cfg, &toolchain, before);
+            Ok(())
+        }
+        Action::Quit | Action::Open(_) => unreachable!(),
+    }
+}
+
+fn toolchains(cfg: &Cfg) -> Result<Vec<ToolchainRow>> {
+    let default = cfg.get_default()?;
+    let system = cfg.list_system_toolchains()?;
+    let mut names = cfg.list_toolchains()?;
+    names.extend(system.iter().cloned());
+    utils::toolchain_sort(&mut names);
+    Ok(names
+        .into_iter()
+        .map(|name| ToolchainRow {
+            default: default.as_ref() == Some(&name),
+            system: system.contains(&name),
+            size: utils::dir_size(&cfg.toolchain_path(&name)).ok(),
+            name,
+        })
+        .collect())
+}
+
+fn open_toolchain(cfg: &Cfg, name: &str) -> Result<OpenToolchain> {
+    let toolchain = cfg.get_toolchain(name, false)?;
+    let mut open = OpenToolchain {
+        name: name.to_owned(),
+        items: Vec::new(),
+        note: None,
+    };
+    let statuses = DistributableToolchain::new_for_components(&toolchain)
+        .and_then(|distributable| Ok((distributable.list_components()?, distributable)));
+    let (statuses, distributable) = match statuses {
+        Ok(statuses) => statuses,
+        Err(e) => {
+            open.note = Some(format!("{:#}", e));
+            return Ok(open);
+        }
+    };
+    for status in statuses {
+        if !status.installed && !status.available {
+            continue;
+        }
+        let target = status.component.short_name_in_manifest() == "rust-std";
+        let name = match (&status.component.target, target) {
+            (Some(triple), true) => triple.to_string(),
+            _ => status.name.clone(),
+        };
+        let size = if status.installed {
+            distributable.component_size(&status.component)?
+        } else {
+            None
+        };
+        open.items.push(ItemRow {
+            name,
+            target,
+            installed: status.installed,
+            size,
+            component: status.component,
+        });
+    }
+    // Components first, then targets.
+    open.items.sort_by_key(|item| item.target);
+    Ok(open)
+}
+
+/// The terminal in raw mode on its alternate screen, until dropped.
+struct Screen {
+    saved: Mode,
+}
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        let saved = raw_mode().context("could not set up the terminal for rustup ui")?;
+        let mut out = process().stdout();
+        // The alternate screen, without the cursor.
+        write!(out, "\x1b[?1049h\x1b[?25l")?;
+        out.flush()?;
+        Ok(Self { saved })
+    }
+
+    fn draw(&self, lines: &[(Style, String)]) -> Result<()> {
+        let mut screen = String::from("\x1b[H\x1b[2J");
+        for (i, (style, line)) in lines.iter().enumerate() {
+            if i > 0 {
+                screen.push_str("\r\n");
+            }
+            screen.push_str(match style {
+                Style::Title => "\x1b[1m",
+                Style::Selected => "\x1b[7m",
+                Style::Help => "\x1b[2m",
+                Style::Plain => "",
+            });
+            screen.push_str(line);
+            if *style != Style::Plain {
+                screen.push_str("\x1b[0m");
+            }
+        }
+        let mut out = process().stdout();
+        out.write_all(screen.as_bytes())?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn read_key(&self) -> Result<Option<Key>> {
+        // An escape sequence arrives all at once.
+        let mut buf = [0; 16];
+        let n = io::stdin().read(&mut buf)?;
+        Ok(parse_key(&buf[..n]))
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let mut out = process().stdout();
+        let _ = write!(out, "\x1b[?25h\x1b[?1049l");
+        let _ = out.flush();
+        set_mode(&self.saved);
+    }
+}
+
+fn parse_key(bytes: &[u8]) -> Option<Key> {
+    Some(match bytes {
+        [0x1b, b'[', b'A', ..] | [0x1b, b'O', b'A', ..] => Key::Up,
+        [0x1b, b'[', b'B', ..] | [0x1b, b'O', b'B', ..] => Key::Down,
+        [0x1b, b'[', b'C', ..] | [0x1b, b'O', b'C', ..] => Key::Right,
+        [0x1b, b'[', b'D', ..] | [0x1b, b'O', b'D', ..] => Key::Left,
+        [0x1b] | [0x7f] | [0x08] => Key::Back,
+        [b'\r', ..] | [b'\n', ..] => Key::Enter,
+        [0x03, ..] => Key::Interrupt,
+        [c, ..] if c.is_ascii_graphic() || *c == b' ' => Key::Char(*c as char),
+        _ => return None,
+    })
+}
+
+#[cfg(unix)]
+type Mode = libc::termios;
+
+#[cfg(unix)]
+fn raw_mode() -> io::Result<Mode> {
+    unsafe {
+        let mut saved = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        libc::cfmakeraw(&mut raw);
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(saved)
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(mode: &Mode) {
+    unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, mode);
+    }
+}
+
+#[cfg(unix)]
+fn terminal_size() -> (usize, usize) {
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 {
+            return (size.ws_col as usize, size.ws_row as usize);
+        }
+    }
+    (80, 24)
+}
+
+/// The modes of the console's input and output.
+#[cfg(windows)]
+type Mode = (u32, u32);
+
+#[cfg(windows)]
+fn raw_mode() -> io::Result<Mode> {
+    use winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::winbase::{STD_INPUT_HANDLE, STD_OUTPUT_HANDLE};
+    use winapi::um::wincon::{
+        ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT,
+        ENABLE_VIRTUAL_TERMINAL_INPUT, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+    };
+
+    unsafe {
+        let input = GetStdHandle(STD_INPUT_HANDLE);
+        let output = GetStdHandle(STD_OUTPUT_HANDLE);
+        let (mut input_mode, mut output_mode) = (0, 0);
+        if GetConsoleMode(input, &mut input_mode) == 0
+            || GetConsoleMode(output, &mut output_mode) == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        // Keys arrive as they are pressed, Ctrl-C included, and as the same
+        // escape sequences as elsewhere.
+        let raw_input = (input_mode
+            & !(ENABLE_ECHO_INPUT | ENABLE_LINE_INPUT | ENABLE_PROCESSED_INPUT))
+            | ENABLE_VIRTUAL_TERMINAL_INPUT;
+        if SetConsoleMode(input, raw_input) == 0
+            || SetConsoleMode(output, output_mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) == 0
+        {
+            let err = io::Error::last_os_error();
+            SetConsoleMode(input, input_mode);
+            return Err(err);
+        }
+        Ok((input_mode, output_mode))
+    }
+}
+
+#[cfg(windows)]
+fn set_mode(mode: &Mode) {
+    use winapi::um::consoleapi::SetConsoleMode;
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::winbase::{STD_INPUT_HANDLE, STD_OUTPUT_HANDLE};
+
+    unsafe {
+        SetConsoleMode(GetStdHandle(STD_INPUT_HANDLE), mode.0);
+        SetConsoleMode(GetStdHandle(STD_OUTPUT_HANDLE), mode.1);
+    }
+}
+
+#[cfg(windows)]
+fn terminal_size() -> (usize, usize) {
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::winbase::STD_OUTPUT_HANDLE;
+    use winapi::um::wincon::GetConsoleScreenBufferInfo;
+
+    unsafe {
+        let mut info = std::mem::zeroed();
+        if GetConsoleScreenBufferInfo(GetStdHandle(STD_OUTPUT_HANDLE), &mut info) != 0 {
+            let window = info.srWindow;
+            let width = (window.Right - window.Left + 1) as usize;
+            let height = (window.Bottom - window.Top + 1) as usize;
+            return (width, height);
+        }
+    }
+    (80, 24)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toolchain(name: &str) -> ToolchainRow {
+        ToolchainRow {
+            name: name.to_owned(),
+            default: name == "stable",
+            system: false,
+            size: Some(1024),
+        }
+    }
+
+    fn item(name: &str, installed: bool) -> ItemRow {
+        ItemRow {
+            name: name.to_owned(),
+            target: false,
+            installed,
+            size: None,
+            component: Component::new(name.to_owned(), None, false),
+        }
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(parse_key(b"\x1b[A"), Some(Key::Up));
+        assert_eq!(parse_key(b"\x1bOB"), Some(Key::Down));
+        assert_eq!(parse_key(b"\x1b"), Some(Key::Back));
+        assert_eq!(parse_key(b"\r"), Some(Key::Enter));
+        assert_eq!(parse_key(b"\x03"), Some(Key::Interrupt));
+        assert_eq!(parse_key(b"x"), Some(Key::Char('x')));
+        assert_eq!(parse_key(b"\x1b[5~"), None);
+        assert_eq!(parse_key(b""), None);
+    }
+
+    #[test]
+    fn moves_and_opens() {
+        let mut ui = Ui::new(vec![toolchain("beta"), toolchain("stable")]);
+        assert_eq!(ui.key(Key::Up), None);
+        assert_eq!(ui.selected, 0);
+        ui.key(Key::Down);
+        ui.key(Key::Char('j'));
+        assert_eq!(ui.selected, 1);
+        assert_eq!(
+            ui.key(Key::Char('u')),
+            Some(Action::Update("stable".into()))
+        );
+        assert_eq!(ui.key(Key::Enter), Some(Action::Open("stable".into())));
+
+        ui.set_open(OpenToolchain {
+            name: "stable".into(),
+            items: vec![item("clippy", false), item("rustfmt", true)],
+            note: None,
+        });
+        assert_eq!(ui.selected, 0);
+        assert_eq!(ui.key(Key::Enter), Some(Action::Add(0)));
+        ui.key(Key::Down);
+        // Removing is asked about first.
+        assert_eq!(ui.key(Key::Char(' ')), None);
+        assert_eq!(ui.key(Key::Char('n')), None);
+        assert_eq!(ui.key(Key::Enter), None);
+        assert_eq!(ui.key(Key::Char('y')), Some(Action::Remove(1)));
+
+        ui.key(Key::Back);
+        assert!(ui.open.is_none());
+        assert_eq!(ui.selected, 1);
+        assert_eq!(ui.key(Key::Char('x')), None);
+        assert_eq!(
+            ui.key(Key::Char('y')),
+            Some(Action::Uninstall("stable".into()))
+        );
+        assert_eq!(ui.key(Key::Char('q')), Some(Action::Quit));
+    }
+
+    #[test]
+    fn draws_and_scrolls() {
+        let names: Vec<_> = (0..10).map(|i| format!("nightly-2021-01-0{}", i)).collect();
+        let mut ui = Ui::new(names.iter().map(|name| toolchain(name)).collect());
+        let lines = ui.lines(40, 8);
+        assert_eq!(lines.len(), 8);
+        assert_eq!(
+            lines[0],
+            (Style::Title, "rustup ui: installed toolchains".into())
+        );
+        assert_eq!(
+            lines[2],
+            (Style::Selected, "nightly-2021-01-00     1.0 KiB".into())
+        );
+        assert_eq!(lines[6], (Style::Plain, String::new()));
+        assert_eq!(lines[7].0, Style::Help);
+        assert_eq!(lines[7].1.len(), 40);
+
+        for _ in 0..9 {
+            ui.key(Key::Down);
+        }
+        let lines = ui.lines(40, 8);
+        assert_eq!(ui.top, 6);
+        assert_eq!(lines[2].1, "nightly-2021-01-06     1.0 KiB");
+        assert_eq!(lines[5].0, Style::Selected);
+        assert_eq!(lines[5].1, "nightly-2021-01-09     1.0 KiB");
+
+        let mut ui = Ui::new(Vec::new());
+        assert_eq!(ui.key(Key::Enter), None);
+        assert_eq!(
+            ui.lines(40, 8)[2],
+            (Style::Plain, "no installed toolchains".into())
+        );
+    }
+}
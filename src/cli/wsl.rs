@@ -0,0 +1,144 @@
+//! `rustup wsl-shims`: running the tools of a Windows install of rustup
+//! from inside WSL, so that one install serves both.
+//!
+//! WSL can run Windows executables directly, but a Windows tool can't make
+//! sense of a Linux path such as `/home/me/project/src/main.rs`, and it
+//! only sees the environment variables named in WSLENV. So rather than the
+//! `.exe` files being run as they are, each gets a small shell script that
+//! translates its absolute path arguments with `wslpath` and passes on the
+//! variables that matter to cargo and rustc before running it.
+
+use std::env::consts::EXE_SUFFIX;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use crate::process;
+use crate::utils::utils::{self, ExitCode};
+use crate::utils::Notification;
+
+/// The line that marks a script as one of these shims.
+const SHIM_MARKER: &str = "# rustup wsl shim";
+
+/// The variables the shims pass on to the Windows tools. `/p` asks for the
+/// value to be translated as a path.
+const SHIM_ENV: &[&str] = &[
+    "RUSTUP_TOOLCHAIN",
+    "RUSTFLAGS",
+    "RUSTDOCFLAGS",
+    "RUST_BACKTRACE",
+    "RUST_LOG",
+    "CARGO_BUILD_TARGET",
+    "CARGO_TARGET_DIR/p",
+];
+
+fn is_wsl() -> bool {
+    process().var_os("WSL_DISTRO_NAME").is_some()
+        || Path::new("/proc/sys/fs/binfmt_misc/WSLInterop").exists()
+}
+
+/// Writes a shim into `dir` for each tool in the `bin` directory of the
+/// Windows `cargo_home`, replacing any shims written before.
+pub(crate) fn install(cargo_home: &Path, dir: &Path) -> Result<ExitCode> {
+    if !is_wsl() || !EXE_SUFFIX.is_empty() {
+        return Err(anyhow!("WSL shims can only be installed from inside WSL"));
+    }
+    let cargo_home = utils::current_dir()?.join(cargo_home);
+    let bin = cargo_home.join("bin");
+    if !utils::is_file(bin.join("rustup.exe")) {
+        return Err(anyhow!(
+            "'{}' doesn't look like the CARGO_HOME of rustup on Windows: it has no 'bin/rustup.exe'",
+            cargo_home.display()
+        ));
+    }
+
+    utils::ensure_dir_exists("WSL shims", dir, &|_: Notification<'_>| ())?;
+    remove_shims(dir)?;
+    let mut tools = Vec::new();
+    for entry in utils::read_dir("Windows CARGO_HOME", &bin)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // rustup itself is left to the Linux one, which manages the shims.
+        if name.len() > 4 && name.to_lowercase().ends_with(".exe") {
+            let tool = &name[..name.len() - 4];
+            if tool != "rustup" {
+                tools.push((tool.to_owned(), entry.path()));
+            }
+        }
+    }
+    tools.sort();
+
+    let mut count = 0;
+    for (tool, exe) in tools {
+        let path = dir.join(&tool);
+        if fs::symlink_metadata(&path).is_ok() {
+            warn!(
+                "'{}' is in the way of the shim for '{}'",
+                path.display(),
+                tool
+            );
+            continue;
+        }
+        utils::write_file("WSL shim", &path, &shim(&tool, &exe))?;
+        utils::make_executable(&path)?;
+        count += 1;
+    }
+
+    info!("installed {} shims in '{}'", count, dir.display());
+    let path = process().var_os("PATH").unwrap_or_default();
+    if !std::env::split_paths(&path).any(|p| p == dir) {
+        info!("add '{}' to PATH to use them", dir.display());
+    }
+    Ok(ExitCode(0))
+}
+
+/// Removes the shims `install` wrote into `dir`.
+pub(crate) fn remove(dir: &Path) -> Result<ExitCode> {
+    let count = remove_shims(dir)?;
+    info!("removed {} shims from '{}'", count, dir.display());
+    Ok(ExitCode(0))
+}
+
+fn remove_shims(dir: &Path) -> Result<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(0),
+    };
+    let mut count = 0;
+    for entry in entries {
+        let path = entry?.path();
+        let is_shim = fs::read_to_string(&path)
+            .map(|contents| contents.lines().nth(1) == Some(SHIM_MARKER))
+            .unwrap_or(false);
+        if is_shim {
+            utils::remove_file("WSL shim", &path)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn shim(tool: &str, exe: &Path) -> String {
+    let exe = exe.to_string_lossy().replace('\'', "'\\''");
+    format!(
+        r#"#!/bin/sh
+{}
+# Runs the Windows '{}' with its absolute path arguments translated.
+for arg do
+    shift
+    case "$arg" in
+        /*) arg=$(wslpath -w "$arg" 2>/dev/null || printf '%s\n' "$arg") ;;
+    esac
+    set -- "$@" "$arg"
+done
+WSLENV="${{WSLENV:+$WSLENV:}}{}"
+export WSLENV
+exec '{}' "$@"
+"#,
+        SHIM_MARKER,
+        tool,
+        SHIM_ENV.join(":"),
+        exe
+    )
+}
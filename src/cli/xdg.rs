@@ -0,0 +1,92 @@
+//! `rustup migrate xdg`: moving a ~/.rustup into the XDG base directories,
+//! the settings to `$XDG_CONFIG_HOME/rustup`, the downloads and other caches
+//! to `$XDG_CACHE_HOME/rustup`, and everything else to
+//! `$XDG_DATA_HOME/rustup`. Once ~/.rustup is gone and the data directory is
+//! there, rustup uses that layout for as long as RUSTUP_HOME isn't set.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::self_update;
+use crate::process;
+use crate::utils::utils::{self, ExitCode, XdgDirs};
+use crate::utils::Notification;
+use crate::Cfg;
+
+/// What goes to the cache directory, being downloads, temporary files and
+/// answers kept to save working them out again.
+static CACHES: &[&str] = &[
+    "downloads",
+    "tmp",
+    "proxy-cache",
+    "override-hook",
+    "channel-bounds",
+    "release-notes.md",
+    "update-check",
+    "update-notices",
+];
+
+pub(crate) fn migrate(cfg: &Cfg) -> Result<ExitCode> {
+    if cfg!(windows) {
+        bail!("the XDG layout is only used on Unix");
+    }
+    if utils::xdg_layout().is_some() {
+        info!("already using the XDG layout");
+        return Ok(ExitCode(0));
+    }
+    if process()
+        .var_os("RUSTUP_HOME")
+        .map(|home| !home.is_empty())
+        .unwrap_or(false)
+    {
+        bail!("the XDG layout is only used without RUSTUP_HOME; unset it first");
+    }
+    let dirs = XdgDirs::from_env().ok_or_else(|| anyhow!("could not find the home directory"))?;
+    let home = &cfg.base_dir;
+    if utils::is_directory(&dirs.data) && fs::read_dir(&dirs.data)?.next().is_some() {
+        bail!("'{}' already exists", dirs.data.display());
+    }
+
+    {
+        let _lock = cfg.lock_metadata()?;
+        for dir in &[&dirs.config, &dirs.cache, &dirs.data] {
+            utils::ensure_dir_exists("XDG", dir, &|_: Notification<'_>| {})?;
+        }
+        for entry in utils::read_dir("rustup home", home)? {
+            let entry = entry.context("could not read the rustup home")?;
+            let name = entry.file_name();
+            let to = match name.to_str() {
+                Some(n) if n == "settings.toml" || n.starts_with("settings.toml.") => &dirs.config,
+                Some(n) if CACHES.contains(&n) => &dirs.cache,
+                _ => &dirs.data,
+            };
+            move_entry(&entry.path(), &to.join(&name))?;
+        }
+    }
+    fs::remove_dir(home).with_context(|| format!("could not remove '{}'", home.display()))?;
+
+    info!("settings moved to '{}'", dirs.config.display());
+    info!("caches moved to '{}'", dirs.cache.display());
+    info!("toolchains and the rest moved to '{}'", dirs.data.display());
+    for rc in self_update::do_remove_rustup_home_exports(home)? {
+        info!("commented out setting RUSTUP_HOME in '{}'", rc.display());
+    }
+    Ok(ExitCode(0))
+}
+
+/// Moves a file or directory, copying it where it can't be renamed, as from
+/// one file system to another.
+fn move_entry(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    if utils::is_directory(from) {
+        utils::copy_dir(from, to, &|_: Notification<'_>| {})?;
+        utils::remove_dir("rustup home", from, &|_: Notification<'_>| {})
+    } else {
+        utils::copy_file(from, to)?;
+        utils::remove_file("rustup home", from)
+    }
+}
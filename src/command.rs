@@ -7,6 +7,19 @@ use anyhow::{Context, Result};
 use crate::errors::*;
 use crate::utils::utils::ExitCode;
 
+/// Whether Ctrl-C was pressed while a command run by `run_command_for_dir`
+/// was running.
+#[cfg(windows)]
+static INTERRUPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(windows)]
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst)
+}
+
+/// Runs `cmd` in place of rustup. On Unix it is exec'd, so that a build
+/// doesn't have a rustup process waiting on every tool it runs and signals
+/// reach the tool directly; anything rustup has to do must be done before.
 pub(crate) fn run_command_for_dir<S: AsRef<OsStr>>(
     mut cmd: Command,
     arg0: &str,
@@ -32,11 +45,19 @@ pub(crate) fn run_command_for_dir<S: AsRef<OsStr>>(
 
     #[cfg(windows)]
     fn exec(cmd: &mut Command) -> io::Result<ExitCode> {
+        use std::sync::atomic::Ordering;
+
         use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
         use winapi::um::consoleapi::SetConsoleCtrlHandler;
 
-        unsafe extern "system" fn ctrlc_handler(_: DWORD) -> BOOL {
-            // Do nothing. Let the child process handle it.
+        const CTRL_C_EVENT: DWORD = 0;
+        const CTRL_BREAK_EVENT: DWORD = 1;
+
+        unsafe extern "system" fn ctrlc_handler(event: DWORD) -> BOOL {
+            // Do nothing but note it. Let the child process handle it.
+            if event == CTRL_C_EVENT || event == CTRL_BREAK_EVENT {
+                INTERRUPTED.store(true, Ordering::SeqCst);
+            }
             TRUE
         }
         unsafe {
@@ -48,6 +69,8 @@ pub(crate) fn run_command_for_dir<S: AsRef<OsStr>>(
             }
         }
         let status = cmd.status()?;
+        // The exit code, which also tells of crashes such as access
+        // violations, is passed on unchanged.
         Ok(ExitCode(status.code().unwrap()))
     }
 }
@@ -1,18 +1,29 @@
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::fmt::{self, Display};
+use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, bail, Context, Result};
+use download::IpVersion;
 use pgp::{Deserializable, SignedPublicKey};
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use thiserror::Error as ThisError;
 
+use crate::cli::common::{can_prompt, read_line};
 use crate::cli::self_update::SelfUpdateMode;
 use crate::dist::download::DownloadCfg;
+use crate::dist::download_log::DownloadLog;
+use crate::dist::manifest::Component;
+use crate::dist::manifestation::CONFIG_FILE;
+use crate::dist::prefix::InstallPrefix;
 use crate::dist::{
     dist::{self, Profile},
     temp,
@@ -20,10 +31,19 @@ use crate::dist::{
 use crate::errors::RustupError;
 use crate::fallback_settings::FallbackSettings;
 use crate::notifications::*;
+use crate::platform;
+use crate::policy::{policy_path, Policy};
 use crate::process;
-use crate::settings::{Settings, SettingsFile, DEFAULT_METADATA_VERSION};
+use crate::settings::{
+    AutoInstallComponents, AutoInstallTargets, ColorTheme, NetworkSettings, ProjectSettings,
+    Settings, SettingsFile, ShimMode, ToolchainFileSearch, UsageStats, VirtualToolchain,
+    DEFAULT_METADATA_VERSION, PROJECT_SETTINGS_FILE,
+};
 use crate::toolchain::{DistributableToolchain, Toolchain, UpdateStatus};
+use crate::usage_log::{self, UsageLog};
+use crate::utils::lock::{FileLock, ToolchainLock};
 use crate::utils::utils;
+use crate::{DUP_TOOLS, TOOLS};
 
 #[derive(Debug, ThisError)]
 enum OverrideFileConfigError {
@@ -38,21 +58,121 @@ enum OverrideFileConfigError {
 #[derive(Debug, Default, Deserialize, PartialEq, Eq)]
 struct OverrideFile {
     toolchain: ToolchainSection,
+    /// Components and targets wanted only on some hosts, keyed by a
+    /// `cfg(...)` condition or a host triple.
+    #[serde(default)]
+    target: BTreeMap<String, PlatformSection>,
 }
 
 impl OverrideFile {
     fn is_empty(&self) -> bool {
         self.toolchain.is_empty()
     }
+
+    /// Adds the components and targets of the `[target]` sections which
+    /// apply to `host` to the toolchain's own.
+    fn for_host(mut self, host: &dist::TargetTriple) -> Result<Self> {
+        for (spec, section) in std::mem::take(&mut self.target) {
+            if platform::matches(&spec, host)? {
+                let toolchain = &mut self.toolchain;
+                toolchain.components = merge_lists(toolchain.components.take(), section.components);
+                toolchain.targets = merge_lists(toolchain.targets.take(), section.targets);
+            }
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+struct PlatformSection {
+    components: Option<ToolchainFileList>,
+    targets: Option<ToolchainFileList>,
+}
+
+/// The components or targets in a toolchain file. A plain list, or one
+/// given as `{ add = [...] }`, adds to those of the file inherited from and
+/// of the `[toolchain]` section; one given as `{ replace = [...] }` is used
+/// instead of them.
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "ListSpec")]
+struct ToolchainFileList {
+    names: Vec<String>,
+    replace: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListSpec {
+    Plain(Vec<String>),
+    Table(ListTable),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListTable {
+    add: Option<Vec<String>>,
+    replace: Option<Vec<String>>,
+}
+
+impl TryFrom<ListSpec> for ToolchainFileList {
+    type Error = String;
+
+    fn try_from(spec: ListSpec) -> std::result::Result<Self, Self::Error> {
+        match spec {
+            ListSpec::Plain(names) => Ok(names.into()),
+            ListSpec::Table(ListTable {
+                add: Some(names),
+                replace: None,
+            }) => Ok(names.into()),
+            ListSpec::Table(ListTable {
+                add: None,
+                replace: Some(names),
+            }) => Ok(Self {
+                names,
+                replace: true,
+            }),
+            ListSpec::Table(_) => Err("expected either `add` or `replace`".to_owned()),
+        }
+    }
+}
+
+impl From<Vec<String>> for ToolchainFileList {
+    fn from(names: Vec<String>) -> Self {
+        Self {
+            names,
+            replace: false,
+        }
+    }
+}
+
+impl Display for ToolchainFileList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.replace {
+            write!(f, "{{ replace = {:?} }}", self.names)
+        } else {
+            write!(f, "{:?}", self.names)
+        }
+    }
 }
 
 #[derive(Debug, Default, Deserialize, PartialEq, Eq)]
 struct ToolchainSection {
     channel: Option<String>,
     path: Option<PathBuf>,
-    components: Option<Vec<String>>,
-    targets: Option<Vec<String>>,
+    components: Option<ToolchainFileList>,
+    targets: Option<ToolchainFileList>,
     profile: Option<String>,
+    /// The SHA-256 of the channel manifest the toolchain must be installed
+    /// from, so that anything else the server sends is refused.
+    #[serde(rename = "manifest-sha256")]
+    manifest_sha256: Option<String>,
+    /// The server to install from, in place of the default one.
+    #[serde(rename = "dist-server")]
+    dist_server: Option<String>,
+    /// Whether to start from the toolchain file in a parent directory, such
+    /// as a workspace's, and only add to or change what it says.
+    #[serde(default)]
+    inherit: bool,
 }
 
 impl ToolchainSection {
@@ -61,9 +181,249 @@ impl ToolchainSection {
             && self.components.is_none()
             && self.targets.is_none()
             && self.path.is_none()
+            && !self.inherit
+    }
+
+    /// Fills in what this section leaves out from `parent`, the section it
+    /// inherits from, which was found in `parent_dir`. Components and
+    /// targets are added to the parent's rather than replacing them.
+    fn inherit_from(mut self, parent: Self, parent_dir: &Path) -> Self {
+        if self.channel.is_none() && self.path.is_none() {
+            self.channel = parent.channel;
+            // A relative path is relative to the file it was written in.
+            self.path = parent.path.map(|path| parent_dir.join(path));
+            if self.manifest_sha256.is_none() {
+                self.manifest_sha256 = parent.manifest_sha256;
+            }
+        }
+        if self.dist_server.is_none() {
+            self.dist_server = parent.dist_server;
+        }
+        self.components = merge_lists(parent.components, self.components);
+        self.targets = merge_lists(parent.targets, self.targets);
+        if self.profile.is_none() {
+            self.profile = parent.profile;
+        }
+        self.inherit = false;
+        self
+    }
+}
+
+/// How many seconds the override hook's answer for a directory is used,
+/// unless the settings say otherwise.
+const DEFAULT_OVERRIDE_HOOK_TTL: u64 = 60;
+
+/// How many directories the proxies remember the toolchain for.
+const PROXY_CACHE_MAX_ENTRIES: usize = 1000;
+
+/// The file in the proxy cache for a directory, and the fingerprint it has
+/// to start with for the toolchain it names to still be the one chosen.
+struct ProxyCacheEntry {
+    file: PathBuf,
+    fingerprint: String,
+}
+
+impl ProxyCacheEntry {
+    fn read(&self) -> Option<String> {
+        let contents = utils::read_file("proxy cache", &self.file).ok()?;
+        let mut lines = contents.splitn(2, '\n');
+        if lines.next()? != self.fingerprint {
+            return None;
+        }
+        lines.next().map(str::to_owned)
+    }
+}
+
+/// The channel which stands for the `rust-version` in Cargo.toml.
+pub(crate) const MSRV_CHANNEL: &str = "msrv";
+
+impl ToolchainSection {
+    /// Replaces a channel of `msrv` with the `rust-version` of the project
+    /// in `dir`, where the toolchain file is.
+    fn resolve_msrv(mut self, dir: &Path) -> Result<Self> {
+        if self.channel.as_deref() == Some(MSRV_CHANNEL) {
+            self.channel = Some(find_msrv(dir)?);
+        }
+        Ok(self)
+    }
+}
+
+/// Finds the `rust-version` of the package or workspace in `dir`, or in the
+/// nearest directory above it whose Cargo.toml gives one.
+pub(crate) fn find_msrv(dir: &Path) -> Result<String> {
+    let mut d = Some(dir);
+    while let Some(current) = d {
+        let manifest = current.join("Cargo.toml");
+        if let Ok(contents) = utils::read_file("Cargo.toml", &manifest) {
+            let value: toml::Value = toml::from_str(&contents)
+                .with_context(|| format!("invalid manifest '{}'", manifest.display()))?;
+            fn rust_version(table: Option<&toml::Value>) -> Option<&str> {
+                table?.get("rust-version")?.as_str()
+            }
+            // A package with `rust-version.workspace = true` has a table
+            // here, and takes it from the workspace above.
+            if let Some(version) = rust_version(value.get("package"))
+                .or_else(|| rust_version(value.get("workspace").and_then(|w| w.get("package"))))
+            {
+                return Ok(version.to_owned());
+            }
+        }
+        d = current.parent();
+    }
+    Err(anyhow!(
+        "'{}' names the `rust-version` in Cargo.toml, but there is none for '{}'",
+        MSRV_CHANNEL,
+        dir.display()
+    ))
+}
+
+/// Whether RUSTUP_DIST_SERVER, or RUSTUP_DIST_ROOT, chooses the dist server,
+/// which neither a project's settings nor its toolchain file replace.
+fn dist_server_from_env() -> bool {
+    ["RUSTUP_DIST_SERVER", "RUSTUP_DIST_ROOT"]
+        .iter()
+        .any(|var| matches!(process().var(var), Ok(v) if !v.is_empty()))
+}
+
+/// Environment variables which toolchain files can always use, besides
+/// those allowed with `rustup set toolchain-file-env`.
+const TOOLCHAIN_FILE_ENV: &[&str] = &["HOME", "USERPROFILE", "CARGO_HOME", "RUSTUP_HOME"];
+
+impl ToolchainSection {
+    /// Replaces `${VAR}` in every value with the environment variable `VAR`,
+    /// which must be one of `TOOLCHAIN_FILE_ENV` or in `allowed`, so that a
+    /// toolchain file from someone else can't read anything else.
+    fn interpolate(mut self, allowed: &[String]) -> Result<Self> {
+        let interpolate = |value: &str| interpolate_env(value, allowed);
+        let interpolate_list = |list: Option<ToolchainFileList>| {
+            list.map(|list| -> Result<_> {
+                Ok(ToolchainFileList {
+                    names: list
+                        .names
+                        .iter()
+                        .map(|v| interpolate(v))
+                        .collect::<Result<_>>()?,
+                    replace: list.replace,
+                })
+            })
+            .transpose()
+        };
+        self.channel = self.channel.as_deref().map(interpolate).transpose()?;
+        if let Some(path) = self.path.as_ref().and_then(|p| p.to_str()) {
+            self.path = Some(PathBuf::from(interpolate(path)?));
+        }
+        self.components = interpolate_list(self.components)?;
+        self.targets = interpolate_list(self.targets)?;
+        self.profile = self.profile.as_deref().map(interpolate).transpose()?;
+        self.dist_server = self.dist_server.as_deref().map(interpolate).transpose()?;
+        Ok(self)
+    }
+}
+
+fn interpolate_env(value: &str, allowed: &[String]) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unterminated '${{' in '{}'", value))?;
+        let name = &rest[start + 2..start + end];
+        if !TOOLCHAIN_FILE_ENV.contains(&name) && !allowed.iter().any(|a| a == name) {
+            bail!(
+                "'${{{}}}' can't be used in a toolchain file unless it is allowed with `rustup set toolchain-file-env`",
+                name
+            );
+        }
+        let var = process().var(name).map_err(|_| {
+            anyhow!(
+                "'${{{}}}' is used in a toolchain file, but {} is not set",
+                name,
+                name
+            )
+        })?;
+        result.push_str(&var);
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Whether `dir` is the root of a git or Mercurial repository.
+fn is_vcs_root(dir: &Path) -> bool {
+    dir.join(".git").exists() || dir.join(".hg").exists()
+}
+
+fn merge_lists(
+    inherited: Option<ToolchainFileList>,
+    own: Option<ToolchainFileList>,
+) -> Option<ToolchainFileList> {
+    match (inherited, own) {
+        (Some(mut list), Some(own)) if !own.replace => {
+            for item in own.names {
+                if !list.names.contains(&item) {
+                    list.names.push(item);
+                }
+            }
+            Some(list)
+        }
+        (inherited, None) => inherited,
+        (_, own) => own,
     }
 }
 
+/// The keys a `[toolchain]` section can have. Anything else is ignored when
+/// the file is loaded, with a warning.
+const TOOLCHAIN_FILE_KEYS: &[&str] = &[
+    "channel",
+    "path",
+    "components",
+    "targets",
+    "profile",
+    "manifest-sha256",
+    "dist-server",
+    "inherit",
+];
+
+/// The keys a `[target.'cfg(...)']` section can have.
+const PLATFORM_SECTION_KEYS: &[&str] = &["components", "targets"];
+
+/// Lists the keys in a toolchain file which rustup doesn't understand, such
+/// as misspellings, as `toolchain.<key>` or a top-level table. A file which
+/// isn't TOML, like the legacy one-line format, has none.
+fn unknown_toolchain_file_keys(contents: &str) -> Vec<String> {
+    let table = match toml::from_str::<toml::value::Table>(contents) {
+        Ok(table) => table,
+        Err(_) => return Vec::new(),
+    };
+    let mut unknown = Vec::new();
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(section) if key == "toolchain" => unknown.extend(
+                section
+                    .keys()
+                    .filter(|key| !TOOLCHAIN_FILE_KEYS.contains(&key.as_str()))
+                    .map(|key| format!("toolchain.{}", key)),
+            ),
+            toml::Value::Table(sections) if key == "target" => {
+                for (spec, section) in sections {
+                    match section {
+                        toml::Value::Table(section) => unknown.extend(
+                            section
+                                .keys()
+                                .filter(|key| !PLATFORM_SECTION_KEYS.contains(&key.as_str()))
+                                .map(|key| format!("target.'{}'.{}", spec, key)),
+                        ),
+                        _ => unknown.push(format!("target.'{}'", spec)),
+                    }
+                }
+            }
+            _ => unknown.push(key),
+        }
+    }
+    unknown
+}
+
 impl<T: Into<String>> From<T> for OverrideFile {
     fn from(channel: T) -> Self {
         let override_ = channel.into();
@@ -73,6 +433,7 @@ impl<T: Into<String>> From<T> for OverrideFile {
                     path: Some(PathBuf::from(override_)),
                     ..Default::default()
                 },
+                ..Default::default()
             }
         } else {
             Self {
@@ -80,8 +441,84 @@ impl<T: Into<String>> From<T> for OverrideFile {
                     channel: Some(override_),
                     ..Default::default()
                 },
+                ..Default::default()
+            }
+        }
+    }
+}
+
+/// One of the places a toolchain can be chosen by, for `rustup override
+/// explain`.
+#[derive(Debug)]
+pub struct OverrideCandidate {
+    /// Where the toolchain is chosen, as `rustup show` puts it.
+    pub source: String,
+    /// What it says, one `key = value` line per setting.
+    pub settings: Vec<String>,
+    /// Whether it builds on the next candidate rather than replacing it.
+    pub inherit: bool,
+}
+
+impl OverrideCandidate {
+    fn new(source: impl Display, toolchain: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            settings: vec![format!("toolchain = {:?}", toolchain)],
+            inherit: false,
+        }
+    }
+
+    fn from_file(path: &Path, contents: &str, parse_mode: ParseMode) -> Self {
+        let source = OverrideReason::ToolchainFile(path.to_owned()).to_string();
+        let file = match Cfg::parse_override_file(contents, parse_mode) {
+            Ok(file) => file,
+            Err(e) => {
+                return Self {
+                    source,
+                    settings: vec![format!("invalid: {:#}", e)],
+                    inherit: false,
+                }
+            }
+        };
+        let section = file.toolchain;
+        let mut settings = Vec::new();
+        if let Some(channel) = section.channel {
+            settings.push(format!("channel = {:?}", channel));
+        }
+        if let Some(path) = section.path {
+            settings.push(format!("path = {:?}", path.display().to_string()));
+        }
+        if let Some(components) = section.components {
+            settings.push(format!("components = {}", components));
+        }
+        if let Some(targets) = section.targets {
+            settings.push(format!("targets = {}", targets));
+        }
+        if let Some(profile) = section.profile {
+            settings.push(format!("profile = {:?}", profile));
+        }
+        if let Some(hash) = section.manifest_sha256 {
+            settings.push(format!("manifest-sha256 = {:?}", hash));
+        }
+        if let Some(server) = section.dist_server {
+            settings.push(format!("dist-server = {:?}", server));
+        }
+        if section.inherit {
+            settings.push("inherit = true".to_owned());
+        }
+        for (spec, platform) in file.target {
+            if let Some(components) = platform.components {
+                settings.push(format!("target.'{}'.components = {}", spec, components));
+            }
+            if let Some(targets) = platform.targets {
+                settings.push(format!("target.'{}'.targets = {}", spec, targets));
             }
         }
+        Self {
+            source,
+            settings,
+            inherit: section.inherit,
+        }
     }
 }
 
@@ -91,6 +528,7 @@ pub enum OverrideReason {
     CommandLine,
     OverrideDB(PathBuf),
     ToolchainFile(PathBuf),
+    OverrideHook(PathBuf),
 }
 
 impl Display for OverrideReason {
@@ -100,6 +538,9 @@ impl Display for OverrideReason {
             Self::CommandLine => write!(f, "overridden by +toolchain on the command line"),
             Self::OverrideDB(path) => write!(f, "directory override for '{}'", path.display()),
             Self::ToolchainFile(path) => write!(f, "overridden by '{}'", path.display()),
+            Self::OverrideHook(path) => {
+                write!(f, "chosen by the override hook for '{}'", path.display())
+            }
         }
     }
 }
@@ -110,6 +551,8 @@ struct OverrideCfg<'a> {
     components: Vec<String>,
     targets: Vec<String>,
     profile: Option<dist::Profile>,
+    manifest_sha256: Option<String>,
+    dist_server: Option<String>,
 }
 
 impl<'a> OverrideCfg<'a> {
@@ -125,13 +568,21 @@ impl<'a> OverrideCfg<'a> {
                     if file.toolchain.targets.is_some()
                         || file.toolchain.components.is_some()
                         || file.toolchain.profile.is_some()
+                        || file.toolchain.manifest_sha256.is_some()
+                        || file.toolchain.dist_server.is_some()
                     {
                         bail!(
                             "toolchain options are ignored for path toolchain ({})",
                             path.display()
                         )
                     }
-                    Some(Toolchain::from_path(cfg, cfg_path, &path)?)
+                    let cfg_path = cfg_path.as_ref().map(AsRef::as_ref);
+                    let toolchain = Toolchain::from_path(cfg, cfg_path, &path)?;
+                    if let Some(cfg_path) = cfg_path {
+                        let what = format!("the toolchain at '{}'", toolchain.path().display());
+                        cfg.ensure_trusted(cfg_path, &what)?;
+                    }
+                    Some(toolchain)
                 }
                 (Some(channel), Some(path)) => {
                     bail!(
@@ -142,14 +593,16 @@ impl<'a> OverrideCfg<'a> {
                 }
                 (None, None) => None,
             },
-            components: file.toolchain.components.unwrap_or_default(),
-            targets: file.toolchain.targets.unwrap_or_default(),
+            components: file.toolchain.components.unwrap_or_default().names,
+            targets: file.toolchain.targets.unwrap_or_default().names,
             profile: file
                 .toolchain
                 .profile
                 .as_deref()
                 .map(dist::Profile::from_str)
                 .transpose()?,
+            manifest_sha256: file.toolchain.manifest_sha256,
+            dist_server: file.toolchain.dist_server,
         })
     }
 }
@@ -228,31 +681,123 @@ impl Display for PgpPublicKey {
 
 pub const UNIX_FALLBACK_SETTINGS: &str = "/etc/rustup/settings.toml";
 
+/// The profile whose home is RUSTUP_HOME itself. Every other profile has its
+/// own home in `RUSTUP_HOME/profiles`.
+pub const DEFAULT_HOME_PROFILE: &str = "default";
+
+fn validate_home_profile_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        bail!(
+            "invalid profile name '{}': only letters, digits, '-' and '_' are allowed",
+            name
+        );
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
 pub struct Cfg {
     pub profile_override: Option<dist::Profile>,
+    /// RUSTUP_HOME, which holds the homes of all the profiles.
+    pub base_dir: PathBuf,
+    pub home_profile: String,
     pub rustup_dir: PathBuf,
     pub settings_file: SettingsFile,
     pub fallback_settings: Option<FallbackSettings>,
     pub toolchains_dir: PathBuf,
+    /// Toolchains managed by an administrator, used when the user hasn't
+    /// installed one of the same name.
+    pub system_toolchains_dir: Option<PathBuf>,
     pub update_hash_dir: PathBuf,
+    pub last_used_dir: PathBuf,
+    pub override_hook_dir: PathBuf,
+    /// The toolchain the proxies last ran in each directory, so that they
+    /// don't have to look for overrides again until something changes.
+    pub proxy_cache_dir: PathBuf,
     pub download_dir: PathBuf,
+    pub download_log: DownloadLog,
+    pub usage_log: UsageLog,
+    /// The `[network]` settings the downloads are made with.
+    network: NetworkSettings,
     pub temp_cfg: temp::Cfg,
-    pgp_keys: Vec<PgpPublicKey>,
+    /// The XDG directories the home is split across, when the XDG layout is
+    /// in use and the home is the default profile's. Other profiles keep
+    /// everything in their own home under the data directory.
+    pub(crate) xdg_dirs: Option<utils::XdgDirs>,
+    pgp_keys: Arc<[PgpPublicKey]>,
     pub toolchain_override: Option<String>,
     pub env_override: Option<String>,
+    /// The toolchain file to use in place of any found in the directories,
+    /// from RUSTUP_TOOLCHAIN_FILE or `--toolchain-file`.
+    pub toolchain_file_override: Option<PathBuf>,
     pub dist_root_url: String,
     pub dist_root_server: String,
-    pub notify_handler: Arc<dyn Fn(Notification<'_>)>,
+    pub notify_handler: Arc<dyn Fn(Notification<'_>) + Send + Sync>,
 }
 
 impl Cfg {
-    pub(crate) fn from_env(notify_handler: Arc<dyn Fn(Notification<'_>)>) -> Result<Self> {
+    /// Reports to `notify_handler` from now on, as does this `Cfg`'s temp
+    /// configuration, such as a copy of it on another thread does.
+    pub(crate) fn set_notify_handler(
+        &mut self,
+        notify_handler: Arc<dyn Fn(Notification<'_>) + Send + Sync>,
+    ) {
+        let notify_clone = notify_handler.clone();
+        self.temp_cfg = temp::Cfg::new(
+            self.temp_cfg.root_directory().to_owned(),
+            &self.temp_cfg.dist_server,
+            Box::new(move |n| (notify_clone)(n.into())),
+        );
+        self.notify_handler = notify_handler;
+    }
+
+    pub(crate) fn from_env(
+        home_profile: Option<&str>,
+        notify_handler: Arc<dyn Fn(Notification<'_>) + Send + Sync>,
+    ) -> Result<Self> {
         // Set up the rustup home directory
-        let rustup_dir = utils::rustup_home()?;
+        let xdg_dirs = utils::xdg_layout();
+        let base_dir = match &xdg_dirs {
+            Some(dirs) => dirs.data.clone(),
+            None => utils::rustup_home()?,
+        };
+        let from_command_line = home_profile.is_some();
+        let home_profile = match home_profile {
+            Some(name) => name.to_owned(),
+            None => Self::active_home_profile_in(&base_dir)?,
+        };
+        validate_home_profile_name(&home_profile)?;
+        let rustup_dir = Self::home_profile_dir_in(&base_dir, &home_profile);
+        let xdg_dirs = xdg_dirs.filter(|_| home_profile == DEFAULT_HOME_PROFILE);
+        // Only `rustup profile switch` makes a home for another profile, so
+        // that a mistyped `--profile` isn't quietly given an empty one.
+        if from_command_line
+            && home_profile != DEFAULT_HOME_PROFILE
+            && !utils::is_directory(&rustup_dir)
+        {
+            bail!(
+                "no such profile '{}', create it with `rustup profile switch`",
+                home_profile
+            );
+        }
 
         utils::ensure_dir_exists("home", &rustup_dir, notify_handler.as_ref())?;
+        utils::share_with_group_if_shared(&rustup_dir);
+        let (config_dir, cache_dir) = match &xdg_dirs {
+            Some(dirs) => {
+                utils::ensure_dir_exists("config", &dirs.config, notify_handler.as_ref())?;
+                utils::ensure_dir_exists("cache", &dirs.cache, notify_handler.as_ref())?;
+                (dirs.config.clone(), dirs.cache.clone())
+            }
+            None => (rustup_dir.clone(), rustup_dir.clone()),
+        };
 
-        let settings_file = SettingsFile::new(rustup_dir.join("settings.toml"));
+        let mut settings_file = SettingsFile::new(config_dir.join("settings.toml"));
+        settings_file.set_policy(Policy::load()?);
 
         // Centralised file for multi-user systems to provide admin/distributor set initial values.
         let fallback_settings = if cfg!(not(windows)) {
@@ -269,8 +814,20 @@ impl Cfg {
         };
 
         let toolchains_dir = rustup_dir.join("toolchains");
+        let system_toolchains_dir = match process().var_os("RUSTUP_SYSTEM_HOME") {
+            Some(s) if !s.is_empty() => Some(PathBuf::from(s)),
+            _ => fallback_settings
+                .as_ref()
+                .and_then(|s| s.system_home.clone()),
+        }
+        .map(|home| home.join("toolchains"));
         let update_hash_dir = rustup_dir.join("update-hashes");
-        let download_dir = rustup_dir.join("downloads");
+        let last_used_dir = rustup_dir.join("last-used");
+        let override_hook_dir = cache_dir.join("override-hook");
+        let proxy_cache_dir = cache_dir.join("proxy-cache");
+        let download_dir = cache_dir.join("downloads");
+        let download_log = DownloadLog::new(rustup_dir.join("logs").join("downloads.log"));
+        let usage_log = UsageLog::new(rustup_dir.join("logs").join("usage.log"));
 
         // PGP keys
         let mut pgp_keys: Vec<PgpPublicKey> = vec![PgpPublicKey::Builtin];
@@ -303,11 +860,20 @@ impl Cfg {
             Ok(())
         })?;
 
+        // Every download made by this process connects as the settings
+        // say, so they have to be in place before the first one starts.
+        let network = settings_file.with(|s| Ok(s.network.clone()))?;
+        download::set_network_options(network.options()?);
+
         // Environment override
         let env_override = process()
             .var("RUSTUP_TOOLCHAIN")
             .ok()
             .and_then(utils::if_not_empty);
+        let toolchain_file_override = match process().var_os("RUSTUP_TOOLCHAIN_FILE") {
+            Some(file) if !file.is_empty() => Some(utils::current_dir()?.join(file)),
+            _ => None,
+        };
 
         let dist_root_server = match process().var("RUSTUP_DIST_SERVER") {
             Ok(ref s) if !s.is_empty() => s.clone(),
@@ -325,8 +891,14 @@ impl Cfg {
         };
 
         let notify_clone = notify_handler.clone();
+        // A directory of the user's choosing may have other files in it, so
+        // rustup's, which it empties, go in one of their own.
+        let temp_dir = settings_file.with(|s| Ok(s.temp.dir.clone()))?;
+        if temp_dir.is_some() {
+            utils::permit_copy_rename();
+        }
         let temp_cfg = temp::Cfg::new(
-            rustup_dir.join("tmp"),
+            temp_dir.map_or_else(|| cache_dir.join("tmp"), |dir| dir.join("rustup")),
             dist_root_server.as_str(),
             Box::new(move |n| (notify_clone)(n.into())),
         );
@@ -334,17 +906,28 @@ impl Cfg {
 
         let cfg = Self {
             profile_override: None,
+            base_dir,
+            home_profile,
             rustup_dir,
             settings_file,
             fallback_settings,
             toolchains_dir,
+            system_toolchains_dir,
             update_hash_dir,
+            last_used_dir,
+            override_hook_dir,
+            proxy_cache_dir,
             download_dir,
+            download_log,
+            usage_log,
+            network,
             temp_cfg,
-            pgp_keys,
+            xdg_dirs,
+            pgp_keys: pgp_keys.into(),
             notify_handler,
             toolchain_override: None,
             env_override,
+            toolchain_file_override,
             dist_root_url: dist_root,
             dist_root_server,
         };
@@ -358,6 +941,100 @@ impl Cfg {
         Ok(cfg)
     }
 
+    fn active_home_profile_in(base_dir: &Path) -> Result<String> {
+        let path = base_dir.join("active-profile");
+        if !utils::is_file(&path) {
+            return Ok(DEFAULT_HOME_PROFILE.to_owned());
+        }
+        let name = utils::read_file("active profile", &path)?;
+        Ok(name.trim().to_owned())
+    }
+
+    /// Where caches that can be fetched again go: the home itself, unless it
+    /// is split across the XDG directories.
+    pub(crate) fn cache_dir(&self) -> &Path {
+        match &self.xdg_dirs {
+            Some(dirs) => &dirs.cache,
+            None => &self.rustup_dir,
+        }
+    }
+
+    fn home_profile_dir_in(base_dir: &Path, name: &str) -> PathBuf {
+        if name == DEFAULT_HOME_PROFILE {
+            base_dir.to_owned()
+        } else {
+            base_dir.join("profiles").join(name)
+        }
+    }
+
+    /// The settings file of `home_profile`, or of the active profile, found
+    /// without setting up the rest of the configuration.
+    pub(crate) fn settings_path(home_profile: Option<&str>) -> Result<PathBuf> {
+        let xdg_dirs = utils::xdg_layout();
+        let base_dir = match &xdg_dirs {
+            Some(dirs) => dirs.data.clone(),
+            None => utils::rustup_home()?,
+        };
+        let home_profile = match home_profile {
+            Some(name) => name.to_owned(),
+            None => Self::active_home_profile_in(&base_dir)?,
+        };
+        validate_home_profile_name(&home_profile)?;
+        Ok(match xdg_dirs {
+            Some(dirs) if home_profile == DEFAULT_HOME_PROFILE => dirs.config,
+            _ => Self::home_profile_dir_in(&base_dir, &home_profile),
+        }
+        .join("settings.toml"))
+    }
+
+    /// The settings file of `home_profile`, or of the active profile, read
+    /// as the configuration reads it, with the machine's policy applied, for
+    /// what is needed before the rest of the configuration is set up.
+    pub(crate) fn settings_file(home_profile: Option<&str>) -> Result<SettingsFile> {
+        let mut settings_file = SettingsFile::new(Self::settings_path(home_profile)?);
+        settings_file.set_policy(Policy::load()?);
+        Ok(settings_file)
+    }
+
+    /// The profile used when `--profile` isn't given.
+    pub fn active_home_profile(&self) -> Result<String> {
+        Self::active_home_profile_in(&self.base_dir)
+    }
+
+    /// Makes `name` the profile used when `--profile` isn't given, creating
+    /// its home if it doesn't exist yet.
+    pub fn switch_home_profile(&self, name: &str) -> Result<()> {
+        validate_home_profile_name(name)?;
+        let path = self.base_dir.join("active-profile");
+        if name == DEFAULT_HOME_PROFILE {
+            if utils::is_file(&path) {
+                utils::remove_file("active profile", &path)?;
+            }
+        } else {
+            let dir = Self::home_profile_dir_in(&self.base_dir, name);
+            utils::ensure_dir_exists("home", &dir, self.notify_handler.as_ref())?;
+            utils::write_file("active profile", &path, name)?;
+        }
+        Ok(())
+    }
+
+    /// Every profile with a home, the default profile first.
+    pub fn list_home_profiles(&self) -> Result<Vec<String>> {
+        let mut names = vec![DEFAULT_HOME_PROFILE.to_owned()];
+        let dir = self.base_dir.join("profiles");
+        if utils::is_directory(&dir) {
+            let mut others: Vec<_> = utils::read_dir("profiles", &dir)?
+                .filter_map(io::Result::ok)
+                .filter(|e| e.file_type().map(|f| f.is_dir()).unwrap_or(false))
+                .filter_map(|e| e.file_name().into_string().ok())
+                .filter(|name| validate_home_profile_name(name).is_ok())
+                .collect();
+            others.sort();
+            names.extend(others);
+        }
+        Ok(names)
+    }
+
     /// construct a download configuration
     pub(crate) fn download_cfg<'a>(
         &'a self,
@@ -369,6 +1046,20 @@ impl Cfg {
             download_dir: &self.download_dir,
             notify_handler,
             pgp_keys: self.get_pgp_keys(),
+            download_log: Some(&self.download_log),
+            network: &self.network,
+            verified_manifest: None,
+        }
+    }
+
+    /// Fails unless the machine's policy, if there is one, allows `desc` to
+    /// be installed from the dist server and mirrors in use.
+    pub(crate) fn check_policy(&self, desc: &dist::ToolchainDesc) -> Result<()> {
+        match self.settings_file.policy() {
+            Some(policy) => {
+                policy.check_install(desc, &self.dist_root_server, &self.network.mirrors)
+            }
+            None => Ok(()),
         }
     }
 
@@ -418,85 +1109,687 @@ impl Cfg {
         }
     }
 
-    pub fn set_toolchain_override(&mut self, toolchain_override: &str) {
-        self.toolchain_override = Some(toolchain_override.to_owned());
+    pub fn set_auto_install_targets(&mut self, mode: &str) -> Result<()> {
+        let auto_install = AutoInstallTargets::from_str(mode)?;
+        self.settings_file.with_mut(|s| {
+            s.auto_install_targets = Some(auto_install);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetAutoInstallTargets(mode));
+        Ok(())
     }
 
-    // Returns a profile, if one exists in the settings file.
-    //
-    // Returns `Err` if the settings file could not be read or the profile is
-    // invalid. Returns `Ok(...)` if there is a valid profile, and `Ok(Profile::Default)`
-    // if there is no profile in the settings file. The last variant happens when
-    // a user upgrades from a version of Rustup without profiles to a version of
-    // Rustup with profiles.
-    pub fn get_profile(&self) -> Result<dist::Profile> {
-        if let Some(p) = self.profile_override {
-            return Ok(p);
-        }
-        self.settings_file.with(|s| {
-            let p = match s.profile {
-                Some(p) => p,
-                None => Profile::Default,
-            };
-            Ok(p)
-        })
+    pub fn set_auto_install_components(&mut self, mode: &str) -> Result<()> {
+        let auto_install = AutoInstallComponents::from_str(mode)?;
+        self.settings_file.with_mut(|s| {
+            s.auto_install_components = Some(auto_install);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetAutoInstallComponents(mode));
+        Ok(())
     }
 
-    pub fn get_self_update_mode(&self) -> Result<SelfUpdateMode> {
-        self.settings_file.with(|s| {
-            let mode = match &s.auto_self_update {
-                Some(mode) => mode.clone(),
-                None => SelfUpdateMode::Enable,
-            };
-            Ok(mode)
-        })
+    pub fn set_toolchain_file_search(&mut self, mode: &str) -> Result<()> {
+        let search = ToolchainFileSearch::from_str(mode)?;
+        self.settings_file.with_mut(|s| {
+            s.toolchain_file_search = Some(search);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetToolchainFileSearch(mode));
+        Ok(())
     }
 
-    pub fn get_toolchain(&self, name: &str, create_parent: bool) -> Result<Toolchain<'_>> {
-        if create_parent {
-            utils::ensure_dir_exists("toolchains", &self.toolchains_dir, &|n| {
-                (self.notify_handler)(n)
-            })?;
+    pub fn set_shims(&mut self, mode: &str) -> Result<()> {
+        let shims = ShimMode::from_str(mode)?;
+        if cfg!(windows) && shims == ShimMode::Symlink {
+            bail!("symlink shims are only available on Unix");
         }
-
-        Toolchain::from(self, name)
+        self.settings_file.with_mut(|s| {
+            s.shims = Some(shims);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetShims(mode));
+        Ok(())
     }
 
-    pub fn get_hash_file(&self, toolchain: &str, create_parent: bool) -> Result<PathBuf> {
-        if create_parent {
-            utils::ensure_dir_exists(
-                "update-hash",
-                &self.update_hash_dir,
-                self.notify_handler.as_ref(),
-            )?;
-        }
+    pub fn get_shims(&self) -> Result<ShimMode> {
+        self.settings_file
+            .with(|s| Ok(s.shims.unwrap_or(ShimMode::Proxy)))
+    }
 
-        Ok(self.update_hash_dir.join(toolchain))
+    pub fn set_color_theme(&mut self, theme: &str) -> Result<()> {
+        let color_theme = ColorTheme::from_str(theme)?;
+        self.settings_file.with_mut(|s| {
+            s.color_theme = Some(color_theme);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetColorTheme(theme));
+        Ok(())
     }
 
-    pub fn which_binary_by_toolchain(
-        &self,
-        toolchain: &str,
-        binary: &str,
-    ) -> Result<Option<PathBuf>> {
-        let toolchain = self.get_toolchain(toolchain, false)?;
-        if toolchain.exists() {
-            Ok(Some(toolchain.binary_file(binary)))
-        } else {
-            Ok(None)
-        }
+    pub fn get_color_theme(&self) -> Result<ColorTheme> {
+        self.settings_file
+            .with(|s| Ok(s.color_theme.unwrap_or(ColorTheme::Default)))
     }
 
-    pub fn which_binary(&self, path: &Path, binary: &str) -> Result<Option<PathBuf>> {
-        let (toolchain, _) = self.find_or_install_override_toolchain_or_default(path)?;
-        Ok(Some(toolchain.binary_file(binary)))
+    pub fn set_usage_stats(&mut self, mode: &str) -> Result<()> {
+        let usage_stats = UsageStats::from_str(mode)?;
+        self.settings_file.with_mut(|s| {
+            s.usage_stats = Some(usage_stats);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetUsageStats(mode));
+        Ok(())
     }
 
-    pub fn upgrade_data(&self) -> Result<()> {
-        let current_version = self.settings_file.with(|s| Ok(s.version.clone()))?;
+    pub fn get_usage_stats(&self) -> Result<UsageStats> {
+        self.settings_file
+            .with(|s| Ok(s.usage_stats.unwrap_or(UsageStats::Disable)))
+    }
 
-        if current_version == DEFAULT_METADATA_VERSION {
-            (self.notify_handler)(Notification::MetadataUpgradeNotNeeded(&current_version));
+    /// Records that `tool` of `toolchain` is about to be run, if usage
+    /// statistics are on. Failing to is ignored, as it is no reason not to
+    /// run the tool.
+    pub(crate) fn record_usage(&self, toolchain: &str, tool: &str) {
+        if !matches!(self.get_usage_stats(), Ok(UsageStats::Enable)) {
+            return;
+        }
+        let entry = usage_log::Entry {
+            time: chrono::Utc::now(),
+            toolchain: toolchain.to_owned(),
+            tool: tool.to_owned(),
+        };
+        if let Err(e) = self.usage_log.record(&entry) {
+            debug!("could not record the use of '{}': {:#}", tool, e);
+        }
+    }
+
+    /// Uses the settings of the project's `.rustup.toml`, found in the
+    /// current directory or above it, in place of the user's, if its
+    /// directory is trusted. Its dist server is used unless
+    /// RUSTUP_DIST_SERVER chooses one already, and a toolchain file's takes
+    /// its place in turn. Since this is done for every command and proxy, a
+    /// file which isn't trusted, or is invalid, is only warned about rather
+    /// than asked about or failed on.
+    pub fn use_project_settings(&mut self) -> Result<()> {
+        let cwd = match process().current_dir() {
+            Ok(cwd) => cwd,
+            Err(_) => return Ok(()),
+        };
+        let file = match self.find_project_settings(&cwd)? {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let dir = file.parent().unwrap_or(&file);
+        let notify = self.notify_handler.as_ref();
+        if !self.settings_file.with(|s| Ok(s.is_trusted(dir, notify)))? {
+            notify(Notification::IgnoringUntrustedProjectSettings(&file, dir));
+            return Ok(());
+        }
+        let project = match utils::read_file("project settings", &file)
+            .and_then(|contents| ProjectSettings::parse(&file, &contents))
+        {
+            Ok(project) => project,
+            Err(e) => {
+                notify(Notification::IgnoringInvalidProjectSettings(&file, &e));
+                return Ok(());
+            }
+        };
+        for key in &project.unknown {
+            notify(Notification::UnknownProjectSetting(&file, key));
+        }
+        if let Some(server) = &project.dist_server {
+            if !dist_server_from_env() {
+                self.set_dist_server(server);
+            }
+        }
+        self.settings_file.set_project(project);
+        // The project's mirrors are used for the downloads that follow.
+        self.apply_network_settings()
+    }
+
+    /// The `.rustup.toml` in `dir` or the nearest directory above it.
+    fn find_project_settings(&self, dir: &Path) -> Result<Option<PathBuf>> {
+        let stops_at_vcs_root = self.settings_file.with(|s| Ok(s.stops_at_vcs_root()))?;
+        let mut dir = Some(dir);
+        while let Some(d) = dir {
+            let file = d.join(PROJECT_SETTINGS_FILE);
+            if utils::is_file(&file) {
+                return Ok(Some(file));
+            }
+            dir = match d.parent() {
+                Some(_) if stops_at_vcs_root && is_vcs_root(d) => None,
+                parent => parent,
+            };
+        }
+        Ok(None)
+    }
+
+    /// A copy of this `Cfg` which installs from `server`, the `dist-server`
+    /// of the toolchain file `reason` names, unless RUSTUP_DIST_SERVER
+    /// chooses a server already. The server is only used if the file's
+    /// directory is trusted; otherwise the usual one is, with a warning.
+    fn with_project_dist_server(
+        &self,
+        server: Option<&str>,
+        reason: Option<&OverrideReason>,
+    ) -> Result<Option<Self>> {
+        let (server, file) = match (server, reason) {
+            (Some(server), Some(OverrideReason::ToolchainFile(file))) => (server, file),
+            _ => return Ok(None),
+        };
+        if dist_server_from_env() {
+            return Ok(None);
+        }
+        let what = format!("the dist server '{}'", server);
+        match self.ensure_trusted(file, &what) {
+            Ok(()) => {
+                let mut cfg = self.clone();
+                cfg.set_dist_server(server);
+                Ok(Some(cfg))
+            }
+            Err(e) => match e.downcast_ref::<RustupError>() {
+                Some(RustupError::UntrustedDirectory { dir, .. }) => {
+                    (self.notify_handler)(Notification::IgnoringUntrustedDistServer(server, dir));
+                    Ok(None)
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    pub(crate) fn set_dist_server(&mut self, server: &str) {
+        let server = server.trim_end_matches('/');
+        self.dist_root_server = server.to_owned();
+        self.dist_root_url = format!("{}/dist", server);
+        self.temp_cfg.dist_server = server.to_owned();
+    }
+
+    /// Makes sure that the directory of the toolchain file `file`, which
+    /// uses `what`, is trusted, asking whether to trust it from now on if
+    /// there is a terminal to ask at. Otherwise a repository could have
+    /// rustup run anything, by naming it as a toolchain.
+    pub(crate) fn ensure_trusted(&self, file: &Path, what: &str) -> Result<()> {
+        let dir = file.parent().unwrap_or(file);
+        let notify = self.notify_handler.as_ref();
+        if self.settings_file.with(|s| Ok(s.is_trusted(dir, notify)))? {
+            return Ok(());
+        }
+        if can_prompt() {
+            write!(
+                process().stderr(),
+                "'{}' uses {}. Trust '{}' and everything in it? (y/N) ",
+                file.display(),
+                what,
+                dir.display()
+            )?;
+            let answer = read_line()?.trim().to_lowercase();
+            if matches!(answer.as_str(), "y" | "yes") {
+                self.trust_dir(dir)?;
+                return Ok(());
+            }
+        }
+        Err(RustupError::UntrustedDirectory {
+            file: file.to_owned(),
+            dir: dir.to_owned(),
+            what: what.to_owned(),
+        }
+        .into())
+    }
+
+    /// Trusts the toolchain files in `dir` and below, or in every directory
+    /// if it is `*`, returning whether it wasn't trusted already.
+    pub fn trust_dir(&self, dir: &Path) -> Result<bool> {
+        let notify = self.notify_handler.as_ref();
+        self.settings_file
+            .with_mut(|s| Ok(s.add_trusted_dir(dir, notify)))
+    }
+
+    pub fn untrust_dir(&self, dir: &Path) -> Result<bool> {
+        let notify = self.notify_handler.as_ref();
+        self.settings_file
+            .with_mut(|s| Ok(s.remove_trusted_dir(dir, notify)))
+    }
+
+    pub fn list_trusted_dirs(&self) -> Result<Vec<String>> {
+        self.settings_file.with(|s| Ok(s.trusted_dirs.clone()))
+    }
+
+    /// The tools besides the built-in ones which have proxies.
+    pub fn list_proxies(&self) -> Result<Vec<String>> {
+        self.settings_file.with(|s| Ok(s.proxies.clone()))
+    }
+
+    /// Records that `tool` has a proxy, returning whether it didn't already.
+    pub fn add_proxy(&self, tool: &str) -> Result<bool> {
+        if tool.is_empty()
+            || !tool
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            || tool.starts_with("rustup")
+        {
+            bail!("invalid tool name '{}'", tool);
+        }
+        if TOOLS.iter().chain(DUP_TOOLS).any(|t| *t == tool) {
+            bail!("'{}' always has a proxy", tool);
+        }
+        self.settings_file.with_mut(|s| {
+            if s.proxies.iter().any(|t| t == tool) {
+                return Ok(false);
+            }
+            s.proxies.push(tool.to_owned());
+            Ok(true)
+        })
+    }
+
+    /// Forgets the proxy for `tool`, returning whether it had one.
+    pub fn remove_proxy(&self, tool: &str) -> Result<bool> {
+        self.settings_file.with_mut(|s| {
+            let len = s.proxies.len();
+            s.proxies.retain(|t| t != tool);
+            Ok(s.proxies.len() != len)
+        })
+    }
+
+    /// Allows toolchain files to use the environment variables `vars` as
+    /// `${VAR}`, replacing any allowed before.
+    pub fn set_toolchain_file_env(&self, vars: &[&str]) -> Result<()> {
+        for var in vars {
+            if var.is_empty() || !var.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                bail!("invalid environment variable name '{}'", var);
+            }
+        }
+        self.settings_file.with_mut(|s| {
+            s.toolchain_file_env = vars.iter().map(|v| (*v).to_owned()).collect();
+            Ok(())
+        })
+    }
+
+    /// Installs `components` with every new toolchain, replacing those
+    /// set before.
+    pub fn set_default_components(&self, components: &[String]) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.default_components = components.to_vec();
+            Ok(())
+        })
+    }
+
+    /// Installs `targets` with every new toolchain, replacing those set
+    /// before.
+    pub fn set_default_targets(&self, targets: &[String]) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.default_targets = targets.to_vec();
+            Ok(())
+        })
+    }
+
+    /// Sets the command asked for the toolchain to use in a directory, or
+    /// removes it if `command` is empty, and forgets its earlier answers.
+    pub fn set_override_hook(&self, command: &[&str], ttl: Option<u64>) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.override_hook.command = command.iter().map(|&arg| arg.to_owned()).collect();
+            s.override_hook.ttl = ttl;
+            Ok(())
+        })?;
+        if self.override_hook_dir.exists() {
+            utils::remove_dir(
+                "override hook cache",
+                &self.override_hook_dir,
+                self.notify_handler.as_ref(),
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn set_ip_version(&mut self, version: &str) -> Result<()> {
+        let ip_version = IpVersion::from_str(version)?;
+        self.settings_file.with_mut(|s| {
+            s.network.ip_version = Some(ip_version);
+            Ok(())
+        })?;
+        self.apply_network_settings()?;
+        (self.notify_handler)(Notification::SetIpVersion(version));
+        Ok(())
+    }
+
+    /// Installs the toolchains that follow from what `rustup prefetch`
+    /// saved, as `network.offline` does.
+    pub(crate) fn set_offline(&mut self) {
+        self.network.offline = Some(true);
+    }
+
+    /// Makes the downloads that follow connect as the `[network]` settings
+    /// now say.
+    pub(crate) fn apply_network_settings(&mut self) -> Result<()> {
+        self.network = self.settings_file.with(|s| Ok(s.network.clone()))?;
+        download::set_network_options(self.network.options()?);
+        Ok(())
+    }
+
+    pub fn set_toolchain_override(&mut self, toolchain_override: &str) {
+        self.toolchain_override = Some(toolchain_override.to_owned());
+    }
+
+    pub fn set_toolchain_file_override(&mut self, toolchain_file: &Path) -> Result<()> {
+        self.toolchain_file_override = Some(utils::current_dir()?.join(toolchain_file));
+        Ok(())
+    }
+
+    // Returns a profile, if one exists in the settings file.
+    //
+    // Returns `Err` if the settings file could not be read or the profile is
+    // invalid. Returns `Ok(...)` if there is a valid profile, and `Ok(Profile::Default)`
+    // if there is no profile in the settings file. The last variant happens when
+    // a user upgrades from a version of Rustup without profiles to a version of
+    // Rustup with profiles.
+    pub fn get_profile(&self) -> Result<dist::Profile> {
+        if let Some(p) = self.profile_override {
+            return Ok(p);
+        }
+        self.settings_file.with(|s| {
+            let p = match s.profile {
+                Some(p) => p,
+                None => Profile::Default,
+            };
+            Ok(p)
+        })
+    }
+
+    pub fn get_self_update_mode(&self) -> Result<SelfUpdateMode> {
+        self.settings_file.with(|s| {
+            let mode = match &s.auto_self_update {
+                Some(mode) => mode.clone(),
+                None => SelfUpdateMode::Enable,
+            };
+            Ok(mode)
+        })
+    }
+
+    pub fn get_auto_install_targets(&self) -> Result<AutoInstallTargets> {
+        self.settings_file
+            .with(|s| Ok(s.auto_install_targets.unwrap_or(AutoInstallTargets::Prompt)))
+    }
+
+    pub fn get_auto_install_components(&self) -> Result<AutoInstallComponents> {
+        self.settings_file.with(|s| {
+            Ok(s.auto_install_components
+                .unwrap_or(AutoInstallComponents::Ask))
+        })
+    }
+
+    /// The components installed with every new toolchain, on top of those
+    /// of the profile.
+    pub fn get_default_components(&self) -> Result<Vec<String>> {
+        self.settings_file
+            .with(|s| Ok(s.default_components.clone()))
+    }
+
+    /// The targets installed with every new toolchain.
+    pub fn get_default_targets(&self) -> Result<Vec<String>> {
+        self.settings_file.with(|s| Ok(s.default_targets.clone()))
+    }
+
+    /// Takes the locks that a change to the toolchain `name` is made under,
+    /// so that processes sharing the home take turns with it.
+    pub(crate) fn lock_toolchain(&self, name: &str) -> Result<ToolchainLock> {
+        let notify = |n: crate::utils::Notification<'_>| (self.notify_handler)(n.into());
+        let metadata = FileLock::shared("metadata", &self.metadata_lock_path(), &notify)?;
+        let toolchain = FileLock::exclusive("toolchain", &self.toolchain_lock_path(name), &notify)?;
+        Ok(ToolchainLock::new(metadata, toolchain))
+    }
+
+    /// Takes the lock that changes to the home as a whole are made under,
+    /// which waits for every change to a toolchain to finish first.
+    pub(crate) fn lock_metadata(&self) -> Result<FileLock> {
+        FileLock::exclusive("metadata", &self.metadata_lock_path(), &|n| {
+            (self.notify_handler)(n.into())
+        })
+    }
+
+    fn metadata_lock_path(&self) -> PathBuf {
+        self.rustup_dir.join("locks").join("metadata.lock")
+    }
+
+    fn toolchain_lock_path(&self, name: &str) -> PathBuf {
+        self.rustup_dir
+            .join("locks")
+            .join("toolchains")
+            .join(format!("{}.lock", name))
+    }
+
+    /// Takes the locks for a change to the toolchain `name` if no other
+    /// process is changing it, without waiting.
+    fn try_lock_toolchain(&self, name: &str) -> Result<Option<ToolchainLock>> {
+        let metadata = match FileLock::try_shared("metadata", &self.metadata_lock_path())? {
+            Some(lock) => lock,
+            None => return Ok(None),
+        };
+        let toolchain = FileLock::try_exclusive("toolchain", &self.toolchain_lock_path(name))?;
+        Ok(toolchain.map(|toolchain| ToolchainLock::new(metadata, toolchain)))
+    }
+
+    fn incomplete_marker(&self, name: &str) -> PathBuf {
+        self.rustup_dir.join("incomplete").join(name)
+    }
+
+    /// Records that the toolchain `name` is being installed, or updated if
+    /// it already `exists`, so that if rustup is interrupted the next run
+    /// can tell it apart from a complete toolchain.
+    pub(crate) fn mark_incomplete(&self, name: &str, exists: bool) -> Result<()> {
+        let marker = self.incomplete_marker(name);
+        utils::ensure_dir_exists("incomplete installs", marker.parent().unwrap(), &|n| {
+            (self.notify_handler)(n)
+        })?;
+        utils::write_file(
+            "incomplete install",
+            &marker,
+            if exists { "update" } else { "install" },
+        )
+    }
+
+    pub(crate) fn mark_complete(&self, name: &str) -> Result<()> {
+        utils::ensure_file_removed("incomplete install", &self.incomplete_marker(name))
+    }
+
+    pub(crate) fn needs_repair(&self, name: &str) -> bool {
+        utils::is_file(self.incomplete_marker(name))
+    }
+
+    /// Empties the download cache and the directory for temporary files,
+    /// for `rustup toolchain uninstall --purge`, returning how many bytes
+    /// that freed.
+    pub fn purge_caches(&self) -> Result<u64> {
+        // Wait for other processes to finish with them first.
+        let _lock = self.lock_metadata()?;
+        let mut freed = 0;
+        for dir in &[self.download_dir.as_path(), self.temp_cfg.root_directory()] {
+            if utils::is_directory(dir) {
+                freed += utils::dir_size(dir)?;
+                utils::delete_dir_contents(dir);
+            }
+        }
+        Ok(freed)
+    }
+
+    /// Removes the directory overrides for any of `toolchains`, returning
+    /// the directories they were for.
+    pub fn remove_overrides_for(&self, toolchains: &[String]) -> Result<Vec<String>> {
+        let overrides = self.settings_file.with(|s| Ok(s.overrides.clone()))?;
+        let mut dirs = Vec::new();
+        for (dir, name) in overrides {
+            let name = self.resolve_toolchain(&name).unwrap_or(name);
+            if toolchains.contains(&name) {
+                dirs.push(dir);
+            }
+        }
+        if !dirs.is_empty() {
+            self.settings_file.with_mut(|s| {
+                for dir in &dirs {
+                    s.overrides.remove(dir);
+                }
+                Ok(())
+            })?;
+        }
+        Ok(dirs)
+    }
+
+    /// Tidies up after rustup processes which were interrupted: toolchains
+    /// whose first install didn't finish are removed, and those whose update
+    /// didn't finish, or which are missing their manifest, are reported until
+    /// they are repaired. Temporary files that have been left behind for a
+    /// day are deleted.
+    pub fn clean_up_incomplete_installs(&self) -> Result<()> {
+        if let Some(age) = self.settings_file.with(|s| Ok(s.temp.max_age()))? {
+            let freed = self.temp_cfg.clean_older_than(age);
+            if freed > 0 {
+                (self.notify_handler)(Notification::RemovedStaleTempFiles(freed));
+            }
+        }
+
+        let incomplete_dir = self.rustup_dir.join("incomplete");
+        let mut incomplete = Vec::new();
+        if utils::is_directory(&incomplete_dir) {
+            for entry in utils::read_dir("incomplete installs", &incomplete_dir)? {
+                let entry = entry.context("failed to read incomplete installs")?;
+                if let Ok(name) = entry.file_name().into_string() {
+                    let kind = utils::read_file("incomplete install", &entry.path())?;
+                    incomplete.push((name, kind.trim() == "update"));
+                }
+            }
+        }
+        // Toolchains installed by a rustup which didn't leave markers can
+        // still be recognised by their missing manifest.
+        for name in self.list_toolchains()? {
+            if Toolchain::is_custom_name(&name) || incomplete.iter().any(|(n, _)| *n == name) {
+                continue;
+            }
+            let path = self.toolchains_dir.join(&name);
+            let config = InstallPrefix::from(path.clone()).manifest_file(CONFIG_FILE);
+            let linked = fs::symlink_metadata(&path)
+                .map(|m| m.file_type().is_symlink())
+                .unwrap_or(false);
+            if !linked && !utils::is_file(&config) {
+                incomplete.push((name, true));
+            }
+        }
+
+        for (name, updated) in incomplete {
+            // Skip toolchains that another process is installing right now.
+            let _lock = match self.try_lock_toolchain(&name)? {
+                Some(lock) => lock,
+                None => continue,
+            };
+            let toolchain = self.get_toolchain(&name, false)?;
+            if updated && toolchain.exists() {
+                if !self.needs_repair(&name) {
+                    self.mark_incomplete(&name, true)?;
+                }
+                (self.notify_handler)(Notification::ToolchainNeedsRepair(&name));
+            } else {
+                (self.notify_handler)(Notification::RemovingIncompleteToolchain(&name));
+                toolchain.remove_files()?;
+                utils::ensure_file_removed("update hash", &self.update_hash_dir.join(&name))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_toolchain(&self, name: &str, create_parent: bool) -> Result<Toolchain<'_>> {
+        if create_parent {
+            utils::ensure_dir_exists("toolchains", &self.toolchains_dir, &|n| {
+                (self.notify_handler)(n)
+            })?;
+        }
+
+        Toolchain::from(self, name)
+    }
+
+    /// The toolchain `rustup toolchain link` makes as `name`: `name` resolved
+    /// as any other, except for `msrv`, which is linked as itself rather
+    /// than as the `rust-version` of the project it is linked in, so that
+    /// it stands in for the `rust-version` everywhere.
+    pub(crate) fn get_toolchain_to_link(&self, name: &str) -> Result<Toolchain<'_>> {
+        if name == MSRV_CHANNEL {
+            utils::ensure_dir_exists("toolchains", &self.toolchains_dir, &|n| {
+                (self.notify_handler)(n)
+            })?;
+            return Ok(Toolchain::named(self, name));
+        }
+        self.get_toolchain(name, true)
+    }
+
+    /// The toolchain `name` names in every directory, for making it the
+    /// default: unlike `get_toolchain`, a host override for the current
+    /// directory doesn't choose its host, since the default is used
+    /// everywhere.
+    pub(crate) fn get_toolchain_to_save(&self, name: &str) -> Result<Toolchain<'_>> {
+        if self.get_virtual_toolchain(name)?.is_some() {
+            return self.get_toolchain(name, false);
+        }
+        Toolchain::from(self, &self.resolve_toolchain_in(name, None)?)
+    }
+
+    /// The toolchain `name` names in `dir`, for overriding it there: `msrv`
+    /// is the `rust-version` of the project in `dir`, and a host override
+    /// for `dir` chooses its host, rather than those of the current
+    /// directory.
+    pub(crate) fn get_toolchain_in(&self, name: &str, dir: &Path) -> Result<Toolchain<'_>> {
+        if self.get_virtual_toolchain(name)?.is_some() {
+            return self.get_toolchain(name, false);
+        }
+        Toolchain::from(self, &self.resolve_toolchain_in(name, Some(dir))?)
+    }
+
+    pub fn get_hash_file(&self, toolchain: &str, create_parent: bool) -> Result<PathBuf> {
+        if create_parent {
+            utils::ensure_dir_exists(
+                "update-hash",
+                &self.update_hash_dir,
+                self.notify_handler.as_ref(),
+            )?;
+        }
+
+        Ok(self.update_hash_dir.join(toolchain))
+    }
+
+    pub fn get_last_used_file(&self, toolchain: &str, create_parent: bool) -> Result<PathBuf> {
+        if create_parent {
+            utils::ensure_dir_exists(
+                "last-used",
+                &self.last_used_dir,
+                self.notify_handler.as_ref(),
+            )?;
+        }
+
+        Ok(self.last_used_dir.join(toolchain))
+    }
+
+    pub fn which_binary_by_toolchain(
+        &self,
+        toolchain: &str,
+        binary: &str,
+    ) -> Result<Option<PathBuf>> {
+        let toolchain = self.get_toolchain(toolchain, false)?;
+        let toolchain = toolchain.for_tool(binary)?.unwrap_or(toolchain);
+        if toolchain.exists() {
+            Ok(Some(toolchain.binary_file(binary)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn which_binary(&self, path: &Path, binary: &str) -> Result<Option<PathBuf>> {
+        let (toolchain, _) = self.find_or_install_override_toolchain_or_default(path)?;
+        let toolchain = toolchain.for_tool(binary)?.unwrap_or(toolchain);
+        Ok(Some(toolchain.binary_file(binary)))
+    }
+
+    pub fn upgrade_data(&self) -> Result<()> {
+        let _lock = self.lock_metadata()?;
+        let current_version = self.settings_file.with(|s| Ok(s.version.clone()))?;
+
+        if current_version == DEFAULT_METADATA_VERSION {
+            (self.notify_handler)(Notification::MetadataUpgradeNotNeeded(&current_version));
             return Ok(());
         }
 
@@ -567,14 +1860,22 @@ impl Cfg {
             override_ = Some((name.into(), OverrideReason::Environment));
         }
 
-        // Then walk up the directory tree from 'path' looking for either the
-        // directory in override database, or a `rust-toolchain` file.
+        // Then ask the override hook, if there is one, and otherwise walk up
+        // the directory tree from 'path' looking for either the directory in
+        // override database, or a `rust-toolchain` file.
         if override_.is_none() {
-            self.settings_file.with(|s| {
-                override_ = self.find_override_from_dir_walk(path, s)?;
-
-                Ok(())
-            })?;
+            // A copy, so that the settings can be changed along the way.
+            let settings = self.settings_file.with(|s| Ok(s.clone()))?;
+            override_ = match self.ask_override_hook(path, &settings)? {
+                Some(name) => Some((
+                    name.as_str().into(),
+                    OverrideReason::OverrideHook(path.to_owned()),
+                )),
+                None => match self.find_override_from_toolchain_file_override(&settings)? {
+                    Some(override_) => Some(override_),
+                    None => self.find_override_from_dir_walk(path, &settings)?,
+                },
+            };
         }
 
         if let Some((file, reason)) = override_ {
@@ -599,6 +1900,10 @@ impl Cfg {
                     "the toolchain file at '{}' specifies an uninstalled toolchain",
                     utils::canonicalize_path(path, self.notify_handler.as_ref()).display(),
                 ),
+                OverrideReason::OverrideHook(ref path) => format!(
+                    "the override hook for '{}' specifies an uninstalled toolchain",
+                    path.display(),
+                ),
             };
 
             let cfg_file = if let OverrideReason::ToolchainFile(ref path) = reason {
@@ -628,6 +1933,74 @@ impl Cfg {
         }
     }
 
+    /// Asks the override hook which toolchain to use in `dir`, or takes its
+    /// answer from the cache while that is fresh. `None` if there is no hook
+    /// or it doesn't name a toolchain.
+    fn ask_override_hook(&self, dir: &Path, settings: &Settings) -> Result<Option<String>> {
+        let hook = &settings.override_hook;
+        let (program, args) = match hook.command.split_first() {
+            Some(command) => command,
+            None => return Ok(None),
+        };
+        let ttl = Duration::from_secs(hook.ttl.unwrap_or(DEFAULT_OVERRIDE_HOOK_TTL));
+        let key = format!("{:x}", Sha256::digest(dir.to_string_lossy().as_bytes()));
+        let cache_file = self.override_hook_dir.join(key);
+
+        let now = SystemTime::now();
+        let cached = utils::read_file("override hook cache", &cache_file)
+            .ok()
+            .and_then(|contents| {
+                let mut lines = contents.splitn(2, '\n');
+                let asked = UNIX_EPOCH + Duration::from_secs(lines.next()?.parse().ok()?);
+                let name = lines.next()?;
+                match now.duration_since(asked) {
+                    Ok(age) if age < ttl => Some(name.to_owned()),
+                    _ => None,
+                }
+            });
+        let name = match cached {
+            Some(name) => name,
+            None => {
+                let output = Command::new(program)
+                    .args(args)
+                    .arg(dir)
+                    .current_dir(dir)
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::inherit())
+                    .output()
+                    .with_context(|| format!("failed to run the override hook '{}'", program))?;
+                if !output.status.success() {
+                    bail!(
+                        "the override hook '{}' failed for '{}': {}",
+                        program,
+                        dir.display(),
+                        output.status
+                    );
+                }
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let name = stdout.lines().next().unwrap_or("").trim().to_owned();
+                if ttl > Duration::from_secs(0) {
+                    let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                    // Without the cache the hook is only asked more often.
+                    let _ = utils::ensure_dir_exists(
+                        "override hook cache",
+                        &self.override_hook_dir,
+                        self.notify_handler.as_ref(),
+                    )
+                    .and_then(|_| {
+                        utils::write_file(
+                            "override hook cache",
+                            &cache_file,
+                            &format!("{}\n{}", secs, name),
+                        )
+                    });
+                }
+                name
+            }
+        };
+        Ok(Some(name).filter(|name| !name.is_empty()))
+    }
+
     fn find_override_from_dir_walk(
         &self,
         dir: &Path,
@@ -635,6 +2008,9 @@ impl Cfg {
     ) -> Result<Option<(OverrideFile, OverrideReason)>> {
         let notify = self.notify_handler.as_ref();
         let mut dir = Some(dir);
+        // Whether toolchain files are still looked for, which they aren't
+        // above the root of a repository if the settings say so.
+        let mut files = true;
 
         while let Some(d) = dir {
             // First check the override database
@@ -642,6 +2018,11 @@ impl Cfg {
                 let reason = OverrideReason::OverrideDB(d.to_owned());
                 return Ok(Some((name.into(), reason)));
             }
+            dir = d.parent();
+            if !files {
+                continue;
+            }
+            files = !(settings.stops_at_vcs_root() && is_vcs_root(d));
 
             // Then look for 'rust-toolchain' or 'rust-toolchain.toml'
             let path_rust_toolchain = d.join("rust-toolchain");
@@ -672,24 +2053,320 @@ impl Cfg {
             };
 
             if let Ok(contents) = contents {
-                let override_file = Cfg::parse_override_file(contents, parse_mode)?;
-                if let Some(toolchain_name) = &override_file.toolchain.channel {
-                    let all_toolchains = self.list_toolchains()?;
-                    if !all_toolchains.iter().any(|s| s == toolchain_name) {
-                        // The given name is not resolvable as a toolchain, so
-                        // instead check it's plausible for installation later
-                        dist::validate_channel_name(&toolchain_name)?;
+                let override_file =
+                    self.load_toolchain_file(&toolchain_file, &contents, parse_mode, settings)?;
+                let reason = OverrideReason::ToolchainFile(toolchain_file);
+                return Ok(Some((override_file, reason)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the toolchain file given by RUSTUP_TOOLCHAIN_FILE or
+    /// `--toolchain-file`, if there is one.
+    fn find_override_from_toolchain_file_override(
+        &self,
+        settings: &Settings,
+    ) -> Result<Option<(OverrideFile, OverrideReason)>> {
+        let toolchain_file = match &self.toolchain_file_override {
+            Some(toolchain_file) => toolchain_file,
+            None => return Ok(None),
+        };
+        let contents = utils::read_file("toolchain file", toolchain_file)?;
+        let parse_mode = ParseMode::for_file(toolchain_file);
+        let override_file =
+            self.load_toolchain_file(toolchain_file, &contents, parse_mode, settings)?;
+        let reason = OverrideReason::ToolchainFile(toolchain_file.clone());
+        Ok(Some((override_file, reason)))
+    }
+
+    /// Makes what the toolchain file at `toolchain_file` says, in `contents`,
+    /// apply to this host, and fills in what it inherits.
+    fn load_toolchain_file(
+        &self,
+        toolchain_file: &Path,
+        contents: &str,
+        parse_mode: ParseMode,
+        settings: &Settings,
+    ) -> Result<OverrideFile> {
+        let notify = self.notify_handler.as_ref();
+        let dir = toolchain_file.parent().unwrap_or(toolchain_file);
+        let override_file = Cfg::parse_override_file(contents, parse_mode)?;
+        for key in unknown_toolchain_file_keys(contents) {
+            notify(Notification::UnknownToolchainFileKey(toolchain_file, &key));
+        }
+        let mut override_file = if override_file.target.is_empty() {
+            override_file
+        } else {
+            override_file.for_host(&self.get_host_triple()?)?
+        };
+        override_file.toolchain = override_file
+            .toolchain
+            .interpolate(&settings.toolchain_file_env)
+            .and_then(|section| section.resolve_msrv(dir))
+            .with_context(|| format!("invalid toolchain file '{}'", toolchain_file.display()))?;
+        if let Some(toolchain_name) = &override_file.toolchain.channel {
+            let all_toolchains = self.list_toolchains()?;
+            if !all_toolchains.iter().any(|s| s == toolchain_name)
+                && !settings.toolchain_aliases.contains_key(toolchain_name)
+                && !settings.virtual_toolchains.contains_key(toolchain_name)
+            {
+                // The given name is not resolvable as a toolchain, so
+                // instead check it's plausible for installation later
+                dist::validate_channel_name(&toolchain_name)?;
+            }
+        }
+
+        if override_file.toolchain.inherit {
+            self.inherit_override(override_file, dir, settings)
+        } else {
+            Ok(override_file)
+        }
+    }
+
+    /// Lists everything that could choose the toolchain for `path`, in
+    /// order of precedence, so the first is the one that does.
+    pub fn override_candidates(&self, path: &Path) -> Result<Vec<OverrideCandidate>> {
+        let mut candidates = Vec::new();
+        if let Some(name) = &self.toolchain_override {
+            candidates.push(OverrideCandidate::new(OverrideReason::CommandLine, name));
+        }
+        if let Some(name) = &self.env_override {
+            candidates.push(OverrideCandidate::new(OverrideReason::Environment, name));
+        }
+        let settings = self.settings_file.with(|s| Ok(s.clone()))?;
+        if let Some(name) = self.ask_override_hook(path, &settings)? {
+            let reason = OverrideReason::OverrideHook(path.to_owned());
+            candidates.push(OverrideCandidate::new(reason, &name));
+        }
+        if let Some(file) = &self.toolchain_file_override {
+            let contents = utils::read_file("toolchain file", file)?;
+            let parse_mode = ParseMode::for_file(file);
+            candidates.push(OverrideCandidate::from_file(file, &contents, parse_mode));
+        }
+
+        let notify = self.notify_handler.as_ref();
+        self.settings_file.with(|settings| {
+            let mut dir = Some(path);
+            let mut files = true;
+            while let Some(d) = dir {
+                if let Some(name) = settings.dir_override(d, notify) {
+                    let reason = OverrideReason::OverrideDB(d.to_owned());
+                    candidates.push(OverrideCandidate::new(reason, &name));
+                }
+                dir = d.parent();
+                if !files {
+                    continue;
+                }
+                files = !(settings.stops_at_vcs_root() && is_vcs_root(d));
+                // Where both are present, 'rust-toolchain' wins.
+                for &(name, parse_mode) in &[
+                    ("rust-toolchain", ParseMode::Both),
+                    ("rust-toolchain.toml", ParseMode::OnlyToml),
+                ] {
+                    let file = d.join(name);
+                    if let Ok(contents) = utils::read_file("toolchain file", &file) {
+                        candidates.push(OverrideCandidate::from_file(&file, &contents, parse_mode));
                     }
                 }
+            }
+            Ok(())
+        })?;
 
-                let reason = OverrideReason::ToolchainFile(toolchain_file);
-                return Ok(Some((override_file, reason)));
+        if let Some(name) = self.get_default()? {
+            candidates.push(OverrideCandidate::new("default toolchain", &name));
+        }
+        Ok(candidates)
+    }
+
+    /// Checks the toolchain file at `path`, or in the directory `path`, for
+    /// anything which would stop it working: unknown keys, invalid settings,
+    /// and components or targets which its channel doesn't have for this
+    /// host. Returns the file checked and a description of each problem.
+    pub fn check_toolchain_file(&self, path: &Path) -> Result<(PathBuf, Vec<String>)> {
+        let file = if utils::is_directory(path) {
+            let toml = path.join("rust-toolchain.toml");
+            if utils::is_file(&toml) && !utils::is_file(path.join("rust-toolchain")) {
+                toml
+            } else {
+                path.join("rust-toolchain")
+            }
+        } else {
+            path.to_owned()
+        };
+        let parse_mode = ParseMode::for_file(&file);
+        let contents = utils::read_file("toolchain file", &file)?;
+        let override_file = match Cfg::parse_override_file(&contents, parse_mode) {
+            Ok(override_file) => override_file,
+            Err(e) => return Ok((file, vec![format!("{:#}", e)])),
+        };
+
+        let mut problems: Vec<_> = unknown_toolchain_file_keys(&contents)
+            .into_iter()
+            .map(|key| format!("unknown key '{}'", key))
+            .collect();
+        for spec in override_file.target.keys() {
+            if let Err(e) = platform::validate(spec) {
+                problems.push(format!("{:#}", e));
+            }
+        }
+        // Only what applies to this host can be checked against a release.
+        let section = match override_file.for_host(&self.get_host_triple()?) {
+            Ok(override_file) => override_file.toolchain,
+            Err(_) => return Ok((file, problems)),
+        };
+        let allowed = self
+            .settings_file
+            .with(|s| Ok(s.toolchain_file_env.clone()))?;
+        let dir = file.parent().unwrap_or_else(|| Path::new("."));
+        let section = match section
+            .interpolate(&allowed)
+            .and_then(|section| section.resolve_msrv(dir))
+        {
+            Ok(section) => section,
+            Err(e) => {
+                problems.push(format!("{:#}", e));
+                return Ok((file, problems));
+            }
+        };
+        if let Some(profile) = &section.profile {
+            if let Err(e) = Profile::from_str(profile) {
+                problems.push(format!("{:#}", e));
+            }
+        }
+        match (&section.channel, &section.path) {
+            (Some(_), Some(_)) => {
+                problems.push("only one of 'channel' and 'path' can be given".to_owned())
+            }
+            (None, Some(toolchain_path)) => {
+                if section.components.is_some()
+                    || section.targets.is_some()
+                    || section.profile.is_some()
+                {
+                    problems.push(
+                        "'components', 'targets' and 'profile' can't be given for a path toolchain"
+                            .to_owned(),
+                    );
+                }
+                if !utils::is_directory(dir.join(toolchain_path)) {
+                    problems.push(format!(
+                        "there is no toolchain at '{}'",
+                        toolchain_path.display()
+                    ));
+                }
+            }
+            (Some(channel), None) => {
+                self.check_toolchain_file_channel(channel, &section, &mut problems)?
+            }
+            (None, None) => {}
+        }
+        Ok((file, problems))
+    }
+
+    fn check_toolchain_file_channel(
+        &self,
+        channel: &str,
+        section: &ToolchainSection,
+        problems: &mut Vec<String>,
+    ) -> Result<()> {
+        let known = self.list_toolchains()?.iter().any(|name| name == channel)
+            || self.settings_file.with(|s| {
+                Ok(s.toolchain_aliases.contains_key(channel)
+                    || s.virtual_toolchains.contains_key(channel))
+            })?;
+        let desc = match dist::PartialToolchainDesc::from_str(channel) {
+            Ok(desc) => desc,
+            // A custom toolchain has no manifest to check against.
+            Err(_) if known => return Ok(()),
+            Err(e) => {
+                problems.push(format!("invalid channel '{}': {:#}", channel, e));
+                return Ok(());
+            }
+        };
+        let desc = desc.resolve(&self.get_host_triple()?)?;
+
+        let notify = |n: crate::dist::Notification<'_>| (self.notify_handler)(n.into());
+        let manifest = match dist::dl_v2_manifest(self.download_cfg(&notify), None, &desc)? {
+            Some((manifest, hash)) => {
+                if let Some(pinned) = &section.manifest_sha256 {
+                    if !pinned.eq_ignore_ascii_case(&hash) {
+                        problems.push(format!(
+                            "the channel manifest for '{}' has SHA-256 '{}', but '{}' is pinned",
+                            desc, hash, pinned
+                        ));
+                    }
+                }
+                manifest
+            }
+            None => {
+                problems.push(format!("there is no release of '{}'", desc));
+                return Ok(());
+            }
+        };
+        let available = |component: Component| {
+            let component = manifest.rename_component(&component).unwrap_or(component);
+            manifest
+                .get_package(component.short_name_in_manifest())
+                .and_then(|package| package.get_target(component.target.as_ref()))
+                .map(|package| package.available())
+                .unwrap_or(false)
+        };
+        for name in section.components.iter().flat_map(|list| &list.names) {
+            let component = Component::new(name.clone(), Some(desc.target.clone()), false);
+            if !available(component) {
+                problems.push(format!(
+                    "component '{}' is not available for '{}'",
+                    name, desc
+                ));
+            }
+        }
+        for target in section.targets.iter().flat_map(|list| &list.names) {
+            let component = Component::new(
+                "rust-std".to_owned(),
+                Some(dist::TargetTriple::new(target)),
+                false,
+            );
+            if !available(component) {
+                problems.push(format!(
+                    "target '{}' is not available for '{}'",
+                    target, desc
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Combines a toolchain file which sets `inherit` with the override for
+    /// the directories above `dir`, which it was found in. Without one, it
+    /// stands alone, as any other toolchain file.
+    fn inherit_override(
+        &self,
+        override_file: OverrideFile,
+        dir: &Path,
+        settings: &Settings,
+    ) -> Result<OverrideFile> {
+        let parent = match dir.parent() {
+            // Nothing is inherited from outside the repository.
+            Some(_) if settings.stops_at_vcs_root() && is_vcs_root(dir) => None,
+            Some(parent) => self.find_override_from_dir_walk(parent, settings)?,
+            None => None,
+        };
+        match parent {
+            Some((parent_file, reason)) => {
+                let parent_dir = match &reason {
+                    OverrideReason::ToolchainFile(path) => path.parent().unwrap_or(dir),
+                    _ => dir,
+                };
+                Ok(OverrideFile {
+                    toolchain: override_file
+                        .toolchain
+                        .inherit_from(parent_file.toolchain, parent_dir),
+                    ..Default::default()
+                })
             }
-
-            dir = d.parent();
+            None => Ok(override_file),
         }
-
-        Ok(None)
     }
 
     fn parse_override_file<S: AsRef<str>>(
@@ -769,32 +2446,53 @@ impl Cfg {
             }
         }
 
-        if let Some((toolchain, components, targets, reason, profile)) =
-            match self.find_override_config(path)? {
-                Some((
-                    OverrideCfg {
+        if let Some((
+            toolchain,
+            components,
+            targets,
+            reason,
+            profile,
+            manifest_sha256,
+            dist_server,
+        )) = match self.find_override_config(path)? {
+            Some((
+                OverrideCfg {
+                    toolchain,
+                    components,
+                    targets,
+                    profile,
+                    manifest_sha256,
+                    dist_server,
+                },
+                reason,
+            )) => match toolchain {
+                Some(toolchain) => Some((
+                    toolchain,
+                    components,
+                    targets,
+                    Some(reason),
+                    profile,
+                    manifest_sha256,
+                    dist_server,
+                )),
+                // The file's dist server is only for the toolchain it
+                // chooses, not for the default.
+                None => self.find_default()?.map(|toolchain| {
+                    (
                         toolchain,
                         components,
                         targets,
+                        Some(reason),
                         profile,
-                    },
-                    reason,
-                )) => {
-                    let default = if toolchain.is_none() {
-                        self.find_default()?
-                    } else {
-                        None
-                    };
-
-                    toolchain
-                        .or(default)
-                        .map(|toolchain| (toolchain, components, targets, Some(reason), profile))
-                }
-                None => self
-                    .find_default()?
-                    .map(|toolchain| (toolchain, vec![], vec![], None, None)),
-            }
-        {
+                        manifest_sha256,
+                        None,
+                    )
+                }),
+            },
+            None => self
+                .find_default()?
+                .map(|toolchain| (toolchain, vec![], vec![], None, None, None, None)),
+        } {
             if toolchain.is_custom() {
                 if !toolchain.exists() {
                     return Err(
@@ -806,9 +2504,43 @@ impl Cfg {
                 let targets: Vec<_> = targets.iter().map(AsRef::as_ref).collect();
 
                 let distributable = DistributableToolchain::new(&toolchain)?;
-                if !toolchain.exists() || !components_exist(&distributable, &components, &targets)?
-                {
-                    distributable.install_from_dist(true, false, &components, &targets, profile)?;
+                let existed = toolchain.exists();
+                if !existed || !components_exist(&distributable, &components, &targets)? {
+                    let install = |distributable: &DistributableToolchain<'_>| -> Result<()> {
+                        match &manifest_sha256 {
+                            Some(hash) => distributable.install_pinned(
+                                hash,
+                                &components,
+                                &targets,
+                                profile,
+                            )?,
+                            None => distributable.install_from_dist(
+                                true,
+                                false,
+                                &components,
+                                &targets,
+                                profile,
+                            )?,
+                        };
+                        Ok(())
+                    };
+                    match self.with_project_dist_server(dist_server.as_deref(), reason.as_ref())? {
+                        Some(cfg) => {
+                            let toolchain = Toolchain::from(&cfg, toolchain.name())?;
+                            install(&DistributableToolchain::new(&toolchain)?)?;
+                        }
+                        None => install(&distributable)?,
+                    }
+                }
+                if let Some(hash) = &manifest_sha256 {
+                    if let Err(e) = distributable.check_installed_manifest_hash(hash) {
+                        // The server changed its mind after the check above;
+                        // don't leave behind what wasn't vetted.
+                        if !existed {
+                            toolchain.remove()?;
+                        }
+                        return Err(e);
+                    }
                 }
             }
 
@@ -846,6 +2578,39 @@ impl Cfg {
         }
     }
 
+    /// The toolchains in the system store which the user hasn't installed
+    /// one of the same name over.
+    pub fn list_system_toolchains(&self) -> Result<Vec<String>> {
+        let dir = match &self.system_toolchains_dir {
+            Some(dir) if utils::is_directory(dir) => dir,
+            _ => return Ok(Vec::new()),
+        };
+        let mut toolchains: Vec<_> = utils::read_dir("system toolchains", dir)?
+            .filter_map(io::Result::ok)
+            .filter(|e| e.file_type().map(|f| !f.is_file()).unwrap_or(false))
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|name| !self.toolchains_dir.join(name).exists())
+            .collect();
+
+        utils::toolchain_sort(&mut toolchains);
+
+        Ok(toolchains)
+    }
+
+    /// Where the toolchain called `name` is, or would be installed: the
+    /// user's own toolchain takes precedence over the system's.
+    pub(crate) fn toolchain_path(&self, name: &str) -> PathBuf {
+        let path = self.toolchains_dir.join(name);
+        // A dangling link still shadows the system's toolchain.
+        if fs::symlink_metadata(&path).is_ok() {
+            return path;
+        }
+        match &self.system_toolchains_dir {
+            Some(dir) if dir.join(name).is_dir() => dir.join(name),
+            _ => path,
+        }
+    }
+
     pub fn list_channels(&self) -> Result<Vec<(String, Result<Toolchain<'_>>)>> {
         let toolchains = self.list_toolchains()?;
 
@@ -906,7 +2671,18 @@ impl Cfg {
     }
 
     pub fn create_command_for_dir(&self, path: &Path, binary: &str) -> Result<Command> {
-        let (ref toolchain, _) = self.toolchain_for_dir(path)?;
+        let (toolchain, _) = self.toolchain_for_dir(path)?;
+        self.create_command_for_dir_toolchain(toolchain, binary)
+    }
+
+    fn create_command_for_dir_toolchain(
+        &self,
+        toolchain: Toolchain<'_>,
+        binary: &str,
+    ) -> Result<Command> {
+        let toolchain = &toolchain.for_tool(binary)?.unwrap_or(toolchain);
+        toolchain.mark_used();
+        self.record_usage(toolchain.name(), binary);
 
         if let Some(cmd) = self.maybe_do_cargo_fallback(toolchain, binary)? {
             Ok(cmd)
@@ -918,6 +2694,174 @@ impl Cfg {
         }
     }
 
+    /// Like `create_command_for_dir`, but for the proxies, which run far
+    /// more often than anything else: the toolchain chosen for `path` is
+    /// remembered until the settings, or a file in `path` or above it which
+    /// could change the choice, is touched.
+    pub fn create_command_for_dir_cached(&self, path: &Path, binary: &str) -> Result<Command> {
+        let entry = match self.proxy_cache_entry(path)? {
+            Some(entry) => entry,
+            None => return self.create_command_for_dir(path, binary),
+        };
+        if let Some(name) = entry.read() {
+            if matches!(self.get_toolchain(&name, false), Ok(t) if t.exists()) {
+                return self.create_command_for_toolchain(&name, false, binary);
+            }
+        }
+
+        let (toolchain, _) = self.toolchain_for_dir(path)?;
+        self.remember_toolchain(&entry, &toolchain, path);
+        self.create_command_for_dir_toolchain(toolchain, binary)
+    }
+
+    /// The name of the toolchain active in `path`, for `rustup prompt`: the
+    /// one the proxy cache remembers while that holds, or else the one found
+    /// as `rustup show active-toolchain` finds it, but without installing
+    /// it. `None` if there is none.
+    pub fn active_toolchain_name_cached(&self, path: &Path) -> Result<Option<String>> {
+        let entry = self.proxy_cache_entry(path)?;
+        if let Some(name) = entry.as_ref().and_then(ProxyCacheEntry::read) {
+            return Ok(Some(name));
+        }
+
+        let toolchain = match self.find_override(path)? {
+            Some((toolchain, _)) => Some(toolchain),
+            None => self.find_default()?,
+        };
+        Ok(toolchain.map(|toolchain| {
+            if let Some(entry) = &entry {
+                self.remember_toolchain(entry, &toolchain, path);
+            }
+            toolchain.requested_name().to_owned()
+        }))
+    }
+
+    /// Where the toolchain chosen for `path` is remembered, or `None` if the
+    /// choice is made by `+toolchain`, `RUSTUP_TOOLCHAIN`,
+    /// `--toolchain-file` or an override hook rather than by files.
+    fn proxy_cache_entry(&self, path: &Path) -> Result<Option<ProxyCacheEntry>> {
+        let (hook, env) = self.settings_file.with(|s| {
+            Ok((
+                !s.override_hook.command.is_empty(),
+                s.toolchain_file_env.clone(),
+            ))
+        })?;
+        if self.toolchain_override.is_some()
+            || self.env_override.is_some()
+            || self.toolchain_file_override.is_some()
+            || hook
+        {
+            return Ok(None);
+        }
+
+        let key = format!("{:x}", Sha256::digest(path.to_string_lossy().as_bytes()));
+        Ok(Some(ProxyCacheEntry {
+            file: self.proxy_cache_dir.join(key),
+            fingerprint: self.proxy_cache_fingerprint(path, &env),
+        }))
+    }
+
+    fn remember_toolchain(&self, entry: &ProxyCacheEntry, toolchain: &Toolchain<'_>, path: &Path) {
+        let name = toolchain.requested_name();
+        // Toolchains given by path can't be found again by name, and those
+        // a toolchain file pins or asks components of must be checked on
+        // every run.
+        if matches!(self.get_toolchain(name, false), Ok(ref t) if t.path() == toolchain.path())
+            && matches!(self.override_needs_checks(path), Ok(false))
+        {
+            // Without the cache the overrides are only looked for again.
+            let _ = self.trim_proxy_cache().and_then(|_| {
+                utils::write_file(
+                    "proxy cache",
+                    &entry.file,
+                    &format!("{}\n{}", entry.fingerprint, name),
+                )
+            });
+        }
+    }
+
+    /// Whether the toolchain file which applies to `path` pins the hash of
+    /// its toolchain's manifest or lists components or targets, which are
+    /// checked for each time the toolchain is chosen.
+    fn override_needs_checks(&self, path: &Path) -> Result<bool> {
+        Ok(match self.find_override_config(path)? {
+            Some((o, _)) => {
+                o.manifest_sha256.is_some() || !o.components.is_empty() || !o.targets.is_empty()
+            }
+            None => false,
+        })
+    }
+
+    /// Makes sure there's room in the proxy cache for another directory,
+    /// starting it afresh once it holds `PROXY_CACHE_MAX_ENTRIES` of them,
+    /// so that it doesn't go on growing with every directory ever built in.
+    fn trim_proxy_cache(&self) -> Result<()> {
+        let full = fs::read_dir(&self.proxy_cache_dir)
+            .map(|entries| entries.count() >= PROXY_CACHE_MAX_ENTRIES)
+            .unwrap_or(false);
+        if full {
+            utils::remove_dir(
+                "proxy cache",
+                &self.proxy_cache_dir,
+                self.notify_handler.as_ref(),
+            )?;
+        }
+        utils::ensure_dir_exists(
+            "proxy cache",
+            &self.proxy_cache_dir,
+            self.notify_handler.as_ref(),
+        )?;
+        Ok(())
+    }
+
+    /// What the choice of toolchain for `path` depends on: the settings
+    /// files, the toolchain files, manifests and repository roots in `path`
+    /// and the directories above it, and the variables in `env`, which
+    /// toolchain files may use.
+    fn proxy_cache_fingerprint(&self, path: &Path, env: &[String]) -> String {
+        fn stamp(path: &Path) -> String {
+            match fs::metadata(path) {
+                Ok(meta) => {
+                    let modified = meta
+                        .modified()
+                        .ok()
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .unwrap_or_default();
+                    format!(
+                        "{}.{}:{}",
+                        modified.as_secs(),
+                        modified.subsec_nanos(),
+                        meta.len()
+                    )
+                }
+                Err(_) => "-".to_owned(),
+            }
+        }
+
+        let mut stamps = vec![stamp(self.settings_file.path())];
+        if cfg!(not(windows)) {
+            let fallback = match process().var("RUSTUP_OVERRIDE_UNIX_FALLBACK_SETTINGS") {
+                Ok(s) => PathBuf::from(s),
+                Err(_) => PathBuf::from(UNIX_FALLBACK_SETTINGS),
+            };
+            stamps.push(stamp(&fallback));
+        }
+        if let Some(policy) = policy_path() {
+            stamps.push(stamp(&policy));
+        }
+        for dir in path.ancestors() {
+            for name in &["rust-toolchain", "rust-toolchain.toml", "Cargo.toml"] {
+                stamps.push(stamp(&dir.join(name)));
+            }
+            // Only whether these exist matters, and they change all the time.
+            stamps.push(is_vcs_root(dir).to_string());
+        }
+        for var in env {
+            stamps.push(format!("{}={:?}", var, process().var_os(var)));
+        }
+        format!("{:x}", Sha256::digest(stamps.join(" ").as_bytes()))
+    }
+
     pub fn create_command_for_toolchain(
         &self,
         toolchain: &str,
@@ -925,10 +2869,13 @@ impl Cfg {
         binary: &str,
     ) -> Result<Command> {
         let toolchain = self.get_toolchain(toolchain, false)?;
+        let toolchain = toolchain.for_tool(binary)?.unwrap_or(toolchain);
         if install_if_missing && !toolchain.exists() {
             let distributable = DistributableToolchain::new(&toolchain)?;
             distributable.install_from_dist(true, false, &[], &[], None)?;
         }
+        toolchain.mark_used();
+        self.record_usage(toolchain.name(), binary);
 
         if let Some(cmd) = self.maybe_do_cargo_fallback(&toolchain, binary)? {
             Ok(cmd)
@@ -939,6 +2886,19 @@ impl Cfg {
         }
     }
 
+    /// Sets up `cmd`, whose environment has been cleared, with the variables
+    /// `create_command_for_toolchain` gives `binary` from `toolchain`.
+    pub fn set_clean_env_for_toolchain(
+        &self,
+        toolchain: &str,
+        binary: &str,
+        cmd: &mut Command,
+    ) -> Result<()> {
+        let toolchain = self.get_toolchain(toolchain, false)?;
+        let toolchain = toolchain.for_tool(binary)?.unwrap_or(toolchain);
+        toolchain.as_installed_common()?.set_env_for(cmd, false)
+    }
+
     // Custom toolchains don't have cargo, so here we detect that situation and
     // try to find a different cargo.
     fn maybe_do_cargo_fallback(
@@ -946,11 +2906,26 @@ impl Cfg {
         toolchain: &Toolchain<'_>,
         binary: &str,
     ) -> Result<Option<Command>> {
-        if !toolchain.is_custom() {
+        if binary != "cargo" && binary != "cargo.exe" {
             return Ok(None);
         }
 
-        if binary != "cargo" && binary != "cargo.exe" {
+        if let Some(fallback) = self.cargo_fallback_toolchain(toolchain)? {
+            let distributable = DistributableToolchain::new(&fallback)?;
+            let cmd = distributable.create_fallback_command("cargo", toolchain)?;
+            return Ok(Some(cmd));
+        }
+
+        Ok(None)
+    }
+
+    /// The toolchain whose cargo is run in place of `toolchain`'s, when
+    /// `toolchain` is a custom toolchain without a cargo of its own.
+    pub fn cargo_fallback_toolchain(
+        &self,
+        toolchain: &Toolchain<'_>,
+    ) -> Result<Option<Toolchain<'_>>> {
+        if !toolchain.is_custom() {
             return Ok(None);
         }
 
@@ -965,9 +2940,7 @@ impl Cfg {
         for fallback in &["nightly", "beta", "stable"] {
             let fallback = self.get_toolchain(fallback, false)?;
             if fallback.exists() {
-                let distributable = DistributableToolchain::new(&fallback)?;
-                let cmd = distributable.create_fallback_command("cargo", toolchain)?;
-                return Ok(Some(cmd));
+                return Ok(Some(fallback));
             }
         }
 
@@ -986,6 +2959,10 @@ impl Cfg {
         })
     }
 
+    /// The host triple that toolchain names which leave it out are for in
+    /// every directory: the one set with `rustup set default-host`, else
+    /// the one rustup runs on. What is saved in the settings, such as the
+    /// default toolchain, is resolved against this.
     pub fn get_default_host_triple(&self) -> Result<dist::TargetTriple> {
         Ok(self
             .settings_file
@@ -997,17 +2974,318 @@ impl Cfg {
             .unwrap_or_else(dist::TargetTriple::from_host_or_build))
     }
 
+    /// The host triple to use in `dir`: that of a host override for it or
+    /// a directory above it, else the default host triple.
+    pub fn get_host_triple_in(&self, dir: &Path) -> Result<dist::TargetTriple> {
+        match self.find_host_override(dir)? {
+            Some((host_triple, _)) => Ok(host_triple),
+            None => self.get_default_host_triple(),
+        }
+    }
+
+    /// The host triple to use in the current directory.
+    pub fn get_host_triple(&self) -> Result<dist::TargetTriple> {
+        match process().current_dir() {
+            Ok(cwd) => self.get_host_triple_in(&cwd),
+            Err(_) => self.get_default_host_triple(),
+        }
+    }
+
+    /// The host triple to resolve the toolchain name `name` against: that
+    /// of a host override for `dir`, if given, else that of a host
+    /// override for the toolchain, else the default host triple.
+    fn host_triple_for(&self, name: &str, dir: Option<&Path>) -> Result<dist::TargetTriple> {
+        if let Some(dir) = dir {
+            if let Some((host_triple, _)) = self.find_host_override(dir)? {
+                return Ok(host_triple);
+            }
+        }
+        let host_triple = self
+            .settings_file
+            .with(|s| Ok(s.toolchain_host_overrides.get(name).cloned()))?;
+        match host_triple {
+            Some(host_triple) => Ok(dist::TargetTriple::new(&host_triple)),
+            None => self.get_default_host_triple(),
+        }
+    }
+
+    pub fn set_host_override(&self, path: &Path, host_triple: &str) -> Result<()> {
+        // As for the default host triple, check that it can name toolchains.
+        dist::PartialToolchainDesc::from_str("stable")?
+            .resolve(&dist::TargetTriple::new(host_triple))?;
+        self.settings_file.with_mut(|s| {
+            s.add_host_override(path, host_triple.to_owned(), self.notify_handler.as_ref());
+            Ok(())
+        })
+    }
+
+    pub fn remove_host_override(&self, path: &Path) -> Result<bool> {
+        self.settings_file
+            .with_mut(|s| Ok(s.remove_host_override(path, self.notify_handler.as_ref())))
+    }
+
+    /// Sets the host triple to use for `toolchain`, a name which leaves it
+    /// out, such as `nightly`, wherever no directory has a host override.
+    pub fn set_toolchain_host_override(&self, toolchain: &str, host_triple: &str) -> Result<()> {
+        if dist::PartialToolchainDesc::from_str(toolchain)?.has_triple() {
+            bail!(
+                "'{}' already names its host; a host can only be set for a name without one",
+                toolchain
+            );
+        }
+        dist::PartialToolchainDesc::from_str("stable")?
+            .resolve(&dist::TargetTriple::new(host_triple))?;
+        self.settings_file.with_mut(|s| {
+            s.toolchain_host_overrides
+                .insert(toolchain.to_owned(), host_triple.to_owned());
+            Ok(())
+        })
+    }
+
+    pub fn remove_toolchain_host_override(&self, toolchain: &str) -> Result<bool> {
+        self.settings_file
+            .with_mut(|s| Ok(s.toolchain_host_overrides.remove(toolchain).is_some()))
+    }
+
+    /// Finds the host override for `dir` or the nearest directory above it
+    /// which has one, along with that directory.
+    pub fn find_host_override(&self, dir: &Path) -> Result<Option<(dist::TargetTriple, PathBuf)>> {
+        let notify = self.notify_handler.as_ref();
+        self.settings_file.with(|s| {
+            if s.host_overrides.is_empty() {
+                return Ok(None);
+            }
+            let mut dir = Some(dir);
+            while let Some(d) = dir {
+                if let Some(host_triple) = s.dir_host_override(d, notify) {
+                    return Ok(Some((dist::TargetTriple::new(&host_triple), d.to_owned())));
+                }
+                dir = d.parent();
+            }
+            Ok(None)
+        })
+    }
+
     pub fn resolve_toolchain(&self, name: &str) -> Result<String> {
-        if let Ok(desc) = dist::PartialToolchainDesc::from_str(name) {
-            let host = self.get_default_host_triple()?;
-            Ok(desc.resolve(&host)?.to_string())
-        } else {
-            Ok(name.to_owned())
+        let cwd = process().current_dir().ok();
+        self.resolve_toolchain_in(name, cwd.as_deref())
+    }
+
+    /// Resolves `name` as it is used in `dir`, or, without one, as it is
+    /// used in every directory, leaving out host overrides for directories.
+    fn resolve_toolchain_in(&self, name: &str, dir: Option<&Path>) -> Result<String> {
+        // Aliases always name a real toolchain, never another alias.
+        let name = match self.get_alias(name)? {
+            Some(target) => target,
+            // Unless a toolchain of that name, which would be a linked one,
+            // is installed.
+            None if name == MSRV_CHANNEL && !self.toolchains_dir.join(name).exists() => match dir {
+                Some(dir) => find_msrv(dir)?,
+                None => find_msrv(&process().current_dir()?)?,
+            },
+            None => name.to_owned(),
+        };
+        match dist::PartialToolchainDesc::from_str(&name) {
+            Ok(desc) => {
+                let host = self.host_triple_for(&name, dir)?;
+                let desc = desc.resolve(&host)?;
+                if dist::is_date_bound(&name) {
+                    self.resolve_date_bound(&desc)
+                } else {
+                    Ok(desc.to_string())
+                }
+            }
+            // Only a channel bound, like `stable<=1.75`, contains a `<`, so
+            // say what is wrong with it rather than take it for a custom
+            // toolchain.
+            Err(e) if name.contains('<') => Err(e),
+            Err(_) => Ok(name),
+        }
+    }
+
+    /// The newest release up to `latest`, the latest a channel bounded by a
+    /// date could resolve to. What a bound in the past resolves to can't
+    /// change, since releases aren't added to days gone by, so it is
+    /// remembered rather than looked for on every run.
+    fn resolve_date_bound(&self, latest: &dist::ToolchainDesc) -> Result<String> {
+        let key = format!("{:x}", Sha256::digest(latest.to_string().as_bytes()));
+        let memo = self.cache_dir().join("channel-bounds").join(key);
+        if let Ok(resolved) = utils::read_file("channel bound", &memo) {
+            return Ok(resolved.trim().to_owned());
+        }
+
+        let notify = |n: crate::dist::Notification<'_>| (self.notify_handler)(n.into());
+        let installed = |c: &dist::ToolchainDesc| self.toolchains_dir.join(c.to_string()).is_dir();
+        let resolved = dist::newest_release(self.download_cfg(&notify), latest, &installed)?;
+        let resolved = resolved.to_string();
+
+        // Releases can turn up a day or so after their date.
+        let settled = (chrono::Utc::now() - chrono::Duration::days(2))
+            .format("%Y-%m-%d")
+            .to_string();
+        if matches!(&latest.date, Some(date) if *date < settled) {
+            // Without it, the bound is only looked for again.
+            let _ =
+                utils::ensure_dir_exists("cache", memo.parent().unwrap(), &|_: Notification<
+                    '_,
+                >| {})
+                .and_then(|_| utils::write_file("channel bound", &memo, &resolved));
+        }
+        Ok(resolved)
+    }
+
+    pub fn get_alias(&self, alias: &str) -> Result<Option<String>> {
+        self.settings_file
+            .with(|s| Ok(s.toolchain_aliases.get(alias).cloned()))
+    }
+
+    pub fn set_alias(&self, alias: &str, toolchain: &str) -> Result<()> {
+        if alias.is_empty()
+            || alias.contains(&['/', '\\'][..])
+            || alias == MSRV_CHANNEL
+            || dist::PartialToolchainDesc::from_str(alias).is_ok()
+        {
+            return Err(anyhow!("invalid toolchain alias: '{}'", alias));
+        }
+        if self.toolchains_dir.join(alias).exists() {
+            return Err(anyhow!(
+                "'{}' is already the name of an installed toolchain",
+                alias
+            ));
+        }
+        if self.get_alias(toolchain)?.is_some() {
+            return Err(anyhow!(
+                "'{}' is itself an alias; aliases must name a toolchain",
+                toolchain
+            ));
+        }
+        self.settings_file.with_mut(|s| {
+            s.toolchain_aliases
+                .insert(alias.to_owned(), toolchain.to_owned());
+            Ok(())
+        })
+    }
+
+    /// Returns whether there was such an alias.
+    pub fn remove_alias(&self, alias: &str) -> Result<bool> {
+        self.settings_file
+            .with_mut(|s| Ok(s.toolchain_aliases.remove(alias).is_some()))
+    }
+
+    /// Removes `name` from the settings if it is an alias or a virtual
+    /// toolchain, returning whether it was one. The toolchain it stands for
+    /// is left as it is.
+    pub fn remove_toolchain_name(&self, name: &str) -> Result<bool> {
+        Ok(self.remove_alias(name)? || self.remove_virtual_toolchain(name)?)
+    }
+
+    pub fn get_virtual_toolchain(&self, name: &str) -> Result<Option<VirtualToolchain>> {
+        self.settings_file
+            .with(|s| Ok(s.virtual_toolchains.get(name).cloned()))
+    }
+
+    /// Defines the virtual toolchain `name`, replacing any existing one.
+    pub fn set_virtual_toolchain(&self, name: &str, toolchain: VirtualToolchain) -> Result<()> {
+        if name.is_empty()
+            || name.contains(&['/', '\\'][..])
+            || dist::PartialToolchainDesc::from_str(name).is_ok()
+        {
+            return Err(anyhow!("invalid virtual toolchain name: '{}'", name));
+        }
+        if self.toolchains_dir.join(name).exists() || self.get_alias(name)?.is_some() {
+            return Err(anyhow!(
+                "'{}' is already the name of a toolchain or alias",
+                name
+            ));
+        }
+        for source in toolchain.tools.values().chain(Some(&toolchain.base)) {
+            if source == name || self.get_virtual_toolchain(source)?.is_some() {
+                return Err(anyhow!(
+                    "'{}' is itself a virtual toolchain; virtual toolchains must be made of real ones",
+                    source
+                ));
+            }
+        }
+        self.settings_file.with_mut(|s| {
+            s.virtual_toolchains.insert(name.to_owned(), toolchain);
+            Ok(())
+        })
+    }
+
+    /// Returns whether there was such a virtual toolchain.
+    pub fn remove_virtual_toolchain(&self, name: &str) -> Result<bool> {
+        self.settings_file
+            .with_mut(|s| Ok(s.virtual_toolchains.remove(name).is_some()))
+    }
+
+    /// The environment variables set for `toolchain`'s tools, which must be
+    /// a full toolchain name.
+    pub fn get_toolchain_env(&self, toolchain: &str) -> Result<BTreeMap<String, String>> {
+        self.settings_file
+            .with(|s| Ok(s.toolchain_env.get(toolchain).cloned().unwrap_or_default()))
+    }
+
+    pub fn set_toolchain_env(&self, toolchain: &str, key: &str, value: &str) -> Result<()> {
+        if key.is_empty() || key.contains(&['=', '\0'][..]) {
+            return Err(anyhow!("invalid environment variable name: '{}'", key));
         }
+        self.settings_file.with_mut(|s| {
+            s.toolchain_env
+                .entry(toolchain.to_owned())
+                .or_default()
+                .insert(key.to_owned(), value.to_owned());
+            Ok(())
+        })
+    }
+
+    /// The command to run `toolchain`'s rustc through, if any.
+    pub fn get_rustc_wrapper(&self, toolchain: &str) -> Result<Option<String>> {
+        self.settings_file.with(|s| {
+            let wrapper = match s.toolchain_rustc_wrapper.get(toolchain) {
+                Some(wrapper) => Some(wrapper.clone()),
+                None => s.rustc_wrapper.clone(),
+            };
+            Ok(wrapper.filter(|w| !w.is_empty()))
+        })
+    }
+
+    /// Sets the command to run rustc through for `toolchain`, or for every
+    /// toolchain without one of its own. `None` removes it, and for a
+    /// toolchain an empty command means none at all.
+    pub fn set_rustc_wrapper(&self, toolchain: Option<&str>, wrapper: Option<&str>) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            match (toolchain, wrapper) {
+                (Some(toolchain), Some(wrapper)) => {
+                    s.toolchain_rustc_wrapper
+                        .insert(toolchain.to_owned(), wrapper.to_owned());
+                }
+                (Some(toolchain), None) => {
+                    s.toolchain_rustc_wrapper.remove(toolchain);
+                }
+                (None, wrapper) => s.rustc_wrapper = wrapper.map(str::to_owned),
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns whether the variable was set for the toolchain.
+    pub fn unset_toolchain_env(&self, toolchain: &str, key: &str) -> Result<bool> {
+        self.settings_file.with_mut(|s| {
+            let vars = match s.toolchain_env.get_mut(toolchain) {
+                Some(vars) => vars,
+                None => return Ok(false),
+            };
+            let removed = vars.remove(key).is_some();
+            if vars.is_empty() {
+                s.toolchain_env.remove(toolchain);
+            }
+            Ok(removed)
+        })
     }
 }
 
 /// Specifies how a `rust-toolchain`/`rust-toolchain.toml` configuration file should be parsed.
+#[derive(Clone, Copy)]
 enum ParseMode {
     /// Only permit TOML format in a configuration file.
     ///
@@ -1021,6 +3299,17 @@ enum ParseMode {
     Both,
 }
 
+impl ParseMode {
+    /// How a toolchain file with any name is parsed: as TOML if it ends in
+    /// `.toml`, and otherwise in either format.
+    fn for_file(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext == "toml" => Self::OnlyToml,
+            _ => Self::Both,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1039,7 +3328,11 @@ mod tests {
                     components: None,
                     targets: None,
                     profile: None,
-                }
+                    manifest_sha256: None,
+                    dist_server: None,
+                    inherit: false,
+                },
+                target: BTreeMap::new(),
             }
         );
     }
@@ -1060,13 +3353,16 @@ profile = "default"
                 toolchain: ToolchainSection {
                     channel: Some("nightly-2020-07-10".into()),
                     path: None,
-                    components: Some(vec!["rustfmt".into(), "rustc-dev".into()]),
-                    targets: Some(vec![
-                        "wasm32-unknown-unknown".into(),
-                        "thumbv2-none-eabi".into()
-                    ]),
+                    components: Some(vec!["rustfmt".into(), "rustc-dev".into()].into()),
+                    targets: Some(
+                        vec!["wasm32-unknown-unknown".into(), "thumbv2-none-eabi".into()].into()
+                    ),
                     profile: Some("default".into()),
-                }
+                    manifest_sha256: None,
+                    dist_server: None,
+                    inherit: false,
+                },
+                target: BTreeMap::new(),
             }
         );
     }
@@ -1087,7 +3383,11 @@ channel = "nightly-2020-07-10"
                     components: None,
                     targets: None,
                     profile: None,
-                }
+                    manifest_sha256: None,
+                    dist_server: None,
+                    inherit: false,
+                },
+                target: BTreeMap::new(),
             }
         );
     }
@@ -1108,7 +3408,11 @@ path = "foobar"
                     components: None,
                     targets: None,
                     profile: None,
-                }
+                    manifest_sha256: None,
+                    dist_server: None,
+                    inherit: false,
+                },
+                target: BTreeMap::new(),
             }
         );
     }
@@ -1127,10 +3431,14 @@ components = []
                 toolchain: ToolchainSection {
                     channel: Some("nightly-2020-07-10".into()),
                     path: None,
-                    components: Some(vec![]),
+                    components: Some(vec![].into()),
                     targets: None,
                     profile: None,
-                }
+                    manifest_sha256: None,
+                    dist_server: None,
+                    inherit: false,
+                },
+                target: BTreeMap::new(),
             }
         );
     }
@@ -1150,9 +3458,13 @@ targets = []
                     channel: Some("nightly-2020-07-10".into()),
                     path: None,
                     components: None,
-                    targets: Some(vec![]),
+                    targets: Some(vec![].into()),
                     profile: None,
-                }
+                    manifest_sha256: None,
+                    dist_server: None,
+                    inherit: false,
+                },
+                target: BTreeMap::new(),
             }
         );
     }
@@ -1170,14 +3482,120 @@ components = [ "rustfmt" ]
                 toolchain: ToolchainSection {
                     channel: None,
                     path: None,
-                    components: Some(vec!["rustfmt".into()]),
+                    components: Some(vec!["rustfmt".into()].into()),
                     targets: None,
                     profile: None,
-                }
+                    manifest_sha256: None,
+                    dist_server: None,
+                    inherit: false,
+                },
+                target: BTreeMap::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn inherit_toolchain_section() {
+        let parent = Cfg::parse_override_file(
+            r#"[toolchain]
+channel = "nightly-2020-07-10"
+components = [ "rustfmt" ]
+targets = [ "wasm32-unknown-unknown" ]
+"#,
+            ParseMode::OnlyToml,
+        )
+        .unwrap();
+        let child = Cfg::parse_override_file(
+            r#"[toolchain]
+inherit = true
+components = [ "rustfmt", "rust-src" ]
+profile = "minimal"
+"#,
+            ParseMode::OnlyToml,
+        )
+        .unwrap();
+
+        assert_eq!(
+            child
+                .toolchain
+                .inherit_from(parent.toolchain, Path::new("/workspace")),
+            ToolchainSection {
+                channel: Some("nightly-2020-07-10".into()),
+                path: None,
+                components: Some(vec!["rustfmt".into(), "rust-src".into()].into()),
+                targets: Some(vec!["wasm32-unknown-unknown".into()].into()),
+                profile: Some("minimal".into()),
+                manifest_sha256: None,
+                dist_server: None,
+                inherit: false,
             }
         );
     }
 
+    #[test]
+    fn inherit_toolchain_section_add_and_replace() {
+        let parse = |contents: &str| {
+            Cfg::parse_override_file(contents, ParseMode::OnlyToml).map(|file| file.toolchain)
+        };
+        let parent = || {
+            parse(
+                r#"[toolchain]
+channel = "nightly"
+components = [ "rustfmt" ]
+targets = [ "wasm32-unknown-unknown" ]
+"#,
+            )
+            .unwrap()
+        };
+        let child = parse(
+            r#"[toolchain]
+inherit = true
+components = { add = [ "clippy" ] }
+targets = { replace = [ "thumbv2-none-eabi" ] }
+"#,
+        )
+        .unwrap()
+        .inherit_from(parent(), Path::new("/workspace"));
+        assert_eq!(
+            child.components.unwrap().names,
+            vec!["rustfmt".to_owned(), "clippy".to_owned()]
+        );
+        assert_eq!(
+            child.targets.unwrap().names,
+            vec!["thumbv2-none-eabi".to_owned()]
+        );
+
+        for invalid in &[
+            "components = { add = [], replace = [] }",
+            "components = {}",
+            "components = { remove = [ \"rustfmt\" ] }",
+        ] {
+            assert!(
+                parse(&format!("[toolchain]\n{}", invalid)).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_toolchain_file_keys_are_found() {
+        let contents = r#"
+[toolchain]
+channel = "nightly-2020-07-10"
+componets = [ "rustfmt" ]
+
+[tools]
+rustfmt = true
+"#;
+
+        assert_eq!(
+            unknown_toolchain_file_keys(contents),
+            vec!["toolchain.componets".to_owned(), "tools".to_owned()]
+        );
+        assert!(unknown_toolchain_file_keys("nightly-2020-07-10").is_empty());
+    }
+
     #[test]
     fn parse_empty_toml_toolchain_file() {
         let contents = r#"
@@ -54,8 +54,9 @@ use varsource::*;
 /// and even there we should be doing debouncing and managing update rates).
 /// The real trait is CurrentProcess; HomeProcess is a single trait because
 /// Box<T> only allows autotraits to be added to it; so we use a subtrait to add
-/// home::Env in.
-pub trait HomeProcess: CurrentProcess + home::Env {
+/// home::Env in. It is Send so that work can be handed to other threads along
+/// with the process it is done for.
+pub trait HomeProcess: CurrentProcess + home::Env + Send {
     fn clone_boxed(&self) -> Box<dyn HomeProcess>;
 }
 
@@ -63,7 +64,7 @@ pub trait HomeProcess: CurrentProcess + home::Env {
 
 impl<T> HomeProcess for T
 where
-    T: 'static + CurrentProcess + home::Env + Clone,
+    T: 'static + CurrentProcess + home::Env + Clone + Send,
 {
     fn clone_boxed(&self) -> Box<dyn HomeProcess + 'static> {
         Box::new(T::clone(self))
@@ -59,6 +59,16 @@ impl<'a> Transaction<'a> {
     /// Commit must be called for all successful transactions. If not
     /// called the transaction will be rolled back on drop.
     pub fn commit(mut self) {
+        for item in &self.changes {
+            match item {
+                ChangedItem::AddedFile(path)
+                | ChangedItem::AddedDir(path)
+                | ChangedItem::ModifiedFile(path, _) => {
+                    utils::share_with_group(&self.prefix.abs_path(path), true)
+                }
+                ChangedItem::RemovedFile(..) | ChangedItem::RemovedDir(..) => {}
+            }
+        }
         self.committed = true;
     }
 
@@ -3,24 +3,26 @@ use std::env;
 use std::fmt;
 use std::io::Write;
 use std::ops::Deref;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::{anyhow, bail, Context, Result};
 use chrono::{Date, NaiveDate, TimeZone, Utc};
 use lazy_static::lazy_static;
 use regex::Regex;
+use sha2::{Digest, Sha256};
 use thiserror::Error as ThisError;
 
-use crate::dist::download::DownloadCfg;
-use crate::dist::manifest::{Component, Manifest as ManifestV2};
-use crate::dist::manifestation::{Changes, Manifestation, UpdateStatus};
+use crate::dist::download::{DownloadCfg, UPDATE_HASH_LEN};
+use crate::dist::manifest::{Component, Manifest as ManifestV2, PackageTargets};
+use crate::dist::manifestation::{Changes, Manifestation, UpdatePlan, UpdateStatus};
 use crate::dist::notifications::*;
 use crate::dist::prefix::InstallPrefix;
 use crate::dist::temp;
 pub use crate::dist::triple::*;
-use crate::errors::RustupError;
+use crate::errors::{Hint, RustupError};
 use crate::process;
+use crate::utils::raw;
 use crate::utils::utils;
 
 pub static DEFAULT_DIST_SERVER: &str = "https://static.rust-lang.org";
@@ -86,13 +88,62 @@ fn components_missing_msg(cs: &[Component], manifest: &ManifestV2, toolchain: &s
 }
 
 #[derive(Debug, ThisError)]
-enum DistError {
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum DistError {
+    /// The last field is the latest earlier nightly which has the
+    /// components, when a dated nightly lacks them.
     #[error("{}", components_missing_msg(&.0, &.1, &.2))]
-    ToolchainComponentsMissing(Vec<Component>, ManifestV2, String),
+    ToolchainComponentsMissing(Vec<Component>, ManifestV2, String, Option<ToolchainDesc>),
     #[error("no release found for '{0}'")]
     MissingReleaseForToolchain(String),
 }
 
+impl DistError {
+    pub(crate) fn hint(&self) -> Option<Hint> {
+        match self {
+            Self::ToolchainComponentsMissing(components, manifest, _, Some(latest)) => {
+                let names = components
+                    .iter()
+                    .map(|c| c.description(manifest))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let name = if latest.target == TargetTriple::from_host_or_build() {
+                    latest.manifest_name()
+                } else {
+                    latest.to_string()
+                };
+                let mut command = format!("rustup toolchain install {}", name);
+                let (targets, components): (Vec<_>, Vec<_>) = components
+                    .iter()
+                    .partition(|c| c.short_name_in_manifest() == "rust-std");
+                if !components.is_empty() {
+                    let components: Vec<_> =
+                        components.iter().map(|c| c.short_name(manifest)).collect();
+                    command.push_str(&format!(" --component {}", components.join(",")));
+                }
+                let targets: Vec<_> = targets
+                    .iter()
+                    .filter_map(|c| c.target.as_ref())
+                    .filter(|target| **target != latest.target)
+                    .map(ToString::to_string)
+                    .collect();
+                if !targets.is_empty() {
+                    command.push_str(&format!(" --target {}", targets.join(",")));
+                }
+                Some(Hint::run(
+                    format!(
+                        "the latest nightly with {} is {}; to install it",
+                        names,
+                        latest.date.as_deref().unwrap_or_default()
+                    ),
+                    command,
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 struct ParsedToolchainDesc {
     channel: String,
@@ -152,9 +203,88 @@ static TRIPLE_MIPS64_UNKNOWN_LINUX_GNUABI64: &str = "mips64-unknown-linux-gnuabi
 #[cfg(all(not(windows), target_endian = "little"))]
 static TRIPLE_MIPS64_UNKNOWN_LINUX_GNUABI64: &str = "mips64el-unknown-linux-gnuabi64";
 
+lazy_static! {
+    static ref BOUND_RE: Regex =
+        Regex::new(r"^(stable|beta|nightly)(<=?)(\d{4}-\d{2}-\d{2}|\d+\.\d+(?:\.\d+)?)(-.+)?$")
+            .unwrap();
+}
+
+/// Whether `desc` is a channel bounded by a date, like `nightly<2024-06-01`.
+pub(crate) fn is_date_bound(desc: &str) -> bool {
+    matches!(BOUND_RE.captures(desc), Some(c) if c[3].len() == 10)
+}
+
+/// Resolves a channel with an upper bound, like `stable<=1.75` or
+/// `nightly<2024-06-01`, to the newest release within the bound, giving
+/// `None` for a toolchain name without one.
+///
+/// Stable can be bounded by a version, and any channel by a date. A bound
+/// by a date resolves to that date, or the day before it, which is only the
+/// latest release there could be: `newest_release` finds the one there is. A bound
+/// below a version resolves to the release before it, which for `<1.75` is
+/// the latest `1.74.x`.
+fn resolve_channel_bound(desc: &str) -> Result<Option<String>> {
+    if !desc.contains('<') {
+        return Ok(None);
+    }
+    let c = BOUND_RE
+        .captures(desc)
+        .ok_or_else(|| RustupError::InvalidToolchainName(desc.to_string()))?;
+    let channel = &c[1];
+    let inclusive = &c[2] == "<=";
+    let bound = &c[3];
+    let rest = c.get(4).map_or("", |m| m.as_str());
+
+    if let Ok(date) = NaiveDate::parse_from_str(bound, "%Y-%m-%d") {
+        let date = if inclusive {
+            Some(date)
+        } else {
+            date.pred_opt()
+        }
+        .ok_or_else(|| RustupError::InvalidToolchainName(desc.to_string()))?;
+        return Ok(Some(format!(
+            "{}-{}{}",
+            channel,
+            date.format("%Y-%m-%d"),
+            rest
+        )));
+    }
+    if bound.len() == 10 {
+        bail!("invalid date in toolchain name '{}'", desc);
+    }
+    if channel != "stable" {
+        bail!(
+            "'{}' is bounded by a version, but only stable releases have version numbers",
+            desc
+        );
+    }
+
+    let parts: Vec<u32> = bound
+        .split('.')
+        .map(|part| part.parse())
+        .collect::<std::result::Result<_, _>>()
+        .map_err(|_| RustupError::InvalidToolchainName(desc.to_string()))?;
+    let version = match (inclusive, parts.as_slice()) {
+        (true, _) => bound.to_owned(),
+        (false, [major, minor, patch]) if *patch > 0 => {
+            format!("{}.{}.{}", major, minor, patch - 1)
+        }
+        (false, [major, minor, ..]) if *minor > 0 => format!("{}.{}", major, minor - 1),
+        _ => bail!(
+            "can't tell which release comes before {}; give a bound like 'stable<=1.75' instead",
+            bound
+        ),
+    };
+    Ok(Some(format!("{}{}", version, rest)))
+}
+
 impl FromStr for ParsedToolchainDesc {
     type Err = anyhow::Error;
     fn from_str(desc: &str) -> Result<Self> {
+        if let Some(resolved) = resolve_channel_bound(desc)? {
+            return resolved.parse();
+        }
+
         lazy_static! {
             static ref TOOLCHAIN_CHANNEL_PATTERN: String = format!(
                 r"^({})(?:-(\d{{4}}-\d{{2}}-\d{{2}}))?(?:-(.+))?$",
@@ -669,15 +799,7 @@ fn update_from_dist_<'a>(
     let mut backtrack_limit: Option<i32> = if toolchain.date.is_some() {
         None
     } else {
-        // We limit the backtracking to 21 days by default (half a release cycle).
-        // The limit of 21 days is an arbitrary selection, so we let the user override it.
-        const BACKTRACK_LIMIT_DEFAULT: i32 = 21;
-        let provided = process()
-            .var("RUSTUP_BACKTRACK_LIMIT")
-            .ok()
-            .and_then(|v| v.parse().ok())
-            .unwrap_or(BACKTRACK_LIMIT_DEFAULT);
-        Some(if provided < 1 { 1 } else { provided })
+        Some(backtrack_limit())
     };
 
     // In case there is no allow-downgrade option set
@@ -719,7 +841,7 @@ fn update_from_dist_<'a>(
             Ok(v) => break Ok(v),
             Err(e) => {
                 if !backtrack {
-                    break Err(e);
+                    break Err(with_latest_nightly(download, &toolchain, e));
                 }
 
                 let cause = e.downcast_ref::<DistError>();
@@ -784,6 +906,352 @@ fn update_from_dist_<'a>(
     }
 }
 
+/// How many nightlies to go back through looking for one which has the
+/// components wanted.
+fn backtrack_limit() -> i32 {
+    // We limit the backtracking to 21 days by default (half a release cycle).
+    // The limit of 21 days is an arbitrary selection, so we let the user override it.
+    const BACKTRACK_LIMIT_DEFAULT: i32 = 21;
+    let provided = process()
+        .var("RUSTUP_BACKTRACK_LIMIT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(BACKTRACK_LIMIT_DEFAULT);
+    if provided < 1 {
+        1
+    } else {
+        provided
+    }
+}
+
+/// Adds to `e`, if it is about a dated nightly lacking components, the
+/// latest earlier nightly which has them, for the hint to suggest.
+fn with_latest_nightly(
+    download: DownloadCfg<'_>,
+    toolchain: &ToolchainDesc,
+    mut e: anyhow::Error,
+) -> anyhow::Error {
+    if toolchain.channel != "nightly" {
+        return e;
+    }
+    if let Some(DistError::ToolchainComponentsMissing(components, _, _, latest)) =
+        e.downcast_mut::<DistError>()
+    {
+        *latest = latest_nightly_with(download, toolchain, components);
+    }
+    e
+}
+
+/// The latest nightly before `toolchain` which has all of `components`,
+/// going back no further than the backtrack limit. Only the manifests are
+/// downloaded, and the search gives up at the first failure other than a
+/// day without a nightly.
+fn latest_nightly_with(
+    download: DownloadCfg<'_>,
+    toolchain: &ToolchainDesc,
+    components: &[Component],
+) -> Option<ToolchainDesc> {
+    let mut date = utc_from_manifest_date(toolchain.date.as_ref()?)?;
+    for _ in 0..backtrack_limit() {
+        date = date.pred();
+        let mut candidate = toolchain.clone();
+        candidate.date = Some(date.format("%Y-%m-%d").to_string());
+        let manifest = match dl_v2_manifest(download, None, &candidate) {
+            Ok(Some((manifest, _))) => manifest,
+            Ok(None) => continue,
+            Err(e) => match e.downcast_ref::<RustupError>() {
+                Some(RustupError::DownloadNotExists { .. }) => continue,
+                _ => return None,
+            },
+        };
+        let has = |c: &Component| {
+            manifest
+                .get_package(c.short_name_in_manifest())
+                .and_then(|pkg| pkg.get_target(c.target.as_ref()))
+                .map(|pkg| pkg.available())
+                .unwrap_or(false)
+        };
+        if components.iter().all(has) {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+/// The newest release of `toolchain`'s channel dated no later than it, for
+/// a channel bounded by a date, looked for a day at a time, as far back as
+/// `latest_nightly_with` looks. A day is only asked of the dist server if
+/// its release isn't installed, as `installed` says.
+pub(crate) fn newest_release(
+    download: DownloadCfg<'_>,
+    toolchain: &ToolchainDesc,
+    installed: &dyn Fn(&ToolchainDesc) -> bool,
+) -> Result<ToolchainDesc> {
+    let latest = toolchain
+        .date
+        .as_deref()
+        .and_then(utc_from_manifest_date)
+        .ok_or_else(|| anyhow!("'{}' has no date to look back from", toolchain))?;
+    // Stable is only released every six weeks, so look back over two
+    // release cycles for it.
+    let limit = if toolchain.channel == "stable" {
+        backtrack_limit().max(84)
+    } else {
+        backtrack_limit()
+    };
+    let mut date = latest;
+    for _ in 0..limit {
+        let mut candidate = toolchain.clone();
+        candidate.date = Some(date.format("%Y-%m-%d").to_string());
+        date = date.pred();
+        if installed(&candidate) {
+            return Ok(candidate);
+        }
+        let url = utils::parse_url(&format!(
+            "{}.sha256",
+            candidate.manifest_v2_url(download.dist_root)
+        ))?;
+        let file = download.temp_cfg.new_file()?;
+        match utils::download_file(&url, &file, None, &|n| (download.notify_handler)(n.into())) {
+            Ok(()) => return Ok(candidate),
+            Err(e) => match e.downcast_ref::<RustupError>() {
+                Some(RustupError::DownloadNotExists { .. }) => continue,
+                _ => {
+                    return Err(e).with_context(|| {
+                        format!("could not look for the newest release of '{}'", toolchain)
+                    })
+                }
+            },
+        }
+    }
+    Err(anyhow!(
+        "no release of '{}' was found in the {} days up to {}",
+        toolchain.channel,
+        limit,
+        latest.format("%Y-%m-%d")
+    ))
+}
+
+/// The components to install for `toolchain`: those in `profile`, plus any
+/// requested explicitly or implied by `targets`.
+fn requested_changes(
+    m: &ManifestV2,
+    toolchain: &ToolchainDesc,
+    profile: Option<Profile>,
+    components: &[&str],
+    targets: &[&str],
+) -> Result<Changes> {
+    let profile_components = match profile {
+        Some(profile) => m.get_profile_components(profile, &toolchain.target)?,
+        None => Vec::new(),
+    };
+
+    let mut all_components: HashSet<Component> = profile_components.into_iter().collect();
+
+    let rust_package = m.get_package("rust")?;
+    let rust_target_package = rust_package.get_target(Some(&toolchain.target.clone()))?;
+
+    for component in components.iter().copied() {
+        let mut component =
+            Component::new(component.to_string(), Some(toolchain.target.clone()), false);
+        if let Some(renamed) = m.rename_component(&component) {
+            component = renamed;
+        }
+        // Look up the newly constructed/renamed component and ensure that
+        // if it's a wildcard component we note such, otherwise we end up
+        // exacerbating the problem we thought we'd fixed with #2087 and #2115
+        if let Some(c) = rust_target_package
+            .components
+            .iter()
+            .find(|c| c.short_name_in_manifest() == component.short_name_in_manifest())
+        {
+            if c.target.is_none() {
+                component = component.wildcard();
+            }
+        }
+        all_components.insert(component);
+    }
+
+    for target in targets {
+        let triple = TargetTriple::new(target);
+        all_components.insert(Component::new("rust-std".to_string(), Some(triple), false));
+    }
+
+    let mut explicit_add_components: Vec<_> = all_components.into_iter().collect();
+    explicit_add_components.sort();
+
+    Ok(Changes {
+        explicit_add_components,
+        remove_components: Vec::new(),
+    })
+}
+
+/// What updating the toolchain at `prefix` to the latest `toolchain` would
+/// change, found out without changing anything. `None` if it is up to date.
+pub fn plan_update(
+    download: DownloadCfg<'_>,
+    update_hash: Option<&Path>,
+    toolchain: &ToolchainDesc,
+    profile: Option<Profile>,
+    prefix: &InstallPrefix,
+    components: &[&str],
+    targets: &[&str],
+) -> Result<Option<UpdatePlan>> {
+    let update_hash = if components.is_empty() && targets.is_empty() {
+        update_hash
+    } else {
+        None
+    };
+    let m = match dl_v2_manifest(download, update_hash, toolchain)? {
+        Some((m, _)) => m,
+        None => return Ok(None),
+    };
+    let changes = requested_changes(&m, toolchain, profile, components, targets)?;
+    let manifestation = Manifestation::open(prefix.clone(), toolchain.target.clone())?;
+    manifestation.plan(&m, changes, &download, &toolchain.manifest_name())
+}
+
+/// Downloads and verifies everything needed to install `toolchain` into the
+/// download cache, without touching any installed toolchain.
+///
+/// The channel manifest is saved alongside, so that a later install can
+/// proceed without network access. Returns the date of the manifest.
+pub fn prefetch(
+    download: DownloadCfg<'_>,
+    toolchain: &ToolchainDesc,
+    profile: Option<Profile>,
+    components: &[&str],
+    targets: &[&str],
+) -> Result<String> {
+    let toolchain_str = toolchain.to_string();
+    (download.notify_handler)(Notification::DownloadingManifest(&toolchain_str));
+    let manifest_url = toolchain.manifest_v2_url(download.dist_root);
+    let (manifest_file, _) = download
+        .download_and_check(&manifest_url, None, ".toml")?
+        .ok_or_else(|| anyhow!("no manifest was downloaded for '{}'", toolchain_str))?;
+    let manifest_str = utils::read_file("manifest", &manifest_file)?;
+    let m = ManifestV2::parse(&manifest_str)?;
+    (download.notify_handler)(Notification::DownloadedManifest(
+        &m.date,
+        m.get_rust_version().ok(),
+    ));
+
+    let changes = requested_changes(&m, toolchain, profile, components, targets)?;
+    let prefix = InstallPrefix::from(download.temp_cfg.new_directory()?.to_path_buf());
+    let manifestation = Manifestation::open(prefix, toolchain.target.clone())?;
+    manifestation.prefetch(
+        &m,
+        changes,
+        &download,
+        download.notify_handler,
+        &toolchain.manifest_name(),
+    )?;
+
+    // Only record the manifest once every package it refers to is cached.
+    let manifest_path = prefetched_manifest_path(&download, toolchain);
+    utils::ensure_dir_exists(
+        "prefetched manifests",
+        manifest_path.parent().unwrap(),
+        download.notify_handler,
+    )?;
+    utils::write_file("prefetched manifest", &manifest_path, &manifest_str)?;
+
+    Ok(m.date)
+}
+
+fn prefetched_manifest_path(download: &DownloadCfg<'_>, toolchain: &ToolchainDesc) -> PathBuf {
+    download
+        .download_dir
+        .join("manifests")
+        .join(format!("channel-rust-{}.toml", toolchain.manifest_name()))
+}
+
+/// Empties the download cache in `download_dir`, but for the manifests
+/// `prefetch` saved and the packages they refer to, which are kept until
+/// they have been installed. A saved manifest which can't be read, such as
+/// one cut short, is deleted along with the rest.
+pub fn clean_download_dir(download_dir: &Path) -> Result<()> {
+    if !utils::is_directory(download_dir) {
+        return Ok(());
+    }
+    let manifests_dir = download_dir.join("manifests");
+    let mut prefetched = HashSet::new();
+    if utils::is_directory(&manifests_dir) {
+        for entry in utils::read_dir("prefetched manifests", &manifests_dir)? {
+            let manifest_path = entry?.path();
+            let m = match utils::read_file("prefetched manifest", &manifest_path)
+                .and_then(|manifest_str| ManifestV2::parse(&manifest_str))
+            {
+                Ok(m) => m,
+                Err(_) => {
+                    utils::ensure_file_removed("prefetched manifest", &manifest_path)?;
+                    continue;
+                }
+            };
+            for package in m.packages.values() {
+                let targets = match &package.targets {
+                    PackageTargets::Wildcard(tpkg) => vec![tpkg],
+                    PackageTargets::Targeted(tpkgs) => tpkgs.values().collect(),
+                };
+                for tpkg in targets {
+                    prefetched.extend(tpkg.bins.iter().map(|(_, bin)| bin.hash.clone()));
+                }
+            }
+        }
+    }
+
+    for entry in utils::read_dir("download cache", download_dir)? {
+        let path = entry?.path();
+        if path == manifests_dir {
+            continue;
+        }
+        let name = path.file_name().unwrap().to_string_lossy();
+        if prefetched.contains(&*name) {
+            continue;
+        }
+        if utils::is_directory(&path) {
+            raw::remove_dir(&path).with_context(|| RustupError::RemovingDirectory {
+                name: "download cache",
+                path: path.clone(),
+            })?;
+        } else {
+            utils::ensure_file_removed("cached download", &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Forgets the manifest `prefetch` saved for `toolchain` once one at least
+/// as new, dated `installed`, has been installed, which leaves it no use.
+fn prune_prefetched_manifest(
+    download: &DownloadCfg<'_>,
+    toolchain: &ToolchainDesc,
+    installed: &str,
+) -> Result<()> {
+    if let Some((manifest, _)) = read_prefetched_manifest(download, toolchain)? {
+        if *manifest.date <= *installed {
+            let manifest_path = prefetched_manifest_path(download, toolchain);
+            utils::ensure_file_removed("prefetched manifest", &manifest_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Loads a manifest saved by `prefetch`, along with its partial hash.
+fn read_prefetched_manifest(
+    download: &DownloadCfg<'_>,
+    toolchain: &ToolchainDesc,
+) -> Result<Option<(ManifestV2, String)>> {
+    let manifest_path = prefetched_manifest_path(download, toolchain);
+    if !utils::is_file(&manifest_path) {
+        return Ok(None);
+    }
+    let manifest_str = utils::read_file("prefetched manifest", &manifest_path)?;
+    let hash = format!("{:x}", Sha256::digest(manifest_str.as_bytes()));
+    let partial_hash = hash.chars().take(UPDATE_HASH_LEN).collect();
+    Ok(Some((ManifestV2::parse(&manifest_str)?, partial_hash)))
+}
+
 fn try_update_from_dist_<'a>(
     download: DownloadCfg<'a>,
     update_hash: Option<&Path>,
@@ -818,49 +1286,7 @@ fn try_update_from_dist_<'a>(
                 m.get_rust_version().ok(),
             ));
 
-            let profile_components = match profile {
-                Some(profile) => m.get_profile_components(profile, &toolchain.target)?,
-                None => Vec::new(),
-            };
-
-            let mut all_components: HashSet<Component> = profile_components.into_iter().collect();
-
-            let rust_package = m.get_package("rust")?;
-            let rust_target_package = rust_package.get_target(Some(&toolchain.target.clone()))?;
-
-            for component in components.iter().copied() {
-                let mut component =
-                    Component::new(component.to_string(), Some(toolchain.target.clone()), false);
-                if let Some(renamed) = m.rename_component(&component) {
-                    component = renamed;
-                }
-                // Look up the newly constructed/renamed component and ensure that
-                // if it's a wildcard component we note such, otherwise we end up
-                // exacerbating the problem we thought we'd fixed with #2087 and #2115
-                if let Some(c) = rust_target_package
-                    .components
-                    .iter()
-                    .find(|c| c.short_name_in_manifest() == component.short_name_in_manifest())
-                {
-                    if c.target.is_none() {
-                        component = component.wildcard();
-                    }
-                }
-                all_components.insert(component);
-            }
-
-            for target in targets {
-                let triple = TargetTriple::new(target);
-                all_components.insert(Component::new("rust-std".to_string(), Some(triple), false));
-            }
-
-            let mut explicit_add_components: Vec<_> = all_components.into_iter().collect();
-            explicit_add_components.sort();
-
-            let changes = Changes {
-                explicit_add_components,
-                remove_components: Vec::new(),
-            };
+            let changes = requested_changes(&m, toolchain, profile, components, targets)?;
 
             *fetched = m.date.clone();
 
@@ -873,10 +1299,13 @@ fn try_update_from_dist_<'a>(
                 &toolchain.manifest_name(),
                 true,
             ) {
-                Ok(status) => match status {
-                    UpdateStatus::Unchanged => Ok(None),
-                    UpdateStatus::Changed => Ok(Some(hash)),
-                },
+                Ok(status) => {
+                    prune_prefetched_manifest(&download, toolchain, &m.date)?;
+                    match status {
+                        UpdateStatus::Unchanged => Ok(None),
+                        UpdateStatus::Changed => Ok(Some(hash)),
+                    }
+                }
                 Err(err) => match err.downcast_ref::<RustupError>() {
                     Some(RustupError::RequestedComponentsUnavailable {
                         components,
@@ -886,6 +1315,7 @@ fn try_update_from_dist_<'a>(
                         components.to_owned(),
                         manifest.to_owned(),
                         toolchain.to_owned(),
+                        None,
                     ))),
                     Some(_) | None => Err(err),
                 },
@@ -975,11 +1405,56 @@ fn try_update_from_dist_<'a>(
     }
 }
 
+/// A channel manifest which was checked against the hash a toolchain file
+/// pins, installed from as it is rather than downloaded again, so that what
+/// was checked is what is installed.
+#[derive(Debug)]
+pub struct VerifiedManifest {
+    pub toolchain: ToolchainDesc,
+    pub manifest: ManifestV2,
+    pub hash: String,
+}
+
 pub fn dl_v2_manifest<'a>(
     download: DownloadCfg<'a>,
     update_hash: Option<&Path>,
     toolchain: &ToolchainDesc,
 ) -> Result<Option<(ManifestV2, String)>> {
+    // Nothing but the manifest checked against the pin is installed, not
+    // even an earlier nightly when it lacks components.
+    if let Some(verified) = download.verified_manifest {
+        if verified.toolchain.to_string() != toolchain.to_string() {
+            bail!(
+                "only the pinned channel manifest of '{}' can be installed, not that of '{}'",
+                verified.toolchain,
+                toolchain
+            );
+        }
+        if let Some(hash_file) = update_hash {
+            if utils::read_file("update hash", hash_file).ok() == Some(verified.hash.clone()) {
+                return Ok(None);
+            }
+        }
+        return Ok(Some((verified.manifest.clone(), verified.hash.clone())));
+    }
+
+    // Working offline, install from what `rustup prefetch` saved for the
+    // toolchain, if anything, without asking the dist server for newer.
+    if download.network.offline == Some(true) {
+        if let Some((manifest, hash)) = read_prefetched_manifest(&download, toolchain)? {
+            (download.notify_handler)(Notification::UsingPrefetchedManifest(
+                &toolchain.manifest_name(),
+                &manifest.date,
+            ));
+            if let Some(hash_file) = update_hash {
+                if utils::read_file("update hash", hash_file).ok() == Some(hash.clone()) {
+                    return Ok(None);
+                }
+            }
+            return Ok(Some((manifest, hash)));
+        }
+    }
+
     let manifest_url = toolchain.manifest_v2_url(download.dist_root);
     match download.download_and_check(&manifest_url, update_hash, ".toml") {
         Ok(manifest_dl) => {
@@ -1113,6 +1588,49 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_channel_bounds() {
+        let success_cases = vec![
+            ("stable<=1.75", "1.75"),
+            ("stable<=1.75.0", "1.75.0"),
+            ("stable<1.75", "1.74"),
+            ("stable<1.75.0", "1.74"),
+            ("stable<1.75.2", "1.75.1"),
+            ("nightly<=2024-06-01", "nightly-2024-06-01"),
+            ("nightly<2024-06-01", "nightly-2024-05-31"),
+            (
+                "beta<2024-03-01-x86_64-unknown-linux-gnu",
+                "beta-2024-02-29-x86_64-unknown-linux-gnu",
+            ),
+            (
+                "stable<1.75-x86_64-pc-windows-msvc",
+                "1.74-x86_64-pc-windows-msvc",
+            ),
+        ];
+        for (input, expected) in success_cases {
+            assert_eq!(
+                resolve_channel_bound(input).unwrap().as_deref(),
+                Some(expected),
+                "input: `{}`",
+                input
+            );
+        }
+
+        assert!(resolve_channel_bound("stable").unwrap().is_none());
+        for input in &[
+            "beta<=1.75",
+            "stable<1.0",
+            "nightly<2024-02-30",
+            "1.75<=1.76",
+        ] {
+            assert!(
+                resolve_channel_bound(input).is_err(),
+                "expected `{}` to fail",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_tracking_channels() {
         static CASES: &[(&str, bool)] = &[
@@ -1,18 +1,37 @@
+use std::collections::HashMap;
 use std::fs;
 use std::ops;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use lazy_static::lazy_static;
 use sha2::{Digest, Sha256};
-use url::Url;
+use url::{Origin, Url};
 
 use crate::config::PgpPublicKey;
+use crate::dist::dist::VerifiedManifest;
+use crate::dist::download_log::{DownloadLog, Entry, Outcome};
+use crate::dist::metalink::Metalink;
 use crate::dist::notifications::*;
 use crate::dist::temp;
 use crate::errors::*;
-use crate::utils::utils;
-
-const UPDATE_HASH_LEN: usize = 20;
+use crate::process;
+use crate::settings::NetworkSettings;
+use crate::utils::lock::FileLock;
+use crate::utils::utils::{self, StreamHasher};
+
+pub(crate) const UPDATE_HASH_LEN: usize = 20;
+
+lazy_static! {
+    /// How long each Metalink mirror took to respond, by its origin, so that
+    /// a mirror which answered is probed once however many files are
+    /// downloaded from it.
+    static ref MIRROR_RESPONSE_TIMES: Mutex<HashMap<Origin, Duration>> =
+        Mutex::new(HashMap::new());
+}
 
 #[derive(Copy, Clone)]
 pub struct DownloadCfg<'a> {
@@ -21,10 +40,78 @@ pub struct DownloadCfg<'a> {
     pub download_dir: &'a PathBuf,
     pub notify_handler: &'a dyn Fn(Notification<'_>),
     pub pgp_keys: &'a [PgpPublicKey],
+    pub download_log: Option<&'a DownloadLog>,
+    pub network: &'a NetworkSettings,
+    /// The channel manifest to install from, already checked against the
+    /// hash a toolchain file pins, in place of downloading it again.
+    pub verified_manifest: Option<&'a VerifiedManifest>,
 }
 
 pub struct File {
     path: PathBuf,
+    cached_hash: Option<String>,
+    blake3: bool,
+    source: Url,
+}
+
+impl File {
+    /// The expected hash of a file which was found in the download cache.
+    ///
+    /// Cached files are not hashed when they are handed out; instead the
+    /// caller is expected to verify this hash while reading the file, so
+    /// that reusing a download costs a single pass over it. The hash is
+    /// computed with the hasher returned by `File::hasher`.
+    pub fn cached_hash(&self) -> Option<&str> {
+        self.cached_hash.as_deref()
+    }
+
+    /// A hasher of the kind that `cached_hash` was computed with.
+    pub(crate) fn hasher(&self) -> ContentHasher {
+        ContentHasher::new(self.blake3)
+    }
+
+    /// Where the file was downloaded from, which may be a mirror.
+    pub(crate) fn source(&self) -> &Url {
+        &self.source
+    }
+}
+
+/// The hash a downloaded package is verified with: BLAKE3 when the manifest
+/// provides one, since it is several times faster to compute on large
+/// files, and SHA-256 otherwise.
+#[derive(Clone)]
+pub(crate) enum ContentHasher {
+    Sha256(Sha256),
+    Blake3(blake3::Hasher),
+}
+
+impl ContentHasher {
+    pub(crate) fn new(use_blake3: bool) -> Self {
+        if use_blake3 {
+            Self::Blake3(blake3::Hasher::new())
+        } else {
+            Self::Sha256(Sha256::new())
+        }
+    }
+
+    /// The hex-encoded digest of everything hashed so far.
+    pub(crate) fn finalize(self) -> String {
+        match self {
+            Self::Sha256(h) => format!("{:x}", h.finalize()),
+            Self::Blake3(h) => h.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+impl StreamHasher for ContentHasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(h) => Digest::update(h, data),
+            Self::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
 }
 
 impl ops::Deref for File {
@@ -36,11 +123,26 @@ impl ops::Deref for File {
 }
 
 impl<'a> DownloadCfg<'a> {
-    /// Downloads a file and validates its hash. Resumes interrupted downloads.
-    /// Partial downloads are stored in `self.download_dir`, keyed by hash. If the
-    /// target file already exists, then the hash is checked and it is returned
-    /// immediately without re-downloading.
-    pub fn download(&self, url: &Url, hash: &str) -> Result<File> {
+    /// The bytes `download` would fetch for the file: none if it is in the
+    /// download cache already. `None` if the server doesn't say.
+    pub fn download_size(&self, url: &Url, hash: &str) -> Result<Option<u64>> {
+        if self.download_dir.join(hash).exists() {
+            return Ok(Some(0));
+        }
+        let scratch = self.temp_cfg.new_file()?;
+        Ok(utils::remote_size(url, &scratch))
+    }
+
+    /// Downloads a file and validates its hash while it is streamed to disk.
+    /// Resumes interrupted downloads. Partial downloads are stored in
+    /// `self.download_dir`, keyed by hash. If the target file already exists,
+    /// then it is returned immediately without re-downloading, and its hash
+    /// is left for the caller to check; see `File::cached_hash`.
+    ///
+    /// `hash` is the file's SHA-256, which names it in the download
+    /// directory. If `blake3` is given, the file is verified against that
+    /// instead.
+    pub fn download(&self, url: &Url, hash: &str, blake3: Option<&str>) -> Result<File> {
         utils::ensure_dir_exists(
             "Download Directory",
             &self.download_dir,
@@ -48,16 +150,24 @@ impl<'a> DownloadCfg<'a> {
         )?;
         let target_file = self.download_dir.join(Path::new(hash));
 
+        // Another process may be downloading the same file. Once it is
+        // done, the file is taken from the cache below.
+        let _lock = FileLock::exclusive(
+            "download",
+            &self.download_dir.join(format!("{}.lock", hash)),
+            &|n| (self.notify_handler)(n.into()),
+        )?;
+
+        let expected_hash = blake3.unwrap_or(hash);
+
         if target_file.exists() {
-            let cached_result = file_hash(&target_file, self.notify_handler)?;
-            if hash == cached_result {
-                (self.notify_handler)(Notification::FileAlreadyDownloaded);
-                (self.notify_handler)(Notification::ChecksumValid(&url.to_string()));
-                return Ok(File { path: target_file });
-            } else {
-                (self.notify_handler)(Notification::CachedFileChecksumFailed);
-                fs::remove_file(&target_file).context("cleaning up previous download")?;
-            }
+            (self.notify_handler)(Notification::FileAlreadyDownloaded);
+            return Ok(File {
+                path: target_file,
+                cached_hash: Some(expected_hash.to_owned()),
+                blake3: blake3.is_some(),
+                source: url.clone(),
+            });
         }
 
         let partial_file_path = target_file.with_file_name(
@@ -71,26 +181,47 @@ impl<'a> DownloadCfg<'a> {
 
         let partial_file_existed = partial_file_path.exists();
 
-        let mut hasher = Sha256::new();
+        let mut hasher = ContentHasher::new(blake3.is_some());
 
-        if let Err(e) = utils::download_file_with_resume(
-            &url,
-            &partial_file_path,
-            Some(&mut hasher),
-            true,
-            &|n| (self.notify_handler)(n.into()),
-        ) {
-            let err = Err(e);
-            if partial_file_existed {
-                return err.context(RustupError::BrokenPartialFile);
-            } else {
-                return err;
+        let sources = self.sources(url, hash)?;
+        let mut used_source = url;
+        for (i, source) in sources.iter().enumerate() {
+            used_source = source;
+            if source != url {
+                (self.notify_handler)(Notification::UsingMirror(source.as_str()));
             }
-        };
+            match utils::download_file_with_resume(
+                source,
+                &partial_file_path,
+                Some(&mut hasher),
+                true,
+                &|n| (self.notify_handler)(n.into()),
+            ) {
+                Ok(()) => break,
+                Err(e) => {
+                    if let Some(next) = sources.get(i + 1) {
+                        // The partial file is discarded when a transfer
+                        // fails, so the next mirror starts from scratch.
+                        (self.notify_handler)(Notification::MirrorFailed(
+                            source.as_str(),
+                            next.as_str(),
+                        ));
+                        hasher = ContentHasher::new(blake3.is_some());
+                        continue;
+                    }
+                    let err = Err(e);
+                    if partial_file_existed {
+                        return err.context(RustupError::BrokenPartialFile);
+                    } else {
+                        return err;
+                    }
+                }
+            }
+        }
 
-        let actual_hash = format!("{:x}", hasher.finalize());
+        let actual_hash = hasher.finalize();
 
-        if hash != actual_hash {
+        if expected_hash != actual_hash {
             // Incorrect hash
             if partial_file_existed {
                 self.clean(&[hash.to_string() + &".partial".to_string()])?;
@@ -98,7 +229,7 @@ impl<'a> DownloadCfg<'a> {
             } else {
                 Err(RustupError::ChecksumFailed {
                     url: url.to_string(),
-                    expected: hash.to_string(),
+                    expected: expected_hash.to_string(),
                     calculated: actual_hash,
                 }
                 .into())
@@ -112,8 +243,121 @@ impl<'a> DownloadCfg<'a> {
                 &target_file,
                 self.notify_handler,
             )?;
-            Ok(File { path: target_file })
+            utils::share_with_group(&target_file, false);
+            Ok(File {
+                path: target_file,
+                cached_hash: None,
+                blake3: blake3.is_some(),
+                source: used_source.clone(),
+            })
+        }
+    }
+
+    /// The URLs to try, in order, when downloading `url`: those below,
+    /// followed by `url` on each of the mirrors of the `[network]`
+    /// settings, if it is on the dist server.
+    fn sources(&self, url: &Url, hash: &str) -> Result<Vec<Url>> {
+        let mut sources = self.metalink_sources(url, hash)?;
+        for mirror in self.on_mirrors(url.as_str()) {
+            let mirror = utils::parse_url(&mirror)?;
+            if !sources.contains(&mirror) {
+                sources.push(mirror);
+            }
+        }
+        Ok(sources)
+    }
+
+    /// `url` on each of the mirrors of the `[network]` settings, if it is
+    /// on the dist server.
+    fn on_mirrors(&self, url: &str) -> Vec<String> {
+        let server = self.temp_cfg.dist_server.trim_end_matches('/');
+        match url.strip_prefix(server) {
+            Some(path) if path.starts_with('/') => self
+                .network
+                .mirrors
+                .iter()
+                .map(|mirror| format!("{}{}", mirror, path))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// With `RUSTUP_USE_METALINK` set, a Metalink document is looked for
+    /// alongside `url` (with a `.meta4` suffix). Its mirrors are tried
+    /// fastest first, going by priority and then by how quickly the most
+    /// preferred few answer a HEAD request, made once per mirror for the
+    /// whole of rustup's run unless it fails; `url` itself is kept as a last
+    /// resort. The channel manifest remains the authority on what the file
+    /// should hash to, so a Metalink disagreeing with it is ignored.
+    fn metalink_sources(&self, url: &Url, hash: &str) -> Result<Vec<Url>> {
+        const MAX_PROBED_MIRRORS: usize = 4;
+
+        if process().var_os("RUSTUP_USE_METALINK").is_none() {
+            return Ok(vec![url.clone()]);
+        }
+
+        let metalink_url = utils::parse_url(&(url.to_string() + ".meta4"))?;
+        let metalink_file = self.temp_cfg.new_file()?;
+        let metalink = utils::download_file(&metalink_url, &metalink_file, None, &|n| {
+            (self.notify_handler)(n.into())
+        })
+        .and_then(|_| utils::read_file("metalink", &metalink_file))
+        .and_then(|data| Metalink::parse(&data));
+        let metalink = match metalink {
+            Ok(metalink) => metalink,
+            Err(_) => {
+                (self.notify_handler)(Notification::NoMetalink(url.as_str()));
+                return Ok(vec![url.clone()]);
+            }
+        };
+        if metalink.sha256.as_deref() != Some(hash) {
+            (self.notify_handler)(Notification::MetalinkHashMismatch(url.as_str()));
+            return Ok(vec![url.clone()]);
+        }
+
+        let mut mirrors = metalink.mirrors;
+        let probed = mirrors.len().min(MAX_PROBED_MIRRORS);
+        let mut timed: Vec<_> = mirrors
+            .drain(..probed)
+            .map(|m| (response_time(&m), m))
+            .collect();
+        // Unreachable mirrors sort last, after every responsive one.
+        timed.sort_by_key(|(t, _)| (t.is_none(), *t));
+
+        let mut sources: Vec<_> = timed.into_iter().map(|(_, m)| m).collect();
+        sources.extend(mirrors);
+        if !sources.contains(url) {
+            sources.push(url.clone());
         }
+        Ok(sources)
+    }
+
+    /// Adds a download to the download log, if one is kept. The log is
+    /// purely diagnostic, so failing to write it is not an error.
+    pub(crate) fn log_download(
+        &self,
+        url: &str,
+        source: &str,
+        outcome: Outcome,
+        retries: usize,
+        started: Instant,
+    ) {
+        if let Some(log) = self.download_log {
+            let _ = log.record(&Entry {
+                time: Utc::now(),
+                url: url.to_owned(),
+                source: source.to_owned(),
+                outcome,
+                retries,
+                duration: started.elapsed(),
+            });
+        }
+    }
+
+    /// Discards a cached file whose contents did not match its expected hash.
+    pub fn discard_cached(&self, file: File) -> Result<()> {
+        (self.notify_handler)(Notification::CachedFileChecksumFailed);
+        fs::remove_file(&file.path).context("cleaning up previous download")
     }
 
     pub fn clean(&self, hashes: &[String]) -> Result<()> {
@@ -187,6 +431,27 @@ impl<'a> DownloadCfg<'a> {
         url_str: &str,
         update_hash: Option<&Path>,
         ext: &str,
+    ) -> Result<Option<(temp::File<'a>, String)>> {
+        let first_err = match self.download_and_check_from(url_str, update_hash, ext) {
+            Ok(file) => return Ok(file),
+            Err(e) => e,
+        };
+        let mut from = url_str.to_owned();
+        for mirror in self.on_mirrors(url_str) {
+            (self.notify_handler)(Notification::MirrorFailed(&from, &mirror));
+            if let Ok(file) = self.download_and_check_from(&mirror, update_hash, ext) {
+                return Ok(file);
+            }
+            from = mirror;
+        }
+        Err(first_err)
+    }
+
+    fn download_and_check_from(
+        &self,
+        url_str: &str,
+        update_hash: Option<&Path>,
+        ext: &str,
     ) -> Result<Option<(temp::File<'a>, String)>> {
         let hash = self.download_hash(url_str)?;
         let partial_hash: String = hash.chars().take(UPDATE_HASH_LEN).collect();
@@ -209,14 +474,19 @@ impl<'a> DownloadCfg<'a> {
         let url = utils::parse_url(url_str)?;
         let file = self.temp_cfg.new_file_with_ext("", ext)?;
 
+        let started = Instant::now();
         let mut hasher = Sha256::new();
-        utils::download_file(&url, &file, Some(&mut hasher), &|n| {
+        if let Err(e) = utils::download_file(&url, &file, Some(&mut hasher), &|n| {
             (self.notify_handler)(n.into())
-        })?;
+        }) {
+            self.log_download(url_str, url_str, Outcome::from_error(&e), 0, started);
+            return Err(e);
+        }
         let actual_hash = format!("{:x}", hasher.finalize());
 
         if hash != actual_hash {
             // Incorrect hash
+            self.log_download(url_str, url_str, Outcome::ChecksumFailed, 0, started);
             return Err(RustupError::ChecksumFailed {
                 url: url_str.to_owned(),
                 expected: hash,
@@ -224,6 +494,7 @@ impl<'a> DownloadCfg<'a> {
             }
             .into());
         } else {
+            self.log_download(url_str, url_str, Outcome::Success, 0, started);
             (self.notify_handler)(Notification::ChecksumValid(url_str));
         }
 
@@ -239,20 +510,15 @@ impl<'a> DownloadCfg<'a> {
     }
 }
 
-fn file_hash(path: &Path, notify_handler: &dyn Fn(Notification<'_>)) -> Result<String> {
-    let mut hasher = Sha256::new();
-    let notification_converter = |notification: crate::utils::Notification<'_>| {
-        notify_handler(notification.into());
-    };
-    let mut downloaded = utils::FileReaderWithProgress::new_file(&path, &notification_converter)?;
-    use std::io::Read;
-    let mut buf = vec![0; 32768];
-    while let Ok(n) = downloaded.read(&mut buf) {
-        if n == 0 {
-            break;
-        }
-        hasher.update(&buf[..n]);
+/// How long the mirror `url` is on took to respond, probing it unless it
+/// already answered. A failed probe says nothing of the mirror's other
+/// files, so it is probed again for the next one.
+fn response_time(url: &Url) -> Option<Duration> {
+    let origin = url.origin();
+    if let Some(time) = MIRROR_RESPONSE_TIMES.lock().unwrap().get(&origin) {
+        return Some(*time);
     }
-
-    Ok(format!("{:x}", hasher.finalize()))
+    let time = utils::time_to_respond(url)?;
+    MIRROR_RESPONSE_TIMES.lock().unwrap().insert(origin, time);
+    Some(time)
 }
@@ -0,0 +1,199 @@
+//! A record of the downloads rustup has made, so that intermittent network
+//! trouble can be diagnosed after the fact with `rustup report network`.
+//!
+//! Each download is a tab-separated line in `RUSTUP_HOME/logs/downloads.log`.
+//! When that file grows past `MAX_LOG_LEN` it is rotated to `downloads.log.1`,
+//! the previous `.1` to `.2`, and so on, keeping `MAX_ROTATED_LOGS` old logs.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::errors::RustupError;
+use crate::utils::utils;
+
+const MAX_LOG_LEN: u64 = 1024 * 1024;
+const MAX_ROTATED_LOGS: usize = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Success,
+    /// The file was downloaded but did not match the manifest's hash.
+    ChecksumFailed,
+    Failed(String),
+}
+
+impl Outcome {
+    pub(crate) fn from_error(e: &anyhow::Error) -> Self {
+        match e.downcast_ref::<RustupError>() {
+            Some(RustupError::ChecksumFailed { .. }) => Self::ChecksumFailed,
+            _ => Self::Failed(format!("{:#}", e)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    pub url: String,
+    /// Where the file was fetched from; differs from `url` when a mirror
+    /// served it.
+    pub source: String,
+    pub outcome: Outcome,
+    pub retries: usize,
+    pub duration: Duration,
+}
+
+impl Entry {
+    fn to_line(&self) -> String {
+        let (outcome, message) = match &self.outcome {
+            Outcome::Success => ("ok", ""),
+            Outcome::ChecksumFailed => ("checksum", ""),
+            Outcome::Failed(message) => ("failed", message.as_str()),
+        };
+        // Tabs and newlines would break up the record.
+        let message: String = message
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.time.to_rfc3339(),
+            outcome,
+            self.retries,
+            self.duration.as_millis(),
+            self.url,
+            self.source,
+            message
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(7, '\t');
+        let time = DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+        let outcome = fields.next()?;
+        let retries = fields.next()?.parse().ok()?;
+        let duration = Duration::from_millis(fields.next()?.parse().ok()?);
+        let url = fields.next()?.to_owned();
+        let source = fields.next()?.to_owned();
+        let outcome = match outcome {
+            "ok" => Outcome::Success,
+            "checksum" => Outcome::ChecksumFailed,
+            "failed" => Outcome::Failed(fields.next().unwrap_or_default().to_owned()),
+            _ => return None,
+        };
+        Some(Self {
+            time: time.with_timezone(&Utc),
+            url,
+            source,
+            outcome,
+            retries,
+            duration,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DownloadLog {
+    path: PathBuf,
+}
+
+impl DownloadLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `entry` to the log, rotating it first if it has grown too
+    /// large.
+    pub fn record(&self, entry: &Entry) -> Result<()> {
+        utils::append_rotating_log(
+            "download log",
+            &self.path,
+            &entry.to_line(),
+            MAX_LOG_LEN,
+            MAX_ROTATED_LOGS,
+        )
+    }
+
+    /// Every entry still in the log, oldest first. Lines which can't be
+    /// understood are skipped.
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        let paths = (1..=MAX_ROTATED_LOGS)
+            .rev()
+            .map(|n| self.rotated(n))
+            .chain(Some(self.path.clone()));
+        for path in paths {
+            if !utils::is_file(&path) {
+                continue;
+            }
+            let contents = utils::read_file("download log", &path)?;
+            entries.extend(contents.lines().filter_map(Entry::from_line));
+        }
+        Ok(entries)
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        utils::rotated_log(&self.path, n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(outcome: Outcome) -> Entry {
+        Entry {
+            time: DateTime::parse_from_rfc3339("2021-06-01T12:00:00+00:00")
+                .unwrap()
+                .with_timezone(&Utc),
+            url: "https://static.rust-lang.org/dist/rustc.tar.xz".to_owned(),
+            source: "https://mirror.example.com/dist/rustc.tar.xz".to_owned(),
+            outcome,
+            retries: 2,
+            duration: Duration::from_millis(1500),
+        }
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        for outcome in &[
+            Outcome::Success,
+            Outcome::ChecksumFailed,
+            Outcome::Failed("timed out".to_owned()),
+        ] {
+            let entry = entry(outcome.clone());
+            assert_eq!(Entry::from_line(&entry.to_line()), Some(entry));
+        }
+
+        let entry = entry(Outcome::Failed(
+            "could not connect\n\tcaused by: reset".to_owned(),
+        ));
+        assert_eq!(
+            Entry::from_line(&entry.to_line()).unwrap().outcome,
+            Outcome::Failed("could not connect  caused by: reset".to_owned())
+        );
+    }
+
+    #[test]
+    fn rotates_when_full() {
+        let dir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+        let log = DownloadLog::new(dir.path().join("logs").join("downloads.log"));
+        let filler = "#".repeat(MAX_LOG_LEN as usize);
+        for _ in 0..=MAX_ROTATED_LOGS {
+            log.record(&entry(Outcome::Success)).unwrap();
+            utils::append_file("filler", log.path(), &filler).unwrap();
+        }
+        log.record(&entry(Outcome::Success)).unwrap();
+
+        assert!(log.rotated(MAX_ROTATED_LOGS).exists());
+        assert!(!log.rotated(MAX_ROTATED_LOGS + 1).exists());
+        assert_eq!(log.entries().unwrap().len(), MAX_ROTATED_LOGS + 1);
+    }
+}
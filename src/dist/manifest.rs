@@ -81,7 +81,11 @@ impl CompressionKind {
 #[derive(Clone, Debug, PartialEq)]
 pub struct HashedBinary {
     pub url: String,
+    /// The SHA-256 of the file, which every manifest provides.
     pub hash: String,
+    /// The BLAKE3 of the file, if the manifest provides it. This is much
+    /// quicker to compute, so it is checked in preference to `hash`.
+    pub blake3: Option<String>,
 }
 
 #[derive(Clone, Debug, Eq, Ord, PartialOrd)]
@@ -449,10 +453,12 @@ impl TargetedPackage {
             for kind in COMPRESSION_KIND_PREFERENCE_ORDER.iter().copied() {
                 let url_key = format!("{}url", kind.key_prefix());
                 let hash_key = format!("{}hash", kind.key_prefix());
+                let blake3_key = format!("{}blake3", kind.key_prefix());
                 let url = get_string(&mut table, &url_key, path).ok();
                 let hash = get_string(&mut table, &hash_key, path).ok();
+                let blake3 = get_opt_string(&mut table, &blake3_key, path)?;
                 if let (Some(url), Some(hash)) = (url, hash) {
-                    bins.push((kind, HashedBinary { url, hash }));
+                    bins.push((kind, HashedBinary { url, hash, blake3 }));
                 }
             }
             Ok(Self { bins, components })
@@ -480,6 +486,10 @@ impl TargetedPackage {
                 let hash_key = format!("{}hash", kind.key_prefix());
                 result.insert(url_key, toml::Value::String(bin.url));
                 result.insert(hash_key, toml::Value::String(bin.hash));
+                if let Some(blake3) = bin.blake3 {
+                    let blake3_key = format!("{}blake3", kind.key_prefix());
+                    result.insert(blake3_key, toml::Value::String(blake3));
+                }
             }
             result.insert("available".to_owned(), toml::Value::Boolean(true));
         }
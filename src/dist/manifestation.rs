@@ -1,7 +1,9 @@
 //! Maintains a Rust installation by installing individual Rust
 //! platform components from a distribution server.
 
+use std::io::{self, Read};
 use std::path::Path;
+use std::time::Instant;
 
 use anyhow::{anyhow, bail, Context, Result};
 use retry::delay::NoDelay;
@@ -13,13 +15,15 @@ use crate::dist::component::{
 };
 use crate::dist::config::Config;
 use crate::dist::dist::{Profile, TargetTriple, DEFAULT_DIST_SERVER};
-use crate::dist::download::{DownloadCfg, File};
+use crate::dist::download::{ContentHasher, DownloadCfg, File};
+use crate::dist::download_log::Outcome;
 use crate::dist::manifest::{Component, CompressionKind, Manifest, TargetedPackage};
 use crate::dist::notifications::*;
 use crate::dist::prefix::InstallPrefix;
 use crate::dist::temp;
 use crate::errors::{OperationError, RustupError};
 use crate::process;
+use crate::settings::NetworkSettings;
 use crate::utils::utils;
 
 pub const DIST_MANIFEST: &str = "multirust-channel-manifest.toml";
@@ -68,6 +72,25 @@ impl Changes {
     }
 }
 
+/// What `Manifestation::update` would change, worked out without
+/// downloading any packages.
+#[derive(Debug)]
+pub struct UpdatePlan {
+    pub components: Vec<ComponentChange>,
+    /// The bytes to download, less those in the download cache already, or
+    /// `None` if the size of some package couldn't be found out.
+    pub download_size: Option<u64>,
+}
+
+/// A component installed, updated or removed. `from` is `None` for one
+/// added, and `to` for one removed; the versions are those of the package.
+#[derive(Debug)]
+pub struct ComponentChange {
+    pub name: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
 #[derive(PartialEq, Debug)]
 pub enum UpdateStatus {
     Changed,
@@ -153,18 +176,16 @@ impl Manifestation {
         let altered = temp_cfg.dist_server != DEFAULT_DIST_SERVER;
 
         // Download component packages and validate hashes
-        let mut things_to_install: Vec<(Component, CompressionKind, File)> = Vec::new();
+        let mut things_to_install = Vec::new();
         let mut things_downloaded: Vec<String> = Vec::new();
         let components = update.components_urls_and_hashes(new_manifest)?;
 
-        const DEFAULT_MAX_RETRIES: usize = 3;
-        let max_retries: usize = process()
-            .var("RUSTUP_MAX_RETRIES")
-            .ok()
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(DEFAULT_MAX_RETRIES);
+        let download_with_retry =
+            |component: &Component, url: &str, hash: &str, blake3: Option<&str>| {
+                download_with_retry(download_cfg, new_manifest, component, url, hash, blake3)
+            };
 
-        for (component, format, url, hash) in components {
+        for (component, format, url, hash, blake3) in components {
             notify_handler(Notification::DownloadingComponent(
                 &component.short_name(new_manifest),
                 &self.target_triple,
@@ -176,33 +197,11 @@ impl Manifestation {
                 url
             };
 
-            let url_url = utils::parse_url(&url)?;
-
-            let downloaded_file = retry(NoDelay.take(max_retries), || {
-                match download_cfg.download(&url_url, &hash) {
-                    Ok(f) => OperationResult::Ok(f),
-                    Err(e) => {
-                        match e.downcast_ref::<RustupError>() {
-                            Some(RustupError::BrokenPartialFile) => {
-                                notify_handler(Notification::RetryingDownload(&url));
-                                return OperationResult::Retry(OperationError(e));
-                            }
-                            Some(RustupError::DownloadingFile { .. }) => {
-                                notify_handler(Notification::RetryingDownload(&url));
-                                return OperationResult::Retry(OperationError(e));
-                            }
-                            Some(_) => return OperationResult::Err(OperationError(e)),
-                            None => (),
-                        };
-                        OperationResult::Err(OperationError(e))
-                    }
-                }
-            })
-            .with_context(|| RustupError::ComponentDownloadFailed(component.name(new_manifest)))?;
+            let downloaded_file = download_with_retry(&component, &url, &hash, blake3.as_deref())?;
 
-            things_downloaded.push(hash);
+            things_downloaded.push(hash.clone());
 
-            things_to_install.push((component, format, downloaded_file));
+            things_to_install.push((component, format, downloaded_file, url, hash, blake3));
         }
 
         // Begin transaction
@@ -229,7 +228,7 @@ impl Manifestation {
         }
 
         // Install components
-        for (component, format, installer_file) in things_to_install {
+        for (component, format, installer_file, url, hash, blake3) in things_to_install {
             // For historical reasons, the rust-installer component
             // names are not the same as the dist manifest component
             // names. Some are just the component name some are the
@@ -247,24 +246,44 @@ impl Manifestation {
             let notification_converter = |notification: crate::utils::Notification<'_>| {
                 notify_handler(notification.into());
             };
-            let gz;
-            let xz;
-            let zst;
-            let reader =
-                utils::FileReaderWithProgress::new_file(&installer_file, &notification_converter)?;
-            let package: &dyn Package = match format {
-                CompressionKind::GZip => {
-                    gz = TarGzPackage::new(reader, temp_cfg, Some(&notification_converter))?;
-                    &gz
-                }
-                CompressionKind::XZ => {
-                    xz = TarXzPackage::new(reader, temp_cfg, Some(&notification_converter))?;
-                    &xz
-                }
-                CompressionKind::ZStd => {
-                    zst = TarZStdPackage::new(reader, temp_cfg, Some(&notification_converter))?;
-                    &zst
+
+            let package = if let Some(expected) = installer_file.cached_hash().map(str::to_owned) {
+                // Files reused from the download cache are verified while
+                // they are unpacked rather than in a separate pass. If the
+                // cached copy turns out to be bad it is fetched afresh, and
+                // that download is verified as it streams to disk.
+                let mut hasher = installer_file.hasher();
+                let package = open_package(
+                    &installer_file,
+                    format,
+                    temp_cfg,
+                    &notification_converter,
+                    Some(&mut hasher),
+                );
+                if hasher.finalize() == expected {
+                    notify_handler(Notification::ChecksumValid(&url));
+                    package?
+                } else {
+                    drop(package);
+                    download_cfg.discard_cached(installer_file)?;
+                    let installer_file =
+                        download_with_retry(&component, &url, &hash, blake3.as_deref())?;
+                    open_package(
+                        &installer_file,
+                        format,
+                        temp_cfg,
+                        &notification_converter,
+                        None,
+                    )?
                 }
+            } else {
+                open_package(
+                    &installer_file,
+                    format,
+                    temp_cfg,
+                    &notification_converter,
+                    None,
+                )?
             };
 
             // If the package doesn't contain the component that the
@@ -303,6 +322,153 @@ impl Manifestation {
         Ok(UpdateStatus::Changed)
     }
 
+    /// What `update` would change, with the versions of the components
+    /// before and after and the size of the packages to download, or `None`
+    /// if it would change nothing.
+    pub fn plan(
+        &self,
+        new_manifest: &Manifest,
+        changes: Changes,
+        download_cfg: &DownloadCfg<'_>,
+        toolchain_str: &str,
+    ) -> Result<Option<UpdatePlan>> {
+        let config = self.read_config()?;
+        let update = Update::build_update(self, new_manifest, &changes, &config, &|_| {})?;
+        if update.nothing_changes() {
+            return Ok(None);
+        }
+        update.unavailable_components(new_manifest, toolchain_str)?;
+
+        let old_manifest = self.load_manifest()?;
+        let version = |manifest: &Manifest, component: &Component| {
+            manifest
+                .get_package(&component.short_name_in_manifest())
+                .ok()
+                .map(|package| package.version.clone())
+        };
+        let name = |manifest: &Manifest, component: &Component| {
+            if component.target.as_ref() == Some(&self.target_triple) {
+                component.short_name(manifest)
+            } else {
+                component.name(manifest)
+            }
+        };
+        let mut components = Vec::new();
+        for component in &update.components_to_install {
+            let from = match &old_manifest {
+                Some(old_manifest) if update.components_to_uninstall.contains(component) => {
+                    version(old_manifest, component)
+                }
+                _ => None,
+            };
+            components.push(ComponentChange {
+                name: name(new_manifest, component),
+                from,
+                to: version(new_manifest, component),
+            });
+        }
+        for component in &update.components_to_uninstall {
+            if update.components_to_install.contains(component) {
+                continue;
+            }
+            let manifest = old_manifest.as_ref().unwrap_or(new_manifest);
+            components.push(ComponentChange {
+                name: name(manifest, component),
+                from: version(manifest, component),
+                to: None,
+            });
+        }
+        components.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let altered = download_cfg.temp_cfg.dist_server != DEFAULT_DIST_SERVER;
+        let mut download_size = Some(0);
+        for (_, _, url, hash, _) in update.components_urls_and_hashes(new_manifest)? {
+            let url = if altered {
+                url.replace(
+                    DEFAULT_DIST_SERVER,
+                    download_cfg.temp_cfg.dist_server.as_str(),
+                )
+            } else {
+                url
+            };
+            let size = download_cfg.download_size(&utils::parse_url(&url)?, &hash)?;
+            download_size = download_size.and_then(|total| size.map(|size| total + size));
+        }
+
+        Ok(Some(UpdatePlan {
+            components,
+            download_size,
+        }))
+    }
+
+    /// Download and verify the packages `update` would install for a
+    /// fresh toolchain, leaving them in the download cache.
+    ///
+    /// Nothing under the install prefix is modified. Packages which are
+    /// already cached are hashed in full, and replaced if they turn out to be
+    /// corrupt, so that a later offline install can rely on them.
+    pub fn prefetch(
+        &self,
+        new_manifest: &Manifest,
+        changes: Changes,
+        download_cfg: &DownloadCfg<'_>,
+        notify_handler: &dyn Fn(Notification<'_>),
+        toolchain_str: &str,
+    ) -> Result<()> {
+        let temp_cfg = download_cfg.temp_cfg;
+        let update = Update::build_update(self, new_manifest, &changes, &None, notify_handler)?;
+        update.unavailable_components(new_manifest, toolchain_str)?;
+
+        let altered = temp_cfg.dist_server != DEFAULT_DIST_SERVER;
+        for (component, _, url, hash, blake3) in update.components_urls_and_hashes(new_manifest)? {
+            notify_handler(Notification::DownloadingComponent(
+                &component.short_name(new_manifest),
+                &self.target_triple,
+                component.target.as_ref(),
+            ));
+            let url = if altered {
+                url.replace(DEFAULT_DIST_SERVER, temp_cfg.dist_server.as_str())
+            } else {
+                url
+            };
+
+            let blake3 = blake3.as_deref();
+            let file =
+                download_with_retry(download_cfg, new_manifest, &component, &url, &hash, blake3)?;
+            if let Some(expected) = file.cached_hash() {
+                notify_handler(Notification::VerifyingComponent(
+                    &component.short_name(new_manifest),
+                    &self.target_triple,
+                    component.target.as_ref(),
+                ));
+                let notification_converter = |notification: crate::utils::Notification<'_>| {
+                    notify_handler(notification.into());
+                };
+                let mut hasher = file.hasher();
+                let mut reader = utils::HashingReader::new(
+                    utils::FileReaderWithProgress::new_file(&file, &notification_converter)?,
+                    &mut hasher,
+                );
+                io::copy(&mut reader, &mut io::sink())?;
+                if hasher.finalize() == expected {
+                    notify_handler(Notification::ChecksumValid(&url));
+                } else {
+                    download_cfg.discard_cached(file)?;
+                    download_with_retry(
+                        download_cfg,
+                        new_manifest,
+                        &component,
+                        &url,
+                        &hash,
+                        blake3,
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn uninstall(
         &self,
         manifest: &Manifest,
@@ -367,6 +533,21 @@ impl Manifestation {
         }
     }
 
+    /// Records `manifest` as the one the installation's components come
+    /// from, without changing any of them, so that components can be added
+    /// to a toolchain which wasn't installed from it.
+    pub fn save_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let prefix = self.installation.prefix();
+        utils::ensure_dir_exists("manifest", &prefix.manifest_dir(), &|_: Notification<
+            '_,
+        >| {})?;
+        utils::write_file(
+            "manifest",
+            &prefix.manifest_file(DIST_MANIFEST),
+            &manifest.clone().stringify(),
+        )
+    }
+
     pub fn load_manifest(&self) -> Result<Option<Manifest>> {
         let prefix = self.installation.prefix();
         let old_manifest_path = prefix.manifest_file(DIST_MANIFEST);
@@ -422,6 +603,9 @@ impl Manifestation {
             temp_cfg,
             notify_handler,
             pgp_keys,
+            download_log: None,
+            network: &NetworkSettings::default(),
+            verified_manifest: None,
         };
 
         let dl = dlcfg.download_and_check(&url, update_hash, ".tar.gz")?;
@@ -490,6 +674,114 @@ impl Manifestation {
     }
 }
 
+/// Downloads a component package into the download cache, retrying when
+/// the transfer is interrupted.
+fn download_with_retry(
+    download_cfg: &DownloadCfg<'_>,
+    new_manifest: &Manifest,
+    component: &Component,
+    url: &str,
+    hash: &str,
+    blake3: Option<&str>,
+) -> Result<File> {
+    const DEFAULT_MAX_RETRIES: usize = 3;
+    let max_retries: usize = process()
+        .var("RUSTUP_MAX_RETRIES")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .or(download_cfg.network.retries)
+        .unwrap_or(DEFAULT_MAX_RETRIES);
+    let notify_handler = download_cfg.notify_handler;
+
+    let url_url = utils::parse_url(url)?;
+    let started = Instant::now();
+    let mut attempts: usize = 0;
+    let result = retry(NoDelay.take(max_retries), || {
+        attempts += 1;
+        match download_cfg.download(&url_url, hash, blake3) {
+            Ok(f) => OperationResult::Ok(f),
+            Err(e) => {
+                match e.downcast_ref::<RustupError>() {
+                    Some(RustupError::BrokenPartialFile) => {
+                        notify_handler(Notification::RetryingDownload(url));
+                        return OperationResult::Retry(OperationError(e));
+                    }
+                    Some(RustupError::DownloadingFile { .. }) => {
+                        notify_handler(Notification::RetryingDownload(url));
+                        return OperationResult::Retry(OperationError(e));
+                    }
+                    Some(_) => return OperationResult::Err(OperationError(e)),
+                    None => (),
+                };
+                OperationResult::Err(OperationError(e))
+            }
+        }
+    });
+
+    let retries = attempts.saturating_sub(1);
+    match &result {
+        // Reused from the download cache, so nothing was downloaded.
+        Ok(file) if file.cached_hash().is_some() => {}
+        Ok(file) => download_cfg.log_download(
+            url,
+            file.source().as_str(),
+            Outcome::Success,
+            retries,
+            started,
+        ),
+        Err(retry::Error::Operation {
+            error: OperationError(e),
+            ..
+        }) => download_cfg.log_download(url, url, Outcome::from_error(e), retries, started),
+        Err(retry::Error::Internal(e)) => {
+            download_cfg.log_download(url, url, Outcome::Failed(e.clone()), retries, started)
+        }
+    }
+
+    result.with_context(|| RustupError::ComponentDownloadFailed(component.name(new_manifest)))
+}
+
+/// Unpacks an installer file into a temporary directory, optionally hashing
+/// the whole file as it is read.
+fn open_package<'a>(
+    installer_file: &Path,
+    format: CompressionKind,
+    temp_cfg: &'a temp::Cfg,
+    notify_handler: &'a dyn Fn(crate::utils::Notification<'_>),
+    hasher: Option<&mut ContentHasher>,
+) -> Result<Box<dyn Package + 'a>> {
+    let mut reader = utils::FileReaderWithProgress::new_file(installer_file, notify_handler)?;
+    match hasher {
+        None => unpack_package(&mut reader, format, temp_cfg, notify_handler),
+        Some(hasher) => {
+            let mut reader = utils::HashingReader::new(&mut reader, hasher);
+            let package = unpack_package(&mut reader, format, temp_cfg, notify_handler);
+            // The archive may end before the file does (or unpacking may have
+            // failed part way); hash whatever is left so the digest covers
+            // the entire file.
+            io::copy(&mut reader, &mut io::sink())?;
+            package
+        }
+    }
+}
+
+fn unpack_package<'a, R: Read>(
+    reader: R,
+    format: CompressionKind,
+    temp_cfg: &'a temp::Cfg,
+    notify_handler: &'a dyn Fn(crate::utils::Notification<'_>),
+) -> Result<Box<dyn Package + 'a>> {
+    Ok(match format {
+        CompressionKind::GZip => {
+            Box::new(TarGzPackage::new(reader, temp_cfg, Some(notify_handler))?)
+        }
+        CompressionKind::XZ => Box::new(TarXzPackage::new(reader, temp_cfg, Some(notify_handler))?),
+        CompressionKind::ZStd => {
+            Box::new(TarZStdPackage::new(reader, temp_cfg, Some(notify_handler))?)
+        }
+    })
+}
+
 #[derive(Debug)]
 struct Update {
     components_to_uninstall: Vec<Component>,
@@ -691,7 +983,7 @@ impl Update {
     fn components_urls_and_hashes(
         &self,
         new_manifest: &Manifest,
-    ) -> Result<Vec<(Component, CompressionKind, String, String)>> {
+    ) -> Result<Vec<(Component, CompressionKind, String, String, Option<String>)>> {
         let mut components_urls_and_hashes = Vec::new();
         for component in &self.components_to_install {
             let package = new_manifest.get_package(&component.short_name_in_manifest())?;
@@ -708,6 +1000,7 @@ impl Update {
                 target_package.bins[0].0,
                 target_package.bins[0].1.url.clone(),
                 target_package.bins[0].1.hash.clone(),
+                target_package.bins[0].1.blake3.clone(),
             ));
         }
 
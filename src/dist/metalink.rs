@@ -0,0 +1,148 @@
+//! Parsing of Metalink (RFC 5854) documents, which list the mirrors an
+//! artifact can be downloaded from along with its hashes.
+//!
+//! Only the parts rustup needs are understood: the SHA-256 hash of the
+//! (first) file described and its mirror URLs with their priorities.
+
+use anyhow::{anyhow, Context, Result};
+use url::Url;
+use xml::name::OwnedName;
+use xml::reader::{EventReader, XmlEvent};
+
+const NAMESPACE: &str = "urn:ietf:params:xml:ns:metalink";
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Metalink {
+    pub sha256: Option<String>,
+    /// Mirror URLs, most preferred first.
+    pub mirrors: Vec<Url>,
+}
+
+impl Metalink {
+    pub(crate) fn parse(data: &str) -> Result<Self> {
+        let mut sha256 = None;
+        let mut mirrors = Vec::new();
+        // The elements read into, outermost first, each with the attribute
+        // which matters of it: the type of a hash, or the priority of a URL.
+        let mut open: Vec<(String, Option<String>)> = Vec::new();
+        let mut files = 0;
+        let mut text = String::new();
+        for event in EventReader::from_str(data) {
+            match event.context("invalid metalink document")? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    if open.is_empty() && !is_metalink(&name, "metalink") {
+                        return Err(anyhow!("not a metalink document"));
+                    }
+                    if open.len() == 1 && is_metalink(&name, "file") {
+                        files += 1;
+                    }
+                    let attribute = match name.local_name.as_str() {
+                        "hash" => "type",
+                        "url" => "priority",
+                        _ => "",
+                    };
+                    let value = attributes
+                        .into_iter()
+                        .find(|a| a.name.namespace.is_none() && a.name.local_name == attribute)
+                        .map(|a| a.value);
+                    let local = if name.namespace.as_deref() == Some(NAMESPACE) {
+                        name.local_name
+                    } else {
+                        String::new()
+                    };
+                    open.push((local, value));
+                    text.clear();
+                }
+                XmlEvent::Characters(s) | XmlEvent::CData(s) => text.push_str(&s),
+                XmlEvent::EndElement { .. } => {
+                    let (local, value) = open.pop().unwrap_or_default();
+                    // Only what is directly in the first file counts, not
+                    // the hashes of its pieces, say.
+                    let in_first_file = open.len() == 2 && open[1].0 == "file" && files == 1;
+                    match local.as_str() {
+                        "hash" if in_first_file && value.as_deref() == Some("sha-256") => {
+                            let hash = text.trim();
+                            if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                                sha256.get_or_insert_with(|| hash.to_ascii_lowercase());
+                            }
+                        }
+                        "url" if in_first_file => {
+                            // Mirrors without a priority are tried last.
+                            let priority = value
+                                .and_then(|p| p.parse::<u32>().ok())
+                                .unwrap_or(u32::MAX);
+                            if let Ok(url) = Url::parse(text.trim()) {
+                                mirrors.push((priority, url));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Stable, so mirrors of equal priority keep document order.
+        mirrors.sort_by_key(|(priority, _)| *priority);
+
+        Ok(Self {
+            sha256,
+            mirrors: mirrors.into_iter().map(|(_, url)| url).collect(),
+        })
+    }
+}
+
+fn is_metalink(name: &OwnedName, local: &str) -> bool {
+    name.namespace.as_deref() == Some(NAMESPACE) && name.local_name == local
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hash_and_orders_mirrors() {
+        let doc = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="rustc-nightly-x86_64-unknown-linux-gnu.tar.xz">
+    <pieces length="1048576" type="sha-256">
+      <hash>0000000000000000000000000000000000000000000000000000000000000000</hash>
+    </pieces>
+    <hash type="sha-256">F3B7B1A1D8C2A0B7E0C8A5B4F6B0D2D1C7E9A8B6C5D4E3F2A1B0C9D8E7F6A5B4</hash>
+    <url location="us">https://us.example.com/dist/rustc.tar.xz</url>
+    <url location="de" priority="2">https://de.example.com/dist/rustc.tar.xz?a=1&amp;b=2</url>
+    <!-- <url priority="0">https://commented.example.com/</url> -->
+    <url priority="1"><![CDATA[https://cdn.example.com/dist/rustc.tar.xz]]></url>
+  </file>
+  <file name="other.tar.xz">
+    <url priority="0">https://other.example.com/other.tar.xz</url>
+  </file>
+</metalink>"#;
+        let metalink = Metalink::parse(doc).unwrap();
+        assert_eq!(
+            metalink.sha256.as_deref(),
+            Some("f3b7b1a1d8c2a0b7e0c8a5b4f6b0d2d1c7e9a8b6c5d4e3f2a1b0c9d8e7f6a5b4")
+        );
+        let mirrors: Vec<_> = metalink.mirrors.iter().map(Url::as_str).collect();
+        assert_eq!(
+            mirrors,
+            [
+                "https://cdn.example.com/dist/rustc.tar.xz",
+                "https://de.example.com/dist/rustc.tar.xz?a=1&b=2",
+                "https://us.example.com/dist/rustc.tar.xz",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        assert!(Metalink::parse("<html>not found</html>").is_err());
+        assert!(Metalink::parse("not xml at all").is_err());
+        // Metalink 3, which has another namespace and layout.
+        assert!(Metalink::parse(r#"<metalink xmlns="http://www.metalinker.org/"/>"#).is_err());
+        assert!(
+            Metalink::parse(r#"<metalink xmlns="urn:ietf:params:xml:ns:metalink"><file>"#).is_err()
+        );
+    }
+}
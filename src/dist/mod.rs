@@ -9,9 +9,12 @@ pub mod config;
 #[allow(clippy::module_inception)]
 pub mod dist;
 pub mod download;
+pub mod download_log;
 pub mod manifest;
 pub mod manifestation;
+mod metalink;
 pub mod notifications;
 pub mod prefix;
 pub mod signatures;
+pub mod toolchain_archive;
 pub mod triple;
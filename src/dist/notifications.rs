@@ -26,6 +26,7 @@ pub enum Notification<'a> {
     NonFatalError(&'a anyhow::Error),
     MissingInstalledComponent(&'a str),
     DownloadingComponent(&'a str, &'a TargetTriple, Option<&'a TargetTriple>),
+    VerifyingComponent(&'a str, &'a TargetTriple, Option<&'a TargetTriple>),
     InstallingComponent(&'a str, &'a TargetTriple, Option<&'a TargetTriple>),
     RemovingComponent(&'a str, &'a TargetTriple, Option<&'a TargetTriple>),
     RemovingOldComponent(&'a str, &'a TargetTriple, Option<&'a TargetTriple>),
@@ -35,10 +36,15 @@ pub enum Notification<'a> {
     SkippingNightlyMissingComponent(&'a ToolchainDesc, &'a Manifest, &'a [Component]),
     ForcingUnavailableComponent(&'a str),
     ManifestChecksumFailedHack,
+    UsingPrefetchedManifest(&'a str, &'a str),
     ComponentUnavailable(&'a str, Option<&'a TargetTriple>),
     StrayHash(&'a Path),
     SignatureInvalid(&'a str),
     RetryingDownload(&'a str),
+    UsingMirror(&'a str),
+    MirrorFailed(&'a str, &'a str),
+    NoMetalink(&'a str),
+    MetalinkHashMismatch(&'a str),
 }
 
 impl<'a> From<crate::utils::Notification<'a>> for Notification<'a> {
@@ -63,6 +69,9 @@ impl<'a> Notification<'a> {
             | SignatureValid(_, _)
             | NoUpdateHash(_)
             | FileAlreadyDownloaded
+            | UsingMirror(_)
+            | NoMetalink(_)
+            | VerifyingComponent(_, _, _)
             | DownloadingLegacyManifest => NotificationLevel::Verbose,
             Extracting(_, _)
             | DownloadingComponent(_, _, _)
@@ -75,6 +84,7 @@ impl<'a> Notification<'a> {
             | DownloadingManifest(_)
             | SkippingNightlyMissingComponent(_, _, _)
             | RetryingDownload(_)
+            | UsingPrefetchedManifest(_, _)
             | DownloadedManifest(_, _) => NotificationLevel::Info,
             CantReadUpdateHash(_)
             | ExtensionNotInstalled(_)
@@ -82,11 +92,30 @@ impl<'a> Notification<'a> {
             | CachedFileChecksumFailed
             | ComponentUnavailable(_, _)
             | ForcingUnavailableComponent(_)
+            | MirrorFailed(_, _)
+            | MetalinkHashMismatch(_)
             | StrayHash(_) => NotificationLevel::Warn,
             NonFatalError(_) => NotificationLevel::Error,
             SignatureInvalid(_) => NotificationLevel::Warn,
         }
     }
+
+    pub(crate) fn target(&self) -> &'static str {
+        use self::Notification::*;
+        match self {
+            Temp(_) => "rustup",
+            Utils(n) => n.target(),
+            FileAlreadyDownloaded
+            | CachedFileChecksumFailed
+            | RetryingDownload(_)
+            | UsingMirror(_)
+            | MirrorFailed(_, _)
+            | NoMetalink(_)
+            | MetalinkHashMismatch(_) => "download",
+            RollingBack | NonFatalError(_) => "transaction",
+            _ => "dist",
+        }
+    }
 }
 
 impl<'a> Display for Notification<'a> {
@@ -126,6 +155,13 @@ impl<'a> Display for Notification<'a> {
                     write!(f, "downloading component '{}' for '{}'", c, t.unwrap())
                 }
             }
+            VerifyingComponent(c, h, t) => {
+                if Some(h) == t.as_ref() || t.is_none() {
+                    write!(f, "verifying cached component '{}'", c)
+                } else {
+                    write!(f, "verifying cached component '{}' for '{}'", c, t.unwrap())
+                }
+            }
             InstallingComponent(c, h, t) => {
                 if Some(h) == t.as_ref() || t.is_none() {
                     write!(f, "installing component '{}'", c)
@@ -163,6 +199,11 @@ impl<'a> Display for Notification<'a> {
             ManifestChecksumFailedHack => {
                 write!(f, "update not yet available, sorry! try again later")
             }
+            UsingPrefetchedManifest(name, date) => write!(
+                f,
+                "working offline: installing '{}' from the manifest of {} which `rustup prefetch` saved",
+                name, date
+            ),
             ComponentUnavailable(pkg, toolchain) => {
                 if let Some(tc) = toolchain {
                     write!(f, "component '{}' is not available on target '{}'", pkg, tc)
@@ -196,6 +237,18 @@ impl<'a> Display for Notification<'a> {
             }
             SignatureInvalid(url) => write!(f, "Signature verification failed for '{}'", url),
             RetryingDownload(url) => write!(f, "retrying download for '{}'", url),
+            UsingMirror(url) => write!(f, "downloading from mirror '{}'", url),
+            MirrorFailed(url, next) => write!(
+                f,
+                "download from '{}' failed, switching to mirror '{}'",
+                url, next
+            ),
+            NoMetalink(url) => write!(f, "no metalink found for '{}'", url),
+            MetalinkHashMismatch(url) => write!(
+                f,
+                "ignoring metalink for '{}': its hash does not match the channel manifest",
+                url
+            ),
         }
     }
 }
@@ -3,6 +3,8 @@ use std::fs;
 use std::io;
 use std::ops;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 pub use anyhow::{Context, Result};
 use thiserror::Error as ThisError;
@@ -30,10 +32,11 @@ pub enum Notification<'a> {
     DirectoryDeletion(&'a Path, io::Result<()>),
 }
 
+#[derive(Clone)]
 pub struct Cfg {
     root_directory: PathBuf,
     pub dist_server: String,
-    notify_handler: Box<dyn Fn(Notification<'_>)>,
+    notify_handler: Arc<dyn Fn(Notification<'_>) + Send + Sync>,
 }
 
 #[derive(Debug)]
@@ -93,20 +96,24 @@ impl Cfg {
     pub fn new(
         root_directory: PathBuf,
         dist_server: &str,
-        notify_handler: Box<dyn Fn(Notification<'_>)>,
+        notify_handler: Box<dyn Fn(Notification<'_>) + Send + Sync>,
     ) -> Self {
         Self {
             root_directory,
             dist_server: dist_server.to_owned(),
-            notify_handler,
+            notify_handler: notify_handler.into(),
         }
     }
 
     pub fn create_root(&self) -> Result<bool> {
-        raw::ensure_dir_exists(&self.root_directory, |p| {
+        let created = raw::ensure_dir_exists(&self.root_directory, |p| {
             (self.notify_handler)(Notification::CreatingRoot(p));
         })
-        .with_context(|| Error::CreatingRoot(PathBuf::from(&self.root_directory)))
+        .with_context(|| Error::CreatingRoot(PathBuf::from(&self.root_directory)))?;
+        if created {
+            utils::share_with_group(&self.root_directory, false);
+        }
+        Ok(created)
     }
 
     pub fn new_directory(&self) -> Result<Dir<'_>> {
@@ -123,6 +130,7 @@ impl Cfg {
                 (self.notify_handler)(Notification::CreatingDirectory(&temp_dir));
                 fs::create_dir(&temp_dir)
                     .with_context(|| Error::CreatingDirectory(PathBuf::from(&temp_dir)))?;
+                utils::share_with_group(&temp_dir, false);
                 return Ok(Dir {
                     cfg: self,
                     path: temp_dir,
@@ -149,6 +157,7 @@ impl Cfg {
                 (self.notify_handler)(Notification::CreatingFile(&temp_file));
                 fs::File::create(&temp_file)
                     .with_context(|| Error::CreatingFile(PathBuf::from(&temp_file)))?;
+                utils::share_with_group(&temp_file, false);
                 return Ok(File {
                     cfg: self,
                     path: temp_file,
@@ -157,9 +166,47 @@ impl Cfg {
         }
     }
 
+    pub fn root_directory(&self) -> &Path {
+        &self.root_directory
+    }
+
     pub fn clean(&self) {
         utils::delete_dir_contents(&self.root_directory);
     }
+
+    /// Deletes the temporary files and directories that haven't been
+    /// modified for `age`, which were left behind by interrupted processes,
+    /// returning how many bytes that freed.
+    pub fn clean_older_than(&self, age: Duration) -> u64 {
+        let entries = match fs::read_dir(&self.root_directory) {
+            Ok(entries) => entries,
+            Err(_) => return 0,
+        };
+        let mut freed = 0;
+        for entry in entries.filter_map(io::Result::ok) {
+            let elapsed = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok());
+            if !matches!(elapsed, Some(elapsed) if elapsed >= age) {
+                continue;
+            }
+            let path = entry.path();
+            if path.is_dir() {
+                let size = utils::dir_size(&path).unwrap_or(0);
+                if remove_dir_all::remove_dir_all(&path).is_ok() {
+                    freed += size;
+                }
+            } else {
+                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                if fs::remove_file(&path).is_ok() {
+                    freed += size;
+                }
+            }
+        }
+        freed
+    }
 }
 
 impl fmt::Debug for Cfg {
@@ -0,0 +1,231 @@
+//! Archives of whole installed toolchains, made by `rustup toolchain export`
+//! so a toolchain can be carried to another machine without network access,
+//! and installed there by `rustup toolchain import`.
+//!
+//! An archive is a tarball holding the toolchain directory under `toolchain/`
+//! and a small `rustup-toolchain.toml` describing it. The tarball is
+//! compressed according to the extension of the file it is written to.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::errors::RustupError;
+use crate::toml_utils::*;
+
+/// The name of the metadata file at the root of the archive.
+pub const METADATA_FILE: &str = "rustup-toolchain.toml";
+/// The directory in the archive the toolchain's files are stored under.
+pub const TOOLCHAIN_DIR: &str = "toolchain";
+const METADATA_VERSION: &str = "1";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.ends_with(".tar.gz") {
+            Ok(Self::Gzip)
+        } else if name.ends_with(".tar.xz") {
+            Ok(Self::Xz)
+        } else if name.ends_with(".tar.zst") {
+            Ok(Self::Zstd)
+        } else {
+            Err(anyhow!(
+                "unsupported archive '{}': expected a name ending in .tar.gz, .tar.xz or .tar.zst",
+                path.display()
+            ))
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    /// The full name of the toolchain, e.g. `nightly-x86_64-unknown-linux-gnu`.
+    pub name: String,
+    /// The toolchain's update hash, so `rustup update` knows it is current.
+    pub update_hash: Option<String>,
+}
+
+impl Metadata {
+    pub fn parse(data: &str) -> Result<Self> {
+        let mut table: toml::value::Table =
+            toml::from_str(data).context("error parsing toolchain archive metadata")?;
+        let version = get_string(&mut table, "version", "")?;
+        if version != METADATA_VERSION {
+            return Err(RustupError::UnknownMetadataVersion(version).into());
+        }
+        Ok(Self {
+            name: get_string(&mut table, "name", "")?,
+            update_hash: get_opt_string(&mut table, "update_hash", "")?,
+        })
+    }
+
+    pub fn stringify(self) -> String {
+        let mut table = toml::value::Table::new();
+        table.insert(
+            "version".to_owned(),
+            toml::Value::String(METADATA_VERSION.to_owned()),
+        );
+        table.insert("name".to_owned(), toml::Value::String(self.name));
+        if let Some(v) = self.update_hash {
+            table.insert("update_hash".to_owned(), toml::Value::String(v));
+        }
+        toml::Value::Table(table).to_string()
+    }
+}
+
+/// Writes the toolchain installed at `toolchain_dir` to an archive at `dest`.
+/// The archive only appears at `dest` once it is complete.
+pub fn export(toolchain_dir: &Path, metadata: Metadata, dest: &Path) -> Result<()> {
+    let compression = Compression::from_path(dest)?;
+    let mut partial = dest.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    let result = write_archive(toolchain_dir, metadata, &partial, compression);
+    match result {
+        Ok(()) => fs::rename(&partial, dest).with_context(|| RustupError::WritingFile {
+            name: "toolchain archive",
+            path: dest.to_owned(),
+        }),
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+fn write_archive(
+    toolchain_dir: &Path,
+    metadata: Metadata,
+    dest: &Path,
+    compression: Compression,
+) -> Result<()> {
+    let file = File::create(dest).with_context(|| RustupError::WritingFile {
+        name: "toolchain archive",
+        path: dest.to_owned(),
+    })?;
+    let file = io::BufWriter::new(file);
+    match compression {
+        Compression::Gzip => {
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            write_tar(toolchain_dir, metadata, encoder)?.finish()?;
+        }
+        Compression::Xz => {
+            let encoder = xz2::write::XzEncoder::new(file, 6);
+            write_tar(toolchain_dir, metadata, encoder)?.finish()?;
+        }
+        Compression::Zstd => {
+            let encoder = zstd::stream::write::Encoder::new(file, 0)?;
+            write_tar(toolchain_dir, metadata, encoder)?.finish()?;
+        }
+    }
+    Ok(())
+}
+
+fn write_tar<W: Write>(toolchain_dir: &Path, metadata: Metadata, out: W) -> Result<W> {
+    let mut builder = tar::Builder::new(out);
+    // Toolchains contain symlinks on some platforms; keep them as they are.
+    builder.follow_symlinks(false);
+
+    let metadata = metadata.stringify();
+    let mut header = tar::Header::new_gnu();
+    header.set_size(metadata.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, METADATA_FILE, metadata.as_bytes())?;
+
+    builder
+        .append_dir_all(TOOLCHAIN_DIR, toolchain_dir)
+        .with_context(|| RustupError::ReadingDirectory {
+            name: "toolchain",
+            path: toolchain_dir.to_owned(),
+        })?;
+    Ok(builder.into_inner()?)
+}
+
+/// Reads the metadata of the archive at `src` without unpacking it.
+pub fn read_metadata(src: &Path) -> Result<Metadata> {
+    let mut archive = open(src)?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_ref() == Path::new(METADATA_FILE) {
+            let mut data = String::new();
+            entry.read_to_string(&mut data)?;
+            return Metadata::parse(&data);
+        }
+    }
+    Err(anyhow!(
+        "'{}' is not a toolchain archive: it has no {}",
+        src.display(),
+        METADATA_FILE
+    ))
+}
+
+/// Unpacks the archive at `src` into `dir`, leaving the toolchain's files in
+/// `dir/toolchain`.
+pub fn unpack(src: &Path, dir: &Path) -> Result<()> {
+    open(src)?
+        .unpack(dir)
+        .with_context(|| format!("failed to unpack '{}'", src.display()))?;
+    if !dir.join(TOOLCHAIN_DIR).is_dir() {
+        return Err(anyhow!(
+            "'{}' is not a toolchain archive: it has no {}/ directory",
+            src.display(),
+            TOOLCHAIN_DIR
+        ));
+    }
+    Ok(())
+}
+
+fn open(src: &Path) -> Result<tar::Archive<Box<dyn Read>>> {
+    let compression = Compression::from_path(src)?;
+    let file = File::open(src).with_context(|| RustupError::ReadingFile {
+        name: "toolchain archive",
+        path: src.to_owned(),
+    })?;
+    let file = io::BufReader::new(file);
+    let reader: Box<dyn Read> = match compression {
+        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(file)),
+        Compression::Xz => Box::new(xz2::read::XzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(file)?),
+    };
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    Ok(archive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_round_trips() {
+        let metadata = Metadata {
+            name: "nightly-x86_64-unknown-linux-gnu".to_owned(),
+            update_hash: Some("abc123".to_owned()),
+        };
+        assert_eq!(
+            Metadata::parse(&metadata.clone().stringify()).unwrap(),
+            metadata
+        );
+        assert!(Metadata::parse("version = \"2\"\nname = \"nightly\"").is_err());
+    }
+
+    #[test]
+    fn compression_from_extension() {
+        let of = |name: &str| Compression::from_path(Path::new(name)).ok();
+        assert_eq!(of("nightly.tar.zst"), Some(Compression::Zstd));
+        assert_eq!(of("dir/nightly.tar.xz"), Some(Compression::Xz));
+        assert_eq!(of("nightly.tar.gz"), Some(Compression::Gzip));
+        assert_eq!(of("nightly.zip"), None);
+    }
+}
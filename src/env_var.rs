@@ -6,6 +6,10 @@ use crate::process;
 
 pub const RUST_RECURSION_COUNT_MAX: u32 = 5;
 
+/// What each of a chain of proxies ran, one line each, so that the error
+/// when they run each other endlessly can say how.
+pub const RUSTUP_PROXY_TRACE: &str = "RUSTUP_PROXY_TRACE";
+
 #[allow(unused)]
 pub fn append_path(name: &str, value: Vec<PathBuf>, cmd: &mut Command) {
     let old_value = process().var_os(name);
@@ -36,6 +40,20 @@ pub fn prepend_path(name: &str, value: Vec<PathBuf>, cmd: &mut Command) {
     }
 }
 
+/// Adds `entry` to RUSTUP_PROXY_TRACE for `cmd`. A chain starts afresh
+/// where RUST_RECURSION_COUNT isn't set, whatever the trace says.
+pub fn trace_proxy(entry: &str, cmd: &mut Command) {
+    let mut trace = match process().var_os("RUST_RECURSION_COUNT") {
+        Some(_) => process().var(RUSTUP_PROXY_TRACE).unwrap_or_default(),
+        None => String::new(),
+    };
+    if !trace.is_empty() {
+        trace.push('\n');
+    }
+    trace.push_str(entry);
+    cmd.env(RUSTUP_PROXY_TRACE, trace);
+}
+
 pub fn inc(name: &str, cmd: &mut Command) {
     let old_value = process()
         .var(name)
@@ -1,13 +1,14 @@
 #![allow(clippy::large_enum_variant)]
 
 use std::ffi::OsString;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use std::io::{self, Write};
 use std::path::PathBuf;
 
 use thiserror::Error as ThisError;
 use url::Url;
 
+use crate::dist::dist::PartialToolchainDesc;
 use crate::dist::manifest::{Component, Manifest};
 
 pub const TOOLSTATE_MSG: &str =
@@ -18,6 +19,39 @@ pub const TOOLSTATE_MSG: &str =
      Then you can use the toolchain with commands such as:\n\n    \
      cargo +nightly-2018-12-27 build";
 
+/// A suggestion for getting past a failure, shown after the error as a
+/// `help:` line, ending with the command to run if there is one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hint {
+    pub message: String,
+    pub command: Option<String>,
+}
+
+impl Hint {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            command: None,
+        }
+    }
+
+    pub fn run(message: impl Into<String>, command: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            command: Some(command.into()),
+        }
+    }
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.command {
+            Some(command) => write!(f, "{}, run `{}`", self.message, command),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
 /// A type erasing thunk for the retry crate to permit use with anyhow. See <https://github.com/dtolnay/anyhow/issues/149>
 #[derive(Debug, ThisError)]
 #[error(transparent)]
@@ -84,10 +118,34 @@ pub enum RustupError {
     },
     #[error("command failed: '{}'", PathBuf::from(.name).display())]
     RunningCommand { name: OsString },
+    #[error("'{name}' is locked by the policy in '{}'", .policy.display())]
+    LockedByPolicy { name: String, policy: PathBuf },
+    #[error(
+        "toolchain '{toolchain}' is not allowed by the policy in '{}'; allowed channels are {allowed}",
+        .policy.display()
+    )]
+    ChannelNotAllowed {
+        toolchain: String,
+        allowed: String,
+        policy: PathBuf,
+    },
+    #[error(
+        "dist server '{server}' is not allowed by the policy in '{}'; allowed servers are {allowed}",
+        .policy.display()
+    )]
+    DistServerNotAllowed {
+        server: String,
+        allowed: String,
+        policy: PathBuf,
+    },
     #[error("toolchain '{0}' is not installable")]
     ToolchainNotInstallable(String),
     #[error("toolchain '{0}' is not installed")]
     ToolchainNotInstalled(String),
+    #[error("could not lock {name} file '{}'", .path.display())]
+    LockingFile { name: &'static str, path: PathBuf },
+    #[error("toolchain '{0}' is provided by the system and can't be changed by rustup")]
+    SystemToolchainReadOnly(String),
     #[error("no override and no default toolchain set")]
     ToolchainNotSelected,
     #[error("toolchain '{}' does not contain component {}{}{}", .name, .component, if let Some(suggestion) = .suggestion {
@@ -104,6 +162,20 @@ pub enum RustupError {
         component: String,
         suggestion: Option<String>,
     },
+    #[error(
+        "the channel manifest for '{name}' has SHA-256 '{actual}', but '{expected}' is pinned"
+    )]
+    PinnedManifestMismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("'{}' uses {what}, but '{}' is not trusted", .file.display(), .dir.display())]
+    UntrustedDirectory {
+        file: PathBuf,
+        dir: PathBuf,
+        what: String,
+    },
     #[error("unknown metadata version: '{0}'")]
     UnknownMetadataVersion(String),
     #[error("manifest version '{0}' is not supported")]
@@ -112,6 +184,38 @@ pub enum RustupError {
     WritingFile { name: &'static str, path: PathBuf },
 }
 
+impl RustupError {
+    /// What to do about this failure, where there is something to suggest.
+    pub fn hint(&self) -> Option<Hint> {
+        match self {
+            Self::ChecksumFailed { .. } => Some(Hint::new(
+                "the file may have changed on the server while it was downloaded; try again later",
+            )),
+            // Only a toolchain from the dist server can be installed by name.
+            Self::ToolchainNotInstalled(name) if name.parse::<PartialToolchainDesc>().is_ok() => {
+                Some(Hint::run(
+                    "to install it",
+                    format!("rustup toolchain install {}", name),
+                ))
+            }
+            Self::ToolchainNotSelected => Some(Hint::run(
+                "to install the stable toolchain and make it the default",
+                "rustup default stable",
+            )),
+            Self::LockedByPolicy { .. }
+            | Self::ChannelNotAllowed { .. }
+            | Self::DistServerNotAllowed { .. } => Some(Hint::new(
+                "the policy is set by the administrator of this machine",
+            )),
+            Self::UntrustedDirectory { dir, .. } => Some(Hint::run(
+                "if you trust what is in it",
+                format!("rustup trust add {}", dir.display()),
+            )),
+            _ => None,
+        }
+    }
+}
+
 fn remove_component_msg(cs: &Component, manifest: &Manifest, toolchain: &str) -> String {
     if cs.short_name_in_manifest() == "rust-std" {
         // We special-case rust-std as it's the stdlib so really you want to do
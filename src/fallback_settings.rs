@@ -1,6 +1,6 @@
 use serde::Deserialize;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 
@@ -9,12 +9,15 @@ use crate::utils::utils;
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct FallbackSettings {
     pub default_toolchain: Option<String>,
+    /// A read-only rustup home whose toolchains every user can run.
+    pub system_home: Option<PathBuf>,
 }
 
 impl Default for FallbackSettings {
     fn default() -> Self {
         Self {
             default_toolchain: None,
+            system_home: None,
         }
     }
 }
@@ -0,0 +1,101 @@
+//! Commands set in the `[hooks]` table of the settings, run through the
+//! shell before and after a toolchain is installed or updated, or
+//! components are added to or removed from one:
+//!
+//! ```toml
+//! [hooks]
+//! post-toolchain-update = "cargo install-update -a"
+//! ```
+//!
+//! Each is told what is happening through `RUSTUP_HOOK`,
+//! `RUSTUP_HOOK_TOOLCHAIN` and `RUSTUP_HOOK_COMPONENTS`, and as a JSON
+//! object on its standard input. A hook run before a change which fails
+//! stops the change; one run after it only warns.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+
+use crate::notifications::Notification;
+use crate::Cfg;
+
+/// The hooks there are, as they are named in the settings.
+pub const HOOKS: &[&str] = &[
+    "pre-toolchain-install",
+    "post-toolchain-install",
+    "pre-toolchain-update",
+    "post-toolchain-update",
+    "pre-component-add",
+    "post-component-add",
+    "pre-component-remove",
+    "post-component-remove",
+];
+
+#[derive(Serialize)]
+struct Change<'a> {
+    hook: &'a str,
+    toolchain: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    components: &'a [String],
+}
+
+/// Runs the `pre-` hook for `change` to `toolchain`, such as
+/// `toolchain-install`, failing if the hook does.
+pub(crate) fn before(
+    cfg: &Cfg,
+    change: &str,
+    toolchain: &str,
+    components: &[String],
+) -> Result<()> {
+    run(cfg, &format!("pre-{}", change), toolchain, components)
+}
+
+/// Runs the `post-` hook for `change` to `toolchain`, warning if it fails,
+/// since the change has been made by then.
+pub(crate) fn after(cfg: &Cfg, change: &str, toolchain: &str, components: &[String]) {
+    if let Err(e) = run(cfg, &format!("post-{}", change), toolchain, components) {
+        (cfg.notify_handler)(Notification::HookFailed(&e));
+    }
+}
+
+fn run(cfg: &Cfg, hook: &str, toolchain: &str, components: &[String]) -> Result<()> {
+    debug_assert!(HOOKS.contains(&hook));
+    let command = match cfg.settings_file.with(|s| Ok(s.hooks.get(hook).cloned()))? {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+    (cfg.notify_handler)(Notification::RunningHook(hook));
+
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(&command);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(&command);
+        cmd
+    };
+    let mut child = cmd
+        .env("RUSTUP_HOOK", hook)
+        .env("RUSTUP_HOOK_TOOLCHAIN", toolchain)
+        .env("RUSTUP_HOOK_COMPONENTS", components.join(","))
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run the {} hook '{}'", hook, command))?;
+    let change = serde_json::to_string(&Change {
+        hook,
+        toolchain,
+        components,
+    })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook needn't read it.
+        let _ = writeln!(stdin, "{}", change);
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("the {} hook '{}' failed: {}", hook, command, status);
+    }
+    Ok(())
+}
@@ -16,6 +16,8 @@ use crate::utils::utils;
 #[derive(Copy, Clone)]
 pub enum InstallMethod<'a> {
     Copy(&'a Path, &'a CustomToolchain<'a>),
+    /// Like `Copy`, but hard linking files where possible.
+    Hardlink(&'a Path, &'a CustomToolchain<'a>),
     Link(&'a Path, &'a CustomToolchain<'a>),
     // bool is whether to force an update
     Dist {
@@ -42,6 +44,12 @@ pub enum InstallMethod<'a> {
 impl<'a> InstallMethod<'a> {
     // Install a toolchain
     pub fn install(&self, toolchain: &Toolchain<'a>) -> Result<UpdateStatus> {
+        if let InstallMethod::Dist { desc, .. } = self {
+            toolchain.cfg().check_policy(desc)?;
+        }
+        // Another process may be installing the same toolchain. Once it is
+        // done, the update hash shows there is nothing left to do here.
+        let _lock = toolchain.cfg().lock_toolchain(toolchain.name())?;
         let previous_version = if toolchain.exists() {
             Some(toolchain.rustc_version())
         } else {
@@ -60,9 +68,20 @@ impl<'a> InstallMethod<'a> {
             &toolchain.path(),
             &toolchain.name(),
         ));
+        // Should rustup be interrupted, the marker is left behind for the
+        // next run to find. A toolchain that already needed repairing still
+        // does unless this actually reinstalled something.
+        let needed_repair = toolchain.cfg().needs_repair(toolchain.name());
+        toolchain
+            .cfg()
+            .mark_incomplete(toolchain.name(), previous_version.is_some())?;
         let updated = self.run(&toolchain.path(), &|n| {
             (toolchain.cfg().notify_handler)(n.into())
-        })?;
+        });
+        if !needed_repair || matches!(updated, Ok(true)) {
+            toolchain.cfg().mark_complete(toolchain.name())?;
+        }
+        let updated = updated?;
 
         if !updated {
             (toolchain.cfg().notify_handler)(RootNotification::UpdateHashMatches);
@@ -104,6 +123,12 @@ impl<'a> InstallMethod<'a> {
         match self {
             InstallMethod::Copy(src, ..) => {
                 utils::copy_dir(src, path, notify_handler)?;
+                utils::share_with_group(path, true);
+                Ok(true)
+            }
+            InstallMethod::Hardlink(src, ..) => {
+                utils::hardlink_dir(src, path, notify_handler)?;
+                utils::share_with_group(path, true);
                 Ok(true)
             }
             InstallMethod::Link(src, ..) => {
@@ -68,9 +68,13 @@ pub mod dist;
 pub mod env_var;
 pub mod errors;
 pub mod fallback_settings;
+mod hooks;
 mod install;
 mod notifications;
+mod platform;
+pub mod policy;
 pub mod settings;
 pub mod test;
 pub mod toolchain;
+mod usage_log;
 pub mod utils;
@@ -3,6 +3,7 @@ use std::path::{Path, PathBuf};
 
 use crate::dist::temp;
 use crate::utils::notify::NotificationLevel;
+use crate::utils::units::{Size, Unit, UnitMode};
 
 #[derive(Debug)]
 pub enum Notification<'a> {
@@ -14,6 +15,13 @@ pub enum Notification<'a> {
     SetOverrideToolchain(&'a Path, &'a str),
     SetProfile(&'a str),
     SetSelfUpdate(&'a str),
+    SetIpVersion(&'a str),
+    SetAutoInstallTargets(&'a str),
+    SetAutoInstallComponents(&'a str),
+    SetToolchainFileSearch(&'a str),
+    SetShims(&'a str),
+    SetColorTheme(&'a str),
+    SetUsageStats(&'a str),
     LookingForToolchain(&'a str),
     ToolchainDirectory(&'a Path, &'a str),
     UpdatingToolchain(&'a str),
@@ -23,12 +31,19 @@ pub enum Notification<'a> {
     UninstallingToolchain(&'a str),
     UninstalledToolchain(&'a str),
     ToolchainNotInstalled(&'a str),
+    RemovingIncompleteToolchain(&'a str),
+    /// How many bytes of temporary files left behind were removed.
+    RemovedStaleTempFiles(u64),
+    ToolchainNeedsRepair(&'a str),
     UpdateHashMatches,
     UpgradingMetadata(&'a str, &'a str),
     MetadataUpgradeNotNeeded(&'a str),
     WritingMetadataVersion(&'a str),
     ReadMetadataVersion(&'a str),
     NonFatalError(&'a anyhow::Error),
+    RunningHook(&'a str),
+    /// A hook run after a change failed, which leaves the change made.
+    HookFailed(&'a anyhow::Error),
     UpgradeRemovesToolchains,
     MissingFileDuringSelfUninstall(PathBuf),
     PlainVerboseMessage(&'a str),
@@ -37,6 +52,20 @@ pub enum Notification<'a> {
         rust_toolchain: &'a Path,
         rust_toolchain_toml: &'a Path,
     },
+    /// A toolchain file has a key that rustup doesn't know, and ignores
+    UnknownToolchainFileKey(&'a Path, &'a str),
+    /// A toolchain file in a directory which isn't trusted names a dist
+    /// server, which is ignored
+    IgnoringUntrustedDistServer(&'a str, &'a Path),
+    /// A project's `.rustup.toml` has a setting that rustup doesn't know, or
+    /// that a project can't set, and ignores
+    UnknownProjectSetting(&'a Path, &'a str),
+    /// A project's `.rustup.toml` in a directory which isn't trusted, which
+    /// is ignored
+    IgnoringUntrustedProjectSettings(&'a Path, &'a Path),
+    /// A project's `.rustup.toml` which can't be read, or is invalid, and is
+    /// ignored
+    IgnoringInvalidProjectSettings(&'a Path, &'a anyhow::Error),
 }
 
 impl<'a> From<crate::dist::Notification<'a>> for Notification<'a> {
@@ -75,16 +104,42 @@ impl<'a> Notification<'a> {
             | SetOverrideToolchain(_, _)
             | SetProfile(_)
             | SetSelfUpdate(_)
+            | SetIpVersion(_)
+            | SetAutoInstallTargets(_)
+            | SetAutoInstallComponents(_)
+            | SetToolchainFileSearch(_)
+            | SetShims(_)
+            | SetColorTheme(_)
+            | SetUsageStats(_)
             | UsingExistingToolchain(_)
             | UninstallingToolchain(_)
             | UninstalledToolchain(_)
             | ToolchainNotInstalled(_)
+            | RemovingIncompleteToolchain(_)
+            | RemovedStaleTempFiles(_)
             | UpgradingMetadata(_, _)
             | MetadataUpgradeNotNeeded(_) => NotificationLevel::Info,
             NonFatalError(_) => NotificationLevel::Error,
+            RunningHook(_) => NotificationLevel::Info,
+            HookFailed(_) => NotificationLevel::Warn,
             UpgradeRemovesToolchains
+            | ToolchainNeedsRepair(_)
             | MissingFileDuringSelfUninstall(_)
-            | DuplicateToolchainFile { .. } => NotificationLevel::Warn,
+            | DuplicateToolchainFile { .. }
+            | UnknownToolchainFileKey(_, _)
+            | IgnoringUntrustedDistServer(_, _)
+            | UnknownProjectSetting(_, _)
+            | IgnoringUntrustedProjectSettings(_, _)
+            | IgnoringInvalidProjectSettings(_, _) => NotificationLevel::Warn,
+        }
+    }
+
+    pub(crate) fn target(&self) -> &'static str {
+        use self::Notification::*;
+        match self {
+            Install(n) => n.target(),
+            Utils(n) => n.target(),
+            _ => "rustup",
         }
     }
 }
@@ -105,6 +160,19 @@ impl<'a> Display for Notification<'a> {
             ),
             SetProfile(name) => write!(f, "profile set to '{}'", name),
             SetSelfUpdate(mode) => write!(f, "auto-self-update mode set to '{}'", mode),
+            SetIpVersion(version) => write!(f, "download IP version set to '{}'", version),
+            SetAutoInstallTargets(mode) => {
+                write!(f, "auto-install-targets mode set to '{}'", mode)
+            }
+            SetAutoInstallComponents(mode) => {
+                write!(f, "auto-install-components mode set to '{}'", mode)
+            }
+            SetToolchainFileSearch(mode) => {
+                write!(f, "toolchain-file-search mode set to '{}'", mode)
+            }
+            SetShims(mode) => write!(f, "shims mode set to '{}'", mode),
+            SetColorTheme(theme) => write!(f, "color theme set to '{}'", theme),
+            SetUsageStats(mode) => write!(f, "usage-stats mode set to '{}'", mode),
             LookingForToolchain(name) => write!(f, "looking for installed toolchain '{}'", name),
             ToolchainDirectory(path, _) => write!(f, "toolchain directory: '{}'", path.display()),
             UpdatingToolchain(name) => write!(f, "updating existing install for '{}'", name),
@@ -114,6 +182,23 @@ impl<'a> Display for Notification<'a> {
             UninstallingToolchain(name) => write!(f, "uninstalling toolchain '{}'", name),
             UninstalledToolchain(name) => write!(f, "toolchain '{}' uninstalled", name),
             ToolchainNotInstalled(name) => write!(f, "no toolchain installed for '{}'", name),
+            RemovedStaleTempFiles(bytes) => write!(
+                f,
+                "removed {} of temporary files left behind",
+                Size::new(*bytes as usize, Unit::B, UnitMode::Norm)
+                    .to_string()
+                    .trim_start()
+            ),
+            RemovingIncompleteToolchain(name) => write!(
+                f,
+                "removing toolchain '{}', whose installation was interrupted",
+                name
+            ),
+            ToolchainNeedsRepair(name) => write!(
+                f,
+                "toolchain '{}' may be broken, as its last update was interrupted; run `rustup toolchain repair {}` to reinstall it",
+                name, name
+            ),
             UpdateHashMatches => write!(f, "toolchain is already up to date"),
             UpgradingMetadata(from_ver, to_ver) => write!(
                 f,
@@ -128,6 +213,8 @@ impl<'a> Display for Notification<'a> {
             WritingMetadataVersion(ver) => write!(f, "writing metadata version: '{}'", ver),
             ReadMetadataVersion(ver) => write!(f, "read metadata version: '{}'", ver),
             NonFatalError(e) => write!(f, "{}", e),
+            RunningHook(hook) => write!(f, "running the {} hook", hook),
+            HookFailed(e) => write!(f, "{:#}", e),
             UpgradeRemovesToolchains => write!(
                 f,
                 "this upgrade will remove all existing toolchains. you will need to reinstall them"
@@ -153,6 +240,33 @@ impl<'a> Display for Notification<'a> {
                     .unwrap_or_else(|_| PathBuf::from(rust_toolchain_toml))
                     .display(),
             ),
+            IgnoringUntrustedDistServer(server, dir) => write!(
+                f,
+                "ignoring the dist server '{}', since '{}' is not trusted; see `rustup trust --help`",
+                server,
+                dir.display()
+            ),
+            UnknownProjectSetting(path, key) => write!(
+                f,
+                "setting '{}' in '{}' is ignored; see `rustup help set`",
+                key,
+                path.display()
+            ),
+            IgnoringUntrustedProjectSettings(path, dir) => write!(
+                f,
+                "ignoring '{}', since '{}' is not trusted; see `rustup trust --help`",
+                path.display(),
+                dir.display()
+            ),
+            IgnoringInvalidProjectSettings(path, e) => {
+                write!(f, "ignoring '{}': {:#}", path.display(), e)
+            }
+            UnknownToolchainFileKey(path, key) => write!(
+                f,
+                "unknown key '{}' in '{}' is ignored; run `rustup toolchain-file check` for details",
+                key,
+                path.display()
+            ),
         }
     }
 }
@@ -0,0 +1,274 @@
+//! Conditions on the host platform, written as in Rust's `#[cfg(...)]`, for
+//! the `[target.'cfg(...)']` sections of toolchain files.
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::dist::dist::TargetTriple;
+
+#[derive(Debug, PartialEq)]
+enum CfgExpr {
+    Name(String),
+    KeyValue(String, String),
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Not(Box<CfgExpr>),
+}
+
+/// Whether `spec`, either `cfg(...)` or a whole target triple, applies to
+/// `host`.
+pub(crate) fn matches(spec: &str, host: &TargetTriple) -> Result<bool> {
+    let spec = spec.trim();
+    if !spec.starts_with("cfg(") {
+        return Ok(spec == host.to_string());
+    }
+    let expr = parse(spec).map_err(|e| anyhow!("invalid condition '{}': {}", spec, e))?;
+    Ok(expr.eval(&HostCfg::new(host)))
+}
+
+/// Checks that `spec` is a condition [`matches`] understands.
+pub(crate) fn validate(spec: &str) -> Result<()> {
+    let spec = spec.trim();
+    if spec.starts_with("cfg(") {
+        parse(spec).map_err(|e| anyhow!("invalid condition '{}': {}", spec, e))?;
+    }
+    Ok(())
+}
+
+impl CfgExpr {
+    fn eval(&self, host: &HostCfg) -> bool {
+        match self {
+            Self::Name(name) => match name.as_str() {
+                "windows" | "unix" => &host.family == name,
+                _ => false,
+            },
+            Self::KeyValue(key, value) => match key.as_str() {
+                "target_arch" => &host.arch == value,
+                "target_os" => &host.os == value,
+                "target_family" => &host.family == value,
+                "target_env" => &host.env == value,
+                "target_vendor" => &host.vendor == value,
+                _ => false,
+            },
+            Self::All(exprs) => exprs.iter().all(|e| e.eval(host)),
+            Self::Any(exprs) => exprs.iter().any(|e| e.eval(host)),
+            Self::Not(expr) => !expr.eval(host),
+        }
+    }
+}
+
+/// The `cfg` values rustc would set for the host, worked out from its
+/// triple.
+struct HostCfg {
+    arch: String,
+    os: String,
+    family: String,
+    env: String,
+    vendor: String,
+}
+
+impl HostCfg {
+    fn new(host: &TargetTriple) -> Self {
+        let triple = host.to_string();
+        let parts: Vec<_> = triple.split('-').collect();
+        let arch = match parts[0] {
+            "i386" | "i586" | "i686" => "x86",
+            "riscv64gc" => "riscv64",
+            a if a.starts_with("arm") => "arm",
+            a => a,
+        };
+        let has = |part: &str| parts[1..].iter().any(|p| p.starts_with(part));
+        let os = if has("windows") {
+            "windows"
+        } else if has("darwin") {
+            "macos"
+        } else if has("android") {
+            "android"
+        } else {
+            [
+                "linux", "ios", "freebsd", "netbsd", "openbsd", "illumos", "solaris",
+            ]
+            .iter()
+            .copied()
+            .find(|os| has(os))
+            .unwrap_or("")
+        };
+        let family = match os {
+            "windows" => "windows",
+            "" => "",
+            _ => "unix",
+        };
+        let env = match parts.last() {
+            Some(e) if parts.len() > 3 && e.starts_with("gnu") => "gnu",
+            Some(e) if parts.len() > 3 && e.starts_with("musl") => "musl",
+            Some(&"msvc") => "msvc",
+            _ => "",
+        };
+        let vendor = match parts.get(1) {
+            Some(&vendor) if parts.len() > 2 && vendor != "linux" => vendor,
+            _ => "unknown",
+        };
+        Self {
+            arch: arch.to_owned(),
+            os: os.to_owned(),
+            family: family.to_owned(),
+            env: env.to_owned(),
+            vendor: vendor.to_owned(),
+        }
+    }
+}
+
+fn parse(spec: &str) -> Result<CfgExpr> {
+    let mut parser = Parser { rest: spec };
+    parser.expect_word("cfg")?;
+    parser.expect('(')?;
+    let expr = parser.expr()?;
+    parser.expect(')')?;
+    if !parser.rest.trim().is_empty() {
+        bail!("unexpected '{}'", parser.rest.trim());
+    }
+    Ok(expr)
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn expr(&mut self) -> Result<CfgExpr> {
+        let word = self.word()?;
+        match word {
+            "all" | "any" => {
+                self.expect('(')?;
+                let mut exprs = Vec::new();
+                while !self.eat(')') {
+                    exprs.push(self.expr()?);
+                    if !self.eat(',') {
+                        self.expect(')')?;
+                        break;
+                    }
+                }
+                Ok(if word == "all" {
+                    CfgExpr::All(exprs)
+                } else {
+                    CfgExpr::Any(exprs)
+                })
+            }
+            "not" => {
+                self.expect('(')?;
+                let expr = self.expr()?;
+                self.expect(')')?;
+                Ok(CfgExpr::Not(Box::new(expr)))
+            }
+            _ if self.eat('=') => Ok(CfgExpr::KeyValue(word.to_owned(), self.string()?)),
+            _ => Ok(CfgExpr::Name(word.to_owned())),
+        }
+    }
+
+    fn word(&mut self) -> Result<&'a str> {
+        self.rest = self.rest.trim_start();
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            bail!("expected a name at '{}'", self.rest);
+        }
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Ok(word)
+    }
+
+    fn expect_word(&mut self, expected: &str) -> Result<()> {
+        match self.word()? {
+            word if word == expected => Ok(()),
+            word => bail!("expected '{}', found '{}'", expected, word),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let end = self
+            .rest
+            .find('"')
+            .ok_or_else(|| anyhow!("unterminated string"))?;
+        let value = self.rest[..end].to_owned();
+        self.rest = &self.rest[end + 1..];
+        Ok(value)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.rest = self.rest.trim_start();
+        if self.rest.starts_with(c) {
+            self.rest = &self.rest[1..];
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.eat(c) {
+            Ok(())
+        } else if self.rest.is_empty() {
+            bail!("expected '{}' at the end", c)
+        } else {
+            bail!("expected '{}' at '{}'", c, self.rest)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions_match_host() {
+        let linux = TargetTriple::new("x86_64-unknown-linux-gnu");
+        let macos = TargetTriple::new("aarch64-apple-darwin");
+        let windows = TargetTriple::new("x86_64-pc-windows-msvc");
+        let cases: &[(&str, [bool; 3])] = &[
+            ("cfg(unix)", [true, true, false]),
+            ("cfg(windows)", [false, false, true]),
+            (r#"cfg(target_os = "macos")"#, [false, true, false]),
+            (r#"cfg(target_arch = "x86_64")"#, [true, false, true]),
+            (r#"cfg(target_env = "msvc")"#, [false, false, true]),
+            (r#"cfg(target_vendor = "apple")"#, [false, true, false]),
+            (
+                r#"cfg(all(unix, not(target_os = "macos")))"#,
+                [true, false, false],
+            ),
+            (
+                r#"cfg(any(windows, target_os = "macos"))"#,
+                [false, true, true],
+            ),
+            ("cfg(all())", [true, true, true]),
+            ("x86_64-pc-windows-msvc", [false, false, true]),
+        ];
+        for (spec, expected) in cases {
+            for (host, expected) in [&linux, &macos, &windows].iter().zip(expected) {
+                assert_eq!(
+                    matches(spec, host).unwrap(),
+                    *expected,
+                    "{} on {}",
+                    spec,
+                    host
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_conditions_are_rejected() {
+        for spec in &[
+            "cfg(",
+            "cfg()",
+            "cfg(unix",
+            "cfg(unix) extra",
+            r#"cfg(target_os = macos)"#,
+            r#"cfg(target_os = "macos)"#,
+            "cfg(not(unix, windows))",
+        ] {
+            assert!(validate(spec).is_err(), "{}", spec);
+        }
+    }
+}
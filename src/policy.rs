@@ -0,0 +1,322 @@
+//! A machine-wide policy, set by an administrator in
+//! /etc/rustup/policy.toml, or on Windows in the file named by the
+//! `PolicyFile` value of `HKEY_LOCAL_MACHINE\SOFTWARE\Rustup`. It can lock
+//! settings to values the user can't change, restrict the channels and dist
+//! servers toolchains are installed from, and add mirrors to the user's.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::dist::dist::ToolchainDesc;
+use crate::errors::RustupError;
+use crate::process;
+use crate::settings::{parse_mirrors, set_override, settings_in, Settings};
+use crate::toml_utils::*;
+use crate::utils::utils;
+
+pub const UNIX_POLICY_FILE: &str = "/etc/rustup/policy.toml";
+
+/// Where the policy is, if there can be one. RUSTUP_OVERRIDE_POLICY names
+/// one for machines without a policy, such as in the tests; unlike
+/// RUSTUP_OVERRIDE_UNIX_FALLBACK_SETTINGS, it can't replace the machine's
+/// own, or the policy would be no more than a suggestion.
+pub(crate) fn policy_path() -> Option<PathBuf> {
+    match system_policy_path() {
+        Some(path) if utils::is_file(&path) => Some(path),
+        path => match process().var_os("RUSTUP_OVERRIDE_POLICY") {
+            Some(over) if !over.is_empty() => Some(PathBuf::from(over)),
+            _ => path,
+        },
+    }
+}
+
+#[cfg(not(windows))]
+fn system_policy_path() -> Option<PathBuf> {
+    Some(PathBuf::from(UNIX_POLICY_FILE))
+}
+
+#[cfg(windows)]
+fn system_policy_path() -> Option<PathBuf> {
+    use winreg::enums::HKEY_LOCAL_MACHINE;
+    use winreg::RegKey;
+
+    let key = RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey("SOFTWARE\\Rustup")
+        .ok()?;
+    let path: String = key.get_value("PolicyFile").ok()?;
+    Some(PathBuf::from(path))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Policy {
+    pub path: PathBuf,
+    /// The settings which are locked, named as `rustup config` names them,
+    /// with lists joined by commas.
+    locked: Vec<(&'static str, String)>,
+    /// The channels toolchains may be installed from, such as `stable` or
+    /// `1.70`, which allows every `1.70.x`. Empty allows every channel.
+    pub allowed_channels: Vec<String>,
+    /// The dist servers toolchains may be installed from. Empty allows
+    /// every server.
+    pub allowed_dist_servers: Vec<String>,
+    /// Mirrors used after the user's, which are allowed whatever
+    /// `allowed_dist_servers` says.
+    pub mirrors: Vec<String>,
+}
+
+impl Policy {
+    /// The policy in effect, if there is one.
+    pub fn load() -> Result<Option<Self>> {
+        let path = match policy_path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        // As with the fallback settings, a policy which isn't there or
+        // can't be read is no policy at all.
+        match utils::read_file("policy", &path) {
+            Err(e) => match e.downcast_ref::<io::Error>() {
+                Some(io_err) => match io_err.kind() {
+                    io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied => Ok(None),
+                    _ => Err(e),
+                },
+                None => Err(e),
+            },
+            Ok(data) => Ok(Some(Self::parse(&path, &data)?)),
+        }
+    }
+
+    pub fn parse(path: &Path, data: &str) -> Result<Self> {
+        let what = || format!("invalid policy in '{}'", path.display());
+        let mut table: toml::value::Table = toml::from_str(data).with_context(what)?;
+        let strings = |array: toml::value::Array, key: &str| -> Result<Vec<String>> {
+            array
+                .into_iter()
+                .map(|v| match v {
+                    toml::Value::String(s) => Ok(s.trim_end_matches('/').to_owned()),
+                    _ => Err(anyhow!("expected a list of strings for '{}'", key)),
+                })
+                .collect()
+        };
+        let allowed_channels = strings(
+            get_array(&mut table, "allowed_channels", "").with_context(what)?,
+            "allowed_channels",
+        )
+        .with_context(what)?;
+        let allowed_dist_servers = strings(
+            get_array(&mut table, "allowed_dist_servers", "").with_context(what)?,
+            "allowed_dist_servers",
+        )
+        .with_context(what)?;
+        let mirrors = strings(
+            get_array(&mut table, "mirrors", "").with_context(what)?,
+            "mirrors",
+        )
+        .and_then(|mirrors| parse_mirrors(&mirrors.join(",")))
+        .with_context(what)?;
+
+        let locked = get_table(&mut table, "locked", "")
+            .and_then(settings_in)
+            .context("in 'locked'")
+            .with_context(what)?;
+
+        if let Some(key) = table.keys().next() {
+            return Err(anyhow!("unknown key '{}'", key)).with_context(what);
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            locked,
+            allowed_channels,
+            allowed_dist_servers,
+            mirrors,
+        })
+    }
+
+    /// The value `name` is locked to, if it is.
+    fn locked(&self, name: &str) -> Option<&str> {
+        self.locked
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| &**value)
+    }
+
+    /// Whether setting `name` to `value` would change it from the value it
+    /// is locked to, so that a list in another order, say, isn't counted.
+    fn blocks(&self, name: &str, value: &str) -> bool {
+        let locked_value = match self.locked(name) {
+            Some(v) => v,
+            None => return false,
+        };
+        let mut wanted = Settings::default();
+        let mut locked = Settings::default();
+        set_override(&mut wanted, name, value).is_err()
+            || set_override(&mut locked, name, locked_value).is_err()
+            || wanted != locked
+    }
+
+    /// Fails if `value`, which `source` sets `name` to, is blocked.
+    pub(crate) fn check(&self, source: &str, name: &str, value: &str) -> Result<()> {
+        if self.blocks(name, value) {
+            return Err(self.locked_error(name))
+                .with_context(|| format!("could not use {}", source));
+        }
+        Ok(())
+    }
+
+    fn locked_error(&self, name: &str) -> anyhow::Error {
+        RustupError::LockedByPolicy {
+            name: name.to_owned(),
+            policy: self.path.clone(),
+        }
+        .into()
+    }
+
+    /// Gives `settings` the locked values and the policy's mirrors.
+    pub(crate) fn apply(&self, settings: &mut Settings) -> Result<()> {
+        for (name, value) in &self.locked {
+            set_override(settings, name, value)?;
+        }
+        for mirror in &self.mirrors {
+            if !settings.network.mirrors.contains(mirror) {
+                settings.network.mirrors.push(mirror.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if a change to the user's settings from `before` to `after`
+    /// changed a locked setting to anything but its locked value. Each
+    /// command changes one setting at a time, which is what this looks for.
+    pub(crate) fn check_change(&self, before: &Settings, after: &Settings) -> Result<()> {
+        if before == after {
+            return Ok(());
+        }
+        for (name, value) in &self.locked {
+            let mut locked_after = after.clone();
+            set_override(&mut locked_after, name, value)?;
+            if locked_after == *after {
+                continue;
+            }
+            // Locking the setting in both makes them the same only if it is
+            // what changed.
+            let mut locked_before = before.clone();
+            set_override(&mut locked_before, name, value)?;
+            if locked_before == locked_after {
+                return Err(self.locked_error(name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails unless `desc` may be installed from `dist_server`, through the
+    /// user's `mirrors`.
+    pub(crate) fn check_install(
+        &self,
+        desc: &ToolchainDesc,
+        dist_server: &str,
+        mirrors: &[String],
+    ) -> Result<()> {
+        let channel = &desc.channel;
+        let channel_allowed = self.allowed_channels.is_empty()
+            || self
+                .allowed_channels
+                .iter()
+                .any(|allowed| channel == allowed || channel.starts_with(&format!("{}.", allowed)));
+        if !channel_allowed {
+            return Err(RustupError::ChannelNotAllowed {
+                toolchain: desc.to_string(),
+                allowed: self.allowed_channels.join(", "),
+                policy: self.path.clone(),
+            }
+            .into());
+        }
+
+        if self.allowed_dist_servers.is_empty() {
+            return Ok(());
+        }
+        let servers = std::iter::once(dist_server)
+            .chain(mirrors.iter().map(|m| &**m))
+            .map(|server| server.trim_end_matches('/'));
+        for server in servers {
+            if !self.allowed_dist_servers.iter().any(|s| s == server)
+                && !self.mirrors.iter().any(|m| m == server)
+            {
+                return Err(RustupError::DistServerNotAllowed {
+                    server: server.to_owned(),
+                    allowed: self.allowed_dist_servers.join(", "),
+                    policy: self.path.clone(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dist::dist::Profile;
+    use std::str::FromStr;
+
+    #[test]
+    fn locks_settings() {
+        let policy = Policy::parse(
+            Path::new("policy.toml"),
+            r#"
+allowed_channels = ["stable", "1.70"]
+allowed_dist_servers = ["https://rust.example.com/"]
+mirrors = ["https://mirror.example.com"]
+
+[locked]
+profile = "minimal"
+
+[locked.network]
+retries = 5
+timeout = 60
+"#,
+        )
+        .unwrap();
+
+        let mut settings = Settings::default();
+        policy.apply(&mut settings).unwrap();
+        assert_eq!(settings.profile, Some(Profile::Minimal));
+        assert_eq!(settings.network.retries, Some(5));
+        assert_eq!(settings.network.timeout, Some(60));
+        assert_eq!(settings.network.mirrors, ["https://mirror.example.com"]);
+
+        assert!(policy.check("a test", "profile", "minimal").is_ok());
+        assert!(policy.check("a test", "profile", "complete").is_err());
+        assert!(policy.check("a test", "shims", "link").is_ok());
+
+        let mut changed = settings.clone();
+        changed.profile = Some(Profile::Complete);
+        assert!(policy.check_change(&settings, &changed).is_err());
+        changed.profile = Some(Profile::Minimal);
+        changed.default_toolchain = Some("stable".to_owned());
+        assert!(policy.check_change(&settings, &changed).is_ok());
+
+        let server = "https://rust.example.com";
+        let install = |name: &str, server: &str, mirrors: &[String]| {
+            let name = format!("{}-x86_64-unknown-linux-gnu", name);
+            policy.check_install(&ToolchainDesc::from_str(&name).unwrap(), server, mirrors)
+        };
+        assert!(install("stable", server, &[]).is_ok());
+        assert!(install("1.70.1", server, &[]).is_ok());
+        assert!(install("1.71.0", server, &[]).is_err());
+        assert!(install("nightly", server, &[]).is_err());
+        assert!(install("stable", "https://static.rust-lang.org", &[]).is_err());
+        assert!(install("stable", server, &["https://mirror.example.com".to_owned()]).is_ok());
+        assert!(install("stable", server, &["https://other.example.com".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let path = Path::new("policy.toml");
+        assert!(Policy::parse(path, "allowed_toolchains = []").is_err());
+        assert!(Policy::parse(path, "[locked]\nprofle = \"minimal\"").is_err());
+        assert!(Policy::parse(path, "[locked]\nprofile = \"smallest\"").is_err());
+    }
+}
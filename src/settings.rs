@@ -1,24 +1,384 @@
 use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use download::IpVersion;
 
 use crate::cli::self_update::SelfUpdateMode;
 use crate::dist::dist::Profile;
 use crate::errors::*;
 use crate::notifications::*;
+use crate::policy::Policy;
+use crate::process;
 use crate::toml_utils::*;
+use crate::utils::lock::FileLock;
+use crate::utils::toml_document;
 use crate::utils::utils;
 
 pub const SUPPORTED_METADATA_VERSIONS: [&str; 2] = ["2", "12"];
 pub const DEFAULT_METADATA_VERSION: &str = "12";
 
+/// The version of the layout of settings.toml itself, kept as
+/// `schema_version`, as opposed to `version`, which is that of the rest of
+/// RUSTUP_HOME. Files from before it was kept are schema 0.
+pub const SETTINGS_SCHEMA_VERSION: i64 = 1;
+
+/// A change to the layout of settings.toml, made to the TOML as read before
+/// it is parsed, bringing it from the schema before `to` up to `to`.
+struct Migration {
+    to: i64,
+    what: &'static str,
+    apply: fn(&mut toml::value::Table) -> Result<()>,
+}
+
+/// The migrations, oldest first. Each new schema adds one here, and bumps
+/// `SETTINGS_SCHEMA_VERSION` to its `to`.
+static MIGRATIONS: &[Migration] = &[Migration {
+    to: 1,
+    what: "record the schema version",
+    apply: |_| Ok(()),
+}];
+
+/// Brings `table` up to the newest schema in `migrations`, returning the
+/// schema it had if that was older. A table from a newer rustup is left as
+/// it is: its schema is kept, and the keys this rustup doesn't know with it.
+fn migrate(table: &mut toml::value::Table, migrations: &[Migration]) -> Result<Option<i64>> {
+    let from = get_opt_integer(table, "schema_version", "")?.unwrap_or(0);
+    let mut schema = from;
+    for migration in migrations.iter().filter(|m| m.to > from) {
+        (migration.apply)(table).with_context(|| {
+            format!(
+                "could not bring the settings up to schema {}: {}",
+                migration.to, migration.what
+            )
+        })?;
+        schema = migration.to;
+    }
+    table.insert("schema_version".to_owned(), toml::Value::Integer(schema));
+    Ok(if schema > from { Some(from) } else { None })
+}
+
+/// The settings which can be overridden for a single invocation by a
+/// `RUSTUP_SETTINGS_*` environment variable, named as `rustup config` names
+/// them, with how to set each from the variable's value.
+static ENV_OVERRIDES: &[(&str, fn(&mut Settings, &str) -> Result<()>)] = &[
+    ("default_toolchain", |s, v| {
+        s.default_toolchain = Some(v.to_owned());
+        Ok(())
+    }),
+    ("default_host_triple", |s, v| {
+        s.default_host_triple = Some(v.to_owned());
+        Ok(())
+    }),
+    ("profile", |s, v| {
+        s.profile = Some(Profile::from_str(v)?);
+        Ok(())
+    }),
+    ("auto_self_update", |s, v| {
+        s.auto_self_update = Some(SelfUpdateMode::from_str(v)?);
+        Ok(())
+    }),
+    ("auto_install_targets", |s, v| {
+        s.auto_install_targets = Some(AutoInstallTargets::from_str(v)?);
+        Ok(())
+    }),
+    ("auto_install_components", |s, v| {
+        s.auto_install_components = Some(AutoInstallComponents::from_str(v)?);
+        Ok(())
+    }),
+    ("toolchain_file_search", |s, v| {
+        s.toolchain_file_search = Some(ToolchainFileSearch::from_str(v)?);
+        Ok(())
+    }),
+    ("shims", |s, v| {
+        s.shims = Some(ShimMode::from_str(v)?);
+        Ok(())
+    }),
+    ("color_theme", |s, v| {
+        s.color_theme = Some(ColorTheme::from_str(v)?);
+        Ok(())
+    }),
+    ("usage_stats", |s, v| {
+        s.usage_stats = Some(UsageStats::from_str(v)?);
+        Ok(())
+    }),
+    ("rustc_wrapper", |s, v| {
+        s.rustc_wrapper = Some(v.to_owned());
+        Ok(())
+    }),
+    ("default_components", |s, v| {
+        s.default_components = parse_name_list(v, "component")?;
+        Ok(())
+    }),
+    ("default_targets", |s, v| {
+        s.default_targets = parse_name_list(v, "target")?;
+        Ok(())
+    }),
+    ("pgp_keys", |s, v| {
+        s.pgp_keys = Some(v.to_owned());
+        Ok(())
+    }),
+    ("network.ip-version", |s, v| {
+        s.network.ip_version = Some(IpVersion::from_str(v)?);
+        Ok(())
+    }),
+    ("network.proxy", |s, v| {
+        s.network.proxy = Some(parse_proxy(v)?);
+        Ok(())
+    }),
+    ("network.retries", |s, v| {
+        let retries = usize::from_str(v).map_err(|_| anyhow!("expected a number of retries"))?;
+        s.network.retries = Some(retries);
+        Ok(())
+    }),
+    ("network.timeout", |s, v| {
+        s.network.timeout = Some(parse_positive(v, "seconds")?);
+        Ok(())
+    }),
+    ("network.rate-limit", |s, v| {
+        s.network.rate_limit = Some(parse_positive(v, "bytes a second")?);
+        Ok(())
+    }),
+    ("network.mirrors", |s, v| {
+        s.network.mirrors = parse_mirrors(v)?;
+        Ok(())
+    }),
+    ("network.offline", |s, v| {
+        s.network.offline =
+            Some(bool::from_str(v).map_err(|_| anyhow!("expected 'true' or 'false'"))?);
+        Ok(())
+    }),
+    ("temp.dir", |s, v| {
+        s.temp.dir = Some(parse_temp_dir(v)?);
+        Ok(())
+    }),
+    ("temp.max-age", |s, v| {
+        let hours = u64::from_str(v).map_err(|_| anyhow!("expected a number of hours"))?;
+        s.temp.max_age = Some(hours);
+        Ok(())
+    }),
+    ("override_hook.ttl", |s, v| {
+        let ttl = u64::from_str(v).map_err(|_| anyhow!("expected a number of seconds"))?;
+        s.override_hook.ttl = Some(ttl);
+        Ok(())
+    }),
+    ("auto_update.interval", |s, v| {
+        let days = u64::from_str(v).map_err(|_| anyhow!("expected a number of days"))?;
+        s.auto_update.interval = Some(days);
+        Ok(())
+    }),
+    ("auto_update.channels", |s, v| {
+        s.auto_update.channels = Some(SelfUpdateMode::from_str(v)?);
+        Ok(())
+    }),
+];
+
+/// The environment variable which overrides the setting `name`, such as
+/// `RUSTUP_SETTINGS_NETWORK_IP_VERSION` for `network.ip-version`.
+pub fn env_override_var(name: &str) -> String {
+    format!(
+        "RUSTUP_SETTINGS_{}",
+        name.to_uppercase().replace(&['.', '-'][..], "_")
+    )
+}
+
+/// The components or targets, as `what` says, named in `list`, separated by
+/// commas or whitespace, as `rustup config` and the `RUSTUP_SETTINGS_*`
+/// variables take them.
+pub fn parse_name_list(list: &str, what: &str) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for name in list.split(|c: char| c == ',' || c.is_whitespace()) {
+        if name.is_empty() {
+            continue;
+        }
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        {
+            return Err(anyhow!("invalid {} name '{}'", what, name));
+        }
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_owned());
+        }
+    }
+    Ok(names)
+}
+
+/// The name of the file of settings for the project in its directory and
+/// below.
+pub const PROJECT_SETTINGS_FILE: &str = ".rustup.toml";
+
+/// The settings a project's `.rustup.toml` may set, as `rustup config`
+/// names them, besides `dist_server`.
+static PROJECT_KEYS: &[&str] = &[
+    "profile",
+    "auto_install_targets",
+    "auto_install_components",
+    "default_components",
+    "default_targets",
+    "network.mirrors",
+];
+
+/// The settings of a project's `.rustup.toml`, which take the place of the
+/// user's for commands run in the project. Only those in `PROJECT_KEYS` and
+/// `dist_server` can be set there.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProjectSettings {
+    pub path: PathBuf,
+    pub dist_server: Option<String>,
+    /// The settings, with lists joined by commas, as the
+    /// `RUSTUP_SETTINGS_*` variables give them.
+    values: Vec<(&'static str, String)>,
+    /// The keys rustup doesn't know, which are ignored.
+    pub unknown: Vec<String>,
+}
+
+impl ProjectSettings {
+    pub fn parse(path: &Path, data: &str) -> Result<Self> {
+        let what = || format!("invalid project settings in '{}'", path.display());
+        let mut table: toml::value::Table = toml::from_str(data).with_context(what)?;
+        let dist_server = get_opt_string(&mut table, "dist_server", "").with_context(what)?;
+        let mut values = Vec::new();
+        for &name in PROJECT_KEYS {
+            if let Some(value) = take_setting(&mut table, name) {
+                values.push((name, setting_value(name, value).with_context(what)?));
+            }
+        }
+        let mut unknown = Vec::new();
+        unknown_settings(table, "", &mut unknown);
+        Ok(Self {
+            path: path.to_owned(),
+            dist_server,
+            values,
+            unknown,
+        })
+    }
+
+    /// Fails if the project sets a setting the policy locks to another
+    /// value.
+    fn check_policy(&self, policy: &Policy) -> Result<()> {
+        let source = format!("'{}'", self.path.display());
+        for (name, value) in &self.values {
+            policy.check(&source, name, value)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&self, settings: &mut Settings) -> Result<()> {
+        for (name, value) in &self.values {
+            set_override(settings, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes the setting `name` from `table`, looking for one such as
+/// `network.mirrors` in the `network` table, which is dropped once empty.
+fn take_setting(table: &mut toml::value::Table, name: &str) -> Option<toml::Value> {
+    let mut parts = name.splitn(2, '.');
+    let (section, key) = match (parts.next(), parts.next()) {
+        (Some(section), Some(key)) => (section, key),
+        _ => return table.remove(name),
+    };
+    let inner = match table.get_mut(section) {
+        Some(toml::Value::Table(inner)) => inner,
+        _ => return None,
+    };
+    let value = inner.remove(key);
+    if inner.is_empty() {
+        table.remove(section);
+    }
+    value
+}
+
+/// Adds the names of the settings in `table`, with those in tables named as
+/// `rustup config` names them, to `names`.
+fn unknown_settings(table: toml::value::Table, prefix: &str, names: &mut Vec<String>) {
+    for (key, value) in table {
+        let full = format!("{}{}", prefix, key);
+        match value {
+            toml::Value::Table(inner) => unknown_settings(inner, &format!("{}.", full), names),
+            _ => names.push(full),
+        }
+    }
+}
+
+/// The value of the setting `name` as the `RUSTUP_SETTINGS_*` variables
+/// give it, from a string or a list of strings in a TOML file. It is checked
+/// now, so that a mistake is found whichever command is run.
+pub(crate) fn setting_value(name: &str, value: toml::Value) -> Result<String> {
+    let value = match value {
+        toml::Value::String(value) => value,
+        toml::Value::Array(list) => {
+            let list: Option<Vec<&str>> = list.iter().map(toml::Value::as_str).collect();
+            list.map(|list| list.join(","))
+                .ok_or_else(|| anyhow!("expected a list of strings for '{}'", name))?
+        }
+        toml::Value::Integer(value) => value.to_string(),
+        _ => return Err(anyhow!("expected a string for '{}'", name)),
+    };
+    set_override(&mut Settings::default(), name, &value)
+        .with_context(|| format!("invalid value '{}' for '{}'", value, name))?;
+    Ok(value)
+}
+
+/// The settings in `table` of a TOML file, named as `rustup config` names
+/// them, with those in tables within it, such as `[network]`, named with a
+/// dot, and with their values as `setting_value` gives them.
+pub(crate) fn settings_in(table: toml::value::Table) -> Result<Vec<(&'static str, String)>> {
+    fn add(
+        settings: &mut Vec<(&'static str, String)>,
+        table: toml::value::Table,
+        prefix: &str,
+    ) -> Result<()> {
+        for (key, value) in table {
+            let full = format!("{}{}", prefix, key);
+            if let toml::Value::Table(table) = value {
+                add(settings, table, &format!("{}.", full))?;
+                continue;
+            }
+            let name = ENV_OVERRIDES
+                .iter()
+                .map(|(n, _)| *n)
+                .find(|n| *n == full)
+                .ok_or_else(|| anyhow!("unknown setting '{}'", full))?;
+            settings.push((name, setting_value(name, value)?));
+        }
+        Ok(())
+    }
+
+    let mut settings = Vec::new();
+    add(&mut settings, table, "")?;
+    Ok(settings)
+}
+
+/// Sets `name`, one of `ENV_OVERRIDES`, to `value`.
+pub(crate) fn set_override(settings: &mut Settings, name: &str, value: &str) -> Result<()> {
+    let (_, set) = ENV_OVERRIDES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .expect("only the settings `rustup config` knows are set");
+    set(settings, value)
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct SettingsFile {
     path: PathBuf,
     cache: RefCell<Option<Settings>>,
+    /// The settings of the project the command is run in, if it has any.
+    project: RefCell<Option<ProjectSettings>>,
+    /// The machine's policy, whose locked settings take the place of all
+    /// others.
+    policy: Option<Policy>,
+    /// `cache` with the project's settings, the overrides from the
+    /// environment and the policy applied, which is what `with` shows;
+    /// `with_mut` changes only what is in the file.
+    overridden: RefCell<Option<Settings>>,
 }
 
 impl SettingsFile {
@@ -26,23 +386,80 @@ impl SettingsFile {
         Self {
             path,
             cache: RefCell::new(None),
+            project: RefCell::new(None),
+            policy: None,
+            overridden: RefCell::new(None),
         }
     }
 
+    pub(crate) fn set_policy(&mut self, policy: Option<Policy>) {
+        self.policy = policy;
+        *self.overridden.get_mut() = None;
+    }
+
+    pub(crate) fn policy(&self) -> Option<&Policy> {
+        self.policy.as_ref()
+    }
+
+    /// Uses `project`'s settings in place of the user's.
+    pub(crate) fn set_project(&self, project: ProjectSettings) {
+        *self.project.borrow_mut() = Some(project);
+        *self.overridden.borrow_mut() = None;
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replaces the file in one step, so that other processes never see it
+    /// half written. Where it can, only the keys which changed are
+    /// rewritten, so that the comments and layout of a file kept with
+    /// someone's dotfiles survive.
     fn write_settings(&self) -> Result<()> {
         let s = self.cache.borrow().as_ref().unwrap().clone();
-        utils::write_file("settings", &self.path, &s.stringify())?;
+        let original = if utils::is_file(&self.path) {
+            utils::read_file("settings", &self.path)?
+        } else {
+            String::new()
+        };
+        let mut temp = self.path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let content = toml_document::rewrite(&original, &s.into_toml());
+        utils::write_file("settings", &temp, &content)?;
+        utils::rename_file("settings", &temp, &self.path, &|_: Notification<'_>| {})?;
+        Ok(())
+    }
+
+    /// Keeps `content`, the settings as they were before being brought up
+    /// from schema `from`, beside them, unless an earlier upgrade from the
+    /// same schema already did.
+    fn back_up(&self, content: &str, from: i64) -> Result<()> {
+        let mut backup = self.path.as_os_str().to_owned();
+        backup.push(format!(".v{}.bak", from));
+        let backup = PathBuf::from(backup);
+        if !utils::is_file(&backup) {
+            utils::write_file("settings backup", &backup, content)?;
+        }
         Ok(())
     }
 
     fn read_settings(&self) -> Result<()> {
+        // Already read, perhaps by a caller of `with` further up the stack,
+        // which still holds them.
+        if self.cache.borrow().is_some() {
+            return Ok(());
+        }
         let mut needs_save = false;
+        let mut migrated = None;
         {
             let mut b = self.cache.borrow_mut();
             if b.is_none() {
                 *b = Some(if utils::is_file(&self.path) {
                     let content = utils::read_file("settings", &self.path)?;
-                    Settings::parse(&content)?
+                    let (settings, migrated_from) = Settings::parse_migrating(&content)?;
+                    migrated = migrated_from.map(|from| (content, from));
+                    settings
                 } else {
                     needs_save = true;
                     Default::default()
@@ -52,21 +469,66 @@ impl SettingsFile {
         if needs_save {
             self.write_settings()?;
         }
+        // Reading the settings mustn't fail because they can't be written,
+        // as where RUSTUP_HOME is shared with users who can't write to it:
+        // they are migrated again on each read instead.
+        if let Some((content, from)) = migrated {
+            let saved = self
+                .back_up(&content, from)
+                .and_then(|()| self.write_settings());
+            if let Err(e) = saved {
+                warn!("could not save the migrated settings: {:#}", e);
+            }
+        }
         Ok(())
     }
 
     pub fn with<T, F: FnOnce(&Settings) -> Result<T>>(&self, f: F) -> Result<T> {
         self.read_settings()?;
+        if self.overridden.borrow().is_none() {
+            // Settings can no longer be None so it's OK to unwrap
+            let mut settings = self.cache.borrow().as_ref().unwrap().clone();
+            if let Some(project) = &*self.project.borrow() {
+                if let Some(policy) = &self.policy {
+                    project.check_policy(policy)?;
+                }
+                project.apply(&mut settings)?;
+            }
+            settings.apply_env_overrides(self.policy.as_ref())?;
+            if let Some(policy) = &self.policy {
+                policy.apply(&mut settings)?;
+            }
+            *self.overridden.borrow_mut() = Some(settings);
+        }
 
-        // Settings can no longer be None so it's OK to unwrap
-        f(self.cache.borrow().as_ref().unwrap())
+        f(self.overridden.borrow().as_ref().unwrap())
     }
 
+    /// Changes the settings. Other rustup processes may have changed them
+    /// since they were read, so they're read afresh while holding a lock.
     pub fn with_mut<T, F: FnOnce(&mut Settings) -> Result<T>>(&self, f: F) -> Result<T> {
+        let mut lock_path = self.path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let _lock = FileLock::exclusive("settings", Path::new(&lock_path), &|_| {})?;
+        *self.cache.borrow_mut() = None;
+        *self.overridden.borrow_mut() = None;
         self.read_settings()?;
 
         // Settings can no longer be None so it's OK to unwrap
+        // Kept to find a change to a setting the policy locks.
+        let before = self
+            .policy
+            .as_ref()
+            .and_then(|_| self.cache.borrow().clone());
         let result = { f(self.cache.borrow_mut().as_mut().unwrap())? };
+        if let (Some(policy), Some(before)) = (&self.policy, &before) {
+            let checked = policy.check_change(before, self.cache.borrow().as_ref().unwrap());
+            if let Err(e) = checked {
+                // Forgotten, so that the change isn't written later.
+                *self.cache.borrow_mut() = None;
+                return Err(e);
+            }
+        }
         self.write_settings()?;
         Ok(result)
     }
@@ -75,24 +537,111 @@ impl SettingsFile {
 #[derive(Clone, Debug, PartialEq)]
 pub struct Settings {
     pub version: String,
+    /// The schema of the file, `SETTINGS_SCHEMA_VERSION` unless it was
+    /// written by a newer rustup.
+    pub schema_version: i64,
     pub default_host_triple: Option<String>,
     pub default_toolchain: Option<String>,
     pub profile: Option<Profile>,
     pub overrides: BTreeMap<String, String>,
+    /// Host triples to use in place of the default one within directories,
+    /// set with `rustup override host`.
+    pub host_overrides: BTreeMap<String, String>,
+    /// Host triples to use in place of the default one for toolchain names
+    /// which leave it out, such as `nightly`, set with `rustup override
+    /// host --toolchain`.
+    pub toolchain_host_overrides: BTreeMap<String, String>,
+    /// Alternative names for toolchains, set with `rustup toolchain alias`,
+    /// from the `toolchain_aliases` table.
+    pub toolchain_aliases: BTreeMap<String, String>,
+    /// Shorter names for rustup's own commands, such as `up = "update"`,
+    /// from the `aliases` table.
+    pub command_aliases: BTreeMap<String, String>,
+    /// Shell commands run before and after toolchains and components
+    /// change, by the names in `hooks::HOOKS`, from the `hooks` table.
+    pub hooks: BTreeMap<String, String>,
+    /// Environment variables to set for each toolchain's tools, set with
+    /// `rustup toolchain env`.
+    pub toolchain_env: BTreeMap<String, BTreeMap<String, String>>,
+    /// Toolchains which take some of their tools from other toolchains, set
+    /// with `rustup toolchain virtual`.
+    pub virtual_toolchains: BTreeMap<String, VirtualToolchain>,
+    /// Environment variables which toolchain files may use as `${VAR}`,
+    /// set with `rustup set toolchain-file-env`.
+    pub toolchain_file_env: Vec<String>,
+    /// Tools besides the built-in ones which are given proxies, added with
+    /// `rustup proxy add`.
+    pub proxies: Vec<String>,
+    /// The command the proxies run rustc through, such as `sccache`, set
+    /// with `rustup set rustc-wrapper`.
+    pub rustc_wrapper: Option<String>,
+    /// Wrappers for particular toolchains in place of `rustc_wrapper`, where
+    /// an empty one means none.
+    pub toolchain_rustc_wrapper: BTreeMap<String, String>,
+    /// Directories whose toolchain files may name a toolchain to run by
+    /// its path, set with `rustup trust`. `*` trusts every directory.
+    pub trusted_dirs: Vec<String>,
+    /// Components installed with every new toolchain on top of those of
+    /// the profile, set with `rustup set default-components`.
+    pub default_components: Vec<String>,
+    /// Targets installed with every new toolchain, set with
+    /// `rustup set default-targets`.
+    pub default_targets: Vec<String>,
     pub pgp_keys: Option<String>,
     pub auto_self_update: Option<SelfUpdateMode>,
+    pub auto_install_targets: Option<AutoInstallTargets>,
+    pub auto_install_components: Option<AutoInstallComponents>,
+    pub toolchain_file_search: Option<ToolchainFileSearch>,
+    pub shims: Option<ShimMode>,
+    pub color_theme: Option<ColorTheme>,
+    /// Whether the tools run are recorded for `rustup stats`, set with
+    /// `rustup set usage-stats`.
+    pub usage_stats: Option<UsageStats>,
+    pub network: NetworkSettings,
+    pub temp: TempSettings,
+    pub override_hook: OverrideHookSettings,
+    pub auto_update: AutoUpdateSettings,
+    /// Keys this rustup doesn't know, from a newer one, written back as
+    /// they were so that it still finds them.
+    pub unknown: toml::value::Table,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
             version: DEFAULT_METADATA_VERSION.to_owned(),
+            schema_version: SETTINGS_SCHEMA_VERSION,
             default_host_triple: None,
             default_toolchain: None,
             profile: Some(Profile::Default),
             overrides: BTreeMap::new(),
+            host_overrides: BTreeMap::new(),
+            toolchain_host_overrides: BTreeMap::new(),
+            toolchain_aliases: BTreeMap::new(),
+            command_aliases: BTreeMap::new(),
+            hooks: BTreeMap::new(),
+            toolchain_env: BTreeMap::new(),
+            virtual_toolchains: BTreeMap::new(),
+            toolchain_file_env: Vec::new(),
+            proxies: Vec::new(),
+            rustc_wrapper: None,
+            toolchain_rustc_wrapper: BTreeMap::new(),
+            trusted_dirs: Vec::new(),
+            default_components: Vec::new(),
+            default_targets: Vec::new(),
             pgp_keys: None,
             auto_self_update: None,
+            auto_install_targets: None,
+            auto_install_components: None,
+            toolchain_file_search: None,
+            shims: None,
+            color_theme: None,
+            usage_stats: None,
+            network: NetworkSettings::default(),
+            temp: TempSettings::default(),
+            override_hook: OverrideHookSettings::default(),
+            auto_update: AutoUpdateSettings::default(),
+            unknown: toml::value::Table::new(),
         }
     }
 }
@@ -137,9 +686,114 @@ impl Settings {
         self.overrides.get(&key).cloned()
     }
 
+    pub(crate) fn remove_host_override(
+        &mut self,
+        path: &Path,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> bool {
+        let key = Self::path_to_key(path, notify_handler);
+        self.host_overrides.remove(&key).is_some()
+    }
+
+    pub(crate) fn add_host_override(
+        &mut self,
+        path: &Path,
+        host_triple: String,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) {
+        let key = Self::path_to_key(path, notify_handler);
+        self.host_overrides.insert(key, host_triple);
+    }
+
+    pub(crate) fn dir_host_override(
+        &self,
+        dir: &Path,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> Option<String> {
+        let key = Self::path_to_key(dir, notify_handler);
+        self.host_overrides.get(&key).cloned()
+    }
+
+    /// Trusts `path` and everything in it, returning whether it wasn't
+    /// trusted already.
+    pub(crate) fn add_trusted_dir(
+        &mut self,
+        path: &Path,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> bool {
+        let key = if path == Path::new("*") {
+            "*".to_owned()
+        } else {
+            Self::path_to_key(path, notify_handler)
+        };
+        if self.trusted_dirs.contains(&key) {
+            return false;
+        }
+        self.trusted_dirs.push(key);
+        true
+    }
+
+    pub(crate) fn remove_trusted_dir(
+        &mut self,
+        path: &Path,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> bool {
+        let key = if path == Path::new("*") {
+            "*".to_owned()
+        } else {
+            Self::path_to_key(path, notify_handler)
+        };
+        let len = self.trusted_dirs.len();
+        self.trusted_dirs.retain(|dir| *dir != key);
+        self.trusted_dirs.len() != len
+    }
+
+    /// Whether `dir` is a trusted directory or inside one.
+    pub(crate) fn is_trusted(&self, dir: &Path, notify_handler: &dyn Fn(Notification<'_>)) -> bool {
+        let key = Self::path_to_key(dir, notify_handler);
+        self.trusted_dirs
+            .iter()
+            .any(|trusted| trusted == "*" || Path::new(&key).starts_with(trusted))
+    }
+
+    /// Whether toolchain files above the root of a repository are ignored.
+    pub(crate) fn stops_at_vcs_root(&self) -> bool {
+        self.toolchain_file_search == Some(ToolchainFileSearch::VcsRoot)
+    }
+
+    /// Applies the `RUSTUP_SETTINGS_*` environment variables which are set,
+    /// failing for one which would change a setting `policy` locks.
+    fn apply_env_overrides(&mut self, policy: Option<&Policy>) -> Result<()> {
+        for (name, set) in ENV_OVERRIDES {
+            let var = env_override_var(name);
+            if let Ok(value) = process().var(&var) {
+                if let Some(policy) = policy {
+                    policy.check(&var, name, &value)?;
+                }
+                set(self, &value)
+                    .with_context(|| format!("invalid value '{}' for {}", value, var))?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn parse(data: &str) -> Result<Self> {
-        let value = toml::from_str(data).context("error parsing settings")?;
-        Self::from_toml(value, "")
+        Ok(Self::parse_migrating(data)?.0)
+    }
+
+    /// Parses the settings, first bringing them up to the current schema,
+    /// and returns with them the schema they had if bringing them up
+    /// changed anything but the schema itself.
+    fn parse_migrating(data: &str) -> Result<(Self, Option<i64>)> {
+        let mut value: toml::value::Table =
+            toml::from_str(data).context("error parsing settings")?;
+        let mut before = value.clone();
+        let migrated_from = migrate(&mut value, MIGRATIONS)?;
+        before.remove("schema_version");
+        let mut after = value.clone();
+        after.remove("schema_version");
+        let migrated_from = migrated_from.filter(|_| before != after);
+        Ok((Self::from_toml(value, "")?, migrated_from))
     }
 
     pub fn stringify(self) -> String {
@@ -153,22 +807,105 @@ impl Settings {
         }
         let auto_self_update = get_opt_string(&mut table, "auto_self_update", path)?
             .and_then(|mode| SelfUpdateMode::from_str(mode.as_str()).ok());
+        let auto_install_targets = get_opt_string(&mut table, "auto_install_targets", path)?
+            .and_then(|mode| AutoInstallTargets::from_str(mode.as_str()).ok());
+        let auto_install_components = get_opt_string(&mut table, "auto_install_components", path)?
+            .and_then(|mode| AutoInstallComponents::from_str(mode.as_str()).ok());
+        let toolchain_file_search = get_opt_string(&mut table, "toolchain_file_search", path)?
+            .and_then(|mode| ToolchainFileSearch::from_str(mode.as_str()).ok());
+        let shims = get_opt_string(&mut table, "shims", path)?
+            .and_then(|mode| ShimMode::from_str(mode.as_str()).ok());
+        let color_theme = get_opt_string(&mut table, "color_theme", path)?
+            .and_then(|theme| ColorTheme::from_str(theme.as_str()).ok());
+        let usage_stats = get_opt_string(&mut table, "usage_stats", path)?
+            .and_then(|mode| UsageStats::from_str(mode.as_str()).ok());
         let profile = get_opt_string(&mut table, "profile", path)?
             .and_then(|p| Profile::from_str(p.as_str()).ok());
+        let schema_version = get_opt_integer(&mut table, "schema_version", path)?.unwrap_or(0);
         Ok(Self {
             version,
+            schema_version,
             default_host_triple: get_opt_string(&mut table, "default_host_triple", path)?,
             default_toolchain: get_opt_string(&mut table, "default_toolchain", path)?,
             profile,
-            overrides: Self::table_to_overrides(&mut table, path)?,
+            overrides: Self::table_to_strings(&mut table, "overrides", path)?,
+            host_overrides: Self::table_to_strings(&mut table, "host_overrides", path)?,
+            toolchain_host_overrides: Self::table_to_strings(
+                &mut table,
+                "toolchain_host_overrides",
+                path,
+            )?,
+            toolchain_aliases: Self::table_to_strings(&mut table, "toolchain_aliases", path)?,
+            command_aliases: Self::table_to_strings(&mut table, "aliases", path)?,
+            hooks: Self::table_to_strings(&mut table, "hooks", path)?,
+            toolchain_env: Self::table_to_env(&mut table, "toolchain_env", path)?,
+            virtual_toolchains: Self::table_to_virtual_toolchains(
+                &mut table,
+                "virtual_toolchains",
+                path,
+            )?,
+            toolchain_file_env: get_array(&mut table, "toolchain_file_env", path)?
+                .into_iter()
+                .filter_map(|v| v.as_str().map(str::to_owned))
+                .collect(),
+            proxies: get_array(&mut table, "proxies", path)?
+                .into_iter()
+                .filter_map(|v| v.as_str().map(str::to_owned))
+                .collect(),
+            rustc_wrapper: get_opt_string(&mut table, "rustc_wrapper", path)?,
+            toolchain_rustc_wrapper: Self::table_to_strings(
+                &mut table,
+                "toolchain_rustc_wrapper",
+                path,
+            )?,
+            trusted_dirs: get_array(&mut table, "trusted_dirs", path)?
+                .into_iter()
+                .filter_map(|v| v.as_str().map(str::to_owned))
+                .collect(),
+            default_components: get_array(&mut table, "default_components", path)?
+                .into_iter()
+                .filter_map(|v| v.as_str().map(str::to_owned))
+                .collect(),
+            default_targets: get_array(&mut table, "default_targets", path)?
+                .into_iter()
+                .filter_map(|v| v.as_str().map(str::to_owned))
+                .collect(),
             pgp_keys: get_opt_string(&mut table, "pgp_keys", path)?,
             auto_self_update,
+            auto_install_targets,
+            auto_install_components,
+            toolchain_file_search,
+            shims,
+            color_theme,
+            usage_stats,
+            network: NetworkSettings::from_toml(
+                get_table(&mut table, "network", path)?,
+                &(path.to_owned() + "network."),
+            )?,
+            temp: TempSettings::from_toml(
+                get_table(&mut table, "temp", path)?,
+                &(path.to_owned() + "temp."),
+            )?,
+            override_hook: OverrideHookSettings::from_toml(
+                get_table(&mut table, "override_hook", path)?,
+                &(path.to_owned() + "override_hook."),
+            )?,
+            auto_update: AutoUpdateSettings::from_toml(
+                get_table(&mut table, "auto_update", path)?,
+                &(path.to_owned() + "auto_update."),
+            )?,
+            // What's left once the known keys are taken out.
+            unknown: table,
         })
     }
     pub fn into_toml(self) -> toml::value::Table {
-        let mut result = toml::value::Table::new();
+        let mut result = self.unknown;
 
         result.insert("version".to_owned(), toml::Value::String(self.version));
+        result.insert(
+            "schema_version".to_owned(),
+            toml::Value::Integer(self.schema_version),
+        );
 
         if let Some(v) = self.default_host_triple {
             result.insert("default_host_triple".to_owned(), toml::Value::String(v));
@@ -193,18 +930,188 @@ impl Settings {
             );
         }
 
-        let overrides = Self::overrides_to_table(self.overrides);
+        if let Some(v) = self.auto_install_targets {
+            result.insert(
+                "auto_install_targets".to_owned(),
+                toml::Value::String(v.to_string()),
+            );
+        }
+
+        if let Some(v) = self.auto_install_components {
+            result.insert(
+                "auto_install_components".to_owned(),
+                toml::Value::String(v.to_string()),
+            );
+        }
+
+        if let Some(v) = self.toolchain_file_search {
+            result.insert(
+                "toolchain_file_search".to_owned(),
+                toml::Value::String(v.to_string()),
+            );
+        }
+
+        if let Some(v) = self.shims {
+            result.insert("shims".to_owned(), toml::Value::String(v.to_string()));
+        }
+
+        if let Some(v) = self.color_theme {
+            result.insert("color_theme".to_owned(), toml::Value::String(v.to_string()));
+        }
+
+        if let Some(v) = self.usage_stats {
+            result.insert("usage_stats".to_owned(), toml::Value::String(v.to_string()));
+        }
+
+        let network = self.network.into_toml();
+        if !network.is_empty() {
+            result.insert("network".to_owned(), toml::Value::Table(network));
+        }
+
+        let temp = self.temp.into_toml();
+        if !temp.is_empty() {
+            result.insert("temp".to_owned(), toml::Value::Table(temp));
+        }
+
+        let override_hook = self.override_hook.into_toml();
+        if !override_hook.is_empty() {
+            result.insert(
+                "override_hook".to_owned(),
+                toml::Value::Table(override_hook),
+            );
+        }
+
+        let auto_update = self.auto_update.into_toml();
+        if !auto_update.is_empty() {
+            result.insert("auto_update".to_owned(), toml::Value::Table(auto_update));
+        }
+
+        if !self.host_overrides.is_empty() {
+            let host_overrides = Self::strings_to_table(self.host_overrides);
+            result.insert(
+                "host_overrides".to_owned(),
+                toml::Value::Table(host_overrides),
+            );
+        }
+
+        if !self.toolchain_host_overrides.is_empty() {
+            let toolchain_host_overrides = Self::strings_to_table(self.toolchain_host_overrides);
+            result.insert(
+                "toolchain_host_overrides".to_owned(),
+                toml::Value::Table(toolchain_host_overrides),
+            );
+        }
+
+        if !self.toolchain_aliases.is_empty() {
+            let toolchain_aliases = Self::strings_to_table(self.toolchain_aliases);
+            result.insert(
+                "toolchain_aliases".to_owned(),
+                toml::Value::Table(toolchain_aliases),
+            );
+        }
+
+        if !self.command_aliases.is_empty() {
+            let aliases = Self::strings_to_table(self.command_aliases);
+            result.insert("aliases".to_owned(), toml::Value::Table(aliases));
+        }
+
+        if !self.hooks.is_empty() {
+            let hooks = Self::strings_to_table(self.hooks);
+            result.insert("hooks".to_owned(), toml::Value::Table(hooks));
+        }
+
+        if !self.toolchain_env.is_empty() {
+            let mut toolchain_env = toml::value::Table::new();
+            for (toolchain, vars) in self.toolchain_env {
+                let vars = Self::strings_to_table(vars);
+                toolchain_env.insert(toolchain, toml::Value::Table(vars));
+            }
+            result.insert(
+                "toolchain_env".to_owned(),
+                toml::Value::Table(toolchain_env),
+            );
+        }
+
+        if !self.virtual_toolchains.is_empty() {
+            let mut virtual_toolchains = toml::value::Table::new();
+            for (name, toolchain) in self.virtual_toolchains {
+                virtual_toolchains.insert(name, toml::Value::Table(toolchain.into_toml()));
+            }
+            result.insert(
+                "virtual_toolchains".to_owned(),
+                toml::Value::Table(virtual_toolchains),
+            );
+        }
+
+        if !self.toolchain_file_env.is_empty() {
+            let vars = self
+                .toolchain_file_env
+                .into_iter()
+                .map(toml::Value::String)
+                .collect();
+            result.insert("toolchain_file_env".to_owned(), toml::Value::Array(vars));
+        }
+
+        if !self.proxies.is_empty() {
+            let tools = self.proxies.into_iter().map(toml::Value::String).collect();
+            result.insert("proxies".to_owned(), toml::Value::Array(tools));
+        }
+
+        if let Some(v) = self.rustc_wrapper {
+            result.insert("rustc_wrapper".to_owned(), toml::Value::String(v));
+        }
+
+        if !self.toolchain_rustc_wrapper.is_empty() {
+            let wrappers = Self::strings_to_table(self.toolchain_rustc_wrapper);
+            result.insert(
+                "toolchain_rustc_wrapper".to_owned(),
+                toml::Value::Table(wrappers),
+            );
+        }
+
+        if !self.trusted_dirs.is_empty() {
+            let dirs = self
+                .trusted_dirs
+                .into_iter()
+                .map(toml::Value::String)
+                .collect();
+            result.insert("trusted_dirs".to_owned(), toml::Value::Array(dirs));
+        }
+
+        if !self.default_components.is_empty() {
+            let components = self
+                .default_components
+                .into_iter()
+                .map(toml::Value::String)
+                .collect();
+            result.insert(
+                "default_components".to_owned(),
+                toml::Value::Array(components),
+            );
+        }
+
+        if !self.default_targets.is_empty() {
+            let targets = self
+                .default_targets
+                .into_iter()
+                .map(toml::Value::String)
+                .collect();
+            result.insert("default_targets".to_owned(), toml::Value::Array(targets));
+        }
+
+        let overrides = Self::strings_to_table(self.overrides);
         result.insert("overrides".to_owned(), toml::Value::Table(overrides));
 
         result
     }
 
-    fn table_to_overrides(
+    fn table_to_strings(
         table: &mut toml::value::Table,
+        key: &str,
         path: &str,
     ) -> Result<BTreeMap<String, String>> {
         let mut result = BTreeMap::new();
-        let pkg_table = get_table(table, "overrides", path)?;
+        let pkg_table = get_table(table, key, path)?;
 
         for (k, v) in pkg_table {
             if let toml::Value::String(t) = v {
@@ -215,11 +1122,911 @@ impl Settings {
         Ok(result)
     }
 
-    fn overrides_to_table(overrides: BTreeMap<String, String>) -> toml::value::Table {
+    fn table_to_env(
+        table: &mut toml::value::Table,
+        key: &str,
+        path: &str,
+    ) -> Result<BTreeMap<String, BTreeMap<String, String>>> {
+        let mut result = BTreeMap::new();
+        let mut env_table = get_table(table, key, path)?;
+        let toolchains: Vec<String> = env_table.keys().cloned().collect();
+
+        for toolchain in toolchains {
+            let vars_path = format!("{}{}.", path, key);
+            let vars = Self::table_to_strings(&mut env_table, &toolchain, &vars_path)?;
+            if !vars.is_empty() {
+                result.insert(toolchain, vars);
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn table_to_virtual_toolchains(
+        table: &mut toml::value::Table,
+        key: &str,
+        path: &str,
+    ) -> Result<BTreeMap<String, VirtualToolchain>> {
+        let mut result = BTreeMap::new();
+        let virtual_table = get_table(table, key, path)?;
+
+        for (name, v) in virtual_table {
+            if let toml::Value::Table(t) = v {
+                let toolchain_path = format!("{}{}.{}.", path, key, name);
+                result.insert(name, VirtualToolchain::from_toml(t, &toolchain_path)?);
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn strings_to_table(strings: BTreeMap<String, String>) -> toml::value::Table {
         let mut result = toml::value::Table::new();
-        for (k, v) in overrides {
+        for (k, v) in strings {
             result.insert(k, toml::Value::String(v));
         }
         result
     }
 }
+
+/// A toolchain which is `base`, except that the tools in `tools` are run
+/// from the toolchains they map to. Tools can be named by their binary, such
+/// as `cargo-fmt`, or by the component that provides them, such as `rustfmt`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VirtualToolchain {
+    pub base: String,
+    pub tools: BTreeMap<String, String>,
+}
+
+impl VirtualToolchain {
+    fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
+        Ok(Self {
+            base: get_string(&mut table, "base", path)?,
+            tools: Settings::table_to_strings(&mut table, "tools", path)?,
+        })
+    }
+
+    fn into_toml(self) -> toml::value::Table {
+        let mut result = toml::value::Table::new();
+        result.insert("base".to_owned(), toml::Value::String(self.base));
+        let tools = Settings::strings_to_table(self.tools);
+        result.insert("tools".to_owned(), toml::Value::Table(tools));
+        result
+    }
+}
+
+/// What the proxies do when cargo or rustc is asked to build for a target
+/// whose standard library isn't installed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutoInstallTargets {
+    Enable,
+    Disable,
+    Prompt,
+}
+
+impl AutoInstallTargets {
+    pub fn modes() -> &'static [&'static str] {
+        &["enable", "disable", "prompt"]
+    }
+
+    pub fn default_mode() -> &'static str {
+        "prompt"
+    }
+}
+
+impl FromStr for AutoInstallTargets {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode {
+            "enable" => Ok(Self::Enable),
+            "disable" => Ok(Self::Disable),
+            "prompt" => Ok(Self::Prompt),
+            _ => Err(anyhow!(
+                "unknown auto-install-targets mode: '{}'; valid modes are {}",
+                mode,
+                Self::modes().join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AutoInstallTargets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Enable => "enable",
+            Self::Disable => "disable",
+            Self::Prompt => "prompt",
+        })
+    }
+}
+
+/// What the proxies do when run for a tool whose component isn't installed
+/// in the toolchain, such as `cargo fmt` without rustfmt.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutoInstallComponents {
+    Ask,
+    Always,
+    Never,
+}
+
+impl AutoInstallComponents {
+    pub fn modes() -> &'static [&'static str] {
+        &["ask", "always", "never"]
+    }
+
+    pub fn default_mode() -> &'static str {
+        "ask"
+    }
+}
+
+impl FromStr for AutoInstallComponents {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode {
+            "ask" => Ok(Self::Ask),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(anyhow!(
+                "unknown auto-install-components mode: '{}'; valid modes are {}",
+                mode,
+                Self::modes().join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AutoInstallComponents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Ask => "ask",
+            Self::Always => "always",
+            Self::Never => "never",
+        })
+    }
+}
+
+/// How far up from the current directory rustup looks for a toolchain file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToolchainFileSearch {
+    /// Up to the root of the filesystem.
+    Filesystem,
+    /// Up to the root of the repository, found by its `.git` or `.hg`.
+    VcsRoot,
+}
+
+impl ToolchainFileSearch {
+    pub fn modes() -> &'static [&'static str] {
+        &["filesystem", "vcs-root"]
+    }
+
+    pub fn default_mode() -> &'static str {
+        "filesystem"
+    }
+}
+
+impl FromStr for ToolchainFileSearch {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode {
+            "filesystem" => Ok(Self::Filesystem),
+            "vcs-root" => Ok(Self::VcsRoot),
+            _ => Err(anyhow!(
+                "unknown toolchain-file-search mode: '{}'; valid modes are {}",
+                mode,
+                Self::modes().join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ToolchainFileSearch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Filesystem => "filesystem",
+            Self::VcsRoot => "vcs-root",
+        })
+    }
+}
+
+/// The colors rustup uses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorTheme {
+    Default,
+    /// Without red and green to tell apart.
+    Colorblind,
+    /// Bold text, but no colors.
+    Monochrome,
+}
+
+impl ColorTheme {
+    pub fn themes() -> &'static [&'static str] {
+        &["default", "colorblind", "monochrome"]
+    }
+
+    pub fn default_theme() -> &'static str {
+        "default"
+    }
+}
+
+impl FromStr for ColorTheme {
+    type Err = anyhow::Error;
+
+    fn from_str(theme: &str) -> Result<Self> {
+        match theme {
+            "default" => Ok(Self::Default),
+            "colorblind" => Ok(Self::Colorblind),
+            "monochrome" => Ok(Self::Monochrome),
+            _ => Err(anyhow!(
+                "unknown color theme: '{}'; valid themes are {}",
+                theme,
+                Self::themes().join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColorTheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Default => "default",
+            Self::Colorblind => "colorblind",
+            Self::Monochrome => "monochrome",
+        })
+    }
+}
+
+/// Whether the tools the proxies run are recorded for `rustup stats`. Off
+/// unless turned on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UsageStats {
+    Enable,
+    Disable,
+}
+
+impl UsageStats {
+    pub fn modes() -> &'static [&'static str] {
+        &["enable", "disable"]
+    }
+
+    pub fn default_mode() -> &'static str {
+        "disable"
+    }
+}
+
+impl FromStr for UsageStats {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode {
+            "enable" => Ok(Self::Enable),
+            "disable" => Ok(Self::Disable),
+            _ => Err(anyhow!(
+                "unknown usage-stats mode: '{}'; valid modes are {}",
+                mode,
+                Self::modes().join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for UsageStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Enable => "enable",
+            Self::Disable => "disable",
+        })
+    }
+}
+
+/// What the tools in `CARGO_HOME/bin` are.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShimMode {
+    /// Links to rustup, which picks the toolchain each time they run.
+    Proxy,
+    /// Symlinks to the default toolchain's own binaries.
+    Symlink,
+}
+
+impl ShimMode {
+    pub fn modes() -> &'static [&'static str] {
+        &["proxy", "symlink"]
+    }
+
+    pub fn default_mode() -> &'static str {
+        "proxy"
+    }
+}
+
+impl FromStr for ShimMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode {
+            "proxy" => Ok(Self::Proxy),
+            "symlink" => Ok(Self::Symlink),
+            _ => Err(anyhow!(
+                "unknown shims mode: '{}'; valid modes are {}",
+                mode,
+                Self::modes().join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ShimMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Proxy => "proxy",
+            Self::Symlink => "symlink",
+        })
+    }
+}
+
+/// The `[network]` table of the settings file: how downloads connect,
+/// which the download layer is set up with once for the process.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NetworkSettings {
+    pub ip_version: Option<IpVersion>,
+    /// The proxy for every download, in place of those from the
+    /// environment.
+    pub proxy: Option<String>,
+    /// How many times to retry a component download which fails.
+    pub retries: Option<usize>,
+    /// How many seconds to wait for a server to connect, or to send more.
+    pub timeout: Option<u64>,
+    /// The most bytes a second to download at.
+    pub rate_limit: Option<u64>,
+    /// Dist servers to fall back on, in order, when a download from the
+    /// dist server fails.
+    pub mirrors: Vec<String>,
+    /// Whether to install from the manifests `rustup prefetch` saved,
+    /// rather than ask the dist server for newer ones.
+    pub offline: Option<bool>,
+    pub unknown: toml::value::Table,
+}
+
+impl NetworkSettings {
+    fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
+        let ip_version = get_opt_string(&mut table, "ip-version", path)?
+            .map(|v| IpVersion::from_str(v.as_str()))
+            .transpose()
+            .with_context(|| format!("invalid value for '{}ip-version'", path))?;
+        let proxy = get_opt_string(&mut table, "proxy", path)?;
+        let retries = get_opt_integer(&mut table, "retries", path)?.map(|n| n.max(0) as usize);
+        let timeout = get_opt_integer(&mut table, "timeout", path)?
+            .filter(|&n| n > 0)
+            .map(|n| n as u64);
+        let rate_limit = get_opt_integer(&mut table, "rate-limit", path)?
+            .filter(|&n| n > 0)
+            .map(|n| n as u64);
+        let mirrors = get_array(&mut table, "mirrors", path)?
+            .into_iter()
+            .filter_map(|v| v.as_str().map(str::to_owned))
+            .collect();
+        let offline = get_opt_bool(&mut table, "offline", path)?;
+        Ok(Self {
+            ip_version,
+            proxy,
+            retries,
+            timeout,
+            rate_limit,
+            mirrors,
+            offline,
+            unknown: table,
+        })
+    }
+
+    fn into_toml(self) -> toml::value::Table {
+        let mut result = self.unknown;
+        if let Some(v) = self.ip_version {
+            result.insert("ip-version".to_owned(), toml::Value::String(v.to_string()));
+        }
+        if let Some(v) = self.proxy {
+            result.insert("proxy".to_owned(), toml::Value::String(v));
+        }
+        if let Some(v) = self.retries {
+            result.insert("retries".to_owned(), toml::Value::Integer(v as i64));
+        }
+        if let Some(v) = self.timeout {
+            result.insert("timeout".to_owned(), toml::Value::Integer(v as i64));
+        }
+        if let Some(v) = self.rate_limit {
+            result.insert("rate-limit".to_owned(), toml::Value::Integer(v as i64));
+        }
+        if !self.mirrors.is_empty() {
+            let mirrors = self.mirrors.into_iter().map(toml::Value::String).collect();
+            result.insert("mirrors".to_owned(), toml::Value::Array(mirrors));
+        }
+        if let Some(v) = self.offline {
+            result.insert("offline".to_owned(), toml::Value::Boolean(v));
+        }
+        result
+    }
+
+    /// What the download layer is set up with.
+    pub(crate) fn options(&self) -> Result<download::NetworkOptions> {
+        let defaults = download::NetworkOptions::default();
+        Ok(download::NetworkOptions {
+            ip_version: self.ip_version.unwrap_or(defaults.ip_version),
+            proxy: match &self.proxy {
+                Some(proxy) => Some(
+                    utils::parse_url(proxy)
+                        .with_context(|| format!("invalid proxy '{}' in the settings", proxy))?,
+                ),
+                None => None,
+            },
+            timeout: self
+                .timeout
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.timeout),
+            rate_limit: self.rate_limit,
+        })
+    }
+}
+
+fn parse_proxy(value: &str) -> Result<String> {
+    utils::parse_url(value).map_err(|_| anyhow!("expected a URL, such as http://proxy:3128"))?;
+    Ok(value.to_owned())
+}
+
+fn parse_positive(value: &str, unit: &str) -> Result<u64> {
+    match u64::from_str(value) {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(anyhow!("expected a number of {} above 0", unit)),
+    }
+}
+
+/// The dist servers in `list`, separated by commas or whitespace.
+pub(crate) fn parse_mirrors(list: &str) -> Result<Vec<String>> {
+    let mut mirrors = Vec::new();
+    for mirror in list.split(|c: char| c == ',' || c.is_whitespace()) {
+        if mirror.is_empty() {
+            continue;
+        }
+        utils::parse_url(mirror).map_err(|_| anyhow!("expected URLs, not '{}'", mirror))?;
+        mirrors.push(mirror.trim_end_matches('/').to_owned());
+    }
+    Ok(mirrors)
+}
+
+/// How long temporary files left behind by an install which didn't finish
+/// are kept, unless the settings say otherwise.
+pub const DEFAULT_TEMP_MAX_AGE_HOURS: u64 = 24;
+
+/// The `[temp]` table of the settings file: where temporary files go, and
+/// when those left behind are removed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TempSettings {
+    /// The directory to keep them in, in place of the cache directory, such
+    /// as to keep them off a small tmpfs. They go in a `rustup` directory
+    /// inside it, since rustup empties its own.
+    pub dir: Option<PathBuf>,
+    /// How many hours old the ones left behind are before they are removed,
+    /// with 0 keeping them.
+    pub max_age: Option<u64>,
+    pub unknown: toml::value::Table,
+}
+
+impl TempSettings {
+    fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
+        let dir = get_opt_string(&mut table, "dir", path)?.map(PathBuf::from);
+        let max_age = get_opt_integer(&mut table, "max-age", path)?.map(|n| n.max(0) as u64);
+        Ok(Self {
+            dir,
+            max_age,
+            unknown: table,
+        })
+    }
+
+    fn into_toml(self) -> toml::value::Table {
+        let mut result = self.unknown;
+        if let Some(v) = self.dir {
+            result.insert(
+                "dir".to_owned(),
+                toml::Value::String(v.display().to_string()),
+            );
+        }
+        if let Some(v) = self.max_age {
+            // Beyond what TOML can hold is as good as forever.
+            let v = i64::try_from(v).unwrap_or(i64::MAX);
+            result.insert("max-age".to_owned(), toml::Value::Integer(v));
+        }
+        result
+    }
+
+    /// How long temporary files are kept before they count as left behind,
+    /// or `None` if they are kept however old they are.
+    pub fn max_age(&self) -> Option<Duration> {
+        match self.max_age.unwrap_or(DEFAULT_TEMP_MAX_AGE_HOURS) {
+            0 => None,
+            hours => Some(Duration::from_secs(hours.saturating_mul(60 * 60))),
+        }
+    }
+}
+
+fn parse_temp_dir(value: &str) -> Result<PathBuf> {
+    let dir = PathBuf::from(value);
+    if !dir.is_absolute() {
+        return Err(anyhow!("expected an absolute path"));
+    }
+    Ok(dir)
+}
+
+/// The `[override_hook]` table of the settings file: a command which is
+/// asked for the toolchain to use in a directory, set with `rustup set
+/// override-hook`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OverrideHookSettings {
+    /// The program and its arguments. The directory is added to them.
+    pub command: Vec<String>,
+    /// How many seconds its answer for a directory is used before it is
+    /// asked again.
+    pub ttl: Option<u64>,
+    pub unknown: toml::value::Table,
+}
+
+impl OverrideHookSettings {
+    fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
+        let command = get_array(&mut table, "command", path)?
+            .into_iter()
+            .filter_map(|v| v.as_str().map(str::to_owned))
+            .collect();
+        let ttl = get_opt_integer(&mut table, "ttl", path)?.map(|ttl| ttl.max(0) as u64);
+        Ok(Self {
+            command,
+            ttl,
+            unknown: table,
+        })
+    }
+
+    fn into_toml(self) -> toml::value::Table {
+        let mut result = self.unknown;
+        if !self.command.is_empty() {
+            let command = self.command.into_iter().map(toml::Value::String).collect();
+            result.insert("command".to_owned(), toml::Value::Array(command));
+        }
+        if let Some(ttl) = self.ttl {
+            result.insert("ttl".to_owned(), toml::Value::Integer(ttl as i64));
+        }
+        result
+    }
+}
+
+/// The `[auto_update]` table of the settings file: how often an ordinary
+/// `rustup` command checks for updates in the background, and what is done
+/// with those to the channels. Those to rustup itself follow
+/// `auto_self_update`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AutoUpdateSettings {
+    /// How many days go by between checks, with 0, as when it isn't set,
+    /// never checking.
+    pub interval: Option<u64>,
+    /// Whether updates to the installed channels are installed, only
+    /// reported, or not looked for, in the modes of `auto_self_update`.
+    pub channels: Option<SelfUpdateMode>,
+    pub unknown: toml::value::Table,
+}
+
+impl AutoUpdateSettings {
+    fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
+        let interval = get_opt_integer(&mut table, "interval", path)?.map(|n| n.max(0) as u64);
+        let channels = get_opt_string(&mut table, "channels", path)?
+            .and_then(|mode| SelfUpdateMode::from_str(mode.as_str()).ok());
+        Ok(Self {
+            interval,
+            channels,
+            unknown: table,
+        })
+    }
+
+    fn into_toml(self) -> toml::value::Table {
+        let mut result = self.unknown;
+        if let Some(v) = self.interval {
+            let v = i64::try_from(v).unwrap_or(i64::MAX);
+            result.insert("interval".to_owned(), toml::Value::Integer(v));
+        }
+        if let Some(v) = self.channels {
+            result.insert("channels".to_owned(), toml::Value::String(v.to_string()));
+        }
+        result
+    }
+
+    /// How long to wait between checks, or `None` if they are off.
+    pub fn interval(&self) -> Option<Duration> {
+        match self.interval.unwrap_or(0) {
+            0 => None,
+            days => Some(Duration::from_secs(days.saturating_mul(24 * 60 * 60))),
+        }
+    }
+
+    /// What is done with updates to the channels: by default they are only
+    /// reported.
+    pub fn channels(&self) -> SelfUpdateMode {
+        self.channels.clone().unwrap_or(SelfUpdateMode::CheckOnly)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename_default(table: &mut toml::value::Table) -> Result<()> {
+        if let Some(v) = table.remove("default") {
+            table.insert("default_toolchain".to_owned(), v);
+        }
+        Ok(())
+    }
+
+    fn drop_legacy(table: &mut toml::value::Table) -> Result<()> {
+        table.remove("legacy");
+        Ok(())
+    }
+
+    static TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            to: 1,
+            what: "record the schema version",
+            apply: |_| Ok(()),
+        },
+        Migration {
+            to: 2,
+            what: "rename default",
+            apply: rename_default,
+        },
+        Migration {
+            to: 3,
+            what: "drop legacy",
+            apply: drop_legacy,
+        },
+    ];
+
+    fn table(data: &str) -> toml::value::Table {
+        toml::from_str(data).unwrap()
+    }
+
+    #[test]
+    fn migrate_from_each_schema() {
+        let mut t = table("version = \"12\"\ndefault = \"stable\"\nlegacy = true\n");
+        assert_eq!(migrate(&mut t, TEST_MIGRATIONS).unwrap(), Some(0));
+        assert_eq!(
+            t,
+            table("version = \"12\"\ndefault_toolchain = \"stable\"\nschema_version = 3\n")
+        );
+
+        // Only the migrations past the file's schema are applied.
+        let mut t =
+            table("version = \"12\"\nschema_version = 2\ndefault = \"kept\"\nlegacy = true\n");
+        assert_eq!(migrate(&mut t, TEST_MIGRATIONS).unwrap(), Some(2));
+        assert_eq!(
+            t,
+            table("version = \"12\"\nschema_version = 3\ndefault = \"kept\"\n")
+        );
+
+        let mut t = table("version = \"12\"\nschema_version = 3\n");
+        assert_eq!(migrate(&mut t, TEST_MIGRATIONS).unwrap(), None);
+    }
+
+    #[test]
+    fn migrate_leaves_newer_schema() {
+        let mut t = table("version = \"12\"\nschema_version = 7\ndefault = \"stable\"\n");
+        assert_eq!(migrate(&mut t, TEST_MIGRATIONS).unwrap(), None);
+        assert_eq!(
+            t,
+            table("version = \"12\"\nschema_version = 7\ndefault = \"stable\"\n")
+        );
+    }
+
+    #[test]
+    fn failed_migration_names_schema() {
+        static FAILING: &[Migration] = &[Migration {
+            to: 1,
+            what: "fail",
+            apply: |_| Err(anyhow!("nope")),
+        }];
+        let mut t = table("version = \"12\"\n");
+        let e = migrate(&mut t, FAILING).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "could not bring the settings up to schema 1: fail"
+        );
+    }
+
+    #[test]
+    fn parse_stamps_schema_version() {
+        // Nothing but the schema changes, so there is nothing to save.
+        let (settings, from) = Settings::parse_migrating("version = \"12\"\n").unwrap();
+        assert_eq!(from, None);
+        assert_eq!(settings.schema_version, SETTINGS_SCHEMA_VERSION);
+        assert_eq!(
+            Settings::parse_migrating(&settings.stringify()).unwrap().1,
+            None
+        );
+    }
+
+    #[test]
+    fn env_override_var_names() {
+        assert_eq!(env_override_var("profile"), "RUSTUP_SETTINGS_PROFILE");
+        assert_eq!(
+            env_override_var("auto_self_update"),
+            "RUSTUP_SETTINGS_AUTO_SELF_UPDATE"
+        );
+        assert_eq!(
+            env_override_var("network.ip-version"),
+            "RUSTUP_SETTINGS_NETWORK_IP_VERSION"
+        );
+        assert_eq!(
+            env_override_var("network.rate-limit"),
+            "RUSTUP_SETTINGS_NETWORK_RATE_LIMIT"
+        );
+    }
+
+    #[test]
+    fn alias_tables() {
+        let settings = Settings::parse(
+            "version = \"12\"\n[toolchain_aliases]\nmine = \"beta\"\n[aliases]\nup = \"update\"\n",
+        )
+        .unwrap();
+        assert_eq!(settings.toolchain_aliases["mine"], "beta");
+        assert_eq!(settings.command_aliases["up"], "update");
+        let written = settings.into_toml();
+        assert_eq!(written["toolchain_aliases"]["mine"].as_str(), Some("beta"));
+        assert_eq!(written["aliases"]["up"].as_str(), Some("update"));
+    }
+
+    #[test]
+    fn network_settings() {
+        let settings = Settings::parse(
+            "version = \"12\"\n[network]\nproxy = \"http://proxy:3128\"\nretries = 5\ntimeout = 60\nrate-limit = 1000000\nmirrors = [\"https://mirror.example\"]\n",
+        )
+        .unwrap();
+        assert_eq!(settings.network.retries, Some(5));
+        assert_eq!(settings.network.mirrors, vec!["https://mirror.example"]);
+        let options = settings.network.options().unwrap();
+        assert_eq!(options.proxy.unwrap().as_str(), "http://proxy:3128/");
+        assert_eq!(options.timeout, Duration::from_secs(60));
+        assert_eq!(options.rate_limit, Some(1_000_000));
+        assert_eq!(
+            Settings::parse(&settings.clone().stringify()).unwrap(),
+            settings
+        );
+        let e = Settings::parse("version = \"12\"\n[network]\nip-version = \"4\"\n").unwrap_err();
+        assert_eq!(e.to_string(), "invalid value for 'network.ip-version'");
+
+        let mut settings = Settings::default();
+        assert!(set_override(&mut settings, "network.timeout", "0").is_err());
+        assert!(set_override(&mut settings, "network.mirrors", "not a url").is_err());
+        set_override(
+            &mut settings,
+            "network.mirrors",
+            "https://a.example/, https://b.example",
+        )
+        .unwrap();
+        assert_eq!(
+            settings.network.mirrors,
+            vec!["https://a.example", "https://b.example"]
+        );
+    }
+
+    #[test]
+    fn huge_ages_do_not_overflow() {
+        let mut settings = Settings::default();
+        let huge = u64::MAX.to_string();
+        set_override(&mut settings, "temp.max-age", &huge).unwrap();
+        set_override(&mut settings, "auto_update.interval", &huge).unwrap();
+        assert_eq!(settings.temp.max_age(), Some(Duration::from_secs(u64::MAX)));
+        assert_eq!(
+            settings.auto_update.interval(),
+            Some(Duration::from_secs(u64::MAX))
+        );
+        // They are kept rather than wrapping round to nothing.
+        let settings = Settings::parse(&settings.stringify()).unwrap();
+        assert_eq!(settings.temp.max_age(), Some(Duration::from_secs(u64::MAX)));
+        assert_eq!(
+            settings.auto_update.interval(),
+            Some(Duration::from_secs(u64::MAX))
+        );
+    }
+
+    #[test]
+    fn name_lists() {
+        assert_eq!(
+            parse_name_list("clippy, rust-src clippy", "component").unwrap(),
+            vec!["clippy", "rust-src"]
+        );
+        assert!(parse_name_list("", "component").unwrap().is_empty());
+        assert_eq!(
+            parse_name_list("clippy;rm", "component")
+                .unwrap_err()
+                .to_string(),
+            "invalid component name 'clippy;rm'"
+        );
+        assert_eq!(
+            parse_name_list(
+                "wasm32-unknown-unknown,aarch64-unknown-linux-musl",
+                "target"
+            )
+            .unwrap(),
+            vec!["wasm32-unknown-unknown", "aarch64-unknown-linux-musl"]
+        );
+    }
+
+    #[test]
+    fn project_settings() {
+        let path = Path::new("/project/.rustup.toml");
+        let project = ProjectSettings::parse(
+            path,
+            "dist_server = 'https://mirror.example'\ndefault_targets = ['wasm32-unknown-unknown', 'aarch64-unknown-linux-musl']\nauto_install_components = 'always'\ndefault_toolchain = 'nightly'\n",
+        )
+        .unwrap();
+        assert_eq!(
+            project.dist_server.as_deref(),
+            Some("https://mirror.example")
+        );
+        assert_eq!(project.unknown, vec!["default_toolchain"]);
+        let mut settings = Settings::default();
+        project.apply(&mut settings).unwrap();
+        assert_eq!(
+            settings.default_targets,
+            vec!["wasm32-unknown-unknown", "aarch64-unknown-linux-musl"]
+        );
+        assert_eq!(
+            settings.auto_install_components,
+            Some(AutoInstallComponents::Always)
+        );
+        assert_eq!(settings.default_toolchain, None);
+
+        assert!(ProjectSettings::parse(path, "default_targets = [1]").is_err());
+        assert!(ProjectSettings::parse(path, "profile = 'tiny'").is_err());
+
+        let project = ProjectSettings::parse(
+            path,
+            "[network]\nmirrors = ['https://mirror.example/']\nproxy = 'http://proxy:3128'\n",
+        )
+        .unwrap();
+        assert_eq!(project.unknown, vec!["network.proxy"]);
+        let mut settings = Settings::default();
+        project.apply(&mut settings).unwrap();
+        assert_eq!(settings.network.mirrors, vec!["https://mirror.example"]);
+        assert_eq!(settings.network.proxy, None);
+    }
+
+    #[test]
+    fn unknown_keys_round_trip() {
+        let data = r#"version = "12"
+schema_version = 9
+default_toolchain = "stable"
+future = "value"
+
+[network]
+ip-version = "v6"
+future = 3
+
+[override_hook]
+ttl = 60
+future = true
+
+[future_table]
+key = "value"
+"#;
+        let settings = Settings::parse(data).unwrap();
+        assert_eq!(settings.schema_version, 9);
+        assert_eq!(settings.default_toolchain.as_deref(), Some("stable"));
+        assert!(settings.unknown.contains_key("future"));
+        assert!(settings.network.unknown.contains_key("future"));
+        assert!(settings.override_hook.unknown.contains_key("future"));
+
+        let written = table(&settings.clone().stringify());
+        assert_eq!(written["future"].as_str(), Some("value"));
+        assert_eq!(written["future_table"]["key"].as_str(), Some("value"));
+        assert_eq!(written["network"]["future"].as_integer(), Some(3));
+        assert_eq!(written["override_hook"]["future"].as_bool(), Some(true));
+        assert_eq!(written["schema_version"].as_integer(), Some(9));
+        assert_eq!(
+            Settings::parse(&settings.clone().stringify()).unwrap(),
+            settings
+        );
+    }
+}
@@ -6,7 +6,7 @@ use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, bail, Context, Result};
 use thiserror::Error as ThisError;
@@ -14,16 +14,19 @@ use wait_timeout::ChildExt;
 
 use crate::component_for_bin;
 use crate::config::Cfg;
-use crate::dist::dist::Profile;
+use crate::dist::component::{Components, Transaction};
 use crate::dist::dist::TargetTriple;
 use crate::dist::dist::ToolchainDesc;
+use crate::dist::dist::{self, PartialToolchainDesc, Profile, VerifiedManifest};
 use crate::dist::download::DownloadCfg;
 use crate::dist::manifest::Component;
 use crate::dist::manifest::Manifest;
-use crate::dist::manifestation::{Changes, Manifestation};
+use crate::dist::manifestation::{Changes, Manifestation, UpdatePlan, DIST_MANIFEST};
 use crate::dist::prefix::InstallPrefix;
+use crate::dist::toolchain_archive;
 use crate::env_var;
 use crate::errors::*;
+use crate::hooks;
 use crate::install::{self, InstallMethod};
 use crate::notifications::*;
 use crate::process;
@@ -45,6 +48,8 @@ enum InstalledPath<'a> {
 pub struct Toolchain<'a> {
     cfg: &'a Cfg,
     name: String,
+    /// The virtual toolchain this was reached through, if any.
+    virtual_name: Option<String>,
     path: PathBuf,
     dist_handler: Box<dyn Fn(crate::dist::Notification<'_>) + 'a>,
 }
@@ -68,16 +73,35 @@ static V1_COMMON_COMPONENT_LIST: &[&str] = &["cargo", "rustc", "rust-docs"];
 
 impl<'a> Toolchain<'a> {
     pub fn from(cfg: &'a Cfg, name: &str) -> Result<Self> {
-        let resolved_name = cfg.resolve_toolchain(name)?;
-        let path = cfg.toolchains_dir.join(&resolved_name);
+        // A virtual toolchain is its base, except for the tools it takes
+        // from elsewhere.
+        let (virtual_name, name) = match cfg.get_virtual_toolchain(name)? {
+            Some(toolchain) => (Some(name.to_owned()), toolchain.base),
+            None => (None, name.to_owned()),
+        };
+        let resolved_name = cfg.resolve_toolchain(&name)?;
+        let path = cfg.toolchain_path(&resolved_name);
         Ok(Toolchain {
             cfg,
             name: resolved_name,
+            virtual_name,
             path,
             dist_handler: Box::new(move |n| (cfg.notify_handler)(n.into())),
         })
     }
 
+    /// The toolchain installed as `name` itself, without resolving it as a
+    /// channel, alias or virtual toolchain.
+    pub(crate) fn named(cfg: &'a Cfg, name: &str) -> Self {
+        Toolchain {
+            cfg,
+            name: name.to_owned(),
+            virtual_name: None,
+            path: cfg.toolchain_path(name),
+            dist_handler: Box::new(move |n| (cfg.notify_handler)(n.into())),
+        }
+    }
+
     pub fn from_path(
         cfg: &'a Cfg,
         cfg_file: Option<impl AsRef<Path>>,
@@ -105,6 +129,7 @@ impl<'a> Toolchain<'a> {
                 .to_str()
                 .ok_or_else(|| anyhow!(InvalidToolchainPath(path.clone())))?
                 .to_owned(),
+            virtual_name: None,
             path,
             dist_handler: Box::new(move |n| (cfg.notify_handler)(n.into())),
         })
@@ -136,6 +161,41 @@ impl<'a> Toolchain<'a> {
     pub fn path(&self) -> &Path {
         &self.path
     }
+    /// The name of the virtual toolchain this was reached through, if any.
+    pub fn virtual_name(&self) -> Option<&str> {
+        self.virtual_name.as_deref()
+    }
+    /// The name to record when this is made the default or an override, and
+    /// to give its tools in RUSTUP_TOOLCHAIN: a virtual toolchain's own name,
+    /// so that whatever runs next also takes tools from the right toolchain.
+    pub(crate) fn requested_name(&self) -> &str {
+        self.virtual_name.as_deref().unwrap_or(&self.name)
+    }
+    /// The toolchain that `binary` is run from, when this was reached through
+    /// a virtual toolchain that takes it from another; `None` if it is run
+    /// from this one.
+    pub fn for_tool(&self, binary: &str) -> Result<Option<Toolchain<'a>>> {
+        let virtual_name = match &self.virtual_name {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let tools = match self.cfg.get_virtual_toolchain(virtual_name)? {
+            Some(toolchain) => toolchain.tools,
+            None => return Ok(None),
+        };
+        let tool = binary.strip_suffix(EXE_SUFFIX).unwrap_or(binary);
+        let source = tools
+            .get(tool)
+            .or_else(|| component_for_bin(binary).and_then(|c| tools.get(c)));
+        match source {
+            Some(source) => {
+                let mut toolchain = Toolchain::from(self.cfg, source)?;
+                toolchain.virtual_name = self.virtual_name.clone();
+                Ok(Some(toolchain))
+            }
+            None => Ok(None),
+        }
+    }
     fn is_symlink(&self) -> bool {
         use std::fs;
         fs::symlink_metadata(&self.path)
@@ -162,8 +222,28 @@ impl<'a> Toolchain<'a> {
     pub fn verify(&self) -> Result<()> {
         utils::assert_is_directory(&self.path)
     }
+    /// Is this toolchain one of the system's, which rustup mustn't change.
+    pub fn is_system(&self) -> bool {
+        match &self.cfg.system_toolchains_dir {
+            Some(dir) => self.path.starts_with(dir),
+            None => false,
+        }
+    }
+    fn ensure_not_system(&self) -> Result<()> {
+        if self.is_system() {
+            return Err(RustupError::SystemToolchainReadOnly(self.name.clone()).into());
+        }
+        Ok(())
+    }
     // Custom and Distributable. Installed only.
     pub fn remove(&self) -> Result<()> {
+        self.ensure_not_system()?;
+        let _lock = self.cfg.lock_toolchain(&self.name)?;
+        self.remove_files()
+    }
+
+    /// Removes the toolchain, for a caller that already holds its lock.
+    pub(crate) fn remove_files(&self) -> Result<()> {
         if self.exists() || self.is_symlink() {
             (self.cfg.notify_handler)(Notification::UninstallingToolchain(&self.name));
         } else {
@@ -182,7 +262,7 @@ impl<'a> Toolchain<'a> {
         if !self.exists() {
             (self.cfg.notify_handler)(Notification::UninstalledToolchain(&self.name));
         }
-        Ok(())
+        self.cfg.mark_complete(&self.name)
     }
 
     // Custom only
@@ -223,15 +303,89 @@ impl<'a> Toolchain<'a> {
     }
     // Custom and Distributable. Installed only.
     pub fn make_default(&self) -> Result<()> {
-        self.cfg.set_default(&self.name)
+        self.cfg.set_default(self.requested_name())
     }
     // Custom and Distributable. Installed only.
     pub fn make_override(&self, path: &Path) -> Result<()> {
         self.cfg.settings_file.with_mut(|s| {
-            s.add_override(path, self.name.clone(), self.cfg.notify_handler.as_ref());
+            s.add_override(
+                path,
+                self.requested_name().to_owned(),
+                self.cfg.notify_handler.as_ref(),
+            );
             Ok(())
         })
     }
+    // Custom and Distributable. Installed only.
+    /// When the toolchain was last installed or updated.
+    pub fn installed_at(&self) -> Result<SystemTime> {
+        use std::fs;
+        let manifest = InstallPrefix::from(self.path.clone()).manifest_file(DIST_MANIFEST);
+        let path = if utils::is_file(&manifest) {
+            manifest
+        } else {
+            self.path.clone()
+        };
+        fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .with_context(|| RustupError::ReadingFile {
+                name: "toolchain",
+                path,
+            })
+    }
+
+    // Custom and Distributable. Installed only.
+    /// When the toolchain was last run through a proxy or `rustup run`, or
+    /// installed or updated, whichever is later.
+    pub fn last_used(&self) -> Result<SystemTime> {
+        let installed = self.installed_at()?;
+        Ok(match self.recorded_use() {
+            Some(used) => used.max(installed),
+            None => installed,
+        })
+    }
+
+    // Custom and Distributable. Installed only.
+    /// Records that the toolchain is about to be run, for `last_used`.
+    /// Proxies do this on every invocation, so the record is only rewritten
+    /// once it is an hour old, and failing to write it is ignored.
+    pub fn mark_used(&self) {
+        const DEBOUNCE: Duration = Duration::from_secs(60 * 60);
+
+        let now = SystemTime::now();
+        if !self.exists() {
+            return;
+        }
+        if let Some(used) = self.recorded_use() {
+            if matches!(now.duration_since(used), Ok(age) if age < DEBOUNCE) {
+                return;
+            }
+        }
+        let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        if let Some(path) = self.last_used_file(true) {
+            let _ = utils::write_file("last used", &path, &secs.to_string());
+        }
+    }
+
+    fn recorded_use(&self) -> Option<SystemTime> {
+        let path = self.last_used_file(false)?;
+        let secs = utils::read_file("last used", &path)
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+        Some(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Where the time of last use is recorded. Toolchains run straight from
+    /// a path rather than from the toolchains directory have no record.
+    fn last_used_file(&self, create_parent: bool) -> Option<PathBuf> {
+        if self.path != self.cfg.toolchains_dir.join(&self.name) {
+            return None;
+        }
+        self.cfg.get_last_used_file(&self.name, create_parent).ok()
+    }
+
     // Distributable and Custom. Installed only.
     pub fn binary_file(&self, name: &str) -> PathBuf {
         let mut path = self.path.clone();
@@ -344,49 +498,90 @@ impl<'a> InstalledCommonToolchain<'a> {
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(0);
             if recursion_count > env_var::RUST_RECURSION_COUNT_MAX - 1 {
-                let binary_lossy: String = binary.to_string_lossy().into();
-                if let Ok(distributable) = DistributableToolchain::new(self.0) {
-                    if let (Some(component_name), Ok(component_statuses), Ok(Some(manifest))) = (
-                        component_for_bin(&binary_lossy),
-                        distributable.list_components(),
-                        distributable.get_manifest(),
-                    ) {
-                        let component_status = component_statuses
-                            .iter()
-                            .find(|cs| cs.component.short_name(&manifest) == component_name)
-                            .unwrap_or_else(|| {
-                                panic!("component {} should be in the manifest", component_name)
-                            });
-                        if !component_status.available {
-                            return Err(anyhow!(format!(
-                                "the '{}' component which provides the command '{}' is not available for the '{}' toolchain", component_status.component.short_name(&manifest), binary_lossy, self.0.name)));
-                        }
-                        if component_status.installed {
-                            return Err(anyhow!(format!(
-                                "the '{}' binary, normally provided by the '{}' component, is not applicable to the '{}' toolchain", binary_lossy, component_status.component.short_name(&manifest), self.0.name)));
-                        }
-                    }
-                }
-                let defaults = self.0.cfg.get_default()?;
-                return Err(anyhow!(format!(
-                    "'{}' is not installed for the toolchain '{}'{}",
-                    binary.to_string_lossy(),
-                    self.0.name,
-                    install_msg(
-                        &binary.to_string_lossy(),
-                        &self.0.name,
-                        Some(&self.0.name) == defaults.as_ref()
-                    )
-                )));
+                return Err(self.missing_binary_error(&binary));
             }
             Path::new(&binary)
         };
-        let mut cmd = Command::new(&path);
-        self.set_env(&mut cmd);
+
+        // rustc is run through the wrapper set for the toolchain, if there
+        // is one, unless cargo is already running it through its own.
+        let wrapper = if Path::new(&binary).file_stem() == Some("rustc".as_ref())
+            && !matches!(process().var_os("RUSTC_WRAPPER"), Some(w) if !w.is_empty())
+        {
+            self.0.cfg.get_rustc_wrapper(&self.0.name)?
+        } else {
+            None
+        };
+        let mut cmd = match wrapper {
+            Some(wrapper) => {
+                let mut cmd = Command::new(wrapper);
+                cmd.arg(path);
+                cmd
+            }
+            None => Command::new(&path),
+        };
+        self.set_env(&mut cmd)?;
+        env_var::trace_proxy(
+            &format!(
+                "{}\t{}\t{}\t{}\t{}",
+                binary.to_string_lossy(),
+                self.0.name,
+                process().var("RUSTUP_TOOLCHAIN").unwrap_or_default(),
+                self.0.path.display(),
+                path.display()
+            ),
+            &mut cmd,
+        );
         Ok(cmd)
     }
 
-    fn set_env(&self, cmd: &mut Command) {
+    /// The error for `binary`, with its executable suffix, not being in the
+    /// toolchain, saying how to install it where that can be done.
+    pub(crate) fn missing_binary_error(&self, binary: &OsStr) -> anyhow::Error {
+        let binary_lossy: String = binary.to_string_lossy().into();
+        if let Ok(distributable) = DistributableToolchain::new(self.0) {
+            if let (Some(component_name), Ok(component_statuses), Ok(Some(manifest))) = (
+                component_for_bin(&binary_lossy),
+                distributable.list_components(),
+                distributable.get_manifest(),
+            ) {
+                let component_status = component_statuses
+                    .iter()
+                    .find(|cs| cs.component.short_name(&manifest) == component_name)
+                    .unwrap_or_else(|| {
+                        panic!("component {} should be in the manifest", component_name)
+                    });
+                if !component_status.available {
+                    return anyhow!(format!(
+                        "the '{}' component which provides the command '{}' is not available for the '{}' toolchain", component_status.component.short_name(&manifest), binary_lossy, self.0.name));
+                }
+                if component_status.installed {
+                    return anyhow!(format!(
+                        "the '{}' binary, normally provided by the '{}' component, is not applicable to the '{}' toolchain", binary_lossy, component_status.component.short_name(&manifest), self.0.name));
+                }
+            }
+        }
+        let defaults = self.0.cfg.get_default().ok().flatten();
+        anyhow!(format!(
+            "'{}' is not installed for the toolchain '{}'{}",
+            binary_lossy,
+            self.0.name,
+            install_msg(
+                &binary_lossy,
+                &self.0.name,
+                Some(&self.0.name) == defaults.as_ref()
+            )
+        ))
+    }
+
+    fn set_env(&self, cmd: &mut Command) -> Result<()> {
+        self.set_env_for(cmd, true)
+    }
+
+    /// Sets up the environment for the toolchain's tools. Where `cmd`
+    /// inherits rustup's environment the user's own variables win over
+    /// the toolchain's, and otherwise the toolchain's are always set.
+    pub(crate) fn set_env_for(&self, cmd: &mut Command, inherits_env: bool) -> Result<()> {
         self.set_ldpath(cmd);
 
         // Because rustup and cargo use slightly different
@@ -399,8 +594,21 @@ impl<'a> InstalledCommonToolchain<'a> {
 
         env_var::inc("RUST_RECURSION_COUNT", cmd);
 
-        cmd.env("RUSTUP_TOOLCHAIN", &self.0.name);
-        cmd.env("RUSTUP_HOME", &self.0.cfg.rustup_dir);
+        cmd.env("RUSTUP_TOOLCHAIN", self.0.requested_name());
+        // A home split across the XDG directories can't be given as
+        // RUSTUP_HOME; without it, the tools find the same layout again.
+        if self.0.cfg.xdg_dirs.is_none() {
+            cmd.env("RUSTUP_HOME", &self.0.cfg.rustup_dir);
+        }
+
+        // As with cargo's `[env]` table, variables the user has set
+        // themselves take precedence over the toolchain's.
+        for (key, value) in self.0.cfg.get_toolchain_env(&self.0.name)? {
+            if !inherits_env || process().var_os(&key).is_none() {
+                cmd.env(key, value);
+            }
+        }
+        Ok(())
     }
 
     fn set_ldpath(&self, cmd: &mut Command) {
@@ -495,12 +703,83 @@ impl<'a> CustomToolchain<'a> {
 
         Ok(())
     }
+
+    // Not installed only.
+    /// Installs the toolchain from an archive made by `export` under this
+    /// custom name. The archive must hold a toolchain for this host. Its
+    /// manifest comes with it, so components can still be listed.
+    pub fn install_from_archive(&self, archive: &Path) -> Result<()> {
+        if self.0.exists() {
+            bail!("toolchain '{}' is already installed", self.0.name);
+        }
+        let metadata = toolchain_archive::read_metadata(archive)?;
+        let archived = ToolchainDesc::from_str(&metadata.name)?;
+        if archived.target != self.0.cfg.get_host_triple()? {
+            bail!(
+                "cannot install '{}' as '{}': it is for a different host",
+                metadata.name,
+                self.0.name
+            );
+        }
+
+        let dir = self.0.cfg.temp_cfg.new_directory()?;
+        toolchain_archive::unpack(archive, &dir)?;
+        InstallMethod::Copy(&dir.join(toolchain_archive::TOOLCHAIN_DIR), self).install(&self.0)?;
+        Ok(())
+    }
+
+    // Installed only.
+    /// Gives a linked toolchain a channel manifest to add components from:
+    /// either a manifest file, or the manifest for a release on the dist
+    /// server, such as `nightly-2024-03-01`.
+    pub fn set_component_source(&self, source: &str) -> Result<()> {
+        let host = self.0.cfg.get_host_triple()?;
+        let manifest = if utils::is_file(source) {
+            Manifest::parse(&utils::read_file("component manifest", Path::new(source))?)?
+        } else {
+            let desc = PartialToolchainDesc::from_str(source)?.resolve(&host)?;
+            let download_cfg = self.0.cfg.download_cfg(&*self.0.dist_handler);
+            dist::dl_v2_manifest(download_cfg, None, &desc)?
+                .ok_or_else(|| anyhow!("no release found for '{}'", source))?
+                .0
+        };
+        manifest.get_package("rust")?.get_target(Some(&host))?;
+
+        let _lock = self.0.cfg.lock_toolchain(&self.0.name)?;
+        let prefix = InstallPrefix::from(self.0.path.to_owned());
+        Manifestation::open(prefix, host)?.save_manifest(&manifest)
+    }
+
+    // Not installed only.
+    /// Installs a copy of another installed toolchain, for `rustup toolchain
+    /// clone`. With `hardlink`, files are shared with the original wherever
+    /// the filesystem allows, so they must be replaced rather than edited.
+    pub fn install_from_toolchain(&self, src: &Toolchain<'_>, hardlink: bool) -> Result<()> {
+        if !src.exists() {
+            bail!(RustupError::ToolchainNotInstalled(src.name().to_owned()));
+        }
+        if hardlink {
+            InstallMethod::Hardlink(src.path(), self).install(&self.0)?;
+        } else {
+            InstallMethod::Copy(src.path(), self).install(&self.0)?;
+        }
+        Ok(())
+    }
 }
 
 impl<'a> InstalledToolchain<'a> for CustomToolchain<'a> {
     fn installed_paths(&self) -> Result<Vec<InstalledPath<'a>>> {
         let path = &self.0.path;
-        Ok(vec![InstalledPath::Dir { path }])
+        let mut paths = vec![InstalledPath::Dir { path }];
+        paths.extend(
+            self.0
+                .last_used_file(false)
+                .map(|path| InstalledPath::File {
+                    name: "last used",
+                    path,
+                }),
+        );
+        Ok(paths)
     }
 }
 
@@ -522,55 +801,80 @@ impl<'a> DistributableToolchain<'a> {
     /// Temporary helper until we further split this into a newtype for
     /// InstalledDistributableToolchain - one where the type can protect component operations.
     pub fn new_for_components(toolchain: &'a Toolchain<'a>) -> Result<DistributableToolchain<'a>> {
+        // Linked toolchains can have components too, once they have been
+        // given a manifest to add them from.
+        let prefix = InstallPrefix::from(toolchain.path().to_owned());
+        if toolchain.is_custom() && utils::is_file(prefix.manifest_file(DIST_MANIFEST)) {
+            return Ok(DistributableToolchain(toolchain));
+        }
         DistributableToolchain::new(toolchain).context(RustupError::ComponentsUnsupported(
             toolchain.name().to_string(),
         ))
     }
 
     // Installed only.
-    pub fn add_component(&self, mut component: Component) -> Result<()> {
+    pub fn add_component(&self, component: Component) -> Result<()> {
+        self.add_components(vec![component])
+    }
+
+    /// Adds all of `components` in a single transaction, so that if any of
+    /// them can't be added, none of them are.
+    pub fn add_components(&self, components: Vec<Component>) -> Result<()> {
         if !self.0.exists() {
             return Err(RustupError::ToolchainNotInstalled(self.0.name.to_owned()).into());
         }
+        self.0.ensure_not_system()?;
+        let names: Vec<String> = components.iter().map(Component::name_in_manifest).collect();
+        hooks::before(self.0.cfg, "component-add", &self.0.name, &names)?;
+        self.install_components(components)?;
+        hooks::after(self.0.cfg, "component-add", &self.0.name, &names);
+        Ok(())
+    }
 
-        let toolchain = &self.0.name;
-        let toolchain = ToolchainDesc::from_str(toolchain).expect("must be valid");
+    fn install_components(&self, components: Vec<Component>) -> Result<()> {
+        self.0.cfg.check_policy(&self.desc()?)?;
+        let _lock = self.0.cfg.lock_toolchain(&self.0.name)?;
 
+        let target = self.component_target()?;
         let prefix = InstallPrefix::from(self.0.path.to_owned());
-        let manifestation = Manifestation::open(prefix, toolchain.target.clone())?;
+        let manifestation = Manifestation::open(prefix, target.clone())?;
 
         if let Some(manifest) = manifestation.load_manifest()? {
-            // Rename the component if necessary.
-            if let Some(c) = manifest.rename_component(&component) {
-                component = c;
-            }
-
-            // Validate the component name
             let rust_pkg = manifest
                 .packages
                 .get("rust")
                 .expect("manifest should contain a rust package");
             let targ_pkg = rust_pkg
                 .targets
-                .get(&toolchain.target)
+                .get(&target)
                 .expect("installed manifest should have a known target");
 
-            if !targ_pkg.components.contains(&component) {
-                let wildcard_component = component.wildcard();
-                if targ_pkg.components.contains(&wildcard_component) {
-                    component = wildcard_component;
-                } else {
-                    return Err(RustupError::UnknownComponent {
-                        name: self.0.name.to_string(),
-                        component: component.description(&manifest),
-                        suggestion: self.get_component_suggestion(&component, &manifest, false),
+            let mut explicit_add_components = Vec::new();
+            for mut component in components {
+                // Rename the component if necessary.
+                if let Some(c) = manifest.rename_component(&component) {
+                    component = c;
+                }
+
+                // Validate the component name
+                if !targ_pkg.components.contains(&component) {
+                    let wildcard_component = component.wildcard();
+                    if targ_pkg.components.contains(&wildcard_component) {
+                        component = wildcard_component;
+                    } else {
+                        return Err(RustupError::UnknownComponent {
+                            name: self.0.name.to_string(),
+                            component: component.description(&manifest),
+                            suggestion: self.get_component_suggestion(&component, &manifest, false),
+                        }
+                        .into());
                     }
-                    .into());
                 }
+                explicit_add_components.push(component);
             }
 
             let changes = Changes {
-                explicit_add_components: vec![component],
+                explicit_add_components,
                 remove_components: vec![],
             };
 
@@ -580,7 +884,7 @@ impl<'a> DistributableToolchain<'a> {
                 false,
                 &self.download_cfg(),
                 &self.download_cfg().notify_handler,
-                &toolchain.manifest_name(),
+                &self.manifest_name(),
                 false,
             )?;
 
@@ -637,7 +941,7 @@ impl<'a> DistributableToolchain<'a> {
             src_file
         };
         let mut cmd = Command::new(exe_path);
-        installed_primary.set_env(&mut cmd); // set up the environment to match rustc, not cargo
+        installed_primary.set_env(&mut cmd)?; // set up the environment to match rustc, not cargo
         cmd.env("RUSTUP_TOOLCHAIN", &primary_toolchain.name);
         Ok(cmd)
     }
@@ -647,6 +951,25 @@ impl<'a> DistributableToolchain<'a> {
         ToolchainDesc::from_str(&self.0.name)
     }
 
+    /// The target that the toolchain's components are for. Linked
+    /// toolchains are taken to be for the host.
+    pub fn component_target(&self) -> Result<TargetTriple> {
+        if self.0.is_custom() {
+            self.0.cfg.get_host_triple()
+        } else {
+            Ok(self.desc()?.target)
+        }
+    }
+
+    /// The name of the channel the toolchain's components come from, as
+    /// shown in messages.
+    fn manifest_name(&self) -> String {
+        match self.desc() {
+            Ok(desc) => desc.manifest_name(),
+            Err(_) => self.0.name.clone(),
+        }
+    }
+
     fn download_cfg(&self) -> DownloadCfg<'_> {
         self.0.cfg.download_cfg(&*self.0.dist_handler)
     }
@@ -759,15 +1082,96 @@ impl<'a> DistributableToolchain<'a> {
         targets: &[&str],
         profile: Option<Profile>,
     ) -> Result<UpdateStatus> {
+        let (default_components, default_targets) = self.new_toolchain_extras()?;
+        let mut components = components.to_vec();
+        components.extend(default_components.iter().map(|c| &**c));
+        let mut targets = targets.to_vec();
+        targets.extend(default_targets.iter().map(|t| &**t));
+        self.install_exactly(
+            self.download_cfg(),
+            force_update,
+            allow_downgrade,
+            &components,
+            &targets,
+            profile,
+        )
+    }
+
+    // Installed or not installed.
+    /// Installs the toolchain, or the components and targets it lacks, as
+    /// `install_from_dist` does, from the release whose channel manifest has
+    /// the SHA-256 `expected`. The manifest is downloaded once, checked, and
+    /// installed from as it is, so that nothing else the server sends in the
+    /// meantime can be installed in its place.
+    pub fn install_pinned(
+        &self,
+        expected: &str,
+        components: &[&str],
+        targets: &[&str],
+        profile: Option<Profile>,
+    ) -> Result<UpdateStatus> {
+        let desc = self.desc()?;
+        let (manifest, hash) = dist::dl_v2_manifest(self.download_cfg(), None, &desc)?
+            .ok_or_else(|| anyhow!("no release found for '{}'", desc))?;
+        self.check_pinned_hash(expected, &hash)?;
+        let verified = VerifiedManifest {
+            toolchain: desc,
+            manifest,
+            hash,
+        };
+        let (default_components, default_targets) = self.new_toolchain_extras()?;
+        let mut components = components.to_vec();
+        components.extend(default_components.iter().map(|c| &**c));
+        let mut targets = targets.to_vec();
+        targets.extend(default_targets.iter().map(|t| &**t));
+        self.install_exactly(
+            DownloadCfg {
+                verified_manifest: Some(&verified),
+                ..self.download_cfg()
+            },
+            true,
+            false,
+            &components,
+            &targets,
+            profile,
+        )
+    }
+
+    /// The components and targets set with `rustup set default-components`
+    /// and `rustup set default-targets`, if the toolchain is yet to be
+    /// installed.
+    fn new_toolchain_extras(&self) -> Result<(Vec<String>, Vec<String>)> {
+        if self.0.exists() {
+            Ok((Vec::new(), Vec::new()))
+        } else {
+            Ok((
+                self.0.cfg.get_default_components()?,
+                self.0.cfg.get_default_targets()?,
+            ))
+        }
+    }
+
+    /// Installs or updates the toolchain, with `components` and no others
+    /// besides those of the profile.
+    fn install_exactly(
+        &self,
+        dl_cfg: DownloadCfg<'_>,
+        force_update: bool,
+        allow_downgrade: bool,
+        components: &[&str],
+        targets: &[&str],
+        profile: Option<Profile>,
+    ) -> Result<UpdateStatus> {
+        self.0.ensure_not_system()?;
         let update_hash = self.update_hash()?;
         let old_date = self.get_manifest().ok().and_then(|m| m.map(|m| m.date));
-        InstallMethod::Dist {
+        self.install_with_hooks(InstallMethod::Dist {
             desc: &self.desc()?,
             profile: profile
                 .map(Ok)
                 .unwrap_or_else(|| self.0.cfg.get_profile())?,
             update_hash: Some(&update_hash),
-            dl_cfg: self.download_cfg(),
+            dl_cfg,
             force_update,
             allow_downgrade,
             exists: self.0.exists(),
@@ -775,15 +1179,158 @@ impl<'a> DistributableToolchain<'a> {
             components,
             targets,
             distributable: &self,
+        })
+    }
+
+    /// Installs the toolchain by `method`, with the hooks for installing
+    /// it, or for updating it if it is installed already. The hook after an
+    /// update is only run if there was one to make.
+    fn install_with_hooks(&self, method: InstallMethod<'_>) -> Result<UpdateStatus> {
+        let change = if self.0.exists() {
+            "toolchain-update"
+        } else {
+            "toolchain-install"
+        };
+        hooks::before(self.0.cfg, change, &self.0.name, &[])?;
+        let status = method.install(&self.0)?;
+        if !matches!(status, UpdateStatus::Unchanged) {
+            hooks::after(self.0.cfg, change, &self.0.name, &[]);
         }
-        .install(&self.0)
+        Ok(status)
+    }
+
+    // Installed only.
+    /// Reinstalls the toolchain from its channel, with the components and
+    /// targets it had, for when it has been left broken.
+    pub fn repair(&self) -> Result<UpdateStatus> {
+        self.0.ensure_not_system()?;
+        let prefix = InstallPrefix::from(self.0.path.to_owned());
+        let host = self.desc()?.target;
+        let config = Manifestation::open(prefix, host.clone())
+            .and_then(|m| m.read_config())
+            .ok()
+            .flatten();
+
+        let installed = config.as_ref().map(|c| &c.components[..]).unwrap_or(&[]);
+        let (components, targets) = split_components(installed, &host);
+        let components: Vec<&str> = components.iter().map(|s| &**s).collect();
+        let targets: Vec<&str> = targets.iter().map(|s| &**s).collect();
+        // Without a record of its components, the toolchain is reinstalled
+        // with the default profile.
+        let profile = config.as_ref().map(|_| Profile::Minimal);
+
+        {
+            let _lock = self.0.cfg.lock_toolchain(&self.0.name)?;
+            self.0.remove_files()?;
+        }
+        self.install_exactly(
+            self.download_cfg(),
+            false,
+            false,
+            &components,
+            &targets,
+            profile,
+        )
     }
 
     // Installed or not installed.
+    /// Installs the release of the toolchain's channel from `date`, with
+    /// exactly `components`, whether or not the channel has moved on since.
+    /// The toolchain keeps its name, so `rustup update` brings it up to
+    /// date again.
+    pub fn install_release(&self, date: &str, components: &[Component]) -> Result<UpdateStatus> {
+        self.0.ensure_not_system()?;
+        let desc = ToolchainDesc {
+            date: Some(date.to_owned()),
+            ..self.desc()?
+        };
+        let (components, targets) = split_components(components, &desc.target);
+        let components: Vec<&str> = components.iter().map(|s| &**s).collect();
+        let targets: Vec<&str> = targets.iter().map(|s| &**s).collect();
+        let update_hash = self.update_hash()?;
+        let old_date = self.get_manifest().ok().and_then(|m| m.map(|m| m.date));
+        self.install_with_hooks(InstallMethod::Dist {
+            desc: &desc,
+            profile: Profile::Minimal,
+            update_hash: Some(&update_hash),
+            dl_cfg: self.download_cfg(),
+            force_update: false,
+            allow_downgrade: true,
+            exists: self.0.exists(),
+            old_date: old_date.as_deref(),
+            components: &components,
+            targets: &targets,
+            distributable: &self,
+        })
+    }
+
+    // Installed or not installed.
+    /// The manifest of the release from `date`, downloaded without
+    /// installing anything.
+    pub fn release_manifest(&self, date: &str) -> Result<Manifest> {
+        let desc = ToolchainDesc {
+            date: Some(date.to_owned()),
+            ..self.desc()?
+        };
+        match dist::dl_v2_manifest(self.download_cfg(), None, &desc)? {
+            Some((manifest, _)) => Ok(manifest),
+            None => Err(anyhow!("no manifest was found for '{}'", desc)),
+        }
+    }
+
+    /// Downloads everything needed to install this toolchain into the
+    /// download cache, without installing it. Returns the manifest date.
+    pub fn prefetch(
+        &self,
+        components: &[&str],
+        targets: &[&str],
+        profile: Option<Profile>,
+    ) -> Result<String> {
+        let profile = match profile {
+            Some(profile) => profile,
+            None => self.0.cfg.get_profile()?,
+        };
+        let desc = self.desc()?;
+        self.0.cfg.check_policy(&desc)?;
+        let (default_components, default_targets) = self.new_toolchain_extras()?;
+        let mut components = components.to_vec();
+        components.extend(default_components.iter().map(|c| &**c));
+        let mut targets = targets.to_vec();
+        targets.extend(default_targets.iter().map(|t| &**t));
+        crate::dist::dist::prefetch(
+            self.download_cfg(),
+            &desc,
+            Some(profile),
+            &components,
+            &targets,
+        )
+    }
+
+    // Installed only.
+    /// What `install_from_dist` would change, without changing anything:
+    /// `None` if the toolchain is up to date.
+    pub fn plan_update(&self, components: &[&str], targets: &[&str]) -> Result<Option<UpdatePlan>> {
+        let update_hash = self.update_hash()?;
+        // As with `install_from_dist`, the profile only matters to a fresh
+        // install.
+        crate::dist::dist::plan_update(
+            self.download_cfg(),
+            Some(&update_hash),
+            &self.desc()?,
+            None,
+            &InstallPrefix::from(self.0.path.to_owned()),
+            components,
+            targets,
+        )
+    }
+
     pub fn install_from_dist_if_not_installed(&self) -> Result<UpdateStatus> {
         let update_hash = self.update_hash()?;
         (self.0.cfg.notify_handler)(Notification::LookingForToolchain(&self.0.name));
         if !self.0.exists() {
+            let (default_components, default_targets) = self.new_toolchain_extras()?;
+            let components: Vec<&str> = default_components.iter().map(|c| &**c).collect();
+            let targets: Vec<&str> = default_targets.iter().map(|t| &**t).collect();
             Ok(InstallMethod::Dist {
                 desc: &self.desc()?,
                 profile: self.0.cfg.get_profile()?,
@@ -793,8 +1340,8 @@ impl<'a> DistributableToolchain<'a> {
                 allow_downgrade: false,
                 exists: false,
                 old_date: None,
-                components: &[],
-                targets: &[],
+                components: &components,
+                targets: &targets,
                 distributable: &self,
             }
             .install(&self.0)?)
@@ -810,12 +1357,12 @@ impl<'a> DistributableToolchain<'a> {
             bail!(RustupError::ToolchainNotInstalled(self.0.name.to_owned()));
         }
 
-        let toolchain = &self.0.name;
-        let toolchain = ToolchainDesc::from_str(toolchain)
+        let target = self
+            .component_target()
             .context(RustupError::ComponentsUnsupported(self.0.name.to_string()))?;
 
         let prefix = InstallPrefix::from(self.0.path.to_owned());
-        let manifestation = Manifestation::open(prefix, toolchain.target.clone())?;
+        let manifestation = Manifestation::open(prefix, target.clone())?;
 
         if let Some(manifest) = manifestation.load_manifest()? {
             let config = manifestation.read_config()?;
@@ -830,7 +1377,7 @@ impl<'a> DistributableToolchain<'a> {
                 .expect("manifest should contain a rust package");
             let targ_pkg = rust_pkg
                 .targets
-                .get(&toolchain.target)
+                .get(&target)
                 .expect("installed manifest should have a known target");
 
             for component in &targ_pkg.components {
@@ -872,42 +1419,92 @@ impl<'a> DistributableToolchain<'a> {
     }
 
     // Installed only.
-    pub fn remove_component(&self, mut component: Component) -> Result<()> {
+    /// The disk space taken by the files of an installed component, in
+    /// bytes, or `None` if it isn't installed.
+    pub fn component_size(&self, component: &Component) -> Result<Option<u64>> {
+        let prefix = InstallPrefix::from(self.0.path.to_owned());
+        let installation = Components::open(prefix.clone())?;
+        // As in `Manifestation::uninstall_component`, the name it was
+        // installed under may or may not include the target.
+        let installed = match installation.find(&component.name_in_manifest())? {
+            Some(installed) => installed,
+            None => match installation.find(component.short_name_in_manifest())? {
+                Some(installed) => installed,
+                None => return Ok(None),
+            },
+        };
+        let mut size = 0;
+        for part in installed.parts()? {
+            let path = prefix.abs_path(&part.1);
+            size += if part.0 == "dir" {
+                utils::dir_size(&path).unwrap_or(0)
+            } else {
+                std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0)
+            };
+        }
+        Ok(Some(size))
+    }
+
+    // Installed only.
+    pub fn remove_component(&self, component: Component) -> Result<()> {
+        self.remove_components(vec![component])
+    }
+
+    /// Removes all of `components` in a single transaction, so that if any
+    /// of them can't be removed, none of them are.
+    pub fn remove_components(&self, components: Vec<Component>) -> Result<()> {
         // Overlapping code with get_manifest :/.
         if !self.0.exists() {
             return Err(RustupError::ToolchainNotInstalled(self.0.name.to_owned()).into());
         }
+        self.0.ensure_not_system()?;
+        let names: Vec<String> = components.iter().map(Component::name_in_manifest).collect();
+        hooks::before(self.0.cfg, "component-remove", &self.0.name, &names)?;
+        self.uninstall_components(components)?;
+        hooks::after(self.0.cfg, "component-remove", &self.0.name, &names);
+        Ok(())
+    }
 
-        let toolchain = &self.0.name;
-        let toolchain = ToolchainDesc::from_str(toolchain).expect("must be valid");
+    fn uninstall_components(&self, components: Vec<Component>) -> Result<()> {
+        let _lock = self.0.cfg.lock_toolchain(&self.0.name)?;
 
         let prefix = InstallPrefix::from(self.0.path.to_owned());
-        let manifestation = Manifestation::open(prefix, toolchain.target.clone())?;
+        let manifestation = Manifestation::open(prefix, self.component_target()?)?;
 
         if let Some(manifest) = manifestation.load_manifest()? {
-            // Rename the component if necessary.
-            if let Some(c) = manifest.rename_component(&component) {
-                component = c;
-            }
+            // A linked toolchain has no configuration until a component is
+            // first added to it.
+            let installed = manifestation
+                .read_config()?
+                .map(|config| config.components)
+                .unwrap_or_default();
+
+            let mut remove_components = Vec::new();
+            for mut component in components {
+                // Rename the component if necessary.
+                if let Some(c) = manifest.rename_component(&component) {
+                    component = c;
+                }
 
-            let dist_config = manifestation.read_config()?.unwrap();
-            if !dist_config.components.contains(&component) {
-                let wildcard_component = component.wildcard();
-                if dist_config.components.contains(&wildcard_component) {
-                    component = wildcard_component;
-                } else {
-                    return Err(RustupError::UnknownComponent {
-                        name: self.0.name.to_string(),
-                        component: component.description(&manifest),
-                        suggestion: self.get_component_suggestion(&component, &manifest, true),
+                if !installed.contains(&component) {
+                    let wildcard_component = component.wildcard();
+                    if installed.contains(&wildcard_component) {
+                        component = wildcard_component;
+                    } else {
+                        return Err(RustupError::UnknownComponent {
+                            name: self.0.name.to_string(),
+                            component: component.description(&manifest),
+                            suggestion: self.get_component_suggestion(&component, &manifest, true),
+                        }
+                        .into());
                     }
-                    .into());
                 }
+                remove_components.push(component);
             }
 
             let changes = Changes {
                 explicit_add_components: vec![],
-                remove_components: vec![component],
+                remove_components,
             };
 
             manifestation.update(
@@ -916,7 +1513,7 @@ impl<'a> DistributableToolchain<'a> {
                 false,
                 &self.download_cfg(),
                 &self.download_cfg().notify_handler,
-                &toolchain.manifest_name(),
+                &self.manifest_name(),
                 false,
             )?;
 
@@ -931,6 +1528,13 @@ impl<'a> DistributableToolchain<'a> {
 
     // Installed only.
     pub fn show_dist_version(&self) -> Result<Option<String>> {
+        Ok(self.show_dist_release()?.map(|(version, _)| version))
+    }
+
+    // Installed only.
+    /// The version and release date of the update to the toolchain, if
+    /// there is one.
+    pub fn show_dist_release(&self) -> Result<Option<(String, String)>> {
         let update_hash = self.update_hash()?;
 
         match crate::dist::dist::dl_v2_manifest(
@@ -938,19 +1542,131 @@ impl<'a> DistributableToolchain<'a> {
             Some(&update_hash),
             &self.desc()?,
         )? {
-            Some((manifest, _)) => Ok(Some(manifest.get_rust_version()?.to_string())),
+            Some((manifest, _)) => Ok(Some((
+                manifest.get_rust_version()?.to_string(),
+                manifest.date,
+            ))),
             None => Ok(None),
         }
     }
 
     // Installed only.
     pub fn show_version(&self) -> Result<Option<String>> {
+        Ok(self.show_release()?.map(|(version, _)| version))
+    }
+
+    // Installed only.
+    /// The version and release date of the toolchain as installed.
+    pub fn show_release(&self) -> Result<Option<(String, String)>> {
         match self.get_manifest()? {
-            Some(manifest) => Ok(Some(manifest.get_rust_version()?.to_string())),
+            Some(manifest) => Ok(Some((
+                manifest.get_rust_version()?.to_string(),
+                manifest.date,
+            ))),
             None => Ok(None),
         }
     }
 
+    // Installed only.
+    /// Packs the toolchain into an archive at `dest`, for `rustup toolchain
+    /// export`.
+    pub fn export(&self, dest: &Path) -> Result<()> {
+        if !self.0.exists() {
+            bail!(RustupError::ToolchainNotInstalled(self.0.name.to_owned()));
+        }
+        let update_hash = self.update_hash()?;
+        let update_hash = if utils::is_file(&update_hash) {
+            Some(utils::read_file("update hash", &update_hash)?)
+        } else {
+            None
+        };
+        let metadata = toolchain_archive::Metadata {
+            name: self.0.name.clone(),
+            update_hash,
+        };
+        toolchain_archive::export(&self.0.path, metadata, dest)
+    }
+
+    // Not installed only.
+    /// Installs the toolchain from an archive made by `export`. The archive
+    /// must hold a toolchain for the same host, though it may have been
+    /// exported under a different name.
+    pub fn install_from_archive(&self, archive: &Path) -> Result<()> {
+        let _lock = self.0.cfg.lock_toolchain(&self.0.name)?;
+        if self.0.exists() {
+            bail!("toolchain '{}' is already installed", self.0.name);
+        }
+        let metadata = toolchain_archive::read_metadata(archive)?;
+        let archived = ToolchainDesc::from_str(&metadata.name)?;
+        let desc = self.desc()?;
+        if archived.target != desc.target {
+            bail!(
+                "cannot install '{}' as '{}': they are for different hosts",
+                metadata.name,
+                self.0.name
+            );
+        }
+
+        let dir = self.0.cfg.temp_cfg.new_directory()?;
+        toolchain_archive::unpack(archive, &dir)?;
+        let result = self.move_from_archive(&dir.join(toolchain_archive::TOOLCHAIN_DIR));
+        if result.is_err() {
+            // The transaction leaves the toolchain's own directory behind.
+            let _ = std::fs::remove_dir(&self.0.path);
+            return result;
+        }
+
+        // The update hash only describes the channel it was exported from.
+        if let Some(update_hash) = metadata.update_hash {
+            if metadata.name == self.0.name {
+                utils::write_file("update hash", &self.update_hash()?, &update_hash)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn move_from_archive(&self, src: &Path) -> Result<()> {
+        let prefix = InstallPrefix::from(self.0.path.to_owned());
+        let mut tx = Transaction::new(prefix, &self.0.cfg.temp_cfg, &*self.0.dist_handler);
+        for entry in utils::read_dir("toolchain archive", src)? {
+            let entry = entry?;
+            let relpath = PathBuf::from(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                tx.move_dir("toolchain", relpath, &entry.path())?;
+            } else {
+                tx.move_file("toolchain", relpath, &entry.path())?;
+            }
+        }
+        tx.commit();
+        Ok(())
+    }
+
+    // Installed only.
+    /// Checks that the toolchain was installed from a channel manifest with
+    /// the SHA-256 `expected`.
+    pub fn check_installed_manifest_hash(&self, expected: &str) -> Result<()> {
+        let update_hash = self.0.cfg.get_hash_file(&self.0.name, false)?;
+        let hash = if utils::is_file(&update_hash) {
+            utils::read_file("update hash", &update_hash)?
+        } else {
+            "unknown".to_owned()
+        };
+        self.check_pinned_hash(expected, hash.trim())
+    }
+
+    fn check_pinned_hash(&self, expected: &str, actual: &str) -> Result<()> {
+        if expected.eq_ignore_ascii_case(actual) {
+            Ok(())
+        } else {
+            Err(RustupError::PinnedManifestMismatch {
+                name: self.0.name.clone(),
+                expected: expected.to_owned(),
+                actual: actual.to_owned(),
+            }
+            .into())
+        }
+    }
+
     // Installed only.
     fn update_hash(&self) -> Result<PathBuf> {
         self.0.cfg.get_hash_file(&self.0.name, true)
@@ -974,15 +1690,49 @@ impl<'a> DistributableToolchain<'a> {
     }
 }
 
+/// Splits `components` into the names of those to install for `host` and
+/// the other targets to install the standard library for, as an install
+/// from dist takes them.
+fn split_components(components: &[Component], host: &TargetTriple) -> (Vec<String>, Vec<String>) {
+    let mut names = Vec::new();
+    let mut targets = Vec::new();
+    for component in components {
+        let name = component.short_name_in_manifest();
+        match &component.target {
+            Some(target) if target != host => {
+                if name == "rust-std" {
+                    targets.push(target.to_string());
+                } else {
+                    warn!(
+                        "component '{}' will not be installed",
+                        component.name_in_manifest()
+                    );
+                }
+            }
+            _ => names.push(name.clone()),
+        }
+    }
+    (names, targets)
+}
+
 impl<'a> InstalledToolchain<'a> for DistributableToolchain<'a> {
     fn installed_paths(&self) -> Result<Vec<InstalledPath<'a>>> {
         let path = &self.0.path;
-        Ok(vec![
+        let mut paths = vec![
             InstalledPath::File {
                 name: "update hash",
                 path: self.update_hash()?,
             },
             InstalledPath::Dir { path },
-        ])
+        ];
+        paths.extend(
+            self.0
+                .last_used_file(false)
+                .map(|path| InstalledPath::File {
+                    name: "last used",
+                    path,
+                }),
+        );
+        Ok(paths)
     }
 }
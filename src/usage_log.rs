@@ -0,0 +1,127 @@
+//! A record of which tools of which toolchains the proxies and `rustup run`
+//! have run, for `rustup stats`. It is only kept once usage statistics are
+//! turned on with `rustup set usage-stats enable`, and never leaves the
+//! machine.
+//!
+//! Each run is a tab-separated line in `RUSTUP_HOME/logs/usage.log`, rotated
+//! as the download log is.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::utils::utils;
+
+const MAX_LOG_LEN: u64 = 1024 * 1024;
+const MAX_ROTATED_LOGS: usize = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    pub toolchain: String,
+    /// The tool run, such as `cargo` or `rustfmt`.
+    pub tool: String,
+}
+
+impl Entry {
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}",
+            self.time.to_rfc3339(),
+            self.toolchain,
+            self.tool
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(3, '\t');
+        let time = DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+        Some(Self {
+            time: time.with_timezone(&Utc),
+            toolchain: fields.next()?.to_owned(),
+            tool: fields.next()?.to_owned(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UsageLog {
+    path: PathBuf,
+}
+
+impl UsageLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&self, entry: &Entry) -> Result<()> {
+        utils::append_rotating_log(
+            "usage log",
+            &self.path,
+            &entry.to_line(),
+            MAX_LOG_LEN,
+            MAX_ROTATED_LOGS,
+        )
+    }
+
+    fn paths(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        (1..=MAX_ROTATED_LOGS)
+            .rev()
+            .map(move |n| utils::rotated_log(&self.path, n))
+            .chain(Some(self.path.clone()))
+    }
+
+    /// Every entry still in the log, oldest first. Lines which can't be
+    /// understood are skipped.
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for path in self.paths() {
+            if !utils::is_file(&path) {
+                continue;
+            }
+            let contents = utils::read_file("usage log", &path)?;
+            entries.extend(contents.lines().filter_map(Entry::from_line));
+        }
+        Ok(entries)
+    }
+
+    /// Removes everything recorded.
+    pub fn clear(&self) -> Result<()> {
+        for path in self.paths() {
+            if utils::is_file(&path) {
+                utils::remove_file("usage log", &path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip() {
+        let dir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+        let log = UsageLog::new(dir.path().join("logs").join("usage.log"));
+        let entry = Entry {
+            time: DateTime::parse_from_rfc3339("2021-06-01T12:00:00+00:00")
+                .unwrap()
+                .with_timezone(&Utc),
+            toolchain: "stable-x86_64-unknown-linux-gnu".to_owned(),
+            tool: "cargo".to_owned(),
+        };
+        log.record(&entry).unwrap();
+        utils::append_file("junk", log.path(), "not an entry").unwrap();
+        log.record(&entry).unwrap();
+        assert_eq!(log.entries().unwrap(), [entry.clone(), entry]);
+
+        log.clear().unwrap();
+        assert!(log.entries().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+//! Binary patches, for updating rustup by downloading how the new version
+//! differs from the installed one rather than the whole of it.
+//!
+//! A patch is xz-compressed. Uncompressed, it is `MAGIC` followed by
+//! entries, up to the end, each of which adds to the new file:
+//!
+//! - the offset in the old file and the length of a run of it, as
+//!   little-endian `u64`s, and then that many bytes, each added, wrapping, to
+//!   the byte of the run at its place: code which moved keeps most of its
+//!   bytes, so these are mostly zeros, which compress well;
+//! - then the length of the bytes to insert as they are, as a little-endian
+//!   `u64`, and those bytes.
+//!
+//! Nothing here checks that the result is what was meant; the caller
+//! compares it with the hash of the new file.
+
+use std::convert::TryFrom;
+use std::io::Read;
+
+use anyhow::{anyhow, Context, Result};
+
+pub(crate) const MAGIC: &[u8] = b"RUSTUP-PATCH-1\n";
+
+/// How many times the size of the old file the new one may be, so that a
+/// broken or hostile patch can't use up the memory before the hash of what
+/// it makes is checked.
+const MAX_GROWTH: usize = 4;
+
+/// `old` changed as `patch` says.
+pub(crate) fn apply<R: Read>(old: &[u8], patch: R) -> Result<Vec<u8>> {
+    let mut patch = xz2::read::XzDecoder::new(patch);
+    let mut magic = [0; MAGIC.len()];
+    patch
+        .read_exact(&mut magic)
+        .context("error reading the patch")?;
+    if magic != MAGIC {
+        return Err(anyhow!("not a patch this rustup understands"));
+    }
+
+    let max_len = old.len().saturating_mul(MAX_GROWTH);
+    let check_len = |new: &Vec<u8>, len: u64| {
+        let fits = usize::try_from(len)
+            .ok()
+            .and_then(|len| new.len().checked_add(len))
+            .map_or(false, |total| total <= max_len);
+        if fits {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "the patch makes a file more than {} times the size of the old one",
+                MAX_GROWTH
+            ))
+        }
+    };
+    let mut new = Vec::with_capacity(old.len());
+    while let Some(offset) = read_u64_or_end(&mut patch)? {
+        let len = read_u64(&mut patch)?;
+        let run = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(len).ok())
+            .and_then(|(offset, len)| old.get(offset..offset.checked_add(len)?))
+            .ok_or_else(|| anyhow!("the patch reads past the end of the old file"))?;
+        check_len(&new, len)?;
+        let diff = read_bytes(&mut patch, len)?;
+        new.extend(run.iter().zip(&diff).map(|(o, d)| o.wrapping_add(*d)));
+
+        let len = read_u64(&mut patch)?;
+        check_len(&new, len)?;
+        new.extend(read_bytes(&mut patch, len)?);
+    }
+    Ok(new)
+}
+
+/// The next number in `patch`, which mustn't end before it.
+fn read_u64<R: Read>(patch: &mut R) -> Result<u64> {
+    read_u64_or_end(patch)?.ok_or_else(|| anyhow!("the patch ends part way through"))
+}
+
+/// The next number in `patch`, or `None` at its end, where an entry may end
+/// it.
+fn read_u64_or_end<R: Read>(patch: &mut R) -> Result<Option<u64>> {
+    let mut bytes = [0; 8];
+    let mut read = 0;
+    while read < bytes.len() {
+        match patch
+            .read(&mut bytes[read..])
+            .context("error reading the patch")?
+        {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(anyhow!("the patch ends part way through")),
+            n => read += n,
+        }
+    }
+    Ok(Some(u64::from_le_bytes(bytes)))
+}
+
+/// The next `len` bytes of `patch`, read without trusting `len` enough to
+/// allocate it up front.
+fn read_bytes<R: Read>(patch: &mut R, len: u64) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    patch
+        .take(len)
+        .read_to_end(&mut bytes)
+        .context("error reading the patch")?;
+    if bytes.len() as u64 != len {
+        return Err(anyhow!("the patch ends part way through"));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn compress(entries: &[u8]) -> Vec<u8> {
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+        encoder.write_all(MAGIC).unwrap();
+        encoder.write_all(entries).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn entry(offset: u64, diff: &[u8], insert: &[u8]) -> Vec<u8> {
+        let mut entry = offset.to_le_bytes().to_vec();
+        entry.extend(&(diff.len() as u64).to_le_bytes());
+        entry.extend(diff);
+        entry.extend(&(insert.len() as u64).to_le_bytes());
+        entry.extend(insert);
+        entry
+    }
+
+    #[test]
+    fn applies_runs_and_inserts() {
+        let old = b"hello, world";
+        let mut entries = entry(7, &[0, 0, 0, 0, 0], b" and ");
+        entries.extend(entry(0, &[0, 1, 0, 0, 0], b"!"));
+        let new = apply(old, &compress(&entries)[..]).unwrap();
+        assert_eq!(new, b"world and hfllo!");
+    }
+
+    #[test]
+    fn rejects_bad_patches() {
+        let old = b"hello";
+        let mut not_a_patch = xz2::write::XzEncoder::new(Vec::new(), 6);
+        not_a_patch.write_all(b"something else entirely").unwrap();
+        assert!(apply(old, &not_a_patch.finish().unwrap()[..]).is_err());
+
+        let past_the_end = compress(&entry(3, &[0, 0, 0], b""));
+        assert!(apply(old, &past_the_end[..]).is_err());
+
+        let mut cut_short = entry(0, &[0, 0], b"abc");
+        cut_short.truncate(cut_short.len() - 1);
+        assert!(apply(old, &compress(&cut_short)[..]).is_err());
+
+        let mut just_the_offset = entry(0, &[0], b"");
+        just_the_offset.truncate(8);
+        assert!(apply(old, &compress(&just_the_offset)[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_patches_which_grow_too_much() {
+        let old = b"hello";
+        let fits = compress(&entry(0, &[0; 5], &[b'!'; 15]));
+        assert_eq!(apply(old, &fits[..]).unwrap().len(), 20);
+
+        let too_big = compress(&entry(0, &[0; 5], &[b'!'; 16]));
+        assert!(apply(old, &too_big[..]).is_err());
+
+        // The length is refused before anything is read for it.
+        let mut huge = entry(0, &[], b"");
+        huge.truncate(huge.len() - 8);
+        huge.extend(&u64::MAX.to_le_bytes());
+        assert!(apply(old, &compress(&huge)[..]).is_err());
+    }
+}
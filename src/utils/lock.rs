@@ -0,0 +1,194 @@
+//! Advisory locks on files in RUSTUP_HOME, so that several rustup processes,
+//! possibly run by different users, can share a home without corrupting it.
+//!
+//! The locks are advisory: they only keep out other rustup processes, and
+//! are released when the lock is dropped or the process exits.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use fs2::FileExt;
+
+use crate::errors::RustupError;
+use crate::utils::notifications::Notification;
+use crate::utils::utils;
+
+#[derive(Debug)]
+pub struct FileLock {
+    file: Option<File>,
+}
+
+impl FileLock {
+    /// Takes an exclusive lock on `path`, creating it if needed, and waiting
+    /// for any other process holding it to let go.
+    ///
+    /// On filesystems without locking support the lock is not taken at all,
+    /// as refusing to work there would be worse than the risk of a race.
+    pub fn exclusive(
+        name: &'static str,
+        path: &Path,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> Result<Self> {
+        Self::lock(name, path, true, notify_handler)
+    }
+
+    /// Takes a shared lock on `path`, which any number of processes can hold
+    /// at once, but not while another holds an exclusive lock on it.
+    pub fn shared(
+        name: &'static str,
+        path: &Path,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> Result<Self> {
+        Self::lock(name, path, false, notify_handler)
+    }
+
+    /// Takes an exclusive lock on `path` if no other process holds it,
+    /// without waiting.
+    pub fn try_exclusive(name: &'static str, path: &Path) -> Result<Option<Self>> {
+        Self::try_lock(name, path, true)
+    }
+
+    /// Takes a shared lock on `path` unless another process holds an
+    /// exclusive one, without waiting.
+    pub fn try_shared(name: &'static str, path: &Path) -> Result<Option<Self>> {
+        Self::try_lock(name, path, false)
+    }
+
+    fn try_lock(name: &'static str, path: &Path, exclusive: bool) -> Result<Option<Self>> {
+        let context = || RustupError::LockingFile {
+            name,
+            path: PathBuf::from(path),
+        };
+        if let Some(parent) = path.parent() {
+            utils::ensure_dir_exists(name, parent, &|_: Notification<'_>| {})
+                .with_context(context)?;
+        }
+        let file = open(path).with_context(context)?;
+        let attempt = if exclusive {
+            file.try_lock_exclusive()
+        } else {
+            FileExt::try_lock_shared(&file)
+        };
+        match attempt {
+            Ok(()) => Ok(Some(Self { file: Some(file) })),
+            Err(e) if is_contended(&e) => Ok(None),
+            Err(e) if is_unsupported(&e) => Ok(Some(Self { file: None })),
+            Err(e) => Err(e).with_context(context),
+        }
+    }
+
+    fn lock(
+        name: &'static str,
+        path: &Path,
+        exclusive: bool,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> Result<Self> {
+        let context = || RustupError::LockingFile {
+            name,
+            path: PathBuf::from(path),
+        };
+        if let Some(lock) = Self::try_lock(name, path, exclusive)? {
+            return Ok(lock);
+        }
+        notify_handler(Notification::WaitingForLock(name, path));
+        let file = open(path).with_context(context)?;
+        if exclusive {
+            file.lock_exclusive()
+        } else {
+            FileExt::lock_shared(&file)
+        }
+        .with_context(context)?;
+        Ok(Self { file: Some(file) })
+    }
+}
+
+/// Held while a toolchain is changed: a shared lock on the metadata of the
+/// whole home, and an exclusive lock on the toolchain itself. Changes to
+/// different toolchains can go ahead at the same time, while a second change
+/// to the same toolchain waits for the first, and then usually finds that
+/// there is nothing left to do.
+#[derive(Debug)]
+pub struct ToolchainLock {
+    _metadata: FileLock,
+    _toolchain: FileLock,
+}
+
+impl ToolchainLock {
+    pub fn new(metadata: FileLock, toolchain: FileLock) -> Self {
+        Self {
+            _metadata: metadata,
+            _toolchain: toolchain,
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            let _ = file.unlock();
+        }
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create(true);
+    // Other users sharing the home need to be able to lock the file too.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o666);
+    }
+    let file = options.open(path)?;
+    utils::share_with_group(path, false);
+    Ok(file)
+}
+
+fn is_contended(e: &io::Error) -> bool {
+    e.raw_os_error() == fs2::lock_contended_error().raw_os_error()
+}
+
+fn is_unsupported(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        matches!(e.raw_os_error(), Some(libc::ENOTSUP) | Some(libc::ENOLCK))
+    }
+    #[cfg(windows)]
+    {
+        e.raw_os_error() == Some(winapi::shared::winerror::ERROR_INVALID_FUNCTION as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive_lock_excludes_others() {
+        let dir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+        let path = dir.path().join("test.lock");
+        let lock = FileLock::exclusive("test", &path, &|_| {}).unwrap();
+
+        let other = open(&path).unwrap();
+        assert!(is_contended(&other.try_lock_exclusive().unwrap_err()));
+        assert!(FileLock::try_shared("test", &path).unwrap().is_none());
+        drop(lock);
+        other.try_lock_exclusive().unwrap();
+    }
+
+    #[test]
+    fn shared_locks_exclude_only_exclusive_ones() {
+        let dir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+        let path = dir.path().join("locks").join("test.lock");
+        let first = FileLock::shared("test", &path, &|_| {}).unwrap();
+        let second = FileLock::shared("test", &path, &|_| {}).unwrap();
+
+        let other = open(&path).unwrap();
+        assert!(is_contended(&other.try_lock_exclusive().unwrap_err()));
+        drop(first);
+        drop(second);
+        other.try_lock_exclusive().unwrap();
+    }
+}
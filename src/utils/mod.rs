@@ -1,6 +1,9 @@
 ///!  Utility functions for Rustup
+pub(crate) mod binary_patch;
+pub(crate) mod lock;
 pub(crate) mod notifications;
 pub mod raw;
+pub(crate) mod toml_document;
 pub(crate) mod toml_utils;
 pub(crate) mod tty;
 pub(crate) mod units;
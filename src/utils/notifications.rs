@@ -1,6 +1,7 @@
 use std::fmt::{self, Display};
 use std::path::Path;
 
+use download::Backend;
 use url::Url;
 
 use crate::utils::notify::NotificationLevel;
@@ -34,12 +35,19 @@ pub enum Notification<'a> {
     Error(String),
     UsingCurl,
     UsingReqwest,
+    /// The download failed in a way that another backend may cope with, and
+    /// is being retried with that backend.
+    DownloadBackendFallback(Backend, Backend),
+    /// A download succeeded after falling back to another backend.
+    DownloadBackendSucceeded(Backend),
     /// Renaming encountered a file in use error and is retrying.
     /// The InUse aspect is a heuristic - the OS specifies
     /// Permission denied, but as we work in users home dirs and
     /// running programs like virus scanner are known to cause this
     /// the heuristic is quite good.
     RenameInUse(&'a Path, &'a Path),
+    /// Another rustup process holds a lock this one needs.
+    WaitingForLock(&'a str, &'a Path),
 }
 
 impl<'a> Notification<'a> {
@@ -60,11 +68,33 @@ impl<'a> Notification<'a> {
             | ResumingPartialDownload
             | UsingCurl
             | UsingReqwest => NotificationLevel::Verbose,
-            RenameInUse(_, _) => NotificationLevel::Info,
+            RenameInUse(_, _)
+            | DownloadBackendFallback(_, _)
+            | DownloadBackendSucceeded(_)
+            | WaitingForLock(_, _) => NotificationLevel::Info,
             NoCanonicalPath(_) => NotificationLevel::Warn,
             Error(_) => NotificationLevel::Error,
         }
     }
+
+    /// The part of rustup the notification is logged as coming from.
+    pub(crate) fn target(&self) -> &'static str {
+        use self::Notification::*;
+        match self {
+            DownloadingFile(_, _)
+            | DownloadContentLengthReceived(_)
+            | DownloadDataReceived(_)
+            | DownloadFinished
+            | DownloadPushUnit(_)
+            | DownloadPopUnit
+            | ResumingPartialDownload
+            | UsingCurl
+            | UsingReqwest
+            | DownloadBackendFallback(_, _)
+            | DownloadBackendSucceeded(_) => "download",
+            _ => "rustup",
+        }
+    }
 }
 
 impl<'a> Display for Notification<'a> {
@@ -101,6 +131,16 @@ impl<'a> Display for Notification<'a> {
             ResumingPartialDownload => write!(f, "resuming partial download"),
             UsingCurl => write!(f, "downloading with curl"),
             UsingReqwest => write!(f, "downloading with reqwest"),
+            DownloadBackendFallback(from, to) => {
+                write!(f, "download with {} failed, retrying with {}", from, to)
+            }
+            DownloadBackendSucceeded(backend) => write!(f, "download succeeded with {}", backend),
+            WaitingForLock(name, path) => write!(
+                f,
+                "waiting for another rustup process to release the {} lock: '{}'",
+                name,
+                path.display()
+            ),
         }
     }
 }
@@ -247,6 +247,24 @@ pub(crate) fn copy_dir(src: &Path, dest: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Like `copy_dir`, but hard links files rather than copying them wherever
+/// the filesystem allows it.
+pub(crate) fn hardlink_dir(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir(dest)?;
+    for entry in src.read_dir()? {
+        let entry = entry?;
+        let kind = entry.file_type()?;
+        let src = entry.path();
+        let dest = dest.join(entry.file_name());
+        if kind.is_dir() {
+            hardlink_dir(&src, &dest)?;
+        } else if fs::hard_link(&src, &dest).is_err() {
+            fs::copy(&src, &dest)?;
+        }
+    }
+    Ok(())
+}
+
 fn has_cmd(cmd: &str) -> bool {
     let cmd = format!("{}{}", cmd, env::consts::EXE_SUFFIX);
     let path = process().var_os("PATH").unwrap_or_default();
@@ -0,0 +1,187 @@
+//! Writing a TOML table back over the document it was read from, so that
+//! the comments and layout someone gave the file survive the changes
+//! `rustup` makes to it.
+//!
+//! The document is edited with `toml_edit`: keys which are gone are removed,
+//! values which changed are replaced where they are, keeping any comment on
+//! their line, and keys which are new go at the end of their table, with new
+//! tables at the end of the document. Should the edited document not read
+//! back as the table, the table is written out afresh instead.
+
+use toml::value::Table;
+use toml::Value;
+use toml_edit::{ArrayOfTables, Document, Item};
+
+/// `table` written as a document, keeping what it can of `original`.
+pub(crate) fn rewrite(original: &str, table: &Table) -> String {
+    let fresh = || Value::Table(table.clone()).to_string();
+    if original.trim().is_empty() {
+        return fresh();
+    }
+    let (old, mut document) = match (
+        toml::from_str::<Table>(original),
+        original.parse::<Document>(),
+    ) {
+        (Ok(old), Ok(document)) => (old, document),
+        _ => return fresh(),
+    };
+    if edit(document.as_table_mut(), &old, table).is_none() {
+        return fresh();
+    }
+    let edited = document.to_string();
+    match toml::from_str::<Table>(&edited) {
+        Ok(read_back) if read_back == *table => edited,
+        _ => fresh(),
+    }
+}
+
+/// Whether `value` is written as a table of its own, or several, rather than
+/// on the line of its key.
+fn is_table(value: &Value) -> bool {
+    match value {
+        Value::Table(_) => true,
+        Value::Array(array) => array.iter().any(Value::is_table),
+        _ => false,
+    }
+}
+
+/// Makes `document`, which reads as `old`, read as `new`.
+fn edit(document: &mut toml_edit::Table, old: &Table, new: &Table) -> Option<()> {
+    for name in old.keys().filter(|name| !new.contains_key(*name)) {
+        document.remove(name);
+    }
+    for (name, value) in new {
+        let old_value = old.get(name);
+        if old_value == Some(value) {
+            continue;
+        }
+        match (document.get_mut(name), old_value, value) {
+            (Some(Item::Table(table)), Some(Value::Table(old)), Value::Table(new)) => {
+                edit(table, old, new)?;
+                continue;
+            }
+            (Some(Item::Value(existing)), _, value) if !is_table(value) => {
+                let mut replacement = to_value(value)?;
+                *replacement.decor_mut() = existing.decor().clone();
+                *existing = replacement;
+                continue;
+            }
+            _ => {}
+        }
+        // Anything else, such as a value which became a table, is written
+        // anew in its place.
+        document.insert(name, to_item(value)?);
+    }
+    Some(())
+}
+
+fn to_item(value: &Value) -> Option<Item> {
+    match value {
+        Value::Table(table) => {
+            let mut result = toml_edit::Table::new();
+            // So that a table holding only tables gets no header of its own.
+            result.set_implicit(true);
+            for (name, value) in table {
+                result.insert(name, to_item(value)?);
+            }
+            Some(Item::Table(result))
+        }
+        Value::Array(array) if is_table(value) => {
+            let mut result = ArrayOfTables::new();
+            for value in array {
+                result.push(to_item(value)?.into_table().ok()?);
+            }
+            Some(Item::ArrayOfTables(result))
+        }
+        value => to_value(value).map(Item::Value),
+    }
+}
+
+/// A value which isn't a table, as written on the line of its key.
+fn to_value(value: &Value) -> Option<toml_edit::Value> {
+    value.to_string().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(document: &str) -> Table {
+        toml::from_str(document).unwrap()
+    }
+
+    #[test]
+    fn keeps_comments_and_order() {
+        let original = r#"# Settings for my machine.
+profile = "minimal" # the smallest
+version = "12"
+auto_self_update = "disable"
+
+# Where to get them.
+[network]
+timeout = 60 # seconds
+mirrors = [
+    "https://one.example.com", # nearest
+]
+
+[overrides]
+# My project.
+"/home/me/project" = "nightly"
+"/home/me/old" = "1.40.0"
+"#;
+        let mut new = table(original);
+        new.insert("profile".to_owned(), Value::String("default".to_owned()));
+        new.insert("shims".to_owned(), Value::String("link".to_owned()));
+        new.remove("auto_self_update");
+        new.insert("aliases".to_owned(), Value::Table(table("mine = \"beta\"")));
+        let network = new["network"].as_table_mut().unwrap();
+        network.insert("timeout".to_owned(), Value::Integer(30));
+        network.insert("ip-version".to_owned(), Value::String("v6".to_owned()));
+        let overrides = new["overrides"].as_table_mut().unwrap();
+        overrides.remove("/home/me/old");
+        overrides.insert("/tmp/x".to_owned(), Value::String("beta".to_owned()));
+
+        assert_eq!(
+            rewrite(original, &new),
+            r#"# Settings for my machine.
+profile = "default" # the smallest
+version = "12"
+shims = "link"
+
+# Where to get them.
+[network]
+timeout = 30 # seconds
+mirrors = [
+    "https://one.example.com", # nearest
+]
+ip-version = "v6"
+
+[overrides]
+# My project.
+"/home/me/project" = "nightly"
+"/tmp/x" = "beta"
+
+[aliases]
+mine = "beta"
+"#
+        );
+    }
+
+    #[test]
+    fn writes_values_which_became_tables_anew() {
+        let original = "# Mine.\nversion = \"12\"\naliases = \"none\"\n";
+        let new = table("version = \"13\"\n[aliases]\nmine = \"beta\"\n");
+        assert_eq!(
+            rewrite(original, &new),
+            "# Mine.\nversion = \"13\"\n\n[aliases]\nmine = \"beta\"\n"
+        );
+    }
+
+    #[test]
+    fn writes_afresh_what_it_cannot_read() {
+        let new = table("version = \"12\"\n");
+        let fresh = Value::Table(new.clone()).to_string();
+        assert_eq!(rewrite("", &new), fresh);
+        assert_eq!(rewrite("a = [\n", &new), fresh);
+    }
+}
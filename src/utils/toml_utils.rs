@@ -35,6 +35,30 @@ pub(crate) fn get_opt_string(
     }
 }
 
+pub(crate) fn get_opt_integer(
+    table: &mut toml::value::Table,
+    key: &str,
+    path: &str,
+) -> Result<Option<i64>> {
+    match table.remove(key) {
+        Some(toml::Value::Integer(i)) => Ok(Some(i)),
+        Some(_) => Err(ExpectedType("integer", path.to_owned() + key).into()),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn get_opt_bool(
+    table: &mut toml::value::Table,
+    key: &str,
+    path: &str,
+) -> Result<Option<bool>> {
+    match table.remove(key) {
+        Some(toml::Value::Boolean(b)) => Ok(Some(b)),
+        Some(_) => Err(ExpectedType("bool", path.to_owned() + key).into()),
+        None => Ok(None),
+    }
+}
+
 pub(crate) fn get_bool(table: &mut toml::value::Table, key: &str, path: &str) -> Result<bool> {
     get_value(table, key, path).and_then(|v| {
         if let toml::Value::Boolean(b) = v {
@@ -19,6 +19,16 @@ pub(crate) fn stdout_isatty() -> bool {
     isatty(winapi::um::winbase::STD_OUTPUT_HANDLE)
 }
 
+#[cfg(unix)]
+pub(crate) fn stdin_isatty() -> bool {
+    isatty(libc::STDIN_FILENO)
+}
+
+#[cfg(windows)]
+pub(crate) fn stdin_isatty() -> bool {
+    isatty(winapi::um::winbase::STD_INPUT_HANDLE)
+}
+
 #[inline]
 #[cfg(unix)]
 fn isatty(fd: libc::c_int) -> bool {
@@ -3,6 +3,8 @@ use std::env;
 use std::fs::{self, File};
 use std::io::{self, BufReader, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Context, Result};
 use retry::delay::{jitter, Fibonacci};
@@ -30,13 +32,18 @@ pub fn ensure_dir_exists<'a, N>(
 where
     N: From<Notification<'a>>,
 {
-    raw::ensure_dir_exists(path, |_| {
+    let missing: Vec<_> = path.ancestors().take_while(|p| !p.exists()).collect();
+    let created = raw::ensure_dir_exists(path, |_| {
         notify_handler(Notification::CreatingDirectory(name, path).into())
     })
     .with_context(|| RustupError::CreatingDirectory {
         name,
         path: PathBuf::from(path),
-    })
+    })?;
+    for dir in missing {
+        share_with_group(dir, false);
+    }
+    Ok(created)
 }
 
 pub(crate) fn open_file(name: &'static str, path: &Path) -> Result<File> {
@@ -57,7 +64,9 @@ pub fn write_file(name: &'static str, path: &Path, contents: &str) -> Result<()>
     raw::write_file(path, contents).with_context(|| RustupError::WritingFile {
         name,
         path: PathBuf::from(path),
-    })
+    })?;
+    share_with_group(path, false);
+    Ok(())
 }
 
 pub(crate) fn append_file(name: &'static str, path: &Path, line: &str) -> Result<()> {
@@ -67,6 +76,39 @@ pub(crate) fn append_file(name: &'static str, path: &Path, line: &str) -> Result
     })
 }
 
+/// Appends `line` to the log at `path`. Once the log has grown to
+/// `max_len`, it is first rotated to `path.1`, the previous `.1` to `.2`,
+/// and so on, keeping `keep` old logs.
+pub(crate) fn append_rotating_log(
+    name: &'static str,
+    path: &Path,
+    line: &str,
+    max_len: u64,
+    keep: usize,
+) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let len = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if len >= max_len {
+        for n in (1..keep).rev() {
+            let from = rotated_log(path, n);
+            if from.exists() {
+                fs::rename(&from, rotated_log(path, n + 1))?;
+            }
+        }
+        fs::rename(path, rotated_log(path, 1))?;
+    }
+    append_file(name, path, line)
+}
+
+/// The log at `path` as `append_rotating_log` rotates it the `n`th time.
+pub(crate) fn rotated_log(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{}", n));
+    path.with_file_name(name)
+}
+
 pub(crate) fn write_line(
     name: &'static str,
     file: &mut File,
@@ -145,10 +187,21 @@ pub fn download_file(
     download_file_with_resume(&url, &path, hasher, false, &notify_handler)
 }
 
-pub(crate) fn download_file_with_resume(
+/// An incremental hash function that downloaded data can be fed through.
+pub(crate) trait StreamHasher: Clone {
+    fn update(&mut self, data: &[u8]);
+}
+
+impl StreamHasher for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(self, data);
+    }
+}
+
+pub(crate) fn download_file_with_resume<H: StreamHasher>(
     url: &Url,
     path: &Path,
-    hasher: Option<&mut Sha256>,
+    hasher: Option<&mut H>,
     resume_from_partial: bool,
     notify_handler: &dyn Fn(Notification<'_>),
 ) -> Result<()> {
@@ -180,16 +233,15 @@ pub(crate) fn download_file_with_resume(
     }
 }
 
-fn download_file_(
+fn download_file_<H: StreamHasher>(
     url: &Url,
     path: &Path,
-    hasher: Option<&mut Sha256>,
+    hasher: Option<&mut H>,
     resume_from_partial: bool,
     notify_handler: &dyn Fn(Notification<'_>),
 ) -> Result<()> {
     use download::download_to_path_with_backend;
-    use download::{Backend, Event, TlsBackend};
-    use sha2::Digest;
+    use download::{Backend, Event};
     use std::cell::RefCell;
 
     notify_handler(Notification::DownloadingFile(url, path));
@@ -222,35 +274,117 @@ fn download_file_(
 
     // Download the file
 
-    // Keep the curl env var around for a bit
-    let use_curl_backend = process().var_os("RUSTUP_USE_CURL").is_some();
-    let use_rustls = process().var_os("RUSTUP_USE_RUSTLS").is_some();
-    let (backend, notification) = if use_curl_backend {
-        (Backend::Curl, Notification::UsingCurl)
-    } else {
-        let tls_backend = if use_rustls {
-            TlsBackend::Rustls
-        } else {
-            #[cfg(feature = "reqwest-default-tls")]
-            {
-                TlsBackend::Default
-            }
-            #[cfg(not(feature = "reqwest-default-tls"))]
-            {
-                TlsBackend::Rustls
-            }
-        };
-        (Backend::Reqwest(tls_backend), Notification::UsingReqwest)
-    };
-    notify_handler(notification);
-    let res =
+    let backend = default_backend();
+    notify_handler(match backend {
+        Backend::Curl => Notification::UsingCurl,
+        Backend::Reqwest(_) => Notification::UsingReqwest,
+    });
+
+    // Snapshot the hash state so that a retry with another backend starts
+    // from the same place as the original attempt.
+    let initial_hasher = hasher.borrow().as_ref().map(|h| (**h).clone());
+
+    let mut res =
         download_to_path_with_backend(backend, url, path, resume_from_partial, Some(callback));
 
+    if let Err(e) = &res {
+        if let Some(fallback) = fallback_backend(backend) {
+            if download::is_backend_specific_error(e) {
+                notify_handler(Notification::DownloadBackendFallback(backend, fallback));
+                if let (Some(h), Some(initial)) = (hasher.borrow_mut().as_mut(), &initial_hasher) {
+                    **h = initial.clone();
+                }
+                res = download_to_path_with_backend(
+                    fallback,
+                    url,
+                    path,
+                    resume_from_partial,
+                    Some(callback),
+                );
+                if res.is_ok() {
+                    notify_handler(Notification::DownloadBackendSucceeded(fallback));
+                }
+            }
+        }
+    }
+
     notify_handler(Notification::DownloadFinished);
 
     res
 }
 
+/// Measures how long `url` takes to answer a request for its headers alone.
+/// Returns `None` if the server couldn't be reached, or doesn't have it.
+pub(crate) fn time_to_respond(url: &Url) -> Option<Duration> {
+    let start = Instant::now();
+    download::probe_with_backend(default_backend(), url)
+        .ok()
+        .map(|()| start.elapsed())
+}
+
+/// The size of what `url` points at, as the server gives it, abandoning the
+/// transfer as soon as it does. `scratch` is written to and then removed.
+/// Returns `None` if the server doesn't say, or couldn't be reached.
+pub(crate) fn remote_size(url: &Url, scratch: &Path) -> Option<u64> {
+    use download::Event;
+    use std::cell::Cell;
+
+    let size = Cell::new(None);
+    let _ = download::download_to_path_with_backend(
+        default_backend(),
+        url,
+        scratch,
+        false,
+        Some(&|event| match event {
+            Event::DownloadContentLengthReceived(len) => {
+                size.set(Some(len));
+                Err(anyhow!("probe complete"))
+            }
+            Event::DownloadDataReceived(_) => Err(anyhow!("probe complete")),
+            _ => Ok(()),
+        }),
+    );
+    size.get()
+}
+
+fn default_backend() -> download::Backend {
+    // Keep the curl env var around for a bit
+    if process().var_os("RUSTUP_USE_CURL").is_some() {
+        download::Backend::Curl
+    } else {
+        download::Backend::Reqwest(tls_backend())
+    }
+}
+
+fn tls_backend() -> download::TlsBackend {
+    use download::TlsBackend;
+
+    if process().var_os("RUSTUP_USE_RUSTLS").is_some() {
+        TlsBackend::Rustls
+    } else {
+        #[cfg(feature = "reqwest-default-tls")]
+        {
+            TlsBackend::Default
+        }
+        #[cfg(not(feature = "reqwest-default-tls"))]
+        {
+            TlsBackend::Rustls
+        }
+    }
+}
+
+/// The backend to retry with when `backend` fails for reasons specific to
+/// it, if rustup was built with more than one backend.
+fn fallback_backend(backend: download::Backend) -> Option<download::Backend> {
+    use download::Backend;
+
+    match backend {
+        Backend::Curl if cfg!(feature = "reqwest-backend") => Some(Backend::Reqwest(tls_backend())),
+        Backend::Reqwest(_) if cfg!(feature = "curl-backend") => Some(Backend::Curl),
+        _ => None,
+    }
+}
+
 pub(crate) fn parse_url(url: &str) -> Result<Url> {
     Url::parse(url).with_context(|| format!("failed to parse url: {}", url))
 }
@@ -304,7 +438,7 @@ pub fn hardlink_file(src: &Path, dest: &Path) -> Result<()> {
 }
 
 #[cfg(unix)]
-fn symlink_file(src: &Path, dest: &Path) -> Result<()> {
+pub(crate) fn symlink_file(src: &Path, dest: &Path) -> Result<()> {
     std::os::unix::fs::symlink(src, dest).with_context(|| RustupError::LinkingFile {
         src: PathBuf::from(src),
         dest: PathBuf::from(dest),
@@ -312,7 +446,7 @@ fn symlink_file(src: &Path, dest: &Path) -> Result<()> {
 }
 
 #[cfg(windows)]
-fn symlink_file(src: &Path, dest: &Path) -> Result<()> {
+pub(crate) fn symlink_file(src: &Path, dest: &Path) -> Result<()> {
     // we are supposed to not use symlink on windows
     Err(anyhow!(RustupError::LinkingFile {
         src: PathBuf::from(src),
@@ -338,6 +472,24 @@ where
     })
 }
 
+pub(crate) fn hardlink_dir<'a, N>(
+    src: &'a Path,
+    dest: &'a Path,
+    notify_handler: &dyn Fn(N),
+) -> Result<()>
+where
+    N: From<Notification<'a>>,
+{
+    notify_handler(Notification::CopyingDirectory(src, dest).into());
+    raw::hardlink_dir(src, dest).with_context(|| {
+        format!(
+            "could not copy directory from '{}' to '{}'",
+            src.display(),
+            dest.display()
+        )
+    })
+}
+
 pub(crate) fn copy_file(src: &Path, dest: &Path) -> Result<()> {
     let metadata = fs::symlink_metadata(src).with_context(|| RustupError::ReadingFile {
         name: "metadata for",
@@ -442,6 +594,24 @@ pub fn file_size(path: &Path) -> Result<u64> {
         .len())
 }
 
+/// The total size of the files under `path`. Symlinks are not followed.
+pub fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path).with_context(|| RustupError::ReadingDirectory {
+        name: "directory",
+        path: PathBuf::from(path),
+    })? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        size += if meta.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            meta.len()
+        };
+    }
+    Ok(size)
+}
+
 pub(crate) fn make_executable(path: &Path) -> Result<()> {
     #[allow(clippy::unnecessary_wraps)]
     #[cfg(windows)]
@@ -504,6 +674,10 @@ pub(crate) fn create_rustup_home() -> Result<()> {
     if process().var_os("RUSTUP_HOME").is_some() {
         return Ok(());
     }
+    // Nor if the XDG layout is in use, which a ~/.rustup would replace.
+    if xdg_layout().is_some() {
+        return Ok(());
+    }
 
     let home = rustup_home_in_user_dir()?;
     fs::create_dir_all(&home).context("unable to create ~/.rustup")?;
@@ -524,6 +698,108 @@ pub(crate) fn rustup_home() -> Result<PathBuf> {
     home::rustup_home_from(&home_process()).context("failed to determine rustup home dir")
 }
 
+/// Where rustup keeps things in the opt-in XDG base directory layout: the
+/// settings under the config directory, downloads and other caches under the
+/// cache directory, and the toolchains and everything else under the data
+/// directory.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct XdgDirs {
+    pub(crate) config: PathBuf,
+    pub(crate) cache: PathBuf,
+    pub(crate) data: PathBuf,
+}
+
+impl XdgDirs {
+    /// The directories from `XDG_CONFIG_HOME`, `XDG_CACHE_HOME` and
+    /// `XDG_DATA_HOME`, or their defaults under the home directory where
+    /// those aren't set or, as the specification has it, aren't absolute.
+    pub(crate) fn from_env() -> Option<Self> {
+        let home = home_dir()?;
+        let dir = |var: &str, default: &str| {
+            process()
+                .var_os(var)
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .unwrap_or_else(|| home.join(default))
+                .join("rustup")
+        };
+        Some(Self {
+            config: dir("XDG_CONFIG_HOME", ".config"),
+            cache: dir("XDG_CACHE_HOME", ".cache"),
+            data: dir("XDG_DATA_HOME", ".local/share"),
+        })
+    }
+}
+
+/// The XDG layout, if it is in use: RUSTUP_HOME isn't set, there is no
+/// ~/.rustup, and `rustup migrate xdg` has made the data directory. It is
+/// only ever used on Unix.
+pub(crate) fn xdg_layout() -> Option<XdgDirs> {
+    if cfg!(windows) || process().var_os("RUSTUP_HOME").map(|home| !home.is_empty()) == Some(true) {
+        return None;
+    }
+    if rustup_home_in_user_dir()
+        .map(|home| home.exists())
+        .unwrap_or(true)
+    {
+        return None;
+    }
+    XdgDirs::from_env().filter(|dirs| is_directory(&dirs.data))
+}
+
+/// Whether the rustup home is shared with its group, which
+/// `share_with_group_if_shared` finds out.
+static SHARED_WITH_GROUP: AtomicBool = AtomicBool::new(false);
+
+/// A rustup home whose group may write to it is shared between users, so
+/// what this process creates there is to be made writable by the group as
+/// well, by `share_with_group`.
+pub(crate) fn share_with_group_if_shared(home: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let shared = fs::metadata(home)
+            .map(|m| m.permissions().mode() & 0o020 != 0)
+            .unwrap_or(false);
+        SHARED_WITH_GROUP.store(shared, Ordering::Relaxed);
+    }
+    #[cfg(not(unix))]
+    let _ = home;
+}
+
+/// Gives the group of a shared rustup home the same access to `path`, which
+/// this process created, as its owner has, and, with `recursive`, to
+/// everything in it. This is done to each path rather than through the
+/// umask, which would change it for the whole process. A path that can't be
+/// changed, such as one another user owns, is left as it is.
+pub(crate) fn share_with_group(path: &Path, recursive: bool) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if !SHARED_WITH_GROUP.load(Ordering::Relaxed) {
+            return;
+        }
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) if !metadata.file_type().is_symlink() => metadata,
+            _ => return,
+        };
+        let mode = metadata.permissions().mode();
+        let shared = mode | (mode & 0o700) >> 3;
+        if shared != mode {
+            let _ = fs::set_permissions(path, fs::Permissions::from_mode(shared));
+        }
+        if recursive && metadata.is_dir() {
+            for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+                share_with_group(&entry.path(), true);
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (path, recursive);
+}
+
 pub(crate) fn format_path_for_display(path: &str) -> String {
     let unc_present = path.find(r"\\?\");
 
@@ -592,6 +868,15 @@ where
     }
 }
 
+static PERMIT_COPY_RENAME: AtomicBool = AtomicBool::new(false);
+
+/// Lets renames fall back on copying, as RUSTUP_PERMIT_COPY_RENAME does,
+/// for the rest of the process, such as when the temporary files are kept
+/// on another filesystem from the toolchains.
+pub(crate) fn permit_copy_rename() {
+    PERMIT_COPY_RENAME.store(true, Ordering::Relaxed);
+}
+
 fn rename<'a, N>(
     name: &'static str,
     src: &'a Path,
@@ -618,7 +903,8 @@ where
                 }
                 #[cfg(target_os = "linux")]
                 io::ErrorKind::Other
-                    if process().var_os("RUSTUP_PERMIT_COPY_RENAME").is_some()
+                    if (PERMIT_COPY_RENAME.load(Ordering::Relaxed)
+                        || process().var_os("RUSTUP_PERMIT_COPY_RENAME").is_some())
                         && Some(EXDEV) == e.raw_os_error() =>
                 {
                     match copy_and_delete(name, src, dest, notify_handler) {
@@ -704,6 +990,27 @@ impl<'a> io::Read for FileReaderWithProgress<'a> {
     }
 }
 
+/// A reader which feeds everything read through it into a hasher, so that a
+/// file can be verified in the same pass that consumes it.
+pub(crate) struct HashingReader<'a, R, H> {
+    inner: R,
+    hasher: &'a mut H,
+}
+
+impl<'a, R: io::Read, H: StreamHasher> HashingReader<'a, R, H> {
+    pub(crate) fn new(inner: R, hasher: &'a mut H) -> Self {
+        HashingReader { inner, hasher }
+    }
+}
+
+impl<'a, R: io::Read, H: StreamHasher> io::Read for HashingReader<'a, R, H> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let nbytes = self.inner.read(buf)?;
+        self.hasher.update(&buf[..nbytes]);
+        Ok(nbytes)
+    }
+}
+
 // search user database to get home dir of euid user
 #[cfg(unix)]
 pub(crate) fn home_dir_from_passwd() -> Option<PathBuf> {
@@ -207,6 +207,121 @@ nightly-{0} - Update available : 1.2.0 (hash-nightly-1) -> 1.3.0 (hash-nightly-2
     })
 }
 
+#[test]
+fn check_updates_json() {
+    check_update_setup(&|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "update", "stable"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_stdout_ok(
+            config,
+            &["rustup", "check", "--format", "json"],
+            for_host!(
+                r#"  "toolchains": [
+    {{
+      "name": "stable-{0}",
+      "current_version": "1.0.0 (hash-stable-1.0.0)",
+      "current_date": "2015-01-01",
+      "update_available": true,
+      "update_version": "1.1.0 (hash-stable-1.1.0)",
+      "update_date": "2015-01-02"
+    }}
+  ],
+"#
+            ),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "--format", "json", "check"],
+            &format!(
+                r#"  "rustup": {{
+    "name": "rustup",
+    "current_version": "{}",
+    "update_available": false,
+    "update_version": null,
+    "self_update_available": false,
+    "auto_self_update": "disable"
+  }}
+}}
+"#,
+                env!("CARGO_PKG_VERSION")
+            ),
+        );
+    })
+}
+
+#[test]
+fn status() {
+    check_update_setup(&|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "default", "stable"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_stdout_ok(
+            config,
+            &["rustup", "status"],
+            for_host!(
+                r"active toolchain: stable-{0} (default)
+updates:
+  stable-{0}: update available (1.0.0 (hash-stable-1.0.0) -> 1.1.0 (hash-stable-1.1.0))
+"
+            ),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "status"],
+            &format!("  rustup: up to date ({})\n", env!("CARGO_PKG_VERSION")),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "status"],
+            "  downloads: 0 B\n  temporary files: 0 B\n",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "--format", "json", "status"],
+            for_host!(
+                r#"  "active_toolchain": {{
+    "name": "stable-{0}",
+    "reason": "default",
+    "installed": true
+  }},
+  "toolchains": [
+    {{
+      "name": "stable-{0}",
+      "current_version": "1.0.0 (hash-stable-1.0.0)",
+      "update_available": true,
+      "update_version": "1.1.0 (hash-stable-1.1.0)",
+      "error": null
+    }}
+  ],
+"#
+            ),
+        );
+    })
+}
+
+#[test]
+fn check_updates_self_json() {
+    let test_version = "2.0.0";
+
+    self_update_setup(
+        &|config, _| {
+            expect_stdout_ok(
+                config,
+                &["rustup", "check", "--format", "json"],
+                &format!(
+                    r#"    "update_available": true,
+    "update_version": "{}",
+    "self_update_available": true,
+"#,
+                    test_version
+                ),
+            );
+        },
+        test_version,
+    )
+}
+
 #[test]
 fn check_updates_self() {
     let test_version = "2.0.0";
@@ -534,8 +649,7 @@ fn list_overrides_with_nonexistent() {
                 path_formatted + " (not a directory)",
                 &format!("nightly-{}", trip)
             ),
-            "info: you may remove overrides for non-existent directories with
-`rustup override unset --nonexistent`\n",
+            "info: you may remove stale overrides with `rustup override prune`\n",
         );
     });
 }
@@ -48,6 +48,7 @@ fn run_input_with_env(
 
     SanitizedOutput {
         ok: out.status.success(),
+        status: out.status.code(),
         stdout: String::from_utf8(out.stdout).unwrap(),
         stderr: String::from_utf8(out.stderr).unwrap(),
     }
@@ -12,8 +12,8 @@ use rustup::utils::utils;
 
 use crate::mock::clitools::{
     self, expect_component_executable, expect_component_not_executable, expect_err,
-    expect_not_stderr_ok, expect_ok, expect_ok_contains, expect_ok_eq, expect_ok_ex,
-    expect_stderr_ok, expect_stdout_ok, run, set_current_dist_date, Config, Scenario,
+    expect_not_stderr_ok, expect_not_stdout_ok, expect_ok, expect_ok_contains, expect_ok_eq,
+    expect_ok_ex, expect_stderr_ok, expect_stdout_ok, run, set_current_dist_date, Config, Scenario,
 };
 
 pub fn setup(f: &dyn Fn(&mut Config)) {
@@ -494,6 +494,36 @@ fn run_rls_when_not_installed() {
     });
 }
 
+#[test]
+fn auto_install_components() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        // Without a terminal to ask at, the default is only to say how.
+        expect_err(
+            config,
+            &["rls", "--version"],
+            "To install, run `rustup component add rls`",
+        );
+
+        expect_ok(
+            config,
+            &["rustup", "set", "auto-install-components", "never"],
+        );
+        expect_err(config, &["rls", "--version"], "is not installed");
+
+        expect_ok(
+            config,
+            &["rustup", "set", "auto-install-components", "always"],
+        );
+        expect_ok(config, &["rls", "--version"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list", "--installed"],
+            "rls-",
+        );
+    });
+}
+
 #[test]
 fn run_rust_lldb_when_not_in_toolchain() {
     clitools::setup(Scenario::UnavailableRls, &|config| {
@@ -767,6 +797,84 @@ fn completion_default() {
     });
 }
 
+#[test]
+fn completion_rustup_adds_hook() {
+    setup(&|config| {
+        expect_stdout_ok(
+            config,
+            &["rustup", "completions", "bash"],
+            "complete -F _rustup_values -o bashdefault -o default rustup",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "completions", "fish"],
+            "(__rustup_values installed-targets)",
+        );
+    });
+}
+
+#[test]
+fn completion_lists_toolchains() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "list-completions", "toolchains"],
+            for_host!("nightly\nnightly-{}\n"),
+        );
+    });
+}
+
+#[test]
+fn completion_lists_components_and_targets() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "list-completions", "components"],
+            "rls\n",
+        );
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "list-completions", "components"],
+            "rust-std",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "list-completions", "installed-components"],
+            "rustc\n",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "list-completions", "targets"],
+            clitools::CROSS_ARCH1,
+        );
+        expect_ok(config, &["rustup", "target", "add", clitools::CROSS_ARCH1]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "list-completions", "installed-targets"],
+            clitools::CROSS_ARCH1,
+        );
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "list-completions", "targets"],
+            clitools::CROSS_ARCH1,
+        );
+    });
+}
+
+#[test]
+fn completion_lists_nothing_without_toolchain() {
+    setup(&|config| {
+        expect_ok_ex(
+            config,
+            &["rustup", "list-completions", "components"],
+            "",
+            "",
+        );
+    });
+}
+
 #[test]
 fn completion_bad_shell() {
     setup(&|config| {
@@ -860,6 +968,134 @@ fn which() {
     });
 }
 
+#[test]
+fn which_all() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let out = run(config, "rustup", &["which", "--all"], &[]);
+        assert!(out.ok);
+        let rustc = out
+            .stdout
+            .lines()
+            .find(|line| line.starts_with("rustc "))
+            .unwrap();
+        assert!(rustc.contains(for_host!("nightly-{}")));
+        assert!(out.stdout.lines().any(|line| line.starts_with("cargo ")));
+        // The mock toolchain has no rls.
+        assert!(!out.stdout.lines().any(|line| line.starts_with("rls ")));
+    });
+}
+
+#[test]
+fn which_trace() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "which", "--trace", "rustc"],
+            for_host!("toolchain: nightly-{} (default toolchain)"),
+        );
+        expect_ok(config, &["rustup", "override", "set", "stable"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "which", "--trace", "rustc"],
+            "(directory override for",
+        );
+
+        let path = config.customdir.join("custom-1");
+        let path = path.to_string_lossy();
+        expect_ok(config, &["rustup", "toolchain", "link", "custom", &path]);
+        expect_stdout_ok(
+            config,
+            &[
+                "rustup",
+                "which",
+                "--trace",
+                "--toolchain",
+                "custom",
+                "cargo",
+            ],
+            for_host!("cargo: 'custom' has no cargo, so the one from 'nightly-{}' is used"),
+        );
+        expect_stdout_ok(
+            config,
+            &[
+                "rustup",
+                "which",
+                "--trace",
+                "--toolchain",
+                "custom",
+                "cargo",
+            ],
+            "toolchain: custom (given by --toolchain)",
+        );
+    });
+}
+
+#[test]
+fn which_chain() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let out = run(config, "rustup", &["which", "--chain", "rustc"], &[]);
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.contains("command: "));
+        assert!(out
+            .stdout
+            .contains(for_host!("toolchain: nightly-{} (default toolchain)")));
+        assert!(out.stdout.contains(&format!(
+            "binary: {}",
+            config
+                .rustupdir
+                .join("toolchains")
+                .join(for_host!("nightly-{}"))
+                .join(format!("bin/rustc{}", EXE_SUFFIX))
+                .display()
+        )));
+
+        let path = config.customdir.join("custom-1");
+        let path = path.to_string_lossy();
+        expect_ok(config, &["rustup", "toolchain", "link", "custom", &path]);
+        let out = run(
+            config,
+            "rustup",
+            &[
+                "which",
+                "--chain",
+                "--format",
+                "json",
+                "--toolchain",
+                "custom",
+                "cargo",
+            ],
+            &[],
+        );
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.contains("\"version\": 1"));
+        assert!(out.stdout.contains("\"reason\": \"given by --toolchain\""));
+        assert!(out
+            .stdout
+            .contains(for_host!("\"binary_toolchain\": \"nightly-{}\"")));
+
+        // As do the other commands, it honours the global format.
+        let out = run(
+            config,
+            "rustup",
+            &["which", "--chain", "rustc"],
+            &[("RUSTUP_FORMAT", "json")],
+        );
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.starts_with("{\n  \"version\": 1,"));
+        let out = run(
+            config,
+            "rustup",
+            &["--format", "json", "which", "--chain", "rustc"],
+            &[],
+        );
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.contains("\"tool\": \"rustc\""));
+    });
+}
+
 #[test]
 fn override_by_toolchain_on_the_command_line() {
     setup(&|config| {
@@ -981,3 +1217,219 @@ fn deprecated_interfaces() {
         );
     })
 }
+
+#[test]
+#[cfg(unix)]
+fn wsl_shims() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    setup(&|config| {
+        let windows_home = config.homedir.join("windows-cargo");
+        let bin = windows_home.join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join("rustup.exe"), "").unwrap();
+        let cargo = bin.join("cargo.exe");
+        fs::write(&cargo, "#!/bin/sh\necho \"args: $* wslenv: $WSLENV\"").unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+        let windows_home = windows_home.to_str().unwrap();
+        let dir = config.homedir.join("wsl-bin");
+        let args = &[
+            "wsl-shims",
+            "install",
+            windows_home,
+            "--dir",
+            dir.to_str().unwrap(),
+        ];
+
+        let out = run(config, "rustup", args, &[]);
+        assert!(!out.ok);
+        assert!(out.stderr.contains("can only be installed from inside WSL"));
+
+        let out = run(config, "rustup", args, &[("WSL_DISTRO_NAME", "Test")]);
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stderr.contains("installed 1 shims"));
+        assert!(!dir.join("rustup").exists());
+
+        // Without wslpath the arguments are passed on as they are.
+        let out = Command::new(dir.join("cargo"))
+            .args(&["build", "/some/path"])
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8(out.stdout).unwrap();
+        assert!(stdout.contains("args: build /some/path"));
+        assert!(stdout.contains("RUSTUP_TOOLCHAIN"));
+
+        let args = &[
+            "rustup",
+            "wsl-shims",
+            "remove",
+            "--dir",
+            dir.to_str().unwrap(),
+        ];
+        expect_stderr_ok(config, args, "removed 1 shims");
+        assert!(!dir.join("cargo").exists());
+    });
+}
+
+#[test]
+fn ui_needs_a_terminal() {
+    setup(&|config| {
+        expect_err(config, &["rustup", "ui"], "rustup ui needs a terminal");
+    });
+}
+
+#[test]
+fn rustup_log_filters_by_target() {
+    setup(&|config| {
+        let out = run(
+            config,
+            "rustup",
+            &["default", "nightly"],
+            &[("RUSTUP_LOG", "warn,dist=debug")],
+        );
+        assert!(out.ok);
+        assert!(out.stderr.contains("info: syncing channel updates"));
+        assert!(out.stderr.contains("verbose: checksum passed"));
+        assert!(!out.stderr.contains("info: default toolchain set"));
+        assert!(!out.stderr.contains("verbose: toolchain 'nightly"));
+    });
+}
+
+#[test]
+fn rustup_log_file_has_json_lines() {
+    setup(&|config| {
+        let log = config.homedir.join("rustup.log");
+        let out = run(
+            config,
+            "rustup",
+            &["default", "nightly"],
+            &[("RUSTUP_LOG_FILE", log.to_str().unwrap())],
+        );
+        assert!(out.ok);
+        let log = std::fs::read_to_string(&log).unwrap();
+        let records: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(records.iter().any(|record| record["level"] == "info"
+            && record["target"] == "dist"
+            && record["message"] == "downloading component 'rustc'"));
+        assert!(records.iter().all(|record| record["timestamp"].is_string()));
+    });
+}
+
+#[test]
+fn color_follows_flag_and_environment() {
+    setup(&|config| {
+        let colored = |args: &[&str], env: &[(&str, &str)]| {
+            let out = run(config, "rustup", args, env);
+            assert!(out.ok);
+            out.stderr.contains('\x1b')
+        };
+        let set_profile = ["set", "profile", "minimal"];
+        assert!(!colored(&set_profile, &[]));
+        assert!(colored(
+            &["--color", "always", "set", "profile", "minimal"],
+            &[]
+        ));
+        assert!(colored(&set_profile, &[("CLICOLOR_FORCE", "1")]));
+        assert!(!colored(&set_profile, &[("CLICOLOR_FORCE", "0")]));
+        assert!(!colored(
+            &set_profile,
+            &[("CLICOLOR_FORCE", "1"), ("NO_COLOR", "1")]
+        ));
+        assert!(!colored(
+            &["set", "profile", "minimal", "--color", "never"],
+            &[("CLICOLOR_FORCE", "1")]
+        ));
+    });
+}
+
+#[test]
+fn set_color_theme() {
+    setup(&|config| {
+        let colors = |config: &Config| {
+            let out = run(config, "rustup", &["doctor"], &[("CLICOLOR_FORCE", "1")]);
+            out.stdout.contains("\x1b[3") || out.stdout.contains("\x1b[9")
+        };
+        assert!(colors(config));
+        expect_stderr_ok(
+            config,
+            &["rustup", "set", "color-theme", "monochrome"],
+            "color theme set to 'monochrome'",
+        );
+        assert!(!colors(config));
+        expect_err(
+            config,
+            &["rustup", "set", "color-theme", "pastel"],
+            "'pastel' isn't a valid value",
+        );
+    });
+}
+
+#[test]
+fn config_get_set_unset() {
+    setup(&|config| {
+        expect_stderr_ok(
+            config,
+            &["rustup", "config", "set", "profile", "minimal"],
+            "profile set to 'minimal'",
+        );
+        expect_stdout_ok(config, &["rustup", "config", "get", "profile"], "minimal");
+        expect_stdout_ok(config, &["rustup", "config", "list"], "profile = minimal");
+        expect_stdout_ok(
+            config,
+            &["rustup", "config", "list", "--all"],
+            "color_theme (not set)",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "--format", "json", "config", "list"],
+            "\"profile\": \"minimal\"",
+        );
+
+        expect_ok(config, &["rustup", "config", "unset", "profile"]);
+        let out = run(config, "rustup", &["config", "get", "profile"], &[]);
+        assert!(!out.ok);
+        assert_eq!(out.stdout, "");
+    });
+}
+
+#[test]
+fn config_set_checks_values() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "config", "set", "profile", "minimal"]);
+        expect_err(
+            config,
+            &["rustup", "config", "set", "profile", "tiny"],
+            "unknown profile name: 'tiny'",
+        );
+        expect_err(
+            config,
+            &["rustup", "config", "set", "override_hook.ttl", "soon"],
+            "expected a number of seconds",
+        );
+        expect_err(
+            config,
+            &["rustup", "config", "set", "colour_theme", "monochrome"],
+            "'colour_theme' isn't a valid value",
+        );
+        expect_stdout_ok(config, &["rustup", "config", "get", "profile"], "minimal");
+    });
+}
+
+#[test]
+fn non_interactive_ui_is_refused() {
+    setup(&|config| {
+        let out = run(
+            config,
+            "rustup",
+            &["ui"],
+            &[("RUSTUP_NON_INTERACTIVE", "1")],
+        );
+        assert!(!out.ok);
+        assert!(out.stderr.contains("[reason: terminal-required]"));
+    });
+}
@@ -4,7 +4,7 @@ pub mod mock;
 
 use std::env::consts::EXE_SUFFIX;
 use std::fs;
-use std::path::{PathBuf, MAIN_SEPARATOR};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
 
 use rustup::for_host;
 use rustup::test::this_host_triple;
@@ -27,13 +27,29 @@ pub fn setup(f: &dyn Fn(&Config)) {
     });
 }
 
+/// Like `expect_ok_ex`, but checking only what `rustup update` prints after
+/// the changes it is about to make, as the download sizes there vary with
+/// the mock archives.
+fn expect_update_ok_ex(config: &Config, args: &[&str], stdout: &str, stderr: &str) {
+    let out = run(config, args[0], &args[1..], &[]);
+    let summary = out
+        .stdout
+        .rsplit("download size: ")
+        .next()
+        .and_then(|rest| rest.split_once('\n'))
+        .map(|(_, summary)| summary);
+    assert!(out.ok);
+    assert_eq!(summary, Some(stdout));
+    assert_eq!(out.stderr, stderr);
+}
+
 #[test]
 fn rustup_stable() {
     setup(&|config| {
         set_current_dist_date(config, "2015-01-01");
         expect_ok(config, &["rustup", "update", "stable"]);
         set_current_dist_date(config, "2015-01-02");
-        expect_ok_ex(
+        expect_update_ok_ex(
             config,
             &["rustup", "update"],
             for_host!(
@@ -70,7 +86,7 @@ fn rustup_stable_quiet() {
         set_current_dist_date(config, "2015-01-01");
         expect_ok(config, &["rustup", "--quiet", "update", "stable"]);
         set_current_dist_date(config, "2015-01-02");
-        expect_ok_ex(
+        expect_update_ok_ex(
             config,
             &["rustup", "--quiet", "update"],
             for_host!(
@@ -124,6 +140,37 @@ info: cleaning up downloads & tmp directories
     });
 }
 
+#[test]
+fn update_dry_run() {
+    setup(&|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "update", "stable"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_stdout_ok(
+            config,
+            &["rustup", "update", "--dry-run"],
+            for_host!(
+                r"stable-{0}:
+  cargo: 1.0.0 (hash-stable-1.0.0) -> 1.1.0 (hash-stable-1.1.0)
+  rust-docs: 1.0.0 (hash-stable-1.0.0) -> 1.1.0 (hash-stable-1.1.0)
+"
+            ),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "update", "--dry-run"],
+            "download size: ",
+        );
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.0.0");
+        expect_ok(config, &["rustup", "update", "--no-self-update"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "update", "--dry-run"],
+            for_host!("stable-{}: up to date"),
+        );
+    });
+}
+
 #[test]
 fn rustup_all_channels() {
     setup(&|config| {
@@ -132,9 +179,9 @@ fn rustup_all_channels() {
         expect_ok(config, &["rustup", "update", "beta"]);
         expect_ok(config, &["rustup", "update", "nightly"]);
         set_current_dist_date(config, "2015-01-02");
-        expect_ok_ex(
+        expect_update_ok_ex(
             config,
-            &["rustup", "update"],
+            &["rustup", "update", "--jobs", "1"],
             for_host!(
                 r"
    stable-{0} updated - 1.1.0 (hash-stable-1.1.0) (from 1.0.0 (hash-stable-1.0.0))
@@ -202,9 +249,9 @@ fn rustup_some_channels_up_to_date() {
         expect_ok(config, &["rustup", "update", "nightly"]);
         set_current_dist_date(config, "2015-01-02");
         expect_ok(config, &["rustup", "update", "beta"]);
-        expect_ok_ex(
+        expect_update_ok_ex(
             config,
-            &["rustup", "update"],
+            &["rustup", "update", "--jobs", "1"],
             for_host!(
                 r"
    stable-{0} updated - 1.1.0 (hash-stable-1.1.0) (from 1.0.0 (hash-stable-1.0.0))
@@ -250,6 +297,39 @@ info: cleaning up downloads & tmp directories
     })
 }
 
+#[test]
+fn rustup_all_channels_in_parallel() {
+    setup(&|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "update", "stable"]);
+        expect_ok(config, &["rustup", "update", "beta"]);
+        expect_ok(config, &["rustup", "update", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_stdout_ok(
+            config,
+            &["rustup", "update", "--jobs", "2"],
+            for_host!(
+                r"
+   stable-{0} updated - 1.1.0 (hash-stable-1.1.0) (from 1.0.0 (hash-stable-1.0.0))
+     beta-{0} updated - 1.2.0 (hash-beta-1.2.0) (from 1.1.0 (hash-beta-1.1.0))
+  nightly-{0} updated - 1.3.0 (hash-nightly-2) (from 1.2.0 (hash-nightly-1))
+"
+            ),
+        );
+        set_current_dist_date(config, "2015-01-01");
+        expect_stderr_ok(
+            config,
+            &["rustup", "update", "--force"],
+            "info: updating 3 toolchains, 3 at a time",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "run", "nightly", "rustc", "--version"],
+            "hash-nightly-1",
+        );
+    })
+}
+
 #[test]
 fn rustup_no_channels() {
     setup(&|config| {
@@ -340,6 +420,46 @@ fn add_target() {
     });
 }
 
+#[test]
+fn default_targets() {
+    setup(&|config| {
+        let std = |toolchain: &str, target: &str| {
+            format!(
+                "toolchains/{}-{}/lib/rustlib/{}/lib/libstd.rlib",
+                toolchain,
+                this_host_triple(),
+                target
+            )
+        };
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok_ex(
+            config,
+            &[
+                "rustup",
+                "set",
+                "default-targets",
+                clitools::CROSS_ARCH1,
+                clitools::CROSS_ARCH2,
+            ],
+            "",
+            &format!(
+                "info: new toolchains also get {}, {}\n",
+                clitools::CROSS_ARCH1,
+                clitools::CROSS_ARCH2
+            ),
+        );
+        expect_ok(config, &["rustup", "update", "stable"]);
+        assert!(!config.rustupdir.has(&std("stable", clitools::CROSS_ARCH1)));
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        assert!(config.rustupdir.has(&std("nightly", clitools::CROSS_ARCH1)));
+        assert!(config.rustupdir.has(&std("nightly", clitools::CROSS_ARCH2)));
+
+        expect_ok(config, &["rustup", "config", "unset", "default_targets"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
+        assert!(!config.rustupdir.has(&std("beta", clitools::CROSS_ARCH1)));
+    });
+}
+
 #[test]
 fn remove_target() {
     setup(&|config| {
@@ -1011,6 +1131,61 @@ fn override_set_unset_with_path() {
     });
 }
 
+#[test]
+fn override_prune() {
+    setup(&|config| {
+        let cwd = config.current_dir();
+        let (kept, uninstalled, removed) = (cwd.join("kept"), cwd.join("old"), cwd.join("gone"));
+        for (dir, toolchain) in &[
+            (&kept, "nightly"),
+            (&uninstalled, "stable"),
+            (&removed, "beta"),
+        ] {
+            fs::create_dir_all(dir).unwrap();
+            let dir = dir.to_str().unwrap();
+            expect_ok(
+                config,
+                &["rustup", "override", "set", toolchain, "--path", dir],
+            );
+        }
+        expect_ok(config, &["rustup", "toolchain", "uninstall", "stable"]);
+        fs::remove_dir(&removed).unwrap();
+
+        expect_stdout_ok(config, &["rustup", "override", "list"], " (not installed)");
+        expect_stdout_ok(
+            config,
+            &["rustup", "override", "list"],
+            " (not a directory)",
+        );
+        expect_stderr_ok(
+            config,
+            &["rustup", "override", "prune"],
+            "run `rustup override prune --yes` to go ahead",
+        );
+        expect_err(
+            config,
+            &["rustup", "--non-interactive", "override", "prune"],
+            "[reason: confirmation-required]",
+        );
+        expect_stdout_ok(config, &["rustup", "override", "list"], "beta");
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "override", "prune", "--yes"],
+            "removed 2 stale overrides",
+        );
+        let out = run(config, "rustup", &["override", "list"], &[]);
+        assert!(out.stdout.contains("nightly"));
+        assert!(!out.stdout.contains("stable"));
+        assert!(!out.stdout.contains("beta"));
+        expect_stderr_ok(
+            config,
+            &["rustup", "override", "prune"],
+            "no stale overrides",
+        );
+    });
+}
+
 #[test]
 fn show_toolchain_env() {
     setup(&|config| {
@@ -1155,1050 +1330,3949 @@ fn set_default_host_invalid_triple_valid_partial() {
     });
 }
 
-// #422
 #[test]
-fn update_doesnt_update_non_tracking_channels() {
+fn set_ip_version() {
     setup(&|config| {
+        expect_stderr_ok(
+            config,
+            &["rustup", "set", "ip-version", "v4"],
+            "download IP version set to 'v4'",
+        );
+        let settings = fs::read_to_string(config.rustupdir.join("settings.toml")).unwrap();
+        assert!(settings.contains("[network]\nip-version = \"v4\""));
+        // Downloads still work with the address family restricted.
         expect_ok(config, &["rustup", "default", "nightly"]);
-        expect_ok(config, &["rustup", "update", "nightly-2015-01-01"]);
-        let mut cmd = clitools::cmd(config, "rustup", &["update"]);
-        clitools::env(config, &mut cmd);
-        let out = cmd.output().unwrap();
-        let stderr = String::from_utf8(out.stderr).unwrap();
-        assert!(!stderr.contains(for_host!(
-            "syncing channel updates for 'nightly-2015-01-01-{}'"
-        )));
     });
 }
 
 #[test]
-fn toolchain_install_is_like_update() {
+fn set_ip_version_invalid() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
-        expect_stdout_ok(
+        expect_err(
             config,
-            &["rustup", "run", "nightly", "rustc", "--version"],
-            "hash-nightly-2",
+            &["rustup", "set", "ip-version", "v5"],
+            "'v5' isn't a valid value",
         );
     });
 }
 
 #[test]
-fn toolchain_install_is_like_update_quiet() {
+fn network_mirrors() {
     setup(&|config| {
+        let mirror = format!("file://{}", config.distdir.to_string_lossy());
         expect_ok(
             config,
-            &["rustup", "--quiet", "toolchain", "install", "nightly"],
+            &["rustup", "config", "set", "network.mirrors", &mirror],
         );
+        expect_ok(config, &["rustup", "config", "set", "network.retries", "1"]);
+        expect_stdout_ok(config, &["rustup", "config", "get", "network.retries"], "1");
+        expect_err(
+            config,
+            &["rustup", "config", "set", "network.timeout", "0"],
+            "invalid value '0' for 'network.timeout'",
+        );
+
+        // Everything comes from the mirror while the dist server is down.
+        let down = config.homedir.join("down");
+        let env = [(
+            "RUSTUP_DIST_SERVER",
+            format!("file://{}", down.to_string_lossy()),
+        )];
+        let env: Vec<_> = env.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        let out = run(config, "rustup", &["toolchain", "install", "nightly"], &env);
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stderr.contains("switching to mirror"), "{}", out.stderr);
         expect_stdout_ok(
             config,
-            &["rustup", "run", "nightly", "rustc", "--version"],
+            &["rustc", "+nightly", "--version"],
             "hash-nightly-2",
         );
     });
 }
 
 #[test]
-fn toolchain_install_is_like_update_except_that_bare_install_is_an_error() {
+fn report_network_summarizes_downloads() {
     setup(&|config| {
-        expect_err(
+        expect_stdout_ok(
             config,
-            &["rustup", "toolchain", "install"],
-            "arguments were not provided",
+            &["rustup", "report", "network"],
+            "no downloads recorded in the last 7 days",
+        );
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let log = fs::read_to_string(config.rustupdir.join("logs/downloads.log")).unwrap();
+        assert!(log.contains("\tok\t0\t"));
+        expect_stdout_ok(
+            config,
+            &["rustup", "report", "network"],
+            "Downloads in the last 7 days: ",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "report", "network"],
+            "failed:             0",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "report", "network", "--days", "4294967295"],
+            "Downloads in the last 4294967295 days: ",
         );
+        for days in &["0", "-5", "99999999999999"] {
+            expect_err(
+                config,
+                &["rustup", "report", "network", &format!("--days={}", days)],
+                &format!("'{}' is not a number of days", days),
+            );
+        }
     });
 }
 
 #[test]
-fn toolchain_update_is_like_update() {
+fn toolchain_list_verbose_shows_details() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "toolchain", "update", "nightly"]);
-        expect_stdout_ok(
-            config,
-            &["rustup", "run", "nightly", "rustc", "--version"],
-            "hash-nightly-2",
-        );
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "target", "add", clitools::CROSS_ARCH1]);
+        let out = run(config, "rustup", &["toolchain", "list", "-v"], &[]);
+        assert!(out.ok);
+        assert!(out.stdout.contains("  size: "));
+        assert!(out.stdout.contains("  installed: "));
+        assert!(out.stdout.contains("  last used: "));
+        let line = |prefix: &str| {
+            let line = out.stdout.lines().find(|l| l.starts_with(prefix));
+            line.unwrap_or_else(|| panic!("no {:?} in {}", prefix, out.stdout))
+        };
+        assert!(line("  targets: ").contains(&this_host_triple()));
+        assert!(line("  targets: ").contains(clitools::CROSS_ARCH1));
+        assert!(line("  components: ").contains("rustc"));
     });
 }
 
 #[test]
-fn toolchain_uninstall_is_like_uninstall() {
+fn toolchain_list_json() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "uninstall", "nightly"]);
-        let mut cmd = clitools::cmd(config, "rustup", &["show"]);
-        clitools::env(config, &mut cmd);
-        let out = cmd.output().unwrap();
-        assert!(out.status.success());
-        let stdout = String::from_utf8(out.stdout).unwrap();
-        assert!(!stdout.contains(for_host!("'nightly-2015-01-01-{}'")));
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "list", "--format", "json"],
+            r#""toolchains": []"#,
+        );
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let out = run(
+            config,
+            "rustup",
+            &["toolchain", "list", "--format", "json"],
+            &[],
+        );
+        assert!(out.ok);
+        for expected in &[
+            format!(r#""name": "nightly-{}""#, this_host_triple()),
+            r#""default": true"#.to_owned(),
+            r#""override": false"#.to_owned(),
+            r#""size": "#.to_owned(),
+            r#""last_used": ""#.to_owned(),
+            r#""cargo""#.to_owned(),
+        ] {
+            assert!(out.stdout.contains(expected.as_str()), "{}", out.stdout);
+        }
     });
 }
 
 #[test]
-fn toolchain_update_is_like_update_except_that_bare_install_is_an_error() {
+fn global_json_format() {
     setup(&|config| {
-        expect_err(
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "override", "set", "stable"]);
+        let json = |args: &[&str], env: &[(&str, &str)]| {
+            let out = run(config, "rustup", args, env);
+            assert!(out.ok, "{}", out.stderr);
+            assert!(
+                out.stdout.starts_with("{\n  \"version\": 1,\n"),
+                "{}",
+                out.stdout
+            );
+            out.stdout
+        };
+        let contains = |out: &str, expected: &str| {
+            assert!(out.contains(expected), "no {} in {}", expected, out);
+        };
+
+        let out = json(&["--format", "json", "show"], &[]);
+        contains(
+            &out,
+            &format!(r#""default_host": "{}""#, this_host_triple()),
+        );
+        contains(&out, &for_host!(r#""default_toolchain": "nightly-{0}""#));
+        contains(&out, &for_host!(r#""name": "stable-{0}""#));
+        contains(&out, r#""rustc_version": "1.1.0 (hash-stable-1.1.0)""#);
+        contains(&out, &for_host!(r#""cargo-{0}""#));
+        let sysroot = config
+            .rustupdir
+            .join("toolchains")
+            .join(for_host!("stable-{0}"));
+        let rustc = sysroot.join("bin").join(format!("rustc{}", EXE_SUFFIX));
+        let as_json = |path| serde_json::to_string(path).unwrap();
+        contains(&out, &format!(r#""sysroot": {}"#, as_json(&sysroot)));
+        contains(&out, &format!(r#""rustc": {}"#, as_json(&rustc)));
+
+        let out = json(&["toolchain", "list"], &[("RUSTUP_FORMAT", "json")]);
+        contains(&out, &for_host!(r#""name": "nightly-{0}""#));
+        contains(&out, r#""override": true"#);
+
+        let out = json(
+            &["--format", "json", "component", "list", "--installed"],
+            &[],
+        );
+        contains(&out, &for_host!(r#""toolchain": "stable-{0}""#));
+        contains(&out, &for_host!(r#""name": "rustc-{0}""#));
+        assert!(!out.contains(r#""installed": false"#), "{}", out);
+
+        let out = json(&["--format", "json", "target", "list"], &[]);
+        contains(&out, &format!(r#""name": "{}""#, clitools::CROSS_ARCH1));
+        contains(&out, r#""installed": false"#);
+
+        let out = json(&["--format", "json", "override", "list"], &[]);
+        contains(&out, &for_host!(r#""toolchain": "stable-{0}""#));
+        contains(&out, r#""directory_exists": true"#);
+        contains(&out, r#""toolchain_installed": true"#);
+
+        // --format wins over RUSTUP_FORMAT, given before the command or after it.
+        let out = run(
             config,
-            &["rustup", "toolchain", "update"],
-            "arguments were not provided",
+            "rustup",
+            &["toolchain", "list", "--format", "text"],
+            &[("RUSTUP_FORMAT", "json")],
+        );
+        assert!(out.ok);
+        assert_eq!(
+            out.stdout,
+            *for_host!("stable-{0} (override)\nnightly-{0} (default)\n")
+        );
+        let out = run(
+            config,
+            "rustup",
+            &["--format", "text", "override", "list"],
+            &[("RUSTUP_FORMAT", "json")],
+        );
+        assert!(out.ok);
+        assert!(!out.stdout.contains('{'), "{}", out.stdout);
+
+        let out = run(config, "rustup", &["show"], &[("RUSTUP_FORMAT", "yaml")]);
+        assert!(!out.ok);
+        assert!(
+            out.stderr.contains("invalid RUSTUP_FORMAT 'yaml'"),
+            "{}",
+            out.stderr
         );
     });
 }
 
 #[test]
-fn proxy_toolchain_shorthand() {
+fn system_toolchains() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "toolchain", "update", "nightly"]);
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
-        expect_stdout_ok(
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let system_home = config.homedir.join("system");
+        let nightly = format!("nightly-{}", this_host_triple());
+        fs::create_dir_all(system_home.join("toolchains")).unwrap();
+        fs::rename(
+            config.rustupdir.join("toolchains").join(&nightly),
+            system_home.join("toolchains").join(&nightly),
+        )
+        .unwrap();
+        expect_ok(config, &["rustup", "toolchain", "install", "stable"]);
+
+        let system_home = system_home.to_string_lossy();
+        let env = &[("RUSTUP_SYSTEM_HOME", &*system_home)];
+        let out = run(config, "rustup", &["toolchain", "list"], env);
+        assert!(out.ok);
+        assert_eq!(
+            out.stdout,
+            format!(
+                "stable-{0}\nnightly-{0} (default) (system)\n",
+                this_host_triple()
+            )
+        );
+        let out = run(config, "rustc", &["--version"], env);
+        assert!(out.stdout.contains("hash-nightly-2"), "{}", out.stdout);
+
+        let out = run(
             config,
-            &["rustc", "+stable", "--version"],
-            "hash-stable-1.1.0",
+            "rustup",
+            &["toolchain", "uninstall", "nightly"],
+            env,
         );
+        assert!(!out.ok);
+        assert!(out.stderr.contains(&format!(
+            "toolchain '{}' is provided by the system and can't be changed by rustup",
+            nightly
+        )));
+        let out = run(config, "rustup", &["component", "add", "rust-src"], env);
+        assert!(!out.ok);
+        assert!(out.stderr.contains("is provided by the system"));
+
         expect_stdout_ok(
             config,
-            &["rustc", "+nightly", "--version"],
-            "hash-nightly-2",
+            &["rustup", "toolchain", "list"],
+            &format!("stable-{}\n", this_host_triple()),
         );
     });
 }
 
 #[test]
-fn add_component() {
+fn toolchain_alias() {
     setup(&|config| {
         expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "component", "add", "rust-src"]);
-        let path = format!(
-            "toolchains/stable-{}/lib/rustlib/src/rust-src/foo.rs",
-            this_host_triple()
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "alias"],
+            "no toolchain aliases",
+        );
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "alias", "bisect", "nightly"],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "alias"],
+            "bisect -> nightly",
+        );
+        expect_stdout_ok(config, &["rustc", "+bisect", "--version"], "hash-nightly-2");
+
+        let toolchain_file = config.current_dir().join("rust-toolchain.toml");
+        raw::write_file(&toolchain_file, "[toolchain]\nchannel = 'bisect'").unwrap();
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+
+        // Re-pointing the alias moves everything that uses it along.
+        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
+        expect_ok(config, &["rustup", "toolchain", "alias", "bisect", "beta"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-beta-1.2.0");
+        expect_stdout_ok(config, &["rustup", "toolchain", "alias", "bisect"], "beta");
+
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "alias", "--remove", "bisect"],
+        );
+        expect_err(
+            config,
+            &["rustup", "toolchain", "alias", "--remove", "bisect"],
+            "no toolchain alias named 'bisect'",
         );
-        assert!(config.rustupdir.has(&path));
     });
 }
 
 #[test]
-fn remove_component() {
+fn toolchain_alias_rejects_bad_names() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "component", "add", "rust-src"]);
-        let path = PathBuf::from(format!(
-            "toolchains/stable-{}/lib/rustlib/src/rust-src/foo.rs",
-            this_host_triple()
-        ));
-        assert!(config.rustupdir.has(&path));
-        expect_ok(config, &["rustup", "component", "remove", "rust-src"]);
-        assert!(!config.rustupdir.has(&path.parent().unwrap()));
-    });
-}
-
-#[test]
-fn add_remove_multiple_components() {
-    let files = [
-        "lib/rustlib/src/rust-src/foo.rs".to_owned(),
-        format!("lib/rustlib/{}/analysis/libfoo.json", this_host_triple()),
-    ];
-
-    setup(&|config| {
-        expect_ok(config, &["rustup", "default", "nightly"]);
-        expect_ok(
+        expect_err(
             config,
-            &["rustup", "component", "add", "rust-src", "rust-analysis"],
+            &["rustup", "toolchain", "alias", "stable", "nightly"],
+            "invalid toolchain alias: 'stable'",
         );
-        for file in &files {
-            let path = format!("toolchains/nightly-{}/{}", this_host_triple(), file);
-            assert!(config.rustupdir.has(&path));
-        }
         expect_ok(
             config,
-            &["rustup", "component", "remove", "rust-src", "rust-analysis"],
+            &["rustup", "toolchain", "alias", "bisect", "nightly"],
+        );
+        expect_err(
+            config,
+            &["rustup", "toolchain", "alias", "other", "bisect"],
+            "'bisect' is itself an alias",
         );
-        for file in &files {
-            let path = PathBuf::from(format!(
-                "toolchains/nightly-{}/{}",
-                this_host_triple(),
-                file
-            ));
-            assert!(!config.rustupdir.has(&path.parent().unwrap()));
-        }
     });
 }
 
 #[test]
-fn file_override() {
+fn uninstalling_alias_or_virtual_toolchain_keeps_base() {
     setup(&|config| {
         expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        expect_ok(config, &["rustup", "toolchain", "alias", "mine", "stable"]);
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "virtual", "tools", "stable"],
+        );
 
+        expect_ok(config, &["rustup", "toolchain", "uninstall", "mine"]);
+        expect_ok(config, &["rustup", "toolchain", "uninstall", "tools"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "alias"],
+            "no toolchain aliases",
+        );
         expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
-
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(&toolchain_file, "nightly").unwrap();
-
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
-    });
-}
-
-#[test]
-fn env_override_path() {
-    setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
-
-        let toolchain_path = config
+        assert!(config
             .rustupdir
             .join("toolchains")
-            .join(format!("nightly-{}", this_host_triple()));
-
-        let mut cmd = clitools::cmd(config, "rustc", &["--version"]);
-        clitools::env(config, &mut cmd);
-        cmd.env("RUSTUP_TOOLCHAIN", toolchain_path.to_str().unwrap());
-
-        let out = cmd.output().unwrap();
-        assert!(String::from_utf8(out.stdout)
-            .unwrap()
-            .contains("hash-nightly-2"));
+            .join(for_host!("stable-{}"))
+            .exists());
     });
 }
 
 #[test]
-fn plus_override_path() {
+fn virtual_toolchain() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "stable"]);
         expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "component",
+                "add",
+                "rls",
+                "--toolchain",
+                "nightly",
+            ],
+        );
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "virtual",
+                "nightly-tools",
+                "stable",
+                "rls=nightly",
+                "rustc=nightly",
+            ],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "virtual"],
+            "nightly-tools -> stable, rls from nightly, rustc from nightly",
+        );
 
-        let toolchain_path = config
-            .rustupdir
-            .join("toolchains")
-            .join(format!("nightly-{}", this_host_triple()));
+        expect_ok(config, &["rustup", "default", "nightly-tools"]);
+        expect_stdout_ok(config, &["cargo", "--version"], "hash-stable-1.1.0");
+        expect_stdout_ok(config, &["rls", "--version"], "hash-nightly-2");
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        // Tools run by other tools come from the same places.
+        expect_stdout_ok(config, &["cargo", "--call-rustc"], "hash-nightly-2");
+        expect_stdout_ok(
+            config,
+            &["rustup", "run", "nightly-tools", "rls", "--version"],
+            "hash-nightly-2",
+        );
         expect_stdout_ok(
+            config,
+            &["rustup", "which", "rls"],
+            for_host!("toolchains/nightly-{}"),
+        );
+
+        expect_err(
+            config,
+            &["rustup", "toolchain", "virtual", "stable", "nightly"],
+            "invalid virtual toolchain name: 'stable'",
+        );
+        expect_err(
+            config,
+            &["rustup", "toolchain", "virtual", "other", "nightly-tools"],
+            "'nightly-tools' is itself a virtual toolchain",
+        );
+        expect_err(
+            config,
+            &["rustup", "toolchain", "virtual", "other", "stable", "rls"],
+            "expected TOOL=TOOLCHAIN, found 'rls'",
+        );
+
+        expect_ok(
             config,
             &[
                 "rustup",
-                "run",
-                toolchain_path.to_str().unwrap(),
-                "rustc",
-                "--version",
+                "toolchain",
+                "virtual",
+                "--remove",
+                "nightly-tools",
             ],
-            "hash-nightly-2",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "virtual"],
+            "no virtual toolchains",
         );
     });
 }
 
 #[test]
-fn file_override_path() {
+fn install_with_channel_bound() {
     setup(&|config| {
         expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "install", "nightly<2015-01-02"],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustc", "+nightly-2015-01-01", "--version"],
+            "hash-nightly-1",
+        );
 
-        let toolchain_path = config
-            .rustupdir
-            .join("toolchains")
-            .join(format!("nightly-{}", this_host_triple()));
         let toolchain_file = config.current_dir().join("rust-toolchain.toml");
         raw::write_file(
             &toolchain_file,
-            &format!("[toolchain]\npath='{}'", toolchain_path.to_str().unwrap()),
+            "[toolchain]\nchannel = 'nightly<=2015-01-01'",
         )
         .unwrap();
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-1");
 
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
-
-        // Check that the toolchain has the right name
+        // Days without a release are passed over for the newest before them.
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "install", "nightly<2015-01-10"],
+        );
         expect_stdout_ok(
             config,
-            &["rustup", "show", "active-toolchain"],
-            &format!("nightly-{}", this_host_triple()),
+            &["rustc", "+nightly-2015-01-02", "--version"],
+            "hash-nightly-2",
+        );
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "install", "stable<=2015-01-20"],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustc", "+stable-2015-01-02", "--version"],
+            "hash-stable-1.1.0",
+        );
+        expect_err(
+            config,
+            &["rustup", "toolchain", "install", "nightly<2014-12-01"],
+            "no release of 'nightly' was found in the 21 days up to 2014-11-30",
+        );
+
+        expect_err(
+            config,
+            &["rustup", "toolchain", "install", "beta<=1.1"],
+            "only stable releases have version numbers",
         );
     });
 }
 
 #[test]
-fn proxy_override_path() {
+#[cfg(unix)]
+fn toolchain_env() {
     setup(&|config| {
         expect_ok(config, &["rustup", "default", "stable"]);
         expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "env",
+                "nightly",
+                "RUSTUP_TEST_VAR=asan",
+            ],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "env", "nightly"],
+            "RUSTUP_TEST_VAR=asan",
+        );
 
-        let toolchain_path = config
-            .rustupdir
-            .join("toolchains")
-            .join(format!("nightly-{}", this_host_triple()));
-        let toolchain_file = config.current_dir().join("rust-toolchain.toml");
-        raw::write_file(
-            &toolchain_file,
-            &format!("[toolchain]\npath='{}'", toolchain_path.to_str().unwrap()),
-        )
-        .unwrap();
+        let echo = "echo var=$RUSTUP_TEST_VAR";
+        expect_stdout_ok(
+            config,
+            &["rustup", "run", "nightly", "sh", "-c", echo],
+            "var=asan",
+        );
+        let out = run(config, "rustup", &["run", "stable", "sh", "-c", echo], &[]);
+        assert!(out.stdout.contains("var=\n"));
 
-        expect_stdout_ok(config, &["cargo", "--call-rustc"], "hash-nightly-2");
+        // The caller's own environment wins.
+        let out = run(
+            config,
+            "rustup",
+            &["run", "nightly", "sh", "-c", echo],
+            &[("RUSTUP_TEST_VAR", "mine")],
+        );
+        assert!(out.stdout.contains("var=mine"));
+
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "env",
+                "nightly",
+                "--unset",
+                "RUSTUP_TEST_VAR",
+            ],
+        );
+        let out = run(config, "rustup", &["run", "nightly", "sh", "-c", echo], &[]);
+        assert!(out.stdout.contains("var=\n"));
+        expect_err(
+            config,
+            &["rustup", "toolchain", "env", "nightly", "RUSTUP_TEST_VAR"],
+            "expected KEY=VALUE",
+        );
     });
 }
 
 #[test]
-fn file_override_path_relative() {
+fn toolchain_export() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
-
-        let toolchain_path = config
-            .rustupdir
-            .join("toolchains")
-            .join(format!("nightly-{}", this_host_triple()));
-        let toolchain_file = config.current_dir().join("rust-toolchain.toml");
-
-        // Find shared prefix so we can determine a relative path
-        let mut p1 = toolchain_path.components().peekable();
-        let mut p2 = toolchain_file.components().peekable();
-        while let (Some(p1p), Some(p2p)) = (p1.peek(), p2.peek()) {
-            if p1p == p2p {
-                let _ = p1.next();
-                let _ = p2.next();
-            } else {
-                // The two paths diverge here
-                break;
-            }
-        }
-        let mut relative_path = PathBuf::new();
-        // NOTE: We skip 1 since we don't need to .. across the .toml file at the end of the path
-        for _ in p2.skip(1) {
-            relative_path.push("..");
-        }
-        for p in p1 {
-            relative_path.push(p);
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "toolchain", "export", "nightly"]);
+        let archive = config
+            .current_dir()
+            .join(format!("nightly-{}.tar.zst", this_host_triple()));
+        let file = fs::File::open(&archive).unwrap();
+        let mut tar = tar::Archive::new(zstd::stream::read::Decoder::new(file).unwrap());
+        let paths: Vec<PathBuf> = tar
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().into_owned())
+            .collect();
+        for expected in &[
+            "rustup-toolchain.toml",
+            "toolchain/lib/rustlib/multirust-channel-manifest.toml",
+            &format!("toolchain/bin/rustc{}", EXE_SUFFIX),
+        ] {
+            assert!(paths.contains(&PathBuf::from(expected)), "{:?}", paths);
         }
-        assert!(relative_path.is_relative());
 
-        raw::write_file(
-            &toolchain_file,
-            &format!("[toolchain]\npath='{}'", relative_path.to_str().unwrap()),
-        )
-        .unwrap();
-
-        // Change into an ephemeral dir so that we test that the path is relative to the override
-        let ephemeral = config.current_dir().join("ephemeral");
-        fs::create_dir_all(&ephemeral).unwrap();
-        config.change_dir(&ephemeral, || {
-            expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
-        });
-    });
-}
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "export",
+                "nightly",
+                "-o",
+                "nightly.zip",
+            ],
+            "nightly.zip': expected a name ending in .tar.gz, .tar.xz or .tar.zst",
+        );
+        expect_err(
+            config,
+            &["rustup", "toolchain", "export", "beta"],
+            for_host!("toolchain 'beta-{}' is not installed"),
+        );
+    });
+}
 
 #[test]
-fn file_override_path_no_options() {
+fn toolchain_import() {
     setup(&|config| {
-        // Make a plausible-looking toolchain
-        let cwd = config.current_dir();
-        let toolchain_path = cwd.join("ephemeral");
-        let toolchain_bin = toolchain_path.join("bin");
-        fs::create_dir_all(&toolchain_bin).unwrap();
-
-        let toolchain_file = cwd.join("rust-toolchain.toml");
-        raw::write_file(
-            &toolchain_file,
-            "[toolchain]\npath=\"ephemeral\"\ntargets=[\"dummy\"]",
-        )
-        .unwrap();
-
-        expect_err(
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "target", "add", clitools::CROSS_ARCH1]);
+        expect_ok(
             config,
-            &["rustc", "--version"],
-            "toolchain options are ignored for path toolchain (ephemeral)",
+            &[
+                "rustup",
+                "toolchain",
+                "export",
+                "nightly",
+                "-o",
+                "nightly.tar.xz",
+            ],
         );
+        expect_ok(config, &["rustup", "toolchain", "uninstall", "nightly"]);
 
-        raw::write_file(
-            &toolchain_file,
-            "[toolchain]\npath=\"ephemeral\"\ncomponents=[\"dummy\"]",
-        )
-        .unwrap();
-
-        expect_err(
+        expect_ok(config, &["rustup", "toolchain", "import", "nightly.tar.xz"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        expect_stdout_ok(
             config,
-            &["rustc", "--version"],
-            "toolchain options are ignored for path toolchain (ephemeral)",
+            &["rustup", "component", "list", "--installed"],
+            &format!("rust-std-{}", clitools::CROSS_ARCH1),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "update", "nightly", "--no-self-update"],
+            "unchanged",
         );
-
-        raw::write_file(
-            &toolchain_file,
-            "[toolchain]\npath=\"ephemeral\"\nprofile=\"minimal\"",
-        )
-        .unwrap();
-
         expect_err(
             config,
-            &["rustc", "--version"],
-            "toolchain options are ignored for path toolchain (ephemeral)",
+            &["rustup", "toolchain", "import", "nightly.tar.xz"],
+            "is already installed",
         );
-    });
-}
-
-#[test]
-fn file_override_path_xor_channel() {
-    setup(&|config| {
-        // Make a plausible-looking toolchain
-        let cwd = config.current_dir();
-        let toolchain_path = cwd.join("ephemeral");
-        let toolchain_bin = toolchain_path.join("bin");
-        fs::create_dir_all(&toolchain_bin).unwrap();
 
-        let toolchain_file = cwd.join("rust-toolchain.toml");
-        raw::write_file(
-            &toolchain_file,
-            "[toolchain]\npath=\"ephemeral\"\nchannel=\"nightly\"",
-        )
-        .unwrap();
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "import",
+                "nightly.tar.xz",
+                "--name",
+                "nightly-2015-01-01",
+            ],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustc", "+nightly-2015-01-01", "--version"],
+            "hash-nightly-2",
+        );
 
+        // A custom name, as `toolchain link` takes.
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "import",
+                "nightly.tar.xz",
+                "--name",
+                "mine",
+            ],
+        );
+        expect_stdout_ok(config, &["rustc", "+mine", "--version"], "hash-nightly-2");
         expect_err(
             config,
-            &["rustc", "--version"],
-            "cannot specify both channel (nightly) and path (ephemeral) simultaneously",
+            &[
+                "rustup",
+                "toolchain",
+                "import",
+                "nightly.tar.xz",
+                "--name",
+                "mine",
+            ],
+            "is already installed",
         );
     });
 }
 
 #[test]
-fn file_override_subdir() {
+fn doctor() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
         expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        expect_ok(config, &["rustup", "override", "set", "nightly"]);
 
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
-
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(&toolchain_file, "nightly").unwrap();
+        // Keep any Rust installed outside the test out of the way.
+        let path = config.cargodir.join("bin");
+        let path = path.to_string_lossy();
+        let env = &[("PATH", &*path)];
+        let out = run(config, "rustup", &["doctor"], env);
+        for expected in &[
+            "proxies: ok",
+            "PATH: ok",
+            "overrides: ok",
+            "rustup home: ok",
+            "network: ok",
+        ] {
+            assert!(out.stdout.contains(expected), "{}", out.stdout);
+        }
 
-        let subdir = cwd.join("subdir");
-        fs::create_dir_all(&subdir).unwrap();
-        config.change_dir(&subdir, &|| {
-            expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
-        });
+        expect_ok(config, &["rustup", "toolchain", "uninstall", "nightly"]);
+        fs::remove_file(config.cargodir.join(format!("bin/rustdoc{}", EXE_SUFFIX))).unwrap();
+        let out = run(config, "rustup", &["doctor"], env);
+        assert!(!out.ok);
+        for expected in &[
+            "the rustdoc proxy is missing".to_owned(),
+            "fix: run `rustup self update` to restore it".to_owned(),
+            format!(
+                "the override for '{}' is 'nightly-{}', which is not installed",
+                config.current_dir().display(),
+                this_host_triple()
+            ),
+        ] {
+            assert!(out.stdout.contains(expected.as_str()), "{}", out.stdout);
+        }
     });
 }
 
 #[test]
-fn file_override_with_archive() {
+fn home_profiles() {
     setup(&|config| {
         expect_ok(config, &["rustup", "default", "stable"]);
+        // Only switching to a profile makes its home.
+        expect_err(
+            config,
+            &["rustup", "--profile", "work", "default", "nightly"],
+            "no such profile 'work', create it with `rustup profile switch`",
+        );
+        assert!(!config.rustupdir.join("profiles").join("work").exists());
+        expect_ok(config, &["rustup", "profile", "switch", "work"]);
+        expect_ok(config, &["rustup", "profile", "switch", "default"]);
         expect_ok(
             config,
-            &["rustup", "toolchain", "install", "nightly-2015-01-01"],
+            &["rustup", "--profile", "work", "default", "nightly"],
         );
-
         expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+        expect_stdout_ok(
+            config,
+            &["rustup", "--profile", "work", "show", "home"],
+            &config
+                .rustupdir
+                .join("profiles")
+                .join("work")
+                .to_string_lossy(),
+        );
 
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(&toolchain_file, "nightly-2015-01-01").unwrap();
+        expect_ok(config, &["rustup", "profile", "switch", "work"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        expect_stdout_ok(
+            config,
+            &["rustup", "profile", "list"],
+            "default\nwork (active)\n",
+        );
+        expect_stdout_ok(config, &["rustup", "show"], "home profile: work");
 
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-1");
+        expect_ok(config, &["rustup", "profile", "switch", "default"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+        expect_err(
+            config,
+            &["rustup", "profile", "switch", "client/a"],
+            "invalid profile name 'client/a'",
+        );
     });
 }
 
 #[test]
-fn file_override_toml_format_select_installed_toolchain() {
+#[cfg(unix)]
+fn bisect() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(
             config,
-            &["rustup", "toolchain", "install", "nightly-2015-01-01"],
+            &[
+                "rustup",
+                "bisect",
+                "--start",
+                "2015-01-01",
+                "--end",
+                "2015-01-02",
+                "--",
+                "sh",
+                "-c",
+                "rustc --version | grep -q hash-nightly-2",
+            ],
+            "nightly-2015-01-02",
         );
+        let toolchains = config.rustupdir.join("toolchains");
+        for date in &["2015-01-01", "2015-01-02"] {
+            assert!(!toolchains
+                .join(format!("nightly-{}-{}", date, this_host_triple()))
+                .exists());
+        }
 
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
-
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(
-            &toolchain_file,
-            r#"
-[toolchain]
-channel = "nightly-2015-01-01"
-"#,
-        )
-        .unwrap();
-
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-1");
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "bisect",
+                "--start",
+                "2015-01-01",
+                "--end",
+                "2015-01-02",
+                "--",
+                "true",
+            ],
+            "there is nothing to bisect",
+        );
     });
 }
 
 #[test]
-fn file_override_toml_format_install_both_toolchain_and_components() {
+fn toolchain_clone() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
-        expect_not_stdout_ok(
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(
             config,
-            &["rustup", "component", "list"],
-            "rust-src (installed)",
+            &["rustup", "toolchain", "clone", "nightly", "patched"],
+        );
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "clone",
+                "nightly",
+                "copied",
+                "--copy",
+            ],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustc", "+patched", "--version"],
+            "hash-nightly-2",
         );
 
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(
-            &toolchain_file,
-            r#"
-[toolchain]
-channel = "nightly-2015-01-01"
-components = [ "rust-src" ]
-"#,
-        )
-        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let rustc = |toolchain: &str| {
+                let path = config
+                    .rustupdir
+                    .join("toolchains")
+                    .join(toolchain)
+                    .join("bin/rustc");
+                fs::metadata(path).unwrap().ino()
+            };
+            let original = rustc(&format!("nightly-{}", this_host_triple()));
+            assert_eq!(rustc("patched"), original);
+            assert_ne!(rustc("copied"), original);
+        }
 
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-1");
-        expect_stdout_ok(
+        expect_ok(config, &["rustup", "toolchain", "uninstall", "patched"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+
+        expect_err(
             config,
-            &["rustup", "component", "list"],
-            "rust-src (installed)",
+            &["rustup", "toolchain", "clone", "nightly", "copied"],
+            "toolchain 'copied' is already installed",
+        );
+        expect_err(
+            config,
+            &["rustup", "toolchain", "clone", "nightly", "beta"],
+            "invalid custom toolchain name: 'beta'",
         );
     });
 }
 
 #[test]
-fn file_override_toml_format_add_missing_components() {
+fn interrupted_installs_are_cleaned_up() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_not_stdout_ok(
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "component", "add", "rls"]);
+        let nightly = format!("nightly-{}", this_host_triple());
+        let beta = format!("beta-{}", this_host_triple());
+        let marker = |name: &str, kind: &str| {
+            let dir = config.rustupdir.join("incomplete");
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(name), kind).unwrap();
+        };
+
+        // A first install that was interrupted is finished off.
+        let partial = config.rustupdir.join("toolchains").join(&beta);
+        fs::create_dir_all(partial.join("bin")).unwrap();
+        marker(&beta, "install");
+        expect_stderr_ok(
             config,
-            &["rustup", "component", "list"],
-            "rust-src (installed)",
+            &["rustup", "show"],
+            &format!(
+                "removing toolchain '{}', whose installation was interrupted",
+                beta
+            ),
         );
-
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(
-            &toolchain_file,
-            r#"
-[toolchain]
-components = [ "rust-src" ]
-"#,
-        )
-        .unwrap();
-
+        assert!(!partial.exists());
+        assert!(!config.rustupdir.has(format!("incomplete/{}", beta)));
+
+        // An update that was interrupted is reported until it is repaired.
+        marker(&nightly, "update");
+        let warning = format!("run `rustup toolchain repair {}`", nightly);
+        expect_stderr_ok(config, &["rustup", "show"], &warning);
+        expect_stderr_ok(config, &["rustup", "update", "nightly"], &warning);
+        expect_stderr_ok(config, &["rustup", "show"], &warning);
+        expect_ok(config, &["rustup", "toolchain", "repair", "nightly"]);
+        expect_not_stderr_ok(config, &["rustup", "show"], &warning);
         expect_stdout_ok(
             config,
-            &["rustup", "component", "list"],
-            "rust-src (installed)",
+            &["rustup", "component", "list", "--installed"],
+            "rls",
         );
+
+        // So is a toolchain without its manifest.
+        let manifest = config
+            .rustupdir
+            .join("toolchains")
+            .join(&nightly)
+            .join("lib/rustlib/multirust-config.toml");
+        fs::remove_file(manifest).unwrap();
+        expect_stderr_ok(config, &["rustup", "show"], &warning);
+        expect_ok(config, &["rustup", "toolchain", "repair", "nightly"]);
+        expect_not_stderr_ok(config, &["rustup", "show"], &warning);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
     });
 }
 
 #[test]
-fn file_override_toml_format_add_missing_targets() {
+fn toolchain_gc_keeps_default_and_overrides() {
     setup(&|config| {
         expect_ok(config, &["rustup", "default", "stable"]);
-        expect_not_stdout_ok(
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
+        expect_ok(config, &["rustup", "override", "set", "beta"]);
+
+        // Everything was installed moments ago.
+        expect_stderr_ok(
             config,
-            &["rustup", "component", "list"],
-            "arm-linux-androideabi (installed)",
+            &["rustup", "toolchain", "gc", "--unused-for", "90d"],
+            "no toolchains have gone unused for that long",
         );
-
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(
-            &toolchain_file,
-            r#"
-[toolchain]
-targets = [ "arm-linux-androideabi" ]
-"#,
+        expect_stdout_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "gc",
+                "--unused-for",
+                "0s",
+                "--dry-run",
+            ],
+            for_host!("nightly-{} (unused for 0 days)"),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "list"],
+            for_host!("nightly-{}"),
+        );
+
+        expect_ok(config, &["rustup", "toolchain", "gc", "--unused-for", "0s"]);
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "toolchain", "list"],
+            for_host!("nightly-{}"),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "list"],
+            for_host!("beta-{}"),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "list"],
+            for_host!("stable-{}"),
+        );
+    });
+}
+
+#[test]
+fn toolchain_gc_keeps_what_virtual_toolchains_run() {
+    setup(&|config| {
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "stable",
+                "nightly",
+                "beta",
+            ],
+        );
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "virtual",
+                "nightly-tools",
+                "stable",
+                "rustc=nightly",
+            ],
+        );
+        expect_ok(config, &["rustup", "default", "nightly-tools"]);
+
+        expect_ok(config, &["rustup", "toolchain", "gc", "--unused-for", "0s"]);
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "toolchain", "list"],
+            for_host!("beta-{}"),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "list"],
+            for_host!("stable-{}"),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "list"],
+            for_host!("nightly-{}"),
+        );
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+    });
+}
+
+#[test]
+fn removals_go_ahead_without_asking_by_default() {
+    setup(&|config| {
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "install", "nightly", "beta"],
+        );
+        // Naming what to remove is taken as the say-so, so there is nothing
+        // to ask non-interactively.
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "--non-interactive",
+                "toolchain",
+                "uninstall",
+                "beta",
+            ],
+        );
+        expect_not_stdout_ok(config, &["rustup", "toolchain", "list"], "beta");
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "uninstall", "--yes", "nightly"],
+        );
+        expect_not_stdout_ok(config, &["rustup", "toolchain", "list"], "nightly");
+
+        let missing = config.current_dir().join("missing");
+        fs::create_dir(&missing).unwrap();
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "override",
+                "set",
+                "stable",
+                "--path",
+                missing.to_str().unwrap(),
+            ],
+        );
+        fs::remove_dir(&missing).unwrap();
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "--non-interactive",
+                "override",
+                "unset",
+                "--nonexistent",
+                "-y",
+            ],
+        );
+        expect_not_stdout_ok(config, &["rustup", "override", "list"], "missing");
+    });
+}
+
+#[test]
+fn toolchain_gc_rejects_bad_length_of_time() {
+    setup(&|config| {
+        expect_err(
+            config,
+            &["rustup", "toolchain", "gc", "--unused-for", "90"],
+            "invalid length of time '90'",
+        );
+    });
+}
+
+#[test]
+fn proxies_record_last_use() {
+    setup(&|config| {
+        let marker = config
+            .rustupdir
+            .join(format!("last-used/nightly-{}", this_host_triple()));
+        let last_use = || -> u64 { fs::read_to_string(&marker).unwrap().parse().unwrap() };
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustc", "--version"]);
+        let first = last_use();
+        assert!(first > 1);
+
+        // Recorded at most once an hour.
+        fs::write(&marker, first.to_string()).unwrap();
+        expect_ok(config, &["rustc", "--version"]);
+        assert_eq!(last_use(), first);
+        fs::write(&marker, "1").unwrap();
+        expect_ok(config, &["rustc", "--version"]);
+        assert!(last_use() >= first);
+
+        expect_ok(config, &["rustup", "toolchain", "remove", "nightly"]);
+        assert!(!marker.exists());
+    });
+}
+
+// #422
+#[test]
+fn update_doesnt_update_non_tracking_channels() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "update", "nightly-2015-01-01"]);
+        let mut cmd = clitools::cmd(config, "rustup", &["update"]);
+        clitools::env(config, &mut cmd);
+        let out = cmd.output().unwrap();
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        assert!(!stderr.contains(for_host!(
+            "syncing channel updates for 'nightly-2015-01-01-{}'"
+        )));
+    });
+}
+
+#[test]
+fn toolchain_install_is_like_update() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "run", "nightly", "rustc", "--version"],
+            "hash-nightly-2",
+        );
+    });
+}
+
+#[test]
+fn toolchain_install_is_like_update_quiet() {
+    setup(&|config| {
+        expect_ok(
+            config,
+            &["rustup", "--quiet", "toolchain", "install", "nightly"],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "run", "nightly", "rustc", "--version"],
+            "hash-nightly-2",
+        );
+    });
+}
+
+#[test]
+fn toolchain_install_is_like_update_except_that_bare_install_is_an_error() {
+    setup(&|config| {
+        expect_err(
+            config,
+            &["rustup", "toolchain", "install"],
+            "arguments were not provided",
+        );
+    });
+}
+
+#[test]
+fn toolchain_update_is_like_update() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "toolchain", "update", "nightly"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "run", "nightly", "rustc", "--version"],
+            "hash-nightly-2",
+        );
+    });
+}
+
+#[test]
+fn toolchain_uninstall_is_like_uninstall() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "uninstall", "nightly"]);
+        let mut cmd = clitools::cmd(config, "rustup", &["show"]);
+        clitools::env(config, &mut cmd);
+        let out = cmd.output().unwrap();
+        assert!(out.status.success());
+        let stdout = String::from_utf8(out.stdout).unwrap();
+        assert!(!stdout.contains(for_host!("'nightly-2015-01-01-{}'")));
+    });
+}
+
+#[test]
+fn toolchain_update_is_like_update_except_that_bare_install_is_an_error() {
+    setup(&|config| {
+        expect_err(
+            config,
+            &["rustup", "toolchain", "update"],
+            "arguments were not provided",
+        );
+    });
+}
+
+#[test]
+fn proxy_toolchain_shorthand() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "update", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+        expect_stdout_ok(
+            config,
+            &["rustc", "+stable", "--version"],
+            "hash-stable-1.1.0",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustc", "+nightly", "--version"],
+            "hash-nightly-2",
+        );
+    });
+}
+
+#[test]
+fn add_component() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "component", "add", "rust-src"]);
+        let path = format!(
+            "toolchains/stable-{}/lib/rustlib/src/rust-src/foo.rs",
+            this_host_triple()
+        );
+        assert!(config.rustupdir.has(&path));
+    });
+}
+
+#[test]
+fn remove_component() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "component", "add", "rust-src"]);
+        let path = PathBuf::from(format!(
+            "toolchains/stable-{}/lib/rustlib/src/rust-src/foo.rs",
+            this_host_triple()
+        ));
+        assert!(config.rustupdir.has(&path));
+        expect_ok(config, &["rustup", "component", "remove", "rust-src"]);
+        assert!(!config.rustupdir.has(&path.parent().unwrap()));
+    });
+}
+
+#[test]
+fn default_components() {
+    setup(&|config| {
+        let src = format!(
+            "toolchains/{{}}-{}/lib/rustlib/src/rust-src/foo.rs",
+            this_host_triple()
+        );
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok_ex(
+            config,
+            &["rustup", "set", "default-components", "rust-src"],
+            "",
+            "info: new toolchains also get rust-src\n",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "config", "get", "default_components"],
+            "rust-src",
+        );
+        // Only toolchains installed from now on get them.
+        expect_ok(config, &["rustup", "update", "stable"]);
+        assert!(!config.rustupdir.has(&src.replace("{}", "stable")));
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        assert!(config.rustupdir.has(&src.replace("{}", "nightly")));
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "beta",
+                "--profile",
+                "minimal",
+            ],
+        );
+        assert!(config.rustupdir.has(&src.replace("{}", "beta")));
+
+        expect_ok_ex(
+            config,
+            &["rustup", "set", "default-components"],
+            "",
+            "info: new toolchains get only the components of the profile\n",
+        );
+        expect_err(
+            config,
+            &["rustup", "set", "default-components", "rust-src;x"],
+            "invalid component name 'rust-src;x'",
+        );
+    });
+}
+
+#[test]
+fn add_remove_multiple_components() {
+    let files = [
+        "lib/rustlib/src/rust-src/foo.rs".to_owned(),
+        format!("lib/rustlib/{}/analysis/libfoo.json", this_host_triple()),
+    ];
+
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(
+            config,
+            &["rustup", "component", "add", "rust-src", "rust-analysis"],
+        );
+        for file in &files {
+            let path = format!("toolchains/nightly-{}/{}", this_host_triple(), file);
+            assert!(config.rustupdir.has(&path));
+        }
+        expect_ok(
+            config,
+            &["rustup", "component", "remove", "rust-src", "rust-analysis"],
+        );
+        for file in &files {
+            let path = PathBuf::from(format!(
+                "toolchains/nightly-{}/{}",
+                this_host_triple(),
+                file
+            ));
+            assert!(!config.rustupdir.has(&path.parent().unwrap()));
+        }
+    });
+}
+
+#[test]
+fn file_override() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "nightly").unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+    });
+}
+
+#[test]
+fn env_override_path() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        let toolchain_path = config
+            .rustupdir
+            .join("toolchains")
+            .join(format!("nightly-{}", this_host_triple()));
+
+        let mut cmd = clitools::cmd(config, "rustc", &["--version"]);
+        clitools::env(config, &mut cmd);
+        cmd.env("RUSTUP_TOOLCHAIN", toolchain_path.to_str().unwrap());
+
+        let out = cmd.output().unwrap();
+        assert!(String::from_utf8(out.stdout)
+            .unwrap()
+            .contains("hash-nightly-2"));
+    });
+}
+
+#[test]
+fn plus_override_path() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        let toolchain_path = config
+            .rustupdir
+            .join("toolchains")
+            .join(format!("nightly-{}", this_host_triple()));
+        expect_stdout_ok(
+            config,
+            &[
+                "rustup",
+                "run",
+                toolchain_path.to_str().unwrap(),
+                "rustc",
+                "--version",
+            ],
+            "hash-nightly-2",
+        );
+    });
+}
+
+#[test]
+fn file_override_path() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        let toolchain_path = config
+            .rustupdir
+            .join("toolchains")
+            .join(format!("nightly-{}", this_host_triple()));
+        let toolchain_file = config.current_dir().join("rust-toolchain.toml");
+        raw::write_file(
+            &toolchain_file,
+            &format!("[toolchain]\npath='{}'", toolchain_path.to_str().unwrap()),
+        )
+        .unwrap();
+        expect_ok(config, &["rustup", "trust", "add", "."]);
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+
+        // Check that the toolchain has the right name
+        expect_stdout_ok(
+            config,
+            &["rustup", "show", "active-toolchain"],
+            &format!("nightly-{}", this_host_triple()),
+        );
+    });
+}
+
+#[test]
+fn file_override_path_interpolated() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        let toolchain_file = config.current_dir().join("rust-toolchain.toml");
+        raw::write_file(
+            &toolchain_file,
+            &format!(
+                "[toolchain]\npath='${{RUSTUP_HOME}}/toolchains/nightly-{}'",
+                this_host_triple()
+            ),
+        )
+        .unwrap();
+        expect_ok(config, &["rustup", "trust", "add", "."]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+
+        let toolchain_path = config
+            .rustupdir
+            .join("toolchains")
+            .join(format!("nightly-{}", this_host_triple()));
+        let toolchain_path = toolchain_path.to_str().unwrap();
+        raw::write_file(&toolchain_file, "[toolchain]\npath='${RUST_DEV}'").unwrap();
+        let out = run(
+            config,
+            "rustc",
+            &["--version"],
+            &[("RUST_DEV", toolchain_path)],
+        );
+        assert!(!out.ok);
+        assert!(out.stderr.contains("rustup set toolchain-file-env"));
+
+        expect_ok(config, &["rustup", "set", "toolchain-file-env", "RUST_DEV"]);
+        let out = run(
+            config,
+            "rustc",
+            &["--version"],
+            &[("RUST_DEV", toolchain_path)],
+        );
+        assert!(out.ok);
+        assert!(out.stdout.contains("hash-nightly-2"));
+        expect_err(config, &["rustc", "--version"], "RUST_DEV is not set");
+    });
+}
+
+#[test]
+fn file_override_path_untrusted() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        let toolchain_path = config
+            .rustupdir
+            .join("toolchains")
+            .join(format!("nightly-{}", this_host_triple()));
+        let toolchain_file = config.current_dir().join("rust-toolchain.toml");
+        raw::write_file(
+            &toolchain_file,
+            &format!("[toolchain]\npath='{}'", toolchain_path.to_str().unwrap()),
+        )
+        .unwrap();
+
+        expect_err(config, &["rustc", "--version"], "is not trusted");
+
+        let sub = config.current_dir().join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        expect_ok(config, &["rustup", "trust", "add", "sub"]);
+        expect_err(config, &["rustc", "--version"], "is not trusted");
+
+        expect_ok(config, &["rustup", "trust", "add", "*"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        expect_stdout_ok(config, &["rustup", "trust", "list"], "*");
+
+        expect_ok(config, &["rustup", "trust", "remove", "*"]);
+        expect_err(config, &["rustc", "--version"], "is not trusted");
+    });
+}
+
+#[test]
+fn config_check() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stderr_ok(
+            config,
+            &["rustup", "config", "check"],
+            "info: the configuration is valid",
+        );
+
+        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
+        expect_ok(config, &["rustup", "override", "set", "beta"]);
+        expect_ok(config, &["rustup", "toolchain", "uninstall", "beta"]);
+        let nowhere = format!("file://{}", config.homedir.join("nowhere").display());
+        expect_ok(
+            config,
+            &["rustup", "config", "set", "network.mirrors", &nowhere],
+        );
+        let settings_file = config.rustupdir.join("settings.toml");
+        let settings = fs::read_to_string(&settings_file).unwrap();
+        raw::write_file(&settings_file, &format!("future_setting = 1\n{}", settings)).unwrap();
+        let out = run(config, "rustup", &["config", "check"], &[]);
+        assert!(!out.ok);
+        assert!(out.stderr.contains(&format!(
+            "error: the override for '{}' is 'beta-{}', which isn't installed",
+            config.current_dir().display(),
+            this_host_triple()
+        )));
+        assert!(out
+            .stderr
+            .contains(&format!("error: the mirror '{}' can't be reached", nowhere)));
+        assert!(out
+            .stderr
+            .contains("warning: unknown setting 'future_setting'"));
+
+        // Too broken for rustup to start, but still checked.
+        raw::write_file(
+            &settings_file,
+            &format!(
+                "profile = 'tiny'\n{}",
+                settings.replace("profile = ", "old_profile = ")
+            ),
+        )
+        .unwrap();
+        let out = run(config, "rustup", &["config", "check"], &[]);
+        assert!(!out.ok);
+        assert!(
+            out.stderr.contains("error: in '")
+                && out.stderr.contains("invalid value 'tiny' for 'profile'"),
+            "{}",
+            out.stderr
+        );
+    });
+}
+
+#[test]
+fn config_export_import() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "set", "profile", "minimal"]);
+        expect_ok(config, &["rustup", "set", "default-components", "rust-src"]);
+        expect_ok(config, &["rustup", "toolchain", "alias", "mine", "beta"]);
+        let out = run(config, "rustup", &["config", "export"], &[]);
+        assert!(out.ok);
+        assert!(out.stdout.contains("default_components = [\"rust-src\"]"));
+        assert!(out.stdout.contains("[toolchain_aliases]\nmine = \"beta\""));
+        assert!(!out.stdout.contains("default_host_triple"));
+        let file = config.homedir.join("rustup-config.toml");
+        raw::write_file(&file, &out.stdout).unwrap();
+
+        // A new machine, as far as rustup can tell.
+        let home = config.homedir.join("new-rustup");
+        let env = [("RUSTUP_HOME", home.to_str().unwrap())];
+        let out = run(
+            config,
+            "rustup",
+            &["config", "import", file.to_str().unwrap()],
+            &env,
+        );
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stderr.contains("imported the settings from"));
+        let src = format!(
+            "toolchains/nightly-{}/lib/rustlib/src/rust-src/foo.rs",
+            this_host_triple()
+        );
+        assert!(home.join(src).exists());
+        let out = run(config, "rustup", &["config", "get", "profile"], &env);
+        assert_eq!(out.stdout, "minimal\n");
+        let out = run(config, "rustup", &["toolchain", "alias", "mine"], &env);
+        assert_eq!(out.stdout, "beta\n");
+
+        raw::write_file(&file, "profile = 'tiny'\n").unwrap();
+        expect_err(
+            config,
+            &["rustup", "config", "import", file.to_str().unwrap()],
+            "invalid value 'tiny' for 'profile'",
+        );
+    });
+}
+
+#[test]
+fn usage_stats() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "stable"]);
+        // Nothing is recorded until it is turned on.
+        expect_ok(config, &["rustc", "--version"]);
+        expect_stderr_ok(
+            config,
+            &["rustup", "stats"],
+            "usage statistics are off; turn them on with `rustup set usage-stats enable`",
+        );
+        expect_stdout_ok(config, &["rustup", "stats"], "Runs in the last 30 days: 0");
+
+        expect_ok(config, &["rustup", "set", "usage-stats", "enable"]);
+        expect_ok(config, &["rustc", "--version"]);
+        expect_ok(config, &["cargo", "--version"]);
+        expect_ok(config, &["rustup", "run", "nightly", "rustc", "--version"]);
+        let out = run(config, "rustup", &["stats"], &[]);
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.contains("Runs in the last 30 days: 3"));
+        assert!(out
+            .stdout
+            .contains(&format!("nightly-{}       3", this_host_triple())));
+        assert!(out.stdout.contains("  cargo       1\n  rustc       2\n"));
+        assert!(out.stderr.contains(&format!(
+            "not run in the last 30 days: stable-{}",
+            this_host_triple()
+        )));
+        let out = run(
+            config,
+            "rustup",
+            &["--format", "json", "stats", "--days", "7"],
+            &[],
+        );
+        assert!(out.stdout.contains("\"days\": 7"), "{}", out.stdout);
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "stats", "--clear"],
+            "usage statistics cleared",
+        );
+        expect_stdout_ok(config, &["rustup", "stats"], "Runs in the last 30 days: 0");
+        expect_err(
+            config,
+            &["rustup", "stats", "--days", "soon"],
+            "'soon' is not a number of days",
+        );
+        expect_err(
+            config,
+            &["rustup", "stats", "--days=-5"],
+            "'-5' is not a number of days",
+        );
+    });
+}
+
+#[test]
+fn temp_dir_settings() {
+    setup(&|config| {
+        let scratch = config.homedir.join("scratch");
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "config",
+                "set",
+                "temp.dir",
+                scratch.to_str().unwrap(),
+            ],
+        );
+        expect_err(
+            config,
+            &["rustup", "config", "set", "temp.dir", "scratch"],
+            "invalid value 'scratch' for 'temp.dir': expected an absolute path",
+        );
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let root = scratch.join("rustup");
+        assert!(root.is_dir());
+        assert!(!config.rustupdir.join("tmp").exists());
+
+        expect_ok(config, &["rustup", "config", "set", "temp.max-age", "0"]);
+        // Left behind by an install which didn't finish.
+        let stale = root.join("abcdefghijklmnop_file");
+        let fresh = root.join("ponmlkjihgfedcba_file");
+        raw::write_file(&stale, &"x".repeat(2048)).unwrap();
+        raw::write_file(&fresh, "").unwrap();
+        let two_days_ago =
+            std::time::SystemTime::now() - std::time::Duration::from_secs(48 * 60 * 60);
+        fs::File::options()
+            .write(true)
+            .open(&stale)
+            .unwrap()
+            .set_modified(two_days_ago)
+            .unwrap();
+        expect_ok(config, &["rustup", "show"]);
+        assert!(stale.exists());
+        expect_ok(config, &["rustup", "config", "unset", "temp.max-age"]);
+        expect_stderr_ok(
+            config,
+            &["rustup", "show"],
+            "info: removed 2.0 KiB of temporary files left behind",
+        );
+        assert!(!stale.exists());
+        assert!(fresh.exists());
+    });
+}
+
+#[test]
+fn project_settings() {
+    setup(&|config| {
+        let std = |toolchain: &str| {
+            format!(
+                "toolchains/{}-{}/lib/rustlib/{}/lib/libstd.rlib",
+                toolchain,
+                this_host_triple(),
+                clitools::CROSS_ARCH1
+            )
+        };
+        expect_ok(config, &["rustup", "default", "stable"]);
+        let file = config.current_dir().join(".rustup.toml");
+        raw::write_file(
+            &file,
+            &format!(
+                "default_targets = ['{}']\nauto_install_targets = 'enable'\nfuture = 1\n",
+                clitools::CROSS_ARCH1
+            ),
+        )
+        .unwrap();
+
+        let out = run(config, "rustup", &["toolchain", "install", "nightly"], &[]);
+        assert!(out.ok);
+        assert!(out.stderr.contains("is not trusted"));
+        // An untrusted file isn't even read.
+        assert!(!out.stderr.contains("setting 'future' in"));
+        assert!(!config.rustupdir.has(&std("nightly")));
+
+        expect_ok(config, &["rustup", "trust", "add", "."]);
+        let out = run(
+            config,
+            "rustup",
+            &["config", "get", "auto_install_targets"],
+            &[],
+        );
+        assert!(out.ok);
+        assert_eq!(out.stdout, "enable\n");
+        assert!(out.stderr.contains("setting 'future' in"));
+        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
+        assert!(config.rustupdir.has(&std("beta")));
+        // Outside the project, the user's settings are used.
+        config.change_dir(&config.emptydir, &|| {
+            let out = run(
+                config,
+                "rustup",
+                &["config", "get", "auto_install_targets"],
+                &[],
+            );
+            assert!(!out.ok);
+            assert_eq!(out.stdout, "");
+        });
+        // The file is left unchanged.
+        assert!(!fs::read_to_string(config.rustupdir.join("settings.toml"))
+            .unwrap()
+            .contains("default_targets"));
+
+        // An invalid file is ignored rather than failing every command.
+        raw::write_file(&file, "profile = 'tiny'\n").unwrap();
+        expect_stderr_ok(
+            config,
+            &["rustup", "show", "active-toolchain"],
+            "invalid value 'tiny' for 'profile'",
+        );
+        expect_ok(config, &["rustc", "--version"]);
+    });
+}
+
+#[test]
+fn machine_policy() {
+    setup(&|config| {
+        let policy = config.homedir.join("policy.toml");
+        let policy = policy.to_string_lossy().into_owned();
+        let dist_server = format!("file://{}", config.distdir.to_string_lossy());
+        raw::write_file(
+            Path::new(&policy),
+            &format!(
+                "allowed_channels = ['stable', 'beta']\n\
+                 allowed_dist_servers = ['{}']\n\
+                 \n\
+                 [locked]\n\
+                 profile = 'minimal'\n",
+                dist_server
+            ),
+        )
+        .unwrap();
+        let env = ("RUSTUP_OVERRIDE_POLICY", &*policy);
+        let rustup = |args: &[&str]| run(config, "rustup", args, &[env]);
+        let locked = format!("'profile' is locked by the policy in '{}'", policy);
+
+        assert_eq!(rustup(&["config", "get", "profile"]).stdout, "minimal\n");
+        let out = rustup(&["set", "profile", "complete"]);
+        assert!(!out.ok);
+        assert!(out.stderr.contains(&locked), "{}", out.stderr);
+        assert!(rustup(&["set", "profile", "minimal"]).ok);
+        let out = run(
+            config,
+            "rustup",
+            &["config", "get", "profile"],
+            &[env, ("RUSTUP_SETTINGS_PROFILE", "complete")],
+        );
+        assert!(!out.ok);
+        assert!(out.stderr.contains("RUSTUP_SETTINGS_PROFILE"));
+        assert!(out.stderr.contains(&locked), "{}", out.stderr);
+
+        assert!(rustup(&["toolchain", "install", "stable"]).ok);
+        let out = rustup(&["toolchain", "install", "nightly"]);
+        assert!(!out.ok);
+        assert!(
+            out.stderr.contains("is not allowed by the policy in"),
+            "{}",
+            out.stderr
+        );
+        let out = run(
+            config,
+            "rustup",
+            &["toolchain", "install", "beta"],
+            &[env, ("RUSTUP_DIST_SERVER", "https://rust.example.com")],
+        );
+        assert!(!out.ok);
+        assert!(
+            out.stderr
+                .contains("dist server 'https://rust.example.com' is not allowed"),
+            "{}",
+            out.stderr
+        );
+
+        // Components and targets are only added from allowed servers too.
+        let out = run(
+            config,
+            "rustup",
+            &[
+                "target",
+                "add",
+                "--toolchain",
+                "stable",
+                clitools::CROSS_ARCH1,
+            ],
+            &[env, ("RUSTUP_DIST_SERVER", "https://rust.example.com")],
+        );
+        assert!(!out.ok);
+        assert!(
+            out.stderr
+                .contains("dist server 'https://rust.example.com' is not allowed"),
+            "{}",
+            out.stderr
+        );
+        let out = run(
+            config,
+            "rustup",
+            &["component", "add", "--toolchain", "stable", "rust-src"],
+            &[env, ("RUSTUP_DIST_SERVER", "https://rust.example.com")],
+        );
+        assert!(!out.ok);
+        assert!(
+            out.stderr
+                .contains("dist server 'https://rust.example.com' is not allowed"),
+            "{}",
+            out.stderr
+        );
+        assert!(
+            rustup(&[
+                "target",
+                "add",
+                "--toolchain",
+                "stable",
+                clitools::CROSS_ARCH1
+            ])
+            .ok
+        );
+
+        // A project's mirrors are held to the allowed dist servers too.
+        let project = config.current_dir().join(".rustup.toml");
+        raw::write_file(
+            &project,
+            "[network]\nmirrors = ['https://mirror.example']\n",
+        )
+        .unwrap();
+        assert!(rustup(&["trust", "add", "."]).ok);
+        let out = rustup(&["toolchain", "install", "beta"]);
+        assert!(!out.ok);
+        assert!(
+            out.stderr
+                .contains("dist server 'https://mirror.example' is not allowed"),
+            "{}",
+            out.stderr
+        );
+        fs::remove_file(&project).unwrap();
+
+        // Without the policy, nothing is locked.
+        expect_ok(config, &["rustup", "set", "profile", "complete"]);
+    });
+}
+
+#[test]
+fn proxy_override_path() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        let toolchain_path = config
+            .rustupdir
+            .join("toolchains")
+            .join(format!("nightly-{}", this_host_triple()));
+        let toolchain_file = config.current_dir().join("rust-toolchain.toml");
+        raw::write_file(
+            &toolchain_file,
+            &format!("[toolchain]\npath='{}'", toolchain_path.to_str().unwrap()),
+        )
+        .unwrap();
+        expect_ok(config, &["rustup", "trust", "add", "."]);
+
+        expect_stdout_ok(config, &["cargo", "--call-rustc"], "hash-nightly-2");
+    });
+}
+
+#[test]
+fn file_override_path_relative() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        let toolchain_path = config
+            .rustupdir
+            .join("toolchains")
+            .join(format!("nightly-{}", this_host_triple()));
+        let toolchain_file = config.current_dir().join("rust-toolchain.toml");
+
+        // Find shared prefix so we can determine a relative path
+        let mut p1 = toolchain_path.components().peekable();
+        let mut p2 = toolchain_file.components().peekable();
+        while let (Some(p1p), Some(p2p)) = (p1.peek(), p2.peek()) {
+            if p1p == p2p {
+                let _ = p1.next();
+                let _ = p2.next();
+            } else {
+                // The two paths diverge here
+                break;
+            }
+        }
+        let mut relative_path = PathBuf::new();
+        // NOTE: We skip 1 since we don't need to .. across the .toml file at the end of the path
+        for _ in p2.skip(1) {
+            relative_path.push("..");
+        }
+        for p in p1 {
+            relative_path.push(p);
+        }
+        assert!(relative_path.is_relative());
+
+        raw::write_file(
+            &toolchain_file,
+            &format!("[toolchain]\npath='{}'", relative_path.to_str().unwrap()),
+        )
+        .unwrap();
+
+        expect_ok(config, &["rustup", "trust", "add", "."]);
+
+        // Change into an ephemeral dir so that we test that the path is relative to the override
+        let ephemeral = config.current_dir().join("ephemeral");
+        fs::create_dir_all(&ephemeral).unwrap();
+        config.change_dir(&ephemeral, || {
+            expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        });
+    });
+}
+
+#[test]
+fn file_override_path_no_options() {
+    setup(&|config| {
+        // Make a plausible-looking toolchain
+        let cwd = config.current_dir();
+        let toolchain_path = cwd.join("ephemeral");
+        let toolchain_bin = toolchain_path.join("bin");
+        fs::create_dir_all(&toolchain_bin).unwrap();
+
+        let toolchain_file = cwd.join("rust-toolchain.toml");
+        raw::write_file(
+            &toolchain_file,
+            "[toolchain]\npath=\"ephemeral\"\ntargets=[\"dummy\"]",
+        )
+        .unwrap();
+
+        expect_err(
+            config,
+            &["rustc", "--version"],
+            "toolchain options are ignored for path toolchain (ephemeral)",
+        );
+
+        raw::write_file(
+            &toolchain_file,
+            "[toolchain]\npath=\"ephemeral\"\ncomponents=[\"dummy\"]",
+        )
+        .unwrap();
+
+        expect_err(
+            config,
+            &["rustc", "--version"],
+            "toolchain options are ignored for path toolchain (ephemeral)",
+        );
+
+        raw::write_file(
+            &toolchain_file,
+            "[toolchain]\npath=\"ephemeral\"\nprofile=\"minimal\"",
+        )
+        .unwrap();
+
+        expect_err(
+            config,
+            &["rustc", "--version"],
+            "toolchain options are ignored for path toolchain (ephemeral)",
+        );
+    });
+}
+
+#[test]
+fn file_override_path_xor_channel() {
+    setup(&|config| {
+        // Make a plausible-looking toolchain
+        let cwd = config.current_dir();
+        let toolchain_path = cwd.join("ephemeral");
+        let toolchain_bin = toolchain_path.join("bin");
+        fs::create_dir_all(&toolchain_bin).unwrap();
+
+        let toolchain_file = cwd.join("rust-toolchain.toml");
+        raw::write_file(
+            &toolchain_file,
+            "[toolchain]\npath=\"ephemeral\"\nchannel=\"nightly\"",
+        )
+        .unwrap();
+
+        expect_err(
+            config,
+            &["rustc", "--version"],
+            "cannot specify both channel (nightly) and path (ephemeral) simultaneously",
+        );
+    });
+}
+
+#[test]
+fn file_override_subdir() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "nightly").unwrap();
+
+        let subdir = cwd.join("subdir");
+        fs::create_dir_all(&subdir).unwrap();
+        config.change_dir(&subdir, &|| {
+            expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        });
+    });
+}
+
+#[test]
+fn file_override_with_archive() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "install", "nightly-2015-01-01"],
+        );
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "nightly-2015-01-01").unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-1");
+    });
+}
+
+#[test]
+fn file_override_toml_format_select_installed_toolchain() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "install", "nightly-2015-01-01"],
+        );
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(
+            &toolchain_file,
+            r#"
+[toolchain]
+channel = "nightly-2015-01-01"
+"#,
+        )
+        .unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-1");
+    });
+}
+
+#[test]
+fn file_override_toml_format_install_both_toolchain_and_components() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            "rust-src (installed)",
+        );
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(
+            &toolchain_file,
+            r#"
+[toolchain]
+channel = "nightly-2015-01-01"
+components = [ "rust-src" ]
+"#,
+        )
+        .unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-1");
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            "rust-src (installed)",
+        );
+    });
+}
+
+#[test]
+fn file_override_toml_format_add_missing_components() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            "rust-src (installed)",
+        );
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(
+            &toolchain_file,
+            r#"
+[toolchain]
+components = [ "rust-src" ]
+"#,
+        )
+        .unwrap();
+
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            "rust-src (installed)",
+        );
+    });
+}
+
+#[test]
+fn file_override_toml_format_add_missing_targets() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            "arm-linux-androideabi (installed)",
+        );
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(
+            &toolchain_file,
+            r#"
+[toolchain]
+targets = [ "arm-linux-androideabi" ]
+"#,
+        )
+        .unwrap();
+
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            "arm-linux-androideabi (installed)",
+        );
+    });
+}
+
+#[test]
+fn file_override_toml_format_inherit() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+
+        let cwd = config.current_dir();
+        raw::write_file(
+            &cwd.join("rust-toolchain.toml"),
+            r#"
+[toolchain]
+channel = "nightly"
+"#,
+        )
+        .unwrap();
+        let member = cwd.join("member");
+        fs::create_dir_all(&member).unwrap();
+        raw::write_file(
+            &member.join("rust-toolchain.toml"),
+            r#"
+[toolchain]
+inherit = true
+targets = [ "arm-linux-androideabi" ]
+"#,
+        )
+        .unwrap();
+
+        config.change_dir(&member, &|| {
+            expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+            expect_stdout_ok(
+                config,
+                &["rustup", "component", "list"],
+                "arm-linux-androideabi (installed)",
+            );
+        });
+    });
+}
+
+#[test]
+fn file_override_toml_format_platform_sections() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+
+        let cwd = config.current_dir();
+        raw::write_file(
+            &cwd.join("rust-toolchain.toml"),
+            &format!(
+                r#"
+[toolchain]
+channel = "nightly"
+
+[target.'{}']
+components = [ "rust-src" ]
+
+[target.'cfg(any())']
+targets = [ "arm-linux-androideabi" ]
+"#,
+                this_host_triple()
+            ),
+        )
+        .unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            "rust-src (installed)",
+        );
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            "arm-linux-androideabi (installed)",
+        );
+    });
+}
+
+#[test]
+fn file_override_toml_format_manifest_sha256() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain.toml");
+        let pinned = |hash: &str| {
+            raw::write_file(
+                &toolchain_file,
+                &format!(
+                    "[toolchain]\nchannel = \"nightly\"\nmanifest-sha256 = \"{}\"",
+                    hash
+                ),
+            )
+            .unwrap();
+        };
+
+        let wrong = "0".repeat(64);
+        pinned(&wrong);
+        expect_err(config, &["rustc", "--version"], "is pinned");
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "toolchain", "list"],
+            for_host!("nightly-{}"),
+        );
+
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        let update_hash = config
+            .rustupdir
+            .join("update-hashes")
+            .join(for_host!("nightly-{}"));
+        let hash = fs::read_to_string(&update_hash).unwrap();
+        pinned(hash.trim());
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+
+        // The toolchain is checked on every run, not only when the project
+        // changes, so one since installed from something else is caught.
+        raw::write_file(&update_hash, &wrong).unwrap();
+        expect_err(config, &["rustc", "--version"], "is pinned");
+        raw::write_file(&update_hash, &hash).unwrap();
+
+        pinned(&wrong);
+        expect_err(config, &["rustc", "--version"], "is pinned");
+    });
+}
+
+#[test]
+#[cfg(unix)]
+fn override_hook() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        let runs = config.current_dir().join("hook-runs");
+
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "set",
+                "override-hook",
+                "--",
+                "sh",
+                "-c",
+                "echo >> hook-runs; echo nightly",
+            ],
+        );
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 1);
+        expect_stdout_ok(
+            config,
+            &["rustup", "override", "explain"],
+            "chosen by the override hook",
+        );
+
+        // Nothing printed means no opinion.
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "set",
+                "override-hook",
+                "--ttl",
+                "0",
+                "--",
+                "sh",
+                "-c",
+                "echo >> hook-runs",
+            ],
+        );
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+        assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 3);
+
+        expect_ok(config, &["rustup", "set", "override-hook", "--", "false"]);
+        expect_err(
+            config,
+            &["rustc", "--version"],
+            "the override hook 'false' failed",
+        );
+
+        expect_ok(config, &["rustup", "set", "override-hook"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+    });
+}
+
+#[test]
+#[cfg(unix)]
+fn rustc_wrapper() {
+    use std::os::unix::fs::PermissionsExt;
+
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        let wrapper = config.current_dir().join("wrapper");
+        raw::write_file(&wrapper, "#!/bin/sh\necho wrapped\nexec \"$@\"").unwrap();
+        fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+        let wrapper = wrapper.to_str().unwrap();
+
+        expect_ok(config, &["rustup", "set", "rustc-wrapper", wrapper]);
+        expect_stdout_ok(config, &["rustc", "--version"], "wrapped");
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+        let out = run(
+            config,
+            "rustc",
+            &["--version"],
+            &[("RUSTC_WRAPPER", "sccache")],
+        );
+        assert!(out.ok, "{}", out.stderr);
+        assert!(!out.stdout.contains("wrapped"));
+
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "set",
+                "rustc-wrapper",
+                "--toolchain",
+                "nightly",
+                "none",
+            ],
+        );
+        let out = run(config, "rustc", &["+nightly", "--version"], &[]);
+        assert!(out.stdout.contains("hash-nightly-2"));
+        assert!(!out.stdout.contains("wrapped"));
+
+        expect_ok(
+            config,
+            &["rustup", "set", "rustc-wrapper", "--toolchain", "nightly"],
+        );
+        expect_stdout_ok(config, &["rustc", "+nightly", "--version"], "wrapped");
+
+        expect_ok(config, &["rustup", "set", "rustc-wrapper"]);
+        let out = run(config, "rustc", &["--version"], &[]);
+        assert!(!out.stdout.contains("wrapped"));
+    });
+}
+
+#[test]
+fn file_override_from_env_and_flag() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "beta"]);
+        let cwd = config.current_dir();
+        raw::write_file(
+            &cwd.join("rust-toolchain.toml"),
+            "[toolchain]\nchannel = \"nightly\"",
+        )
+        .unwrap();
+        raw::write_file(
+            &cwd.join("rust-toolchain.msrv.toml"),
+            "[toolchain]\nchannel = \"stable\"",
+        )
+        .unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        let out = run(
+            config,
+            "rustc",
+            &["--version"],
+            &[("RUSTUP_TOOLCHAIN_FILE", "rust-toolchain.msrv.toml")],
+        );
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.contains("hash-stable-1.1.0"));
+
+        expect_stdout_ok(
+            config,
+            &[
+                "rustup",
+                "--toolchain-file",
+                "rust-toolchain.msrv.toml",
+                "show",
+                "active-toolchain",
+            ],
+            for_host!("stable-{0}"),
+        );
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "--toolchain-file",
+                "missing.toml",
+                "show",
+                "active-toolchain",
+            ],
+            "missing.toml",
+        );
+    });
+}
+
+#[test]
+fn proxy_cache_follows_changes() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "stable"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        assert!(config.rustupdir.has("proxy-cache"));
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+
+        let toolchain_file = config.current_dir().join("rust-toolchain");
+        raw::write_file(&toolchain_file, "stable").unwrap();
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+
+        fs::remove_file(&toolchain_file).unwrap();
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+
+        // A toolchain which is gone is looked for again, and so reinstalled.
+        expect_ok(config, &["rustup", "toolchain", "remove", "stable"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+    });
+}
+
+#[test]
+fn prompt_prints_active_toolchain() {
+    setup(&|config| {
+        let out = run(config, "rustup", &["prompt"], &[]);
+        assert_eq!(out.status, Some(1));
+        assert_eq!(out.stdout, "");
+
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok_ex(
+            config,
+            &["rustup", "prompt"],
+            for_host!("nightly-{0}\n"),
+            "",
+        );
+        assert!(config.rustupdir.has("proxy-cache"));
+        expect_ok_ex(
+            config,
+            &["rustup", "prompt"],
+            for_host!("nightly-{0}\n"),
+            "",
+        );
+
+        // A toolchain file changes the answer, but isn't installed from.
+        let toolchain_file = config.current_dir().join("rust-toolchain");
+        raw::write_file(&toolchain_file, "stable").unwrap();
+        expect_ok_ex(config, &["rustup", "prompt"], for_host!("stable-{0}\n"), "");
+        expect_not_stdout_ok(config, &["rustup", "toolchain", "list"], "stable");
+    });
+}
+
+#[test]
+fn command_aliases() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let settings_file = config.rustupdir.join("settings.toml");
+        let mut settings = fs::read_to_string(&settings_file).unwrap();
+        settings
+            .push_str("\n[aliases]\ntl = \"toolchain list\"\nup = \"update\"\nupdate = \"show\"\n");
+        raw::write_file(&settings_file, &settings).unwrap();
+
+        expect_stdout_ok(
+            config,
+            &["rustup", "tl"],
+            for_host!("nightly-{0} (default)"),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "--color", "never", "tl", "--verbose"],
+            for_host!("nightly-{0} (default)"),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "--format", "text", "tl"],
+            for_host!("nightly-{0} (default)"),
+        );
+        expect_ok(config, &["rustup", "up", "nightly"]);
+        // An alias can't take the place of one of rustup's commands.
+        expect_not_stdout_ok(config, &["rustup", "update", "nightly"], "active toolchain");
+        expect_err(config, &["rustup", "upp"], "");
+    });
+}
+
+#[test]
+fn settings_keep_comments() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let settings_file = config.rustupdir.join("settings.toml");
+        let settings = format!(
+            "# Managed by my dotfiles.\n{}\n# Shortcuts.\n[aliases]\nup = \"update\" # the usual\n",
+            fs::read_to_string(&settings_file).unwrap()
+        );
+        raw::write_file(&settings_file, &settings).unwrap();
+
+        expect_ok(config, &["rustup", "set", "profile", "minimal"]);
+        expect_ok(config, &["rustup", "default", "stable"]);
+        let settings = fs::read_to_string(&settings_file).unwrap();
+        assert!(
+            settings.starts_with("# Managed by my dotfiles.\n"),
+            "{}",
+            settings
+        );
+        assert!(settings.contains("profile = \"minimal\"\n"));
+        assert!(settings.contains(for_host!("default_toolchain = \"stable-{}\"\n")));
+        assert!(settings.contains("\n# Shortcuts.\n[aliases]\nup = \"update\" # the usual\n"));
+
+        // Changes inside tables keep them too.
+        let settings = settings + "\n[network]\nip-version = \"v4\" # for now\n";
+        raw::write_file(&settings_file, &settings).unwrap();
+        expect_ok(config, &["rustup", "set", "ip-version", "v6"]);
+        expect_ok(config, &["rustup", "override", "set", "beta"]);
+        let settings = fs::read_to_string(&settings_file).unwrap();
+        assert!(
+            settings.starts_with("# Managed by my dotfiles.\n"),
+            "{}",
+            settings
+        );
+        assert!(settings.contains("\n# Shortcuts.\n[aliases]\nup = \"update\" # the usual\n"));
+        assert!(settings.contains("\n[network]\nip-version = \"v6\" # for now\n"));
+    });
+}
+
+#[test]
+fn file_override_msrv() {
+    setup(&|config| {
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain.toml");
+        raw::write_file(&toolchain_file, "[toolchain]\nchannel = \"msrv\"").unwrap();
+        expect_err(config, &["rustc", "--version"], "there is none for");
+
+        raw::write_file(
+            &cwd.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"foo\"]\n\n[workspace.package]\nrust-version = \"1.1.0\"",
+        )
+        .unwrap();
+        let member = cwd.join("foo");
+        fs::create_dir_all(&member).unwrap();
+        raw::write_file(
+            &member.join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nrust-version.workspace = true",
+        )
+        .unwrap();
+        config.change_dir(&member, &|| {
+            expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+        });
+
+        fs::remove_file(&toolchain_file).unwrap();
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        config.change_dir(&member, &|| {
+            expect_stdout_ok(
+                config,
+                &["rustc", "+msrv", "--version"],
+                "hash-stable-1.1.0",
+            );
+        });
+    });
+}
+
+#[test]
+fn override_msrv_for_another_directory() {
+    setup(&|config| {
+        let member = config.current_dir().join("foo");
+        fs::create_dir_all(&member).unwrap();
+        raw::write_file(
+            &member.join("Cargo.toml"),
+            "[package]\nname = \"foo\"\nrust-version = \"1.1.0\"",
         )
         .unwrap();
+        // The `rust-version` is the one where the override is, not here.
+        let path = member.to_string_lossy();
+        expect_ok(
+            config,
+            &["rustup", "override", "set", "msrv", "--path", &path],
+        );
+        config.change_dir(&member, &|| {
+            expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+        });
+    });
+}
+
+#[test]
+fn msrv_outside_a_project() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let settings_file = config.rustupdir.join("settings.toml");
+        let settings = fs::read_to_string(&settings_file)
+            .unwrap()
+            .replace(for_host!("nightly-{}"), "msrv");
+        raw::write_file(&settings_file, &settings).unwrap();
+        expect_ok(config, &["rustup", "toolchain", "list"]);
+        expect_ok(config, &["rustup", "show"]);
+        expect_err(config, &["rustc", "--version"], "there is none for");
+
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let toolchain_file = config.current_dir().join("rust-toolchain.toml");
+        raw::write_file(&toolchain_file, "[toolchain]\nchannel = \"msrv\"").unwrap();
+        expect_ok(config, &["rustup", "toolchain", "list"]);
+        expect_ok(config, &["rustup", "show"]);
+        expect_ok(config, &["rustup", "override", "list"]);
+        fs::remove_file(&toolchain_file).unwrap();
+
+        // A linked toolchain of the name comes first.
+        let path = config.customdir.join("custom-1");
+        let path = path.to_string_lossy();
+        expect_ok(config, &["rustup", "toolchain", "link", "msrv", &path]);
+        expect_stdout_ok(config, &["rustc", "+msrv", "--version"], "hash-c-1");
+    });
+}
+
+#[test]
+fn file_override_search_stops_at_vcs_root() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        let cwd = config.current_dir();
+        raw::write_file(&cwd.join("rust-toolchain"), "nightly").unwrap();
+        let repo = cwd.join("repo");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        let subdir = repo.join("src");
+        fs::create_dir_all(&subdir).unwrap();
+
+        config.change_dir(&subdir, &|| {
+            expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+            expect_ok(
+                config,
+                &["rustup", "set", "toolchain-file-search", "vcs-root"],
+            );
+            expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+
+            raw::write_file(
+                &repo.join("rust-toolchain.toml"),
+                "[toolchain]\ninherit = true",
+            )
+            .unwrap();
+            expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+
+            expect_ok(
+                config,
+                &["rustup", "set", "toolchain-file-search", "filesystem"],
+            );
+            expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        });
+    });
+}
+
+#[test]
+fn file_override_toml_format_dist_server() {
+    setup(&|config| {
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain.toml");
+        let server = format!("file://{}", config.distdir.to_string_lossy());
+        raw::write_file(
+            &toolchain_file,
+            &format!(
+                "[toolchain]\nchannel = \"nightly\"\ndist-server = \"{}\"",
+                server
+            ),
+        )
+        .unwrap();
+
+        // Only installing the toolchain the file chooses uses the server.
+        let out = run(
+            config,
+            "rustup",
+            &["toolchain", "list"],
+            &[("RUSTUP_DIST_SERVER", "")],
+        );
+        assert!(out.ok);
+        assert!(!out.stderr.contains("dist server"), "{}", out.stderr);
+
+        let out = run(
+            config,
+            "rustc",
+            &["--version"],
+            &[("RUSTUP_DIST_SERVER", "")],
+        );
+        assert!(out
+            .stderr
+            .contains(&format!("ignoring the dist server '{}'", server)));
+
+        expect_ok(config, &["rustup", "trust", "add", "."]);
+        let out = run(
+            config,
+            "rustc",
+            &["--version"],
+            &[("RUSTUP_DIST_SERVER", "")],
+        );
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.contains("hash-nightly-2"));
+        assert!(!out.stderr.contains("ignoring the dist server"));
+    });
+}
+
+#[test]
+fn file_override_toml_format_skip_invalid_component() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(
+            &toolchain_file,
+            r#"
+[toolchain]
+components = [ "rust-bongo" ]
+"#,
+        )
+        .unwrap();
+
+        expect_stderr_ok(
+            config,
+            &["rustc", "--version"],
+            "warning: Force-skipping unavailable component 'rust-bongo",
+        );
+    });
+}
+
+#[test]
+fn file_override_toml_format_specify_profile() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "set", "profile", "default"]);
+        expect_stderr_ok(
+            config,
+            &["rustup", "default", "stable"],
+            "downloading component 'rust-docs'",
+        );
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(
+            &toolchain_file,
+            r#"
+[toolchain]
+profile = "minimal"
+channel = "nightly"
+"#,
+        )
+        .unwrap();
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            for_host!("rust-docs-{} (installed)"),
+        );
+    });
+}
+
+#[test]
+fn directory_override_beats_file_override() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        expect_ok(config, &["rustup", "override", "set", "beta"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-beta-1.2.0");
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "nightly").unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-beta-1.2.0");
+    });
+}
+
+#[test]
+fn close_file_override_beats_far_directory_override() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        expect_ok(config, &["rustup", "override", "set", "beta"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-beta-1.2.0");
+
+        let cwd = config.current_dir();
+
+        let subdir = cwd.join("subdir");
+        fs::create_dir_all(&subdir).unwrap();
+
+        let toolchain_file = subdir.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "nightly").unwrap();
+
+        config.change_dir(&subdir, &|| {
+            expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        });
+    });
+}
+
+#[test]
+fn directory_override_doesnt_need_to_exist_unless_it_is_selected() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
+        // not installing nightly
+
+        expect_ok(config, &["rustup", "override", "set", "beta"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-beta-1.2.0");
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "nightly").unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-beta-1.2.0");
+    });
+}
+
+#[test]
+fn env_override_beats_file_override() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "nightly").unwrap();
+
+        let mut cmd = clitools::cmd(config, "rustc", &["--version"]);
+        clitools::env(config, &mut cmd);
+        cmd.env("RUSTUP_TOOLCHAIN", "beta");
+
+        let out = cmd.output().unwrap();
+        assert!(String::from_utf8(out.stdout)
+            .unwrap()
+            .contains("hash-beta-1.2.0"));
+    });
+}
+
+#[test]
+fn plus_override_beats_file_override() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "nightly").unwrap();
+
+        expect_stdout_ok(config, &["rustc", "+beta", "--version"], "hash-beta-1.2.0");
+    });
+}
+
+#[test]
+fn bad_file_override() {
+    setup(&|config| {
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "gumbo").unwrap();
+
+        expect_err(
+            config,
+            &["rustc", "--version"],
+            "invalid toolchain name: 'gumbo'",
+        );
+    });
+}
+
+#[test]
+fn valid_override_settings() {
+    setup(&|config| {
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        raw::write_file(&toolchain_file, "nightly").unwrap();
+        expect_ok(config, &["rustc", "--version"]);
+        raw::write_file(&toolchain_file, for_host!("nightly-{}")).unwrap();
+        expect_ok(config, &["rustc", "--version"]);
+        let fullpath = config
+            .rustupdir
+            .clone()
+            .join("toolchains")
+            .join(for_host!("nightly-{}"));
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "link",
+                "system",
+                &format!("{}", fullpath.display()),
+            ],
+        );
+        raw::write_file(&toolchain_file, "system").unwrap();
+        expect_ok(config, &["rustc", "--version"]);
+    })
+}
 
-        expect_stdout_ok(
+#[test]
+fn file_override_with_target_info() {
+    setup(&|config| {
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "nightly-x86_64-unknown-linux-gnu").unwrap();
+
+        expect_err(
             config,
-            &["rustup", "component", "list"],
-            "arm-linux-androideabi (installed)",
+            &["rustc", "--version"],
+            "target triple in channel name 'nightly-x86_64-unknown-linux-gnu'",
         );
     });
 }
 
 #[test]
-fn file_override_toml_format_skip_invalid_component() {
+fn docs_with_path() {
     setup(&|config| {
         expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
 
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(
-            &toolchain_file,
-            r#"
-[toolchain]
-components = [ "rust-bongo" ]
-"#,
-        )
-        .unwrap();
+        let mut cmd = clitools::cmd(config, "rustup", &["doc", "--path"]);
+        clitools::env(config, &mut cmd);
 
-        expect_stderr_ok(
+        let out = cmd.output().unwrap();
+        let path = format!("share{0}doc{0}rust{0}html", MAIN_SEPARATOR);
+        assert!(String::from_utf8(out.stdout).unwrap().contains(&path));
+
+        let mut cmd = clitools::cmd(
             config,
-            &["rustc", "--version"],
-            "warning: Force-skipping unavailable component 'rust-bongo",
+            "rustup",
+            &["doc", "--path", "--toolchain", "nightly"],
         );
+        clitools::env(config, &mut cmd);
+
+        let out = cmd.output().unwrap();
+        assert!(String::from_utf8(out.stdout).unwrap().contains("nightly"));
     });
 }
 
 #[test]
-fn file_override_toml_format_specify_profile() {
+fn docs_topical_with_path() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "set", "profile", "default"]);
-        expect_stderr_ok(
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+
+        for (topic, path) in mock::topical_doc_data::test_cases() {
+            let mut cmd = clitools::cmd(config, "rustup", &["doc", "--path", topic]);
+            clitools::env(config, &mut cmd);
+
+            let out = cmd.output().unwrap();
+            eprintln!("{:?}", String::from_utf8(out.stderr).unwrap());
+            let out_str = String::from_utf8(out.stdout).unwrap();
+            assert!(
+                out_str.contains(&path),
+                "comparing path\ntopic: '{}'\nexpected path: '{}'\noutput: {}\n\n\n",
+                topic,
+                path,
+                out_str,
+            );
+        }
+    });
+}
+
+#[test]
+fn docs_missing() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "set", "profile", "minimal"]);
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_err(
             config,
-            &["rustup", "default", "stable"],
-            "downloading component 'rust-docs'",
+            &["rustup", "doc"],
+            "error: unable to view documentation which is not installed",
         );
+    });
+}
 
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
+#[test]
+fn docs_custom() {
+    setup(&|config| {
+        let path = config.customdir.join("custom-1");
+        let path = path.to_string_lossy();
+        expect_ok(config, &["rustup", "toolchain", "link", "custom", &path]);
+        expect_ok(config, &["rustup", "default", "custom"]);
+        expect_stdout_ok(config, &["rustup", "doc", "--path"], "custom");
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_arg() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let out = run(
+            config,
+            "rustc",
+            &[
+                OsStr::new("--echo-args"),
+                OsStr::new("echoed non-utf8 arg:"),
+                OsStr::from_bytes(b"\xc3\x28"),
+            ],
+            &[("RUST_BACKTRACE", "1")],
+        );
+        assert!(out.stderr.contains("echoed non-utf8 arg"));
+    });
+}
+
+#[cfg(windows)]
+#[test]
+fn non_utf8_arg() {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::OsStringExt;
+
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let out = run(
+            config,
+            "rustc",
+            &[
+                OsString::from("--echo-args".to_string()),
+                OsString::from("echoed non-utf8 arg:".to_string()),
+                OsString::from_wide(&[0xd801, 0xd801]),
+            ],
+            &[("RUST_BACKTRACE", "1")],
+        );
+        assert!(out.stderr.contains("echoed non-utf8 arg"));
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_toolchain() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let out = run(
+            config,
+            "rustc",
+            &[OsStr::from_bytes(b"+\xc3\x28")],
+            &[("RUST_BACKTRACE", "1")],
+        );
+        assert!(out.stderr.contains("toolchain '�(' is not installed"));
+    });
+}
+
+#[cfg(windows)]
+#[test]
+fn non_utf8_toolchain() {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::OsStringExt;
+
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let out = run(
+            config,
+            "rustc",
+            &[OsString::from_wide(&[u16::from(b'+'), 0xd801, 0xd801])],
+            &[("RUST_BACKTRACE", "1")],
+        );
+        assert!(out.stderr.contains("toolchain '��' is not installed"));
+    });
+}
+
+#[test]
+fn check_host_goes_away() {
+    clitools::setup(Scenario::HostGoesMissing, &|config| {
+        set_current_dist_date(config, "2019-12-09");
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        set_current_dist_date(config, "2019-12-10");
+        expect_err(
+            config,
+            &["rustup", "update", "nightly"],
+            for_host!("target '{}' not found in channel"),
+        );
+    })
+}
+
+#[cfg(unix)]
+#[test]
+fn check_unix_settings_fallback() {
+    setup(&|config| {
+        // No default toolchain specified yet
+        expect_err(
+            config,
+            &["rustup", "default"],
+            r"no default toolchain configured",
+        );
+
+        // Default toolchain specified in fallback settings file
+        let mock_settings_file = config.current_dir().join("mock_fallback_settings.toml");
         raw::write_file(
-            &toolchain_file,
-            r#"
-[toolchain]
-profile = "minimal"
-channel = "nightly"
-"#,
+            &mock_settings_file,
+            for_host!(r"default_toolchain = 'nightly-{0}'"),
         )
         .unwrap();
-        expect_not_stdout_ok(
+
+        let mut cmd = clitools::cmd(config, "rustup", &["default"]);
+        clitools::env(config, &mut cmd);
+
+        // Override the path to the fallback settings file to be the mock file
+        cmd.env("RUSTUP_OVERRIDE_UNIX_FALLBACK_SETTINGS", mock_settings_file);
+
+        let out = cmd.output().unwrap();
+        assert!(out.status.success());
+        let stdout = String::from_utf8(out.stdout).unwrap();
+        assert_eq!(
+            &stdout,
+            for_host!(
+                r"nightly-{0} (default)
+"
+            )
+        );
+    });
+}
+
+#[test]
+fn warn_on_unmatch_build() {
+    clitools::setup(Scenario::MultiHost, &|config| {
+        let arch = clitools::MULTI_ARCH1;
+        expect_stderr_ok(
             config,
-            &["rustup", "component", "list"],
-            for_host!("rust-docs-{} (installed)"),
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                &format!("nightly-{}", arch),
+            ],
+            &format!(
+                r"warning: toolchain 'nightly-{0}' may not be able to run on this system.
+warning: If you meant to build software to target that platform, perhaps try `rustup target add {0}` instead?",
+                arch,
+            ),
         );
     });
 }
 
+/// Checks that `rust-toolchain.toml` files are considered
 #[test]
-fn directory_override_beats_file_override() {
+fn rust_toolchain_toml() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
-
-        expect_ok(config, &["rustup", "override", "set", "beta"]);
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-beta-1.2.0");
+        expect_err(
+            config,
+            &["rustc", "--version"],
+            "no override and no default toolchain set",
+        );
 
         let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(&toolchain_file, "nightly").unwrap();
+        let toolchain_file = cwd.join("rust-toolchain.toml");
+        raw::write_file(&toolchain_file, "[toolchain]\nchannel = \"nightly\"").unwrap();
 
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-beta-1.2.0");
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
     });
 }
 
+/// Ensures that `rust-toolchain.toml` files (with `.toml` extension) only allow TOML contents
 #[test]
-fn close_file_override_beats_far_directory_override() {
+fn only_toml_in_rust_toolchain_toml() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
-
-        expect_ok(config, &["rustup", "override", "set", "beta"]);
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-beta-1.2.0");
-
         let cwd = config.current_dir();
-
-        let subdir = cwd.join("subdir");
-        fs::create_dir_all(&subdir).unwrap();
-
-        let toolchain_file = subdir.join("rust-toolchain");
+        let toolchain_file = cwd.join("rust-toolchain.toml");
         raw::write_file(&toolchain_file, "nightly").unwrap();
 
-        config.change_dir(&subdir, &|| {
-            expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
-        });
+        expect_err(
+            config,
+            &["rustc", "--version"],
+            "error parsing override file",
+        );
     });
 }
 
+/// Checks that a warning occurs if both `rust-toolchain` and `rust-toolchain.toml` files exist
 #[test]
-fn directory_override_doesnt_need_to_exist_unless_it_is_selected() {
+fn warn_on_duplicate_rust_toolchain_file() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
-        // not installing nightly
-
-        expect_ok(config, &["rustup", "override", "set", "beta"]);
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-beta-1.2.0");
-
         let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(&toolchain_file, "nightly").unwrap();
+        let toolchain_file_1 = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file_1, "stable").unwrap();
+        let toolchain_file_2 = cwd.join("rust-toolchain.toml");
+        raw::write_file(&toolchain_file_2, "[toolchain]").unwrap();
 
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-beta-1.2.0");
+        expect_stderr_ok(
+            config,
+            &["rustc", "--version"],
+            &format!(
+                "warning: both `{0}` and `{1}` exist. Using `{0}`",
+                toolchain_file_1.canonicalize().unwrap().display(),
+                toolchain_file_2.canonicalize().unwrap().display(),
+            ),
+        );
     });
 }
 
 #[test]
-fn env_override_beats_file_override() {
+fn host_override() {
     setup(&|config| {
         expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
-
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(&toolchain_file, "nightly").unwrap();
-
-        let mut cmd = clitools::cmd(config, "rustc", &["--version"]);
-        clitools::env(config, &mut cmd);
-        cmd.env("RUSTUP_TOOLCHAIN", "beta");
+        let subdir = config.current_dir().join("cross");
+        fs::create_dir_all(&subdir).unwrap();
+        config.change_dir(&subdir, &|| {
+            expect_ok(
+                config,
+                &["rustup", "override", "host", clitools::CROSS_ARCH1],
+            );
+            expect_stdout_ok(
+                config,
+                &["rustup", "show"],
+                &format!("Default host: {} (host override for", clitools::CROSS_ARCH1),
+            );
+            // The mock dist server only has the standard library for it.
+            expect_err(
+                config,
+                &["rustup", "toolchain", "install", "nightly"],
+                &format!("nightly-{}", clitools::CROSS_ARCH1),
+            );
+        });
+        expect_stdout_ok(
+            config,
+            &["rustup", "show"],
+            &format!("Default host: {}\n", this_host_triple()),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "override", "list"],
+            &format!("host {}", clitools::CROSS_ARCH1),
+        );
 
-        let out = cmd.output().unwrap();
-        assert!(String::from_utf8(out.stdout)
-            .unwrap()
-            .contains("hash-beta-1.2.0"));
+        let path = subdir.to_str().unwrap();
+        expect_ok(
+            config,
+            &["rustup", "override", "host", "--unset", "--path", path],
+        );
+        expect_stdout_ok(config, &["rustup", "override", "list"], "no overrides");
     });
 }
 
 #[test]
-fn plus_override_beats_file_override() {
+fn default_is_saved_without_host_override() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
-
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(&toolchain_file, "nightly").unwrap();
-
-        expect_stdout_ok(config, &["rustc", "+beta", "--version"], "hash-beta-1.2.0");
+        let subdir = config.current_dir().join("cross");
+        fs::create_dir_all(&subdir).unwrap();
+        config.change_dir(&subdir, &|| {
+            expect_ok(
+                config,
+                &["rustup", "override", "host", clitools::CROSS_ARCH1],
+            );
+            expect_ok(config, &["rustup", "default", "nightly"]);
+        });
+        expect_stdout_ok(
+            config,
+            &["rustup", "default"],
+            &format!("nightly-{} (default)", this_host_triple()),
+        );
     });
 }
 
 #[test]
-fn bad_file_override() {
+fn toolchain_host_override() {
     setup(&|config| {
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(&toolchain_file, "gumbo").unwrap();
-
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "override",
+                "host",
+                "--toolchain",
+                "nightly",
+                clitools::CROSS_ARCH1,
+            ],
+        );
+        // The mock dist server only has the standard library for it.
         expect_err(
             config,
-            &["rustc", "--version"],
-            "invalid toolchain name: 'gumbo'",
+            &["rustup", "toolchain", "install", "nightly"],
+            &format!("nightly-{}", clitools::CROSS_ARCH1),
         );
-    });
-}
+        expect_ok(config, &["rustup", "toolchain", "install", "stable"]);
 
-#[test]
-fn valid_override_settings() {
-    setup(&|config| {
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        expect_ok(config, &["rustup", "default", "nightly"]);
-        raw::write_file(&toolchain_file, "nightly").unwrap();
-        expect_ok(config, &["rustc", "--version"]);
-        raw::write_file(&toolchain_file, for_host!("nightly-{}")).unwrap();
-        expect_ok(config, &["rustc", "--version"]);
-        let fullpath = config
-            .rustupdir
-            .clone()
-            .join("toolchains")
-            .join(for_host!("nightly-{}"));
         expect_ok(
             config,
             &[
                 "rustup",
-                "toolchain",
-                "link",
-                "system",
-                &format!("{}", fullpath.display()),
+                "override",
+                "host",
+                "--toolchain",
+                "nightly",
+                "--unset",
             ],
         );
-        raw::write_file(&toolchain_file, "system").unwrap();
-        expect_ok(config, &["rustc", "--version"]);
-    })
-}
-
-#[test]
-fn file_override_with_target_info() {
-    setup(&|config| {
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain");
-        raw::write_file(&toolchain_file, "nightly-x86_64-unknown-linux-gnu").unwrap();
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
 
+        let nightly = format!("nightly-{}", this_host_triple());
         expect_err(
             config,
-            &["rustc", "--version"],
-            "target triple in channel name 'nightly-x86_64-unknown-linux-gnu'",
+            &[
+                "rustup",
+                "override",
+                "host",
+                "--toolchain",
+                &nightly,
+                clitools::CROSS_ARCH1,
+            ],
+            "already names its host",
         );
     });
 }
 
 #[test]
-fn docs_with_path() {
+fn toolchain_uninstall_purge() {
     setup(&|config| {
         expect_ok(config, &["rustup", "default", "stable"]);
         expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        expect_ok(config, &["rustup", "override", "set", "nightly"]);
+        let cached = config.rustupdir.join("downloads").join("leftover");
+        raw::write_file(&cached, "leftover").unwrap();
 
-        let mut cmd = clitools::cmd(config, "rustup", &["doc", "--path"]);
-        clitools::env(config, &mut cmd);
+        expect_stderr_ok(
+            config,
+            &["rustup", "toolchain", "uninstall", "--purge", "nightly"],
+            "info: reclaimed ",
+        );
+        expect_stdout_ok(config, &["rustup", "override", "list"], "no overrides");
+        assert!(!cached.exists());
+        assert!(!config
+            .rustupdir
+            .has(&format!("toolchains/nightly-{}", this_host_triple())));
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+    });
+}
 
-        let out = cmd.output().unwrap();
-        let path = format!("share{0}doc{0}rust{0}html", MAIN_SEPARATOR);
-        assert!(String::from_utf8(out.stdout).unwrap().contains(&path));
+#[test]
+fn override_explain() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "override", "set", "nightly"]);
+        let project = config.current_dir().join("project");
+        fs::create_dir_all(&project).unwrap();
+        raw::write_file(
+            &project.join("rust-toolchain.toml"),
+            "[toolchain]\nchannel = \"beta\"\n",
+        )
+        .unwrap();
 
-        let mut cmd = clitools::cmd(
+        config.change_dir(&project, &|| {
+            expect_stdout_ok(
+                config,
+                &["rustup", "override", "explain"],
+                "rust-toolchain.toml' (active)\n     channel = \"beta\"\n2. directory override for '",
+            );
+            expect_stdout_ok(
+                config,
+                &["rustup", "override", "explain"],
+                for_host!("' (ignored)\n     toolchain = \"nightly-{0}\"\n"),
+            );
+            expect_stdout_ok(
+                config,
+                &["rustup", "override", "explain"],
+                for_host!("3. default toolchain (ignored)\n     toolchain = \"stable-{0}\"\n"),
+            );
+        });
+        expect_stdout_ok(
             config,
-            "rustup",
-            &["doc", "--path", "--toolchain", "nightly"],
+            &["rustup", "override", "explain"],
+            "1. directory override for '",
         );
-        clitools::env(config, &mut cmd);
-
-        let out = cmd.output().unwrap();
-        assert!(String::from_utf8(out.stdout).unwrap().contains("nightly"));
     });
 }
 
 #[test]
-fn docs_topical_with_path() {
+fn toolchain_file_check() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "stable"]);
-        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain.toml");
+        raw::write_file(
+            &toolchain_file,
+            r#"
+[toolchain]
+channel = "nightly"
+componets = [ "rust-src" ]
+"#,
+        )
+        .unwrap();
+        expect_err(
+            config,
+            &["rustup", "toolchain-file", "check"],
+            "unknown key 'toolchain.componets'",
+        );
+        expect_stderr_ok(
+            config,
+            &["rustc", "--version"],
+            "unknown key 'toolchain.componets'",
+        );
 
-        for (topic, path) in mock::topical_doc_data::test_cases() {
-            let mut cmd = clitools::cmd(config, "rustup", &["doc", "--path", topic]);
-            clitools::env(config, &mut cmd);
+        raw::write_file(
+            &toolchain_file,
+            r#"
+[toolchain]
+channel = "nightly"
+components = [ "rust-src", "bogus" ]
+targets = [ "bogus-target" ]
+"#,
+        )
+        .unwrap();
+        expect_err(
+            config,
+            &["rustup", "toolchain-file", "check", "rust-toolchain.toml"],
+            "component 'bogus' is not available",
+        );
+        expect_err(
+            config,
+            &["rustup", "toolchain-file", "check"],
+            "target 'bogus-target' is not available",
+        );
 
-            let out = cmd.output().unwrap();
-            eprintln!("{:?}", String::from_utf8(out.stderr).unwrap());
-            let out_str = String::from_utf8(out.stdout).unwrap();
-            assert!(
-                out_str.contains(&path),
-                "comparing path\ntopic: '{}'\nexpected path: '{}'\noutput: {}\n\n\n",
-                topic,
-                path,
-                out_str,
-            );
-        }
+        raw::write_file(
+            &toolchain_file,
+            r#"
+[toolchain]
+channel = "nightly"
+components = [ "rust-src" ]
+"#,
+        )
+        .unwrap();
+        expect_stdout_ok(config, &["rustup", "toolchain-file", "check"], ": ok");
     });
 }
 
 #[test]
-fn docs_missing() {
+#[cfg(unix)]
+fn run_with_env_and_dir() {
+    use std::os::unix::fs::PermissionsExt;
+
     setup(&|config| {
-        expect_ok(config, &["rustup", "set", "profile", "minimal"]);
-        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "stable"]);
+        let tool = config
+            .rustupdir
+            .join("toolchains")
+            .join(format!("stable-{}", this_host_triple()))
+            .join("bin/show-env");
+        raw::write_file(
+            &tool,
+            "#!/bin/sh\necho \"foo=$FOO inherited=$INHERITED toolchain=$RUSTUP_TOOLCHAIN\"\npwd",
+        )
+        .unwrap();
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+        let dir = config.current_dir().join("elsewhere");
+        fs::create_dir(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+
+        let args = &["run", "--env", "FOO=a=b", "--cd", dir, "stable", "show-env"];
+        let out = run(config, "rustup", args, &[("INHERITED", "yes")]);
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out
+            .stdout
+            .contains("foo=a=b inherited=yes toolchain=stable"));
+        assert!(out.stdout.contains("elsewhere"));
+
+        let args = &["run", "--clean-env", "stable", "show-env"];
+        let out = run(config, "rustup", args, &[("INHERITED", "yes")]);
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.contains("foo= inherited= toolchain=stable"));
+
         expect_err(
             config,
-            &["rustup", "doc"],
-            "error: unable to view documentation which is not installed",
+            &["rustup", "run", "--env", "FOO", "stable", "show-env"],
+            "expected KEY=VALUE, found 'FOO'",
+        );
+        expect_err(
+            config,
+            &["rustup", "run", "--cd", "missing", "stable", "show-env"],
+            "'missing' is not a directory",
         );
     });
 }
 
 #[test]
-fn docs_custom() {
+fn news() {
     setup(&|config| {
-        let path = config.customdir.join("custom-1");
-        let path = path.to_string_lossy();
-        expect_ok(config, &["rustup", "toolchain", "link", "custom", &path]);
-        expect_ok(config, &["rustup", "default", "custom"]);
-        expect_stdout_ok(config, &["rustup", "doc", "--path"], "custom");
+        let notes = config.current_dir().join("RELEASES.md");
+        fs::write(
+            &notes,
+            "Version 1.2.0 (2015-03-01)
+==========================
+
+Language
+--------
+- Something new.
+
+Version 1.1.0 (2015-01-02)
+==========================
+
+Compiler
+--------
+- Something faster.
+",
+        )
+        .unwrap();
+        let url = format!("file://{}", notes.to_string_lossy());
+        let env = &[("RUSTUP_RELEASE_NOTES_URL", url.as_str())];
+
+        let out = run(config, "rustup", &["news", "1.1.0"], env);
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.contains("Version 1.1.0 (2015-01-02)"));
+        assert!(out.stdout.contains("Something faster."));
+        assert!(!out.stdout.contains("Something new."));
+
+        // Shown offline from the cached copy.
+        fs::remove_file(&notes).unwrap();
+        let out = run(config, "rustup", &["news", "1.2.0"], env);
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.contains("Something new."));
+        let out = run(config, "rustup", &["news", "1.3.0"], env);
+        assert!(!out.ok);
+
+        expect_err(config, &["rustup", "news"], "no stable toolchain installed");
+        expect_ok(config, &["rustup", "toolchain", "install", "stable"]);
+        let out = run(config, "rustup", &["news"], env);
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.contains("Something faster."));
     });
 }
 
-#[cfg(unix)]
 #[test]
-fn non_utf8_arg() {
-    use std::ffi::OsStr;
-    use std::os::unix::ffi::OsStrExt;
-
+fn settings_schema_migration() {
     setup(&|config| {
+        let settings_file = config.rustupdir.join("settings.toml");
+        let old = "version = \"12\"\nprofile = \"minimal\"\nfrom_the_future = \"kept\"\n\n[aliases]\nup = \"update\"\n";
+        raw::write_file(&settings_file, old).unwrap();
+
         expect_ok(config, &["rustup", "default", "nightly"]);
-        let out = run(
-            config,
-            "rustc",
-            &[
-                OsStr::new("--echo-args"),
-                OsStr::new("echoed non-utf8 arg:"),
-                OsStr::from_bytes(b"\xc3\x28"),
-            ],
-            &[("RUST_BACKTRACE", "1")],
+        let settings = fs::read_to_string(&settings_file).unwrap();
+        assert!(settings.contains("schema_version = 1"), "{}", settings);
+        assert!(
+            settings.contains("[aliases]\nup = \"update\""),
+            "{}",
+            settings
         );
-        assert!(out.stderr.contains("echoed non-utf8 arg"));
+        assert!(
+            settings.contains("from_the_future = \"kept\""),
+            "{}",
+            settings
+        );
+        assert!(settings.contains("profile = \"minimal\""), "{}", settings);
+        // Nothing but the schema needed changing, so there is no backup.
+        assert!(!config.rustupdir.join("settings.toml.v0.bak").exists());
+
+        // Settings from a newer rustup keep their schema.
+        let newer = settings.replace("schema_version = 1", "schema_version = 99");
+        raw::write_file(&settings_file, &newer).unwrap();
+        expect_ok(config, &["rustup", "default", "stable"]);
+        let settings = fs::read_to_string(&settings_file).unwrap();
+        assert!(settings.contains("schema_version = 99"), "{}", settings);
+        assert!(
+            settings.contains("from_the_future = \"kept\""),
+            "{}",
+            settings
+        );
+        assert!(!config.rustupdir.join("settings.toml.v99.bak").exists());
     });
 }
 
-#[cfg(windows)]
 #[test]
-fn non_utf8_arg() {
-    use std::ffi::OsString;
-    use std::os::windows::ffi::OsStringExt;
-
+fn settings_schema_migration_without_changes_writes_nothing() {
     setup(&|config| {
-        expect_ok(config, &["rustup", "default", "nightly"]);
-        let out = run(
-            config,
-            "rustc",
-            &[
-                OsString::from("--echo-args".to_string()),
-                OsString::from("echoed non-utf8 arg:".to_string()),
-                OsString::from_wide(&[0xd801, 0xd801]),
-            ],
-            &[("RUST_BACKTRACE", "1")],
+        expect_ok(config, &["rustup", "default", "stable"]);
+        let settings_file = config.rustupdir.join("settings.toml");
+        let old = format!(
+            "version = \"12\"\ndefault_toolchain = \"{}\"\n",
+            for_host!("stable-{}")
         );
-        assert!(out.stderr.contains("echoed non-utf8 arg"));
+        raw::write_file(&settings_file, &old).unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+        assert_eq!(fs::read_to_string(&settings_file).unwrap(), old);
+        assert!(!config.rustupdir.join("settings.toml.v0.bak").exists());
     });
 }
 
-#[cfg(unix)]
 #[test]
-fn non_utf8_toolchain() {
-    use std::ffi::OsStr;
-    use std::os::unix::ffi::OsStrExt;
-
+fn settings_env_overrides() {
     setup(&|config| {
         expect_ok(config, &["rustup", "default", "nightly"]);
+        let env = &[
+            ("RUSTUP_SETTINGS_PROFILE", "minimal"),
+            ("RUSTUP_SETTINGS_DEFAULT_TOOLCHAIN", "stable"),
+        ];
+        let out = run(config, "rustup", &["show", "profile"], env);
+        assert!(out.ok, "{}", out.stderr);
+        assert_eq!(out.stdout, "minimal\n");
         let out = run(
             config,
-            "rustc",
-            &[OsStr::from_bytes(b"+\xc3\x28")],
-            &[("RUST_BACKTRACE", "1")],
+            "rustup",
+            &["config", "get", "default_toolchain"],
+            env,
         );
-        assert!(out.stderr.contains("toolchain '�(' is not installed"));
-    });
-}
-
-#[cfg(windows)]
-#[test]
-fn non_utf8_toolchain() {
-    use std::ffi::OsString;
-    use std::os::windows::ffi::OsStringExt;
+        assert_eq!(out.stdout, "stable\n");
 
-    setup(&|config| {
-        expect_ok(config, &["rustup", "default", "nightly"]);
+        // Changing the settings doesn't write the overrides to the file.
         let out = run(
             config,
-            "rustc",
-            &[OsString::from_wide(&[u16::from(b'+'), 0xd801, 0xd801])],
-            &[("RUST_BACKTRACE", "1")],
+            "rustup",
+            &["set", "auto-self-update", "disable"],
+            env,
+        );
+        assert!(out.ok, "{}", out.stderr);
+        expect_stdout_ok(config, &["rustup", "show", "profile"], "default");
+        expect_stdout_ok(
+            config,
+            &["rustup", "config", "get", "default_toolchain"],
+            "nightly",
         );
-        assert!(out.stderr.contains("toolchain '��' is not installed"));
-    });
-}
 
-#[test]
-fn check_host_goes_away() {
-    clitools::setup(Scenario::HostGoesMissing, &|config| {
-        set_current_dist_date(config, "2019-12-09");
-        expect_ok(config, &["rustup", "default", "nightly"]);
-        set_current_dist_date(config, "2019-12-10");
-        expect_err(
+        let out = run(
             config,
-            &["rustup", "update", "nightly"],
-            for_host!("target '{}' not found in channel"),
+            "rustup",
+            &["show", "profile"],
+            &[("RUSTUP_SETTINGS_PROFILE", "tiny")],
         );
-    })
+        assert!(!out.ok);
+        assert!(
+            out.stderr
+                .contains("invalid value 'tiny' for RUSTUP_SETTINGS_PROFILE"),
+            "{}",
+            out.stderr
+        );
+    });
 }
 
-#[cfg(unix)]
 #[test]
-fn check_unix_settings_fallback() {
+#[cfg(unix)]
+fn migrate_xdg() {
     setup(&|config| {
-        // No default toolchain specified yet
-        expect_err(
-            config,
-            &["rustup", "default"],
-            r"no default toolchain configured",
-        );
-
-        // Default toolchain specified in fallback settings file
-        let mock_settings_file = config.current_dir().join("mock_fallback_settings.toml");
-        raw::write_file(
-            &mock_settings_file,
-            for_host!(r"default_toolchain = 'nightly-{0}'"),
+        let home = &config.homedir;
+        let config_home = home.join("xdg-config");
+        let cache_home = home.join("xdg-cache");
+        let data_home = home.join("xdg-data");
+        let env = [
+            ("RUSTUP_HOME", ""),
+            ("XDG_CONFIG_HOME", config_home.to_str().unwrap()),
+            ("XDG_CACHE_HOME", cache_home.to_str().unwrap()),
+            ("XDG_DATA_HOME", data_home.to_str().unwrap()),
+        ];
+        let expect_ok = |args: &[&str]| {
+            let out = run(config, "rustup", args, &env);
+            assert!(out.ok, "{}", out.stderr);
+            out
+        };
+        expect_ok(&["set", "auto-self-update", "disable"]);
+        expect_ok(&["default", "nightly"]);
+        assert!(home.join(".rustup").join("toolchains").is_dir());
+        let profile = home.join(".profile");
+        fs::write(
+            &profile,
+            "export RUSTUP_HOME=\"$HOME/.rustup\"\nexport OTHER=1\n",
         )
         .unwrap();
 
-        let mut cmd = clitools::cmd(config, "rustup", &["default"]);
-        clitools::env(config, &mut cmd);
-
-        // Override the path to the fallback settings file to be the mock file
-        cmd.env("RUSTUP_OVERRIDE_UNIX_FALLBACK_SETTINGS", mock_settings_file);
+        // As background update checks leave them.
+        for file in &["update-check", "update-notices"] {
+            fs::write(home.join(".rustup").join(file), "").unwrap();
+        }
 
-        let out = cmd.output().unwrap();
-        assert!(out.status.success());
-        let stdout = String::from_utf8(out.stdout).unwrap();
+        expect_ok(&["migrate", "xdg"]);
+        assert!(!home.join(".rustup").exists());
+        assert!(config_home.join("rustup").join("settings.toml").is_file());
+        assert!(cache_home.join("rustup").join("update-check").is_file());
+        assert!(cache_home.join("rustup").join("update-notices").is_file());
+        assert!(data_home.join("rustup").join("toolchains").is_dir());
         assert_eq!(
-            &stdout,
-            for_host!(
-                r"nightly-{0} (default)
-"
-            )
+            fs::read_to_string(&profile).unwrap(),
+            "# export RUSTUP_HOME=\"$HOME/.rustup\" # moved by `rustup migrate xdg`\nexport OTHER=1\n"
         );
+
+        let out = expect_ok(&["show", "active-toolchain"]);
+        assert!(out.stdout.starts_with("nightly-"), "{}", out.stdout);
+        let out = run(config, "rustc", &["--version"], &env);
+        assert!(out.ok, "{}", out.stderr);
+        assert!(out.stdout.contains("hash-nightly-2"));
+        expect_ok(&["toolchain", "install", "stable"]);
+        assert!(data_home
+            .join("rustup")
+            .join("toolchains")
+            .join(for_host!("stable-{}"))
+            .is_dir());
+        assert!(!home.join(".rustup").exists());
+
+        let out = expect_ok(&["migrate", "xdg"]);
+        assert!(out.stderr.contains("already using the XDG layout"));
     });
 }
 
 #[test]
-fn warn_on_unmatch_build() {
-    clitools::setup(Scenario::MultiHost, &|config| {
-        let arch = clitools::MULTI_ARCH1;
+#[cfg(unix)]
+fn hooks() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let log = config.homedir.join("hooks.log");
+        let command = format!(
+            "echo $RUSTUP_HOOK $RUSTUP_HOOK_TOOLCHAIN $RUSTUP_HOOK_COMPONENTS >> '{0}'; cat >> '{0}'",
+            log.display()
+        );
+        let settings_file = config.rustupdir.join("settings.toml");
+        let mut settings = fs::read_to_string(&settings_file).unwrap();
+        settings.push_str("\n[hooks]\n");
+        for hook in &[
+            "pre-toolchain-install",
+            "post-toolchain-install",
+            "pre-toolchain-update",
+            "post-toolchain-update",
+            "post-component-add",
+        ] {
+            settings.push_str(&format!("{} = \"{}\"\n", hook, command));
+        }
+        settings.push_str("pre-component-remove = \"exit 1\"\n");
+        raw::write_file(&settings_file, &settings).unwrap();
+
         expect_stderr_ok(
             config,
-            &[
-                "rustup",
-                "toolchain",
-                "install",
-                &format!("nightly-{}", arch),
-            ],
-            &format!(
-                r"warning: toolchain 'nightly-{0}' may not be able to run on this system.
-warning: If you meant to build software to target that platform, perhaps try `rustup target add {0}` instead?",
-                arch,
-            ),
+            &["rustup", "toolchain", "install", "stable"],
+            "info: running the pre-toolchain-install hook",
+        );
+        expect_ok(config, &["rustup", "component", "add", "rust-src"]);
+        expect_ok(config, &["rustup", "update", "nightly", "--no-self-update"]);
+        let log = fs::read_to_string(&log).unwrap();
+        assert_eq!(
+            &log,
+            for_host!(
+                r#"pre-toolchain-install stable-{0}
+{{"hook":"pre-toolchain-install","toolchain":"stable-{0}"}}
+post-toolchain-install stable-{0}
+{{"hook":"post-toolchain-install","toolchain":"stable-{0}"}}
+post-component-add nightly-{0} rust-src-{0}
+{{"hook":"post-component-add","toolchain":"nightly-{0}","components":["rust-src-{0}"]}}
+pre-toolchain-update nightly-{0}
+{{"hook":"pre-toolchain-update","toolchain":"nightly-{0}"}}
+"#
+            )
         );
-    });
-}
 
-/// Checks that `rust-toolchain.toml` files are considered
-#[test]
-fn rust_toolchain_toml() {
-    setup(&|config| {
         expect_err(
             config,
-            &["rustc", "--version"],
-            "no override and no default toolchain set",
+            &["rustup", "component", "remove", "rust-src"],
+            "the pre-component-remove hook 'exit 1' failed",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list", "--installed"],
+            "rust-src",
         );
 
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain.toml");
-        raw::write_file(&toolchain_file, "[toolchain]\nchannel = \"nightly\"").unwrap();
-
-        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        settings.push_str("pre-toolchain-upgrade = \"true\"\n");
+        raw::write_file(&settings_file, &settings).unwrap();
+        expect_stderr_ok(
+            config,
+            &["rustup", "config", "check"],
+            "unknown hook 'pre-toolchain-upgrade'",
+        );
     });
 }
 
-/// Ensures that `rust-toolchain.toml` files (with `.toml` extension) only allow TOML contents
 #[test]
-fn only_toml_in_rust_toolchain_toml() {
+fn auto_update() {
     setup(&|config| {
-        let cwd = config.current_dir();
-        let toolchain_file = cwd.join("rust-toolchain.toml");
-        raw::write_file(&toolchain_file, "nightly").unwrap();
-
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "set", "auto-self-update", "disable"]);
+        // Recorded as the last check, so that setting the interval doesn't
+        // start one in the background.
+        expect_ok(config, &["rustup", "self", "auto-update"]);
+        expect_ok(
+            config,
+            &["rustup", "config", "set", "auto_update.interval", "7"],
+        );
         expect_err(
             config,
-            &["rustc", "--version"],
-            "error parsing override file",
+            &["rustup", "config", "set", "auto_update.interval", "weekly"],
+            "invalid value 'weekly' for 'auto_update.interval': expected a number of days",
+        );
+        expect_not_stderr_ok(config, &["rustup", "show"], "can be updated");
+
+        set_current_dist_date(config, "2015-01-02");
+        expect_ok(config, &["rustup", "self", "auto-update"]);
+        expect_stderr_ok(
+            config,
+            &["rustup", "show"],
+            for_host!("info: stable-{0} can be updated to 1.1.0 (hash-stable-1.1.0); run `rustup update` to update it"),
+        );
+        // Each notice is only shown once.
+        expect_not_stderr_ok(config, &["rustup", "show"], "can be updated");
+
+        expect_ok(
+            config,
+            &["rustup", "config", "set", "auto_update.channels", "enable"],
+        );
+        expect_ok(config, &["rustup", "self", "auto-update"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-stable-1.1.0");
+        expect_stderr_ok(
+            config,
+            &["rustup", "show"],
+            for_host!("info: stable-{0} was updated to 1.1.0 (hash-stable-1.1.0)"),
         );
+
+        // Never started in CI.
+        let last_check = config.rustupdir.join("update-check");
+        fs::remove_file(&last_check).unwrap();
+        let out = run(config, "rustup", &["show"], &[("CI", "true")]);
+        assert!(out.ok);
+        assert!(!last_check.exists());
     });
 }
 
-/// Checks that a warning occurs if both `rust-toolchain` and `rust-toolchain.toml` files exist
 #[test]
-fn warn_on_duplicate_rust_toolchain_file() {
+#[cfg(unix)]
+fn shared_home_is_kept_group_writable() {
+    use std::os::unix::fs::PermissionsExt;
+
     setup(&|config| {
-        let cwd = config.current_dir();
-        let toolchain_file_1 = cwd.join("rust-toolchain");
-        raw::write_file(&toolchain_file_1, "stable").unwrap();
-        let toolchain_file_2 = cwd.join("rust-toolchain.toml");
-        raw::write_file(&toolchain_file_2, "[toolchain]").unwrap();
+        let home = config.rustupdir.join("");
+        fs::set_permissions(&home, fs::Permissions::from_mode(0o770)).unwrap();
+        expect_ok(config, &["rustup", "default", "nightly"]);
 
-        expect_stderr_ok(
-            config,
-            &["rustc", "--version"],
-            &format!(
-                "warning: both `{0}` and `{1}` exist. Using `{0}`",
-                toolchain_file_1.canonicalize().unwrap().display(),
-                toolchain_file_2.canonicalize().unwrap().display(),
-            ),
-        );
+        let mode = |path: &str| {
+            fs::metadata(config.rustupdir.join(path))
+                .unwrap()
+                .permissions()
+                .mode()
+        };
+        let toolchain = for_host!("toolchains/nightly-{}");
+        for path in &[
+            "settings.toml",
+            "toolchains",
+            toolchain,
+            &format!("{}/bin/rustc", toolchain),
+        ] {
+            assert_eq!(mode(path) & 0o060, 0o060, "{} isn't shared", path);
+        }
     });
 }
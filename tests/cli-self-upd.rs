@@ -174,6 +174,38 @@ fn uninstall_deletes_bins() {
     });
 }
 
+#[test]
+fn non_interactive_install_and_uninstall() {
+    clitools::setup(Scenario::Empty, &|config| {
+        // Installing goes ahead, as it does by default when asked.
+        expect_ok(
+            config,
+            &[
+                "rustup-init",
+                "--non-interactive",
+                "--no-modify-path",
+                "--default-toolchain",
+                "none",
+            ],
+        );
+        let rustup = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        assert!(rustup.exists());
+
+        // Uninstalling has no default, and is refused.
+        expect_err(
+            config,
+            &["rustup", "self", "uninstall", "--non-interactive"],
+            "[reason: confirmation-required]",
+        );
+        assert!(rustup.exists());
+        expect_ok(
+            config,
+            &["rustup", "--non-interactive", "self", "uninstall", "--yes"],
+        );
+        assert!(!rustup.exists());
+    });
+}
+
 #[test]
 fn uninstall_works_if_some_bins_dont_exist() {
     setup_empty_installed(&|config| {
@@ -208,6 +240,18 @@ fn uninstall_deletes_rustup_home() {
     });
 }
 
+#[test]
+fn uninstall_purge_reports_reclaimed_space() {
+    setup_empty_installed(&|config| {
+        expect_stderr_ok(
+            config,
+            &["rustup", "self", "uninstall", "-y", "--purge"],
+            "info: reclaimed ",
+        );
+        assert!(!config.rustupdir.has("."));
+    });
+}
+
 #[test]
 fn uninstall_works_if_rustup_home_doesnt_exist() {
     setup_empty_installed(&|config| {
@@ -409,6 +453,100 @@ fn update_updates_rustup_bin() {
     });
 }
 
+#[test]
+fn update_from_patch() {
+    update_setup(&|config, self_dist| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+
+        let trip = this_host_triple();
+        let dist_dir = self_dist.join(&format!("archive/{}/{}", TEST_VERSION, trip));
+        let dist_exe = dist_dir.join(&format!("rustup-init{}", EXE_SUFFIX));
+        let bin = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        clitools::patch_update(&dist_exe, &bin);
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "self", "update"],
+            "info: applying self-update patch",
+        );
+        assert_eq!(calc_hash(&bin), calc_hash(&dist_exe));
+
+        // A patched rustup which doesn't match its hash isn't used.
+        let hash_path = dist_dir.join(&format!("rustup-init{}.sha256", EXE_SUFFIX));
+        raw::write_file(&hash_path, &"0".repeat(64)).unwrap();
+        expect_stderr_ok(
+            config,
+            &["rustup", "self", "update"],
+            "could not update rustup from a patch; downloading it whole",
+        );
+        assert_eq!(calc_hash(&bin), calc_hash(&dist_exe));
+    });
+}
+
+#[test]
+fn rollback() {
+    update_setup(&|config, _| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+        expect_err(
+            config,
+            &["rustup", "self", "rollback"],
+            "there is no previous version of rustup to roll back to",
+        );
+
+        let bin = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        let rustc = config.cargodir.join(&format!("bin/rustc{}", EXE_SUFFIX));
+        let before_hash = calc_hash(&bin);
+        expect_ok(config, &["rustup", "self", "update"]);
+        let after_hash = calc_hash(&bin);
+        assert_ne!(before_hash, after_hash);
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "self", "rollback"],
+            "info: rolling back to rustup ",
+        );
+        assert_eq!(calc_hash(&bin), before_hash);
+        assert_eq!(calc_hash(&rustc), before_hash);
+        // And forward again.
+        expect_ok(config, &["rustup", "self", "rollback"]);
+        assert_eq!(calc_hash(&bin), after_hash);
+    });
+}
+
+#[test]
+fn update_unsigned() {
+    update_setup(&|config, self_dist| {
+        expect_ok(config, &["rustup-init", "-y", "--no-modify-path"]);
+
+        let trip = this_host_triple();
+        let dist_dir = self_dist.join(&format!("archive/{}/{}", TEST_VERSION, trip));
+        let bin = config.cargodir.join(&format!("bin/rustup{}", EXE_SUFFIX));
+        let setup = config
+            .cargodir
+            .join(&format!("bin/rustup-init{}", EXE_SUFFIX));
+        let before_hash = calc_hash(&bin);
+
+        // Changed after it was signed.
+        let dist_exe = dist_dir.join(&format!("rustup-init{}", EXE_SUFFIX));
+        raw::append_file(&dist_exe, "").unwrap();
+        expect_err(
+            config,
+            &["rustup", "self", "update"],
+            "isn't signed with a key this rustup trusts, so it wasn't installed",
+        );
+        assert_eq!(calc_hash(&bin), before_hash);
+        assert!(!setup.exists());
+
+        fs::remove_file(dist_dir.join(&format!("rustup-init{}.asc", EXE_SUFFIX))).unwrap();
+        expect_err(
+            config,
+            &["rustup", "self", "update"],
+            "could not download the signature of the self-update",
+        );
+        assert_eq!(calc_hash(&bin), before_hash);
+    });
+}
+
 #[test]
 fn update_bad_schema() {
     update_setup(&|config, self_dist| {
@@ -811,6 +949,195 @@ fn rls_proxy_set_up_after_update() {
     });
 }
 
+#[test]
+#[cfg(unix)]
+fn symlink_shims_follow_default() {
+    setup_installed(&|config| {
+        let rustc_path = config.cargodir.join("bin/rustc");
+        let toolchain_rustc = |name: &str| {
+            config
+                .rustupdir
+                .join("toolchains")
+                .join(format!("{}-{}", name, this_host_triple()))
+                .join("bin/rustc")
+        };
+
+        expect_ok(config, &["rustup", "set", "shims", "symlink"]);
+        assert_eq!(
+            fs::read_link(&rustc_path).unwrap(),
+            toolchain_rustc("stable")
+        );
+        expect_stdout_ok(
+            config,
+            &[rustc_path.to_str().unwrap(), "--version"],
+            "hash-stable-1.1.0",
+        );
+
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        assert_eq!(
+            fs::read_link(&rustc_path).unwrap(),
+            toolchain_rustc("nightly")
+        );
+        expect_stderr_ok(
+            config,
+            &["rustup", "override", "set", "stable"],
+            "don't follow overrides",
+        );
+
+        expect_ok(config, &["rustup", "set", "shims", "proxy"]);
+        assert!(fs::read_link(&rustc_path).is_err());
+        expect_stdout_ok(
+            config,
+            &[rustc_path.to_str().unwrap(), "--version"],
+            "hash-stable-1.1.0",
+        );
+    });
+}
+
+#[test]
+#[cfg(unix)]
+fn custom_proxy_runs_tool_from_toolchain() {
+    use std::os::unix::fs::PermissionsExt;
+
+    setup_installed(&|config| {
+        expect_ok(config, &["rustup", "toolchain", "install", "nightly"]);
+        let tool = config
+            .rustupdir
+            .join("toolchains")
+            .join(format!("stable-{}", this_host_triple()))
+            .join("bin/mdbook");
+        raw::write_file(&tool, "#!/bin/sh\necho mdbook from stable").unwrap();
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+        let mdbook_path = config.cargodir.join("bin/mdbook");
+        let mdbook = mdbook_path.to_str().unwrap();
+
+        expect_err(
+            config,
+            &["rustup", "proxy", "add", "rustc"],
+            "always has a proxy",
+        );
+        expect_ok(config, &["rustup", "proxy", "add", "mdbook"]);
+        expect_stdout_ok(config, &["rustup", "proxy", "list"], "mdbook");
+        expect_stdout_ok(config, &[mdbook, "--version"], "mdbook from stable");
+        expect_err(
+            config,
+            &[mdbook, "+nightly", "--version"],
+            &format!(
+                "'mdbook{}' is not installed for the toolchain 'nightly-{}'",
+                EXE_SUFFIX,
+                this_host_triple()
+            ),
+        );
+        expect_err(
+            config,
+            &[mdbook, "+nosuch", "--version"],
+            "toolchain 'nosuch' is not installed",
+        );
+
+        expect_ok(config, &["rustup", "proxy", "remove", "mdbook"]);
+        assert!(!mdbook_path.exists());
+        raw::write_file(&mdbook_path, "").unwrap();
+        expect_err(
+            config,
+            &["rustup", "proxy", "add", "mdbook"],
+            "isn't a rustup proxy",
+        );
+        expect_stderr_ok(
+            config,
+            &["rustup", "proxy", "list"],
+            "no tools besides the built-in ones have proxies",
+        );
+    });
+}
+
+#[test]
+#[cfg(unix)]
+fn proxy_execs_tool() {
+    use std::os::unix::fs::PermissionsExt;
+
+    setup_installed(&|config| {
+        let tool = config
+            .rustupdir
+            .join("toolchains")
+            .join(format!("stable-{}", this_host_triple()))
+            .join("bin/show-ppid");
+        raw::write_file(&tool, "#!/bin/sh\necho \"ppid=$PPID\"").unwrap();
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+        expect_ok(config, &["rustup", "proxy", "add", "show-ppid"]);
+
+        // The tool takes the proxy's place, so its parent is the test.
+        let proxy = config.cargodir.join("bin/show-ppid");
+        expect_stdout_ok(
+            config,
+            &[proxy.to_str().unwrap()],
+            &format!("ppid={}", std::process::id()),
+        );
+    });
+}
+
+#[test]
+#[cfg(unix)]
+fn proxy_passes_on_exit_status() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::process::ExitStatusExt;
+
+    setup_installed(&|config| {
+        let tool = config
+            .rustupdir
+            .join("toolchains")
+            .join(format!("stable-{}", this_host_triple()))
+            .join("bin/fail");
+        raw::write_file(
+            &tool,
+            "#!/bin/sh\n[ \"$1\" = segv ] && kill -SEGV $$\nexit 42",
+        )
+        .unwrap();
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+        expect_ok(config, &["rustup", "proxy", "add", "fail"]);
+        let proxy = config.cargodir.join("bin/fail");
+        let proxy = proxy.to_str().unwrap();
+
+        let status = clitools::cmd(config, proxy, &[] as &[&str])
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(42));
+        let status = clitools::cmd(config, proxy, &["segv"]).status().unwrap();
+        assert_eq!(status.signal(), Some(11));
+    });
+}
+
+#[test]
+#[cfg(unix)]
+fn proxy_recursion_is_explained() {
+    setup_installed(&|config| {
+        let toolchain = config.homedir.join("looping");
+        fs::create_dir_all(toolchain.join("bin")).unwrap();
+        fs::create_dir_all(toolchain.join("lib")).unwrap();
+        let rustc = config.cargodir.join("bin/rustc");
+        std::os::unix::fs::symlink(&rustc, toolchain.join("bin/rustc")).unwrap();
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "link",
+                "looping",
+                toolchain.to_str().unwrap(),
+            ],
+        );
+        expect_ok(config, &["rustup", "override", "set", "looping"]);
+
+        let out = run(config, rustc.to_str().unwrap(), &["--version"], &[]);
+        assert!(!out.ok);
+        assert!(out.stderr.contains("infinite recursion detected"));
+        assert!(out
+            .stderr
+            .contains("the toolchain here is 'looping' (directory override"));
+        assert!(out.stderr.contains("which is rustup itself"));
+        assert!(out.stderr.contains("rustup override explain"));
+    });
+}
+
 #[test]
 fn update_does_not_overwrite_rustfmt() {
     update_setup(&|config, self_dist| {
@@ -12,8 +12,8 @@ use rustup::test::this_host_triple;
 
 use crate::mock::clitools::{
     self, expect_component_executable, expect_component_not_executable, expect_err,
-    expect_not_stderr_err, expect_not_stdout_ok, expect_ok, expect_ok_ex, expect_stderr_ok,
-    expect_stdout_ok, set_current_dist_date, Config, Scenario,
+    expect_not_stderr_err, expect_not_stderr_ok, expect_not_stdout_ok, expect_ok, expect_ok_ex,
+    expect_stderr_ok, expect_stdout_ok, run, set_current_dist_date, Config, Scenario,
 };
 
 pub fn setup(f: &dyn Fn(&mut Config)) {
@@ -674,6 +674,55 @@ fn add_all_targets() {
     });
 }
 
+#[test]
+fn proxy_installs_missing_target() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let path = |target: &str| {
+            format!(
+                "toolchains/nightly-{}/lib/rustlib/{}/lib/libstd.rlib",
+                this_host_triple(),
+                target
+            )
+        };
+
+        // Without a terminal to ask at, the default is only to say so.
+        expect_stderr_ok(
+            config,
+            &["cargo", "--echo-args", "--target", clitools::CROSS_ARCH1],
+            &format!("target '{}' is not installed", clitools::CROSS_ARCH1),
+        );
+        assert!(!config.rustupdir.has(&path(clitools::CROSS_ARCH1)));
+
+        expect_ok(config, &["rustup", "set", "auto-install-targets", "enable"]);
+        expect_ok(
+            config,
+            &["cargo", "--echo-args", "--target", clitools::CROSS_ARCH1],
+        );
+        assert!(config.rustupdir.has(&path(clitools::CROSS_ARCH1)));
+
+        expect_ok(
+            config,
+            &["rustup", "set", "auto-install-targets", "disable"],
+        );
+        let target_arg = format!("--target={}", clitools::CROSS_ARCH2);
+        expect_ok(config, &["rustc", "--echo-args", &target_arg]);
+        assert!(!config.rustupdir.has(&path(clitools::CROSS_ARCH2)));
+
+        // An installed target is found without loading the manifest.
+        let manifest = config.rustupdir.join(format!(
+            "toolchains/nightly-{}/lib/rustlib/multirust-channel-manifest.toml",
+            this_host_triple()
+        ));
+        fs::remove_file(&manifest).unwrap();
+        expect_not_stderr_ok(
+            config,
+            &["cargo", "--echo-args", "--target", clitools::CROSS_ARCH1],
+            "could not install the requested targets",
+        );
+    });
+}
+
 #[test]
 fn add_all_targets_fail() {
     setup(&|config| {
@@ -697,6 +746,44 @@ fn add_all_targets_fail() {
     });
 }
 
+#[test]
+fn add_targets_all_or_none() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let path = format!(
+            "toolchains/nightly-{}/lib/rustlib/{}/lib/libstd.rlib",
+            this_host_triple(),
+            clitools::CROSS_ARCH1
+        );
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "target",
+                "add",
+                clitools::CROSS_ARCH1,
+                "fake-triple",
+            ],
+            "toolchain 'nightly-",
+        );
+        assert!(!config.rustupdir.has(&path));
+
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "target",
+                "add",
+                "--keep-going",
+                clitools::CROSS_ARCH1,
+                "fake-triple",
+            ],
+            "1 of the 2 requested could not be added",
+        );
+        assert!(config.rustupdir.has(&path));
+    });
+}
+
 #[test]
 fn add_target_by_component_add() {
     setup(&|config| {
@@ -1087,6 +1174,54 @@ Sometimes not all components are available in any given nightly. If you don't ne
     });
 }
 
+#[test]
+fn dated_nightly_missing_component_suggests_latest_with_it() {
+    clitools::setup(Scenario::MissingComponent, &|config| {
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly-2019-09-14",
+                "-c",
+                "rls",
+            ],
+            for_host!(
+                "help: the latest nightly with 'rls' for target '{0}' is 2019-09-13; \
+                 to install it, run `rustup toolchain install nightly-2019-09-13 --component rls`"
+            ),
+        );
+    });
+}
+
+#[test]
+fn failures_exit_with_their_class_code() {
+    setup(&|config| {
+        make_component_unavailable(config, "rust-std", &this_host_triple());
+        let out = run(config, "rustup", &["update", "nightly"], &[]);
+        assert_eq!(out.status, Some(12));
+
+        let out = run(
+            config,
+            "rustup",
+            &["update", "beta"],
+            &[("RUSTUP_DIST_SERVER", "http://127.0.0.1:1")],
+        );
+        assert_eq!(out.status, Some(10));
+
+        let dir = config.distdir.join("dist/2015-01-02");
+        for file in fs::read_dir(&dir).unwrap() {
+            let path = file.unwrap().path();
+            if path.to_string_lossy().contains(".tar.") {
+                rustup::utils::raw::write_file(&path, "xxx").unwrap();
+            }
+        }
+        let out = run(config, "rustup", &["update", "stable"], &[]);
+        assert_eq!(out.status, Some(11));
+    });
+}
+
 #[test]
 fn update_unavailable_force() {
     setup(&|config| {
@@ -1171,6 +1306,76 @@ fn remove_component_suggest_best_match() {
     });
 }
 
+#[test]
+fn add_component_to_linked_toolchain() {
+    setup(&|config| {
+        let path = config.customdir.join("custom-1");
+        let path = path.to_string_lossy();
+        expect_ok(config, &["rustup", "toolchain", "link", "custom", &path]);
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "component",
+                "add",
+                "rust-src",
+                "--toolchain",
+                "custom",
+            ],
+            "toolchain 'custom' does not support components",
+        );
+
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "link",
+                "custom",
+                &path,
+                "--components-from",
+                "nightly",
+            ],
+        );
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "component",
+                "add",
+                "rust-src",
+                "--toolchain",
+                "custom",
+            ],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list", "--toolchain", "custom"],
+            "rust-src (installed)",
+        );
+        assert!(config
+            .customdir
+            .join("custom-1/lib/rustlib/src/rust-src/foo.rs")
+            .exists());
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "component",
+                "remove",
+                "rust-src",
+                "--toolchain",
+                "custom",
+            ],
+        );
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "component", "list", "--toolchain", "custom"],
+            "rust-src (installed)",
+        );
+    });
+}
+
 #[test]
 fn add_target_suggest_best_match() {
     setup(&|config| {
@@ -1510,3 +1715,229 @@ fn regression_2601() {
         );
     });
 }
+
+#[test]
+fn snapshot_save_and_restore() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "component", "add", "rls"]);
+        expect_ok(config, &["rustup", "target", "add", clitools::CROSS_ARCH1]);
+        let lock = config.current_dir().join("env.lock");
+        let lock = lock.to_string_lossy();
+        expect_ok(config, &["rustup", "snapshot", "save", &lock]);
+        let snapshot = fs::read_to_string(&*lock).unwrap();
+        assert!(snapshot.contains("date = \"2015-01-01\""));
+        assert!(snapshot.contains("name = \"rls\""));
+
+        // The channel moves on, and the toolchain is removed.
+        set_current_dist_date(config, "2015-01-02");
+        expect_ok(config, &["rustup", "update", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        expect_ok(config, &["rustup", "component", "remove", "rls"]);
+        expect_ok(config, &["rustup", "component", "add", "rust-src"]);
+        expect_ok(config, &["rustup", "toolchain", "install", "stable"]);
+        expect_ok(config, &["rustup", "default", "stable"]);
+
+        // A snapshot the server no longer agrees with changes nothing.
+        let tampered = config.current_dir().join("tampered.lock");
+        let hash = snapshot.split("hash = \"").nth(1).unwrap();
+        let hash = &hash[..hash.find('"').unwrap()];
+        fs::write(&tampered, snapshot.replace(hash, &"0".repeat(64))).unwrap();
+        expect_err(
+            config,
+            &["rustup", "snapshot", "restore", &tampered.to_string_lossy()],
+            "has changed on the server",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "run", "nightly", "rustc", "--version"],
+            "hash-nightly-2",
+        );
+
+        expect_ok(config, &["rustup", "snapshot", "restore", &lock]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-1");
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list", "--installed"],
+            "rls",
+        );
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "component", "list", "--installed"],
+            "rust-src",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "target", "list", "--installed"],
+            clitools::CROSS_ARCH1,
+        );
+        expect_stderr_ok(
+            config,
+            &["rustup", "snapshot", "restore", &lock],
+            for_host!("'nightly-{}' already matches the snapshot"),
+        );
+
+        // Updating brings the restored toolchain back to the channel's latest.
+        expect_ok(config, &["rustup", "update", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+    });
+}
+
+#[test]
+fn update_keeps_prefetched_downloads() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        expect_ok(config, &["rustup", "prefetch", "nightly"]);
+        expect_ok(config, &["rustup", "update"]);
+        assert!(config.rustupdir.join("downloads/manifests").exists());
+
+        // With no dist server to fall back on, the install needs everything
+        // prefetched to still be cached.
+        let out = run(
+            config,
+            "rustup",
+            &["toolchain", "install", "nightly", "--offline"],
+            &[("RUSTUP_DIST_SERVER", "file:///nonexistent")],
+        );
+        assert!(out.ok, "{}", out.stderr);
+        expect_stdout_ok(
+            config,
+            &["rustup", "run", "nightly", "rustc", "--version"],
+            "hash-nightly-2",
+        );
+    });
+}
+
+#[test]
+fn update_deletes_unreadable_prefetched_manifest() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+        let manifest = config
+            .rustupdir
+            .join("downloads/manifests/channel-rust-nightly.toml");
+        fs::create_dir_all(manifest.parent().unwrap()).unwrap();
+        rustup::utils::raw::write_file(&manifest, "manifest-version = ").unwrap();
+        expect_ok(config, &["rustup", "update"]);
+        assert!(!manifest.exists());
+    });
+}
+
+#[test]
+fn corrupt_cached_download_is_fetched_again() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "prefetch", "nightly"]);
+        let mut corrupted = 0;
+        for entry in fs::read_dir(config.rustupdir.join("downloads")).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_file() {
+                let data = fs::read(&path).unwrap();
+                fs::write(&path, &data[..data.len() / 2]).unwrap();
+                corrupted += 1;
+            }
+        }
+        assert!(corrupted > 0);
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "toolchain", "install", "nightly"],
+            "bad checksum for cached download",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "run", "nightly", "rustc", "--version"],
+            "hash-nightly-2",
+        );
+    });
+}
+
+#[test]
+fn undo_default_and_install() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "default", "beta"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "history"],
+            for_host!("set the default toolchain to 'beta-{}' (was 'nightly-{0}')"),
+        );
+
+        expect_ok(config, &["rustup", "undo"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+        expect_ok(config, &["rustup", "undo"]);
+        expect_not_stdout_ok(config, &["rustup", "toolchain", "list"], "beta");
+        expect_stdout_ok(
+            config,
+            &["rustup", "history"],
+            for_host!("installed 'beta-{}' (undone)"),
+        );
+
+        // What has been undone already isn't undone again.
+        expect_err(config, &["rustup", "undo", "4"], "has been undone already");
+    });
+}
+
+#[test]
+fn undo_install_in_use_is_refused() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_err(
+            config,
+            &["rustup", "undo", "1"],
+            for_host!("'nightly-{}' is the default toolchain"),
+        );
+
+        expect_ok(config, &["rustup", "toolchain", "install", "beta"]);
+        expect_ok(config, &["rustup", "override", "set", "beta"]);
+        expect_err(
+            config,
+            &["rustup", "undo", "3"],
+            for_host!("'beta-{}' is the override for"),
+        );
+
+        expect_ok(config, &["rustup", "override", "unset"]);
+        expect_ok(config, &["rustup", "undo", "--yes", "3"]);
+        expect_not_stdout_ok(config, &["rustup", "toolchain", "list"], "beta");
+    });
+}
+
+#[test]
+fn undo_target_add() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "target", "add", clitools::CROSS_ARCH1]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "--format", "json", "history"],
+            "\"kind\": \"add-components\"",
+        );
+
+        expect_ok(config, &["rustup", "undo"]);
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "target", "list", "--installed"],
+            clitools::CROSS_ARCH1,
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "--format", "json", "history"],
+            "\"kind\": \"undo\"",
+        );
+    });
+}
+
+#[test]
+fn undo_update_is_refused() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_ok(config, &["rustup", "update", "nightly"]);
+        expect_err(
+            config,
+            &["rustup", "undo"],
+            for_host!("(updated 'nightly-{}') can't be undone"),
+        );
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-nightly-2");
+    });
+}
@@ -23,6 +23,7 @@ use rustup::dist::prefix::InstallPrefix;
 use rustup::dist::temp;
 use rustup::dist::Notification;
 use rustup::errors::RustupError;
+use rustup::settings::NetworkSettings;
 use rustup::utils::raw as utils_raw;
 use rustup::utils::utils;
 use rustup::PgpPublicKey;
@@ -518,11 +519,20 @@ fn setup(
     edit: Option<&dyn Fn(&str, &mut MockChannel)>,
     comps: Compressions,
     f: &dyn Fn(&Url, &ToolchainDesc, &InstallPrefix, &DownloadCfg<'_>, &temp::Cfg),
+) {
+    setup_with_vars(HashMap::default(), edit, comps, f)
+}
+
+fn setup_with_vars(
+    vars: HashMap<String, String>,
+    edit: Option<&dyn Fn(&str, &mut MockChannel)>,
+    comps: Compressions,
+    f: &dyn Fn(&Url, &ToolchainDesc, &InstallPrefix, &DownloadCfg<'_>, &temp::Cfg),
 ) {
     let dist_tempdir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
     let mock_dist_server = create_mock_dist_server(dist_tempdir.path(), edit);
     let url = Url::parse(&format!("file://{}", dist_tempdir.path().to_string_lossy())).unwrap();
-    setup_from_dist_server(mock_dist_server, &url, comps, f);
+    setup_from_dist_server_with_vars(vars, mock_dist_server, &url, comps, f);
 }
 
 fn setup_from_dist_server(
@@ -530,6 +540,16 @@ fn setup_from_dist_server(
     url: &Url,
     comps: Compressions,
     f: &dyn Fn(&Url, &ToolchainDesc, &InstallPrefix, &DownloadCfg<'_>, &temp::Cfg),
+) {
+    setup_from_dist_server_with_vars(HashMap::default(), server, url, comps, f)
+}
+
+fn setup_from_dist_server_with_vars(
+    vars: HashMap<String, String>,
+    server: MockDistServer,
+    url: &Url,
+    comps: Compressions,
+    f: &dyn Fn(&Url, &ToolchainDesc, &InstallPrefix, &DownloadCfg<'_>, &temp::Cfg),
 ) {
     server.write(
         &[ManifestVersion::V2],
@@ -559,13 +579,16 @@ fn setup_from_dist_server(
             "test-key".into(),
             get_public_key(),
         )],
+        download_log: None,
+        network: &NetworkSettings::default(),
+        verified_manifest: None,
     };
 
     currentprocess::with(
         Box::new(currentprocess::TestProcess::new(
             &env::current_dir().unwrap(),
             &["rustup"],
-            HashMap::default(),
+            vars,
             "",
         )),
         || f(url, &toolchain, &prefix, &download_cfg, &temp_cfg),
@@ -2097,6 +2120,8 @@ fn reuse_downloaded_file() {
                 "test-key".into(),
                 get_public_key(),
             )],
+            download_log: None,
+            network: &NetworkSettings::default(),
         };
 
         update_from_dist(
@@ -2168,6 +2193,8 @@ fn checks_files_hashes_before_reuse() {
                 "test-key".into(),
                 get_public_key(),
             )],
+            download_log: None,
+            network: &NetworkSettings::default(),
         };
 
         update_from_dist(
@@ -2233,3 +2260,177 @@ fn handle_corrupt_partial_downloads() {
         assert!(utils::path_exists(&prefix.path().join("lib/libstd.rlib")));
     });
 }
+
+#[test]
+fn prefetch_allows_offline_install() {
+    setup(None, GZOnly, &|url, toolchain, prefix, download_cfg, _| {
+        let dist_root = format!("{}/dist", url);
+        let online_cfg = DownloadCfg {
+            dist_root: &dist_root,
+            ..*download_cfg
+        };
+        let date =
+            rustup::dist::dist::prefetch(online_cfg, toolchain, Some(Profile::Default), &[], &[])
+                .unwrap();
+        assert_eq!(date, "2016-02-02");
+        assert!(!utils::path_exists(&prefix.path().join("bin/rustc")));
+
+        // Nothing listens here, so installing has to rely on the cache, but
+        // only does when asked to.
+        let unreachable_cfg = DownloadCfg {
+            dist_root: "http://127.0.0.1:1/dist",
+            ..*download_cfg
+        };
+        let install = |download_cfg| {
+            rustup::dist::dist::update_from_dist(
+                download_cfg,
+                None,
+                toolchain,
+                Some(Profile::Default),
+                prefix,
+                false,
+                false,
+                None,
+                &[],
+                &[],
+            )
+        };
+        assert!(install(unreachable_cfg).is_err());
+        assert!(!utils::path_exists(&prefix.path().join("bin/rustc")));
+
+        let network = NetworkSettings {
+            offline: Some(true),
+            ..NetworkSettings::default()
+        };
+        let offline_cfg = DownloadCfg {
+            network: &network,
+            ..unreachable_cfg
+        };
+        install(offline_cfg).unwrap();
+
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+        assert!(utils::path_exists(&prefix.path().join("lib/libstd.rlib")));
+        // Once installed, the prefetched manifest is of no more use.
+        assert!(!utils::path_exists(
+            &download_cfg
+                .download_dir
+                .join("manifests")
+                .join(format!("channel-rust-{}.toml", toolchain.manifest_name()))
+        ));
+    });
+}
+
+#[test]
+fn downloads_from_metalink_mirror() {
+    let mut vars = HashMap::new();
+    vars.insert("RUSTUP_USE_METALINK".to_owned(), "1".to_owned());
+    setup_with_vars(
+        vars,
+        None,
+        GZOnly,
+        &|url, toolchain, prefix, download_cfg, temp_cfg| {
+            let dist = url.to_file_path().unwrap().join("dist/2016-02-02");
+            let tarball = "rustc-nightly-x86_64-apple-darwin.tar.gz";
+            let hash = utils::read_file("hash", &dist.join(format!("{}.sha256", tarball))).unwrap()
+                [..SHA256_HASH_LEN]
+                .to_owned();
+
+            // Move the tarball to a mirror, leaving only a metalink behind.
+            let mirror_dir = tempfile::Builder::new().prefix("rustup").tempdir().unwrap();
+            let mirrored = mirror_dir.path().join(tarball);
+            fs::rename(dist.join(tarball), &mirrored).unwrap();
+            let metalink = format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="{}">
+    <hash type="sha-256">{}</hash>
+    <url priority="1">{}</url>
+    <url priority="2">{}</url>
+  </file>
+</metalink>"#,
+                tarball,
+                hash,
+                Url::from_file_path(mirror_dir.path().join("missing.tar.gz")).unwrap(),
+                Url::from_file_path(&mirrored).unwrap(),
+            );
+            utils::write_file(
+                "metalink",
+                &dist.join(format!("{}.meta4", tarball)),
+                &metalink,
+            )
+            .unwrap();
+
+            update_from_dist(
+                url,
+                toolchain,
+                prefix,
+                &[],
+                &[],
+                download_cfg,
+                temp_cfg,
+                false,
+            )
+            .unwrap();
+
+            assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+        },
+    );
+}
+
+#[test]
+fn prefers_blake3_hash_when_present() {
+    setup(None, GZOnly, &|url,
+                          toolchain,
+                          prefix,
+                          download_cfg,
+                          temp_cfg| {
+        let dist = url.to_file_path().unwrap().join("dist");
+        let manifest_path = dist.join("channel-rust-nightly.toml");
+        let tarball = dist.join("2016-02-02/rustc-nightly-x86_64-apple-darwin.tar.gz");
+        let set_blake3 = |blake3: &str| {
+            let mut manifest: toml::Value = utils::read_file("manifest", &manifest_path)
+                .unwrap()
+                .parse()
+                .unwrap();
+            manifest["pkg"]["rustc"]["target"]["x86_64-apple-darwin"]
+                .as_table_mut()
+                .unwrap()
+                .insert("blake3".to_owned(), toml::Value::String(blake3.to_owned()));
+            utils::write_file("manifest", &manifest_path, &manifest.to_string()).unwrap();
+        };
+
+        // The SHA-256 still matches, but the BLAKE3 takes precedence.
+        let bogus = "0".repeat(64);
+        set_blake3(&bogus);
+        let err = update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap_err();
+        let err = format!("{:#}", err);
+        assert!(err.contains(&format!("expected: '{}'", bogus)), "{}", err);
+
+        fs::remove_dir_all(download_cfg.download_dir).unwrap();
+        let blake3 = blake3::hash(&fs::read(&tarball).unwrap());
+        set_blake3(&blake3.to_hex());
+        update_from_dist(
+            url,
+            toolchain,
+            prefix,
+            &[],
+            &[],
+            download_cfg,
+            temp_cfg,
+            false,
+        )
+        .unwrap();
+
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+    });
+}
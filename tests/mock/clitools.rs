@@ -6,7 +6,7 @@ use std::env;
 use std::env::consts::EXE_SUFFIX;
 use std::ffi::OsStr;
 use std::fs;
-use std::io;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::Arc;
@@ -21,8 +21,8 @@ use rustup::test::this_host_triple;
 use rustup::utils::{raw, utils};
 
 use crate::mock::dist::{
-    change_channel_date, ManifestVersion, MockChannel, MockComponent, MockDistServer, MockPackage,
-    MockTargetedPackage,
+    change_channel_date, create_hash, create_signature, ManifestVersion, MockChannel,
+    MockComponent, MockDistServer, MockPackage, MockTargetedPackage,
 };
 use crate::mock::topical_doc_data;
 use crate::mock::{MockComponentBuilder, MockFile, MockInstallerBuilder};
@@ -206,6 +206,7 @@ fn create_local_update_server(self_dist: &Path, config: &mut Config, version: &s
     fs::create_dir_all(dist_dir).unwrap();
     output_release_file(&self_dist, "1", version);
     fs::copy(&rustup_bin, &dist_exe).unwrap();
+    sign_update(&dist_exe);
 
     let root_url = format!("file://{}", self_dist.display());
     config.rustup_update_root = Some(root_url);
@@ -244,11 +245,53 @@ pub fn self_update_setup(f: &dyn Fn(&Config, &Path), version: &str) {
 
         // Modify the exe so it hashes different
         raw::append_file(&dist_exe, "").unwrap();
+        sign_update(&dist_exe);
 
         f(config, &self_dist);
     });
 }
 
+/// Writes the detached signature of the self-update `dist_exe` next to it,
+/// as the update root has it.
+pub fn sign_update(dist_exe: &Path) {
+    let signature = create_signature(&fs::read(dist_exe).unwrap()).unwrap();
+    let mut signature_path = dist_exe.as_os_str().to_owned();
+    signature_path.push(".asc");
+    raw::write_file(Path::new(&signature_path), &signature).unwrap();
+}
+
+/// Writes a patch from `from` to the self-update `dist_exe` next to it,
+/// with the hash to check the patched rustup against, as the update root
+/// has them. The patch is as simple as one can be: `dist_exe` as it differs
+/// from the start of `from`, and then whatever is left of it.
+pub fn patch_update(dist_exe: &Path, from: &Path) {
+    let old = fs::read(from).unwrap();
+    let new = fs::read(dist_exe).unwrap();
+    let common = old.len().min(new.len());
+
+    let mut patch = xz2::write::XzEncoder::new(Vec::new(), 6);
+    patch.write_all(b"RUSTUP-PATCH-1\n").unwrap();
+    patch.write_all(&0u64.to_le_bytes()).unwrap();
+    patch.write_all(&(common as u64).to_le_bytes()).unwrap();
+    let diff: Vec<u8> = new[..common]
+        .iter()
+        .zip(&old)
+        .map(|(n, o)| n.wrapping_sub(*o))
+        .collect();
+    patch.write_all(&diff).unwrap();
+    patch
+        .write_all(&((new.len() - common) as u64).to_le_bytes())
+        .unwrap();
+    patch.write_all(&new[common..]).unwrap();
+
+    let mut patch_path = dist_exe.as_os_str().to_owned();
+    patch_path.push(format!(".from-{}.patch", env!("CARGO_PKG_VERSION")));
+    fs::write(&patch_path, patch.finish().unwrap()).unwrap();
+    let mut hash_path = dist_exe.as_os_str().to_owned();
+    hash_path.push(".sha256");
+    create_hash(dist_exe, Path::new(&hash_path));
+}
+
 pub fn output_release_file(dist_dir: &Path, schema: &str, version: &str) {
     let contents = format!(
         r#"
@@ -462,6 +505,7 @@ pub struct Output {
 #[derive(Debug)]
 pub struct SanitizedOutput {
     pub ok: bool,
+    pub status: Option<i32>,
     pub stdout: String,
     pub stderr: String,
 }
@@ -517,6 +561,13 @@ pub fn env<E: rustup_test::Env>(config: &Config, cmd: &mut E) {
             .unwrap()
             .join("tests/mock/signing-key.pub.asc"),
     );
+    // The mock update root signs self-updates with it too
+    cmd.env(
+        "RUSTUP_OVERRIDE_SELF_UPDATE_KEY",
+        std::env::current_dir()
+            .unwrap()
+            .join("tests/mock/signing-key.pub.asc"),
+    );
 
     // The unix fallback settings file may be present in the test environment, so override
     // the path to the settings file with a non-existing path to avoid intereference
@@ -596,6 +647,7 @@ where
     };
     let output = SanitizedOutput {
         ok: matches!(out.status, Some(0)),
+        status: out.status,
         stdout: String::from_utf8(out.stdout).unwrap(),
         stderr: String::from_utf8(out.stderr).unwrap(),
     };
@@ -646,7 +698,7 @@ where
         Ok(process_res) => process_res,
         Err(e) => {
             currentprocess::with(tp.clone(), || rustup::cli::common::report_error(&e));
-            utils::ExitCode(1)
+            utils::ExitCode(rustup::cli::errors::exit_code(&e))
         }
     };
     Output {